@@ -0,0 +1,12 @@
+/*!
+Anything that isn't a `.json` file gets tried first as TOML, then as a PNG
+with embedded parameters; this exercises both loaders.
+*/
+#![no_main]
+
+use jset_desk::rw::{self, ParamFormat};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = rw::load_bytes(data, ParamFormat::Toml);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use jset_desk::rw::{self, ParamFormat};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = rw::load_bytes(data, ParamFormat::Json);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use jset_desk::rw;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|text: &str| {
+    let _ = rw::load_pasted(text);
+});
@@ -0,0 +1,440 @@
+/*!
+Animations between views of an image: a list of keyframes (sets of image
+parameters) is turned into a sequence of frames that move smoothly from
+each one to the next, which are rendered to numbered PNGs, to an animated
+GIF or PNG, or straight to a video if `ffmpeg` is around (see the `video`
+module).
+
+Zooming is interpolated logarithmically, so that a zoom goes in by the
+same factor every frame rather than rushing through the first part and
+crawling through the last, and the view moves toward its destination in
+step with the zoom, so the point being zoomed in on stays put on the
+screen. Each stretch between keyframes gets time in proportion to how far
+it goes (counting each doubling of the zoom as one step, and moving the
+view by its own width as another), so the whole thing moves at a steady
+pace, apart from any easing in and out at the ends.
+
+Unless the animation is a morph (see `Animation::set_morph()`), the color
+map and iterator don't change in between keyframes; each frame uses those
+of the keyframe the stretch it's in started from.
+*/
+
+use std::path::{Path, PathBuf};
+
+use crate::headless;
+use crate::image::*;
+use crate::morph;
+use crate::rw;
+use crate::video::{self, Output};
+
+/** Everything needed to render a frame. */
+pub type Keyframe = (ImageDims, ColorSpec, IterType);
+
+/** How an animation speeds up and slows down at its ends. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Easing {
+    /// The same pace from start to finish.
+    Linear,
+    /// Start slowly.
+    EaseIn,
+    /// End slowly.
+    EaseOut,
+    /// Start and end slowly.
+    EaseInOut,
+}
+
+impl Easing {
+    /** Every easing, in the order they're usually offered. */
+    pub const ALL: [Easing; 4] = [
+        Easing::Linear,
+        Easing::EaseIn,
+        Easing::EaseOut,
+        Easing::EaseInOut,
+    ];
+
+    /** The easing's name, as `from_name()` reads it. */
+    pub fn name(&self) -> &'static str {
+        match self {
+            Easing::Linear => "linear",
+            Easing::EaseIn => "ease-in",
+            Easing::EaseOut => "ease-out",
+            Easing::EaseInOut => "ease-in-out",
+        }
+    }
+
+    /** Parse an easing name ("linear", "ease-in", etc., in any case). */
+    pub fn from_name(name: &str) -> Option<Easing> {
+        let name = name.trim().to_ascii_lowercase();
+        Easing::ALL.iter().copied().find(|e| e.name() == name)
+    }
+
+    /**
+    Return how far along the animation is (from 0 to 1) when `t` of its
+    time (also from 0 to 1) has gone by.
+    */
+    pub fn apply(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/**
+Return the view `t` of the way (from 0 to 1) from `a` to `b`. The width
+changes logarithmically, and the center moves in step with it. The result
+has `a`'s size in pixels and oversampling.
+*/
+pub fn interpolate_dims(a: &ImageDims, b: &ImageDims, t: f64) -> ImageDims {
+    let t = t.clamp(0.0, 1.0);
+    let width = a.width * (b.width / a.width).powf(t);
+    // How far the center has moved: as far as the zoom has gone, so that
+    // the point being zoomed in on stays in the same place on the screen.
+    // (Without any zoom, it just moves at a steady pace.)
+    let dw = a.width - b.width;
+    let s = if dw.abs() > a.width * 1.0e-9 {
+        (a.width - width) / dw
+    } else {
+        t
+    };
+    let ((ax, ay), (bx, by)) = (a.center(), b.center());
+    a.framing(ax + (bx - ax) * s, ay + (by - ay) * s, width, 0.0)
+}
+
+// How far it is from `a` to `b`: the number of doublings (or halvings) of
+// the width, plus the distance between the centers in widths.
+fn distance(a: &ImageDims, b: &ImageDims) -> f64 {
+    let zoom = (b.width / a.width).log2().abs();
+    let ((ax, ay), (bx, by)) = (a.center(), b.center());
+    let pan = (bx - ax).hypot(by - ay) / (a.width * b.width).sqrt();
+    if (zoom + pan).is_finite() {
+        zoom + pan
+    } else {
+        0.0
+    }
+}
+
+/**
+Return the name of frame `n` (counting from 0) of `count`: `base` with the
+frame number (from 1, padded so they sort in order) added to its stem, so
+that "zoom.png" gives "zoom_001.png" and so on.
+*/
+pub fn frame_name(base: &Path, n: usize, count: usize) -> PathBuf {
+    let stem = base.file_stem().unwrap_or_default().to_string_lossy();
+    let digits = count.max(1).to_string().len();
+    base.with_file_name(format!("{}_{:0width$}.png", &stem, n + 1, width = digits))
+}
+
+/**
+Parse how many frames an animation should have, optionally followed by an
+easing (like "120" or "120 ease-in-out"); without one, it's linear.
+*/
+pub fn parse_plan(text: &str) -> Result<(usize, Easing), String> {
+    let mut words = text.split_whitespace();
+    let frames = match words.next().map(|w| w.parse::<usize>()) {
+        Some(Ok(n)) if n >= 2 => n,
+        _ => {
+            return Err(format!(
+                "{:?} doesn't start with a number of frames (at least 2)",
+                text
+            ));
+        }
+    };
+    let easing = match words.next() {
+        None => Easing::Linear,
+        Some(w) => Easing::from_name(w).ok_or_else(|| {
+            let names: Vec<&str> = Easing::ALL.iter().map(|e| e.name()).collect();
+            format!("{:?} isn't one of: {}", w, names.join(", "))
+        })?,
+    };
+    match words.next() {
+        Some(w) => Err(format!("unexpected {:?}", w)),
+        None => Ok((frames, easing)),
+    }
+}
+
+/** A sequence of frames through a list of keyframes. */
+#[derive(Clone, Debug)]
+pub struct Animation {
+    keyframes: Vec<Keyframe>,
+    frames: usize,
+    easing: Easing,
+    // Where along the whole animation (from 0 to 1) each keyframe comes.
+    stops: Vec<f64>,
+    // Whether to blend everything between keyframes, not just the view.
+    morph: bool,
+}
+
+impl Animation {
+    /**
+    Plan an animation of `frames` frames (at least two) going through
+    `keyframes` (at least two). Every frame is the size of the first
+    keyframe (in pixels), and is sampled the same way.
+    */
+    pub fn new(
+        keyframes: Vec<Keyframe>,
+        frames: usize,
+        easing: Easing,
+    ) -> Result<Animation, String> {
+        if keyframes.len() < 2 {
+            return Err("An animation needs at least two keyframes.".to_string());
+        }
+        if frames < 2 {
+            return Err("An animation needs at least two frames.".to_string());
+        }
+        let first = keyframes[0].0;
+        let keyframes: Vec<Keyframe> = keyframes
+            .into_iter()
+            .map(|(dims, cspec, itype)| {
+                let mut dims = dims.resize(first.xpix, first.ypix);
+                dims.oversample = first.oversample;
+                dims.adaptive = first.adaptive;
+                (dims, cspec, itype)
+            })
+            .collect();
+
+        let lengths: Vec<f64> = keyframes
+            .windows(2)
+            .map(|w| distance(&w[0].0, &w[1].0))
+            .collect();
+        let total: f64 = lengths.iter().sum();
+        let n_segs = lengths.len() as f64;
+        let mut stops = vec![0.0];
+        let mut so_far = 0.0;
+        for len in lengths.iter() {
+            // If nothing moves at all, they all get the same time.
+            so_far += if total > 0.0 {
+                len / total
+            } else {
+                1.0 / n_segs
+            };
+            stops.push(so_far);
+        }
+        *stops.last_mut().unwrap() = 1.0;
+
+        Ok(Animation {
+            keyframes,
+            frames,
+            easing,
+            stops,
+            morph: false,
+        })
+    }
+
+    /**
+    Make the frames in between keyframes blend the iterators and colors
+    as well as the views (see the `morph` module).
+    */
+    pub fn set_morph(&mut self, morph: bool) {
+        self.morph = morph;
+    }
+
+    /** The number of frames. */
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /** The parameters of frame `n` (counting from 0). */
+    pub fn frame(&self, n: usize) -> Keyframe {
+        if n + 1 >= self.frames {
+            return self.keyframes.last().unwrap().clone();
+        }
+        let t = (n as f64) / ((self.frames - 1) as f64);
+        let pos = self.easing.apply(t);
+        // The stretch between keyframes this is in (skipping any that
+        // take no time at all), and how far through it.
+        let seg = self.stops[1..]
+            .iter()
+            .position(|&stop| pos < stop)
+            .unwrap_or(self.stops.len() - 2);
+        let (start, end) = (self.stops[seg], self.stops[seg + 1]);
+        let local = if end > start {
+            (pos - start) / (end - start)
+        } else {
+            1.0
+        };
+        let (a, b) = (&self.keyframes[seg], &self.keyframes[seg + 1]);
+        if self.morph {
+            return morph::blend(a, b, local);
+        }
+        (
+            interpolate_dims(&a.0, &b.0, local),
+            a.1.clone(),
+            a.2.clone(),
+        )
+    }
+
+    /**
+    Render every frame to a numbered PNG named after `base` (see
+    `frame_name()`), with its parameters embedded. After each frame,
+    `progress` is called with the number done so far; if it returns
+    `false`, the rest are skipped.
+    */
+    pub fn render<F>(&self, base: &Path, mut progress: F) -> Result<(), String>
+    where
+        F: FnMut(usize) -> bool,
+    {
+        for n in 0..self.frames {
+            let (dims, cspec, itype) = self.frame(n);
+            let fname = frame_name(base, n, self.frames);
+            headless::export(&fname, dims, cspec, itype, true, rw::BitDepth::Eight)
+                .map_err(|e| format!("Error rendering {}: {}", fname.display(), &e))?;
+            if !progress(n + 1) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    Render every frame to `out`: numbered PNGs (like `render()`), or a
+    video or animated image at `fps` frames per second. `progress` works the same way as it
+    does for `render()`.
+    */
+    pub fn render_to<F>(&self, out: &Output, fps: f64, progress: F) -> Result<(), String>
+    where
+        F: FnMut(usize) -> bool,
+    {
+        match out {
+            Output::Frames(base) => self.render(base, progress),
+            _ => {
+                let first = &self.keyframes[0].0;
+                let frame = |n| {
+                    let (dims, cspec, itype) = self.frame(n);
+                    headless::render(dims, cspec, itype).to_rgb8(1).2
+                };
+                video::encode(
+                    out,
+                    (first.xpix, first.ypix),
+                    fps,
+                    self.frames,
+                    frame,
+                    progress,
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keyframe(x: f64, y: f64, width: f64) -> Keyframe {
+        let dims = ImageDims {
+            xpix: 30,
+            ypix: 20,
+            x,
+            y,
+            width,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        let cspec = ColorSpec::new(vec![Gradient::default()], RGB::BLACK);
+        (dims, cspec, IterType::Mandlebrot)
+    }
+
+    #[test]
+    fn easings() {
+        for e in Easing::ALL.iter() {
+            assert_eq!(Easing::from_name(&e.name().to_uppercase()), Some(*e));
+            assert_eq!((e.apply(0.0), e.apply(1.0)), (0.0, 1.0));
+            let steps: Vec<f64> = (0..=10).map(|n| e.apply(n as f64 / 10.0)).collect();
+            assert!(steps.windows(2).all(|w| w[1] > w[0]), "{:?}", e);
+        }
+        assert!(Easing::EaseIn.apply(0.5) < 0.5 && Easing::EaseOut.apply(0.5) > 0.5);
+        assert_eq!(Easing::from_name("bouncy"), None);
+
+        assert_eq!(parse_plan(" 120 "), Ok((120, Easing::Linear)));
+        assert_eq!(parse_plan("60 Ease-Out"), Ok((60, Easing::EaseOut)));
+        assert!(parse_plan("1").is_err());
+        assert!(parse_plan("60 bouncy").is_err());
+        assert!(parse_plan("60 ease-in twice").is_err());
+    }
+
+    #[test]
+    fn zooming() {
+        let (a, _, _) = keyframe(-2.0, 1.0, 3.0);
+        let b = a.recenter(0.75, 0.25).zoom(100.0);
+        let half = interpolate_dims(&a, &b, 0.5);
+        assert!((half.width - 0.3).abs() < 1.0e-12);
+        // The point zoomed in on (the one that's in the same place in both
+        // views) stays in the same place on the screen all along.
+        let ((ax, ay), (bx, by)) = (a.center(), b.center());
+        let k = a.width / (a.width - b.width);
+        let target = (ax + (bx - ax) * k, ay + (by - ay) * k);
+        let frac = |d: &ImageDims| crate::mapping::plane_to_frac(d, target.0, target.1);
+        let (fa, fh, fb) = (frac(&a), frac(&half), frac(&b));
+        for f in [fh, fb] {
+            assert!((fa.0 - f.0).abs() < 1.0e-9 && (fa.1 - f.1).abs() < 1.0e-9);
+        }
+        assert_eq!(interpolate_dims(&a, &b, 0.0), a);
+        let end = interpolate_dims(&a, &b, 1.0);
+        assert!((end.width - b.width).abs() < 1.0e-15);
+
+        assert_eq!(
+            frame_name(Path::new("out/zoom.png"), 6, 120),
+            PathBuf::from("out/zoom_007.png")
+        );
+    }
+
+    #[test]
+    fn animations() {
+        let a = keyframe(-2.0, 1.0, 3.0);
+        let b = (a.0.zoom(4.0), a.1.clone(), a.2.clone());
+        let c = (b.0.zoom(2.0), a.1.clone(), a.2.clone());
+        assert!(Animation::new(vec![a.clone()], 10, Easing::Linear).is_err());
+        assert!(Animation::new(vec![a.clone(), b.clone()], 1, Easing::Linear).is_err());
+
+        // Zooming in 4x takes twice as long as zooming in 2x, so the width
+        // goes down by the same factor every frame.
+        let anim =
+            Animation::new(vec![a.clone(), b.clone(), c.clone()], 7, Easing::Linear).unwrap();
+        assert_eq!(anim.frame(0).0, a.0);
+        assert_eq!(anim.frame(6).0, c.0);
+        let widths: Vec<f64> = (0..7).map(|n| anim.frame(n).0.width).collect();
+        for w in widths.windows(2) {
+            assert!(
+                (w[0] / w[1] - 2f64.powf(0.5)).abs() < 1.0e-9,
+                "{:?}",
+                &widths
+            );
+        }
+
+        // A morph blends the iterators, too.
+        let julia = |re: f64| IterType::Polynomial {
+            coefs: vec![
+                crate::cx::Cx::rect(re, 0.0),
+                crate::cx::Cx::rect(0.0, 0.0),
+                crate::cx::Cx::rect(1.0, 0.0),
+            ],
+        };
+        let ends = vec![
+            (a.0, a.1.clone(), julia(-1.0)),
+            (a.0, a.1.clone(), julia(0.0)),
+        ];
+        let mut morph = Animation::new(ends, 5, Easing::Linear).unwrap();
+        assert_eq!(morph.frame(2).2, julia(-1.0));
+        morph.set_morph(true);
+        assert_eq!(morph.frame(2).2, julia(-0.5));
+
+        let dir = std::env::temp_dir().join(format!("jset_animate_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("zoom.png");
+        let mut done = Vec::new();
+        anim.render(&base, |n| {
+            done.push(n);
+            n < 3
+        })
+        .unwrap();
+        assert_eq!(done, vec![1, 2, 3]);
+        let (dims, _, _) = crate::rw::load(frame_name(&base, 2, 7)).unwrap();
+        assert_eq!(dims, anim.frame(2).0);
+        assert!(!frame_name(&base, 3, 7).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
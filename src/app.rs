@@ -0,0 +1,1780 @@
+/*!
+The running application: its panes, the image they're showing, and what
+it does with each `Msg` the panes send.
+
+This is kept out of `main()` so that it can be driven by tests (or
+anything else) just by feeding it messages.
+*/
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+
+use fltk::dialog;
+
+use crate::animate::{self, Animation};
+use crate::cycle;
+use crate::export;
+use crate::gallery;
+use crate::history::History;
+use crate::hook;
+use crate::image::*;
+use crate::legend;
+use crate::mapping;
+use crate::morph;
+use crate::prefs::WindowPositions;
+use crate::render::{Render, RenderTask};
+use crate::rw;
+use crate::script;
+use crate::ui::{self, Msg};
+use crate::video::{self, Output};
+
+// How many sets of image parameters to remember for undoing.
+const UNDO_LIMIT: usize = 100;
+
+// How many views of the image to remember for going back to.
+const VIEW_LIMIT: usize = 100;
+
+// The minimap's overview only gets iterated this far, so that it's quick
+// even with a very long color map.
+const MINIMAP_LIMIT: usize = 1000;
+
+// Everything about an image that can be undone.
+type Params = (ImageDims, ColorSpec, IterType);
+
+// Asks for a file name, with the same arguments as `ui::pick_a_file()`.
+type FilePicker = Box<dyn FnMut(&str, ui::FileMode) -> Option<String>>;
+// Asks for some text, with a prompt and the text to start with.
+type TextAsker = Box<dyn FnMut(&str, &str) -> Option<String>>;
+type ExportChooser = Box<dyn FnMut(ui::save::ExportOptions) -> Option<ui::save::ExportOptions>>;
+
+// What the steps of rendering an animation are called, and what to say
+// when it's done.
+const ANIMATION_WORDS: (&str, &str) = ("frame", "Animation done.");
+
+// Save `img` (an image of `params`, if they're to be embedded), shrunk by
+// `scale`, to `fname` as `opts` say; PNGs get 16 bits per channel if
+// `deep`.
+fn save_image(
+    fname: &str,
+    img: &FImage32,
+    scale: usize,
+    params: Option<(&ImageDims, &ColorSpec, &IterType)>,
+    opts: &ui::save::ExportOptions,
+    deep: bool,
+) -> Result<(), String> {
+    match opts.format {
+        rw::ImageFormat::Png if deep => {
+            let (xpix, ypix, data) = if img.is_transparent() {
+                img.to_rgba16(scale)
+            } else {
+                img.to_rgb16(scale)
+            };
+            rw::save16_with_metadata(fname, xpix, ypix, &data, params, opts.compression)
+        }
+        rw::ImageFormat::Png => {
+            let (xpix, ypix, data) = img.to_bytes(scale);
+            rw::save_with_metadata(fname, xpix, ypix, &data, params, opts.compression)
+        }
+        format => {
+            let (xpix, ypix, data) = img.to_bytes(scale);
+            rw::save_as(fname, xpix, ypix, &data, format)
+        }
+    }
+}
+
+/**
+A container to hold all the global variables: the application's three
+panes, and everything about the image they're showing.
+
+The main loop hands each `Msg` the panes send it to `handle()`, which is
+where everything the application does in response to the user happens.
+*/
+pub struct Globs {
+    iter_pane: ui::iter::IterPane,
+    colr_pane: ui::color::ColorPane,
+    main_pane: ui::img::ImgPane,
+    minimap: ui::minimap::MiniMap,
+
+    cur_dims: ImageDims,
+    cur_iter: IterType,
+    cur_spec: ColorSpec,
+    cur_cmap: ColorMap,
+    // This is `None` while the map is being generated in the background,
+    // or if the last attempt to generate it was cancelled.
+    cur_imap: Option<IterMap>,
+    cur_fimg: FImage32,
+    // The iteration map of the minimap's overview of the whole set.
+    overview: Option<IterMap>,
+    // Counts saved with an image that's just been loaded, to use instead
+    // of iterating it (see `rw::save_counts()`).
+    loaded_imap: Option<IterMap>,
+
+    cur_scale: usize,
+    // The gradient whose pixels are highlighted on the image, if any.
+    highlight: Option<usize>,
+
+    // The render currently in progress, if any.
+    render: Option<Render>,
+    // Used to give each render a unique id.
+    n_renders: usize,
+    pipe: mpsc::Sender<Msg>,
+
+    // The size of the image last put on the screen.
+    shown: (usize, usize),
+    // The image parameters after each change, for undoing.
+    undo: History<Params>,
+    // Each view of the image, for going back and forth between them.
+    views: History<ImageDims>,
+    // The keyframes picked for an animation.
+    keyframes: Vec<Params>,
+    // The two sets of parameters being morphed between, and the window
+    // with the slider for it.
+    morph_ends: Option<(Params, Params)>,
+    morph_pane: Option<ui::morph::MorphPane>,
+    // Set to stop the animation being rendered in the background; `None`
+    // when there isn't one.
+    animating: Option<Arc<AtomicBool>>,
+    // What went wrong with the last animation render, if anything.
+    animation_error: Arc<Mutex<Option<String>>>,
+    // What each step of the job running in the background is called, and
+    // what to say when it's done (see `start_animation()`).
+    job_words: (&'static str, &'static str),
+    // The timer moving the colors on while they're cycling (`None` when
+    // they aren't), and how far around (from 0 to 1) they've gone.
+    cycling: Option<fltk::app::TimeoutHandle>,
+    cycle_offset: f64,
+    // The clipboard, once an image has been copied to it. It's kept open
+    // because on X11 an image only stays on the clipboard for as long as
+    // whoever copied it is around to hand it over.
+    clipboard: Option<arboard::Clipboard>,
+    // How the user gets asked for a file name (with the same arguments as
+    // `ui::pick_a_file()`) and told about things going wrong.
+    pick_file: FilePicker,
+    tell: Box<dyn FnMut(&str)>,
+    // How the user gets asked to type (or paste) in some text.
+    ask: TextAsker,
+    // How the user gets asked how to save an image (starting from the
+    // size shown, and the kind of file, compression, and legend last used).
+    choose_export: ExportChooser,
+    save_format: rw::ImageFormat,
+    save_compression: rw::PngCompression,
+    save_legend: bool,
+    // The sizes last asked for in an export set.
+    export_sizes: String,
+}
+
+impl Globs {
+    /**
+    Open the application's panes, showing the image described by `dims`
+    (with the color map `initial_spec` and the Mandlebrot iterator). The
+    panes will send their `Msg`s down `pipe`; `version` goes in the title
+    bar.
+    */
+    pub fn new(
+        pipe: mpsc::Sender<Msg>,
+        version: &str,
+        dims: ImageDims,
+        initial_spec: ColorSpec,
+    ) -> Globs {
+        let mut main_pane = ui::img::ImgPane::new(pipe.clone(), version, dims);
+        let colr_pane = ui::color::ColorPane::new(initial_spec, pipe.clone());
+        let mut iter_pane = ui::iter::IterPane::new(IterType::Mandlebrot, pipe.clone());
+        iter_pane.set_periodicity_checking(dims.check_period);
+        let minimap = ui::minimap::MiniMap::new(pipe.clone());
+
+        let color_spec = colr_pane.get_spec();
+        let color_map = ColorMap::make(color_spec.clone());
+        let iter_type = iter_pane.get_itertype();
+        let iter_map = IterMap::with_orbit(
+            dims,
+            iter_type.clone(),
+            color_map.limit(),
+            color_spec.orbit(),
+        );
+
+        let fp_image = iter_map.color(&color_map);
+
+        let (xpix, ypix, rgb_data) = fp_image.to_bytes(1);
+        main_pane.set_image(xpix, ypix, rgb_data);
+
+        let mut globs = Globs {
+            iter_pane,
+            colr_pane,
+            main_pane,
+            minimap,
+
+            cur_dims: dims,
+            cur_iter: iter_type.clone(),
+            cur_spec: color_spec.clone(),
+            cur_cmap: color_map,
+            cur_imap: Some(iter_map),
+            cur_fimg: fp_image,
+            overview: None,
+            loaded_imap: None,
+
+            cur_scale: 1,
+            highlight: None,
+
+            render: None,
+            n_renders: 0,
+            pipe,
+
+            shown: (xpix, ypix),
+            undo: History::new((dims, color_spec, iter_type), UNDO_LIMIT),
+            views: History::new(dims, VIEW_LIMIT),
+            keyframes: Vec::new(),
+            morph_ends: None,
+            morph_pane: None,
+            animating: None,
+            animation_error: Arc::new(Mutex::new(None)),
+            job_words: ANIMATION_WORDS,
+            cycling: None,
+            cycle_offset: 0.0,
+            clipboard: None,
+            pick_file: Box::new(ui::pick_a_file),
+            tell: Box::new(dialog::message_default),
+            ask: Box::new(dialog::input_default),
+            choose_export: Box::new(ui::save::choose_export),
+            save_format: rw::ImageFormat::Png,
+            save_compression: rw::PngCompression::Best,
+            save_legend: false,
+            export_sizes: export::DEFAULT_SIZES.to_string(),
+        };
+        globs.update_minimap(true);
+        // Any layers that need counts of their own get them in the
+        // background.
+        globs.recolor();
+        globs
+    }
+
+    /**
+    Ask for file names and report problems some other way than with
+    dialog boxes, like for driving the application from a test.
+    `pick_file` takes the same arguments as `ui::pick_a_file()`. Images
+    get saved as they're shown, in whatever format was used last (PNG to
+    start with), without asking, and questions that want text typed in go
+    unanswered.
+    */
+    pub fn set_dialogs<P, T>(&mut self, pick_file: P, tell: T)
+    where
+        P: FnMut(&str, ui::FileMode) -> Option<String> + 'static,
+        T: FnMut(&str) + 'static,
+    {
+        self.pick_file = Box::new(pick_file);
+        self.tell = Box::new(tell);
+        self.ask = Box::new(|_, _| None);
+        self.choose_export = Box::new(Some);
+    }
+
+    /** Return the parameters of the image currently shown (or being rendered). */
+    pub fn params(&self) -> (ImageDims, ColorSpec, IterType) {
+        (self.cur_dims, self.cur_spec.clone(), self.cur_iter.clone())
+    }
+
+    /** Return the width and height of the image last put on the screen. */
+    pub fn shown_size(&self) -> (usize, usize) {
+        self.shown
+    }
+
+    /** Return where the windows are (docked panes don't count). */
+    pub fn window_positions(&self) -> WindowPositions {
+        let pos = |p: Option<(i32, i32)>| p.map(|(x, y)| [x, y]);
+        WindowPositions {
+            main: pos(Some(self.main_pane.position())),
+            color: pos(self.colr_pane.position()),
+            iter: pos(self.iter_pane.position()),
+            minimap: pos(self.minimap.position()),
+        }
+    }
+
+    /** Put the windows back where they were (like last time). */
+    pub fn move_windows(&mut self, pos: &WindowPositions) {
+        if let Some([x, y]) = pos.main {
+            self.main_pane.move_to(x, y);
+        }
+        if let Some([x, y]) = pos.color {
+            self.colr_pane.move_to(x, y);
+        }
+        if let Some([x, y]) = pos.iter {
+            self.iter_pane.move_to(x, y);
+        }
+        if let Some([x, y]) = pos.minimap {
+            self.minimap.move_to(x, y);
+        }
+    }
+
+    /**
+    Return everything needed to pick up where things are now: each set of
+    parameters in the undo history (named "step 1", "step 2", and so on),
+    and the views stepped through.
+    */
+    pub fn session(&self) -> rw::Session {
+        let params: Vec<rw::NamedParams> = self
+            .undo
+            .states()
+            .iter()
+            .enumerate()
+            .map(|(n, (dims, cspec, itype))| rw::NamedParams {
+                name: format!("step {}", n + 1),
+                iterator: itype.clone(),
+                dimensions: *dims,
+                color_spec: cspec.clone(),
+            })
+            .collect();
+        rw::Session {
+            current: params[self.undo.position()].name.clone(),
+            view: self.views.position(),
+            views: self.views.states().to_vec(),
+            params,
+        }
+    }
+
+    /**
+    Pick up a saved `session`: its parameter sets become the undo history
+    and its views the view history, and its current parameters get shown.
+    */
+    pub fn restore_session(&mut self, session: rw::Session) {
+        let current = session.current_index().unwrap_or(0);
+        let states: Vec<Params> = session
+            .params
+            .into_iter()
+            .map(|p| (p.dimensions, p.color_spec, p.iterator))
+            .collect();
+        let (undo, views) = match (
+            History::from_states(states, current, UNDO_LIMIT),
+            History::from_states(session.views, session.view, VIEW_LIMIT),
+        ) {
+            (Some(undo), Some(views)) => (undo, views),
+            _ => {
+                (self.tell)("That session has nothing in it.");
+                return;
+            }
+        };
+        self.undo = undo;
+        self.views = views;
+        // These are already current in the histories, so they won't get
+        // recorded again.
+        let (dims, cspec, itype) = self.undo.current().clone();
+        self.apply_params(dims, cspec, itype);
+    }
+
+    /** Return whether a render is going on in the background. */
+    pub fn is_rendering(&self) -> bool {
+        self.render.is_some()
+    }
+
+    /** Do whatever the user has asked for with `msg`. */
+    pub fn handle(&mut self, msg: Msg) {
+        #[cfg(debug_assertions)]
+        println!("{:?}", &msg);
+        match msg {
+            Msg::AddKeyframe => {
+                self.keyframes.push(self.params());
+                let text = format!("Added keyframe {}.", self.keyframes.len());
+                self.main_pane.set_status(&text);
+            }
+            Msg::AnimationProgress(done, total) => {
+                let text = format!("Rendered {} {} of {}.", self.job_words.0, done, total);
+                self.main_pane.set_status(&text);
+            }
+            Msg::AnimationDone => {
+                self.animating = None;
+                match self.animation_error.lock().unwrap().take() {
+                    Some(e) => (self.tell)(&e),
+                    None => self.main_pane.set_status(self.job_words.1),
+                }
+            }
+            Msg::Cancel => {
+                self.cancel_render();
+                // Counts of some other view (or color map) are no use;
+                // without them, the next redraw starts over.
+                if !self.map_is_current() {
+                    self.cur_imap = None;
+                }
+                if let Some(stop) = &self.animating {
+                    stop.store(true, Ordering::Relaxed);
+                }
+            }
+            Msg::CheckPeriod(on) => {
+                // This changes (a few of) the iteration counts, so the whole
+                // thing gets redone.
+                let mut dims = self.cur_dims;
+                dims.check_period = on;
+                self.recheck_and_redraw(dims);
+            }
+            Msg::ClearKeyframes => {
+                self.keyframes.clear();
+                self.main_pane.set_status("Keyframes cleared.");
+            }
+            Msg::CopyImage => {
+                let (xpix, ypix, data) = self.cur_fimg.to_rgba8(self.cur_scale);
+                match self.copy_image(xpix, ypix, data) {
+                    Ok(()) => self.main_pane.set_status("Image copied to the clipboard."),
+                    Err(e) => (self.tell)(&e),
+                }
+            }
+            Msg::CopyShareString => {
+                match rw::share_string(&self.cur_dims, &self.cur_spec, &self.cur_iter) {
+                    Ok(text) => {
+                        fltk::app::copy(&text);
+                        self.main_pane
+                            .set_status("Share string copied to the clipboard.");
+                    }
+                    Err(e) => (self.tell)(&e),
+                }
+            }
+            Msg::CycleColors => {
+                self.toggle_cycling();
+            }
+            Msg::CycleStep => {
+                self.cycle_step();
+            }
+            Msg::ExportCycle => {
+                self.export_cycle();
+            }
+            Msg::ExportSet => {
+                self.export_set();
+            }
+            Msg::ExportMorph => {
+                if let Some((a, b)) = self.morph_ends.clone() {
+                    self.export_animation(vec![a, b], true);
+                }
+            }
+            Msg::FocusColorPane => {
+                self.colr_pane.raise();
+            }
+            Msg::FocusIterPane => {
+                self.iter_pane.raise();
+            }
+            Msg::FocusMainPane => {
+                self.main_pane.raise();
+            }
+            Msg::HighlightGradient(n) => {
+                if n != self.highlight {
+                    self.highlight = n;
+                    self.show_highlight();
+                }
+            }
+            Msg::IterTypeChanged => {
+                let itype = self.iter_pane.get_itertype();
+                let dims = itype.default_view(&self.cur_dims);
+                if dims != self.cur_dims
+                    && dialog::choice2_default(
+                        "Reset the view to frame the new iterator?",
+                        "Keep view",
+                        "Reset view",
+                        "",
+                    ) == Some(1)
+                {
+                    self.recheck_and_redraw(dims);
+                }
+            }
+            Msg::JumpTo(x, y) => {
+                let dims = self.cur_dims;
+                let dims = dims.framing(x, y, dims.width, dims.height());
+                self.recheck_and_redraw(dims);
+            }
+            Msg::Load => {
+                //let fname = match (self.pick_file)(".toml") {
+                let fname = match (self.pick_file)(
+                    "Images and parameters\t*.{png,toml,json}\n\
+                     PNG files\t*.png\nMarkup files\t*.toml\nJSON files\t*.json\n\
+                     Gradients\t*.{ugr,cpt}\nLocations\t*.{kfr,par}",
+                    ui::FileMode::Open,
+                ) {
+                    Some(f) => f,
+                    None => {
+                        return;
+                    }
+                };
+                self.load_file(&fname);
+            }
+            Msg::LoadDropped => {
+                let text = self.main_pane.take_dropped().unwrap_or_default();
+                match rw::dropped_file(&text) {
+                    Some(fname) => self.load_file(&fname),
+                    None => (self.tell)("Only files can be dropped here."),
+                }
+            }
+            Msg::LoadExample(n) => {
+                let name = gallery::names()[n];
+                match gallery::load(name) {
+                    Err(e) => (self.tell)(&format!("Error loading example {}: {}", name, &e)),
+                    Ok((dims, cspec, itype)) => {
+                        self.apply_params(dims, cspec, itype);
+                    }
+                }
+            }
+            Msg::LoadSession => {
+                let fname = match (self.pick_file)(".toml", ui::FileMode::Open) {
+                    Some(f) => f,
+                    None => {
+                        return;
+                    }
+                };
+                match rw::load_session(&fname) {
+                    Err(e) => (self.tell)(&format!("Error loading {}: {}", &fname, &e)),
+                    Ok(session) => self.restore_session(session),
+                }
+            }
+            Msg::LoadShareString => {
+                let text = match (self.ask)("Paste in a share string (jset:...):", "") {
+                    Some(text) => text,
+                    None => {
+                        return;
+                    }
+                };
+                match rw::from_share_string(&text) {
+                    Ok((dims, cspec, itype)) => self.apply_params(dims, cspec, itype),
+                    Err(e) => (self.tell)(&e),
+                }
+            }
+            Msg::Morph => {
+                self.start_morph();
+            }
+            Msg::MorphTo(t) => {
+                if let Some((a, b)) = &self.morph_ends {
+                    let (dims, cspec, itype) = morph::blend(a, b, t);
+                    self.apply_params(dims, cspec, itype);
+                }
+            }
+            Msg::Nudge(fxpix, fypix) => {
+                let mut dims = self.cur_dims;
+                let (dx, dy) = mapping::pixel_offset(&dims, fxpix, fypix);
+                dims.x += dx;
+                dims.y += dy;
+
+                self.recheck_and_redraw(dims);
+            }
+            Msg::Oversample(n, adaptive) => {
+                let mut dims = self.cur_dims;
+                dims.oversample = n;
+                dims.adaptive = adaptive;
+                self.recheck_and_redraw(dims);
+            }
+            Msg::PointerMoved(at) => {
+                let text = match at {
+                    Some((x_frac, y_frac)) => describe_point(
+                        &self.cur_dims,
+                        &self.cur_iter,
+                        self.cur_imap.as_ref(),
+                        x_frac,
+                        y_frac,
+                    ),
+                    None => String::new(),
+                };
+                self.main_pane.set_status(&text);
+            }
+            Msg::PasteParams => {
+                let text = match self.main_pane.take_pasted() {
+                    Some(t) => t,
+                    None => {
+                        return;
+                    }
+                };
+                match rw::load_pasted(&text) {
+                    Err(e) => (self.tell)(&format!("Unable to paste: {}", &e)),
+                    Ok((dims, cspec, itype)) => {
+                        self.apply_params(dims, cspec, itype);
+                    }
+                }
+            }
+            Msg::Recenter(xfrac, yfrac) => {
+                let dims = self.cur_dims.recenter(xfrac, yfrac);
+                self.recheck_and_redraw(dims);
+            }
+            Msg::RenderDone(id) => {
+                self.finish_render(id);
+            }
+            Msg::RenderPreview(id) => {
+                self.show_preview(id);
+            }
+            Msg::RenderProgress(id, done, total) => {
+                if matches!(&self.render, Some(r) if r.id() == id) {
+                    self.main_pane.set_progress(done, total);
+                    self.show_bands();
+                }
+            }
+            Msg::Redo => {
+                if let Some((dims, cspec, itype)) = self.undo.forward() {
+                    self.apply_params(dims, cspec, itype);
+                }
+            }
+            Msg::RenderAnimation => {
+                self.render_animation();
+            }
+            Msg::Recolor => {
+                self.recheck_and_redraw(self.cur_dims);
+            }
+            Msg::Redraw(owidth, oheight) => {
+                let dims = self.cur_dims;
+                let new_xpix = match owidth {
+                    Some(x) => x,
+                    None => dims.xpix,
+                };
+                let new_ypix = match oheight {
+                    Some(y) => y,
+                    None => dims.ypix,
+                };
+                let new_dims = dims.resize(new_xpix, new_ypix);
+                self.recheck_and_redraw(new_dims);
+            }
+            Msg::RunScript => {
+                self.run_script();
+            }
+            Msg::SaveImage => {
+                let start = ui::save::ExportOptions {
+                    format: self.save_format,
+                    xpix: self.shown.0,
+                    ypix: self.shown.1,
+                    oversample: self.cur_dims.samples(),
+                    metadata: self.main_pane.embed_metadata(),
+                    compression: self.save_compression,
+                    legend: self.save_legend,
+                };
+                let opts = match (self.choose_export)(start) {
+                    Some(opts) => opts,
+                    None => {
+                        return;
+                    }
+                };
+                self.save_format = opts.format;
+                self.save_compression = opts.compression;
+                self.save_legend = opts.legend;
+                let rerender = (opts.xpix, opts.ypix, opts.oversample)
+                    != (start.xpix, start.ypix, start.oversample);
+                if rerender && self.animating.is_some() {
+                    (self.tell)("An animation or script is already being rendered.");
+                    return;
+                }
+                if let (true, Err(e)) = (rerender, export::check_size(&opts.dims(&self.cur_dims))) {
+                    (self.tell)(&e);
+                    return;
+                }
+                if !rerender && self.cur_imap.is_none() {
+                    (self.tell)("The image isn't finished drawing; redraw it before saving it.");
+                    return;
+                }
+                let fname = match (self.pick_file)(opts.format.extension(), ui::FileMode::Save) {
+                    Some(fname) => fname,
+                    None => {
+                        return;
+                    }
+                };
+                rw::set_param_locking(self.main_pane.lock_params());
+                if rerender {
+                    self.export_image(fname, opts);
+                    return;
+                }
+                let (xpix, ypix, data) = self.main_pane.get_image();
+                let params = (&self.cur_dims, &self.cur_spec, &self.cur_iter);
+                let params = Some(params).filter(|_| opts.metadata);
+                let deep = self.main_pane.deep_color();
+                let res = if opts.legend {
+                    let mut img = self.cur_fimg.resample(xpix, ypix);
+                    legend::add_legend(&mut img, &self.cur_dims, &self.cur_cmap, &self.cur_iter);
+                    save_image(&fname, &img, 1, params, &opts, deep)
+                } else if opts.format == rw::ImageFormat::Png && deep {
+                    save_image(&fname, &self.cur_fimg, self.cur_scale, params, &opts, true)
+                } else if opts.format == rw::ImageFormat::Png {
+                    rw::save_with_metadata(&fname, xpix, ypix, &data, params, opts.compression)
+                } else {
+                    rw::save_as(&fname, xpix, ypix, &data, opts.format)
+                };
+                let res = res.and_then(|_| {
+                    if self.wants_counts(&opts) {
+                        self.save_counts(&fname)
+                    } else {
+                        Ok(())
+                    }
+                });
+                if let Err(e) = res.and_then(|_| hook::after_save(&fname, xpix, ypix)) {
+                    (self.tell)(&e);
+                };
+            }
+            Msg::SaveSession => {
+                let fname = match (self.pick_file)(".toml", ui::FileMode::Save) {
+                    Some(f) => f,
+                    None => {
+                        return;
+                    }
+                };
+                if let Err(e) = rw::save_session(&self.session(), &fname) {
+                    (self.tell)(&e);
+                }
+            }
+            Msg::SaveValues => {
+                let mut fname = match (self.pick_file)(".toml", ui::FileMode::SaveAny) {
+                    Some(f) => f,
+                    None => {
+                        return;
+                    }
+                };
+                // Parameters can be saved as JSON, too, if the user
+                // explicitly asks for it.
+                if !rw::ParamFormat::is_param_file(&fname) {
+                    fname.push_str(".toml");
+                }
+                rw::set_param_locking(self.main_pane.lock_params());
+                if let Err(estr) = rw::save(&self.cur_dims, &self.cur_spec, &self.cur_iter, &fname)
+                {
+                    (self.tell)(&estr);
+                }
+            }
+            Msg::Scale(n) => {
+                self.cur_scale = n;
+                self.recheck_and_redraw(self.cur_dims);
+            }
+            Msg::ScriptDone => {
+                self.animating = None;
+                match self.animation_error.lock().unwrap().take() {
+                    Some(e) => (self.tell)(&e),
+                    None => self.main_pane.set_status("Script done."),
+                }
+            }
+            Msg::ScriptProgress(n) => {
+                let text = format!("Script images rendered: {}.", n);
+                self.main_pane.set_status(&text);
+            }
+            Msg::StripMetadata => {
+                let src = match (self.pick_file)(".png", ui::FileMode::Open) {
+                    Some(f) => f,
+                    None => {
+                        return;
+                    }
+                };
+                let dest = match (self.pick_file)(".png", ui::FileMode::Save) {
+                    Some(f) => f,
+                    None => {
+                        return;
+                    }
+                };
+                if let Err(e) = rw::strip_metadata(&src, &dest) {
+                    (self.tell)(&e);
+                }
+            }
+            Msg::Undo => {
+                if let Some((dims, cspec, itype)) = self.undo.back() {
+                    self.apply_params(dims, cspec, itype);
+                }
+            }
+            Msg::ViewBack => {
+                if let Some(dims) = self.views.back() {
+                    self.show_view(dims);
+                }
+            }
+            Msg::ViewForward => {
+                if let Some(dims) = self.views.forward() {
+                    self.show_view(dims);
+                }
+            }
+            Msg::Zoom(r) => {
+                let dims = self.cur_dims.zoom(r);
+                self.recheck_and_redraw(dims);
+            }
+            Msg::ZoomRect(x0, y0, x1, y1) => {
+                let dims = self.cur_dims.zoom_to(x0, y0, x1, y1);
+                self.recheck_and_redraw(dims);
+            }
+        }
+    }
+
+    // Given the passed `ImageDims`, decides how much recalculation should
+    // be done, and does only that much, to re-display the current image.
+    //
+    // Any iteration happens in the background; the image will get updated
+    // when the `Msg::RenderDone` comes back.
+    fn recheck_and_redraw(&mut self, new_dims: ImageDims) {
+        let mut should_redraw = false;
+        let mut should_reiterate = false;
+        let mut should_recolor = false;
+
+        if new_dims != self.cur_dims {
+            should_redraw = true;
+            self.cur_dims = new_dims;
+        }
+
+        let new_iter = self.iter_pane.get_itertype();
+        if new_iter != self.cur_iter {
+            should_redraw = true;
+            self.cur_iter = new_iter;
+        }
+
+        let new_spec = self.colr_pane.get_spec();
+        if new_spec != self.cur_spec {
+            let new_cmap = ColorMap::make(new_spec.clone());
+            if new_cmap.limit() > self.cur_cmap.limit() {
+                should_reiterate = true;
+            }
+            // Orbit trap distances and statistics are spread over the
+            // whole limit, so they all change with it (or with the orbit).
+            if new_spec.orbit() != self.cur_spec.orbit()
+                || (new_spec.orbit().is_some() && new_cmap.limit() != self.cur_cmap.limit())
+            {
+                should_redraw = true;
+            }
+            self.cur_spec = new_spec;
+            self.cur_cmap = new_cmap;
+            should_recolor = true;
+        }
+
+        let mut rendering = self.render.is_some();
+        // Counts saved with an image that's just been loaded save iterating
+        // it, if they're really of what's being drawn now.
+        if let Some(imap) = self.loaded_imap.take() {
+            if should_redraw
+                && imap.dims() == self.cur_dims
+                && imap.itertype() == &self.cur_iter
+                && imap.orbit() == self.cur_spec.orbit()
+            {
+                self.cancel_render();
+                rendering = false;
+                should_redraw = false;
+                should_reiterate = imap.limit() < self.cur_cmap.limit();
+                should_recolor = true;
+                self.main_pane.set_approximate(imap.is_approximate());
+                self.cur_imap = Some(imap);
+            }
+        }
+        // Finish off a render that ran out of time.
+        if !rendering && matches!(&self.cur_imap, Some(imap) if imap.is_approximate()) {
+            should_reiterate = true;
+        }
+        if should_redraw
+            || (rendering && should_reiterate)
+            || (!rendering && self.cur_imap.is_none())
+        {
+            self.start_render(RenderTask::Iterate {
+                dims: self.cur_dims,
+                itertype: self.cur_iter.clone(),
+                limit: self.cur_cmap.limit(),
+                orbit: self.cur_spec.orbit(),
+            });
+        } else if should_reiterate {
+            if let Some(imap) = self.cur_imap.take() {
+                self.start_render(RenderTask::Reiterate {
+                    imap,
+                    limit: self.cur_cmap.limit(),
+                });
+            }
+        } else if should_recolor {
+            // If a render is in progress, it'll get colored with the
+            // current color map when it's done.
+            self.recolor();
+        }
+
+        // Undoing and redoing end up back here with parameters that are
+        // already in the history, so they don't get recorded again.
+        self.undo.record(self.params());
+        self.views.record(self.cur_dims);
+        self.update_minimap(should_recolor);
+        self.main_pane
+            .set_view_history(self.views.can_go_back(), self.views.can_go_forward());
+        self.display();
+    }
+
+    // Ask for two parameter files, and open the morph window to blend
+    // between them.
+    fn start_morph(&mut self) {
+        let mut ends: Vec<(String, Params)> = Vec::with_capacity(2);
+        while ends.len() < 2 {
+            let fname = match (self.pick_file)(".toml", ui::FileMode::Open) {
+                Some(f) => f,
+                None => {
+                    return;
+                }
+            };
+            match rw::load(&fname) {
+                Ok(params) => ends.push((fname, params)),
+                Err(e) => {
+                    (self.tell)(&format!("Error loading {}: {}", &fname, &e));
+                    return;
+                }
+            }
+        }
+        let (b_name, b) = ends.pop().unwrap();
+        let (a_name, a) = ends.pop().unwrap();
+        let name = |f: &str| {
+            Path::new(f)
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        if let Some(old) = self.morph_pane.take() {
+            old.close();
+        }
+        self.morph_pane = Some(ui::morph::MorphPane::new(
+            &name(&a_name),
+            &name(&b_name),
+            self.pipe.clone(),
+        ));
+        self.morph_ends = Some((a.clone(), b));
+        let (dims, cspec, itype) = a;
+        self.apply_params(dims, cspec, itype);
+    }
+
+    // Render an animation through the keyframes the user has added.
+    fn render_animation(&mut self) {
+        if self.keyframes.len() < 2 {
+            (self.tell)("Add at least two keyframes (File / Animation / Add Keyframe) first.");
+            return;
+        }
+        self.export_animation(self.keyframes.clone(), false);
+    }
+
+    // Ask how many frames to make through `keyframes` (blending everything
+    // between them if `morph` is set), and where, and start rendering them
+    // in the background.
+    fn export_animation(&mut self, keyframes: Vec<Params>, morph: bool) {
+        if self.animating.is_some() {
+            (self.tell)("An animation is already being rendered.");
+            return;
+        }
+        let plan = match dialog::input_default(
+            "Number of frames, and easing (linear, ease-in, ease-out, or ease-in-out):",
+            "120 ease-in-out",
+        ) {
+            Some(text) => text,
+            None => {
+                return;
+            }
+        };
+        let mut anim = match animate::parse_plan(&plan)
+            .and_then(|(frames, easing)| Animation::new(keyframes, frames, easing))
+        {
+            Ok(a) => a,
+            Err(e) => {
+                (self.tell)(&e);
+                return;
+            }
+        };
+        anim.set_morph(morph);
+        let out = match self.pick_animation_output() {
+            Some(out) => out,
+            None => {
+                return;
+            }
+        };
+        let total = anim.frames();
+        self.start_animation(ANIMATION_WORDS, total, move |progress, _| {
+            anim.render_to(&out, video::DEFAULT_FPS, progress)
+        });
+    }
+
+    // Ask where an animation should go: a video, if the name picked is
+    // one (and ffmpeg is around to make it), an animated GIF or PNG, or
+    // numbered PNGs ("zoom.png" gives "zoom_001.png" and so on).
+    fn pick_animation_output(&mut self) -> Option<Output> {
+        let mut fname = (self.pick_file)(".png", ui::FileMode::SaveAny)?;
+        let named = video::is_video(&fname) || rw::AnimationFormat::of(&fname).is_some();
+        if !named && !fname.to_ascii_lowercase().ends_with(".png") {
+            fname.push_str(".png");
+        }
+        let (out, note) = Output::choose(&fname);
+        if let Some(note) = note {
+            (self.tell)(&note);
+        }
+        Some(out)
+    }
+
+    // Start rendering `total` frames of an animation (or steps of some
+    // other job, called `words.0`, with `words.1` said at the end) in the
+    // background with `job`, which reports how many it's done to the
+    // function it's handed, and stops if that returns `false`. Any
+    // iterating it does should be watched by the `IterWatcher` it's
+    // handed, which gets cancelled along with it.
+    fn start_animation<J>(&mut self, words: (&'static str, &'static str), total: usize, job: J)
+    where
+        J: FnOnce(&mut dyn FnMut(usize) -> bool, &IterWatcher) -> Result<(), String>
+            + Send
+            + 'static,
+    {
+        self.job_words = words;
+        let stop = Arc::new(AtomicBool::new(false));
+        self.animating = Some(stop.clone());
+        let pipe = self.pipe.clone();
+        let error = self.animation_error.clone();
+        let mut watcher = IterWatcher::new();
+        watcher.stop_when(stop.clone());
+        std::thread::spawn(move || {
+            let mut progress = |done| {
+                if pipe.send(Msg::AnimationProgress(done, total)).is_ok() {
+                    fltk::app::awake();
+                }
+                !stop.load(Ordering::Relaxed)
+            };
+            if let Err(e) = job(&mut progress, &watcher) {
+                *error.lock().unwrap() = Some(e);
+            }
+            if pipe.send(Msg::AnimationDone).is_ok() {
+                fltk::app::awake();
+            }
+        });
+    }
+
+    // Ask for a script to run, and start running it in the background,
+    // starting from the image being shown. (It can be stopped the same
+    // way as an animation.)
+    fn run_script(&mut self) {
+        if self.animating.is_some() {
+            (self.tell)("An animation or script is already being rendered.");
+            return;
+        }
+        let fname = match (self.pick_file)(".rhai", ui::FileMode::Open) {
+            Some(fname) => fname,
+            None => {
+                return;
+            }
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        self.animating = Some(stop.clone());
+        let start = self.params();
+        let pipe = self.pipe.clone();
+        let error = self.animation_error.clone();
+        std::thread::spawn(move || {
+            let dir = Path::new(&fname).parent().unwrap_or(Path::new("."));
+            let mut runner = script::Runner::new(start, dir);
+            runner.stop_when(stop);
+            let progress = pipe.clone();
+            runner.on_render(move |_, n| {
+                if progress.send(Msg::ScriptProgress(n)).is_ok() {
+                    fltk::app::awake();
+                }
+            });
+            if let Err(e) = runner.run_file(&fname) {
+                *error.lock().unwrap() = Some(e);
+            }
+            if pipe.send(Msg::ScriptDone).is_ok() {
+                fltk::app::awake();
+            }
+        });
+    }
+
+    // Put `xpix` × `ypix` pixels of 8-bit RGBA `data` on the clipboard.
+    fn copy_image(&mut self, xpix: usize, ypix: usize, data: Vec<u8>) -> Result<(), String> {
+        let clipboard = match self.clipboard.as_mut() {
+            Some(clipboard) => clipboard,
+            None => {
+                let clipboard = arboard::Clipboard::new()
+                    .map_err(|e| format!("Unable to open the clipboard: {}", &e))?;
+                self.clipboard.insert(clipboard)
+            }
+        };
+        let image = arboard::ImageData {
+            width: xpix,
+            height: ypix,
+            bytes: data.into(),
+        };
+        clipboard
+            .set_image(image)
+            .map_err(|e| format!("Error copying the image: {}", &e))
+    }
+
+    // Start the colors cycling through the image, or stop them and put
+    // the image back the way it really is.
+    fn toggle_cycling(&mut self) {
+        match self.cycling.take() {
+            Some(timer) => {
+                fltk::app::remove_timeout3(timer);
+                if let Some(imap) = &self.cur_imap {
+                    self.cur_fimg = imap.color(&self.cur_cmap);
+                    self.display();
+                }
+            }
+            None => {
+                self.cycle_offset = 0.0;
+                let pipe = self.pipe.clone();
+                let timer = fltk::app::add_timeout3(cycle::FRAME_SECONDS, move |h| {
+                    if pipe.send(Msg::CycleStep).is_ok() {
+                        fltk::app::repeat_timeout3(cycle::FRAME_SECONDS, h);
+                    }
+                });
+                self.cycling = Some(timer);
+            }
+        }
+    }
+
+    // Move the cycling colors on a frame, just recoloring the iteration
+    // map already there. (The parameters, and so the undo history, don't
+    // change.)
+    fn cycle_step(&mut self) {
+        // A render in progress is showing its own previews.
+        if self.cycling.is_none() || self.render.is_some() {
+            return;
+        }
+        self.cycle_offset =
+            (self.cycle_offset + cycle::FRAME_SECONDS / cycle::CYCLE_SECONDS).rem_euclid(1.0);
+        if let Some(imap) = &self.cur_imap {
+            let cmap = ColorMap::make(cycle::shifted(&self.cur_spec, self.cycle_offset));
+            self.cur_fimg = imap.color(&cmap);
+            self.display();
+        }
+    }
+
+    // Ask how many frames to make of one full cycle of the colors through
+    // the current image, and where, and start coloring them in the
+    // background.
+    fn export_cycle(&mut self) {
+        if self.animating.is_some() {
+            (self.tell)("An animation is already being rendered.");
+            return;
+        }
+        let imap = match &self.cur_imap {
+            Some(imap) => imap.clone(),
+            None => {
+                (self.tell)("Wait for the image to finish rendering first.");
+                return;
+            }
+        };
+        let text =
+            match dialog::input_default("Number of frames in one cycle of the colors:", "120") {
+                Some(text) => text,
+                None => {
+                    return;
+                }
+            };
+        let frames = match text.trim().parse::<usize>() {
+            Ok(n) if n >= 2 => n,
+            _ => {
+                (self.tell)(&format!(
+                    "{:?} isn't a number of frames (at least 2).",
+                    text.trim()
+                ));
+                return;
+            }
+        };
+        let out = match self.pick_animation_output() {
+            Some(out) => out,
+            None => {
+                return;
+            }
+        };
+        let cspec = self.cur_spec.clone();
+        self.start_animation(ANIMATION_WORDS, frames, move |progress, _| {
+            cycle::render_to(&imap, &cspec, frames, &out, video::DEFAULT_FPS, progress)
+        });
+    }
+
+    // Load the image parameters (or just the colors) in the file `fname`.
+    fn load_file(&mut self, fname: &str) {
+        // Gradient files from other programs only have colors in them, so
+        // everything else stays as it is.
+        if rw::GradientFormat::of(fname).is_some() {
+            match rw::load_gradient(fname) {
+                Err(e) => (self.tell)(&format!("Error loading {}: {}", fname, &e)),
+                Ok(cspec) => {
+                    self.colr_pane.respec(cspec);
+                    self.recheck_and_redraw(self.cur_dims);
+                }
+            }
+            return;
+        }
+        // Likewise, other programs' location files have no colors in them,
+        // though they may say how far to iterate.
+        if rw::LocationFormat::of(fname).is_some() {
+            match rw::load_location(fname) {
+                Err(e) => (self.tell)(&format!("Error loading {}: {}", fname, &e)),
+                Ok(loc) => {
+                    let mut cspec = self.cur_spec.clone();
+                    if let Some(n) = loc.iterations {
+                        cspec.set_iterations(n);
+                    }
+                    self.apply_params(loc.view(&self.cur_dims), cspec, loc.itertype);
+                }
+            }
+            return;
+        }
+        match rw::load_checked(fname) {
+            Err(e) => (self.tell)(&format!("Error loading {}: {}", fname, &e)),
+            Ok((dims, cspec, itype, status)) => {
+                match rw::load_counts(fname, dims, itype.clone(), cspec.orbit()) {
+                    Ok(imap) => self.loaded_imap = imap,
+                    Err(e) => (self.tell)(&format!("Not using the saved counts: {}", &e)),
+                }
+                self.apply_params(dims, cspec, itype);
+                if status != rw::LockStatus::Unlocked {
+                    (self.tell)(&status.describe());
+                }
+            }
+        }
+    }
+
+    // Set all the panes to reflect a set of loaded image parameters, and
+    // redraw accordingly.
+    fn apply_params(&mut self, dims: ImageDims, cspec: ColorSpec, itype: IterType) {
+        self.colr_pane.respec(cspec);
+        // The new iterator pane goes wherever the old one had been moved.
+        let pos = self.iter_pane.position();
+        self.iter_pane = ui::iter::IterPane::new(itype, self.pipe.clone());
+        if let Some((x, y)) = pos {
+            self.iter_pane.move_to(x, y);
+        }
+        self.show_view(dims);
+    }
+
+    // Set the main pane's inputs to match `dims`, and redraw accordingly.
+    fn show_view(&mut self, dims: ImageDims) {
+        self.main_pane.set_input_dimensions(dims.xpix, dims.ypix);
+        self.main_pane.set_oversample(dims.samples(), dims.adaptive);
+        self.iter_pane.set_periodicity_checking(dims.check_period);
+        self.recheck_and_redraw(dims);
+    }
+
+    // Render the current image again as `opts` say (a different size, or
+    // with different oversampling) in the background, and save it to
+    // `fname`.
+    fn export_image(&mut self, fname: String, opts: ui::save::ExportOptions) {
+        let dims = opts.dims(&self.cur_dims);
+        let (cspec, itype) = (self.cur_spec.clone(), self.cur_iter.clone());
+        let deep = self.main_pane.deep_color();
+        let counts = self.wants_counts(&opts);
+        self.main_pane.set_status(&format!(
+            "Rendering {} x {} to save...",
+            dims.xpix, dims.ypix
+        ));
+        self.start_animation(("image", "Image saved."), 1, move |progress, watcher| {
+            let cmap = ColorMap::make(cspec.clone());
+            let cancelled = || "Cancelled; the image wasn't saved.".to_string();
+            let imap =
+                IterMap::new_watched(dims, itype.clone(), cmap.limit(), cspec.orbit(), watcher)
+                    .ok_or_else(cancelled)?;
+            if !imap.iterate_layers(&cmap, watcher) {
+                return Err(cancelled());
+            }
+            let mut img = imap.color(&cmap);
+            if opts.legend {
+                legend::add_legend(&mut img, &dims, &cmap, &itype);
+            }
+            let params = Some((&dims, &cspec, &itype)).filter(|_| opts.metadata);
+            save_image(&fname, &img, 1, params, &opts, deep)?;
+            if counts {
+                rw::save_counts(&fname, &imap)?;
+            }
+            hook::after_save(&fname, dims.xpix, dims.ypix)?;
+            progress(1);
+            Ok(())
+        });
+    }
+
+    // Ask for some sizes, and start saving the image at each of them in
+    // the background (see the `export` module), named after a file picked
+    // for the set, in the format images were last saved in.
+    fn export_set(&mut self) {
+        if self.animating.is_some() {
+            (self.tell)("An animation or script is already being rendered.");
+            return;
+        }
+        let names: Vec<&str> = export::PRESETS.iter().map(|p| p.0).collect();
+        let prompt = format!("Sizes to save (like 800x600, or {}):", names.join(", "));
+        let text = match (self.ask)(&prompt, &self.export_sizes) {
+            Some(text) => text,
+            None => {
+                return;
+            }
+        };
+        let sizes = match export::parse_sizes(&text) {
+            Ok(sizes) => sizes,
+            Err(e) => {
+                (self.tell)(&e);
+                return;
+            }
+        };
+        self.export_sizes = text;
+        let base = match (self.pick_file)(self.save_format.extension(), ui::FileMode::Save) {
+            Some(fname) => PathBuf::from(fname),
+            None => {
+                return;
+            }
+        };
+        rw::set_param_locking(self.main_pane.lock_params());
+        let (dims, cspec, itype) = self.params();
+        let (format, metadata) = (self.save_format, self.main_pane.embed_metadata());
+        let total = sizes.len();
+        self.main_pane.set_status("Rendering the image set...");
+        self.start_animation(
+            ("size", "Image set saved."),
+            total,
+            move |progress, watcher| {
+                export::export_set(
+                    &base, &dims, &cspec, &itype, &sizes, format, metadata, watcher, progress,
+                )
+                .map(|_| ())
+            },
+        );
+    }
+
+    // Whether the iteration counts should be saved with an image saved
+    // as `opts` say.
+    fn wants_counts(&self, opts: &ui::save::ExportOptions) -> bool {
+        opts.format == rw::ImageFormat::Png && opts.metadata && self.main_pane.save_counts()
+    }
+
+    // Save the iteration counts of the current image next to the image
+    // just saved as `fname`, if they're done.
+    fn save_counts(&self, fname: &str) -> Result<(), String> {
+        match &self.cur_imap {
+            Some(imap) if self.render.is_none() && imap.dims() == self.cur_dims => {
+                rw::save_counts(fname, imap)
+            }
+            _ => Err("The image isn't finished drawing, so its counts weren't saved.".to_string()),
+        }
+    }
+
+    // Color the current map, and, if any of the layers need counts of
+    // their own, start them iterating in the background (unless something
+    // else is; they'll get seen to when it's done). Until then, the image
+    // goes without them.
+    fn recolor(&mut self) {
+        let imap = match &self.cur_imap {
+            Some(imap) => imap,
+            None => {
+                return;
+            }
+        };
+        self.cur_fimg = imap.color(&self.cur_cmap);
+        if self.render.is_none() && imap.needs_layers(&self.cur_cmap) {
+            let task = RenderTask::Layers {
+                imap: imap.clone(),
+                cmap: Box::new(self.cur_cmap.clone()),
+            };
+            self.start_render(task);
+        }
+    }
+
+    // Show the current `FImage32` at the current scale.
+    fn display(&mut self) {
+        let (x, y, data) = self.cur_fimg.to_bytes(self.cur_scale);
+
+        self.main_pane.set_image(x, y, data);
+        self.shown = (x, y);
+        if self.highlight.is_some() {
+            self.show_highlight();
+        }
+    }
+
+    // Bring the minimap up to date: make a new overview if the iterator
+    // has changed (or the orbit colored by, or the color map's limit, up
+    // to `MINIMAP_LIMIT`),
+    // recolor it if `recolor` is set, and mark where the current view is.
+    // (It goes without any layers that need counts of their own.)
+    fn update_minimap(&mut self, recolor: bool) {
+        let limit = self.cur_cmap.limit().min(MINIMAP_LIMIT);
+        let stale = match &self.overview {
+            Some(m) => {
+                m.itertype() != &self.cur_iter
+                    || m.limit() != limit
+                    || m.orbit() != self.cur_spec.orbit()
+            }
+            None => true,
+        };
+        if stale {
+            let (xpix, ypix) = self.minimap.image_size();
+            let dims = ImageDims {
+                xpix,
+                ypix,
+                oversample: 1,
+                adaptive: false,
+                ..self.cur_dims
+            };
+            let dims = self.cur_iter.default_view(&dims);
+            self.overview = Some(IterMap::with_orbit(
+                dims,
+                self.cur_iter.clone(),
+                limit,
+                self.cur_spec.orbit(),
+            ));
+        }
+        if let (true, Some(imap)) = (stale || recolor, &self.overview) {
+            let (x, y, data) = imap.color(&self.cur_cmap).to_rgb8(1);
+            self.minimap.set_image(imap.dims(), x, y, data);
+        }
+        self.minimap.show_view(&self.cur_dims);
+    }
+
+    // Highlight the pixels colored by the gradient chosen in the color
+    // pane, or clear the highlight if there isn't one.
+    fn show_highlight(&mut self) {
+        let mask = match (self.highlight, &self.cur_imap) {
+            (Some(n), Some(imap)) => {
+                let spec = &self.cur_spec;
+                let mask = imap.mask(|c| spec.gradient_at(c) == Some(n));
+                let (_, _, data) = mask.to_rgb8(self.cur_scale);
+                Some(data)
+            }
+            _ => None,
+        };
+        self.main_pane.set_overlay(mask);
+    }
+
+    // Start a new background render, cancelling any in progress.
+    fn start_render(&mut self, task: RenderTask) {
+        self.cancel_render();
+        self.n_renders += 1;
+
+        let progress_pipe = self.pipe.clone();
+        let preview_pipe = self.pipe.clone();
+        let done_pipe = self.pipe.clone();
+        let r = Render::start(
+            self.n_renders,
+            task,
+            move |id, done, total| {
+                if progress_pipe
+                    .send(Msg::RenderProgress(id, done, total))
+                    .is_ok()
+                {
+                    fltk::app::awake();
+                }
+            },
+            move |id| {
+                if preview_pipe.send(Msg::RenderPreview(id)).is_ok() {
+                    fltk::app::awake();
+                }
+            },
+            move |id| {
+                if done_pipe.send(Msg::RenderDone(id)).is_ok() {
+                    fltk::app::awake();
+                }
+            },
+        );
+        self.render = Some(r);
+        self.main_pane.set_rendering(true);
+    }
+
+    // Whether the current map's counts are of the current view, iterator,
+    // and color map.
+    fn map_is_current(&self) -> bool {
+        let limit = self.cur_cmap.limit();
+        matches!(&self.cur_imap, Some(m) if m.dims() == self.cur_dims
+            && m.itertype() == &self.cur_iter
+            && m.orbit() == self.cur_spec.orbit()
+            && (m.limit() == limit || (m.orbit().is_none() && m.limit() > limit)))
+    }
+
+    // Stop the render in progress, if there is one.
+    fn cancel_render(&mut self) {
+        if let Some(r) = self.render.take() {
+            r.cancel();
+            self.main_pane.set_rendering(false);
+        }
+    }
+
+    // Display the latest preview from the render with the given `id`, if
+    // it's the current one.
+    fn show_preview(&mut self, id: usize) {
+        let preview = match &self.render {
+            Some(r) if r.id() == id => r.take_preview(),
+            _ => None,
+        };
+        if let Some((factor, imap)) = preview {
+            self.cur_fimg = imap.color(&self.cur_cmap).enlarge(factor, self.cur_dims);
+            self.display();
+        }
+    }
+
+    // Fill in any finished bands of the current render.
+    fn show_bands(&mut self) {
+        let bands = match &self.render {
+            Some(r) => r.take_bands(),
+            None => {
+                return;
+            }
+        };
+        if bands.is_empty() {
+            return;
+        }
+        if self.cur_fimg.dims() != self.cur_dims {
+            self.cur_fimg = FImage32::new(self.cur_dims, RGB::BLACK);
+        }
+        for band in bands.iter() {
+            self.cur_fimg.blit_band(band, &self.cur_cmap);
+        }
+        self.display();
+    }
+
+    // Collect the results of the render with the given `id`, if it's the
+    // current one, and display them.
+    fn finish_render(&mut self, id: usize) {
+        match &self.render {
+            Some(r) if r.id() == id => {}
+            _ => {
+                return;
+            }
+        }
+
+        let r = self.render.take().unwrap();
+        self.main_pane.set_rendering(false);
+        self.main_pane.set_stats(&r.stats().summary());
+        if let Some(imap) = r.finish() {
+            self.main_pane.set_approximate(imap.is_approximate());
+            let failed = imap.failed_rows();
+            let why = imap.failure().unwrap_or_default().to_string();
+            self.cur_imap = Some(imap);
+            self.recolor();
+            self.display();
+            if failed > 0 {
+                (self.tell)(&format!(
+                    "{} rows of the image (marked with stripes) couldn't be \
+                     iterated:\n{}\nTry a different view or iterator.",
+                    failed, &why
+                ));
+            }
+        }
+    }
+}
+
+// What the status line says about the point `x_frac` of the way across
+// the view `dims` and `y_frac` of the way down: where it is on the plane,
+// how far the view is zoomed in (compared to `itype`'s default view), and,
+// if `imap` has one for it, its iteration count.
+fn describe_point(
+    dims: &ImageDims,
+    itype: &IterType,
+    imap: Option<&IterMap>,
+    x_frac: f64,
+    y_frac: f64,
+) -> String {
+    let (x, y) = mapping::frac_to_plane(dims, x_frac, y_frac);
+    // Enough decimal places to tell neighboring pixels apart.
+    let pixel = dims.width / (dims.xpix as f64);
+    let places = ((-pixel.log10()).ceil().max(0.0) as usize) + 1;
+    let zoom = itype.default_view(dims).width / dims.width;
+    let mut text = format!("{:.*} {:+.*}i   zoom {:.3}x", places, x, places, y, zoom);
+
+    if let Some(imap) = imap {
+        let d = imap.dims();
+        if (0.0..1.0).contains(&x_frac) && (0.0..1.0).contains(&y_frac) {
+            let px = (x_frac * d.xpix as f64) as usize;
+            let py = (y_frac * d.ypix as f64) as usize;
+            match imap.count_at(px, py) {
+                Some(n) if n >= imap.limit() => text.push_str("   never escapes"),
+                Some(n) => text.push_str(&format!("   {} iterations", n)),
+                None => {}
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    // Hand `globs` everything that comes down the pipe until there's
+    // nothing left for it to do.
+    fn settle(globs: &mut Globs, rx: &mpsc::Receiver<Msg>) {
+        loop {
+            let wait = if globs.is_rendering() { 30_000 } else { 200 };
+            match rx.recv_timeout(Duration::from_millis(wait)) {
+                Ok(msg) => globs.handle(msg),
+                Err(_) => {
+                    assert!(!globs.is_rendering(), "render never finished");
+                    return;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn status_line() {
+        let dims = ImageDims {
+            xpix: 300,
+            ypix: 200,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 2,
+            adaptive: false,
+            check_period: true,
+        };
+        let imap = IterMap::new(dims, IterType::Mandlebrot, 100);
+        // The origin is in the set; the upper left corner escapes quickly.
+        let text = describe_point(&dims, &IterType::Mandlebrot, Some(&imap), 2.0 / 3.0, 0.5);
+        assert_eq!(text, "0.000 +0.000i   zoom 1.000x   never escapes");
+        let text = describe_point(&dims, &IterType::Mandlebrot, Some(&imap), 0.0, 0.0);
+        assert!(text.starts_with("-2.000 +1.000i   zoom 1.000x"), "{}", text);
+        assert!(text.ends_with(" iterations"), "{}", text);
+
+        let deep = dims.zoom(1000.0);
+        let text = describe_point(&deep, &IterType::Mandlebrot, None, 0.5, 0.5);
+        assert_eq!(text, "-0.500000 +0.000000i   zoom 1000.000x");
+    }
+
+    #[test]
+    #[ignore = "opens windows, so it needs a display (CI runs it under xvfb-run)"]
+    fn message_loop() {
+        let _a = fltk::app::App::default();
+        let (tx, rx) = mpsc::channel::<Msg>();
+        let dims = ImageDims {
+            xpix: 90,
+            ypix: 60,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        let white = ColorSpec::new(vec![Gradient::default()], RGB::WHITE);
+        let mut globs = Globs::new(tx.clone(), "test", dims, white);
+        assert_eq!(globs.shown_size(), (90, 60));
+
+        // File names get "picked" from the end of `files`.
+        let files: Rc<RefCell<Vec<String>>> = Rc::new(RefCell::new(Vec::new()));
+        let told: Rc<RefCell<Vec<String>>> = Rc::new(RefCell::new(Vec::new()));
+        globs.set_dialogs(
+            {
+                let files = files.clone();
+                move |_, _| files.borrow_mut().pop()
+            },
+            {
+                let told = told.clone();
+                move |s| told.borrow_mut().push(s.to_string())
+            },
+        );
+        let send = |msg: Msg, globs: &mut Globs| {
+            tx.send(msg).unwrap();
+            settle(globs, &rx);
+        };
+
+        let dir = std::env::temp_dir();
+        let (saved, loaded) = (
+            dir.join("jset_app_test_out.toml"),
+            dir.join("jset_app_test_in.toml"),
+        );
+        let new_dims = ImageDims {
+            xpix: 120,
+            ypix: 80,
+            x: -1.0,
+            y: 0.5,
+            width: 1.5,
+            ..dims
+        };
+        let spec = ColorSpec::new(
+            vec![
+                Gradient::default(),
+                Gradient {
+                    steps: 50,
+                    start: RGB::WHITE,
+                    end: RGB::new(255.0, 0.0, 0.0),
+                    space: Interpolation::Lab,
+                },
+            ],
+            RGB::BLACK,
+        );
+        rw::save(&new_dims, &spec, &IterType::Mandlebrot, &loaded).unwrap();
+
+        files.borrow_mut().push(loaded.display().to_string());
+        send(Msg::Load, &mut globs);
+        assert_eq!(
+            globs.params(),
+            (new_dims, spec.clone(), IterType::Mandlebrot)
+        );
+        assert_eq!(globs.shown_size(), (120, 80));
+
+        send(Msg::Zoom(2.0), &mut globs);
+        assert_eq!(globs.params().0, new_dims.zoom(2.0));
+        // Going back through views doesn't touch the colors.
+        send(Msg::ViewBack, &mut globs);
+        assert_eq!(globs.params().0, new_dims);
+        send(Msg::ViewBack, &mut globs);
+        assert_eq!(globs.params(), (dims, spec.clone(), IterType::Mandlebrot));
+        send(Msg::ViewBack, &mut globs);
+        assert_eq!(globs.params().0, dims);
+        send(Msg::ViewForward, &mut globs);
+        send(Msg::ViewForward, &mut globs);
+        assert_eq!(globs.params().0, new_dims.zoom(2.0));
+        send(Msg::Undo, &mut globs);
+        assert_eq!(
+            globs.params(),
+            (new_dims, spec.clone(), IterType::Mandlebrot)
+        );
+        send(Msg::Redo, &mut globs);
+        assert_eq!(globs.params().0, new_dims.zoom(2.0));
+        // Nothing left to redo.
+        send(Msg::Redo, &mut globs);
+        assert_eq!(globs.params().0, new_dims.zoom(2.0));
+        send(Msg::Redraw(Some(150), None), &mut globs);
+        assert_eq!(globs.params().0, new_dims.zoom(2.0).resize(150, 80));
+        assert_eq!(globs.shown_size(), (150, 80));
+        send(Msg::Scale(2), &mut globs);
+        assert_eq!(globs.shown_size(), (75, 40));
+
+        // Nothing has changed, so recoloring shouldn't either.
+        let before = globs.params();
+        send(Msg::Recolor, &mut globs);
+        assert_eq!(globs.params(), before);
+        send(Msg::CopyImage, &mut globs);
+        send(Msg::ExportSet, &mut globs);
+        assert_eq!(globs.params(), before);
+        assert!(told.borrow().is_empty());
+
+        // Cycling the colors changes the picture, but not the parameters,
+        // and stopping puts the picture back.
+        let shown = globs.main_pane.get_image();
+        send(Msg::CycleColors, &mut globs);
+        for _ in 0..10 {
+            send(Msg::CycleStep, &mut globs);
+        }
+        assert_ne!(globs.main_pane.get_image(), shown);
+        assert_eq!(globs.params(), before);
+        send(Msg::CycleColors, &mut globs);
+        assert_eq!(globs.main_pane.get_image(), shown);
+
+        files.borrow_mut().push(saved.display().to_string());
+        send(Msg::SaveValues, &mut globs);
+        let (d, s, i) = rw::load(&saved).unwrap();
+        assert_eq!((d, s, i), globs.params());
+
+        // A session brings back the history along with the parameters.
+        let session_file = dir.join("jset_app_test_session.toml");
+        files.borrow_mut().push(session_file.display().to_string());
+        send(Msg::SaveSession, &mut globs);
+        send(Msg::Undo, &mut globs);
+        send(Msg::Undo, &mut globs);
+        files.borrow_mut().push(session_file.display().to_string());
+        send(Msg::LoadSession, &mut globs);
+        assert_eq!(globs.params(), before);
+        send(Msg::Undo, &mut globs);
+        assert_eq!(globs.params().0, new_dims.zoom(2.0));
+        send(Msg::Redo, &mut globs);
+        assert_eq!(globs.params(), before);
+        assert!(told.borrow().is_empty());
+
+        // Failures get reported, and leave everything as it was.
+        files
+            .borrow_mut()
+            .push(dir.join("jset_app_test_nothing.toml").display().to_string());
+        send(Msg::Load, &mut globs);
+        assert_eq!(told.borrow().len(), 1);
+        assert_eq!(globs.params(), before);
+        // So does changing your mind.
+        send(Msg::SaveValues, &mut globs);
+        assert_eq!(told.borrow().len(), 1);
+
+        // Clicking on the minimap moves the view without zooming.
+        send(Msg::JumpTo(-0.5, 0.25), &mut globs);
+        let moved = globs.params().0;
+        assert_eq!(moved.center(), (-0.5, 0.25));
+        assert_eq!((moved.width, moved.xpix), (before.0.width, before.0.xpix));
+
+        // Cancelling a render throws away the old view's counts, so
+        // redrawing the same view starts it over rather than doing nothing.
+        globs.handle(Msg::Zoom(2.0));
+        globs.handle(Msg::Cancel);
+        settle(&mut globs, &rx);
+        let zoomed = globs.params().0;
+        assert_eq!(zoomed, moved.zoom(2.0));
+        assert!(globs.cur_imap.is_none());
+        send(Msg::SaveImage, &mut globs);
+        assert_eq!(told.borrow().len(), 2);
+        send(Msg::Redraw(None, None), &mut globs);
+        assert_eq!(globs.params().0, zoomed);
+        assert!(globs.map_is_current());
+        assert_eq!(globs.cur_fimg.dims(), zoomed);
+        assert_eq!(told.borrow().len(), 2);
+    }
+}
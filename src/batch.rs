@@ -0,0 +1,267 @@
+/*!
+Rendering a whole pile of parameter files in one go, for overnight jobs.
+
+The files can be named one by one, or as folders, in which case every
+`.toml` and `.json` file in the folder gets rendered. (Not `.png`s, since
+those are likely to be earlier renders; name them individually to render
+their embedded parameters.) Each image is rendered at the size and
+oversampling it was saved with, into a file named by a pattern, in which
+these tokens are replaced:
+
+  * `{stem}`: the parameter file's name, without its extension
+  * `{n}`: its position in the batch, counting from 1, padded with zeros
+    so that they all sort in order (`007` in a batch of 120)
+
+The pattern can include folders, which are created as needed. If it
+doesn't end in `.png`, that gets added.
+*/
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use crate::headless;
+use crate::hook;
+use crate::rw;
+
+/** How images are named if no pattern is specified. */
+pub const DEFAULT_PATTERN: &str = "{stem}.png";
+
+/**
+Return the parameter files named by `paths`, with any folders replaced by
+the parameter files in them (sorted by name).
+*/
+pub fn inputs<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<PathBuf>, String> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for path in paths.iter().map(|p| p.as_ref()) {
+        if !path.is_dir() {
+            files.push(path.to_path_buf());
+            continue;
+        }
+        let entries =
+            fs::read_dir(path).map_err(|e| format!("Error reading {}: {}", path.display(), &e))?;
+        let mut found: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && rw::ParamFormat::is_param_file(p))
+            .collect();
+        found.sort();
+        files.append(&mut found);
+    }
+    Ok(files)
+}
+
+/**
+Fill in `pattern` for the `n`th (counting from 0) of `count` files, `src`.
+*/
+pub fn output_name(pattern: &str, src: &Path, n: usize, count: usize) -> PathBuf {
+    let stem = src.file_stem().unwrap_or_default().to_string_lossy();
+    let digits = count.max(1).to_string().len();
+    let name = pattern
+        .replace("{stem}", &stem)
+        .replace("{n}", &format!("{:0width$}", n + 1, width = digits));
+    let mut name = PathBuf::from(name);
+    let is_png = name
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("png"))
+        .unwrap_or(false);
+    if !is_png {
+        let mut with_ext = name.into_os_string();
+        with_ext.push(".png");
+        name = PathBuf::from(with_ext);
+    }
+    name
+}
+
+/** A list of parameter files, and where each one's image goes. */
+pub struct Batch {
+    jobs: Vec<(PathBuf, PathBuf)>,
+    embed_metadata: bool,
+}
+
+impl Batch {
+    /**
+    Plan to render each of `files` into `outbox`, naming the images with
+    `pattern`. It's an error for two files to end up with the same image
+    name (like when rendering folders with files of the same name with
+    the default pattern).
+    */
+    pub fn new<P: AsRef<Path>>(
+        files: Vec<PathBuf>,
+        outbox: P,
+        pattern: &str,
+    ) -> Result<Batch, String> {
+        if files.is_empty() {
+            return Err("There are no parameter files to render.".to_string());
+        }
+        let count = files.len();
+        let mut jobs: Vec<(PathBuf, PathBuf)> = Vec::with_capacity(count);
+        for (n, src) in files.into_iter().enumerate() {
+            let dest = outbox.as_ref().join(output_name(pattern, &src, n, count));
+            if let Some((other, _)) = jobs.iter().find(|(_, d)| d == &dest) {
+                return Err(format!(
+                    "{} and {} would both be rendered to {}; use a pattern with {{n}} in it.",
+                    other.display(),
+                    src.display(),
+                    dest.display()
+                ));
+            }
+            jobs.push((src, dest));
+        }
+        Ok(Batch {
+            jobs,
+            embed_metadata: true,
+        })
+    }
+
+    /** Whether to embed the parameters in the images (the default). */
+    pub fn set_embed_metadata(&mut self, embed: bool) {
+        self.embed_metadata = embed;
+    }
+
+    /** Each parameter file, and the image it'll be rendered to. */
+    pub fn jobs(&self) -> &[(PathBuf, PathBuf)] {
+        &self.jobs
+    }
+
+    /**
+    Render the `n`th file (running the post-save hook, if there is one),
+    returning the name of the image.
+    */
+    pub fn render(&self, n: usize) -> Result<&Path, String> {
+        let (src, dest) = &self.jobs[n];
+        let (dims, cspec, itype) = rw::load(src)?;
+        if let Some(dir) = dest.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Error creating folder {}: {}", dir.display(), &e))?;
+        }
+        headless::export(
+            dest,
+            dims,
+            cspec,
+            itype,
+            self.embed_metadata,
+            rw::BitDepth::Eight,
+        )?;
+        hook::after_save(dest, dims.xpix, dims.ypix)?;
+        Ok(dest)
+    }
+
+    /**
+    Render everything, `n_parallel` files at a time, reporting each one on
+    stderr as it's done. A file that fails doesn't stop the rest. Returns
+    how many failed.
+
+    Each image is already spread across all the iteration threads, so
+    rendering several at once mostly helps with lots of small ones.
+    */
+    pub fn run(&self, n_parallel: usize) -> usize {
+        let next = AtomicUsize::new(0);
+        let failed = AtomicUsize::new(0);
+        let count = self.jobs.len();
+        let work = || loop {
+            let n = next.fetch_add(1, Ordering::Relaxed);
+            if n >= count {
+                return;
+            }
+            let src = &self.jobs[n].0;
+            let start = Instant::now();
+            match self.render(n) {
+                Ok(dest) => eprintln!(
+                    "[{}/{}] rendered {} to {} in {:.1} s",
+                    n + 1,
+                    count,
+                    src.display(),
+                    dest.display(),
+                    start.elapsed().as_secs_f64()
+                ),
+                Err(e) => {
+                    eprintln!(
+                        "[{}/{}] failed to render {}: {}",
+                        n + 1,
+                        count,
+                        src.display(),
+                        &e
+                    );
+                    failed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        };
+        std::thread::scope(|s| {
+            for _ in 1..n_parallel.clamp(1, count) {
+                s.spawn(work);
+            }
+            work();
+        });
+        failed.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::*;
+
+    #[test]
+    fn output_names() {
+        let src = Path::new("in/spiral.toml");
+        assert_eq!(
+            output_name(DEFAULT_PATTERN, src, 0, 1),
+            PathBuf::from("spiral.png")
+        );
+        assert_eq!(
+            output_name("frames/{n}_{stem}", src, 6, 120),
+            PathBuf::from("frames/007_spiral.png")
+        );
+        assert_eq!(
+            output_name("{stem}.PNG", src, 0, 1),
+            PathBuf::from("spiral.PNG")
+        );
+    }
+
+    #[test]
+    fn batches() {
+        let dir = std::env::temp_dir().join(format!("jset_batch_test_{}", std::process::id()));
+        let (inbox, outbox) = (dir.join("in"), dir.join("out"));
+        fs::create_dir_all(&inbox).unwrap();
+        let dims = ImageDims {
+            xpix: 24,
+            ypix: 16,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        let cspec = ColorSpec::new(vec![Gradient::default()], RGB::BLACK);
+        for name in ["b.toml", "a.json"] {
+            rw::save(&dims, &cspec, &IterType::Mandlebrot, &inbox.join(name)).unwrap();
+        }
+        fs::write(inbox.join("notes.txt"), "not parameters").unwrap();
+        fs::write(inbox.join("broken.toml"), "not parameters either").unwrap();
+
+        let files = inputs(&[&inbox]).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                inbox.join("a.json"),
+                inbox.join("b.toml"),
+                inbox.join("broken.toml")
+            ]
+        );
+        // The same file twice would clobber itself.
+        let twice = vec![files[0].clone(), files[0].clone()];
+        assert!(Batch::new(twice, &outbox, DEFAULT_PATTERN).is_err());
+
+        let batch = Batch::new(files, &outbox, "{n}/{stem}").unwrap();
+        assert_eq!(batch.run(2), 1);
+        let (ldims, _, _) = rw::load(outbox.join("2").join("b.png")).unwrap();
+        assert_eq!(ldims, dims);
+        assert!(outbox.join("1").join("a.png").is_file());
+        assert!(!outbox.join("3").join("broken.png").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+/*!
+Time renders of a standard set of views, so that changes that make
+rendering slower show up as numbers rather than as a vague feeling.
+
+The views are the built-in examples (see the `gallery` module), each
+resized to the same image size and iterated with one sample per pixel, so
+that runs on the same machine can be compared. Iterating and coloring are
+timed separately, since they're sped up (or slowed down) by different
+things.
+
+```text
+$ cargo run --release --bin jset_bench -- --bench
+```
+
+Build with `--release`; debug builds are many times slower, and the
+numbers don't mean much.
+*/
+
+use std::time::Duration;
+
+use jset_desk::gallery;
+use jset_desk::image::*;
+use jset_desk::render::{timed, PassStats, RenderStats};
+
+const USAGE: &str = "usage: jset_bench --bench [ WIDTHxHEIGHT ]
+    render each of the built-in examples at WIDTHxHEIGHT (default 800x600)
+    and print how long iterating and coloring each one took
+";
+
+const DEFAULT_SIZE: (usize, usize) = (800, 600);
+
+// Parse an image size like "800x600".
+fn parse_size(s: &str) -> Result<(usize, usize), String> {
+    let (w, h) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("{:?} isn't a size like \"800x600\"", s))?;
+    let parse = |t: &str| -> Result<usize, String> {
+        match t.trim().parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("{:?} isn't a positive whole number", t)),
+        }
+    };
+    Ok((parse(w)?, parse(h)?))
+}
+
+// Seconds, to the millisecond.
+fn secs(d: Duration) -> String {
+    format!("{:.3}s", d.as_secs_f64())
+}
+
+fn run(xpix: usize, ypix: usize) -> Result<(), String> {
+    println!(
+        "{}x{}, 1 sample per pixel, {} threads",
+        xpix,
+        ypix,
+        iteration_threads()
+    );
+    println!(
+        "{:<24} {:>10} {:>10} {:>12}",
+        "view", "iterate", "color", "Mpts/s"
+    );
+
+    let mut iterating = RenderStats {
+        passes: Vec::new(),
+        threads: iteration_threads(),
+    };
+    let mut coloring = Duration::ZERO;
+    for name in gallery::names() {
+        let (dims, cspec, itype) = gallery::load(name)?;
+        let mut dims = dims.resize(xpix, ypix);
+        dims.oversample = 1;
+        dims.adaptive = false;
+
+        let cmap = ColorMap::make(cspec);
+        let (imap, pass) = timed(xpix * ypix, || IterMap::new(dims, itype, cmap.limit()));
+        let (_, color_pass): (FImage32, PassStats) = timed(xpix * ypix, || imap.color(&cmap));
+        let one = RenderStats {
+            passes: vec![pass],
+            threads: iteration_threads(),
+        };
+        println!(
+            "{:<24} {:>10} {:>10} {:>12.2}",
+            name,
+            secs(pass.time),
+            secs(color_pass.time),
+            one.samples_per_second() / 1.0e6
+        );
+        iterating.passes.push(pass);
+        coloring += color_pass.time;
+    }
+
+    println!(
+        "{:<24} {:>10} {:>10} {:>12.2}",
+        "total",
+        secs(iterating.total()),
+        secs(coloring),
+        iterating.samples_per_second() / 1.0e6
+    );
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let size = match args.iter().map(|s| s.as_str()).collect::<Vec<_>>()[..] {
+        ["-h"] | ["--help"] => {
+            print!("{}", USAGE);
+            return;
+        }
+        ["--bench"] => Ok(DEFAULT_SIZE),
+        ["--bench", size] => parse_size(size),
+        _ => {
+            eprint!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    let result = size.and_then(|(w, h)| run(w, h));
+    if let Err(e) = result {
+        eprintln!("{}", &e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("800x600"), Ok((800, 600)));
+        assert_eq!(parse_size("64X48"), Ok((64, 48)));
+        assert!(parse_size("800").is_err());
+        assert!(parse_size("0x600").is_err());
+        assert!(parse_size("800xlots").is_err());
+        // Small enough to be quick even in a debug build.
+        assert!(run(16, 12).is_ok());
+    }
+}
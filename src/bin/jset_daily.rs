@@ -0,0 +1,335 @@
+/*!
+Render a randomly-chosen Julia set and make it the desktop wallpaper.
+
+This is meant to be run once a day (or however often) from cron or Task
+Scheduler. It reads a configuration file listing saved parameter files
+(either `.toml`/`.json` files or PNGs with parameters embedded) to pick a
+location from, and optionally more parameter files to pick a palette
+from, renders the combination at screen resolution, and runs a command
+to set the result as the wallpaper.
+
+```toml
+# Where in the plane to draw; one will be chosen at random.
+locations = ["spirals.toml", "seahorse.png"]
+# Color maps to draw it with; if this is empty or missing, each location
+# is drawn with its own colors.
+palettes = ["fire.toml", "ocean.toml"]
+# Pick the same combination all day, rather than a new one on every run.
+by_date = true
+# Defaults to the size of the screen, but that requires a display. With
+# just one of them, the other goes with the shape of the location.
+width = 1920
+height = 1080
+# Samples per pixel in each direction; see "Antialias" in the application.
+oversample = 2
+# Where to write the image.
+output = "wallpaper.png"
+# The command that sets the wallpaper; "{}" is replaced with the full path
+# of the image. An empty list means just write the image. There's a
+# reasonable default for Windows, macOS, and GNOME.
+command = ["feh", "--bg-fill", "{}"]
+```
+
+Relative paths are relative to the directory containing the configuration
+file, since cron jobs don't run anywhere in particular.
+*/
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_derive::Deserialize;
+
+use jset_desk::headless;
+use jset_desk::image::*;
+use jset_desk::rw;
+
+const USAGE: &str = "usage: jset_daily <config.toml>
+    render one of the configured locations with one of the configured
+    palettes, and set it as the desktop wallpaper
+";
+
+// Configuration files have no business being bigger than this.
+const CONFIG_LIMIT: u64 = 1024 * 1024;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Deserialize)]
+struct Config {
+    locations: Vec<PathBuf>,
+    #[serde(default)]
+    palettes: Vec<PathBuf>,
+    #[serde(default)]
+    by_date: bool,
+    width: Option<usize>,
+    height: Option<usize>,
+    #[serde(default = "default_oversample")]
+    oversample: usize,
+    output: PathBuf,
+    command: Option<Vec<String>>,
+}
+
+fn default_oversample() -> usize {
+    1
+}
+
+impl Config {
+    fn load(fname: &Path) -> Result<Config, String> {
+        match std::fs::metadata(fname) {
+            Ok(md) if md.len() > CONFIG_LIMIT => {
+                return Err(format!("file is larger than {} bytes", CONFIG_LIMIT));
+            }
+            Err(e) => {
+                return Err(format!("{}", &e));
+            }
+            _ => {}
+        }
+        let text = std::fs::read_to_string(fname).map_err(|e| format!("{}", &e))?;
+        let mut cfg: Config = toml::from_str(&text).map_err(|e| format!("{}", &e))?;
+        if cfg.locations.is_empty() {
+            return Err("no locations to choose from".to_string());
+        }
+        if cfg.width == Some(0) || cfg.height == Some(0) {
+            return Err("the width and height must be positive".to_string());
+        }
+
+        let base = fname.parent().unwrap_or_else(|| Path::new(""));
+        for p in cfg.locations.iter_mut().chain(cfg.palettes.iter_mut()) {
+            *p = base.join(&p);
+        }
+        cfg.output = base.join(&cfg.output);
+        Ok(cfg)
+    }
+}
+
+/*
+A tiny pseudorandom number generator (Vigna's SplitMix64); picking from a
+couple of lists hardly needs anything better, and it makes the choice a
+pure function of the seed.
+*/
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            None
+        } else {
+            Some(&items[(self.next() % items.len() as u64) as usize])
+        }
+    }
+}
+
+// Returns the number of the current (UTC) day if `by_date` is set, so every
+// run that day makes the same choice; otherwise something different every
+// time.
+fn seed(by_date: bool) -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    if by_date {
+        now.as_secs() / SECONDS_PER_DAY
+    } else {
+        now.as_nanos() as u64
+    }
+}
+
+// A command that sets the wallpaper, and whether it failing means the
+// wallpaper wasn't set.
+struct WallpaperCommand {
+    args: Vec<String>,
+    required: bool,
+}
+
+// The commands that set the wallpaper on the current platform if the
+// configuration doesn't supply one.
+fn default_commands() -> Vec<WallpaperCommand> {
+    let cmds: Vec<Vec<&str>> = if cfg!(target_os = "windows") {
+        vec![vec![
+            "powershell",
+            "-NoProfile",
+            "-Command",
+            "Add-Type -TypeDefinition 'using System.Runtime.InteropServices; \
+             public class Wallpaper { [DllImport(\"user32.dll\", CharSet=CharSet.Unicode)] \
+             public static extern int SystemParametersInfo(int a, int b, string c, int d); }'; \
+             [Wallpaper]::SystemParametersInfo(20, 0, '{}', 3)",
+        ]]
+    } else if cfg!(target_os = "macos") {
+        vec![vec![
+            "osascript",
+            "-e",
+            "tell application \"System Events\" to tell every desktop to set picture to \"{}\"",
+        ]]
+    } else {
+        // GNOME 42 and later use a different setting in dark mode; older
+        // versions don't have it, which is fine, since `picture-uri` has
+        // already been set by then.
+        vec![
+            vec![
+                "gsettings",
+                "set",
+                "org.gnome.desktop.background",
+                "picture-uri",
+                "file://{}",
+            ],
+            vec![
+                "gsettings",
+                "set",
+                "org.gnome.desktop.background",
+                "picture-uri-dark",
+                "file://{}",
+            ],
+        ]
+    };
+
+    cmds.iter()
+        .enumerate()
+        .map(|(n, c)| WallpaperCommand {
+            args: c.iter().map(|s| s.to_string()).collect(),
+            required: n == 0,
+        })
+        .collect()
+}
+
+fn set_wallpaper(cmds: &[WallpaperCommand], path: &Path) -> Result<(), String> {
+    let path = path.display().to_string();
+    for cmd in cmds.iter() {
+        let args: Vec<String> = cmd.args.iter().map(|a| a.replace("{}", &path)).collect();
+        let (prog, args) = match args.split_first() {
+            Some(x) => x,
+            None => continue,
+        };
+        let err = match Command::new(prog).args(args).status() {
+            Ok(stat) if stat.success() => continue,
+            Ok(stat) => format!("{} exited with {}", prog, stat),
+            Err(e) => format!("unable to run {}: {}", prog, &e),
+        };
+        if cmd.required {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+fn run(cfg_file: &Path) -> Result<(), String> {
+    let cfg = Config::load(cfg_file)
+        .map_err(|e| format!("Error reading {}: {}", cfg_file.display(), &e))?;
+
+    let mut rng = SplitMix(seed(cfg.by_date));
+    // `Config::load()` makes sure there's at least one location.
+    let loc = rng.pick(&cfg.locations).unwrap();
+    let (dims, mut cspec, itype) =
+        rw::load(loc).map_err(|e| format!("Error loading {}: {}", loc.display(), &e))?;
+    if let Some(pal) = rng.pick(&cfg.palettes) {
+        cspec = rw::load(pal)
+            .map_err(|e| format!("Error loading {}: {}", pal.display(), &e))?
+            .1;
+    }
+
+    // With only one of them given, the other follows the location's shape,
+    // like with `jset_desk --export`.
+    let aspect = (dims.xpix as f64) / (dims.ypix as f64);
+    let (width, height) = match (cfg.width, cfg.height) {
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) => (w, ((w as f64) / aspect).round().max(1.0) as usize),
+        (None, Some(h)) => (((h as f64) * aspect).round().max(1.0) as usize, h),
+        (None, None) => {
+            let (w, h) = fltk::app::screen_size();
+            (w as usize, h as usize)
+        }
+    };
+    let mut dims = dims.resize(width, height);
+    dims.oversample = cfg.oversample.clamp(1, MAX_OVERSAMPLE);
+    dims.adaptive = dims.oversample > 1;
+
+    let (xpix, ypix, data) = headless::render(dims, cspec.clone(), itype.clone()).to_bytes(1);
+    let params = Some((&dims, &cspec, &itype));
+    rw::save_with_metadata(
+        &cfg.output,
+        xpix,
+        ypix,
+        &data,
+        params,
+        rw::PngCompression::Best,
+    )?;
+
+    let cmds = match cfg.command {
+        Some(args) => vec![WallpaperCommand {
+            args,
+            required: true,
+        }],
+        None => default_commands(),
+    };
+    let path = std::fs::canonicalize(&cfg.output).unwrap_or(cfg.output);
+    set_wallpaper(&cmds, &path)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|s| s.as_str()) {
+        Some("-h") | Some("--help") => {
+            print!("{}", USAGE);
+        }
+        Some(fname) if args.len() == 1 => {
+            if let Err(e) = run(Path::new(fname)) {
+                eprintln!("{}", &e);
+                std::process::exit(1);
+            }
+        }
+        _ => {
+            eprint!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn picking() {
+        let items = ["a", "b", "c", "d", "e"];
+        let picks = |s| {
+            let mut r = SplitMix(s);
+            (0..20)
+                .map(|_| *r.pick(&items).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(picks(19_000), picks(19_000));
+        assert_ne!(picks(19_000), picks(19_001));
+        assert!(items.iter().all(|x| picks(7).contains(x)));
+        assert_eq!(SplitMix(0).pick::<usize>(&[]), None);
+    }
+
+    #[test]
+    fn config_paths() {
+        let dir = std::env::temp_dir().join("jset_daily_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let fname = dir.join("daily.toml");
+        std::fs::write(
+            &fname,
+            "locations = [\"a.toml\", \"/b.png\"]\noutput = \"out.png\"\n",
+        )
+        .unwrap();
+
+        let cfg = Config::load(&fname).unwrap();
+        assert_eq!(
+            cfg.locations,
+            vec![dir.join("a.toml"), PathBuf::from("/b.png")]
+        );
+        assert_eq!(cfg.output, dir.join("out.png"));
+        assert!(cfg.palettes.is_empty() && !cfg.by_date && cfg.command.is_none());
+        assert_eq!(cfg.oversample, 1);
+
+        std::fs::write(&fname, "locations = []\noutput = \"out.png\"\n").unwrap();
+        assert!(Config::load(&fname).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,255 @@
+/*!
+Finding the spots in a color map that won't show up well in an image:
+gradients whose colors are all too alike to tell apart, and jumps between
+gradients that are much harsher than the steps around them.
+
+Differences between colors are measured as ΔE (CIE76), the distance
+between them in CIELAB space; about 2.3 is the smallest difference most
+people can see.
+*/
+
+use crate::image::{ColorSpec, Gradient, RGB};
+
+/** The smallest ΔE most people can see. */
+pub const JUST_NOTICEABLE: f32 = 2.3;
+/** The ΔE past which a jump between gradients is considered harsh. */
+pub const DEFAULT_HARSH: f32 = 25.0;
+// A jump also has to be this many times bigger than the steps on either
+// side of it to count; a palette of stripes is supposed to be stripy.
+const HARSH_RATIO: f32 = 4.0;
+// How far apart (in CIELAB lightness) fixing a flat gradient moves its
+// ends.
+const FLAT_FIX_LIGHTNESS: f32 = 12.0;
+// The most steps of a gradient looked at to decide whether it's flat.
+const MAX_SAMPLES: usize = 64;
+
+/** The ΔE between two colors. */
+pub fn delta_e(a: RGB, b: RGB) -> f32 {
+    let (l0, a0, b0) = a.to_lab();
+    let (l1, a1, b1) = b.to_lab();
+    ((l1 - l0).powi(2) + (a1 - a0).powi(2) + (b1 - b0).powi(2)).sqrt()
+}
+
+/** Something about a color map that won't look the way it should. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Problem {
+    /// No step of the gradient with this index can be told apart from its
+    /// start; `delta` is the biggest ΔE from it.
+    Flat { gradient: usize, delta: f32 },
+    /// The step from the end of the gradient before the one with this
+    /// index to its start is harsh; `delta` is its ΔE. (When the palette
+    /// repeats, `gradient` 0 means the jump from the end of the palette
+    /// back to its start.)
+    Jump { gradient: usize, delta: f32 },
+}
+
+impl Problem {
+    /** A description of the problem, for people. */
+    pub fn describe(&self) -> String {
+        match *self {
+            Problem::Flat { gradient, delta } => format!(
+                "Gradient {} is too flat to see (ΔE {:.1}).",
+                gradient + 1,
+                delta
+            ),
+            Problem::Jump { gradient: 0, delta } => format!(
+                "The palette jumps harshly from its end back to its start (ΔE {:.0}).",
+                delta
+            ),
+            Problem::Jump { gradient, delta } => format!(
+                "Gradient {} jumps harshly into gradient {} (ΔE {:.0}).",
+                gradient,
+                gradient + 1,
+                delta
+            ),
+        }
+    }
+
+    /** What `fix()` would do about it. */
+    pub fn suggestion(&self) -> String {
+        match *self {
+            Problem::Flat { gradient, .. } => {
+                format!("Spread out the lightness of gradient {}.", gradient + 1)
+            }
+            Problem::Jump { gradient, .. } => format!(
+                "Start gradient {} with the color the one before it ends with.",
+                gradient + 1
+            ),
+        }
+    }
+
+    /** Change `gradients` so that this problem goes away. */
+    pub fn fix(&self, gradients: &mut [Gradient]) {
+        match *self {
+            Problem::Flat { gradient, .. } => {
+                let g = match gradients.get_mut(gradient) {
+                    Some(g) => g,
+                    None => {
+                        return;
+                    }
+                };
+                let (l, a, b) = g.start.to_lab();
+                let (l0, l1) = if l < 50.0 {
+                    (l, l + FLAT_FIX_LIGHTNESS)
+                } else {
+                    (l, l - FLAT_FIX_LIGHTNESS)
+                };
+                let (_, a1, b1) = g.end.to_lab();
+                g.start = RGB::from_lab(l0, a, b);
+                g.end = RGB::from_lab(l1, a1, b1);
+            }
+            Problem::Jump { gradient, .. } => {
+                if gradient >= gradients.len() {
+                    return;
+                }
+                let prev = (gradient + gradients.len() - 1) % gradients.len();
+                gradients[gradient].start = gradients[prev].end;
+            }
+        }
+    }
+}
+
+// The color of each step of a gradient from `from` on, up to `n` steps.
+fn step_colors(g: &Gradient, from: usize, n: usize) -> impl Iterator<Item = RGB> + '_ {
+    let steps = g.steps.max(1);
+    let stride = ((steps - from.min(steps)) / n.max(1)).max(1);
+    (from..steps)
+        .step_by(stride)
+        .map(move |i| g.color_at((i as f32) / (steps as f32)))
+}
+
+// The ΔE between the last two steps of a gradient (or the first two, if
+// `first`). One-step gradients have no steps inside them.
+fn edge_step(g: &Gradient, first: bool) -> Option<f32> {
+    if g.steps < 2 {
+        return None;
+    }
+    let s = g.steps as f32;
+    let (a, b) = if first {
+        (g.color_at(0.0), g.color_at(1.0 / s))
+    } else {
+        (g.color_at((s - 2.0) / s), g.color_at((s - 1.0) / s))
+    };
+    Some(delta_e(a, b))
+}
+
+/**
+List the problems with a color map: gradients with no step as different
+from their start as `flat` ΔE, and jumps between gradients of more than
+`harsh` ΔE (that are also much bigger than the steps around them).
+
+Gradients of a single step are left alone, because they're deliberately
+one flat band with nothing blending into or out of them.
+*/
+pub fn analyze(spec: &ColorSpec, flat: f32, harsh: f32) -> Vec<Problem> {
+    let gradients = spec.clone().gradients();
+    let mut problems: Vec<Problem> = Vec::new();
+
+    for (n, g) in gradients.iter().enumerate() {
+        if g.steps >= 2 {
+            let delta = step_colors(g, 1, MAX_SAMPLES)
+                .map(|c| delta_e(g.start, c))
+                .fold(0.0f32, f32::max);
+            if delta < flat {
+                problems.push(Problem::Flat { gradient: n, delta });
+            }
+        }
+    }
+
+    // Only a repeating palette goes back around from its end to its start.
+    let first = if spec.repeats() > 1 { 0 } else { 1 };
+    for n in first..gradients.len() {
+        let prev = &gradients[(n + gradients.len() - 1) % gradients.len()];
+        let next = &gradients[n];
+        let (before, after) = match (edge_step(prev, false), edge_step(next, true)) {
+            (Some(b), Some(a)) => (b, a),
+            _ => {
+                continue;
+            }
+        };
+        let last = prev.color_at((prev.steps as f32 - 1.0) / (prev.steps as f32));
+        let delta = delta_e(last, next.start);
+        if delta > harsh && delta > HARSH_RATIO * before.max(after) {
+            problems.push(Problem::Jump { gradient: n, delta });
+        }
+    }
+
+    problems
+}
+
+/** Return a copy of `spec` with all of the given `problems` fixed. */
+pub fn fix_all(spec: &ColorSpec, problems: &[Problem]) -> ColorSpec {
+    let mut gradients = spec.clone().gradients();
+    for p in problems.iter() {
+        p.fix(&mut gradients);
+    }
+    spec.with_gradients(gradients)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::Interpolation;
+
+    fn grad(steps: usize, start: [f32; 3], end: [f32; 3]) -> Gradient {
+        Gradient {
+            steps,
+            start: RGB::new(start[0], start[1], start[2]),
+            end: RGB::new(end[0], end[1], end[2]),
+            space: Interpolation::Rgb,
+        }
+    }
+
+    #[test]
+    fn contrast_problems() {
+        assert_eq!(delta_e(RGB::BLACK, RGB::BLACK), 0.0);
+        assert!((delta_e(RGB::BLACK, RGB::WHITE) - 100.0).abs() < 0.1);
+
+        let spec = ColorSpec::new(
+            vec![
+                grad(64, [0.0, 0.0, 0.0], [0.0, 0.0, 255.0]),
+                grad(32, [100.0, 100.0, 100.0], [101.0, 101.0, 100.0]),
+                grad(64, [255.0, 255.0, 0.0], [255.0, 0.0, 0.0]),
+                grad(1, [0.0, 255.0, 0.0], [0.0, 255.0, 0.0]),
+                grad(64, [255.0, 255.0, 255.0], [0.0, 0.0, 0.0]),
+            ],
+            RGB::BLACK,
+        );
+        let problems = analyze(&spec, JUST_NOTICEABLE, DEFAULT_HARSH);
+        let kinds: Vec<(bool, usize)> = problems
+            .iter()
+            .map(|p| match p {
+                Problem::Flat { gradient, .. } => (true, *gradient),
+                Problem::Jump { gradient, .. } => (false, *gradient),
+            })
+            .collect();
+        assert_eq!(kinds, vec![(true, 1), (false, 1), (false, 2)]);
+        assert!(problems[0].describe().starts_with("Gradient 2 "));
+        assert!(problems[1].describe().starts_with("Gradient 1 jumps"));
+
+        let fixed = fix_all(&spec, &problems);
+        assert_eq!(fixed.len(), spec.len());
+        assert!(analyze(&fixed, JUST_NOTICEABLE, DEFAULT_HARSH).is_empty());
+
+        // Going back around only matters when the palette does.
+        let mut spec = fixed;
+        spec.set_repeats(2);
+        let problems = analyze(&spec, JUST_NOTICEABLE, DEFAULT_HARSH);
+        assert!(problems.is_empty(), "{:?}", problems);
+        let mut spec = spec.with_gradients(vec![
+            grad(64, [0.0, 0.0, 0.0], [255.0, 255.0, 255.0]),
+            grad(64, [0.0, 0.0, 255.0], [255.0, 255.0, 255.0]),
+        ]);
+        assert_eq!(spec.repeats(), 2);
+        let problems = analyze(&spec, JUST_NOTICEABLE, DEFAULT_HARSH);
+        assert!(matches!(
+            problems[..],
+            [
+                Problem::Jump { gradient: 0, .. },
+                Problem::Jump { gradient: 1, .. }
+            ]
+        ));
+        spec.set_repeats(1);
+        assert_eq!(analyze(&spec, JUST_NOTICEABLE, DEFAULT_HARSH).len(), 1);
+    }
+}
@@ -0,0 +1,301 @@
+/*!
+Tone curves: for each of the red, green, and blue channels, a smooth curve
+through control points placed by the user, saying what each level of that
+channel in the finished image should become.
+
+Both levels are fractions of full brightness, from 0 to 1. A curve always
+has control points at 0 and 1 (though they can be moved up and down), and
+is a monotone cubic spline (Fritsch–Carlson) through its points, so it
+never overshoots them: between two points it only ever goes in the
+direction it has to.
+*/
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::image::RGB;
+
+/** The most control points a curve can have. */
+pub const MAX_POINTS: usize = 16;
+/** How close together (in level) two control points can be. */
+pub const MIN_GAP: f64 = 0.01;
+// Curves are looked up in tables of this many levels, interpolating
+// between them.
+const TABLE_SIZE: usize = 256;
+
+/**
+The curve for one channel. Control points are `[input, output]` pairs,
+kept sorted by input.
+*/
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<[f64; 2]>", into = "Vec<[f64; 2]>")]
+pub struct Curve {
+    points: Vec<[f64; 2]>,
+}
+
+impl Default for Curve {
+    fn default() -> Self {
+        Curve::identity()
+    }
+}
+
+impl From<Vec<[f64; 2]>> for Curve {
+    fn from(points: Vec<[f64; 2]>) -> Self {
+        Curve::new(points)
+    }
+}
+
+impl From<Curve> for Vec<[f64; 2]> {
+    fn from(c: Curve) -> Self {
+        c.points
+    }
+}
+
+impl Curve {
+    /** The curve that leaves its channel alone. */
+    pub fn identity() -> Curve {
+        Curve {
+            points: vec![[0.0, 0.0], [1.0, 1.0]],
+        }
+    }
+
+    /**
+    Make a curve through the given points. Points get forced into the
+    range 0 to 1 and sorted; ones that aren't numbers, or are closer than
+    `MIN_GAP` to the one before, are dropped; and if there's no point at
+    either end, one is added there (at 0 or 1, like the identity curve).
+    If that leaves more than `MAX_POINTS`, the ones just before the last
+    are dropped.
+    */
+    pub fn new(points: Vec<[f64; 2]>) -> Curve {
+        let mut points: Vec<[f64; 2]> = points
+            .into_iter()
+            .filter(|[x, y]| x.is_finite() && y.is_finite())
+            .map(|[x, y]| [x.clamp(0.0, 1.0), y.clamp(0.0, 1.0)])
+            .collect();
+        points.sort_by(|a, b| a[0].total_cmp(&b[0]));
+        points.dedup_by(|b, a| b[0] - a[0] < MIN_GAP);
+
+        match points.first() {
+            Some([x, _]) if *x < MIN_GAP => points[0][0] = 0.0,
+            _ => points.insert(0, [0.0, 0.0]),
+        }
+        match points.last() {
+            Some([x, _]) if *x > 1.0 - MIN_GAP && points.len() > 1 => {
+                let n = points.len() - 1;
+                points[n][0] = 1.0;
+            }
+            _ => points.push([1.0, 1.0]),
+        }
+        if points.len() > MAX_POINTS {
+            let last = points[points.len() - 1];
+            points.truncate(MAX_POINTS - 1);
+            points.push(last);
+        }
+
+        Curve { points }
+    }
+
+    /** The control points, sorted by input level. */
+    pub fn points(&self) -> &[[f64; 2]] {
+        &self.points
+    }
+
+    /** Return whether this curve leaves its channel alone. */
+    pub fn is_identity(&self) -> bool {
+        self.points.iter().all(|[x, y]| x == y)
+    }
+
+    // The slope the curve should have at each control point.
+    fn tangents(&self) -> Vec<f64> {
+        let p = &self.points;
+        let n = p.len();
+        let d: Vec<f64> = p
+            .windows(2)
+            .map(|w| (w[1][1] - w[0][1]) / (w[1][0] - w[0][0]))
+            .collect();
+        let mut m = vec![0.0; n];
+        m[0] = d[0];
+        m[n - 1] = d[n - 2];
+        for i in 1..(n - 1) {
+            m[i] = if d[i - 1] * d[i] <= 0.0 {
+                0.0
+            } else {
+                (d[i - 1] + d[i]) / 2.0
+            };
+        }
+        // Keep the slopes from getting steep enough to overshoot.
+        for i in 0..(n - 1) {
+            if d[i] == 0.0 {
+                m[i] = 0.0;
+                m[i + 1] = 0.0;
+                continue;
+            }
+            let (a, b) = (m[i] / d[i], m[i + 1] / d[i]);
+            let s = a * a + b * b;
+            if s > 9.0 {
+                let t = 3.0 / s.sqrt();
+                m[i] = t * a * d[i];
+                m[i + 1] = t * b * d[i];
+            }
+        }
+        m
+    }
+
+    // The output level for input `x`, given the `tangents()`.
+    fn eval(&self, m: &[f64], x: f64) -> f64 {
+        let p = &self.points;
+        let i = match p.windows(2).position(|w| x <= w[1][0]) {
+            Some(i) => i,
+            None => {
+                return p[p.len() - 1][1];
+            }
+        };
+        let ([x0, y0], [x1, y1]) = (p[i], p[i + 1]);
+        let h = x1 - x0;
+        let t = ((x - x0) / h).clamp(0.0, 1.0);
+        let (t2, t3) = (t * t, t * t * t);
+        let y = (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+            + (t3 - 2.0 * t2 + t) * h * m[i]
+            + (-2.0 * t3 + 3.0 * t2) * y1
+            + (t3 - t2) * h * m[i + 1];
+        y.clamp(0.0, 1.0)
+    }
+
+    /** Return the output level for input level `x`. */
+    pub fn at(&self, x: f64) -> f64 {
+        self.eval(&self.tangents(), x)
+    }
+
+    /** Return the output levels for `n` evenly spaced inputs from 0 to 1. */
+    pub fn sample(&self, n: usize) -> Vec<f64> {
+        let m = self.tangents();
+        let last = (n.max(2) - 1) as f64;
+        (0..n).map(|i| self.eval(&m, (i as f64) / last)).collect()
+    }
+}
+
+/** The curves for all three channels. */
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Curves {
+    #[serde(default)]
+    pub red: Curve,
+    #[serde(default)]
+    pub green: Curve,
+    #[serde(default)]
+    pub blue: Curve,
+}
+
+impl Curves {
+    /** Return whether these curves leave everything alone. */
+    pub fn is_identity(&self) -> bool {
+        self.red.is_identity() && self.green.is_identity() && self.blue.is_identity()
+    }
+
+    /**
+    Work out the tables for applying these curves quickly, or `None` if
+    they don't do anything.
+    */
+    pub fn tables(&self) -> Option<CurveTables> {
+        if self.is_identity() {
+            return None;
+        }
+        let table = |c: &Curve| -> Vec<f32> {
+            c.sample(TABLE_SIZE)
+                .into_iter()
+                .map(|y| (255.0 * y) as f32)
+                .collect()
+        };
+        Some(CurveTables {
+            red: table(&self.red),
+            green: table(&self.green),
+            blue: table(&self.blue),
+        })
+    }
+}
+
+/** `Curves` worked out into lookup tables, for applying to whole images. */
+#[derive(Clone, Debug)]
+pub struct CurveTables {
+    red: Vec<f32>,
+    green: Vec<f32>,
+    blue: Vec<f32>,
+}
+
+// Look up the channel value `x` (from 0 to 255) in `table`.
+fn look_up(table: &[f32], x: f32) -> f32 {
+    let pos = (x / 255.0) * ((TABLE_SIZE - 1) as f32);
+    let i = (pos as usize).min(TABLE_SIZE - 2);
+    let frac = pos - (i as f32);
+    table[i] + (table[i + 1] - table[i]) * frac
+}
+
+impl CurveTables {
+    /** Return the color `c` with the curves applied. */
+    pub fn apply(&self, c: RGB) -> RGB {
+        RGB::new(
+            look_up(&self.red, c.r()),
+            look_up(&self.green, c.g()),
+            look_up(&self.blue, c.b()),
+        )
+    }
+
+    /** Apply the curves to every color in `colors`. */
+    pub fn apply_all(&self, colors: &mut [RGB]) {
+        for c in colors.iter_mut() {
+            *c = self.apply(*c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::{ColorSpec, Gradient};
+
+    #[test]
+    fn tone_curves() {
+        assert!(Curve::identity().is_identity());
+        assert!(Curves::default().tables().is_none());
+        assert_eq!(Curve::identity().at(0.3), 0.3);
+
+        // Out of order, out of range, too close together, and missing ends.
+        let c = Curve::new(vec![[0.8, 0.9], [0.5, 2.0], [0.505, 0.1], [f64::NAN, 0.0]]);
+        assert_eq!(
+            c.points(),
+            &[[0.0, 0.0], [0.5, 1.0], [0.8, 0.9], [1.0, 1.0]]
+        );
+        let many: Vec<[f64; 2]> = (0..40).map(|n| [n as f64 / 40.0, 0.5]).collect();
+        let c = Curve::new(many);
+        assert_eq!(c.points().len(), MAX_POINTS);
+        assert_eq!(c.points()[MAX_POINTS - 1][0], 1.0);
+
+        // Between points, a monotone curve stays between them.
+        let s = Curve::new(vec![[0.0, 0.0], [0.25, 0.6], [0.5, 0.65], [1.0, 1.0]]);
+        let ys = s.sample(101);
+        assert!(ys.windows(2).all(|w| w[1] >= w[0]));
+        assert!(ys[25..=50].iter().all(|y| (0.6..=0.65).contains(y)));
+        assert!((s.at(0.25) - 0.6).abs() < 1e-9);
+
+        let curves = Curves {
+            red: Curve::new(vec![[0.0, 1.0], [1.0, 0.0]]),
+            ..Curves::default()
+        };
+        let t = curves.tables().unwrap();
+        let c = t.apply(RGB::new(0.0, 100.0, 255.0));
+        assert_eq!(c.r(), 255.0);
+        assert!((c.g() - 100.0).abs() < 0.5);
+        assert_eq!(c.b(), 255.0);
+
+        let text = serde_json::to_string(&curves).unwrap();
+        assert!(text.contains("[[0.0,1.0],[1.0,0.0]]"), "{}", text);
+        let back: Curves = serde_json::from_str(r#"{"red": [[0.5, 0.7]]}"#).unwrap();
+        assert_eq!(back.red.points(), &[[0.0, 0.0], [0.5, 0.7], [1.0, 1.0]]);
+        assert!(back.green.is_identity());
+
+        let mut spec = ColorSpec::new(vec![Gradient::default()], RGB::BLACK);
+        spec.set_curves(curves.clone());
+        let text = toml::to_string(&spec).unwrap();
+        assert_eq!(toml::from_str::<ColorSpec>(&text).unwrap(), spec);
+        assert_eq!(spec.curves(), &curves);
+    }
+}
@@ -4,16 +4,45 @@ a complex number abstraction
 Type `Cx` can use the `+`, `*`, and unary `-` operators; the type also
 features constructors from Cartesian (rectangular) and polar coordinates,
 and accessors to get _|z|_ and _𝜑(z)_.
+
+In saved parameters, a `Cx` is normally written as `[re, im]`. With
+`set_polar_output(true)`, it's written as a table with its polar form
+(`r`, and `theta_pi`, which is _𝜑_ in multiples of _𝜋_, just like in the
+iterator pane) alongside its rectangular one. Either form can be read.
+When a table has both and they disagree (because someone edited one by
+hand), the polar form wins.
 */
 
 #![allow(clippy::from_over_into)]
 
+use std::f64::consts::PI;
 use std::ops::{Add, Mul, Neg};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use ::serde_derive::{Deserialize, Serialize};
 
+// Whether to write both forms of coefficients. See `set_polar_output()`.
+static POLAR_OUTPUT: AtomicBool = AtomicBool::new(false);
+// How far apart (relative to |z|) the polar and rectangular forms of a
+// coefficient can be before they're considered to disagree. Converting
+// to polar form and back isn't exact, so they never quite agree.
+const AGREEMENT: f64 = 1.0e-9;
+
+/**
+Set whether complex numbers get serialized in polar form as well as
+rectangular, for the benefit of people who edit parameter files by hand.
+*/
+pub fn set_polar_output(on: bool) {
+    POLAR_OUTPUT.store(on, Ordering::Relaxed);
+}
+
+/** Return whether complex numbers get serialized in polar form as well. */
+pub fn polar_output() -> bool {
+    POLAR_OUTPUT.load(Ordering::Relaxed)
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(from = "[f64; 2]", into = "[f64; 2]")]
+#[serde(from = "CxRepr", into = "CxRepr")]
 pub struct Cx {
     pub re: f64,
     pub im: f64,
@@ -42,6 +71,33 @@ impl Cx {
     pub fn theta(&self) -> f64 {
         self.im.atan2(self.re)
     }
+
+    pub fn conj(&self) -> Cx {
+        Cx {
+            re: self.re,
+            im: -self.im,
+        }
+    }
+
+    pub fn exp(&self) -> Cx {
+        Cx::polar(self.re.exp(), self.im)
+    }
+
+    pub fn sin(&self) -> Cx {
+        let (sin, cos) = self.re.sin_cos();
+        Cx {
+            re: sin * self.im.cosh(),
+            im: cos * self.im.sinh(),
+        }
+    }
+
+    pub fn cos(&self) -> Cx {
+        let (sin, cos) = self.re.sin_cos();
+        Cx {
+            re: cos * self.im.cosh(),
+            im: -sin * self.im.sinh(),
+        }
+    }
 }
 
 impl Add for Cx {
@@ -88,3 +144,136 @@ impl Into<[f64; 2]> for Cx {
         [self.re, self.im]
     }
 }
+
+// The ways a `Cx` can be written down in saved parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum CxRepr {
+    Rect([f64; 2]),
+    Polar {
+        r: f64,
+        theta_pi: f64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        re: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        im: Option<f64>,
+    },
+    Table {
+        re: f64,
+        im: f64,
+    },
+}
+
+impl CxRepr {
+    fn polar(z: Cx) -> CxRepr {
+        CxRepr::Polar {
+            r: z.r(),
+            theta_pi: z.theta() / PI,
+            re: Some(z.re),
+            im: Some(z.im),
+        }
+    }
+}
+
+impl From<Cx> for CxRepr {
+    fn from(z: Cx) -> CxRepr {
+        if polar_output() {
+            CxRepr::polar(z)
+        } else {
+            CxRepr::Rect(z.into())
+        }
+    }
+}
+
+impl From<CxRepr> for Cx {
+    fn from(repr: CxRepr) -> Cx {
+        match repr {
+            CxRepr::Rect(a) => Cx::from(a),
+            CxRepr::Table { re, im } => Cx::rect(re, im),
+            CxRepr::Polar {
+                r,
+                theta_pi,
+                re,
+                im,
+            } => {
+                let z = Cx::polar(r, theta_pi * PI);
+                match (re, im) {
+                    // The rectangular form is exact, so if nobody's changed
+                    // either one, it's the one to use.
+                    (Some(re), Some(im)) => {
+                        let rect = Cx::rect(re, im);
+                        let diff = (z + -rect).r();
+                        if diff <= AGREEMENT * r.abs().max(1.0) {
+                            rect
+                        } else {
+                            z
+                        }
+                    }
+                    _ => z,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cx_representations() {
+        let z = Cx::rect(-0.7885, 0.1234567890123456);
+        let cases = [
+            (CxRepr::Rect(z.into()), "[-0.7885,0.1234567890123456]"),
+            (
+                CxRepr::Table { re: z.re, im: z.im },
+                r#"{"re":-0.7885,"im":0.1234567890123456}"#,
+            ),
+        ];
+        for (repr, text) in cases.iter() {
+            assert_eq!(serde_json::to_string(repr).unwrap(), *text);
+            let back: Cx = serde_json::from_str::<CxRepr>(text).unwrap().into();
+            assert_eq!(back, z);
+        }
+
+        // Both forms round trip exactly...
+        let text = serde_json::to_string(&CxRepr::polar(z)).unwrap();
+        assert!(text.contains("theta_pi"));
+        let back: Cx = serde_json::from_str::<CxRepr>(&text).unwrap().into();
+        assert_eq!(back, z);
+
+        // ...but when they disagree, the polar form wins.
+        let edited = CxRepr::Polar {
+            r: 2.0,
+            theta_pi: 0.5,
+            re: Some(z.re),
+            im: Some(z.im),
+        };
+        let back: Cx = edited.into();
+        assert!(back.re.abs() < 1.0e-12 && (back.im - 2.0).abs() < 1.0e-12);
+        let back: Cx = serde_json::from_str::<CxRepr>(r#"{"r":1,"theta_pi":1}"#)
+            .unwrap()
+            .into();
+        assert!((back.re + 1.0).abs() < 1.0e-12);
+
+        assert!(serde_json::from_str::<CxRepr>(r#"{"r":1}"#).is_err());
+    }
+
+    #[test]
+    fn cx_functions() {
+        let close = |a: Cx, b: Cx| (a + -b).r() < 1.0e-12;
+        let z = Cx::rect(0.3, -1.2);
+        let i = Cx::rect(0.0, 1.0);
+        let half = Cx::rect(0.5, 0.0);
+        assert!(close(Cx::rect(0.0, PI).exp(), Cx::rect(-1.0, 0.0)));
+        assert!(close(z.exp() * (-z).exp(), Cx::rect(1.0, 0.0)));
+        // sin z = (e^iz - e^-iz) / 2i, and cos z = (e^iz + e^-iz) / 2
+        let (eiz, emiz) = ((i * z).exp(), (-(i * z)).exp());
+        assert!(close(z.sin() * i * Cx::rect(2.0, 0.0), eiz + -emiz));
+        assert!(close(z.cos(), half * (eiz + emiz)));
+        let one = z.sin() * z.sin() + z.cos() * z.cos();
+        assert!(close(one, Cx::rect(1.0, 0.0)));
+        assert_eq!(z.conj(), Cx::rect(0.3, 1.2));
+        assert_eq!((z * z.conj()).im, 0.0);
+    }
+}
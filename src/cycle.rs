@@ -0,0 +1,148 @@
+/*!
+Color cycling: moving the color map's phase steadily around, so the colors
+flow through the image, the way old fractal programs rotated their
+palettes.
+
+Only the colors change from one frame to the next, so every frame is the
+same `IterMap` colored again with the phase moved on a little; nothing
+gets iterated again. That makes it cheap enough to preview live, and to
+export as many frames as anyone wants of an image that took ages to
+iterate. (Like other animations, the frames can go straight into a video
+or an animated GIF or PNG; see the `video` module.)
+*/
+
+use std::path::Path;
+
+use crate::animate::frame_name;
+use crate::image::*;
+use crate::rw;
+use crate::video::{self, Output};
+
+/** How often (in seconds) the live preview moves to its next frame. */
+pub const FRAME_SECONDS: f64 = 1.0 / 30.0;
+/** How long (in seconds) the live preview takes to go around once. */
+pub const CYCLE_SECONDS: f64 = 4.0;
+
+/** Return `cspec` with its phase moved on by `t` of a full cycle. */
+pub fn shifted(cspec: &ColorSpec, t: f64) -> ColorSpec {
+    let mut cspec = cspec.clone();
+    cspec.set_phase(cspec.phase() + t);
+    cspec
+}
+
+/**
+Color `imap` with `cspec` for each of `frames` frames (at least two) of a
+single trip around the color map, and save them as numbered PNGs named
+after `base` (see `animate::frame_name()`), with each frame's parameters
+embedded. The frames loop: the one after the last would be the first
+again. After each frame, `progress` is called with the number done so
+far; if it returns `false`, the rest are skipped.
+*/
+pub fn render<F>(
+    imap: &IterMap,
+    cspec: &ColorSpec,
+    frames: usize,
+    base: &Path,
+    mut progress: F,
+) -> Result<(), String>
+where
+    F: FnMut(usize) -> bool,
+{
+    if frames < 2 {
+        return Err("A color cycle needs at least two frames.".to_string());
+    }
+    for n in 0..frames {
+        let cspec = shifted(cspec, n as f64 / frames as f64);
+        let cmap = ColorMap::make(cspec.clone());
+        imap.iterate_layers(&cmap, &IterWatcher::new());
+        let (xpix, ypix, data) = imap.color(&cmap).to_bytes(1);
+        let fname = frame_name(base, n, frames);
+        rw::save_with_metadata(
+            &fname,
+            xpix,
+            ypix,
+            &data,
+            Some((&imap.dims(), &cspec, imap.itertype())),
+            rw::PngCompression::Best,
+        )?;
+        if !progress(n + 1) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/**
+Color the frames of a cycle the same way as `render()`, but into `out`:
+numbered PNGs, or a video or animated image at `fps` frames per second.
+*/
+pub fn render_to<F>(
+    imap: &IterMap,
+    cspec: &ColorSpec,
+    frames: usize,
+    out: &Output,
+    fps: f64,
+    progress: F,
+) -> Result<(), String>
+where
+    F: FnMut(usize) -> bool,
+{
+    if let Output::Frames(base) = out {
+        return render(imap, cspec, frames, base, progress);
+    }
+    if frames < 2 {
+        return Err("A color cycle needs at least two frames.".to_string());
+    }
+    let dims = imap.dims();
+    let frame = |n| {
+        let cmap = ColorMap::make(shifted(cspec, n as f64 / frames as f64));
+        imap.iterate_layers(&cmap, &IterWatcher::new());
+        imap.color(&cmap).to_rgb8(1).2
+    };
+    video::encode(out, (dims.xpix, dims.ypix), fps, frames, frame, progress)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cycling() {
+        let mut cspec = ColorSpec::new(vec![Gradient::default()], RGB::BLACK);
+        cspec.set_phase(0.75);
+        assert_eq!(shifted(&cspec, 0.5).phase(), 0.25);
+        assert_eq!(shifted(&cspec, 1.0).phase(), 0.75);
+
+        let dims = ImageDims {
+            xpix: 30,
+            ypix: 20,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        let imap = IterMap::new(
+            dims,
+            IterType::Mandlebrot,
+            ColorMap::make(cspec.clone()).limit(),
+        );
+        assert!(render(&imap, &cspec, 1, Path::new("cycle.png"), |_| true).is_err());
+
+        let dir = std::env::temp_dir().join(format!("jset_cycle_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("cycle.png");
+        let mut done = Vec::new();
+        render(&imap, &cspec, 4, &base, |n| {
+            done.push(n);
+            true
+        })
+        .unwrap();
+        assert_eq!(done, vec![1, 2, 3, 4]);
+        let (ldims, lspec, _) = rw::load(frame_name(&base, 2, 4)).unwrap();
+        assert_eq!(ldims, dims);
+        assert_eq!(lspec.phase(), 0.25);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,288 @@
+/*!
+Export sets: the same image saved at several sizes in one go, like a
+wallpaper for each of a few screens.
+
+The image is only iterated once, at a size big enough for the largest of
+them. Each size then takes the middle of that with its own shape (so a
+tall phone wallpaper of a wide image loses its sides) and shrinks it
+down, averaging the pixels (see `FImage32::resample()`), which makes for
+smoother edges than iterating at each size would, and takes a fraction of
+the time.
+*/
+
+use std::path::{Path, PathBuf};
+
+use crate::hook;
+use crate::image::*;
+use crate::rw::{self, ImageFormat, PngCompression};
+
+/** The sizes `parse_sizes()` knows by name. */
+pub const PRESETS: &[(&str, usize, usize)] = &[
+    ("720p", 1280, 720),
+    ("1080p", 1920, 1080),
+    ("1440p", 2560, 1440),
+    ("4k", 3840, 2160),
+    ("phone", 1170, 2532),
+];
+/** The sizes an export set gets if none are given. */
+pub const DEFAULT_SIZES: &str = "1080p, 1440p, 4k, phone";
+/**
+The most samples an image rendered to save can have either way (it takes
+a few dozen bytes a pixel while it's being made); see `check_size()`.
+*/
+pub const MAX_SIZE: usize = 16384;
+
+/** One of the sizes in an export set. */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportSize {
+    /// What goes on the end of the file name.
+    pub name: String,
+    pub xpix: usize,
+    pub ypix: usize,
+}
+
+/**
+Read a list of sizes (separated by commas or spaces), each either the name
+of one of the `PRESETS` or a width and height like "800x600".
+*/
+pub fn parse_sizes(text: &str) -> Result<Vec<ExportSize>, String> {
+    let mut sizes: Vec<ExportSize> = Vec::new();
+    for word in text.split(|c: char| c == ',' || c.is_whitespace()) {
+        let word = word.trim().to_ascii_lowercase();
+        if word.is_empty() {
+            continue;
+        }
+        if let Some(&(name, xpix, ypix)) = PRESETS.iter().find(|p| p.0 == word) {
+            sizes.push(ExportSize {
+                name: name.to_string(),
+                xpix,
+                ypix,
+            });
+            continue;
+        }
+        let pixels = word
+            .split_once('x')
+            .and_then(|(w, h)| Some((w.parse::<usize>().ok()?, h.parse::<usize>().ok()?)));
+        match pixels {
+            Some((xpix, ypix)) if xpix > 0 && ypix > 0 => sizes.push(ExportSize {
+                name: format!("{}x{}", xpix, ypix),
+                xpix,
+                ypix,
+            }),
+            _ => {
+                let names: Vec<&str> = PRESETS.iter().map(|p| p.0).collect();
+                return Err(format!(
+                    "\"{}\" isn't a size; use a width and height like 800x600, or one of {}.",
+                    &word,
+                    names.join(", ")
+                ));
+            }
+        }
+    }
+    if sizes.is_empty() {
+        return Err("No sizes to export.".to_string());
+    }
+    Ok(sizes)
+}
+
+/**
+Return an error if an image of `dims` is too big to render: if, counting
+each sample of an oversampled pixel, it's more than `MAX_SIZE` across or
+down.
+*/
+pub fn check_size(dims: &ImageDims) -> Result<(), String> {
+    let s = dims.samples();
+    if dims.xpix * s > MAX_SIZE || dims.ypix * s > MAX_SIZE {
+        return Err(format!(
+            "{} x {} pixels, with {} x {} samples each, is too big to render; \
+             that can't be more than {} samples across or down.",
+            dims.xpix, dims.ypix, s, s, MAX_SIZE
+        ));
+    }
+    Ok(())
+}
+
+/**
+Return the view of the same region as `dims` (and as oversampled) big
+enough that the middle of it in the shape of each of `sizes` has at least
+that many pixels, or an error if that's too big (see `check_size()`).
+*/
+pub fn master_dims(dims: &ImageDims, sizes: &[ExportSize]) -> Result<ImageDims, String> {
+    let aspect = dims.xpix.max(1) as f64 / dims.ypix.max(1) as f64;
+    let xpix = sizes
+        .iter()
+        .map(|s| {
+            if s.xpix as f64 / s.ypix as f64 >= aspect {
+                s.xpix as f64
+            } else {
+                s.ypix as f64 * aspect
+            }
+        })
+        .fold(1.0, f64::max)
+        .ceil();
+    let ypix = (xpix / aspect).ceil();
+    let s = dims.samples();
+    if xpix * (s as f64) > MAX_SIZE as f64 || ypix * (s as f64) > MAX_SIZE as f64 {
+        return Err(format!(
+            "Those sizes need the image rendered at {} x {} pixels, with {} x {} samples each, \
+             which is more than {} samples across or down.",
+            xpix, ypix, s, s, MAX_SIZE
+        ));
+    }
+    Ok(dims.resize(xpix as usize, ypix as usize))
+}
+
+/** Return the middle of an `xpix` × `ypix` image, in the shape of `size`. */
+pub fn crop_rect(xpix: usize, ypix: usize, size: &ExportSize) -> PixelRect {
+    let shape = size.xpix as f64 / size.ypix as f64;
+    let (w, h) = if (xpix as f64 / ypix as f64) > shape {
+        (((ypix as f64) * shape).round() as usize, ypix)
+    } else {
+        (xpix, ((xpix as f64) / shape).round() as usize)
+    };
+    let (w, h) = (w.clamp(1, xpix), h.clamp(1, ypix));
+    PixelRect::new((xpix - w) / 2, (ypix - h) / 2, w, h)
+}
+
+/**
+Return the name the image of `size` gets in a set named after `base`:
+"julia.png" gives "julia_1080p.png".
+*/
+pub fn output_name(base: &Path, size: &ExportSize) -> PathBuf {
+    let stem = base.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{}_{}", &stem, &size.name);
+    if let Some(ext) = base.extension() {
+        name.push('.');
+        name.push_str(&ext.to_string_lossy());
+    }
+    base.with_file_name(name)
+}
+
+/**
+Render the image described by `dims`, `cspec`, and `itype` once (watched
+by `watcher`, which can call it off), and save it at each of `sizes` in
+`format` (see `output_name()`), PNGs with their parameters embedded if
+`embed_metadata`. After each one, `progress` is called with the number
+done so far; if it returns `false`, the rest are skipped. Returns the
+names of the files saved.
+*/
+#[allow(clippy::too_many_arguments)]
+pub fn export_set<F>(
+    base: &Path,
+    dims: &ImageDims,
+    cspec: &ColorSpec,
+    itype: &IterType,
+    sizes: &[ExportSize],
+    format: ImageFormat,
+    embed_metadata: bool,
+    watcher: &IterWatcher,
+    mut progress: F,
+) -> Result<Vec<PathBuf>, String>
+where
+    F: FnMut(usize) -> bool,
+{
+    let master = master_dims(dims, sizes)?;
+    let cmap = ColorMap::make(cspec.clone());
+    let cancelled = || "Cancelled; no images were saved.".to_string();
+    let imap = IterMap::new_watched(master, itype.clone(), cmap.limit(), cspec.orbit(), watcher)
+        .ok_or_else(cancelled)?;
+    if !imap.iterate_layers(&cmap, watcher) {
+        return Err(cancelled());
+    }
+    let img = imap.color(&cmap);
+
+    let mut saved: Vec<PathBuf> = Vec::with_capacity(sizes.len());
+    for (n, size) in sizes.iter().enumerate() {
+        let piece = match img.crop(crop_rect(master.xpix, master.ypix, size)) {
+            Some(piece) => piece.resample(size.xpix, size.ypix),
+            None => img.resample(size.xpix, size.ypix),
+        };
+        let (xpix, ypix, data) = piece.to_bytes(1);
+        let fname = output_name(base, size);
+        let piece_dims = piece.dims();
+        let params = Some((&piece_dims, cspec, itype)).filter(|_| embed_metadata);
+        match format {
+            ImageFormat::Png => {
+                rw::save_with_metadata(&fname, xpix, ypix, &data, params, PngCompression::Best)?
+            }
+            _ => rw::save_as(&fname, xpix, ypix, &data, format)?,
+        }
+        hook::after_save(&fname, xpix, ypix)?;
+        saved.push(fname);
+        if !progress(n + 1) {
+            break;
+        }
+    }
+    Ok(saved)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn export_sets() {
+        let sizes = parse_sizes("1080p, Phone 40x30").unwrap();
+        assert_eq!(sizes.len(), 3);
+        assert_eq!((sizes[1].xpix, sizes[1].ypix), (1170, 2532));
+        assert_eq!(sizes[2].name, "40x30");
+        assert!(parse_sizes("8k").is_err());
+        assert!(parse_sizes(" , ").is_err());
+
+        let dims = ImageDims {
+            xpix: 160,
+            ypix: 90,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        // A tall size makes the image rendered wide enough that its middle
+        // is still tall enough.
+        let master = master_dims(&dims, &sizes).unwrap();
+        assert_eq!(master.xpix, 4502);
+        let rect = crop_rect(master.xpix, master.ypix, &sizes[1]);
+        assert!(rect.h >= 2532 && rect.h <= master.ypix);
+        assert_eq!(rect.x, (master.xpix - rect.w) / 2);
+        assert!(master_dims(&dims, &parse_sizes("100000x10").unwrap()).is_err());
+        let oversampled = ImageDims {
+            oversample: 4,
+            ..dims
+        };
+        assert!(master_dims(&oversampled, &sizes).is_err());
+        assert!(check_size(&master).is_ok());
+        let over = ImageDims {
+            oversample: 4,
+            ..master
+        };
+        assert!(check_size(&over).is_err());
+
+        assert_eq!(
+            output_name(Path::new("out/julia.png"), &sizes[0]),
+            PathBuf::from("out/julia_1080p.png")
+        );
+
+        let dir = std::env::temp_dir().join(format!("jset_export_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cspec = ColorSpec::new(vec![Gradient::default(); 2], RGB::BLACK);
+        let small = parse_sizes("32x18 10x20").unwrap();
+        let saved = export_set(
+            &dir.join("set.png"),
+            &dims,
+            &cspec,
+            &IterType::Mandlebrot,
+            &small,
+            ImageFormat::Png,
+            true,
+            &IterWatcher::new(),
+            |_| true,
+        )
+        .unwrap();
+        assert_eq!(saved.len(), 2);
+        let (tall, _, _) = rw::load(&saved[1]).unwrap();
+        assert_eq!((tall.xpix, tall.ypix), (10, 20));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,79 @@
+/*!
+A few example parameter files built into the program, so there's
+something to look at (and start from) right away.
+
+The files themselves are in the `gallery` directory next to this one, as
+ordinary TOML parameter files with comments at the top saying what they
+are; they're loaded just like any other parameter file.
+*/
+
+use crate::image::{ColorSpec, ImageDims, IterType};
+use crate::rw::{self, ParamFormat};
+
+struct Example {
+    name: &'static str,
+    params: &'static str,
+}
+
+const EXAMPLES: &[Example] = &[
+    Example {
+        name: "Douady rabbit",
+        params: include_str!("gallery/rabbit.toml"),
+    },
+    Example {
+        name: "Siegel disk",
+        params: include_str!("gallery/siegel.toml"),
+    },
+    Example {
+        name: "Cubic Julia set",
+        params: include_str!("gallery/cubic.toml"),
+    },
+    Example {
+        name: "Seahorse Valley",
+        params: include_str!("gallery/seahorse.toml"),
+    },
+    Example {
+        name: "Tilted Mandlebrot",
+        params: include_str!("gallery/tilted.toml"),
+    },
+];
+
+/** Return the names of the examples, in the order they should be offered. */
+pub fn names() -> Vec<&'static str> {
+    EXAMPLES.iter().map(|ex| ex.name).collect()
+}
+
+/** Load the example with the given name (ignoring case). */
+pub fn load(name: &str) -> Result<(ImageDims, ColorSpec, IterType), String> {
+    match EXAMPLES
+        .iter()
+        .find(|ex| ex.name.eq_ignore_ascii_case(name))
+    {
+        Some(ex) => rw::load_bytes(ex.params.as_bytes(), ParamFormat::Toml),
+        None => Err(format!("There's no example called \"{}\".", name)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn all_examples() {
+        let names = names();
+        assert!(names.len() >= 5);
+        for (i, name) in names.iter().enumerate() {
+            assert!(!names[..i].contains(name), "{} twice", name);
+            // Menus treat these specially.
+            assert!(!name.contains(['/', '&', '_', '|']), "{}", name);
+            let (dims, cspec, _) = load(name).unwrap();
+            assert!(dims.xpix > 0 && dims.ypix > 0);
+            assert!(!cspec.gradients().is_empty());
+        }
+        assert!(matches!(
+            load("siegel disk").unwrap().2,
+            IterType::Polynomial { .. }
+        ));
+        assert!(load("Burning Ship").is_err());
+    }
+}
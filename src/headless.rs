@@ -0,0 +1,270 @@
+/*!
+Rendering images without any user interface at all, for use from the
+command line (or from anything else that just wants a picture).
+
+Everything here blocks until it's done; there's no UI to keep responsive.
+*/
+
+use std::path::Path;
+
+use crate::image::*;
+use crate::rw;
+
+/** The size of thumbnails if none is specified. */
+pub const DEFAULT_THUMBNAIL_SIZE: usize = 256;
+/** The largest thumbnail `thumbnail()` will make. */
+pub const MAX_THUMBNAIL_SIZE: usize = 4096;
+/**
+Roughly how many samples `export()` will iterate at once. (Each one takes
+a `usize` of iteration count, and each pixel a few floats of color.)
+*/
+pub const TILE_SAMPLES: usize = 1 << 24;
+
+/**
+Iterate and color an image.
+
+There's nothing random anywhere in rendering (oversampling takes its
+samples on a fixed grid, and color maps are computed straight from their
+specs), so the same parameters always make exactly the same image, no
+matter how many threads do the work. Anything that renders a sequence of
+images can start over partway through and get the same frames.
+*/
+pub fn render(dims: ImageDims, cspec: ColorSpec, itype: IterType) -> FImage32 {
+    let orbit = cspec.orbit();
+    let cmap = ColorMap::make(cspec);
+    let imap = IterMap::with_orbit(dims, itype, cmap.limit(), orbit);
+    imap.iterate_layers(&cmap, &IterWatcher::new());
+    imap.color(&cmap)
+}
+
+/**
+Return a view of the same region as `dims` that's `size` pixels along its
+longer side. Thumbnails are meant to be quick, so there's no oversampling.
+*/
+pub fn thumbnail_dims(dims: &ImageDims, size: usize) -> ImageDims {
+    let size = size.clamp(1, MAX_THUMBNAIL_SIZE);
+    let (xpix, ypix) = (dims.xpix.max(1) as f64, dims.ypix.max(1) as f64);
+    let (new_xpix, new_ypix) = if xpix >= ypix {
+        (size, ((size as f64) * ypix / xpix).round() as usize)
+    } else {
+        (((size as f64) * xpix / ypix).round() as usize, size)
+    };
+
+    ImageDims {
+        xpix: new_xpix.max(1),
+        ypix: new_ypix.max(1),
+        x: dims.x,
+        y: dims.y,
+        width: dims.width,
+        oversample: 1,
+        adaptive: false,
+        check_period: dims.check_period,
+    }
+}
+
+/**
+Render a `size`-pixel thumbnail of the image described by the parameters
+in `src` (anything `rw::load()` can read) and save it as a PNG to `dest`.
+
+This is meant for file managers, so the thumbnail doesn't carry the
+parameters as metadata.
+*/
+pub fn thumbnail<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dest: Q,
+    size: usize,
+) -> Result<(), String> {
+    let (dims, cspec, itype) = rw::load(src)?;
+    let tdims = thumbnail_dims(&dims, size);
+    let (xpix, ypix, data) = render(tdims, cspec, itype).to_bytes(1);
+    rw::save_without_metadata(dest, xpix, ypix, &data)
+}
+
+/**
+Render the image described by `dims`, `cspec`, and `itype` and save it as
+a PNG to `dest`, optionally with the parameters embedded.
+
+The image is rendered and written a band of rows at a time, so this can
+make images far too big to fit in memory; the size of the output is only
+limited by the size of the disk (and your patience).
+*/
+pub fn export<P: AsRef<Path>>(
+    dest: P,
+    dims: ImageDims,
+    cspec: ColorSpec,
+    itype: IterType,
+    embed_metadata: bool,
+    depth: rw::BitDepth,
+) -> Result<(), String> {
+    let samples_per_row = dims.xpix * dims.samples() * dims.samples();
+    let tile_rows = (TILE_SAMPLES / samples_per_row.max(1)).max(1);
+    export_tiled(dest, dims, cspec, itype, embed_metadata, depth, tile_rows)
+}
+
+fn export_tiled<P: AsRef<Path>>(
+    dest: P,
+    dims: ImageDims,
+    cspec: ColorSpec,
+    itype: IterType,
+    embed_metadata: bool,
+    depth: rw::BitDepth,
+    tile_rows: usize,
+) -> Result<(), String> {
+    let params = if embed_metadata {
+        Some((&dims, &cspec, &itype))
+    } else {
+        None
+    };
+    let alpha = cspec.transparent();
+    let mut png = rw::PngStream::create(dest, dims.xpix, dims.ypix, params, alpha, depth)?;
+
+    let cmap = ColorMap::make(cspec.clone());
+    let mut start = 0;
+    while start < dims.ypix {
+        let n_rows = tile_rows.min(dims.ypix - start);
+        let imap = IterMap::with_rows(
+            dims,
+            start,
+            n_rows,
+            itype.clone(),
+            cmap.limit(),
+            cspec.orbit(),
+        );
+        imap.iterate_layers(&cmap, &IterWatcher::new());
+        let img = imap.color(&cmap);
+        match (depth, alpha) {
+            (rw::BitDepth::Eight, false) => png.write_rows(&img.to_rgb8(1).2)?,
+            (rw::BitDepth::Eight, true) => png.write_rows(&img.to_rgba8(1).2)?,
+            (rw::BitDepth::Sixteen, false) => png.write_rows16(&img.to_rgb16(1).2)?,
+            (rw::BitDepth::Sixteen, true) => png.write_rows16(&img.to_rgba16(1).2)?,
+        }
+        start += n_rows;
+    }
+
+    png.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn thumbnail_shapes() {
+        let dims = ImageDims {
+            xpix: 900,
+            ypix: 600,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 4,
+            adaptive: true,
+            check_period: true,
+        };
+        let t = thumbnail_dims(&dims, 256);
+        assert_eq!((t.xpix, t.ypix), (256, 171));
+        assert_eq!((t.x, t.y, t.width), (dims.x, dims.y, dims.width));
+        assert_eq!(t.samples(), 1);
+        assert!((t.height() - dims.height()).abs() < 0.01);
+
+        let tall = ImageDims {
+            xpix: 100,
+            ypix: 400,
+            ..dims
+        };
+        let t = thumbnail_dims(&tall, 128);
+        assert_eq!((t.xpix, t.ypix), (32, 128));
+
+        let t = thumbnail_dims(&dims, 0);
+        assert!(t.xpix >= 1 && t.ypix >= 1);
+    }
+
+    #[test]
+    fn deterministic_renders() {
+        let dims = ImageDims {
+            xpix: 80,
+            ypix: 60,
+            x: -0.8,
+            y: 0.2,
+            width: 0.1,
+            oversample: 3,
+            adaptive: true,
+            check_period: true,
+        };
+        let cspec = crate::presets::get("Fire").unwrap();
+        let itype = IterType::PseudoMandlebrot {
+            a: crate::cx::Cx::rect(0.9, 0.1),
+            b: crate::cx::Cx::rect(1.0, 0.0),
+        };
+        let (_, _, first) = render(dims, cspec.clone(), itype.clone()).to_rgb8(1);
+        for _ in 0..3 {
+            let (_, _, again) = render(dims, cspec.clone(), itype.clone()).to_rgb8(1);
+            assert!(first == again);
+        }
+    }
+
+    // Stitching tiles together should give the same picture as rendering
+    // the whole thing at once, even where adaptive oversampling compares
+    // pixels at the edges of the tiles with their neighbors.
+    #[test]
+    fn tiled_export() {
+        let dims = ImageDims {
+            xpix: 97,
+            ypix: 61,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 2,
+            adaptive: true,
+            check_period: true,
+        };
+        let cspec = ColorSpec::new(
+            vec![Gradient {
+                steps: 64,
+                space: Interpolation::Rgb,
+                start: RGB::BLACK,
+                end: RGB::WHITE,
+            }],
+            RGB::BLACK,
+        );
+        let fname = std::env::temp_dir().join("jset_desk_tiled_export_test.png");
+        export_tiled(
+            &fname,
+            dims,
+            cspec.clone(),
+            IterType::Mandlebrot,
+            true,
+            rw::BitDepth::Eight,
+            7,
+        )
+        .unwrap();
+
+        let (_, _, whole) = render(dims, cspec.clone(), IterType::Mandlebrot).to_rgb8(1);
+        let mut rdr = png::Decoder::new(std::fs::File::open(&fname).unwrap())
+            .read_info()
+            .unwrap();
+        let mut tiled = vec![0u8; rdr.output_buffer_size()];
+        let info = rdr.next_frame(&mut tiled).unwrap();
+        assert_eq!((info.width, info.height), (97, 61));
+        assert!(whole == tiled);
+
+        let (ldims, lspec, _) = rw::load(&fname).unwrap();
+        assert_eq!((ldims, lspec), (dims, cspec.clone()));
+
+        // Sixteen bits a channel, but the same picture.
+        let deep = rw::BitDepth::Sixteen;
+        export_tiled(&fname, dims, cspec, IterType::Mandlebrot, false, deep, 7).unwrap();
+        let mut rdr = png::Decoder::new(std::fs::File::open(&fname).unwrap())
+            .read_info()
+            .unwrap();
+        let mut tiled = vec![0u8; rdr.output_buffer_size()];
+        let info = rdr.next_frame(&mut tiled).unwrap();
+        assert_eq!(info.bit_depth, png::BitDepth::Sixteen);
+        let n_diff = whole
+            .iter()
+            .zip(tiled.chunks_exact(2))
+            .filter(|(a, b)| (**a as i32 - b[0] as i32).abs() > 1)
+            .count();
+        assert!(n_diff * 1000 < whole.len(), "{} values differ", n_diff);
+        std::fs::remove_file(&fname).unwrap();
+    }
+}
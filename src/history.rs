@@ -0,0 +1,169 @@
+/*!
+A record of the states something has been in, for stepping back through
+them (undo) and forward again (redo).
+*/
+
+/**
+A list of states, with a place in it marking the current one.
+
+Recording a new state throws away any that had been stepped back past, so
+that stepping forward always retraces steps that were actually taken.
+*/
+#[derive(Clone, Debug)]
+pub struct History<T> {
+    states: Vec<T>,
+    current: usize,
+    limit: usize,
+}
+
+impl<T: Clone + PartialEq> History<T> {
+    /**
+    Start a history at `start`, remembering at most `limit` states (at
+    least two, or there'd be nothing to go back to); the oldest ones are
+    forgotten first.
+    */
+    pub fn new(start: T, limit: usize) -> History<T> {
+        History {
+            states: vec![start],
+            current: 0,
+            limit: limit.max(2),
+        }
+    }
+
+    /**
+    Pick a history back up from `states` (oldest first, as returned by
+    `states()`), with the one at index `current` the current one. Like
+    `new()`, it only keeps the last `limit` of them. Returns `None` if
+    there are no states at all.
+    */
+    pub fn from_states(states: Vec<T>, current: usize, limit: usize) -> Option<History<T>> {
+        if states.is_empty() {
+            return None;
+        }
+        let limit = limit.max(2);
+        let extra = states.len().saturating_sub(limit);
+        let current = current.min(states.len() - 1).saturating_sub(extra);
+        Some(History {
+            states: states.into_iter().skip(extra).collect(),
+            current,
+            limit,
+        })
+    }
+
+    /** All the states, oldest first. */
+    pub fn states(&self) -> &[T] {
+        &self.states
+    }
+
+    /** Where the current state is in `states()`. */
+    pub fn position(&self) -> usize {
+        self.current
+    }
+
+    /** The current state. */
+    pub fn current(&self) -> &T {
+        &self.states[self.current]
+    }
+
+    /**
+    Make `state` the current one, unless it already is. Returns whether
+    it got recorded.
+    */
+    pub fn record(&mut self, state: T) -> bool {
+        if &state == self.current() {
+            return false;
+        }
+        self.states.truncate(self.current + 1);
+        self.states.push(state);
+        if self.states.len() > self.limit {
+            let extra = self.states.len() - self.limit;
+            self.states.drain(..extra);
+        }
+        self.current = self.states.len() - 1;
+        true
+    }
+
+    /** Step back to the previous state and return it, if there is one. */
+    pub fn back(&mut self) -> Option<T> {
+        if self.current == 0 {
+            return None;
+        }
+        self.current -= 1;
+        Some(self.current().clone())
+    }
+
+    /** Step forward to the next state and return it, if there is one. */
+    pub fn forward(&mut self) -> Option<T> {
+        if self.current + 1 >= self.states.len() {
+            return None;
+        }
+        self.current += 1;
+        Some(self.current().clone())
+    }
+
+    /** Return whether there's a state to step back to. */
+    pub fn can_go_back(&self) -> bool {
+        self.current > 0
+    }
+
+    /** Return whether there's a state to step forward to. */
+    pub fn can_go_forward(&self) -> bool {
+        self.current + 1 < self.states.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stepping_through() {
+        let mut h = History::new(0, 4);
+        assert!(h.back().is_none() && h.forward().is_none());
+        assert!(!h.record(0));
+        for n in 1..4 {
+            assert!(h.record(n));
+        }
+        assert_eq!(h.back(), Some(2));
+        assert_eq!(h.back(), Some(1));
+        assert_eq!(h.forward(), Some(2));
+        assert!(h.can_go_forward());
+
+        // A new state means the old future is gone.
+        h.record(7);
+        assert!(!h.can_go_forward());
+        assert_eq!(h.back(), Some(2));
+        assert_eq!(h.forward(), Some(7));
+
+        // Only the last few are kept.
+        h.record(8);
+        h.record(9);
+        assert_eq!(h.current(), &9);
+        assert_eq!(h.back(), Some(8));
+        assert_eq!(h.back(), Some(7));
+        assert_eq!(h.back(), Some(2));
+        assert_eq!(h.back(), None);
+        assert!(!h.can_go_back());
+    }
+
+    #[test]
+    fn picking_back_up() {
+        let mut h = History::new(0, 4);
+        for n in 1..4 {
+            h.record(n);
+        }
+        h.back();
+        let mut again = History::from_states(h.states().to_vec(), h.position(), 4).unwrap();
+        assert_eq!(again.current(), &2);
+        assert_eq!(again.forward(), Some(3));
+
+        // Too many states loses the oldest, keeping the same one current.
+        let h = History::from_states(vec![0, 1, 2, 3, 4, 5], 3, 4).unwrap();
+        assert_eq!((h.states(), h.current()), (&[2, 3, 4, 5][..], &3));
+        let h = History::from_states(vec![0, 1, 2, 3, 4, 5], 0, 4).unwrap();
+        assert_eq!(h.current(), &2);
+        let h = History::from_states(vec![0, 1], 7, 4).unwrap();
+        assert_eq!(h.current(), &1);
+        assert!(History::<u8>::from_states(Vec::new(), 0, 4).is_none());
+    }
+}
@@ -0,0 +1,172 @@
+/*!
+Running a command of the user's choosing on each image after it's been
+written, like an optimizer (`oxipng {file}`) or an upload script.
+
+The command is a program followed by its arguments, separated by spaces
+(quote any argument with spaces in it). It isn't run through a shell, so
+file names can't be mistaken for shell syntax. These tokens are replaced
+wherever they occur in the arguments:
+
+  * `{file}`: the full path of the image
+  * `{dir}`: the directory the image is in
+  * `{name}`: the image's file name, without the directory
+  * `{stem}`: the file name without its extension
+  * `{width}`, `{height}`: the image's dimensions in pixels
+*/
+
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+
+// The most of the command's complaints to pass along when it fails.
+const MAX_STDERR_LINES: usize = 8;
+
+static POST_SAVE: Mutex<Option<Hook>> = Mutex::new(None);
+
+/** A command to be run on saved images. */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hook {
+    args: Vec<String>,
+}
+
+impl Hook {
+    /** Split a command line into a program and its arguments. */
+    pub fn parse(cmd: &str) -> Result<Hook, String> {
+        let mut args: Vec<String> = Vec::new();
+        let mut arg: Option<String> = None;
+        let mut quote: Option<char> = None;
+        for c in cmd.chars() {
+            match (quote, c) {
+                (Some(q), c) if c == q => {
+                    quote = None;
+                }
+                (Some(_), c) => arg.get_or_insert_with(String::new).push(c),
+                (None, '"') | (None, '\'') => {
+                    quote = Some(c);
+                    arg.get_or_insert_with(String::new);
+                }
+                (None, c) if c.is_whitespace() => {
+                    if let Some(a) = arg.take() {
+                        args.push(a);
+                    }
+                }
+                (None, c) => arg.get_or_insert_with(String::new).push(c),
+            }
+        }
+        if let Some(q) = quote {
+            return Err(format!("Unmatched {} in command: {}", q, cmd));
+        }
+        if let Some(a) = arg {
+            args.push(a);
+        }
+        if args.is_empty() {
+            return Err("No command given.".to_string());
+        }
+        Ok(Hook { args })
+    }
+
+    /** The program and its arguments, with the tokens filled in. */
+    pub fn args_for(&self, file: &Path, xpix: usize, ypix: usize) -> Vec<String> {
+        let file = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+        let dir = file.parent().unwrap_or_else(|| Path::new("."));
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+        let tokens = [
+            ("{file}", file.to_string_lossy()),
+            ("{dir}", dir.to_string_lossy()),
+            ("{name}", name),
+            ("{stem}", stem),
+            ("{width}", xpix.to_string().into()),
+            ("{height}", ypix.to_string().into()),
+        ];
+
+        self.args
+            .iter()
+            .map(|a| {
+                tokens
+                    .iter()
+                    .fold(a.clone(), |a, (tok, val)| a.replace(tok, val))
+            })
+            .collect()
+    }
+
+    /**
+    Run the command on the given image, waiting for it to finish. A
+    failure to start or a nonzero exit status is an error, which includes
+    whatever the command had to say about it.
+    */
+    pub fn run(&self, file: &Path, xpix: usize, ypix: usize) -> Result<(), String> {
+        let args = self.args_for(file, xpix, ypix);
+        let output = match Command::new(&args[0]).args(&args[1..]).output() {
+            Ok(output) => output,
+            Err(e) => {
+                let estr = format!("Error running {}: {}", &args[0], &e);
+                return Err(estr);
+            }
+        };
+        if output.status.success() {
+            return Ok(());
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let lines: Vec<&str> = stderr.lines().collect();
+        let tail = &lines[lines.len().saturating_sub(MAX_STDERR_LINES)..];
+        let mut estr = format!("{} failed ({})", &args[0], &output.status);
+        for line in tail.iter() {
+            estr.push('\n');
+            estr.push_str(line);
+        }
+        Err(estr)
+    }
+}
+
+/** Set the command to run on each image after it's saved (or none). */
+pub fn set_post_save(hook: Option<Hook>) {
+    *POST_SAVE.lock().unwrap() = hook;
+}
+
+/** The command run on each image after it's saved, if there is one. */
+pub fn post_save() -> Option<Hook> {
+    POST_SAVE.lock().unwrap().clone()
+}
+
+/**
+Run the post-save command (if one's been set) on a freshly-saved image.
+*/
+pub fn after_save<P: AsRef<Path>>(file: P, xpix: usize, ypix: usize) -> Result<(), String> {
+    match post_save() {
+        Some(hook) => hook.run(file.as_ref(), xpix, ypix),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hook_commands() {
+        let hook = Hook::parse("  oxipng -o 4 {file}").unwrap();
+        assert_eq!(hook.args, vec!["oxipng", "-o", "4", "{file}"]);
+        let hook = Hook::parse(r#"upload "my pics/{name}" '' x"y z"w"#).unwrap();
+        assert_eq!(hook.args, vec!["upload", "my pics/{name}", "", "xy zw"]);
+        assert!(Hook::parse("   ").is_err());
+        assert!(Hook::parse("echo \"oops").is_err());
+
+        let hook = Hook::parse("cp {file} /tmp/{stem}_{width}x{height}.png {name}").unwrap();
+        let file = Path::new("no_such_dir/spiral.png");
+        assert_eq!(
+            hook.args_for(file, 640, 480),
+            vec![
+                "cp",
+                "no_such_dir/spiral.png",
+                "/tmp/spiral_640x480.png",
+                "spiral.png"
+            ]
+        );
+
+        let hook = Hook::parse("jset_no_such_program {file}").unwrap();
+        let e = hook.run(file, 1, 1).unwrap_err();
+        assert!(e.contains("jset_no_such_program"));
+    }
+}
@@ -2,31 +2,70 @@
 Everything required for specifying and creating the bytes of an image.
 */
 
+use std::any::Any;
 use std::convert::{From, Into};
 use std::default::Default;
-use std::sync::mpsc;
-use std::thread;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use ::serde_derive::{Deserialize, Serialize};
 use lazy_static::lazy_static;
+use rayon::prelude::*;
+use wide::{f64x4, CmpLe, CmpLt};
 
+use crate::curves::{CurveTables, Curves};
 use crate::cx::Cx;
+use crate::mapping;
+
+// The number of threads to iterate with, if it's been set (0 if it hasn't).
+static THREADS_WANTED: AtomicUsize = AtomicUsize::new(0);
 
 lazy_static! {
-    static ref N_THREADS: usize = num_cpus::get_physical();
+    static ref N_THREADS: usize = match THREADS_WANTED.load(Ordering::Relaxed) {
+        0 => num_cpus::get_physical(),
+        n => n,
+    };
+    // All iteration happens in this pool, so threads get reused from one
+    // render to the next.
+    static ref POOL: rayon::ThreadPool = rayon::ThreadPoolBuilder::new()
+        .num_threads(*N_THREADS)
+        .thread_name(|n| format!("jset iterator {}", n))
+        .build()
+        .expect("unable to start iteration thread pool");
 }
 
 // When a point's squared modulus exceeds this amount under iteration, it
 // will be considered to have "diverged" and will be colored the "default"
 // color.
 const SQ_MOD_LIMIT: f64 = 1.0e100;
+// The number of pixels iterated at once by the vectorized iterators.
+const LANES: usize = 4;
+// When periodicity checking is on, a point whose orbit comes back within
+// this (squared) distance of where it was is taken to be stuck in a cycle.
+const PERIOD_SQ_EPSILON: f64 = 1.0e-24;
+// Saved iteration counts (see `IterMap::write_counts()`) start with this.
+const COUNTS_MAGIC: &[u8; 8] = b"jsetcnt1";
+// The most chunks saved counts can come in (far more than any render
+// uses), so reading them never takes much more than the counts themselves.
+const MAX_COUNT_CHUNKS: usize = 65536;
+// When sampling adaptively, pixels whose iteration counts differ from a
+// neighbor's by more than this get the full complement of samples.
+const ADAPTIVE_THRESHOLD: usize = 1;
 // The number of chunks per physical computer core each `Itermap` will be
 // split into for parallel processing. Larger values will result in less
 // idle time due to different image chunks taking differnt amounts of time
-// to process, but will also incur more thread spawning/switching overhead.
-// I haven't done any profiling around this value, and I'm sure the "best"
-// value is highly situation-dependent.
-const CHUNKS_PER_THREAD: usize = 2;
+// to process (the thread pool steals work, so cheap exterior chunks get
+// balanced against expensive interior ones), but each chunk has a little
+// bookkeeping overhead. I haven't done any profiling around this value, and
+// I'm sure the "best" value is highly situation-dependent.
+const CHUNKS_PER_THREAD: usize = 8;
+// When an iteration runs past its watcher's deadline, the rest of it gets
+// done with the iteration limit divided by this.
+const OVERTIME_DIVISOR: usize = 8;
 // The largest factor by which an `FImage32` will scale itself when generating
 // an 8-bit representation of itself. This is hard-coded so the hot loop
 // of the scaling algorithm can use the stack.
@@ -34,6 +73,21 @@ const MAX_SCALE_FACTOR: usize = 5;
 // Calculated value for the size of the stack-allocated array used for
 // scaling-down `FImage32` pixels.
 const SCALE_PALETTE_SIZE: usize = MAX_SCALE_FACTOR * MAX_SCALE_FACTOR;
+/**
+The largest number of samples per pixel in each direction that can be
+asked for with `ImageDims::oversample`.
+*/
+pub const MAX_OVERSAMPLE: usize = 4;
+// The size of the stack-allocated array used for averaging samples.
+const SAMPLE_PALETTE_SIZE: usize = MAX_OVERSAMPLE * MAX_OVERSAMPLE;
+/** The most times a `ColorSpec` can run through its palette. */
+pub const MAX_REPEATS: usize = 1000;
+
+// The D65 white point, in CIE XYZ coordinates.
+const D65: [f64; 3] = [0.95047, 1.0, 1.08883];
+// Constants from the CIELAB definition: (6/29)^3 and (29/3)^3.
+const LAB_EPSILON: f64 = 216.0 / 24389.0;
+const LAB_KAPPA: f64 = 24389.0 / 27.0;
 
 /**
 Represents a color with red, green, and blue components as floating-point
@@ -97,6 +151,14 @@ impl RGB {
         [self.r as u8, self.g as u8, self.b as u8]
     }
 
+    /**
+    Convert to 16-bit `[R, G, B]` values (where 65535 is full brightness),
+    keeping the fractions that `to_rgb8()` throws away.
+    */
+    pub fn to_rgb16(&self) -> [u16; 3] {
+        [self.r, self.g, self.b].map(|x| (x * 257.0).round().clamp(0.0, 65535.0) as u16)
+    }
+
     /** Average a slice of color values. */
     pub fn average(colors: &[RGB]) -> RGB {
         let (mut rtot, mut gtot, mut btot): (f32, f32, f32) = (0.0, 0.0, 0.0);
@@ -111,6 +173,131 @@ impl RGB {
         RGB::new(rtot / nf, gtot / nf, btot / nf)
     }
 
+    /**
+    Return the color's hue (in degrees, from 0 up to 360), saturation,
+    and value (both from 0 to 1). Grays all have a hue of 0.
+    */
+    pub fn to_hsv(&self) -> (f32, f32, f32) {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let delta = max - min;
+        if delta <= 0.0 {
+            return (0.0, 0.0, max / 255.0);
+        }
+
+        let sector = if max == self.r {
+            ((self.g - self.b) / delta).rem_euclid(6.0)
+        } else if max == self.g {
+            ((self.b - self.r) / delta) + 2.0
+        } else {
+            ((self.r - self.g) / delta) + 4.0
+        };
+        ((60.0 * sector) % 360.0, delta / max, max / 255.0)
+    }
+
+    /**
+    Return the color's CIELAB coordinates: lightness (from 0 to 100), and
+    the green–red and blue–yellow axes, taking the RGB values as sRGB and
+    using the D65 white point. Equal distances in this space look like
+    about equal differences in color.
+    */
+    pub fn to_lab(&self) -> (f32, f32, f32) {
+        let lin = |c: f32| {
+            let c = (c as f64) / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        let (r, g, b) = (lin(self.r), lin(self.g), lin(self.b));
+        let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / D65[0];
+        let y = (0.2126729 * r + 0.7151522 * g + 0.0721750 * b) / D65[1];
+        let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / D65[2];
+
+        let f = |t: f64| {
+            if t > LAB_EPSILON {
+                t.cbrt()
+            } else {
+                (LAB_KAPPA * t + 16.0) / 116.0
+            }
+        };
+        let (fx, fy, fz) = (f(x), f(y), f(z));
+        (
+            (116.0 * fy - 16.0) as f32,
+            (500.0 * (fx - fy)) as f32,
+            (200.0 * (fy - fz)) as f32,
+        )
+    }
+
+    /**
+    Make a color from CIELAB coordinates (see `to_lab()`). Plenty of Lab
+    colors can't be shown on a screen; those get clamped to the nearest
+    RGB values.
+    */
+    pub fn from_lab(l: f32, a: f32, b: f32) -> RGB {
+        let fy = ((l as f64) + 16.0) / 116.0;
+        let fx = fy + (a as f64) / 500.0;
+        let fz = fy - (b as f64) / 200.0;
+        let finv = |f: f64| {
+            if f * f * f > LAB_EPSILON {
+                f * f * f
+            } else {
+                (116.0 * f - 16.0) / LAB_KAPPA
+            }
+        };
+        let (x, y, z) = (finv(fx) * D65[0], finv(fy) * D65[1], finv(fz) * D65[2]);
+
+        let r = 3.2404542 * x - 1.5371385 * y - 0.4985314 * z;
+        let g = -0.9692660 * x + 1.8760108 * y + 0.0415560 * z;
+        let b = 0.0556434 * x - 0.2040259 * y + 1.0572252 * z;
+        let gamma = |c: f64| {
+            let c = if c <= 0.0031308 {
+                12.92 * c
+            } else {
+                1.055 * c.powf(1.0 / 2.4) - 0.055
+            };
+            (255.0 * c) as f32
+        };
+        RGB::new(gamma(r), gamma(g), gamma(b))
+    }
+
+    /**
+    Return the color's CIELCh coordinates: the lightness from `to_lab()`,
+    chroma (distance from gray), and hue (in degrees, from 0 up to 360).
+    */
+    pub fn to_lch(&self) -> (f32, f32, f32) {
+        let (l, a, b) = self.to_lab();
+        (l, a.hypot(b), b.atan2(a).to_degrees().rem_euclid(360.0))
+    }
+
+    /** Make a color from CIELCh coordinates (see `to_lch()`). */
+    pub fn from_lch(l: f32, c: f32, h: f32) -> RGB {
+        let h = h.to_radians();
+        RGB::from_lab(l, c * h.cos(), c * h.sin())
+    }
+
+    /**
+    Make a color from a hue (in degrees; any value will be wrapped around
+    into the range 0 to 360), saturation, and value (both from 0 to 1).
+    */
+    pub fn from_hsv(hue: f32, sat: f32, val: f32) -> RGB {
+        let hue = hue.rem_euclid(360.0) / 60.0;
+        let (sat, val) = (sat.clamp(0.0, 1.0), 255.0 * val.clamp(0.0, 1.0));
+        let chroma = val * sat;
+        let x = chroma * (1.0 - ((hue % 2.0) - 1.0).abs());
+        let (r, g, b) = match hue as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = val - chroma;
+        RGB::new(r + m, g + m, b + m)
+    }
+
     pub const BLACK: RGB = RGB {
         r: 0.0,
         g: 0.0,
@@ -141,12 +328,41 @@ impl Into<[f32; 3]> for RGB {
     }
 }
 
+/** Return the number of threads iteration is spread across. */
+pub fn iteration_threads() -> usize {
+    *N_THREADS
+}
+
+/**
+Spread iteration across `n` threads instead of one per CPU core. This only
+works before anything has been iterated; after that, the threads have
+already been started.
+*/
+pub fn set_iteration_threads(n: usize) {
+    THREADS_WANTED.store(n, Ordering::Relaxed);
+}
+
 /**
 Represents a mapping from the pixels of an image to a view of the
 complex plane. `xpix` and `ypix` are the dimensions of the image in pixels,
 (`x`, `y`) is the location of the upper-left-hand corner of the image on
 the complex plane, and `width` is the horizontal size of the image on the
 complex plane.
+
+For anti-aliasing, each pixel can be the average of an `oversample` by
+`oversample` grid of sample points spread evenly across it. An
+`oversample` of 1 (the default, for parameters saved before this existed)
+means one sample per pixel, at its upper-left corner. If `adaptive` is
+set, only pixels on the edges between different iteration counts get the
+full grid of samples; the rest just get the one.
+
+With `check_period` set (the default), the Mandlebrot and polynomial
+iterators notice when a point has settled into a cycle (which means it's
+never going to diverge) and give up on it right away instead of iterating
+it all the way to the limit, which speeds up images with a lot of
+"inside" in them a _lot_. The catch is that a point that just passes very
+close to where it's been before can be mistaken for a cycling one, so a
+few iteration counts may come out differently than with it off.
 */
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ImageDims {
@@ -155,9 +371,52 @@ pub struct ImageDims {
     pub x: f64,
     pub y: f64,
     pub width: f64,
+    #[serde(default = "ImageDims::no_oversample")]
+    pub oversample: usize,
+    #[serde(default)]
+    pub adaptive: bool,
+    #[serde(
+        default = "ImageDims::checked",
+        skip_serializing_if = "ImageDims::is_checked"
+    )]
+    pub check_period: bool,
 }
 
 impl ImageDims {
+    fn no_oversample() -> usize {
+        1
+    }
+    fn checked() -> bool {
+        true
+    }
+    // So dims that check for periodic orbits, as usual, don't say so.
+    fn is_checked(b: &bool) -> bool {
+        *b
+    }
+
+    /**
+    Return the number of samples per pixel in each direction, forced into
+    the range 1 to `MAX_OVERSAMPLE`.
+    */
+    pub fn samples(&self) -> usize {
+        self.oversample.clamp(1, MAX_OVERSAMPLE)
+    }
+
+    /**
+    Return a view of the same region with a pixel for every sample point
+    of this one.
+    */
+    pub fn sample_dims(&self) -> ImageDims {
+        let s = self.samples();
+        ImageDims {
+            xpix: self.xpix * s,
+            ypix: self.ypix * s,
+            oversample: 1,
+            adaptive: false,
+            ..*self
+        }
+    }
+
     /** Return the vertical size of the image on the complex plane. */
     pub fn height(&self) -> f64 {
         self.width * (self.ypix as f64) / (self.xpix as f64)
@@ -180,6 +439,9 @@ impl ImageDims {
             x: n_x,
             y: n_y,
             width: n_w,
+            oversample: self.oversample,
+            adaptive: self.adaptive,
+            check_period: self.check_period,
         }
     }
 
@@ -203,6 +465,9 @@ impl ImageDims {
                 x: n_x,
                 y: self.y,
                 width: new_w,
+                oversample: self.oversample,
+                adaptive: self.adaptive,
+                check_period: self.check_period,
             }
         } else {
             let new_h = self.width / new_aspect;
@@ -213,34 +478,143 @@ impl ImageDims {
                 x: self.x,
                 y: n_y,
                 width: self.width,
+                oversample: self.oversample,
+                adaptive: self.adaptive,
+                check_period: self.check_period,
             }
         }
     }
 
+    /**
+    Return a view of (very nearly) the same region with `1/factor` as many
+    pixels in each direction. Pixel (_i_, _j_) of the new view lands on
+    exactly the same point of the plane as pixel (_factor_ × _i_,
+    _factor_ × _j_) of this one.
+
+    This is used for quick, low-resolution previews, so the new view isn't
+    oversampled.
+    */
+    pub fn shrink(&self, factor: usize) -> ImageDims {
+        let factor = factor.max(1);
+        let xpix = self.xpix.div_ceil(factor);
+        let ypix = self.ypix.div_ceil(factor);
+        let width = self.width * ((xpix * factor) as f64) / (self.xpix as f64);
+        ImageDims {
+            xpix,
+            ypix,
+            x: self.x,
+            y: self.y,
+            width,
+            oversample: 1,
+            adaptive: false,
+            check_period: self.check_period,
+        }
+    }
+
     /**
     Return a new view with the center at new specified position:
     `x_frac` of the way across the image, `y_frac` of the way down it.
     */
     pub fn recenter(&self, x_frac: f64, y_frac: f64) -> ImageDims {
-        let (xof, yof) = (x_frac - 0.5, y_frac - 0.5);
-        let (xo, yo) = (xof * self.width, yof * self.height());
-        let (nx, ny) = (self.x + xo, self.y - yo);
+        let (c_x, c_y) = mapping::frac_to_plane(self, x_frac, y_frac);
         ImageDims {
             xpix: self.xpix,
             ypix: self.ypix,
-            x: nx,
-            y: ny,
+            x: c_x - self.width / 2.0,
+            y: c_y + self.height() / 2.0,
             width: self.width,
+            oversample: self.oversample,
+            adaptive: self.adaptive,
+            check_period: self.check_period,
+        }
+    }
+
+    /**
+    Return a view (with the same pixel dimensions) of the rectangle with
+    corners (`x0_frac`, `y0_frac`) and (`x1_frac`, `y1_frac`), given as
+    fractions of the way across and down this one. The rectangle won't
+    generally have the same shape as the image, so the view takes in a
+    little extra on two sides.
+    */
+    pub fn zoom_to(&self, x0_frac: f64, y0_frac: f64, x1_frac: f64, y1_frac: f64) -> ImageDims {
+        let (x0, y0) = mapping::frac_to_plane(self, x0_frac, y0_frac);
+        let (x1, y1) = mapping::frac_to_plane(self, x1_frac, y1_frac);
+        self.framing(
+            (x0 + x1) / 2.0,
+            (y0 + y1) / 2.0,
+            (x1 - x0).abs(),
+            (y1 - y0).abs(),
+        )
+    }
+
+    /**
+    Return a view with the same pixel dimensions (and sampling), centered
+    on (`c_x`, `c_y`), and just big enough to take in a `width` by `height`
+    region of the plane.
+    */
+    pub fn framing(&self, c_x: f64, c_y: f64, width: f64, height: f64) -> ImageDims {
+        let aspect = (self.xpix as f64) / (self.ypix as f64);
+        let new_w = width.max(height * aspect);
+        let new_h = new_w / aspect;
+        ImageDims {
+            x: c_x - new_w / 2.0,
+            y: c_y + new_h / 2.0,
+            width: new_w,
+            ..*self
+        }
+    }
+
+    /**
+    Return the view of just the `n_rows` rows of this one starting at row
+    `start`. Rendering each of a series of these and stacking the results
+    gives (floating-point rounding aside) the same image as rendering this
+    view, unless it's adaptively oversampled, in which case the pixels at
+    the edges of the bands can come out differently; `IterMap::with_rows()`
+    iterates a band as part of the whole view instead.
+    */
+    pub fn rows(&self, start: usize, n_rows: usize) -> ImageDims {
+        ImageDims {
+            ypix: n_rows,
+            y: mapping::pixel_y(self, start),
+            ..*self
         }
     }
 }
 
+/**
+How a `Gradient` gets from its start color to its end color.
+
+`Rgb` goes in a straight line through RGB space, which passes through
+washed-out grays between colors of very different hues. The other two go
+through hue, saturation, and value instead, so the colors in between
+stay as vivid as the ends: `Hsv` goes the short way around the color
+wheel, and `HsvLong` the long way (all the way around, if the start and
+end have the same hue).
+
+Neither of those changes at an even pace to the eye, though; a long RGB
+gradient can have a muddy middle, and an HSV one bright bands around
+yellow and cyan. `Lab` goes in a straight line through CIELAB space, and
+`Lch` the short way around the hues of CIELCh, both of which are built so
+that equal steps look about equally different.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interpolation {
+    #[default]
+    Rgb,
+    Hsv,
+    HsvLong,
+    Lab,
+    Lch,
+}
+
 /** Specifies a single gradient in a `ColorMap`. */
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Gradient {
     pub steps: usize,
     pub start: RGB,
     pub end: RGB,
+    #[serde(default)]
+    pub space: Interpolation,
 }
 
 impl Default for Gradient {
@@ -249,174 +623,1236 @@ impl Default for Gradient {
             start: RGB::BLACK,
             end: RGB::WHITE,
             steps: 256,
+            space: Interpolation::Rgb,
+        }
+    }
+}
+
+impl Gradient {
+    /** Return the same gradient going the other way. */
+    pub fn reversed(&self) -> Gradient {
+        Gradient {
+            start: self.end,
+            end: self.start,
+            ..*self
+        }
+    }
+
+    /** Return the color `frac` (from 0 to 1) of the way from start to end. */
+    pub fn color_at(&self, frac: f32) -> RGB {
+        let (s, e) = (self.start, self.end);
+        let lerp = |a: f32, b: f32| a + frac * (b - a);
+        match self.space {
+            Interpolation::Rgb => RGB::new(lerp(s.r, e.r), lerp(s.g, e.g), lerp(s.b, e.b)),
+            Interpolation::Hsv | Interpolation::HsvLong => {
+                let (h0, s0, v0) = s.to_hsv();
+                let (h1, s1, v1) = e.to_hsv();
+                let long = self.space == Interpolation::HsvLong;
+                let (h0, dh) = hue_path((h0, s0 > 0.0), (h1, s1 > 0.0), long);
+                RGB::from_hsv(h0 + frac * dh, lerp(s0, s1), lerp(v0, v1))
+            }
+            Interpolation::Lab => {
+                let (l0, a0, b0) = s.to_lab();
+                let (l1, a1, b1) = e.to_lab();
+                RGB::from_lab(lerp(l0, l1), lerp(a0, a1), lerp(b0, b1))
+            }
+            Interpolation::Lch => {
+                let (l0, c0, h0) = s.to_lch();
+                let (l1, c1, h1) = e.to_lch();
+                let (h0, dh) = hue_path((h0, c0 > GRAY_CHROMA), (h1, c1 > GRAY_CHROMA), false);
+                RGB::from_lch(lerp(l0, l1), lerp(c0, c1), h0 + frac * dh)
+            }
         }
     }
 }
 
+// Colors with less CIELCh chroma than this are (to the eye) gray.
+const GRAY_CHROMA: f32 = 0.5;
+
+/*
+Return the hue a gradient between hues `from` and `to` should start at,
+and how far around the color wheel (in degrees, either way) it should go.
+The `bool`s say whether each end has a hue at all; a gray doesn't really,
+so it borrows the other end's rather than dragging the gradient through
+red.
+*/
+fn hue_path(from: (f32, bool), to: (f32, bool), long: bool) -> (f32, f32) {
+    let (h0, h1) = match (from.1, to.1) {
+        (false, true) => (to.0, to.0),
+        (true, false) => (from.0, from.0),
+        _ => (from.0, to.0),
+    };
+    let mut dh = (h1 - h0).rem_euclid(360.0);
+    if dh > 180.0 {
+        dh -= 360.0;
+    }
+    if long {
+        dh = if dh < 0.0 { dh + 360.0 } else { dh - 360.0 };
+    }
+    (h0, dh)
+}
+
 /**
-Specifies a `ColorMap`.
+How iteration counts are spread across the colors of a `ColorMap`.
 
-A `ColorMap` is relatively large and requires some computation to
-produce; this is a much lighter struct to generate/retain/compare.
-An actual `ColorMap` can be produced when needed.
+With the default `Linear` mapping, a point that takes _n_ iterations to
+escape gets the _n_th color. Most of the interesting detail is in points
+that take a long time to escape, though, which all get crammed into the
+last few colors. The other mappings give more of the palette to higher
+counts: a count that's a fraction _x_ of the way to the limit gets the
+color that's _f(x)_ of the way through the palette, where _f(x)_ is
+log(1 + _nx_)/log(1 + _n_) (for a map of _n_ colors), √_x_, or _x_ to
+some power.
 */
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct ColorSpec {
-    length: usize,
-    default: RGB,
-    gradients: Vec<Gradient>,
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Transfer {
+    #[default]
+    Linear,
+    Log,
+    Sqrt,
+    Power {
+        exponent: f64,
+    },
 }
 
-impl ColorSpec {
-    /** Collect some `Gradient`s and a default color together to produce
-    a spec. */
-    pub fn new(gradients: Vec<Gradient>, default: RGB) -> ColorSpec {
-        let length = gradients.iter().map(|g| g.steps).sum();
+impl Transfer {
+    /** The smallest exponent a `Power` mapping can have. */
+    pub const MIN_EXPONENT: f64 = 0.05;
+    /** The largest exponent a `Power` mapping can have. */
+    pub const MAX_EXPONENT: f64 = 20.0;
 
-        ColorSpec {
-            length,
-            default,
-            gradients,
+    /**
+    Return the index of the color in a map of `len` colors that a point
+    taking `n` iterations to escape should get. This is always less than
+    `len` if `n` is.
+    */
+    pub fn index(&self, n: usize, len: usize) -> usize {
+        if n >= len {
+            return n;
         }
+        let (x, lenf) = ((n as f64) / (len as f64), len as f64);
+        let frac = match *self {
+            Transfer::Linear => return n,
+            Transfer::Log => (n as f64).ln_1p() / lenf.ln_1p(),
+            Transfer::Sqrt => x.sqrt(),
+            Transfer::Power { exponent: p } => {
+                let p = if p.is_finite() {
+                    p.clamp(Transfer::MIN_EXPONENT, Transfer::MAX_EXPONENT)
+                } else {
+                    1.0
+                };
+                x.powf(p)
+            }
+        };
+        ((frac * lenf) as usize).min(len - 1)
     }
+}
 
-    /** Return the number of steps the resultant `ColorMap` will have */
-    pub fn len(&self) -> usize {
-        self.length
+/**
+Tone adjustments made to an image after it's been colored (and its
+samples averaged), so the output can be tuned without touching the
+palette.
+
+Each channel, taken as a fraction _x_ of full brightness, first has its
+gamma corrected (to _x_^(1/`gamma`), so a `gamma` over 1 lightens the
+midtones), then its contrast stretched by a factor of `contrast` around
+the middle, and then `brightness` added.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Adjustment {
+    pub gamma: f64,
+    pub brightness: f64,
+    pub contrast: f64,
+}
+
+impl Default for Adjustment {
+    fn default() -> Self {
+        Adjustment::NONE
     }
-    /**
-    Return whether the `ColorMap` will have zero steps.
+}
 
-    Honestly, this is just to satisfy Clippy.
-    */
-    pub fn is_empty(&self) -> bool {
-        self.length == 0
+impl Adjustment {
+    /** The adjustment that leaves everything alone. */
+    pub const NONE: Adjustment = Adjustment {
+        gamma: 1.0,
+        brightness: 0.0,
+        contrast: 1.0,
+    };
+    /** The range `gamma` is forced into. */
+    pub const GAMMA_RANGE: (f64, f64) = (0.1, 10.0);
+    /** The range `brightness` is forced into. */
+    pub const BRIGHTNESS_RANGE: (f64, f64) = (-1.0, 1.0);
+    /** The range `contrast` is forced into. */
+    pub const CONTRAST_RANGE: (f64, f64) = (0.0, 10.0);
+
+    /** Return whether this adjustment leaves everything alone. */
+    pub fn is_none(&self) -> bool {
+        *self == Adjustment::NONE
     }
 
-    /** Do the work to turn me into an actual `ColorMap`. */
-    pub fn to_map(self) -> ColorMap {
-        ColorMap::make(self)
+    /**
+    Return this adjustment with each value forced into its range (and any
+    that aren't numbers at all set to leave things alone).
+    */
+    pub fn clamped(&self) -> Adjustment {
+        let clamp = |x: f64, (lo, hi): (f64, f64), none: f64| {
+            if x.is_finite() {
+                x.clamp(lo, hi)
+            } else {
+                none
+            }
+        };
+        Adjustment {
+            gamma: clamp(self.gamma, Adjustment::GAMMA_RANGE, 1.0),
+            brightness: clamp(self.brightness, Adjustment::BRIGHTNESS_RANGE, 0.0),
+            contrast: clamp(self.contrast, Adjustment::CONTRAST_RANGE, 1.0),
+        }
     }
 
-    pub fn default(&self) -> RGB {
-        self.default
+    /** Return the color `c` adjusted. */
+    pub fn apply(&self, c: RGB) -> RGB {
+        let a = self.clamped();
+        let (inv_gamma, brightness, contrast) =
+            (1.0 / a.gamma as f32, a.brightness as f32, a.contrast as f32);
+        let f = |x: f32| {
+            let x = (x / 255.0).powf(inv_gamma);
+            255.0 * ((x - 0.5) * contrast + 0.5 + brightness)
+        };
+        RGB::new(f(c.r), f(c.g), f(c.b))
     }
-    pub fn gradients(self) -> Vec<Gradient> {
-        self.gradients
+
+    /** Adjust every color in `colors`. */
+    pub fn apply_all(&self, colors: &mut [RGB]) {
+        if self.is_none() {
+            return;
+        }
+        for c in colors.iter_mut() {
+            *c = self.apply(*c);
+        }
     }
 }
 
 /**
-The `ColorMap` holds the vector of colors required to turn an `IterMap`
-into a colored image. Its `.get(n)` method will return the `RGB` color
-that a point taking `n` iterations to diverge should be colored. (If
-`n` is greater than the length of the map, it will return the `default`.)
+Lighting that treats the iteration counts as the heights of a surface and
+shades the colored image as if that surface were lit from one direction,
+giving it an embossed, relief-map look.
+
+Each pixel's height is the log of its (averaged) count, so the detail
+near the set doesn't turn into cliffs. The light comes from `azimuth`
+degrees counterclockwise from the right of the image, `elevation`
+degrees above it; `depth` scales the heights (so steeper relief and
+harder shadows), and `strength` is how much the shading is mixed into
+the palette colors, from 0 (not at all) to 1. Flat parts keep their
+colors; slopes facing the light get brighter, and slopes facing away
+darker.
 */
-#[derive(Clone, Debug)]
-pub struct ColorMap {
-    default: RGB,
-    colors: Vec<RGB>,
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Shading {
+    pub azimuth: f64,
+    pub elevation: f64,
+    pub depth: f64,
+    pub strength: f64,
 }
 
-impl ColorMap {
-    /** Gobble up a `ColorSpec` and calculate the vector of colors. */
-    pub fn make(spec: ColorSpec) -> ColorMap {
-        let mut colors: Vec<RGB> = Vec::with_capacity(spec.length);
-        let default = spec.default;
-
-        for grad in spec.gradients.iter() {
-            let dr = grad.end.r - grad.start.r;
-            let dg = grad.end.g - grad.start.g;
-            let db = grad.end.b - grad.start.b;
-            let steps_f = grad.steps as f32;
-            for n in 0..grad.steps {
-                let frac = (n as f32) / steps_f;
-                let c = RGB::new(
-                    grad.start.r + frac * dr,
-                    grad.start.g + frac * dg,
-                    grad.start.b + frac * db,
-                );
-                colors.push(c);
-            }
+impl Default for Shading {
+    fn default() -> Self {
+        Shading {
+            azimuth: 135.0,
+            elevation: 45.0,
+            depth: 4.0,
+            strength: 0.75,
         }
-
-        ColorMap { colors, default }
     }
+}
 
-    /**
-    Return the total number of steps in the `ColorMap`.
-
-    This information is useful for constraining the iterator so it doesn't
-    run forever.
-    */
-    pub fn len(&self) -> usize {
-        self.colors.len()
-    }
+impl Shading {
+    /** The range `elevation` is forced into. */
+    pub const ELEVATION_RANGE: (f64, f64) = (5.0, 90.0);
+    /** The range `depth` is forced into. */
+    pub const DEPTH_RANGE: (f64, f64) = (0.0, 100.0);
+    /** The range `strength` is forced into. */
+    pub const STRENGTH_RANGE: (f64, f64) = (0.0, 1.0);
 
     /**
-    Return whether the `ColorMap` has zero steps.
-
-    This shuts Clippy up.
+    Return this shading with each value forced into its range (and any
+    that aren't numbers at all set to the default).
     */
-    pub fn is_empty(&self) -> bool {
-        self.colors.is_empty()
+    pub fn clamped(&self) -> Shading {
+        let d = Shading::default();
+        let clamp = |x: f64, (lo, hi): (f64, f64), none: f64| {
+            if x.is_finite() {
+                x.clamp(lo, hi)
+            } else {
+                none
+            }
+        };
+        Shading {
+            azimuth: if self.azimuth.is_finite() {
+                self.azimuth.rem_euclid(360.0)
+            } else {
+                d.azimuth
+            },
+            elevation: clamp(self.elevation, Shading::ELEVATION_RANGE, d.elevation),
+            depth: clamp(self.depth, Shading::DEPTH_RANGE, d.depth),
+            strength: clamp(self.strength, Shading::STRENGTH_RANGE, d.strength),
+        }
     }
 
     /**
-    Return the `RGB` color that a point requiring `n` steps to diverge
-    should be colored.
+    Shade `colors`, the rows of an image `xpix` pixels wide, by the
+    `heights` of the same pixels.
     */
-    pub fn get(&self, n: usize) -> RGB {
-        match self.colors.get(n) {
-            Some(c) => *c,
-            None => self.default,
+    pub fn apply(&self, xpix: usize, heights: &[f32], colors: &mut [RGB]) {
+        let s = self.clamped();
+        if xpix == 0 || s.strength == 0.0 {
+            return;
+        }
+        let (az, el) = (s.azimuth.to_radians(), s.elevation.to_radians());
+        let light = [
+            (el.cos() * az.cos()) as f32,
+            (el.cos() * az.sin()) as f32,
+            el.sin() as f32,
+        ];
+        let (depth, strength) = (s.depth as f32, s.strength as f32);
+        let ypix = heights.len().min(colors.len()) / xpix;
+        let h = |x: usize, y: usize| heights[y * xpix + x];
+
+        for y in 0..ypix {
+            let (up, down) = (y.saturating_sub(1), (y + 1).min(ypix - 1));
+            for x in 0..xpix {
+                let (left, right) = (x.saturating_sub(1), (x + 1).min(xpix - 1));
+                // Rows run down the image, but the light's angle is
+                // measured with up being positive.
+                let dx = depth * (h(right, y) - h(left, y)) / (right - left).max(1) as f32;
+                let dy = depth * (h(x, up) - h(x, down)) / (down - up).max(1) as f32;
+                let norm = (dx * dx + dy * dy + 1.0).sqrt();
+                let lit = ((-dx * light[0] - dy * light[1] + light[2]) / norm).max(0.0);
+                let f = 1.0 + strength * (lit / light[2] - 1.0);
+                let c = &mut colors[y * xpix + x];
+                *c = RGB::new(c.r * f, c.g * f, c.b * f);
+            }
         }
     }
 }
 
 /**
-And image with each pixels specified by a 32-bit floating-point `RGB`
-triplet.
+An orbit trap: a shape on the plane that points get colored by how close
+their orbits come to, rather than by how long they take to escape.
 
-This takes up a lot of space, but is a format conventient for making
-calculations.
+As each point is iterated, the distance of every step of its orbit from
+the trap is measured, and the closest it gets decides its color: an orbit
+that lands right on the trap gets the first color of the map, and one that
+never gets within `scale` of it gets the last, with the distances between
+spread evenly over the whole map (however many times it repeats). Every
+point gets a trap color, whether it escapes or not.
+
+The `center` is the point for a `Point` trap, the center of a `Circle`
+(of radius `radius`), and a point the (infinite) `Line` goes through, at
+an angle of `angle_pi` times _π_ from the real axis.
 */
-pub struct FImage32 {
-    dims: ImageDims,
-    data: Vec<RGB>,
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "shape")]
+pub enum Trap {
+    Point {
+        center: Cx,
+        scale: f64,
+    },
+    Line {
+        center: Cx,
+        angle_pi: f64,
+        scale: f64,
+    },
+    Circle {
+        center: Cx,
+        radius: f64,
+        scale: f64,
+    },
 }
 
-impl FImage32 {
-    pub fn xpix(&self) -> usize {
-        self.dims.xpix
-    }
-    pub fn ypix(&self) -> usize {
-        self.dims.ypix
+impl Trap {
+    /** The point the trap is placed around. */
+    pub fn center(&self) -> Cx {
+        match *self {
+            Trap::Point { center, .. } => center,
+            Trap::Line { center, .. } => center,
+            Trap::Circle { center, .. } => center,
+        }
     }
-    pub fn pixels(&self) -> &[RGB] {
-        &self.data
+    /** The distance from the trap that gets the last color. */
+    pub fn scale(&self) -> f64 {
+        match *self {
+            Trap::Point { scale, .. } => scale,
+            Trap::Line { scale, .. } => scale,
+            Trap::Circle { scale, .. } => scale,
+        }
     }
 
-    // Translate the color values directly to 8-bit RGB.
-    //
-    // This method is equivalent to calling `.to_rgb8_scaled(1)`, but requires
-    // a lot less calculation because we're not going through the song and
-    // dance of "averaging" squares of 1 by 1 pixels.
-    fn to_rgb8_full_resolution(&self) -> Vec<u8> {
-        let n_pix = self.dims.xpix * self.dims.ypix;
-        let mut rgb8_data: Vec<u8> = Vec::with_capacity(n_pix * 3);
-        for p in self.data.iter() {
-            for b in p.to_rgb8().iter() {
-                rgb8_data.push(*b);
+    /** Return how far `z` is from the trap. */
+    pub fn distance(&self, z: Cx) -> f64 {
+        match *self {
+            Trap::Point { center, .. } => (z + -center).r(),
+            Trap::Line {
+                center, angle_pi, ..
+            } => {
+                // Turn the line onto the real axis; what's left of the
+                // imaginary part is how far off it z is.
+                let turn = Cx::polar(1.0, -angle_pi * std::f64::consts::PI);
+                ((z + -center) * turn).im.abs()
             }
+            Trap::Circle { center, radius, .. } => ((z + -center).r() - radius).abs(),
         }
-
-        rgb8_data
     }
 
-    // Translate the color values to 8-bit RGB, but scaled down by a
-    // factor of 1/`ratio`. Each pixel value will be calculated by
-    // averaging a `ratio` by `ratio` square of pixels.
+    /**
+    Return the iteration count that stands in for an orbit that came
+    within `d` of the trap, when iterating to `limit`: `d` as a fraction of
+    the way to `scale`, times the limit, but always less than the limit.
+    */
+    pub fn count(&self, d: f64, limit: usize) -> usize {
+        let scale = self.scale();
+        let frac = if scale > 0.0 && d.is_finite() {
+            d / scale
+        } else {
+            1.0
+        };
+        fraction_count(frac, limit)
+    }
+}
+
+/**
+An average of something about every step of a point's orbit, to color it
+by instead of how long it takes to escape.
+
+`Stripe` is the "stripe average": ½ sin(`density` · arg _z_) + ½ for each
+_z_ in the orbit, which makes stripes radiating from the set, `density`
+of them to a turn.
+
+`Triangle` is the "triangle inequality average". Each step of an iterator
+adds a constant to something it's done to _z_, and the size of the sum
+falls somewhere between the smallest and largest the triangle inequality
+lets it be, given the sizes of the two parts; this averages where. Only
+the iterators that actually add a constant each step (everything but the
+exponential and trigonometric ones) give it anything to measure.
+
+Either way the average runs from 0 (the first color of the map) to 1 (the
+last), over the whole map however many times it repeats, and every point
+gets one, whether it escapes or not.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum OrbitStat {
+    Stripe { density: f64 },
+    Triangle,
+}
+
+impl OrbitStat {
+    // This step's contribution to the average, where `w` and `k` are the
+    // two parts (see `Stepper`) that sum to the new `z`, if it makes one.
+    fn term(&self, w: Cx, k: Cx, z: Cx) -> Option<f64> {
+        match *self {
+            OrbitStat::Stripe { density } => Some(0.5 * (density * z.im.atan2(z.re)).sin() + 0.5),
+            OrbitStat::Triangle => {
+                let (w, k) = (w.r(), k.r());
+                let (lo, hi) = ((w - k).abs(), w + k);
+                if hi > lo {
+                    Some((z.r() - lo) / (hi - lo))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/**
+What points get colored by, when it's not how long they take to escape:
+how close their orbits come to a `Trap`, or an `OrbitStat` averaged over
+their orbits.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Orbit {
+    Trap(Trap),
+    Stat(OrbitStat),
+}
+
+// Return the iteration count that stands in for `frac` of the way through
+// the map, when iterating to `limit`; always less than the limit.
+fn fraction_count(frac: f64, limit: usize) -> usize {
+    ((frac.max(0.0) * limit as f64) as usize).min(limit.saturating_sub(1))
+}
+
+/**
+How a `Layer`'s colors combine with the colors under it, the way the blend
+modes of the same names do in image editors: `Normal` just covers them,
+`Multiply` darkens them, `Screen` lightens them, and `Overlay` multiplies
+the dark ones and screens the light ones, which adds contrast.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Blend {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+}
+
+impl Blend {
+    pub const ALL: [Blend; 4] = [
+        Blend::Normal,
+        Blend::Multiply,
+        Blend::Screen,
+        Blend::Overlay,
+    ];
+
+    /** Return `top` blended over `base`, `opacity` (from 0 to 1) of the way. */
+    pub fn mix(&self, base: RGB, top: RGB, opacity: f32) -> RGB {
+        let f = |a: f32, b: f32| {
+            let (a, b) = (a / 255.0, b / 255.0);
+            let x = match self {
+                Blend::Normal => b,
+                Blend::Multiply => a * b,
+                Blend::Screen => 1.0 - (1.0 - a) * (1.0 - b),
+                Blend::Overlay if a < 0.5 => 2.0 * a * b,
+                Blend::Overlay => 1.0 - 2.0 * (1.0 - a) * (1.0 - b),
+            };
+            255.0 * (a + (x - a) * opacity)
+        };
+        RGB::new(f(base.r, top.r), f(base.g, top.g), f(base.b, top.b))
+    }
+}
+
+/**
+One more coloring of the same image, blended over the one its `ColorSpec`
+makes (and any layers before it). A layer has a whole `ColorSpec` of its
+own, so it can color by an orbit trap or statistic, or escape time, as it
+likes; a layer that colors by something other than what the image was
+iterated for needs counts of its own, which `IterMap::iterate_layers()`
+makes (once).
+*/
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Layer {
+    #[serde(default)]
+    pub blend: Blend,
+    /** From 0 (the layer doesn't show) to 1. */
+    #[serde(default = "Layer::opaque")]
+    pub opacity: f32,
+    pub spec: ColorSpec,
+}
+
+impl Layer {
+    // Layers are opaque unless they say otherwise.
+    fn opaque() -> f32 {
+        1.0
+    }
+}
+
+/**
+What colors points that escape, but only after more iterations than the
+palette (however many times it repeats) has colors for. Those come up when
+the spec says to iterate further than the palette goes (or when the
+palette has gotten shorter since the image was iterated).
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Overflow {
+    /// The interior color, the same as points that never escape.
+    #[default]
+    Interior,
+    /// The last color of the palette.
+    Clamp,
+    /// Back around through the palette, as if it repeated forever.
+    Wrap,
+    /// A color of its own.
+    Color { color: RGB },
+}
+
+impl Overflow {
+    // So specs without overflow don't say anything about it.
+    fn is_interior(&self) -> bool {
+        *self == Overflow::Interior
+    }
+}
+
+/**
+Specifies a `ColorMap`.
+
+A `ColorMap` is relatively large and requires some computation to
+produce; this is a much lighter struct to generate/retain/compare.
+An actual `ColorMap` can be produced when needed.
+*/
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "SavedColorSpec")]
+pub struct ColorSpec {
+    length: usize,
+    default: RGB,
+    repeats: usize,
+    // How far to iterate, if that's further than the palette goes.
+    #[serde(skip_serializing_if = "ColorSpec::is_zero")]
+    iterations: usize,
+    #[serde(skip_serializing_if = "ColorSpec::is_false")]
+    transparent: bool,
+    phase: f64,
+    transfer: Transfer,
+    #[serde(skip_serializing_if = "Overflow::is_interior")]
+    overflow: Overflow,
+    #[serde(skip_serializing_if = "Adjustment::is_none")]
+    adjust: Adjustment,
+    #[serde(skip_serializing_if = "Curves::is_identity")]
+    curves: Curves,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shading: Option<Shading>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trap: Option<Trap>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stat: Option<OrbitStat>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    layers: Vec<Layer>,
+    gradients: Vec<Gradient>,
+}
+
+/*
+A `ColorSpec` as it's read. Its `length` is saved alongside its gradients
+rather than worked out from them, and the two can disagree (in a file
+edited by hand, or a share string mangled on the way), which would leave
+colors missing or the map indexed past its end; so it's left out here,
+and the gradients decide it.
+*/
+#[derive(Deserialize)]
+struct SavedColorSpec {
+    default: RGB,
+    #[serde(default = "ColorSpec::no_repeats")]
+    repeats: usize,
+    #[serde(default)]
+    iterations: usize,
+    #[serde(default)]
+    transparent: bool,
+    #[serde(default)]
+    phase: f64,
+    #[serde(default)]
+    transfer: Transfer,
+    #[serde(default)]
+    overflow: Overflow,
+    #[serde(default)]
+    adjust: Adjustment,
+    #[serde(default)]
+    curves: Curves,
+    #[serde(default)]
+    shading: Option<Shading>,
+    #[serde(default)]
+    trap: Option<Trap>,
+    #[serde(default)]
+    stat: Option<OrbitStat>,
+    #[serde(default)]
+    layers: Vec<Layer>,
+    gradients: Vec<Gradient>,
+}
+
+impl From<SavedColorSpec> for ColorSpec {
+    fn from(saved: SavedColorSpec) -> ColorSpec {
+        ColorSpec {
+            length: saved.gradients.iter().map(|g| g.steps).sum(),
+            default: saved.default,
+            repeats: saved.repeats,
+            iterations: saved.iterations,
+            transparent: saved.transparent,
+            phase: saved.phase,
+            transfer: saved.transfer,
+            overflow: saved.overflow,
+            adjust: saved.adjust,
+            curves: saved.curves,
+            shading: saved.shading,
+            trap: saved.trap,
+            stat: saved.stat,
+            layers: saved.layers,
+            gradients: saved.gradients,
+        }
+    }
+}
+
+impl ColorSpec {
+    /** Collect some `Gradient`s and a default color together to produce
+    a spec. */
+    pub fn new(gradients: Vec<Gradient>, default: RGB) -> ColorSpec {
+        let length = gradients.iter().map(|g| g.steps).sum();
+
+        ColorSpec {
+            length,
+            default,
+            repeats: 1,
+            overflow: Overflow::Interior,
+            transparent: false,
+            iterations: 0,
+            phase: 0.0,
+            transfer: Transfer::Linear,
+            adjust: Adjustment::NONE,
+            curves: Curves::default(),
+            shading: None,
+            trap: None,
+            stat: None,
+            layers: Vec::new(),
+            gradients,
+        }
+    }
+
+    /**
+    Make a spec out of a list of colors, like from a palette file. If
+    `steps` is 1 (or 0), each color gets exactly one step; otherwise
+    there's a gradient of `steps` steps from each color to the next.
+    */
+    pub fn from_colors(colors: &[RGB], steps: usize, default: RGB) -> ColorSpec {
+        let gradients = if steps <= 1 || colors.len() < 2 {
+            colors
+                .iter()
+                .map(|c| Gradient {
+                    steps: 1,
+                    start: *c,
+                    end: *c,
+                    space: Interpolation::Rgb,
+                })
+                .collect()
+        } else {
+            colors
+                .windows(2)
+                .map(|w| Gradient {
+                    steps,
+                    start: w[0],
+                    end: w[1],
+                    space: Interpolation::Rgb,
+                })
+                .collect()
+        };
+        ColorSpec::new(gradients, default)
+    }
+
+    // For deserializing specs saved before palettes could repeat.
+    fn no_repeats() -> usize {
+        1
+    }
+    // For leaving out the iteration count when it's unset.
+    fn is_zero(n: &usize) -> bool {
+        *n == 0
+    }
+    // For leaving out switches that are off.
+    fn is_false(b: &bool) -> bool {
+        !*b
+    }
+
+    /** Return the number of steps the resultant `ColorMap` will have */
+    pub fn len(&self) -> usize {
+        self.length
+    }
+    /**
+    Return whether the `ColorMap` will have zero steps.
+
+    Honestly, this is just to satisfy Clippy.
+    */
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /** Do the work to turn me into an actual `ColorMap`. */
+    pub fn to_map(self) -> ColorMap {
+        ColorMap::make(self)
+    }
+
+    /** The color of points that never escape (the interior color). */
+    pub fn default(&self) -> RGB {
+        self.default
+    }
+    /**
+    Whether points that never escape are left transparent (in images that
+    can be), instead of getting the interior color.
+    */
+    pub fn transparent(&self) -> bool {
+        self.transparent
+    }
+    pub fn set_transparent(&mut self, transparent: bool) {
+        self.transparent = transparent;
+    }
+    /** What colors points that escape past the end of the palette. */
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+    /**
+    How many iterations to go before deciding a point doesn't escape, if
+    that's more than the palette has colors for (and 0, or anything less
+    than `palette_limit()`, otherwise).
+    */
+    pub fn iterations(&self) -> usize {
+        self.iterations
+    }
+    pub fn set_iterations(&mut self, iterations: usize) {
+        self.iterations = iterations;
+    }
+    pub fn transfer(&self) -> Transfer {
+        self.transfer
+    }
+    pub fn set_transfer(&mut self, transfer: Transfer) {
+        self.transfer = transfer;
+    }
+    /** How many times the palette is run through before the default color. */
+    pub fn repeats(&self) -> usize {
+        self.repeats
+    }
+    /** Will be forced into the range 1 to `MAX_REPEATS`. */
+    pub fn set_repeats(&mut self, repeats: usize) {
+        self.repeats = repeats.clamp(1, MAX_REPEATS);
+    }
+    /**
+    How far (as a fraction of its length) the palette is rotated, so the
+    first iteration count gets a color that far along.
+    */
+    pub fn phase(&self) -> f64 {
+        self.phase
+    }
+    /** Only the fractional part counts; NaNs and such count as 0. */
+    pub fn set_phase(&mut self, phase: f64) {
+        self.phase = if phase.is_finite() {
+            phase.rem_euclid(1.0)
+        } else {
+            0.0
+        };
+    }
+    /** The tone adjustments made to images after they're colored. */
+    pub fn adjustment(&self) -> Adjustment {
+        self.adjust
+    }
+    /** Values out of range will be forced into it. */
+    pub fn set_adjustment(&mut self, adjust: Adjustment) {
+        self.adjust = adjust.clamped();
+    }
+    /**
+    The tone curves applied to images after they're colored (after the
+    `Adjustment`).
+    */
+    pub fn curves(&self) -> &Curves {
+        &self.curves
+    }
+    pub fn set_curves(&mut self, curves: Curves) {
+        self.curves = curves;
+    }
+    /**
+    The relief lighting applied to images after they're colored (before
+    the `Adjustment`), if any.
+    */
+    pub fn shading(&self) -> Option<Shading> {
+        self.shading
+    }
+    /** Values out of range will be forced into it. */
+    pub fn set_shading(&mut self, shading: Option<Shading>) {
+        self.shading = shading.map(|s| s.clamped());
+    }
+    /**
+    The orbit trap or statistic points are colored by (see `Orbit`), or
+    `None` if they're colored by how long they take to escape. Unlike
+    everything else here, changing this means iterating the image again.
+    */
+    pub fn orbit(&self) -> Option<Orbit> {
+        match (self.trap, self.stat) {
+            (Some(trap), _) => Some(Orbit::Trap(trap)),
+            (None, Some(stat)) => Some(Orbit::Stat(stat)),
+            (None, None) => None,
+        }
+    }
+    /** The layers blended over the colors this makes, bottom first. */
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+    pub fn set_layers(&mut self, layers: Vec<Layer>) {
+        self.layers = layers;
+    }
+    pub fn set_orbit(&mut self, orbit: Option<Orbit>) {
+        (self.trap, self.stat) = match orbit {
+            Some(Orbit::Trap(trap)) => (Some(trap), None),
+            Some(Orbit::Stat(stat)) => (None, Some(stat)),
+            None => (None, None),
+        };
+    }
+    pub fn gradients(self) -> Vec<Gradient> {
+        self.gradients
+    }
+
+    /**
+    Return the spec with the whole palette running backward: the
+    gradients in the opposite order, and each one flipped around.
+    */
+    pub fn reversed(&self) -> ColorSpec {
+        self.with_gradients(self.gradients.iter().rev().map(|g| g.reversed()).collect())
+    }
+
+    /**
+    Return a spec with the given gradients instead of these, but the same
+    default color, mapping, repeats, and phase.
+    */
+    pub fn with_gradients(&self, gradients: Vec<Gradient>) -> ColorSpec {
+        ColorSpec {
+            length: gradients.iter().map(|g| g.steps).sum(),
+            gradients,
+            ..self.clone()
+        }
+    }
+
+    /**
+    Return the iteration limit this spec calls for: points that haven't
+    escaped after this many iterations get the interior color. This is the
+    palette limit, unless `iterations()` says to go further.
+    */
+    pub fn limit(&self) -> usize {
+        self.palette_limit().max(self.iterations)
+    }
+
+    /**
+    Return the number of iterations the palette has colors for: its length
+    times the number of times it repeats. Points that take longer than this
+    to escape get colored according to `overflow()`.
+    */
+    pub fn palette_limit(&self) -> usize {
+        self.length * self.repeats.clamp(1, MAX_REPEATS)
+    }
+
+    /**
+    Return the range of positions in the palette taken up by the `n`th
+    gradient, or `None` if there isn't one.
+    */
+    pub fn gradient_range(&self, n: usize) -> Option<Range<usize>> {
+        let g = self.gradients.get(n)?;
+        let start: usize = self.gradients[..n].iter().map(|g| g.steps).sum();
+        Some(start..(start + g.steps))
+    }
+
+    // The number of steps the palette is rotated by `phase`.
+    fn phase_steps(&self) -> usize {
+        let steps = (self.phase.rem_euclid(1.0) * self.length as f64).round() as usize;
+        steps.checked_rem(self.length).unwrap_or(0)
+    }
+
+    /**
+    Return the position in the palette of the color given to a point that
+    takes `count` iterations to escape (after any `Transfer` mapping,
+    repeating, and rotation), or `None` if it's past the end of the palette.
+    */
+    pub fn color_index(&self, count: usize) -> Option<usize> {
+        if count >= self.palette_limit() {
+            return None;
+        }
+        let n = self.transfer.index(count % self.length, self.length);
+        Some((n + self.phase_steps()) % self.length)
+    }
+
+    /**
+    Return which gradient colors a point that takes `count` iterations to
+    escape, or `None` if it gets the default color.
+    */
+    pub fn gradient_at(&self, count: usize) -> Option<usize> {
+        let mut n = self.color_index(count)?;
+        for (i, g) in self.gradients.iter().enumerate() {
+            if n < g.steps {
+                return Some(i);
+            }
+            n -= g.steps;
+        }
+        None
+    }
+}
+
+/**
+The `ColorMap` holds the vector of colors required to turn an `IterMap`
+into a colored image. Its `.get(n)` method will return the `RGB` color
+that a point taking `n` iterations to diverge should be colored. (If
+`n` is at least the map's `limit()`, it will return the `default`.)
+
+The spec's `Transfer` mapping and phase are baked into the vector of
+colors, so `.get(n)` doesn't have to do much math.
+*/
+#[derive(Clone, Debug)]
+pub struct ColorMap {
+    default: RGB,
+    colors: Vec<RGB>,
+    limit: usize,
+    adjust: Adjustment,
+    curves: Option<CurveTables>,
+    layers: Vec<LayerMap>,
+    palette_limit: usize,
+    overflow: Overflow,
+    transparent: bool,
+    shading: Option<Shading>,
+}
+
+// A `Layer`, made: what `IterMap::color()` needs to color it and blend it in.
+#[derive(Clone, Debug)]
+struct LayerMap {
+    map: ColorMap,
+    orbit: Option<Orbit>,
+    blend: Blend,
+    opacity: f32,
+}
+
+impl ColorMap {
+    /** Gobble up a `ColorSpec` and calculate the vector of colors. */
+    pub fn make(spec: ColorSpec) -> ColorMap {
+        let mut colors: Vec<RGB> = Vec::with_capacity(spec.length);
+        let default = spec.default;
+
+        for grad in spec.gradients.iter() {
+            let steps_f = grad.steps as f32;
+            for n in 0..grad.steps {
+                colors.push(grad.color_at((n as f32) / steps_f));
+            }
+        }
+        // Reading a spec makes its length match its gradients (see
+        // `SavedColorSpec`), but if they ever disagree, the colors that
+        // aren't there get the default rather than taking down the program.
+        if spec.transfer != Transfer::Linear || spec.phase_steps() != 0 {
+            colors = (0..colors.len())
+                .map(|n| {
+                    spec.color_index(n)
+                        .and_then(|i| colors.get(i))
+                        .copied()
+                        .unwrap_or(default)
+                })
+                .collect();
+        }
+
+        ColorMap {
+            colors,
+            default,
+            limit: spec.limit(),
+            palette_limit: spec.palette_limit(),
+            overflow: spec.overflow,
+            transparent: spec.transparent,
+            shading: spec.shading,
+            adjust: spec.adjust,
+            curves: spec.curves.tables(),
+            layers: spec
+                .layers
+                .iter()
+                .map(|l| LayerMap {
+                    map: ColorMap::make(l.spec.clone()),
+                    orbit: l.spec.orbit(),
+                    blend: l.blend,
+                    opacity: l.opacity.clamp(0.0, 1.0),
+                })
+                .collect(),
+        }
+    }
+
+    /** Return the total number of steps in the `ColorMap`. */
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    /**
+    Return the number of iterations after which a point gets the interior
+    color. (This is the length of the map, unless it repeats, or the spec
+    says to iterate further.)
+
+    This information is useful for constraining the iterator so it doesn't
+    run forever.
+    */
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /**
+    Return whether the `ColorMap` has zero steps.
+
+    This shuts Clippy up.
+    */
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    /**
+    Make the tone adjustments (and apply the curves) that images colored
+    with this map get once they've been colored. (The colors the map
+    returns aren't adjusted.) Any shading has to be done before this,
+    because it needs the iteration counts.
+    */
+    pub fn finish(&self, colors: &mut [RGB]) {
+        self.adjust.apply_all(colors);
+        if let Some(curves) = &self.curves {
+            curves.apply_all(colors);
+        }
+    }
+
+    /**
+    Return the colors of one run through the map, one for each step (with
+    the mapping and phase already applied).
+    */
+    pub fn colors(&self) -> &[RGB] {
+        &self.colors
+    }
+
+    /**
+    Return `n` colors spaced evenly from zero iterations up to the limit:
+    the whole map, however many times it repeats, squeezed into `n` steps.
+    */
+    pub fn sample(&self, n: usize) -> Vec<RGB> {
+        (0..n).map(|i| self.get(i * self.limit / n)).collect()
+    }
+
+    /**
+    Return the `RGB` color that a point requiring `n` steps to diverge
+    should be colored.
+    */
+    pub fn get(&self, n: usize) -> RGB {
+        if n >= self.limit || self.colors.is_empty() {
+            return self.default;
+        }
+        let n = if n < self.palette_limit {
+            n
+        } else {
+            match self.overflow {
+                Overflow::Interior => return self.default,
+                Overflow::Clamp => self.palette_limit - 1,
+                Overflow::Wrap => n,
+                Overflow::Color { color } => return color,
+            }
+        };
+        self.colors[n % self.colors.len()]
+    }
+
+    // Like `get()`, but `None` for points that never escape, if they're to
+    // be left transparent.
+    fn get_opaque(&self, n: usize) -> Option<RGB> {
+        if self.transparent && n >= self.limit {
+            None
+        } else {
+            Some(self.get(n))
+        }
+    }
+}
+
+/**
+A rectangle of pixels: `w` × `h` pixels with the upper-left one at
+column `x`, row `y`.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelRect {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
+impl PixelRect {
+    pub fn new(x: usize, y: usize, w: usize, h: usize) -> PixelRect {
+        PixelRect { x, y, w, h }
+    }
+
+    /** Return the part of this rectangle that's inside an `xpix` × `ypix` image. */
+    pub fn clip(&self, xpix: usize, ypix: usize) -> PixelRect {
+        let x = self.x.min(xpix);
+        let y = self.y.min(ypix);
+        PixelRect {
+            x,
+            y,
+            w: self.w.min(xpix - x),
+            h: self.h.min(ypix - y),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.w == 0 || self.h == 0
+    }
+}
+
+// For each of `n` pixels resampled from a row of `src` pixels, the pixels
+// of the row it covers, and how much each counts toward it (adding up to 1).
+fn coverage(src: usize, n: usize) -> Vec<Vec<(usize, f32)>> {
+    let step = src as f64 / n as f64;
+    (0..n)
+        .map(|i| {
+            let (start, end) = (i as f64 * step, (i + 1) as f64 * step);
+            let first = start.floor() as usize;
+            let last = (end.ceil() as usize).min(src).max(first + 1);
+            (first..last)
+                .map(|k| {
+                    let overlap = end.min((k + 1) as f64) - start.max(k as f64);
+                    (k.min(src - 1), (overlap.max(0.0) / step) as f32)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/**
+And image with each pixels specified by a 32-bit floating-point `RGB`
+triplet, and optionally an opacity.
+
+This takes up a lot of space, but is a format conventient for making
+calculations.
+*/
+pub struct FImage32 {
+    dims: ImageDims,
+    data: Vec<RGB>,
+    // How opaque each pixel is (from 0 to 1), if they aren't all entirely.
+    // Transparent pixels still have colors, for where the opacity gets
+    // ignored.
+    alpha: Option<Vec<f32>>,
+}
+
+impl FImage32 {
+    /** Create an image of the given dimensions filled with one color. */
+    pub fn new(dims: ImageDims, color: RGB) -> FImage32 {
+        FImage32 {
+            dims,
+            data: vec![color; dims.xpix * dims.ypix],
+            alpha: None,
+        }
+    }
+
+    pub fn dims(&self) -> ImageDims {
+        self.dims
+    }
+    pub fn xpix(&self) -> usize {
+        self.dims.xpix
+    }
+    pub fn ypix(&self) -> usize {
+        self.dims.ypix
+    }
+    pub fn pixels(&self) -> &[RGB] {
+        &self.data
+    }
+    /**
+    How opaque each pixel is, from 0 to 1, or `None` if they all are
+    (which is the case unless the image was colored with a transparent
+    interior).
+    */
+    pub fn alpha(&self) -> Option<&[f32]> {
+        self.alpha.as_deref()
+    }
+
+    // Make sure there's an opacity for every pixel, to change some of them.
+    fn alpha_mut(&mut self) -> &mut Vec<f32> {
+        let n = self.data.len();
+        self.alpha.get_or_insert_with(|| vec![1.0; n])
+    }
+
+    // Translate the color values directly to 8-bit RGB.
+    //
+    // This method is equivalent to calling `.to_rgb8_scaled(1)`, but requires
+    // a lot less calculation because we're not going through the song and
+    // dance of "averaging" squares of 1 by 1 pixels.
+    fn to_rgb8_full_resolution(&self) -> Vec<u8> {
+        let n_pix = self.dims.xpix * self.dims.ypix;
+        let mut rgb8_data: Vec<u8> = Vec::with_capacity(n_pix * 3);
+        for p in self.data.iter() {
+            for b in p.to_rgb8().iter() {
+                rgb8_data.push(*b);
+            }
+        }
+
+        rgb8_data
+    }
+
+    // Translate the color values to 8-bit RGB, but scaled down by a
+    // factor of 1/`ratio`. Each pixel value will be calculated by
+    // averaging a `ratio` by `ratio` square of pixels.
     fn to_rgb8_scaled(&self, ratio: usize) -> (usize, usize, Vec<u8>) {
         let pix_lines = self.dims.ypix / ratio;
         let pix_cols = self.dims.xpix / ratio;
@@ -424,387 +1860,3454 @@ impl FImage32 {
         let mut rgb8_data: Vec<u8> = Vec::with_capacity(n_pix * 3);
         let mut palette: [RGB; SCALE_PALETTE_SIZE] = [RGB::BLACK; SCALE_PALETTE_SIZE];
 
-        for yi in 0..pix_lines {
-            let base_offs = yi * self.dims.xpix * ratio;
-            for xi in 0..pix_cols {
-                let offs = base_offs + (xi * ratio);
-                let mut pp = 0usize;
-                for y in 0..ratio {
-                    let po = offs + (self.dims.xpix * y);
-                    for x in 0..ratio {
-                        palette[pp] = self.data[po + x];
-                        pp += 1;
+        for yi in 0..pix_lines {
+            let base_offs = yi * self.dims.xpix * ratio;
+            for xi in 0..pix_cols {
+                let offs = base_offs + (xi * ratio);
+                let mut pp = 0usize;
+                for y in 0..ratio {
+                    let po = offs + (self.dims.xpix * y);
+                    for x in 0..ratio {
+                        palette[pp] = self.data[po + x];
+                        pp += 1;
+                    }
+                }
+                let avg_p = RGB::average(&palette[0..pp]);
+                for b in avg_p.to_rgb8().iter() {
+                    rgb8_data.push(*b);
+                }
+            }
+        }
+
+        (pix_cols, pix_lines, rgb8_data)
+    }
+
+    /**
+    Return a copy of the part of this image inside `rect` (or as much of it
+    as is actually inside the image), with its `dims` adjusted to cover
+    just that part of the plane. Returns `None` if `rect` misses the image
+    entirely.
+    */
+    pub fn crop(&self, rect: PixelRect) -> Option<FImage32> {
+        let rect = rect.clip(self.dims.xpix, self.dims.ypix);
+        if rect.is_empty() {
+            return None;
+        }
+        let dims = ImageDims {
+            xpix: rect.w,
+            ypix: rect.h,
+            x: mapping::pixel_x(&self.dims, rect.x),
+            y: mapping::pixel_y(&self.dims, rect.y),
+            width: self.dims.width * (rect.w as f64) / (self.dims.xpix as f64),
+            ..self.dims
+        };
+        let mut data: Vec<RGB> = Vec::with_capacity(rect.w * rect.h);
+        for row in self
+            .data
+            .chunks_exact(self.dims.xpix)
+            .skip(rect.y)
+            .take(rect.h)
+        {
+            data.extend_from_slice(&row[rect.x..(rect.x + rect.w)]);
+        }
+        let alpha = self.alpha.as_ref().map(|a| {
+            a.chunks_exact(self.dims.xpix)
+                .skip(rect.y)
+                .take(rect.h)
+                .flat_map(|row| row[rect.x..(rect.x + rect.w)].iter().copied())
+                .collect()
+        });
+
+        Some(FImage32 { dims, data, alpha })
+    }
+
+    /**
+    Return this image resampled to `xpix` × `ypix` pixels, covering the
+    same part of the plane. Each new pixel is the average of the part of
+    this image it covers, so shrinking by any amount (not just whole
+    factors, like `to_rgb8()` does) doesn't alias.
+    */
+    pub fn resample(&self, xpix: usize, ypix: usize) -> FImage32 {
+        let (xpix, ypix) = (xpix.max(1), ypix.max(1));
+        let (cols, rows) = (
+            coverage(self.dims.xpix, xpix),
+            coverage(self.dims.ypix, ypix),
+        );
+        let dims = ImageDims {
+            xpix,
+            ypix,
+            ..self.dims
+        };
+
+        // Each channel (and the opacity, if there is one), across the rows
+        // and then down the columns.
+        let resample = |values: &dyn Fn(usize) -> f32| -> Vec<f32> {
+            let mut wide: Vec<f32> = Vec::with_capacity(xpix * self.dims.ypix);
+            for y in 0..self.dims.ypix {
+                let row = y * self.dims.xpix;
+                for col in cols.iter() {
+                    wide.push(col.iter().map(|&(x, w)| w * values(row + x)).sum());
+                }
+            }
+            let mut out: Vec<f32> = Vec::with_capacity(xpix * ypix);
+            for row in rows.iter() {
+                for x in 0..xpix {
+                    out.push(row.iter().map(|&(y, w)| w * wide[y * xpix + x]).sum());
+                }
+            }
+            out
+        };
+        let channels: Vec<Vec<f32>> = (0..3)
+            .map(|c| {
+                resample(&|n: usize| {
+                    let px = &self.data[n];
+                    [px.r(), px.g(), px.b()][c]
+                })
+            })
+            .collect();
+        let data = (0..(xpix * ypix))
+            .map(|n| RGB::new(channels[0][n], channels[1][n], channels[2][n]))
+            .collect();
+        let alpha = self.alpha.as_ref().map(|a| resample(&|n: usize| a[n]));
+
+        FImage32 { dims, data, alpha }
+    }
+
+    /**
+    Copy `other` onto this image with its upper-left corner at column `x`,
+    row `y`. Any of `other` that hangs off the edge of this image is
+    ignored. Only pixels are copied; this image's `dims` don't change.
+    */
+    pub fn paste(&mut self, other: &FImage32, x: usize, y: usize) {
+        self.composite(other, x, y, 1.0);
+    }
+
+    /**
+    Like `paste()`, but mix `other` in with the given `opacity` (0.0 leaves
+    this image unchanged, 1.0 replaces its pixels entirely).
+    */
+    pub fn composite(&mut self, other: &FImage32, x: usize, y: usize, opacity: f32) {
+        let rect = PixelRect::new(x, y, other.dims.xpix, other.dims.ypix)
+            .clip(self.dims.xpix, self.dims.ypix);
+        if rect.is_empty() || opacity <= 0.0 {
+            return;
+        }
+        let opacity = opacity.min(1.0);
+        let xpix = self.dims.xpix;
+        if let Some(src_alpha) = other.alpha.as_ref() {
+            let rows = self
+                .alpha_mut()
+                .chunks_exact_mut(xpix)
+                .skip(rect.y)
+                .take(rect.h);
+            for (dest, src) in rows.zip(src_alpha.chunks_exact(other.dims.xpix)) {
+                for (d, s) in dest[rect.x..(rect.x + rect.w)].iter_mut().zip(src.iter()) {
+                    *d += opacity * (s - *d);
+                }
+            }
+        } else if let Some(alpha) = self.alpha.as_mut() {
+            for row in alpha.chunks_exact_mut(xpix).skip(rect.y).take(rect.h) {
+                for d in row[rect.x..(rect.x + rect.w)].iter_mut() {
+                    *d += opacity * (1.0 - *d);
+                }
+            }
+        }
+        let rows = self.data.chunks_exact_mut(xpix).skip(rect.y).take(rect.h);
+        for (dest, src) in rows.zip(other.data.chunks_exact(other.dims.xpix)) {
+            let dest = &mut dest[rect.x..(rect.x + rect.w)];
+            let src = &src[..rect.w];
+            if opacity >= 1.0 {
+                dest.copy_from_slice(src);
+            } else {
+                for (d, s) in dest.iter_mut().zip(src.iter()) {
+                    let mix = |a: f32, b: f32| a + opacity * (b - a);
+                    *d = RGB::new(mix(d.r, s.r), mix(d.g, s.g), mix(d.b, s.b));
+                }
+            }
+        }
+    }
+
+    /**
+    Paint the part of `rect` inside this image `color`, mixed in with the
+    given `opacity` (like `composite()`).
+    */
+    pub fn fill(&mut self, rect: PixelRect, color: RGB, opacity: f32) {
+        let dims = ImageDims {
+            xpix: rect.w,
+            ypix: rect.h,
+            ..self.dims
+        };
+        self.composite(&FImage32::new(dims, color), rect.x, rect.y, opacity);
+    }
+
+    /**
+    Color a finished band of iteration data and copy it into the
+    corresponding rows of this image. Bands that don't fit (because they
+    belong to an image of a different width) are ignored.
+    */
+    pub fn blit_band(&mut self, band: &IterBand, map: &ColorMap) {
+        if band.dims.xpix != self.dims.xpix {
+            return;
+        }
+        let mut colors: Vec<RGB> = Vec::new();
+        let mut alpha: Vec<f32> = Vec::new();
+        if map.transparent {
+            color_samples_alpha(
+                &band.dims,
+                &band.data,
+                |n| map.get_opaque(escaped(n, band.limit)),
+                map.default,
+                &mut colors,
+                &mut alpha,
+            );
+        } else {
+            color_samples(
+                &band.dims,
+                &band.data,
+                |n| map.get(escaped(n, band.limit)),
+                &mut colors,
+            );
+        }
+        // Bands are shaded on their own, so their edges can be a little
+        // off until the whole image is colored.
+        if let Some(shading) = map.shading {
+            let mut heights: Vec<f32> = Vec::with_capacity(colors.len());
+            sample_heights(&band.dims, &band.data, band.limit, &mut heights);
+            shading.apply(band.dims.xpix, &heights, &mut colors);
+        }
+        map.finish(&mut colors);
+        let start = band.y_start * self.dims.xpix;
+        let end = (start + colors.len()).min(self.data.len());
+        if start >= end {
+            return;
+        }
+        self.data[start..end].copy_from_slice(&colors[..(end - start)]);
+        if map.transparent {
+            self.alpha_mut()[start..end].copy_from_slice(&alpha[..(end - start)]);
+        } else if let Some(a) = self.alpha.as_mut() {
+            a[start..end].fill(1.0);
+        }
+    }
+
+    /**
+    Blow this image up by a factor of `factor` to fill `dims`, by just
+    repeating pixels. This is the inverse of `ImageDims::shrink()`, and is
+    used to display low-resolution previews at full size.
+    */
+    pub fn enlarge(&self, factor: usize, dims: ImageDims) -> FImage32 {
+        let factor = factor.max(1);
+        let mut data: Vec<RGB> = Vec::with_capacity(dims.xpix * dims.ypix);
+        for y in 0..dims.ypix {
+            let row = (y / factor).min(self.dims.ypix - 1) * self.dims.xpix;
+            for x in 0..dims.xpix {
+                let col = (x / factor).min(self.dims.xpix - 1);
+                data.push(self.data[row + col]);
+            }
+        }
+        let alpha = self.alpha.as_ref().map(|a| {
+            let mut alpha = Vec::with_capacity(dims.xpix * dims.ypix);
+            for y in 0..dims.ypix {
+                let row = (y / factor).min(self.dims.ypix - 1) * self.dims.xpix;
+                for x in 0..dims.xpix {
+                    alpha.push(a[row + (x / factor).min(self.dims.xpix - 1)]);
+                }
+            }
+            alpha
+        });
+
+        FImage32 { dims, data, alpha }
+    }
+
+    /**
+    Return the image data as a vector of 8-bit RGB color triples, scaled
+    down by a factor of `scale_factor` (a value of 1 will produce a
+    fill-sized image).
+
+    This is the data format that most external things like.
+    */
+    pub fn to_rgb8(&self, scale_factor: usize) -> (usize, usize, Vec<u8>) {
+        if scale_factor < 2 {
+            (
+                self.dims.xpix,
+                self.dims.ypix,
+                self.to_rgb8_full_resolution(),
+            )
+        } else if scale_factor > MAX_SCALE_FACTOR {
+            self.to_rgb8_scaled(MAX_SCALE_FACTOR)
+        } else {
+            self.to_rgb8_scaled(scale_factor)
+        }
+    }
+
+    // Shrink the image by a factor of 1/`ratio`, averaging each `ratio`
+    // by `ratio` square of pixels into one color and opacity. Colors are
+    // averaged by how opaque they are, so the colors of transparent
+    // pixels don't bleed into their neighbors.
+    fn scaled_with_alpha(&self, ratio: usize) -> (usize, usize, Vec<(RGB, f32)>) {
+        let ratio = ratio.clamp(1, MAX_SCALE_FACTOR);
+        let (pix_cols, pix_lines) = (self.dims.xpix / ratio, self.dims.ypix / ratio);
+        let mut pixels: Vec<(RGB, f32)> = Vec::with_capacity(pix_cols * pix_lines);
+        let alpha = |n: usize| self.alpha.as_ref().map_or(1.0, |a| a[n]);
+
+        for yi in 0..pix_lines {
+            for xi in 0..pix_cols {
+                let (mut tot, mut weight) = ([0.0f32; 3], 0.0f32);
+                let mut plain = [0.0f32; 3];
+                for y in 0..ratio {
+                    let po = (yi * ratio + y) * self.dims.xpix + xi * ratio;
+                    for n in po..(po + ratio) {
+                        let (p, a) = (self.data[n], alpha(n));
+                        for (t, c) in tot.iter_mut().zip([p.r, p.g, p.b]) {
+                            *t += a * c;
+                        }
+                        for (t, c) in plain.iter_mut().zip([p.r, p.g, p.b]) {
+                            *t += c;
+                        }
+                        weight += a;
+                    }
+                }
+                let n = (ratio * ratio) as f32;
+                let p = if weight > 0.0 {
+                    RGB::new(tot[0] / weight, tot[1] / weight, tot[2] / weight)
+                } else {
+                    RGB::new(plain[0] / n, plain[1] / n, plain[2] / n)
+                };
+                pixels.push((p, weight / n));
+            }
+        }
+
+        (pix_cols, pix_lines, pixels)
+    }
+
+    /** Return whether any of the image is at all transparent. */
+    pub fn is_transparent(&self) -> bool {
+        self.alpha
+            .as_ref()
+            .is_some_and(|a| a.iter().any(|x| *x < 1.0))
+    }
+
+    /**
+    Like `to_rgb8()`, but with a fourth byte for each pixel's opacity.
+    Shrinking it down averages the colors by how opaque they are, so the
+    colors of transparent pixels don't bleed into their neighbors.
+    */
+    pub fn to_rgba8(&self, scale_factor: usize) -> (usize, usize, Vec<u8>) {
+        let (w, h, pixels) = self.scaled_with_alpha(scale_factor);
+        let mut rgba8_data: Vec<u8> = Vec::with_capacity(pixels.len() * 4);
+        for (p, a) in pixels.iter() {
+            rgba8_data.extend_from_slice(&p.to_rgb8());
+            rgba8_data.push((255.0 * a).round() as u8);
+        }
+
+        (w, h, rgba8_data)
+    }
+
+    // The image as 16-bit RGB values, shrunk by `scale_factor`, with
+    // opacities after each pixel if `with_alpha`.
+    fn to_16(&self, scale_factor: usize, with_alpha: bool) -> (usize, usize, Vec<u16>) {
+        let (w, h, pixels) = self.scaled_with_alpha(scale_factor);
+        let channels = if with_alpha { 4 } else { 3 };
+        let mut rgb16_data: Vec<u16> = Vec::with_capacity(pixels.len() * channels);
+        for (p, a) in pixels.iter() {
+            rgb16_data.extend_from_slice(&p.to_rgb16());
+            if with_alpha {
+                rgb16_data.push((65535.0 * a).round() as u16);
+            }
+        }
+
+        (w, h, rgb16_data)
+    }
+
+    /**
+    Like `to_rgb8()`, but with 16 bits per channel, for saving images with
+    smooth gradients without banding.
+    */
+    pub fn to_rgb16(&self, scale_factor: usize) -> (usize, usize, Vec<u16>) {
+        self.to_16(scale_factor, false)
+    }
+
+    /** Like `to_rgba8()`, but with 16 bits per channel. */
+    pub fn to_rgba16(&self, scale_factor: usize) -> (usize, usize, Vec<u16>) {
+        self.to_16(scale_factor, true)
+    }
+
+    /**
+    Return the image data as 8-bit RGB (like `to_rgb8()`), or, if any of
+    it is transparent, RGBA (like `to_rgba8()`). Either way is fine for
+    saving with `rw::save_with_metadata()` or showing in the image pane.
+    */
+    pub fn to_bytes(&self, scale_factor: usize) -> (usize, usize, Vec<u8>) {
+        if self.is_transparent() {
+            self.to_rgba8(scale_factor)
+        } else {
+            self.to_rgb8(scale_factor)
+        }
+    }
+}
+
+/*
+Color a band of iteration counts from an image with dimensions `dims`,
+appending the pixels to `out`. The counts are laid out in rows of sample
+points (see `ImageDims::sample_dims()`), and each pixel gets the average
+of the colors `f` gives its samples.
+*/
+fn color_samples<F>(dims: &ImageDims, data: &[usize], f: F, out: &mut Vec<RGB>)
+where
+    F: Fn(usize) -> RGB,
+{
+    let s = dims.samples();
+    if s == 1 {
+        out.extend(data.iter().map(|n| f(*n)));
+        return;
+    }
+
+    let row_len = dims.xpix * s;
+    let mut palette: [RGB; SAMPLE_PALETTE_SIZE] = [RGB::BLACK; SAMPLE_PALETTE_SIZE];
+    for rows in data.chunks_exact(row_len * s) {
+        for xp in 0..dims.xpix {
+            let mut pp = 0usize;
+            for y in 0..s {
+                let offs = (y * row_len) + (xp * s);
+                for n in rows[offs..(offs + s)].iter() {
+                    palette[pp] = f(*n);
+                    pp += 1;
+                }
+            }
+            out.push(RGB::average(&palette[0..pp]));
+        }
+    }
+}
+
+/*
+Like `color_samples()`, but `f` gives `None` for samples that should be
+transparent, and each pixel's opacity (the fraction of its samples that
+aren't) gets appended to `alpha`. Pixels get the average color of just
+their opaque samples, or `clear` if they don't have any.
+*/
+fn color_samples_alpha<F>(
+    dims: &ImageDims,
+    data: &[usize],
+    f: F,
+    clear: RGB,
+    out: &mut Vec<RGB>,
+    alpha: &mut Vec<f32>,
+) where
+    F: Fn(usize) -> Option<RGB>,
+{
+    let s = dims.samples();
+    let row_len = dims.xpix * s;
+    let mut palette: [RGB; SAMPLE_PALETTE_SIZE] = [RGB::BLACK; SAMPLE_PALETTE_SIZE];
+    for rows in data.chunks_exact(row_len * s) {
+        for xp in 0..dims.xpix {
+            let mut pp = 0usize;
+            for y in 0..s {
+                let offs = (y * row_len) + (xp * s);
+                for n in rows[offs..(offs + s)].iter() {
+                    if let Some(c) = f(*n) {
+                        palette[pp] = c;
+                        pp += 1;
+                    }
+                }
+            }
+            if pp == 0 {
+                out.push(clear);
+            } else {
+                out.push(RGB::average(&palette[0..pp]));
+            }
+            alpha.push(pp as f32 / (s * s) as f32);
+        }
+    }
+}
+
+/*
+Append the height of each pixel in `data` for shading: the average of the
+log of its samples' counts, with points that never escape (anything at or
+above `limit`) as high as they go.
+*/
+fn sample_heights(dims: &ImageDims, data: &[usize], limit: usize, out: &mut Vec<f32>) {
+    let s = dims.samples();
+    let row_len = dims.xpix * s;
+    let n = (s * s) as f32;
+    for rows in data.chunks_exact(row_len * s) {
+        for xp in 0..dims.xpix {
+            let mut tot = 0.0f32;
+            for y in 0..s {
+                let offs = (y * row_len) + (xp * s);
+                for c in rows[offs..(offs + s)].iter() {
+                    tot += ((*c).min(limit) as f32).ln_1p();
+                }
+            }
+            out.push(tot / n);
+        }
+    }
+}
+
+// Points whose counts reached the limit they were iterated to never escaped,
+// and should get the default color even if the `ColorMap` is longer (like
+// when parts of a map are approximate, or a longer color map is waiting on
+// a reiteration).
+fn escaped(n: usize, limit: usize) -> usize {
+    if n >= limit {
+        usize::MAX
+    } else {
+        n
+    }
+}
+
+// Width (in pixels) of the stripes used to mark rows that failed to iterate.
+const FAILED_STRIPE: usize = 8;
+const FAILED_COLOR: RGB = RGB {
+    r: 255.0,
+    g: 0.0,
+    b: 255.0,
+};
+
+// Push diagonal magenta-and-black stripes for the `n_rows` rows starting at
+// `y_start`, which is hard to mistake for part of any reasonable picture.
+fn mark_failed(dims: &ImageDims, y_start: usize, n_rows: usize, out: &mut Vec<RGB>) {
+    for y in y_start..(y_start + n_rows) {
+        for x in 0..dims.xpix {
+            if ((x + y) / FAILED_STRIPE) & 1 == 0 {
+                out.push(FAILED_COLOR);
+            } else {
+                out.push(RGB::BLACK);
+            }
+        }
+    }
+}
+
+// What saved counts are of: everything that goes into iterating them but
+// the limit (which they can just be reiterated to).
+fn counts_key(dims: &ImageDims, itertype: &IterType, orbit: Option<Orbit>) -> String {
+    format!("{:?} {:?} {:?}", dims, itertype, orbit)
+}
+
+/**
+Get the message out of the payload of a panic (as returned by
+`std::panic::catch_unwind()` or `JoinHandle::join()`), if it has one.
+*/
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/**
+A type to fully describe the type of iteration to be used.
+
+This, combined with an iteration limit (the length of a target `ColorMap`)
+is all the information required for iterating a point.
+*/
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum IterType {
+    Mandlebrot,
+    PseudoMandlebrot {
+        a: Cx,
+        b: Cx,
+    },
+    Polynomial {
+        coefs: Vec<Cx>,
+    },
+    /// conj(z)^2 + c, the Mandlebrot set's mirror-image cousin
+    Tricorn,
+    /// c·exp(z), with c the point being iterated
+    Exponential,
+    /// c·sin(z), with c the point being iterated
+    Sine,
+    /// c·cos(z), with c the point being iterated
+    Cosine,
+}
+
+// The Mandlebrot set fits in this rectangle, centered on this point.
+const MANDLEBROT_CENTER: (f64, f64) = (-0.5, 0.0);
+const MANDLEBROT_SIZE: (f64, f64) = (3.0, 2.0);
+// Likewise the tricorn, and the interesting parts of the c·exp(z) and
+// c·sin(z) (and c·cos(z)) parameter planes.
+const TRICORN_CENTER: (f64, f64) = (-0.45, 0.0);
+const TRICORN_SIZE: (f64, f64) = (3.6, 3.6);
+const EXP_CENTER: (f64, f64) = (-1.0, 0.0);
+const EXP_SIZE: (f64, f64) = (8.0, 6.0);
+const TRIG_SIZE: (f64, f64) = (8.0, 6.0);
+
+impl IterType {
+    /**
+    Return a view (with the same pixel dimensions as `dims`) that frames
+    the interesting part of the plane for this kind of iterator.
+
+    Different iterators put their sets in very different places; the
+    Mandlebrot view of a polynomial iterator is often just a blank image.
+    */
+    pub fn default_view(&self, dims: &ImageDims) -> ImageDims {
+        let (m_x, m_y) = MANDLEBROT_CENTER;
+        let (m_w, m_h) = MANDLEBROT_SIZE;
+        match self {
+            IterType::Mandlebrot => dims.framing(m_x, m_y, m_w, m_h),
+            IterType::PseudoMandlebrot { a, b } => {
+                // Substituting w = az turns az^2 + bc into w^2 + abc, so
+                // this is just the Mandlebrot set, shrunk by |ab| and
+                // rotated by -arg(ab).
+                let ab = *a * *b;
+                let r = ab.r();
+                if !r.is_normal() {
+                    return dims.framing(m_x, m_y, m_w, m_h);
+                }
+                let inv = Cx::polar(1.0 / r, -ab.theta());
+                let center = inv * Cx::rect(m_x, m_y);
+                let (sin, cos) = inv.theta().sin_cos();
+                let (sin, cos) = (sin.abs(), cos.abs());
+                dims.framing(
+                    center.re,
+                    center.im,
+                    (m_w * cos + m_h * sin) / r,
+                    (m_w * sin + m_h * cos) / r,
+                )
+            }
+            IterType::Polynomial { coefs } => {
+                // Everything farther than this from the origin escapes.
+                let radius = match coefs.split_last() {
+                    Some((lead, rest)) if coefs.len() > 2 && lead.r() > 0.0 => {
+                        let tot: f64 = rest.iter().map(|c| c.r()).sum();
+                        ((1.0 + tot) / lead.r()).max(1.0)
+                    }
+                    _ => 2.0,
+                };
+                dims.framing(0.0, 0.0, 2.0 * radius, 2.0 * radius)
+            }
+            IterType::Tricorn => dims.framing(
+                TRICORN_CENTER.0,
+                TRICORN_CENTER.1,
+                TRICORN_SIZE.0,
+                TRICORN_SIZE.1,
+            ),
+            IterType::Exponential => {
+                dims.framing(EXP_CENTER.0, EXP_CENTER.1, EXP_SIZE.0, EXP_SIZE.1)
+            }
+            IterType::Sine | IterType::Cosine => dims.framing(0.0, 0.0, TRIG_SIZE.0, TRIG_SIZE.1),
+        }
+    }
+}
+
+/*
+Brent's cycle detection. Each new value of _z_ is compared against a saved
+one, which gets replaced with the current value after 1, 2, 4, 8, ...
+steps, so a cycle of any period gets caught within a couple of times its
+period (plus however long the orbit takes to settle into it).
+*/
+struct CycleCheck {
+    saved: Cx,
+    steps: usize,
+    period: usize,
+}
+
+impl CycleCheck {
+    fn new(z: Cx) -> CycleCheck {
+        CycleCheck {
+            saved: z,
+            steps: 0,
+            period: 1,
+        }
+    }
+
+    // Returns whether `z` has come back around to the saved value.
+    fn check(&mut self, z: Cx) -> bool {
+        let d = z + -self.saved;
+        if d.sqmod() < PERIOD_SQ_EPSILON {
+            return true;
+        }
+        self.steps += 1;
+        if self.steps == self.period {
+            self.saved = z;
+            self.steps = 0;
+            self.period *= 2;
+        }
+        false
+    }
+}
+
+/* The vectorized version of `CycleCheck`. */
+struct CycleCheckLanes {
+    saved_re: f64x4,
+    saved_im: f64x4,
+    steps: usize,
+    period: usize,
+}
+
+impl CycleCheckLanes {
+    fn new(z_re: f64x4, z_im: f64x4) -> CycleCheckLanes {
+        CycleCheckLanes {
+            saved_re: z_re,
+            saved_im: z_im,
+            steps: 0,
+            period: 1,
+        }
+    }
+
+    // Returns a mask of the lanes that have come back around to their
+    // saved values.
+    fn check(&mut self, z_re: f64x4, z_im: f64x4) -> f64x4 {
+        let d_re = z_re - self.saved_re;
+        let d_im = z_im - self.saved_im;
+        let cycled = (d_re * d_re + d_im * d_im).cmp_lt(f64x4::splat(PERIOD_SQ_EPSILON));
+        self.steps += 1;
+        if self.steps == self.period {
+            self.saved_re = z_re;
+            self.saved_im = z_im;
+            self.steps = 0;
+            self.period *= 2;
+        }
+        cycled
+    }
+}
+
+/*
+Whether `c` is inside the main cardioid or the period-2 bulb of the
+Mandlebrot set. Points in there never escape, so there's no point in
+iterating them; for the default view, that's a good chunk of the image.
+*/
+fn in_main_bulbs(c: Cx) -> bool {
+    let x = c.re - 0.25;
+    let y_sq = c.im * c.im;
+    let q = x * x + y_sq;
+    if q * (q + x) <= 0.25 * y_sq {
+        return true;
+    }
+    let x = c.re + 1.0;
+    x * x + y_sq <= 0.0625
+}
+
+/* Iterate a point using the Mandlebrot iterator. */
+fn mandlebrot_iterator(c: Cx, limit: usize, check_period: bool) -> usize {
+    if in_main_bulbs(c) {
+        return limit;
+    }
+    let mut z = Cx { re: 0.0, im: 0.0 };
+    let mut cycle = CycleCheck::new(z);
+
+    for n in 0..limit {
+        z = (z * z) + c;
+        if z.sqmod() > SQ_MOD_LIMIT {
+            return n;
+        }
+        if check_period && cycle.check(z) {
+            return limit;
+        }
+    }
+    limit
+}
+
+/*
+Iterate a point using the tricorn (or "Mandelbar") iterator
+
+    f(z) = conj(z)^2 + c
+
+which is the Mandlebrot iterator with the orbit reflected in the real axis
+at every step. It has no main cardioid to skip.
+*/
+fn tricorn_iterator(c: Cx, limit: usize, check_period: bool) -> usize {
+    let mut z = Cx { re: 0.0, im: 0.0 };
+    let mut cycle = CycleCheck::new(z);
+
+    for n in 0..limit {
+        let w = z.conj();
+        z = (w * w) + c;
+        if z.sqmod() > SQ_MOD_LIMIT {
+            return n;
+        }
+        if check_period && cycle.check(z) {
+            return limit;
+        }
+    }
+    limit
+}
+
+/*
+Generate and return a function (a closure) to iterate a point using a
+Pseudo-Mandlebrot iterator.
+
+I'm not sure if this is a real thing, but it's what I'm calling it. The
+_Mandlebrot_ iterator uses the function
+
+   f(z) = z^2 + c
+
+to iterate a given point _c_. A _Pseudo_-Mandlebrot iterator is a parametrized
+mapping, such that for a given complex (a, b),
+
+   f(a, b)(z) = az^2 + bc
+
+iterates the given point _c_.
+*/
+fn pseudomandle_maker(a: Cx, b: Cx) -> Box<dyn Fn(Cx, usize) -> usize> {
+    let f = move |c, limit| {
+        let mut z = Cx { re: 0.0, im: 0.0 };
+        let pseudo_c = b * c;
+
+        for n in 0..limit {
+            z = (a * z * z) + pseudo_c;
+            if z.sqmod() > SQ_MOD_LIMIT {
+                return n;
+            }
+        }
+        limit
+    };
+    Box::new(f)
+}
+
+/*
+Generate and return a function (a closure) to iterate a point using an
+arbitrary polynomial iterator.
+
+Given a vector `v` of complex coefficients, this function will generate
+the iteration function
+
+    f(z) = v[0]*z + v[1]*z^2 + v[2]*z^3 + ...
+
+*/
+fn polyiter_maker(v: Vec<Cx>, check_period: bool) -> Box<dyn Fn(Cx, usize) -> usize> {
+    let deg = v.len() - 1;
+    let f = move |c, limit| {
+        let mut z = c;
+        let mut cycle = CycleCheck::new(z);
+        for n in 0..limit {
+            let mut tot = Cx { re: 0.0, im: 0.0 };
+            let mut w = Cx { re: 1.0, im: 0.0 };
+            for a in v[0..deg].iter() {
+                tot = tot + (*a) * w;
+                w = w * z;
+            }
+            tot = unsafe { tot + (*v.get_unchecked(deg) * w) };
+            z = tot;
+            if z.sqmod() > SQ_MOD_LIMIT {
+                return n;
+            }
+            if check_period && cycle.check(z) {
+                return limit;
+            }
+        }
+        limit
+    };
+
+    Box::new(f)
+}
+
+/*
+Generate and return a function (a closure) to iterate a point `c` using
+one of the transcendental iterators
+
+    f(z) = c·exp(z),  c·sin(z),  or  c·cos(z)
+
+starting from the first iterate of the function's one singular value
+(0 for exp, where it can't go, and the critical points π/2 and 0 for sin
+and cos), which is `c` for all three.
+
+These don't escape the way polynomials do: exp(z) is small everywhere in
+the left half-plane, however far out, and sin and cos are bounded along
+the real axis. What sends a point off for good is a big real part, for
+exp, and a big imaginary part, for sin and cos, so those are what get
+checked (before they overflow).
+*/
+fn transcendental_maker(
+    f: fn(&Cx) -> Cx,
+    escaped: fn(Cx) -> bool,
+    check_period: bool,
+) -> Box<dyn Fn(Cx, usize) -> usize> {
+    let g = move |c: Cx, limit| {
+        let mut z = c;
+        let mut cycle = CycleCheck::new(z);
+        for n in 0..limit {
+            if escaped(z) {
+                return n;
+            }
+            z = c * f(&z);
+            if check_period && cycle.check(z) {
+                return limit;
+            }
+        }
+        limit
+    };
+    Box::new(g)
+}
+
+// How far out a transcendental iterator's orbit has to get (in the
+// direction that matters for it) to count as escaped. e^50 is plenty big
+// enough to be sure, and nowhere near overflowing.
+const TRANSCENDENTAL_ESCAPE: f64 = 50.0;
+
+// (An orbit that's gone to NaN has certainly escaped, too.)
+fn exp_escaped(z: Cx) -> bool {
+    z.re > TRANSCENDENTAL_ESCAPE || z.re.is_nan()
+}
+
+fn trig_escaped(z: Cx) -> bool {
+    z.im.abs() > TRANSCENDENTAL_ESCAPE || z.im.is_nan()
+}
+
+// The scalar iterator for any of the transcendental types.
+fn transcendental_iterator(
+    itype: &IterType,
+    check_period: bool,
+) -> Box<dyn Fn(Cx, usize) -> usize> {
+    match itype {
+        IterType::Exponential => transcendental_maker(Cx::exp, exp_escaped, check_period),
+        IterType::Sine => transcendental_maker(Cx::sin, trig_escaped, check_period),
+        _ => transcendental_maker(Cx::cos, trig_escaped, check_period),
+    }
+}
+
+// One step of an orbit: the next `z` for the point `c`, in two parts that
+// get added together, what's done to `z` and the constant added to it
+// (zero, for the iterators that don't add one).
+type Stepper = Box<dyn Fn(Cx, Cx) -> (Cx, Cx)>;
+
+// What's been seen of an orbit so far: the closest it's come to a trap, or
+// the sum (and number) of the terms of a statistic.
+enum Tally {
+    Nearest(Trap, f64),
+    Mean(OrbitStat, f64, usize),
+}
+
+impl Tally {
+    fn add(&mut self, w: Cx, k: Cx, z: Cx) {
+        match self {
+            Tally::Nearest(trap, nearest) => *nearest = nearest.min(trap.distance(z)),
+            Tally::Mean(stat, sum, n) => {
+                if let Some(x) = stat.term(w, k, z) {
+                    *sum += x;
+                    *n += 1;
+                }
+            }
+        }
+    }
+
+    fn count(&self, limit: usize) -> usize {
+        match *self {
+            Tally::Nearest(trap, nearest) => trap.count(nearest, limit),
+            Tally::Mean(_, _, 0) => 0,
+            Tally::Mean(_, sum, n) => fraction_count(sum / n as f64, limit),
+        }
+    }
+}
+
+/*
+Generate and return a function (a closure) to iterate a point with the
+iterator `itype`, keeping track of how close its orbit comes to a trap, or
+averaging a statistic over it, as `orbit` says, and returning that (as a
+count; see `Trap::count()`) instead of how long it took to escape.
+
+This is the slow way round for every iterator, with no vectorizing and no
+shortcuts for points known to be inside: every point's whole orbit counts.
+*/
+fn orbit_maker(
+    itype: IterType,
+    orbit: Orbit,
+    check_period: bool,
+) -> Box<dyn Fn(Cx, usize) -> usize> {
+    let zero = Cx::rect(0.0, 0.0);
+    let far: fn(Cx) -> bool = |z| z.sqmod() > SQ_MOD_LIMIT || z.sqmod().is_nan();
+    // Where the orbit starts (zero, or the point itself), how it steps
+    // from `z` for the point `c`, and when it's escaped.
+    let (from_zero, step, escaped): (bool, Stepper, fn(Cx) -> bool) = match itype {
+        IterType::Mandlebrot => (true, Box::new(|z, c| (z * z, c)), far),
+        IterType::PseudoMandlebrot { a, b } => {
+            (true, Box::new(move |z, c| (a * z * z, b * c)), far)
+        }
+        IterType::Polynomial { coefs } => (
+            false,
+            Box::new(move |z, _| {
+                let w = coefs[1..].iter().rev().fold(zero, |tot, a| (tot + *a) * z);
+                (w, coefs[0])
+            }),
+            far,
+        ),
+        IterType::Tricorn => (true, Box::new(|z: Cx, c| (z.conj() * z.conj(), c)), far),
+        IterType::Exponential => (
+            false,
+            Box::new(move |z: Cx, c| (c * z.exp(), zero)),
+            exp_escaped,
+        ),
+        IterType::Sine => (
+            false,
+            Box::new(move |z: Cx, c| (c * z.sin(), zero)),
+            trig_escaped,
+        ),
+        IterType::Cosine => (
+            false,
+            Box::new(move |z: Cx, c| (c * z.cos(), zero)),
+            trig_escaped,
+        ),
+    };
+
+    let f = move |c: Cx, limit| {
+        let mut z = if from_zero { zero } else { c };
+        let mut tally = match orbit {
+            Orbit::Trap(trap) if from_zero => Tally::Nearest(trap, f64::INFINITY),
+            Orbit::Trap(trap) => Tally::Nearest(trap, trap.distance(z)),
+            Orbit::Stat(stat) => Tally::Mean(stat, 0.0, 0),
+        };
+        let mut cycle = CycleCheck::new(z);
+        for _ in 0..limit {
+            let (w, k) = step(z, c);
+            z = w + k;
+            if escaped(z) {
+                break;
+            }
+            tally.add(w, k, z);
+            // A cycle has been everywhere it's going to go.
+            if check_period && cycle.check(z) {
+                break;
+            }
+        }
+        tally.count(limit)
+    };
+    Box::new(f)
+}
+
+/*
+The vectorized iterators below all take the real and imaginary parts of
+`LANES` points and return how many iterations each took to diverge.
+
+Each lane keeps getting iterated (harmlessly) after it diverges; `live`
+keeps track of which lanes are still in the running, and the loop bails
+as soon as every lane has diverged. Lanes found to be cycling get taken
+out of the running too, and marked `inside` so they come out at the limit.
+*/
+type LaneIterator = Box<dyn Fn(f64x4, f64x4, usize) -> [usize; LANES]>;
+
+// Convert the lane-wise iteration counts to `usize`s.
+fn lane_counts(counts: f64x4) -> [usize; LANES] {
+    let a = counts.to_array();
+    [a[0] as usize, a[1] as usize, a[2] as usize, a[3] as usize]
+}
+
+/* The vectorized version of `in_main_bulbs()`. */
+fn in_main_bulbs_lanes(c_re: f64x4, c_im: f64x4) -> f64x4 {
+    let x = c_re - f64x4::splat(0.25);
+    let y_sq = c_im * c_im;
+    let q = x * x + y_sq;
+    let cardioid = (q * (q + x)).cmp_le(f64x4::splat(0.25) * y_sq);
+    let x = c_re + f64x4::ONE;
+    let bulb = (x * x + y_sq).cmp_le(f64x4::splat(0.0625));
+    cardioid | bulb
+}
+
+/* Iterate `LANES` points at once using the Mandlebrot iterator. */
+fn mandlebrot_lanes(c_re: f64x4, c_im: f64x4, limit: usize, check_period: bool) -> [usize; LANES] {
+    let mut inside = in_main_bulbs_lanes(c_re, c_im);
+    if inside.all() {
+        return [limit; LANES];
+    }
+
+    let sq_mod_limit = f64x4::splat(SQ_MOD_LIMIT);
+    let (mut z_re, mut z_im) = (f64x4::ZERO, f64x4::ZERO);
+    let mut counts = f64x4::ZERO;
+    // Points known to be inside don't need to be iterated.
+    let mut live = !inside;
+    let mut cycle = CycleCheckLanes::new(z_re, z_im);
+
+    for _ in 0..limit {
+        let re_sq = z_re * z_re;
+        let im_sq = z_im * z_im;
+        z_im = (z_re + z_re) * z_im + c_im;
+        z_re = re_sq - im_sq + c_re;
+        live &= (z_re * z_re + z_im * z_im).cmp_le(sq_mod_limit);
+        if check_period {
+            let cycled = live & cycle.check(z_re, z_im);
+            inside |= cycled;
+            live &= !cycled;
+        }
+        if live.none() {
+            break;
+        }
+        counts += live & f64x4::ONE;
+    }
+    lane_counts(inside.blend(f64x4::splat(limit as f64), counts))
+}
+
+/*
+Generate and return a function to iterate `LANES` points at once using
+an arbitrary polynomial iterator. See `polyiter_maker()` for what that
+means.
+*/
+fn polylanes_maker(v: Vec<Cx>, check_period: bool) -> LaneIterator {
+    let coefs: Vec<(f64x4, f64x4)> = v
+        .iter()
+        .map(|a| (f64x4::splat(a.re), f64x4::splat(a.im)))
+        .collect();
+    let f = move |c_re: f64x4, c_im: f64x4, limit: usize| {
+        let sq_mod_limit = f64x4::splat(SQ_MOD_LIMIT);
+        let (mut z_re, mut z_im) = (c_re, c_im);
+        let mut counts = f64x4::ZERO;
+        let mut live = f64x4::ZERO.cmp_le(f64x4::ZERO);
+        let mut inside = !live;
+        let mut cycle = CycleCheckLanes::new(z_re, z_im);
+
+        for _ in 0..limit {
+            let (mut tot_re, mut tot_im) = (f64x4::ZERO, f64x4::ZERO);
+            let (mut w_re, mut w_im) = (f64x4::ONE, f64x4::ZERO);
+            for (a_re, a_im) in coefs.iter() {
+                tot_re += (*a_re * w_re) - (*a_im * w_im);
+                tot_im += (*a_re * w_im) + (*a_im * w_re);
+                let t = (w_re * z_re) - (w_im * z_im);
+                w_im = (w_re * z_im) + (w_im * z_re);
+                w_re = t;
+            }
+            z_re = tot_re;
+            z_im = tot_im;
+            live &= (z_re * z_re + z_im * z_im).cmp_le(sq_mod_limit);
+            if check_period {
+                let cycled = live & cycle.check(z_re, z_im);
+                inside |= cycled;
+                live &= !cycled;
+            }
+            if live.none() {
+                break;
+            }
+            counts += live & f64x4::ONE;
+        }
+        lane_counts(inside.blend(f64x4::splat(limit as f64), counts))
+    };
+    Box::new(f)
+}
+
+/*
+Iterate `n` sample points of row `yp` of `sdims`, in columns `xp0`,
+`xp0 + step`, `xp0 + 2*step`, ..., appending the counts to `out`.
+*/
+#[allow(clippy::too_many_arguments)]
+fn iterate_row(
+    f: &LaneIterator,
+    sdims: &ImageDims,
+    yp: usize,
+    xp0: usize,
+    step: usize,
+    n: usize,
+    limit: usize,
+    out: &mut Vec<usize>,
+) {
+    let y = f64x4::splat(mapping::pixel_y(sdims, yp));
+    for k in (0..n).step_by(LANES) {
+        let mut x = [0.0f64; LANES];
+        for (m, xv) in x.iter_mut().enumerate() {
+            *xv = mapping::pixel_x(sdims, xp0 + ((k + m) * step));
+        }
+        let counts = f(f64x4::from(x), y, limit);
+        // The last group may run off the end of the row.
+        let n_valid = LANES.min(n - k);
+        out.extend_from_slice(&counts[..n_valid]);
+    }
+}
+
+/*
+Wrap one of the scalar iterators so it can be called like a vectorized
+one. This is for iterators that haven't been (or aren't worth) vectorizing.
+*/
+fn scalar_lanes(f: Box<dyn Fn(Cx, usize) -> usize>) -> LaneIterator {
+    let g = move |c_re: f64x4, c_im: f64x4, limit: usize| {
+        let (re, im) = (c_re.to_array(), c_im.to_array());
+        let mut counts = [0usize; LANES];
+        for (n, count) in counts.iter_mut().enumerate() {
+            *count = f(Cx::rect(re[n], im[n]), limit);
+        }
+        counts
+    };
+    Box::new(g)
+}
+
+/**
+A horizontal band of rows from an `IterMap` that has finished iterating,
+handed out while the rest of the map is still being worked on.
+*/
+pub struct IterBand {
+    /// Dimensions of the whole image of which this is a band.
+    pub dims: ImageDims,
+    /// Index of the first row of the band.
+    pub y_start: usize,
+    /// Iteration counts, in rows of `dims.sample_dims().xpix` sample
+    /// points, `dims.samples()` rows of samples per row of pixels.
+    pub data: Vec<usize>,
+    /// The iteration limit the band was iterated to; points with counts
+    /// this high never escaped.
+    pub limit: usize,
+}
+
+/**
+Lets whoever started an iteration keep tabs on it (and call it off) from
+another thread.
+
+The iteration loops check `is_cancelled()` once per row, so a cancelled
+iteration stops promptly. Each time a chunk of the image finishes, the
+band callback (if any) gets passed a copy of its data, and then the
+progress callback (if any) gets called with the number of chunks done so
+far and the total number of chunks.
+
+A watcher can also be given a deadline. Chunks of the image started (or
+still being worked on) after the deadline are iterated with a lower
+limit, so a render that's taking too long finishes with an approximate
+picture rather than not at all; see `IterMap::is_approximate()`.
+*/
+#[derive(Default)]
+pub struct IterWatcher {
+    cancelled: AtomicBool,
+    // Set from outside to cancel, like `cancelled` (see `stop_when()`).
+    stop: Option<Arc<AtomicBool>>,
+    chunks_done: AtomicUsize,
+    deadline: Option<Instant>,
+    overtime_limit: AtomicUsize,
+    on_progress: Option<Box<dyn Fn(usize, usize) + Send + Sync>>,
+    on_band: Option<Box<dyn Fn(IterBand) + Send + Sync>>,
+}
+
+impl IterWatcher {
+    /** Create a watcher with no progress callback. */
+    pub fn new() -> IterWatcher {
+        IterWatcher::default()
+    }
+
+    /** Create a watcher that calls `f(done, total)` as chunks finish. */
+    pub fn with_progress<F>(f: F) -> IterWatcher
+    where
+        F: Fn(usize, usize) + Send + Sync + 'static,
+    {
+        IterWatcher {
+            on_progress: Some(Box::new(f)),
+            ..Default::default()
+        }
+    }
+
+    /** Have `f` called with each band of the image as it's finished. */
+    pub fn set_band_callback<F>(&mut self, f: F)
+    where
+        F: Fn(IterBand) + Send + Sync + 'static,
+    {
+        self.on_band = Some(Box::new(f));
+    }
+
+    /** Ask the iteration being watched to stop as soon as possible. */
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /** Have the iteration being watched stop as soon as `stop` gets set. */
+    pub fn stop_when(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = Some(stop);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || matches!(&self.stop, Some(stop) if stop.load(Ordering::Relaxed))
+    }
+
+    /**
+    Have any of the iteration still to be done after `t` be done with a
+    lower iteration limit.
+    */
+    pub fn set_deadline(&mut self, t: Instant) {
+        self.deadline = Some(t);
+    }
+
+    /** Return whether the deadline (if there is one) has passed. */
+    pub fn out_of_time(&self) -> bool {
+        match self.deadline {
+            Some(t) => Instant::now() >= t,
+            None => false,
+        }
+    }
+
+    // The limit a chunk starting now should be iterated to, if the
+    // iteration as a whole is to `limit`.
+    fn limit_for(&self, limit: usize) -> usize {
+        if self.out_of_time() {
+            limit.min(self.overtime_limit.load(Ordering::Relaxed))
+        } else {
+            limit
+        }
+    }
+
+    // Whether a chunk being iterated to `limit` should stop: because the
+    // iteration has been cancelled, or because it's out of time and should
+    // start over with a lower limit.
+    fn should_stop(&self, limit: usize) -> bool {
+        self.is_cancelled()
+            || (limit > self.overtime_limit.load(Ordering::Relaxed) && self.out_of_time())
+    }
+
+    // Reset the chunk counter at the start of an iteration to `limit`.
+    fn start(&self, total: usize, limit: usize) {
+        self.chunks_done.store(0, Ordering::Relaxed);
+        self.overtime_limit
+            .store((limit / OVERTIME_DIVISOR).max(1), Ordering::Relaxed);
+        if let Some(f) = &self.on_progress {
+            f(0, total);
+        }
+    }
+
+    // Called by each chunk when it's finished.
+    fn chunk_done(&self, chunk: &IterMapChunk, total: usize) {
+        // There's nothing worth showing from a chunk that failed; it'll
+        // be marked when the whole map is colored.
+        if let (Some(f), None) = (&self.on_band, &chunk.failure) {
+            f(IterBand {
+                dims: chunk.dims,
+                y_start: chunk.y_start,
+                data: chunk.data.clone(),
+                limit: chunk.last_limit,
+            });
+        }
+        let done = self.chunks_done.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(f) = &self.on_progress {
+            f(done, total);
+        }
+    }
+}
+
+/*
+A description of a portion of an image to be iterated, suitable to be processed
+in parallel with other `IterMapChunk`s. Together with the length of a target
+`ColorMap`, this is all the information required to make an iteration map
+for the specified portion of an image.
+
+Processing with `.iterate()` will fill the chunk's `.data` member with the
+actual iteration map values.
+
+Processing with `.reiterate()` will extend the iteration map to the new
+limit for only those points who were already at the last limit. The idea
+here is for redrawing an image where the only thing that has changed is
+the length of the `ColorMap`.
+*/
+#[derive(Clone)]
+struct IterMapChunk {
+    dims: ImageDims,
+    itertype: IterType,
+    y_start: usize,
+    n_rows: usize,
+    last_limit: usize,
+    orbit: Option<Orbit>,
+    data: Vec<usize>,
+    // The message from the panic, if iterating this chunk panicked.
+    failure: Option<String>,
+}
+
+impl IterMapChunk {
+    /*
+    Run `f` (one of the iteration methods) on this chunk, catching any
+    panic, so that one bad chunk doesn't take the rest of the image down
+    with it. A chunk that panics is left full of zeros with the panic
+    message in `failure`, and counts as finished.
+
+    Returns `false` if the iteration got cancelled before finishing.
+    */
+    fn run<F>(&mut self, f: F) -> bool
+    where
+        F: FnOnce(&mut IterMapChunk) -> bool,
+    {
+        match panic::catch_unwind(AssertUnwindSafe(|| f(self))) {
+            Ok(finished) => {
+                if finished {
+                    self.failure = None;
+                }
+                finished
+            }
+            Err(payload) => {
+                let s = self.dims.samples();
+                self.data = vec![0; self.dims.xpix * s * self.n_rows * s];
+                self.failure = Some(panic_message(payload.as_ref()));
+                true
+            }
+        }
+    }
+
+    /*
+    Iterate to `limit`, or to the watcher's lower overtime limit if it runs
+    out of time before this chunk is done (starting over if it was already
+    partway through).
+
+    Returns `false` if the iteration got cancelled before finishing.
+    */
+    fn iterate_budgeted(&mut self, limit: usize, watcher: &IterWatcher) -> bool {
+        loop {
+            if self.iterate(watcher.limit_for(limit), watcher) {
+                return true;
+            }
+            if watcher.is_cancelled() {
+                return false;
+            }
+        }
+    }
+
+    // Returns `false` if the iteration got cancelled (or ran out of time)
+    // before finishing.
+    fn iterate(&mut self, limit: usize, watcher: &IterWatcher) -> bool {
+        let s = self.dims.samples();
+        let sdims = self.dims.sample_dims();
+        let check_period = self.dims.check_period;
+        let f: LaneIterator = match (self.itertype.clone(), self.orbit) {
+            (itype, Some(orbit)) => scalar_lanes(orbit_maker(itype, orbit, check_period)),
+            (IterType::Mandlebrot, None) => {
+                Box::new(move |c_re, c_im, limit| mandlebrot_lanes(c_re, c_im, limit, check_period))
+            }
+            (IterType::PseudoMandlebrot { a, b }, None) => scalar_lanes(pseudomandle_maker(a, b)),
+            (IterType::Polynomial { coefs }, None) => polylanes_maker(coefs, check_period),
+            (IterType::Tricorn, None) => scalar_lanes(Box::new(move |c, limit| {
+                tricorn_iterator(c, limit, check_period)
+            })),
+            (t @ (IterType::Exponential | IterType::Sine | IterType::Cosine), None) => {
+                scalar_lanes(transcendental_iterator(&t, check_period))
+            }
+        };
+
+        if self.dims.adaptive && s > 1 {
+            return self.iterate_adaptive(&f, limit, watcher);
+        }
+
+        let n_pix = sdims.xpix * self.n_rows * s;
+        let mut new_data: Vec<usize> = Vec::with_capacity(n_pix);
+        for yp in (self.y_start * s)..((self.y_start + self.n_rows) * s) {
+            if watcher.should_stop(limit) {
+                return false;
+            }
+            iterate_row(&f, &sdims, yp, 0, 1, sdims.xpix, limit, &mut new_data);
+        }
+
+        self.last_limit = limit;
+        self.data = new_data;
+        true
+    }
+
+    /*
+    Iterate only the first sample of each pixel (plus a row on either side
+    of the chunk, to compare its edge rows against), then fill in the rest
+    of the samples of just those pixels whose counts differ from one of
+    their neighbors' by more than `ADAPTIVE_THRESHOLD`. All the samples of
+    the other pixels get copies of the first one.
+
+    Returns `false` if the iteration got cancelled before finishing.
+    */
+    fn iterate_adaptive(&mut self, f: &LaneIterator, limit: usize, watcher: &IterWatcher) -> bool {
+        let s = self.dims.samples();
+        let sdims = self.dims.sample_dims();
+        let xpix = self.dims.xpix;
+        let first = self.y_start.saturating_sub(1);
+        let last = (self.y_start + self.n_rows + 1).min(self.dims.ypix);
+
+        let mut coarse: Vec<usize> = Vec::with_capacity((last - first) * xpix);
+        for yp in first..last {
+            if watcher.should_stop(limit) {
+                return false;
+            }
+            iterate_row(f, &sdims, yp * s, 0, s, xpix, limit, &mut coarse);
+        }
+
+        let differs = |a: usize, b: usize| a.abs_diff(b) > ADAPTIVE_THRESHOLD;
+        let row_len = sdims.xpix;
+        let mut new_data: Vec<usize> = vec![0; row_len * self.n_rows * s];
+        let mut samples: Vec<usize> = Vec::with_capacity(s + LANES);
+        for r in 0..self.n_rows {
+            if watcher.should_stop(limit) {
+                return false;
+            }
+            let yp = self.y_start + r;
+            let ci = (yp - first) * xpix;
+            for xp in 0..xpix {
+                let c = coarse[ci + xp];
+                let edge = (xp > 0 && differs(c, coarse[ci + xp - 1]))
+                    || (xp + 1 < xpix && differs(c, coarse[ci + xp + 1]))
+                    || (yp > first && differs(c, coarse[ci + xp - xpix]))
+                    || (yp + 1 < last && differs(c, coarse[ci + xp + xpix]));
+                let base = (r * s * row_len) + (xp * s);
+                for j in 0..s {
+                    let offs = base + (j * row_len);
+                    if edge {
+                        samples.clear();
+                        iterate_row(f, &sdims, (yp * s) + j, xp * s, 1, s, limit, &mut samples);
+                        new_data[offs..(offs + s)].copy_from_slice(&samples);
+                    } else {
+                        new_data[offs..(offs + s)].fill(c);
+                    }
+                }
+            }
+        }
+
+        self.last_limit = limit;
+        self.data = new_data;
+        true
+    }
+
+    // Returns `false` if the iteration got cancelled before finishing.
+    fn reiterate(&mut self, limit: usize, watcher: &IterWatcher) -> bool {
+        if limit <= self.last_limit {
+            return true;
+        }
+        // Orbit traps and statistics are spread over the whole limit, so
+        // every point changes.
+        if self.orbit.is_some() {
+            return self.iterate(limit, watcher);
+        }
+
+        let check_period = self.dims.check_period;
+        let f: Box<dyn Fn(Cx, usize) -> usize> = match self.itertype.clone() {
+            IterType::Mandlebrot => {
+                Box::new(move |c, limit| mandlebrot_iterator(c, limit, check_period))
+            }
+            IterType::PseudoMandlebrot { a, b } => pseudomandle_maker(a, b),
+            IterType::Polynomial { coefs } => polyiter_maker(coefs, check_period),
+            IterType::Tricorn => Box::new(move |c, limit| tricorn_iterator(c, limit, check_period)),
+            t @ (IterType::Exponential | IterType::Sine | IterType::Cosine) => {
+                transcendental_iterator(&t, check_period)
+            }
+        };
+
+        let s = self.dims.samples();
+        let sdims = self.dims.sample_dims();
+        let row_len = sdims.xpix;
+        let adaptive = self.dims.adaptive && s > 1;
+        for r in 0..self.n_rows {
+            if watcher.is_cancelled() {
+                return false;
+            }
+            let yp = (self.y_start + r) * s;
+            for xp in (0..row_len).step_by(s) {
+                let base = (r * s * row_len) + xp;
+                // When sampling adaptively, pixels whose samples are all the
+                // same only ever had their first sample iterated, so that's
+                // all that gets reiterated.
+                let flat = adaptive
+                    && (0..s).all(|j| {
+                        let offs = base + (j * row_len);
+                        self.data[offs..(offs + s)]
+                            .iter()
+                            .all(|n| *n == self.last_limit)
+                    });
+                if flat {
+                    let (x, y) = mapping::pixel_to_plane(&sdims, xp, yp);
+                    let n = f(Cx { re: x, im: y }, limit);
+                    for j in 0..s {
+                        let offs = base + (j * row_len);
+                        self.data[offs..(offs + s)].fill(n);
+                    }
+                    continue;
+                }
+                for j in 0..s {
+                    for i in 0..s {
+                        let idx = base + (j * row_len) + i;
+                        if self.data[idx] == self.last_limit {
+                            let (x, y) = mapping::pixel_to_plane(&sdims, xp + i, yp + j);
+                            self.data[idx] = f(Cx { re: x, im: y }, limit);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.last_limit = limit;
+        true
+    }
+}
+
+/**
+Represents an "iteration map", that is, a mapping from each pixel in an
+image to how many iterations the associated point takes to diverge.
+
+This structure, combined with a `ColorMap` is all the information needed
+to produce an image.
+*/
+#[derive(Clone)]
+pub struct IterMap {
+    dims: ImageDims,
+    itertype: IterType,
+    limit: usize,
+    orbit: Option<Orbit>,
+    chunks: Vec<IterMapChunk>,
+    // The view the chunks are rows of, and the row of it this map starts
+    // at: `dims` and 0, unless this is a band of it (see `with_rows()`).
+    whole: ImageDims,
+    top: usize,
+    // Maps iterated for layers whose counts this one's won't do for (see
+    // `layer_image()`). They have the same dimensions and iterator, so
+    // clones can share them.
+    layer_maps: Arc<Mutex<Vec<IterMap>>>,
+}
+
+impl IterMap {
+    /** Generate a new `IterMap` from the given information. */
+    pub fn new(dims: ImageDims, itertype: IterType, limit: usize) -> IterMap {
+        IterMap::with_orbit(dims, itertype, limit, None)
+    }
+
+    /**
+    Generate a new `IterMap` whose counts say how close each point's orbit
+    comes to a trap, or what it averages, as `orbit` says (see `Orbit`), or
+    how long it takes to escape, if that's `None`.
+    */
+    pub fn with_orbit(
+        dims: ImageDims,
+        itertype: IterType,
+        limit: usize,
+        orbit: Option<Orbit>,
+    ) -> IterMap {
+        IterMap::new_watched(dims, itertype, limit, orbit, &IterWatcher::new())
+            .expect("uncancellable iteration got cancelled")
+    }
+
+    /**
+    Generate a new `IterMap` (colored by an orbit trap or statistic,
+    optionally, like `with_orbit()`), reporting progress to (and obeying cancellation from)
+    the supplied `watcher`. Returns `None` if cancelled.
+    */
+    pub fn new_watched(
+        dims: ImageDims,
+        itertype: IterType,
+        limit: usize,
+        orbit: Option<Orbit>,
+        watcher: &IterWatcher,
+    ) -> Option<IterMap> {
+        IterMap::new_window(dims, 0, dims.ypix, itertype, limit, orbit, watcher)
+    }
+
+    /**
+    Generate a new `IterMap` of just the `n_rows` rows of `dims` starting
+    at row `start`, for images too big to iterate all at once. It's
+    iterated as part of the whole view, so adaptively oversampled pixels
+    at the edges of the band get compared with their neighbors outside it,
+    and stacking the colored bands gives exactly the image of the whole.
+    Its layers (see `iterate_layers()`) are made the same way.
+    */
+    pub fn with_rows(
+        dims: ImageDims,
+        start: usize,
+        n_rows: usize,
+        itertype: IterType,
+        limit: usize,
+        orbit: Option<Orbit>,
+    ) -> IterMap {
+        IterMap::new_window(
+            dims,
+            start,
+            n_rows,
+            itertype,
+            limit,
+            orbit,
+            &IterWatcher::new(),
+        )
+        .expect("uncancellable iteration got cancelled")
+    }
+
+    fn new_window(
+        whole: ImageDims,
+        top: usize,
+        n_rows: usize,
+        itertype: IterType,
+        limit: usize,
+        orbit: Option<Orbit>,
+        watcher: &IterWatcher,
+    ) -> Option<IterMap> {
+        let n_chunks = CHUNKS_PER_THREAD * *N_THREADS;
+        let chunk_height = n_rows / n_chunks;
+        let last_chunk_height = n_rows % n_chunks;
+
+        let mut to_process: Vec<IterMapChunk> = Vec::new();
+        let mut start_y: usize = top;
+        for _ in 0..n_chunks {
+            let imc = IterMapChunk {
+                dims: whole,
+                itertype: itertype.clone(),
+                y_start: start_y,
+                n_rows: chunk_height,
+                last_limit: 0,
+                orbit,
+                data: Vec::new(),
+                failure: None,
+            };
+            to_process.push(imc);
+            start_y += chunk_height;
+        }
+        if last_chunk_height > 0 {
+            let imc = IterMapChunk {
+                dims: whole,
+                itertype: itertype.clone(),
+                y_start: start_y,
+                n_rows: last_chunk_height,
+                last_limit: 0,
+                orbit,
+                data: Vec::new(),
+                failure: None,
+            };
+            to_process.push(imc);
+        }
+
+        let n_chunks = to_process.len();
+        watcher.start(n_chunks, limit);
+        let finished = POOL.install(|| {
+            to_process.par_iter_mut().all(|imc| {
+                let finished = imc.run(|c| c.iterate_budgeted(limit, watcher));
+                if finished {
+                    watcher.chunk_done(imc, n_chunks);
+                }
+                finished
+            })
+        });
+
+        if !finished {
+            return None;
+        }
+
+        let dims = if (top, n_rows) == (0, whole.ypix) {
+            whole
+        } else {
+            whole.rows(top, n_rows)
+        };
+        Some(IterMap {
+            dims,
+            itertype,
+            limit,
+            orbit,
+            chunks: to_process,
+            whole,
+            top,
+            layer_maps: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    /**
+    Extend the current `IterMap` to more steps.
+
+    This is useful if the image dimensions and iteration parameters haven't
+    changed, but there's a new target `ColorMap` that's of longer length.
+
+    This method will grovel through all the points in the `IterMap` and
+    re-iterate only those who have the previous maximum value.
+    */
+    pub fn reiterate(&mut self, limit: usize) {
+        self.reiterate_watched(limit, &IterWatcher::new());
+    }
+
+    /**
+    Extend the current `IterMap` to more steps, reporting progress to (and
+    obeying cancellation from) the supplied `watcher`.
+
+    Returns `false` if cancelled, in which case the `IterMap` is left in
+    an inconsistent state and should be thrown away.
+    */
+    pub fn reiterate_watched(&mut self, limit: usize, watcher: &IterWatcher) -> bool {
+        #[cfg(debug_assertions)]
+        println!("reiteration! {}", limit);
+
+        if limit <= self.limit && !self.is_approximate() {
+            return true;
+        }
+
+        let n_chunks = self.chunks.len();
+        watcher.start(n_chunks, limit);
+        let finished = POOL.install(|| {
+            self.chunks.par_iter_mut().all(|imc| {
+                // A chunk that failed last time has nothing to build on.
+                // One that's still fine at its old limit when time is up
+                // just stays that way (and the map approximate).
+                let finished = imc.run(|c| {
+                    if c.failure.is_some() {
+                        c.iterate_budgeted(limit, watcher)
+                    } else if watcher.out_of_time() {
+                        true
+                    } else {
+                        c.reiterate(limit, watcher)
                     }
+                });
+                if finished {
+                    watcher.chunk_done(imc, n_chunks);
                 }
-                let avg_p = RGB::average(&palette[0..pp]);
-                for b in avg_p.to_rgb8().iter() {
-                    rgb8_data.push(*b);
+                finished
+            })
+        });
+
+        if finished {
+            self.limit = self.limit.max(limit);
+        }
+        finished
+    }
+
+    pub fn dims(&self) -> ImageDims {
+        self.dims
+    }
+    pub fn itertype(&self) -> &IterType {
+        &self.itertype
+    }
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+    /**
+    Return whether this map was made with periodicity checking on (see
+    `ImageDims`). Any reiteration is done the same way.
+    */
+    pub fn periodicity_checked(&self) -> bool {
+        self.dims.check_period
+    }
+    /** The orbit trap or statistic the map's counts measure, if any. */
+    pub fn orbit(&self) -> Option<Orbit> {
+        self.orbit
+    }
+
+    /**
+    Return the number of rows of the image that couldn't be iterated
+    because the iteration panicked. These get colored with a pattern of
+    magenta and black stripes, rather than with the `ColorMap`.
+    */
+    pub fn failed_rows(&self) -> usize {
+        self.chunks
+            .iter()
+            .filter(|c| c.failure.is_some())
+            .map(|c| c.n_rows)
+            .sum()
+    }
+
+    /**
+    Return whether some of the map was iterated to a lower limit than the
+    rest because the iteration ran out of time (see
+    `IterWatcher::set_deadline()`). Reiterating it will finish the job.
+    */
+    pub fn is_approximate(&self) -> bool {
+        self.chunks
+            .iter()
+            .any(|c| c.failure.is_none() && c.last_limit < self.limit)
+    }
+
+    /**
+    Return the iteration count of pixel (`x`, `y`) (or of its first sample,
+    if the map is oversampled), or `None` if there isn't one: if it's off
+    the edge of the map, or iterating its row panicked. A count that's
+    reached `limit()` means the point never escaped.
+    */
+    pub fn count_at(&self, x: usize, y: usize) -> Option<usize> {
+        if x >= self.dims.xpix {
+            return None;
+        }
+        let y = y + self.top;
+        let chunk = self
+            .chunks
+            .iter()
+            .find(|c| (c.y_start..(c.y_start + c.n_rows)).contains(&y))?;
+        if chunk.failure.is_some() {
+            return None;
+        }
+        let s = self.dims.samples();
+        let row_len = self.dims.xpix * s;
+        chunk
+            .data
+            .get((y - chunk.y_start) * s * row_len + x * s)
+            .copied()
+    }
+
+    /** Return the message from the first panic, if there were any. */
+    pub fn failure(&self) -> Option<&str> {
+        self.chunks.iter().find_map(|c| c.failure.as_deref())
+    }
+
+    /**
+    Write the map's counts to `w` (compressed), so they can be read back
+    with `read_counts()` instead of iterating all over again. Maps with
+    failed rows (see `failed_rows()`) can't be saved.
+    */
+    pub fn write_counts<W: Write>(&self, w: W) -> Result<(), String> {
+        if self.limit > u32::MAX as usize {
+            return Err(format!("can't save counts past {}", u32::MAX));
+        }
+        if self.failed_rows() > 0 {
+            return Err("can't save counts with rows that failed to iterate".to_string());
+        }
+        let werr = |e: std::io::Error| format!("Error writing counts: {}", &e);
+        let mut w = flate2::write::DeflateEncoder::new(w, flate2::Compression::default());
+        let mut buff: Vec<u8> = Vec::new();
+        buff.extend_from_slice(COUNTS_MAGIC);
+        let key = counts_key(&self.dims, &self.itertype, self.orbit);
+        buff.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        buff.extend_from_slice(key.as_bytes());
+        buff.extend_from_slice(&(self.limit as u32).to_le_bytes());
+        buff.push(self.dims.check_period as u8);
+        buff.extend_from_slice(&(self.chunks.len() as u32).to_le_bytes());
+        for chunk in self.chunks.iter() {
+            for &n in [chunk.y_start - self.top, chunk.n_rows, chunk.last_limit]
+                .iter()
+                .chain(chunk.data.iter())
+            {
+                buff.extend_from_slice(&(n as u32).to_le_bytes());
+            }
+            w.write_all(&buff).map_err(werr)?;
+            buff.clear();
+        }
+        w.finish().map_err(werr)?;
+        Ok(())
+    }
+
+    /**
+    Read a map written by `write_counts()` from `r`. It has to have been
+    made with the given `dims`, `itertype` and `orbit`, or this returns an
+    error rather than a map of some other picture.
+    */
+    pub fn read_counts<R: Read>(
+        r: R,
+        dims: ImageDims,
+        itertype: IterType,
+        orbit: Option<Orbit>,
+    ) -> Result<IterMap, String> {
+        let key = counts_key(&dims, &itertype, orbit);
+        let s = dims.samples();
+        // Whatever is there, there's no need to unpack more of it than
+        // counts of the image could take up.
+        let most = (dims.xpix * s)
+            .checked_mul(dims.ypix * s)
+            .and_then(|n| n.checked_mul(4))
+            .and_then(|n| n.checked_add(COUNTS_MAGIC.len() + 13 + key.len()))
+            .and_then(|n| n.checked_add(12 * MAX_COUNT_CHUNKS))
+            .ok_or_else(|| "the image is too big to have saved counts".to_string())?;
+        let mut bytes: Vec<u8> = Vec::new();
+        let decoder = flate2::read::DeflateDecoder::new(r);
+        if let Err(e) = decoder.take(most as u64 + 1).read_to_end(&mut bytes) {
+            return Err(format!("Error reading counts: {}", &e));
+        }
+        if bytes.len() > most {
+            return Err("the saved counts are bigger than the image could have".to_string());
+        }
+        if !bytes.starts_with(COUNTS_MAGIC) {
+            return Err("not a file of saved counts".to_string());
+        }
+        let mut rest = &bytes[COUNTS_MAGIC.len()..];
+        let mut take = |n: usize| -> Result<&[u8], String> {
+            if rest.len() < n {
+                return Err("the saved counts are cut short".to_string());
+            }
+            let (head, tail) = rest.split_at(n);
+            rest = tail;
+            Ok(head)
+        };
+        fn word(b: &[u8]) -> usize {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize
+        }
+
+        let key_len = word(take(4)?);
+        if take(key_len)? != key.as_bytes() {
+            return Err("the saved counts are of a different image".to_string());
+        }
+        let limit = word(take(4)?);
+        // Whether periodicity was checked, which the key already covers.
+        take(1)?;
+        let n_chunks = word(take(4)?);
+        if n_chunks > MAX_COUNT_CHUNKS {
+            return Err("the saved counts are in too many pieces".to_string());
+        }
+        let mut chunks: Vec<IterMapChunk> = Vec::with_capacity(n_chunks.min(dims.ypix));
+        let mut y = 0;
+        for _ in 0..n_chunks {
+            let (y_start, n_rows) = (word(take(4)?), word(take(4)?));
+            let last_limit = word(take(4)?);
+            if y_start != y || n_rows > dims.ypix - y {
+                return Err("the saved counts don't cover the image".to_string());
+            }
+            y += n_rows;
+            let n = dims.xpix * s * n_rows * s;
+            let data = take(4 * n)?.chunks_exact(4).map(word).collect();
+            chunks.push(IterMapChunk {
+                dims,
+                itertype: itertype.clone(),
+                y_start,
+                n_rows,
+                last_limit,
+                orbit,
+                data,
+                failure: None,
+            });
+        }
+        if y != dims.ypix {
+            return Err("the saved counts don't cover the image".to_string());
+        }
+
+        Ok(IterMap {
+            dims,
+            itertype,
+            limit,
+            orbit,
+            chunks,
+            whole: dims,
+            top: 0,
+            layer_maps: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    /**
+    Color the map with the given `ColorMap`. If the map is oversampled,
+    each pixel is the average of the colors of its samples. The map's
+    shading, `Adjustment`, and curves are applied to the averaged pixels,
+    and then its layers (if any) are colored and blended in. Layers that
+    need counts of their own are left out until `iterate_layers()` has
+    made them.
+    */
+    pub fn color(&self, map: &ColorMap) -> FImage32 {
+        let n_pix = self.dims.xpix * self.dims.ypix;
+        let mut rgb_data: Vec<RGB> = Vec::with_capacity(n_pix);
+        let mut alpha: Option<Vec<f32>> = map.transparent.then(|| Vec::with_capacity(n_pix));
+
+        for chunk in self.chunks.iter() {
+            let lim = chunk.last_limit;
+            match (chunk.failure.is_some(), alpha.as_mut()) {
+                (true, alpha) => {
+                    mark_failed(&self.dims, chunk.y_start, chunk.n_rows, &mut rgb_data);
+                    if let Some(a) = alpha {
+                        a.resize(rgb_data.len(), 1.0);
+                    }
+                }
+                (false, Some(a)) => color_samples_alpha(
+                    &self.dims,
+                    &chunk.data,
+                    |n| map.get_opaque(escaped(n, lim)),
+                    map.default,
+                    &mut rgb_data,
+                    a,
+                ),
+                (false, None) => color_samples(
+                    &self.dims,
+                    &chunk.data,
+                    |n| map.get(escaped(n, lim)),
+                    &mut rgb_data,
+                ),
+            }
+        }
+        if let Some(shading) = map.shading {
+            let mut heights: Vec<f32> = Vec::with_capacity(n_pix);
+            for chunk in self.chunks.iter() {
+                if chunk.failure.is_some() {
+                    heights.resize(heights.len() + self.dims.xpix * chunk.n_rows, 0.0);
+                } else {
+                    sample_heights(&self.dims, &chunk.data, chunk.last_limit, &mut heights);
+                }
+            }
+            shading.apply(self.dims.xpix, &heights, &mut rgb_data);
+        }
+        map.finish(&mut rgb_data);
+        for layer in map.layers.iter().filter(|l| l.opacity > 0.0) {
+            let top = match self.layer_image(layer) {
+                Some(top) => top,
+                None => {
+                    continue;
                 }
+            };
+            for (px, t) in rgb_data.iter_mut().zip(top.data.iter()) {
+                *px = layer.blend.mix(*px, *t, layer.opacity);
             }
         }
 
-        (pix_cols, pix_lines, rgb8_data)
+        FImage32 {
+            dims: self.dims,
+            data: rgb_data,
+            alpha,
+        }
+    }
+
+    // Color `layer` with this map's counts, if they'll do for it, or else
+    // with those of a map `iterate_layers()` has made for it, if there's
+    // one yet.
+    fn layer_image(&self, layer: &LayerMap) -> Option<FImage32> {
+        if self.fits_layer(layer) {
+            return Some(self.color(&layer.map));
+        }
+        let cache = self.layer_maps.lock().unwrap();
+        cache
+            .iter()
+            .find(|m| m.fits_layer(layer))
+            .map(|imap| imap.color(&layer.map))
+    }
+
+    // Whether this map's counts measure what `layer` colors by, and go far
+    // enough. Escape times don't change when iterating further, but orbit
+    // counts are spread over the whole limit.
+    fn fits_layer(&self, layer: &LayerMap) -> bool {
+        let limit = layer.map.limit();
+        self.orbit == layer.orbit
+            && (self.limit == limit || (layer.orbit.is_none() && self.limit > limit))
+    }
+
+    // The first of the (visible) layers of `map` that no map has been
+    // iterated for yet.
+    fn missing_layer<'a>(&self, map: &'a ColorMap) -> Option<&'a LayerMap> {
+        let cache = self.layer_maps.lock().unwrap();
+        map.layers.iter().find(|l| {
+            l.opacity > 0.0 && !self.fits_layer(l) && !cache.iter().any(|m| m.fits_layer(l))
+        })
     }
 
     /**
-    Return the image data as a vector of 8-bit RGB color triples, scaled
-    down by a factor of `scale_factor` (a value of 1 will produce a
-    fill-sized image).
+    Return whether any of the layers of `map` need counts of their own
+    (see `iterate_layers()`) before `color()` can blend them in.
+    */
+    pub fn needs_layers(&self, map: &ColorMap) -> bool {
+        self.missing_layer(map).is_some()
+    }
 
-    This is the data format that most external things like.
+    /**
+    Iterate maps for the layers of `map` that color by something this
+    one's counts don't measure (or go further), reporting progress to (and
+    obeying cancellation from) `watcher`. They're kept (and shared with
+    this map's clones) for `color()` to use. Returns `false` if cancelled.
     */
-    pub fn to_rgb8(&self, scale_factor: usize) -> (usize, usize, Vec<u8>) {
-        if scale_factor < 2 {
-            (
-                self.dims.xpix,
+    pub fn iterate_layers(&self, map: &ColorMap, watcher: &IterWatcher) -> bool {
+        while let Some(layer) = self.missing_layer(map) {
+            let imap = IterMap::new_window(
+                self.whole,
+                self.top,
                 self.dims.ypix,
-                self.to_rgb8_full_resolution(),
-            )
-        } else if scale_factor > MAX_SCALE_FACTOR {
-            self.to_rgb8_scaled(MAX_SCALE_FACTOR)
-        } else {
-            self.to_rgb8_scaled(scale_factor)
+                self.itertype.clone(),
+                layer.map.limit(),
+                layer.orbit,
+                watcher,
+            );
+            match imap {
+                Some(imap) => self.layer_maps.lock().unwrap().push(imap),
+                None => {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /**
+    Return an image that's white where `pred` is true of the iteration
+    count and black everywhere else (and gray in oversampled pixels whose
+    samples disagree).
+
+    Scaling this down with `FImage32::to_rgb8()` gives the fraction of each
+    displayed pixel that's in the range, which is what the UI needs to
+    highlight a gradient's band of the image.
+    */
+    pub fn mask<F: Fn(usize) -> bool>(&self, pred: F) -> FImage32 {
+        let n_pix = self.dims.xpix * self.dims.ypix;
+        let mut data: Vec<RGB> = Vec::with_capacity(n_pix);
+        let in_range = |n| {
+            if pred(n) {
+                RGB::WHITE
+            } else {
+                RGB::BLACK
+            }
+        };
+
+        for chunk in self.chunks.iter() {
+            if chunk.failure.is_some() {
+                let n = self.dims.xpix * chunk.n_rows;
+                data.extend(std::iter::repeat_n(RGB::BLACK, n));
+            } else {
+                let lim = chunk.last_limit;
+                color_samples(
+                    &self.dims,
+                    &chunk.data,
+                    |n| in_range(escaped(n, lim)),
+                    &mut data,
+                );
+            }
+        }
+
+        FImage32 {
+            dims: self.dims,
+            data,
+            alpha: None,
         }
     }
 }
 
-/**
-A type to fully describe the type of iteration to be used.
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Relative tolerance for comparing positions on the plane.
+    const TOLERANCE: f64 = 1.0e-9;
+
+    fn close(a: f64, b: f64, scale: f64) -> bool {
+        (a - b).abs() <= TOLERANCE * scale.abs().max(1.0)
+    }
+
+    prop_compose! {
+        fn any_dims()(
+            xpix in 16usize..4096,
+            ypix in 16usize..4096,
+            x in -4.0f64..4.0,
+            y in -4.0f64..4.0,
+            width in 1.0e-6f64..8.0,
+        ) -> ImageDims {
+            ImageDims { xpix, ypix, x, y, width, oversample: 1, adaptive: false, check_period: true }
+        }
+    }
+
+    // Every point the shortcut says is inside had better not escape when
+    // iterated the long way, and the vectorized check has to agree with
+    // the scalar one.
+    #[test]
+    fn main_bulbs_never_escape() {
+        let limit = 2000;
+        for yi in 0..60 {
+            let im = -1.2 + (yi as f64) * 0.04;
+            for xi in (0..100).step_by(LANES) {
+                let re: Vec<f64> = (xi..xi + LANES).map(|n| -2.2 + (n as f64) * 0.03).collect();
+                let lanes = in_main_bulbs_lanes(
+                    f64x4::from([re[0], re[1], re[2], re[3]]),
+                    f64x4::splat(im),
+                )
+                .move_mask();
+                for (n, x) in re.iter().enumerate() {
+                    let c = Cx::rect(*x, im);
+                    let inside = in_main_bulbs(c);
+                    assert_eq!(inside, (lanes >> n) & 1 == 1);
+                    if inside {
+                        let mut z = Cx { re: 0.0, im: 0.0 };
+                        for _ in 0..limit {
+                            z = (z * z) + c;
+                        }
+                        assert!(z.sqmod() <= 4.0);
+                    }
+                }
+            }
+        }
+    }
+
+    // An oversampled image should come out exactly like a bigger image
+    // scaled down, and reiterating it should work like iterating it.
+    #[test]
+    fn oversampling_averages_samples() {
+        let dims = ImageDims {
+            xpix: 45,
+            ypix: 30,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 3,
+            adaptive: false,
+            check_period: false,
+        };
+        let spec = ColorSpec::new(vec![Gradient::default(); 2], RGB::BLACK);
+        let cmap = ColorMap::make(spec);
+
+        assert!(!dims.sample_dims().check_period);
+        let over = IterMap::new(dims, IterType::Mandlebrot, cmap.len());
+        let big = IterMap::new(dims.sample_dims(), IterType::Mandlebrot, cmap.len());
+        assert_eq!(over.color(&cmap).to_rgb8(1), big.color(&cmap).to_rgb8(3));
+
+        let mut stepped = IterMap::new(dims, IterType::Mandlebrot, cmap.len() / 2);
+        stepped.reiterate(cmap.len());
+        assert_eq!(over.color(&cmap).pixels(), stepped.color(&cmap).pixels());
+    }
+
+    // Cropping should cover the same part of the plane as the pixels it
+    // keeps, and pasting the pieces back should put the image back together.
+    #[test]
+    fn crop_and_paste() {
+        let dims = ImageDims {
+            xpix: 40,
+            ypix: 30,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        let spec = ColorSpec::new(vec![Gradient::default(); 2], RGB::BLACK);
+        let cmap = ColorMap::make(spec);
+        let img = IterMap::new(dims, IterType::Mandlebrot, cmap.len()).color(&cmap);
+
+        let rect = PixelRect::new(10, 5, 20, 15);
+        let piece = img.crop(rect).unwrap();
+        assert_eq!((piece.xpix(), piece.ypix()), (20, 15));
+        let (x, y) = mapping::pixel_to_plane(&dims, 10, 5);
+        let p_dims = piece.dims();
+        assert!(close(p_dims.x, x, 1.0) && close(p_dims.y, y, 1.0));
+        assert!(close(p_dims.width, 1.5, 1.0));
+        assert_eq!(piece.pixels()[0], img.pixels()[5 * 40 + 10]);
+        let fresh = IterMap::new(p_dims, IterType::Mandlebrot, cmap.len()).color(&cmap);
+        let n_diff = fresh
+            .pixels()
+            .iter()
+            .zip(piece.pixels().iter())
+            .filter(|(a, b)| a != b)
+            .count();
+        assert!(n_diff < 3);
+
+        // Hanging off the edge just gets clipped.
+        let corner = img.crop(PixelRect::new(35, 25, 20, 20)).unwrap();
+        assert_eq!((corner.xpix(), corner.ypix()), (5, 5));
+        assert!(img.crop(PixelRect::new(40, 0, 5, 5)).is_none());
+
+        let mut blank = FImage32::new(dims, RGB::WHITE);
+        blank.paste(&piece, 10, 5);
+        blank.paste(&corner, 35, 25);
+        assert_eq!(blank.crop(rect).unwrap().pixels(), piece.pixels());
+
+        // Shrinking by a factor that isn't a whole number still averages:
+        // each new pixel is a pixel and a half of the old ones.
+        let stripes = FImage32 {
+            dims: img.dims().resize(6, 2),
+            data: (0..12)
+                .map(|n| if n % 2 == 0 { RGB::WHITE } else { RGB::BLACK })
+                .collect(),
+            alpha: None,
+        };
+        let grey = stripes.resample(4, 1);
+        assert_eq!((grey.xpix(), grey.ypix()), (4, 1));
+        let whites: Vec<f32> = grey
+            .pixels()
+            .iter()
+            .map(|p| 3.0 * p.r() / RGB::WHITE.r())
+            .collect();
+        for (got, want) in whites.iter().zip([2.0, 2.0, 1.0, 1.0]) {
+            assert!((got - want).abs() < 1.0e-3);
+        }
+        assert_eq!(stripes.resample(6, 2).pixels(), stripes.pixels());
+        assert_eq!(blank.pixels()[0], RGB::WHITE);
+        assert_eq!(blank.pixels()[29 * 40 + 39], img.pixels()[29 * 40 + 39]);
+
+        let mut grey = FImage32::new(dims, RGB::BLACK);
+        grey.composite(&FImage32::new(dims, RGB::WHITE), 0, 0, 0.5);
+        assert_eq!(grey.pixels()[100], RGB::new(127.5, 127.5, 127.5));
+        grey.composite(&blank, 39, 29, 0.0);
+        assert_eq!(grey.pixels()[29 * 40 + 39], RGB::new(127.5, 127.5, 127.5));
+    }
+
+    // Adaptive sampling should only ever differ from full oversampling on
+    // pixels it decided were flat, and reiterating it should leave those
+    // flat.
+    #[test]
+    fn adaptive_oversampling() {
+        let dims = ImageDims {
+            xpix: 90,
+            ypix: 60,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 2,
+            adaptive: false,
+            check_period: true,
+        };
+        let adims = ImageDims {
+            adaptive: true,
+            ..dims
+        };
+        let spec = ColorSpec::new(vec![Gradient::default(); 2], RGB::BLACK);
+        let cmap = ColorMap::make(spec);
+
+        let full = IterMap::new(dims, IterType::Mandlebrot, cmap.len());
+        let adapt = IterMap::new(adims, IterType::Mandlebrot, cmap.len());
+        let full_px = full.color(&cmap);
+        let adapt_px = adapt.color(&cmap);
+        let flat_px = IterMap::new(adims.shrink(1), IterType::Mandlebrot, cmap.len()).color(&cmap);
+        let mut n_refined = 0;
+        for n in 0..full_px.pixels().len() {
+            if adapt_px.pixels()[n] != flat_px.pixels()[n] {
+                assert_eq!(adapt_px.pixels()[n], full_px.pixels()[n]);
+                n_refined += 1;
+            }
+        }
+        assert!(n_refined > 0);
+
+        let mut stepped = IterMap::new(adims, IterType::Mandlebrot, cmap.len() / 2);
+        stepped.reiterate(cmap.len());
+        let stepped_px = stepped.color(&cmap);
+        for n in 0..stepped_px.pixels().len() {
+            let p = stepped_px.pixels()[n];
+            assert!(p == full_px.pixels()[n] || p == flat_px.pixels()[n]);
+        }
+    }
+
+    // Zooming to a rectangle should take in all of it, fitting whichever
+    // side is the tighter fit, and keep the pixel size and sampling.
+    #[test]
+    fn rectangle_zooms() {
+        let dims = ImageDims {
+            xpix: 300,
+            ypix: 200,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 2,
+            adaptive: true,
+            check_period: true,
+        };
+        // A square in the middle third, across: the height fits exactly.
+        let v = dims.zoom_to(2.0 / 3.0, 0.25, 1.0 / 3.0, 0.75);
+        assert!(close(v.height(), 1.0, 1.0) && close(v.width, 1.5, 1.0));
+        let (c_x, c_y) = v.center();
+        assert!(close(c_x, -0.5, 1.0) && close(c_y, 0.0, 1.0));
+        assert_eq!(
+            (v.xpix, v.ypix, v.oversample, v.adaptive),
+            (300, 200, 2, true)
+        );
+
+        // A wide, flat one: the width fits.
+        let v = dims.zoom_to(0.0, 0.0, 0.5, 0.1);
+        assert!(close(v.x, -2.0, 1.0) && close(v.width, 1.5, 1.0));
+    }
+
+    // The default Mandlebrot view is the one the program starts with, and
+    // the default views of other iterators should frame their whole sets.
+    #[test]
+    fn default_views() {
+        let dims = ImageDims {
+            xpix: 900,
+            ypix: 600,
+            x: 0.3,
+            y: 0.2,
+            width: 0.01,
+            oversample: 2,
+            adaptive: false,
+            check_period: true,
+        };
+        let m = IterType::Mandlebrot.default_view(&dims);
+        assert!(close(m.x, -2.0, 1.0) && close(m.y, 1.0, 1.0) && close(m.width, 3.0, 1.0));
+        assert_eq!((m.xpix, m.ypix, m.oversample), (900, 600, 2));
+
+        let pm = IterType::PseudoMandlebrot {
+            a: Cx::rect(1.0, 0.0),
+            b: Cx::rect(1.0, 0.0),
+        };
+        assert_eq!(pm.default_view(&dims), m);
 
-This, combined with an iteration limit (the length of a target `ColorMap`)
-is all the information required for iterating a point.
-*/
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-#[serde(tag = "type")]
-pub enum IterType {
-    Mandlebrot,
-    PseudoMandlebrot { a: Cx, b: Cx },
-    Polynomial { coefs: Vec<Cx> },
-}
+        // With ab = 2i, the set is shrunk by half and turned sideways.
+        let pm = IterType::PseudoMandlebrot {
+            a: Cx::rect(2.0, 0.0),
+            b: Cx::rect(0.0, 1.0),
+        };
+        let v = pm.default_view(&dims);
+        let (c_x, c_y) = v.center();
+        assert!(close(c_x, 0.0, 1.0) && close(c_y, 0.25, 1.0));
+        assert!(v.height() >= 1.5 - TOLERANCE && v.width >= 1.0 - TOLERANCE);
 
-/* Iterate a point using the Mandlebrot iterator. */
-fn mandlebrot_iterator(c: Cx, limit: usize) -> usize {
-    let mut z = Cx { re: 0.0, im: 0.0 };
+        // Everything on the edge of a Julia set's default view should
+        // escape right away. (Escaping means reaching SQ_MOD_LIMIT, which
+        // takes about ten squarings even from well outside the set.)
+        let poly = IterType::Polynomial {
+            coefs: vec![
+                Cx::rect(-0.8, 0.156),
+                Cx::rect(0.0, 0.0),
+                Cx::rect(1.0, 0.0),
+            ],
+        };
+        let small = ImageDims {
+            xpix: 90,
+            ypix: 60,
+            ..dims
+        };
+        let v = poly.default_view(&small);
+        let imap = IterMap::new(v.sample_dims(), poly, 256);
+        let counts: Vec<usize> = imap.chunks.iter().flat_map(|c| c.data.clone()).collect();
+        let row_len = v.sample_dims().xpix;
+        assert!(counts[..row_len].iter().all(|&n| n < 16));
+        assert!(counts[(counts.len() - row_len)..].iter().all(|&n| n < 16));
+    }
 
-    for n in 0..limit {
-        z = (z * z) + c;
-        if z.sqmod() > SQ_MOD_LIMIT {
-            return n;
+    #[test]
+    fn tricorn() {
+        for n in 0..40 {
+            let re = -2.2 + 0.08 * n as f64;
+            // On the real axis, it's just the Mandlebrot set...
+            let c = Cx::rect(re, 0.0);
+            assert_eq!(
+                tricorn_iterator(c, 200, false),
+                mandlebrot_iterator(c, 200, false)
+            );
+            // ...and it's symmetric across it.
+            let c = Cx::rect(re, 0.37);
+            assert_eq!(
+                tricorn_iterator(c, 200, false),
+                tricorn_iterator(c.conj(), 200, false)
+            );
         }
+        // Unlike the Mandlebrot set, it doesn't have i in it.
+        assert_eq!(mandlebrot_iterator(Cx::rect(0.0, 1.0), 200, false), 200);
+        assert!(tricorn_iterator(Cx::rect(0.0, 1.0), 200, false) < 200);
     }
-    limit
-}
 
-/*
-Generate and return a function (a closure) to iterate a point using a
-Pseudo-Mandlebrot iterator.
+    #[test]
+    fn orbit_traps() {
+        let point = Trap::Point {
+            center: Cx::rect(1.0, 1.0),
+            scale: 2.0,
+        };
+        let line = Trap::Line {
+            center: Cx::rect(0.0, 1.0),
+            angle_pi: 0.25,
+            scale: 2.0,
+        };
+        let circle = Trap::Circle {
+            center: Cx::rect(0.0, 0.0),
+            radius: 2.0,
+            scale: 2.0,
+        };
+        let z = Cx::rect(4.0, 5.0);
+        assert!(close(point.distance(z), 5.0, 1e-12));
+        assert!(close(line.distance(z), 0.0, 1e-12));
+        assert!(close(circle.distance(z), 41.0_f64.sqrt() - 2.0, 1e-12));
+        assert!(close(circle.distance(Cx::rect(0.0, 0.5)), 1.5, 1e-12));
+        // Halfway to the scale gets halfway along the map; past it, and
+        // never getting there at all, both get the last count.
+        assert_eq!(point.count(1.0, 100), 50);
+        assert_eq!(point.count(0.0, 100), 0);
+        assert_eq!(point.count(5.0, 100), 99);
+        assert_eq!(point.count(f64::INFINITY, 100), 99);
 
-I'm not sure if this is a real thing, but it's what I'm calling it. The
-_Mandlebrot_ iterator uses the function
+        let mut spec = ColorSpec::new(vec![Gradient::default()], RGB::BLACK);
+        assert!(!toml::to_string(&spec).unwrap().contains("trap"));
+        spec.set_orbit(Some(Orbit::Trap(line)));
+        let text = toml::to_string(&spec).unwrap();
+        assert!(text.contains("shape = \"Line\""));
+        assert_eq!(toml::from_str::<ColorSpec>(&text).unwrap(), spec);
 
-   f(z) = z^2 + c
+        let dims = ImageDims {
+            xpix: 40,
+            ypix: 30,
+            x: -2.0,
+            y: 1.2,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        for itype in [IterType::Mandlebrot, IterType::Sine] {
+            let limit = 256;
+            let imap = IterMap::with_orbit(dims, itype.clone(), limit, Some(Orbit::Trap(circle)));
+            let counts: Vec<usize> = imap.chunks.iter().flat_map(|c| c.data.clone()).collect();
+            assert!(counts.iter().all(|&n| n < limit));
+            // Not everything should land in the same place.
+            assert!(counts.iter().any(|&n| n != counts[0]));
 
-to iterate a given point _c_. A _Pseudo_-Mandlebrot iterator is a parametrized
-mapping, such that for a given complex (a, b),
+            let mut redone = IterMap::with_orbit(dims, itype, limit / 2, Some(Orbit::Trap(circle)));
+            redone.reiterate(limit);
+            let again: Vec<usize> = redone.chunks.iter().flat_map(|c| c.data.clone()).collect();
+            assert_eq!(again, counts);
+        }
+    }
 
-   f(a, b)(z) = az^2 + bc
+    #[test]
+    fn orbit_stats() {
+        let stripes = OrbitStat::Stripe { density: 2.0 };
+        let zero = Cx::rect(0.0, 0.0);
+        let term = |stat: OrbitStat, w: Cx, k: Cx| stat.term(w, k, w + k).unwrap();
+        assert!(close(term(stripes, Cx::rect(1.0, 0.0), zero), 0.5, 1e-12));
+        // A quarter turn, doubled, is a half turn: back where it started.
+        assert!(close(term(stripes, Cx::rect(0.0, 1.0), zero), 0.5, 1e-12));
+        // An eighth of a turn, doubled, is a quarter: the top of the sine.
+        assert!(close(term(stripes, Cx::rect(1.0, 1.0), zero), 1.0, 1e-12));
 
-iterates the given point _c_.
-*/
-fn pseudomandle_maker(a: Cx, b: Cx) -> Box<dyn Fn(Cx, usize) -> usize> {
-    let f = move |c, limit| {
-        let mut z = Cx { re: 0.0, im: 0.0 };
-        let pseudo_c = b * c;
+        // Parts lined up make the biggest sum; opposed, the smallest.
+        let tri = OrbitStat::Triangle;
+        assert!(close(
+            term(tri, Cx::rect(3.0, 0.0), Cx::rect(1.0, 0.0)),
+            1.0,
+            1e-12
+        ));
+        assert!(close(
+            term(tri, Cx::rect(3.0, 0.0), Cx::rect(-1.0, 0.0)),
+            0.0,
+            1e-12
+        ));
+        // With nothing added, there's nothing to measure.
+        assert_eq!(tri.term(Cx::rect(3.0, 0.0), zero, Cx::rect(3.0, 0.0)), None);
 
-        for n in 0..limit {
-            z = (a * z * z) + pseudo_c;
-            if z.sqmod() > SQ_MOD_LIMIT {
-                return n;
-            }
+        // Setting a statistic clears a trap, and the other way around.
+        let mut spec = ColorSpec::new(vec![Gradient::default()], RGB::BLACK);
+        spec.set_orbit(Some(Orbit::Stat(stripes)));
+        let text = toml::to_string(&spec).unwrap();
+        assert!(text.contains("kind = \"Stripe\"") && !text.contains("trap"));
+        assert_eq!(toml::from_str::<ColorSpec>(&text).unwrap(), spec);
+        spec.set_orbit(Some(Orbit::Trap(Trap::Point {
+            center: zero,
+            scale: 1.0,
+        })));
+        assert!(!toml::to_string(&spec).unwrap().contains("kind"));
+
+        let dims = ImageDims {
+            xpix: 40,
+            ypix: 30,
+            x: -2.0,
+            y: 1.2,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        let limit = 256;
+        for stat in [stripes, tri] {
+            let imap =
+                IterMap::with_orbit(dims, IterType::Mandlebrot, limit, Some(Orbit::Stat(stat)));
+            let counts: Vec<usize> = imap.chunks.iter().flat_map(|c| c.data.clone()).collect();
+            assert!(counts.iter().all(|&n| n < limit));
+            assert!(counts.iter().any(|&n| n != counts[0]));
         }
-        limit
-    };
-    Box::new(f)
-}
+        // There's nothing for the triangle inequality to measure here.
+        let imap = IterMap::with_orbit(dims, IterType::Exponential, limit, Some(Orbit::Stat(tri)));
+        assert!(imap.chunks.iter().all(|c| c.data.iter().all(|&n| n == 0)));
+    }
 
-/*
-Generate and return a function (a closure) to iterate a point using an
-arbitrary polynomial iterator.
+    #[test]
+    fn layers() {
+        let (a, b) = (RGB::new(51.0, 204.0, 255.0), RGB::new(102.0, 102.0, 0.0));
+        let mix = |blend: Blend, opacity| blend.mix(a, b, opacity).to_rgb8();
+        assert_eq!(mix(Blend::Normal, 1.0), b.to_rgb8());
+        assert_eq!(mix(Blend::Screen, 0.0), a.to_rgb8());
+        assert_eq!(mix(Blend::Normal, 0.5), [76, 153, 127]);
+        assert_eq!(mix(Blend::Multiply, 1.0), [20, 81, 0]);
+        assert_eq!(mix(Blend::Screen, 1.0), [132, 224, 255]);
+        // Dark parts multiply (twice over), light parts screen.
+        assert_eq!(mix(Blend::Overlay, 1.0), [40, 193, 255]);
 
-Given a vector `v` of complex coefficients, this function will generate
-the iteration function
+        let base = ColorSpec::new(vec![Gradient::default()], RGB::BLACK);
+        let mut top = ColorSpec::new(
+            vec![Gradient {
+                start: RGB::WHITE,
+                end: RGB::WHITE,
+                steps: 50,
+                ..Gradient::default()
+            }],
+            RGB::WHITE,
+        );
+        top.set_orbit(Some(Orbit::Stat(OrbitStat::Triangle)));
+        let mut spec = base.clone();
+        spec.set_layers(vec![Layer {
+            blend: Blend::Multiply,
+            opacity: 1.0,
+            spec: top.clone(),
+        }]);
+        let text = toml::to_string(&spec).unwrap();
+        assert!(text.contains("[[layers]]") && text.contains("blend = \"Multiply\""));
+        assert_eq!(toml::from_str::<ColorSpec>(&text).unwrap(), spec);
 
-    f(z) = v[0]*z + v[1]*z^2 + v[2]*z^3 + ...
+        let dims = ImageDims {
+            xpix: 30,
+            ypix: 20,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 2,
+            adaptive: false,
+            check_period: true,
+        };
+        let cmap = ColorMap::make(base.clone());
+        let imap = IterMap::new(dims, IterType::Mandlebrot, cmap.limit());
+        let plain = imap.color(&cmap);
+        // Multiplying by white changes nothing, but the layer still needs
+        // its own iteration, which gets kept.
+        let layered_map = ColorMap::make(spec.clone());
+        assert!(imap.needs_layers(&layered_map));
+        assert!(imap.iterate_layers(&layered_map, &IterWatcher::new()));
+        assert!(!imap.needs_layers(&layered_map));
+        let layered = imap.color(&layered_map);
+        assert_eq!(layered.pixels(), plain.pixels());
+        assert_eq!(imap.layer_maps.lock().unwrap().len(), 1);
+        assert!(imap
+            .clone()
+            .iterate_layers(&layered_map, &IterWatcher::new()));
+        assert_eq!(imap.layer_maps.lock().unwrap().len(), 1);
 
-*/
-fn polyiter_maker(v: Vec<Cx>) -> Box<dyn Fn(Cx, usize) -> usize> {
-    let deg = v.len() - 1;
-    let f = move |c, limit| {
-        let mut z = c;
-        for n in 0..limit {
-            let mut tot = Cx { re: 0.0, im: 0.0 };
-            let mut w = Cx { re: 1.0, im: 0.0 };
-            for a in v[0..deg].iter() {
-                tot = tot + (*a) * w;
-                w = w * z;
-            }
-            tot = unsafe { tot + (*v.get_unchecked(deg) * w) };
-            z = tot;
-            if z.sqmod() > SQ_MOD_LIMIT {
-                return n;
-            }
+        // Escape time to a lower limit can use the map's own counts.
+        let mut spec = base.clone();
+        spec.set_layers(vec![Layer {
+            blend: Blend::Normal,
+            opacity: 1.0,
+            spec: ColorSpec::new(vec![Gradient::default(); 2], RGB::WHITE),
+        }]);
+        let limit = ColorMap::make(spec.layers()[0].spec.clone()).limit();
+        let imap = IterMap::new(dims, IterType::Mandlebrot, limit + 100);
+        let layered = imap.color(&ColorMap::make(spec.clone()));
+        assert!(!imap.needs_layers(&ColorMap::make(spec.clone())));
+        assert!(imap.layer_maps.lock().unwrap().is_empty());
+        assert_eq!(
+            layered.pixels(),
+            imap.color(&ColorMap::make(spec.layers()[0].spec.clone()))
+                .pixels()
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        let grads = vec![Gradient {
+            start: RGB::BLACK,
+            end: RGB::WHITE,
+            steps: 10,
+            space: Interpolation::Rgb,
+        }];
+        let inside = RGB::new(1.0, 2.0, 3.0);
+        let mut spec = ColorSpec::new(grads, inside);
+        spec.set_repeats(2);
+        assert_eq!((spec.limit(), spec.palette_limit()), (20, 20));
+        // Iterating less far than the palette goes doesn't do anything.
+        spec.set_iterations(15);
+        assert_eq!(spec.limit(), 20);
+        spec.set_iterations(50);
+        assert_eq!((spec.limit(), spec.palette_limit()), (50, 20));
+        assert_eq!(spec.color_index(25), None);
+
+        let red = RGB::new(255.0, 0.0, 0.0);
+        let get = |o: Overflow, n: usize| {
+            let mut spec = spec.clone();
+            spec.set_overflow(o);
+            ColorMap::make(spec).get(n)
+        };
+        let plain = ColorMap::make(spec.clone());
+        assert_eq!(get(Overflow::Interior, 25), inside);
+        assert_eq!(get(Overflow::Clamp, 25), plain.get(19));
+        assert_eq!(get(Overflow::Wrap, 25), plain.get(5));
+        assert_eq!(get(Overflow::Color { color: red }, 25), red);
+        // Points that never escape are inside, whatever the overflow.
+        for o in [
+            Overflow::Clamp,
+            Overflow::Wrap,
+            Overflow::Color { color: red },
+        ] {
+            assert_eq!(get(o, 50), inside);
+            assert_eq!(get(o, usize::MAX), inside);
+            assert_eq!(get(o, 7), plain.get(7));
         }
-        limit
-    };
 
-    Box::new(f)
-}
+        spec.set_overflow(Overflow::Color { color: red });
+        let text = toml::to_string(&spec).unwrap();
+        assert!(text.contains("iterations = 50"));
+        assert_eq!(toml::from_str::<ColorSpec>(&text).unwrap(), spec);
+        spec.set_overflow(Overflow::Wrap);
+        let text = toml::to_string(&spec).unwrap();
+        assert!(text.contains("[overflow]") && text.contains("kind = \"Wrap\""));
+        assert_eq!(toml::from_str::<ColorSpec>(&text).unwrap(), spec);
+        let plain = ColorSpec::new(vec![Gradient::default()], inside);
+        let text = toml::to_string(&plain).unwrap();
+        assert!(!text.contains("overflow") && !text.contains("iterations"));
+    }
 
-/*
-A description of a portion of an image to be iterated, suitable to be processed
-in parallel with other `IterMapChunk`s. Together with the length of a target
-`ColorMap`, this is all the information required to make an iteration map
-for the specified portion of an image.
+    #[test]
+    fn transparency() {
+        let mut spec = ColorSpec::new(vec![Gradient::default()], RGB::WHITE);
+        let dims = ImageDims {
+            xpix: 30,
+            ypix: 20,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 3,
+            adaptive: false,
+            check_period: true,
+        };
+        let imap = IterMap::new(dims, IterType::Mandlebrot, spec.limit());
+        assert!(imap.color(&ColorMap::make(spec.clone())).alpha().is_none());
 
-Processing with `.iterate()` will fill the chunk's `.data` member with the
-actual iteration map values.
+        spec.set_transparent(true);
+        let img = imap.color(&ColorMap::make(spec.clone()));
+        let alpha = img.alpha().unwrap();
+        // The origin is inside, the corner outside, and somewhere in
+        // between is the edge, where pixels are only partly covered.
+        assert_eq!(alpha[10 * 30 + 20], 0.0);
+        assert_eq!(alpha[0], 1.0);
+        assert!(alpha.iter().any(|a| *a > 0.0 && *a < 1.0));
+        // The clear pixels don't pick up the interior color.
+        let (_, _, rgba) = img.to_bytes(1);
+        assert_eq!(rgba.len(), 4 * 30 * 20);
+        assert_eq!(rgba[4 * (10 * 30 + 20) + 3], 0);
+        let (x, y, half) = img.to_rgba8(2);
+        assert_eq!((x, y, half.len()), (15, 10, 4 * 15 * 10));
 
-Processing with `.reiterate()` will extend the iteration map to the new
-limit for only those points who were already at the last limit. The idea
-here is for redrawing an image where the only thing that has changed is
-the length of the `ColorMap`.
-*/
-struct IterMapChunk {
-    dims: ImageDims,
-    itertype: IterType,
-    y_start: usize,
-    n_rows: usize,
-    last_limit: usize,
-    data: Vec<usize>,
-}
+        let text = toml::to_string(&spec).unwrap();
+        assert!(text.contains("transparent = true"));
+        assert_eq!(toml::from_str::<ColorSpec>(&text).unwrap(), spec);
+        spec.set_transparent(false);
+        assert!(!toml::to_string(&spec).unwrap().contains("transparent"));
+    }
 
-impl IterMapChunk {
-    fn iterate(&mut self, limit: usize) {
-        let n_pix = self.dims.xpix * self.n_rows;
-        let mut new_data: Vec<usize> = Vec::with_capacity(n_pix);
-        let f_xpix = self.dims.xpix as f64;
-        let f_ypix = self.dims.ypix as f64;
-        let height = self.dims.height();
-        let f = match self.itertype.clone() {
-            IterType::Mandlebrot => Box::new(mandlebrot_iterator),
-            IterType::PseudoMandlebrot { a, b } => pseudomandle_maker(a, b),
-            IterType::Polynomial { coefs } => polyiter_maker(coefs),
+    #[test]
+    fn shading() {
+        let grey = RGB::new(100.0, 100.0, 100.0);
+        let light = Shading {
+            azimuth: 0.0,
+            elevation: 45.0,
+            depth: 1.0,
+            strength: 1.0,
+        };
+        // A ramp rising to the right, facing away from a light on the
+        // right, between two flat stretches.
+        let heights = [0.0, 0.0, 1.0, 2.0, 2.0, 2.0];
+        let mut colors = vec![grey; 6];
+        light.apply(6, &heights, &mut colors);
+        assert_eq!(colors[5], grey);
+        assert!(colors[2].r < grey.r);
+        let mut colors = vec![grey; 6];
+        let left = Shading {
+            azimuth: 180.0,
+            ..light
         };
+        left.apply(6, &heights, &mut colors);
+        assert!(colors[2].r > grey.r);
+        // No strength, no change.
+        let mut colors = vec![grey; 6];
+        Shading {
+            strength: 0.0,
+            ..light
+        }
+        .apply(6, &heights, &mut colors);
+        assert_eq!(colors, vec![grey; 6]);
 
-        for yp in self.y_start..(self.y_start + self.n_rows) {
-            let y_frac = (yp as f64) / f_ypix;
-            let y = self.dims.y - (y_frac * height);
-            for xp in 0..self.dims.xpix {
-                let x_frac = (xp as f64) / f_xpix;
-                let x = self.dims.x + (x_frac * self.dims.width);
-                let n = f(Cx { re: x, im: y }, limit);
-                new_data.push(n);
+        let odd = Shading {
+            azimuth: -90.0,
+            elevation: 0.0,
+            depth: f64::NAN,
+            strength: 2.0,
+        };
+        assert_eq!(
+            odd.clamped(),
+            Shading {
+                azimuth: 270.0,
+                elevation: 5.0,
+                depth: Shading::default().depth,
+                strength: 1.0,
             }
-        }
+        );
 
-        self.last_limit = limit;
-        self.data = new_data;
+        let mut spec = ColorSpec::new(vec![Gradient::default()], RGB::BLACK);
+        spec.set_shading(Some(Shading::default()));
+        let text = toml::to_string(&spec).unwrap();
+        assert!(text.contains("[shading]"));
+        assert_eq!(toml::from_str::<ColorSpec>(&text).unwrap(), spec);
     }
 
-    fn reiterate(&mut self, limit: usize) {
-        if limit < self.last_limit {
-            return;
+    #[test]
+    fn transcendental_iterators() {
+        let count = |itype: IterType, c: Cx| transcendental_iterator(&itype, false)(c, 100);
+        // Small multipliers have an attracting fixed point at 0.
+        for itype in [IterType::Exponential, IterType::Sine, IterType::Cosine] {
+            assert_eq!(count(itype, Cx::rect(0.1, 0.05)), 100);
         }
+        // 2, 2e^2 ≈ 15, 2e^15 ≈ 6.5 million: gone.
+        assert_eq!(count(IterType::Exponential, Cx::rect(2.0, 0.0)), 2);
+        // A big negative real part doesn't count as escaping for exp...
+        assert_eq!(count(IterType::Exponential, Cx::rect(-60.0, 0.0)), 100);
+        // ...and a big real part doesn't for sin, unlike a big imaginary one.
+        assert_eq!(count(IterType::Sine, Cx::rect(0.0, 60.0)), 0);
+        assert!(count(IterType::Sine, Cx::rect(0.5, 10.0)) <= 1);
 
-        let f_xpix = self.dims.xpix as f64;
-        let f_ypix = self.dims.ypix as f64;
-        let height = self.dims.height();
-        let f = match self.itertype.clone() {
-            IterType::Mandlebrot => Box::new(mandlebrot_iterator),
-            IterType::PseudoMandlebrot { a, b } => pseudomandle_maker(a, b),
-            IterType::Polynomial { coefs } => polyiter_maker(coefs),
+        let itype: IterType = toml::from_str("type = \"Cosine\"").unwrap();
+        assert_eq!(itype, IterType::Cosine);
+    }
+
+    // Periodicity checking should only ever turn a count into the limit
+    // (when it decides a point is cycling), and hardly ever wrongly.
+    #[test]
+    fn periodicity_checking() {
+        let dims = ImageDims {
+            xpix: 150,
+            ypix: 100,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
         };
+        let itertypes = vec![
+            IterType::Mandlebrot,
+            IterType::Polynomial {
+                coefs: vec![
+                    Cx::rect(-0.8, 0.156),
+                    Cx::rect(0.0, 0.0),
+                    Cx::rect(1.0, 0.0),
+                ],
+            },
+            IterType::Tricorn,
+            IterType::Sine,
+        ];
+        let limit = 1000;
 
-        let mut idx: usize = 0;
-        for yp in self.y_start..(self.y_start + self.n_rows) {
-            let y_frac = (yp as f64) / f_ypix;
-            let y = self.dims.y - (y_frac * height);
-            for xp in 0..self.dims.xpix {
-                if self.data[idx] == self.last_limit {
-                    let x_frac = (xp as f64) / f_xpix;
-                    let x = self.dims.x + (x_frac * self.dims.width);
-                    let n = f(Cx { re: x, im: y }, limit);
-                    self.data[idx] = n;
+        for it in itertypes.into_iter() {
+            let watcher = IterWatcher::new();
+            let unchecked = ImageDims {
+                check_period: false,
+                ..dims
+            };
+            let plain = IterMap::new_watched(unchecked, it.clone(), limit, None, &watcher).unwrap();
+            let checked = IterMap::new_watched(dims, it, limit, None, &watcher).unwrap();
+            let plain: Vec<usize> = plain.chunks.iter().flat_map(|c| c.data.clone()).collect();
+            let checked: Vec<usize> = checked.chunks.iter().flat_map(|c| c.data.clone()).collect();
+            let mut n_diffs = 0;
+            for (p, c) in plain.iter().zip(checked.iter()) {
+                if p != c {
+                    assert_eq!(*c, limit);
+                    n_diffs += 1;
                 }
-                idx += 1;
             }
+            assert!(n_diffs * 1000 < plain.len());
         }
-
-        self.last_limit = limit;
     }
-}
 
-/**
-Represents an "iteration map", that is, a mapping from each pixel in an
-image to how many iterations the associated point takes to diverge.
+    // Every mapping should run from the first color to the last without
+    // ever going backward, and the map should color counts accordingly.
+    #[test]
+    fn transfer_mappings() {
+        let len = 300;
+        let mappings = [
+            Transfer::Linear,
+            Transfer::Log,
+            Transfer::Sqrt,
+            Transfer::Power { exponent: 0.3 },
+            Transfer::Power { exponent: 2.5 },
+            Transfer::Power { exponent: f64::NAN },
+        ];
+        for t in mappings.iter() {
+            assert_eq!(t.index(0, len), 0);
+            assert_eq!(t.index(len, len), len);
+            let idxs: Vec<usize> = (0..len).map(|n| t.index(n, len)).collect();
+            assert!(idxs.windows(2).all(|w| w[0] <= w[1]));
+            assert!(idxs.iter().all(|i| *i < len));
+        }
+        assert_eq!(Transfer::Sqrt.index(75, 300), 150);
+        assert_eq!(Transfer::Power { exponent: f64::NAN }.index(75, 300), 75);
+        assert!(Transfer::Log.index(30, 300) > 150);
 
-This structure, combined with a `ColorMap` is all the information needed
-to produce an image.
-*/
-pub struct IterMap {
-    dims: ImageDims,
-    itertype: IterType,
-    limit: usize,
-    chunks: Vec<IterMapChunk>,
-}
+        let grads = vec![
+            Gradient {
+                start: RGB::BLACK,
+                end: RGB::WHITE,
+                steps: 100,
+                space: Interpolation::Rgb,
+            },
+            Gradient {
+                start: RGB::WHITE,
+                end: RGB::BLACK,
+                steps: 200,
+                space: Interpolation::Rgb,
+            },
+        ];
+        let mut spec = ColorSpec::new(grads, RGB::BLACK);
+        spec.set_transfer(Transfer::Sqrt);
+        let cmap = ColorMap::make(spec.clone());
+        let linear = ColorMap::make(ColorSpec::new(spec.clone().gradients(), RGB::BLACK));
+        assert_eq!(cmap.len(), 300);
+        assert_eq!(cmap.get(75), linear.get(150));
+        assert_eq!(cmap.get(300), RGB::BLACK);
 
-impl IterMap {
-    /** Generate a new `IterMap` from the given information. */
-    pub fn new(dims: ImageDims, itertype: IterType, limit: usize) -> IterMap {
-        let n_chunks = CHUNKS_PER_THREAD * *N_THREADS;
-        let chunk_height = dims.ypix / n_chunks;
-        let last_chunk_height = dims.ypix % n_chunks;
+        // Counts below 100/300 of the way, squared, land in the first gradient.
+        assert_eq!(spec.gradient_at(33), Some(0));
+        assert_eq!(spec.gradient_at(34), Some(1));
+        assert_eq!(spec.gradient_at(300), None);
+    }
 
-        let mut to_process: Vec<IterMapChunk> = Vec::new();
-        let mut start_y: usize = 0;
-        for _ in 0..n_chunks {
-            let imc = IterMapChunk {
-                dims,
-                itertype: itertype.clone(),
-                y_start: start_y,
-                n_rows: chunk_height,
-                last_limit: 0,
-                data: Vec::new(),
-            };
-            to_process.push(imc);
-            start_y += chunk_height;
-        }
-        if last_chunk_height > 0 {
-            let imc = IterMapChunk {
-                dims,
-                itertype: itertype.clone(),
-                y_start: start_y,
-                n_rows: last_chunk_height,
-                last_limit: 0,
-                data: Vec::new(),
+    // A repeating palette should wrap around instead of running into the
+    // default color, and the phase should rotate it.
+    #[test]
+    fn cyclic_palettes() {
+        let grads = vec![
+            Gradient {
+                start: RGB::BLACK,
+                end: RGB::WHITE,
+                steps: 10,
+                space: Interpolation::Rgb,
+            },
+            Gradient {
+                start: RGB::WHITE,
+                end: RGB::BLACK,
+                steps: 30,
+                space: Interpolation::Rgb,
+            },
+        ];
+        let mut spec = ColorSpec::new(grads, RGB::new(1.0, 2.0, 3.0));
+        let plain = ColorMap::make(spec.clone());
+        spec.set_repeats(3);
+        assert_eq!(spec.limit(), 120);
+        let cmap = ColorMap::make(spec.clone());
+        assert_eq!((cmap.len(), cmap.limit()), (40, 120));
+        assert_eq!(cmap.get(85), plain.get(5));
+        assert_eq!(cmap.get(120), spec.default());
+        let strip = cmap.sample(12);
+        assert_eq!(strip.len(), 12);
+        assert_eq!((strip[1], strip[11]), (cmap.get(10), plain.get(30)));
+        assert_eq!(spec.gradient_at(45), Some(0));
+        assert_eq!(spec.gradient_at(119), Some(1));
+
+        spec.set_phase(1.25);
+        assert_eq!(spec.phase(), 0.25);
+        let rotated = ColorMap::make(spec.clone());
+        assert_eq!(rotated.get(0), plain.get(10));
+        assert_eq!(rotated.get(30), plain.get(0));
+        assert_eq!(rotated.get(41), plain.get(11));
+        assert_eq!(spec.gradient_at(0), Some(1));
+        assert_eq!(spec.gradient_at(30), Some(0));
+
+        // A length that's wrong either way leaves some colors default,
+        // rather than panicking.
+        for length in [30, 50] {
+            let wrong = ColorSpec {
+                length,
+                ..spec.clone()
             };
-            to_process.push(imc);
+            assert_eq!(ColorMap::make(wrong).len(), 40);
         }
 
-        let n_chunks = to_process.len();
-        let mut done_chunks: Vec<IterMapChunk> = Vec::with_capacity(n_chunks);
-        let mut active_threads: usize = 0;
-        let (tx, rx) = mpsc::channel::<IterMapChunk>();
-        while done_chunks.len() < n_chunks {
-            if active_threads < *N_THREADS {
-                if let Some(mut imc) = to_process.pop() {
-                    #[cfg(debug_assertions)]
-                    println!(
-                        "chunk -> (y_start: {}, n_rows: {}, pixels: {})",
-                        imc.y_start,
-                        imc.n_rows,
-                        imc.data.len()
-                    );
-                    let txc = tx.clone();
-                    thread::spawn(move || {
-                        imc.iterate(limit);
-                        txc.send(imc).unwrap();
-                    });
-                    active_threads += 1;
-                }
-            }
-            if active_threads == *N_THREADS || to_process.is_empty() {
-                let imc = rx.recv().unwrap();
-                #[cfg(debug_assertions)]
-                println!(
-                    "<- chunk (y_start: {}, n_rows: {}, pixels: {})",
-                    imc.y_start,
-                    imc.n_rows,
-                    imc.data.len()
-                );
-                active_threads -= 1;
-                done_chunks.push(imc);
-            }
+        spec.set_phase(f64::NAN);
+        spec.set_repeats(0);
+        assert_eq!((spec.phase(), spec.repeats()), (0.0, 1));
+    }
+
+    #[test]
+    fn tone_adjustments() {
+        let gray = RGB::new(64.0, 128.0, 192.0);
+        assert_eq!(Adjustment::NONE.apply(gray), gray);
+        let lighter = Adjustment {
+            gamma: 2.0,
+            ..Adjustment::NONE
         }
+        .apply(gray);
+        assert!(lighter.r() > gray.r() && lighter.b() > gray.b());
+        let flat = Adjustment {
+            contrast: 0.0,
+            ..Adjustment::NONE
+        };
+        assert_eq!(flat.apply(gray), flat.apply(RGB::WHITE));
+        let bright = Adjustment {
+            brightness: 5.0,
+            gamma: f64::NAN,
+            ..Adjustment::NONE
+        };
+        assert_eq!(bright.clamped().gamma, 1.0);
+        assert_eq!(bright.apply(RGB::BLACK), RGB::WHITE);
 
-        done_chunks.sort_by_key(|imc| imc.y_start);
+        // Images get adjusted, but not the colors in the map.
+        let dims = ImageDims {
+            xpix: 20,
+            ypix: 10,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 2,
+            adaptive: false,
+            check_period: true,
+        };
+        let mut spec = ColorSpec::new(vec![Gradient::default()], RGB::BLACK);
+        let imap = IterMap::new(dims, IterType::Mandlebrot, spec.limit());
+        let plain = spec.clone().to_map();
+        spec.set_adjustment(flat);
+        let cmap = spec.clone().to_map();
+        assert_eq!(cmap.colors(), plain.colors());
+        let flattened = imap.color(&cmap);
+        assert!(flattened
+            .pixels()
+            .iter()
+            .all(|c| *c == flat.apply(RGB::BLACK)));
 
-        IterMap {
-            dims,
-            itertype,
-            limit,
-            chunks: done_chunks,
-        }
+        // Specs without adjustments don't mention them.
+        let text = toml::to_string(&spec).unwrap();
+        assert!(text.contains("contrast"));
+        spec.set_adjustment(Adjustment::NONE);
+        let text = toml::to_string(&spec).unwrap();
+        assert!(!text.contains("contrast"));
+        assert_eq!(toml::from_str::<ColorSpec>(&text).unwrap(), spec);
     }
 
-    /**
-    Extend the current `IterMap` to more steps.
+    #[test]
+    fn reversed_specs() {
+        let red = RGB::new(255.0, 0.0, 0.0);
+        let mut spec = ColorSpec::new(
+            vec![
+                Gradient {
+                    steps: 10,
+                    start: RGB::BLACK,
+                    end: red,
+                    space: Interpolation::Hsv,
+                },
+                Gradient {
+                    steps: 30,
+                    start: red,
+                    end: RGB::WHITE,
+                    space: Interpolation::Rgb,
+                },
+            ],
+            red,
+        );
+        spec.set_repeats(2);
+        let rev = spec.reversed();
+        assert_eq!(rev.reversed(), spec);
+        assert_eq!((rev.len(), rev.repeats(), rev.default()), (40, 2, red));
+        let gradients = rev.gradients();
+        assert_eq!((gradients[0].steps, gradients[0].start), (30, RGB::WHITE));
+        assert_eq!(
+            (gradients[1].end, gradients[1].space),
+            (RGB::BLACK, Interpolation::Hsv)
+        );
+    }
 
-    This is useful if the image dimensions and iteration parameters haven't
-    changed, but there's a new target `ColorMap` that's of longer length.
+    #[test]
+    fn hsv_gradients() {
+        let colors = [
+            RGB::new(255.0, 0.0, 0.0),
+            RGB::new(12.0, 200.0, 99.0),
+            RGB::new(40.0, 30.0, 250.0),
+            RGB::new(128.0, 128.0, 128.0),
+            RGB::BLACK,
+        ];
+        for c in colors.iter() {
+            let (h, s, v) = c.to_hsv();
+            let back = RGB::from_hsv(h, s, v);
+            assert!((back.r - c.r).abs() < 0.01 && (back.g - c.g).abs() < 0.01);
+            assert!((back.b - c.b).abs() < 0.01, "{:?} -> {:?}", c, back);
+        }
 
-    This method will grovel through all the points in the `IterMap` and
-    re-iterate only those who have the previous maximum value.
-    */
-    pub fn reiterate(&mut self, limit: usize) {
-        #[cfg(debug_assertions)]
-        println!("reiteration! {}", limit);
+        let red = RGB::new(255.0, 0.0, 0.0);
+        let blue = RGB::new(0.0, 0.0, 255.0);
+        let mut g = Gradient {
+            steps: 10,
+            start: red,
+            end: blue,
+            space: Interpolation::Rgb,
+        };
+        assert_eq!(g.color_at(0.5), RGB::new(127.5, 0.0, 127.5));
+        g.space = Interpolation::Hsv;
+        assert_eq!(g.color_at(0.0), red);
+        assert_eq!(g.color_at(0.5), RGB::new(255.0, 0.0, 255.0));
+        g.space = Interpolation::HsvLong;
+        assert_eq!(g.color_at(0.5), RGB::new(0.0, 255.0, 0.0));
 
-        if limit <= self.limit {
-            return;
+        // Fading to gray shouldn't pass through other hues.
+        g.end = RGB::BLACK;
+        g.space = Interpolation::Hsv;
+        assert_eq!(g.color_at(0.5).to_hsv().0, 0.0);
+        g.start = RGB::new(0.0, 255.0, 0.0);
+        assert_eq!(g.color_at(0.7).to_hsv().0, 120.0);
+    }
+
+    #[test]
+    fn lab_gradients() {
+        let near = |a: (f32, f32, f32), b: (f32, f32, f32)| {
+            (a.0 - b.0).abs() < 0.05 && (a.1 - b.1).abs() < 0.05 && (a.2 - b.2).abs() < 0.05
+        };
+        assert!(near(RGB::WHITE.to_lab(), (100.0, 0.0, 0.0)));
+        assert!(near(RGB::BLACK.to_lab(), (0.0, 0.0, 0.0)));
+        let red = RGB::new(255.0, 0.0, 0.0);
+        assert!(
+            near(red.to_lab(), (53.24, 80.09, 67.20)),
+            "{:?}",
+            red.to_lab()
+        );
+
+        for c in [
+            red,
+            RGB::new(12.0, 200.0, 99.0),
+            RGB::new(40.0, 30.0, 250.0),
+        ]
+        .iter()
+        {
+            let (l, a, b) = c.to_lab();
+            let (l, ch, h) = (l, a.hypot(b), b.atan2(a).to_degrees());
+            for back in [RGB::from_lab(l, a, b), RGB::from_lch(l, ch, h)].iter() {
+                assert!(
+                    near((back.r, back.g, back.b), (c.r, c.g, c.b)),
+                    "{:?}",
+                    back
+                );
+            }
         }
 
-        let n_chunks = self.chunks.len();
-        let mut done_chunks: Vec<IterMapChunk> = Vec::with_capacity(n_chunks);
-        let mut active_threads: usize = 0;
-        let (tx, rx) = mpsc::channel::<IterMapChunk>();
-        while done_chunks.len() < n_chunks {
-            if active_threads < *N_THREADS {
-                if let Some(mut imc) = self.chunks.pop() {
-                    let txc = tx.clone();
-                    thread::spawn(move || {
-                        imc.reiterate(limit);
-                        txc.send(imc).unwrap();
-                    });
-                    active_threads += 1;
+        // Halfway between black and white should look halfway, which is
+        // rather lighter than RGB's halfway.
+        let mut g = Gradient {
+            steps: 10,
+            start: RGB::BLACK,
+            end: RGB::WHITE,
+            space: Interpolation::Lab,
+        };
+        assert!((g.color_at(0.5).to_lab().0 - 50.0).abs() < 0.05);
+        assert!(g.color_at(0.5).r > 100.0);
+        g.space = Interpolation::Lch;
+        let mid = g.color_at(0.5);
+        assert!((mid.r - mid.g).abs() < 0.5 && (mid.g - mid.b).abs() < 0.5);
+
+        g.start = red;
+        g.end = RGB::new(0.0, 0.0, 255.0);
+        let (l0, l1) = (red.to_lch().0, g.end.to_lch().0);
+        let mid = g.color_at(0.5).to_lch();
+        assert!(mid.0 > l1.min(l0) - 1.0 && mid.0 < l1.max(l0) + 1.0);
+    }
+
+    // The mask for each gradient should pick out exactly the pixels that
+    // gradient colors, and the masks shouldn't overlap.
+    #[test]
+    fn gradient_masks() {
+        let dims = ImageDims {
+            xpix: 90,
+            ypix: 60,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        let grads = vec![
+            Gradient {
+                start: RGB::BLACK,
+                end: RGB::WHITE,
+                steps: 3,
+                space: Interpolation::Rgb,
+            },
+            Gradient {
+                start: RGB::WHITE,
+                end: RGB::BLACK,
+                steps: 10,
+                space: Interpolation::Rgb,
+            },
+            Gradient::default(),
+        ];
+        let spec = ColorSpec::new(grads, RGB::BLACK);
+        assert_eq!(spec.gradient_range(1), Some(3..13));
+        assert_eq!(spec.gradient_range(3), None);
+
+        let imap = IterMap::new(dims, IterType::Mandlebrot, spec.len());
+        let counts: Vec<usize> = imap.chunks.iter().flat_map(|c| c.data.clone()).collect();
+        let mut covered = vec![0; counts.len()];
+        for n in 0..3 {
+            let range = spec.gradient_range(n).unwrap();
+            let mask = imap.mask(|c| spec.gradient_at(c) == Some(n));
+            for (i, px) in mask.pixels().iter().enumerate() {
+                let inside = *px == RGB::WHITE;
+                assert_eq!(inside, range.contains(&counts[i]));
+                if inside {
+                    covered[i] += 1;
                 }
             }
-            if active_threads == *N_THREADS || self.chunks.is_empty() {
-                let imc = rx.recv().unwrap();
-                active_threads -= 1;
-                done_chunks.push(imc);
-            }
         }
+        assert!(covered.iter().all(|&c| c <= 1));
+    }
 
-        done_chunks.sort_by_key(|imc| imc.y_start);
+    // A render that's out of time should finish at a lower limit, and
+    // reiterating it should make up the difference.
+    #[test]
+    fn overtime_maps() {
+        let dims = ImageDims {
+            xpix: 60,
+            ypix: 40,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: false,
+        };
+        let limit = 800;
+        let mut watcher = IterWatcher::new();
+        watcher.set_deadline(Instant::now());
+        let mut imap =
+            IterMap::new_watched(dims, IterType::Mandlebrot, limit, None, &watcher).unwrap();
+        let full = IterMap::with_orbit(dims, IterType::Mandlebrot, limit, None);
+        assert!(imap.is_approximate() && !full.is_approximate());
+        assert!(imap
+            .chunks
+            .iter()
+            .all(|c| c.last_limit == limit / OVERTIME_DIVISOR));
 
-        std::mem::swap(&mut self.chunks, &mut done_chunks);
-        self.limit = limit;
-    }
+        // Points that hadn't escaped by the lower limit get the default
+        // color, not the color for that count.
+        let spec = ColorSpec::new(
+            vec![Gradient {
+                steps: limit,
+                ..Gradient::default()
+            }],
+            RGB::new(0.0, 0.0, 255.0),
+        );
+        let cmap = ColorMap::make(spec);
+        let counts: Vec<usize> = imap.chunks.iter().flat_map(|c| c.data.clone()).collect();
+        let img = imap.color(&cmap);
+        for (n, px) in counts.iter().zip(img.pixels().iter()) {
+            if *n >= limit / OVERTIME_DIVISOR {
+                assert_eq!(*px, RGB::new(0.0, 0.0, 255.0));
+            }
+        }
 
-    pub fn dims(&self) -> ImageDims {
-        self.dims
+        assert!(imap.reiterate_watched(limit, &IterWatcher::new()));
+        assert!(!imap.is_approximate());
+        let data =
+            |m: &IterMap| -> Vec<usize> { m.chunks.iter().flat_map(|c| c.data.clone()).collect() };
+        assert_eq!(data(&imap), data(&full));
     }
-    pub fn itertype(&self) -> &IterType {
-        &self.itertype
+
+    // A chunk that panics should be marked, not take the whole map with it,
+    // and should get another try when the map is reiterated.
+    #[test]
+    fn panicking_chunks() {
+        let dims = ImageDims {
+            xpix: 40,
+            ypix: 30,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 2,
+            adaptive: false,
+            check_period: true,
+        };
+        let cmap = ColorMap::make(ColorSpec::new(vec![Gradient::default()], RGB::WHITE));
+        let good = IterMap::new(dims, IterType::Mandlebrot, cmap.limit());
+        let mut imap = IterMap::new(dims, IterType::Mandlebrot, cmap.limit());
+        assert_eq!((imap.failed_rows(), imap.failure()), (0, None));
+
+        let bad = imap.chunks.iter().position(|c| c.n_rows > 0).unwrap();
+        let finished = imap.chunks[bad].run(|_| panic!("oh no"));
+        assert!(finished);
+        let (y_start, n_rows) = (imap.chunks[bad].y_start, imap.chunks[bad].n_rows);
+        assert_eq!(imap.failed_rows(), n_rows);
+        assert_eq!(imap.failure(), Some("oh no"));
+
+        let img = imap.color(&cmap);
+        let row = |img: &FImage32, y: usize| img.pixels()[y * 40..(y + 1) * 40].to_vec();
+        assert_eq!(img.pixels().len(), 40 * 30);
+        assert_eq!(row(&img, y_start)[0], FAILED_COLOR);
+        assert!(row(&img, y_start).contains(&RGB::BLACK));
+        assert!(
+            imap.mask(|_| true).pixels()[y_start * 40..(y_start + n_rows) * 40]
+                .iter()
+                .all(|&px| px == RGB::BLACK)
+        );
+
+        imap.reiterate(cmap.limit() * 2);
+        assert_eq!(imap.failed_rows(), 0);
+        assert_eq!(
+            row(&imap.color(&cmap), y_start),
+            row(&good.color(&cmap), y_start)
+        );
     }
-    pub fn limit(&self) -> usize {
-        self.limit
+
+    #[test]
+    fn saved_counts() {
+        let dims = ImageDims {
+            xpix: 50,
+            ypix: 35,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 2,
+            adaptive: true,
+            check_period: true,
+        };
+        let cmap = ColorMap::make(ColorSpec::new(vec![Gradient::default()], RGB::WHITE));
+        let imap = IterMap::new(dims, IterType::Mandlebrot, cmap.limit());
+        let mut saved: Vec<u8> = Vec::new();
+        imap.write_counts(&mut saved).unwrap();
+        // Most of the counts are the same as their neighbors'.
+        assert!(saved.len() < 4 * 50 * 35);
+
+        let loaded = IterMap::read_counts(&saved[..], dims, IterType::Mandlebrot, None).unwrap();
+        assert_eq!(loaded.limit(), imap.limit());
+        assert_eq!(loaded.periodicity_checked(), imap.periodicity_checked());
+        let data =
+            |m: &IterMap| -> Vec<usize> { m.chunks.iter().flat_map(|c| c.data.clone()).collect() };
+        assert_eq!(data(&loaded), data(&imap));
+        assert_eq!(loaded.color(&cmap).pixels(), imap.color(&cmap).pixels());
+
+        // Counts of anything else don't get passed off as these.
+        let moved = ImageDims { x: -1.9, ..dims };
+        assert!(IterMap::read_counts(&saved[..], moved, IterType::Mandlebrot, None).is_err());
+        assert!(IterMap::read_counts(&saved[..], dims, IterType::Tricorn, None).is_err());
+        assert!(IterMap::read_counts(
+            &saved[..(saved.len() / 2)],
+            dims,
+            IterType::Mandlebrot,
+            None
+        )
+        .is_err());
+
+        // Nor do hostile ones get to take up all the memory there is.
+        let deflate = |bytes: &[u8]| -> Vec<u8> {
+            let mut w = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+            w.write_all(bytes).unwrap();
+            w.finish().unwrap()
+        };
+        let mut raw: Vec<u8> = Vec::new();
+        flate2::read::DeflateDecoder::new(&saved[..])
+            .read_to_end(&mut raw)
+            .unwrap();
+        let mut padded = raw.clone();
+        padded.resize(raw.len() + (1 << 24), 0);
+        let e = IterMap::read_counts(&deflate(&padded)[..], dims, IterType::Mandlebrot, None);
+        assert!(matches!(e, Err(msg) if msg.contains("bigger")));
+        // The first chunk starts after the header: the magic, the key and
+        // its length, the limit, the periodicity byte, and the chunk count.
+        let key_len = counts_key(&dims, &IterType::Mandlebrot, None).len();
+        let first = COUNTS_MAGIC.len() + 4 + key_len + 9;
+        let mut huge = raw.clone();
+        huge[(first + 4)..(first + 8)].copy_from_slice(&u32::MAX.to_le_bytes());
+        let e = IterMap::read_counts(&deflate(&huge)[..], dims, IterType::Mandlebrot, None);
+        assert!(matches!(e, Err(msg) if msg.contains("don't cover")));
     }
 
-    pub fn color(&self, map: &ColorMap) -> FImage32 {
-        let n_pix = self.dims.xpix * self.dims.ypix;
-        let mut rgb_data: Vec<RGB> = Vec::with_capacity(n_pix);
+    proptest! {
+        #[test]
+        fn zoom_preserves_center(d in any_dims(), factor in 0.01f64..100.0) {
+            let z = d.zoom(factor);
+            let (cx, cy) = d.center();
+            let (zx, zy) = z.center();
+            prop_assert!(close(cx, zx, d.width));
+            prop_assert!(close(cy, zy, d.width));
+            prop_assert_eq!((z.xpix, z.ypix), (d.xpix, d.ypix));
+            prop_assert!(close(z.width * factor, d.width, d.width));
+        }
 
-        for chunk in self.chunks.iter() {
-            for n in chunk.data.iter() {
-                rgb_data.push(map.get(*n));
-            }
+        #[test]
+        fn zoom_round_trip(d in any_dims(), factor in 0.01f64..100.0) {
+            let z = d.zoom(factor).zoom(1.0 / factor);
+            prop_assert!(close(z.x, d.x, d.width));
+            prop_assert!(close(z.y, d.y, d.width));
+            prop_assert!(close(z.width, d.width, d.width));
         }
 
-        FImage32 {
-            dims: self.dims,
-            data: rgb_data,
+        #[test]
+        fn resize_preserves_center_and_coverage(
+            d in any_dims(),
+            xpix in 16usize..4096,
+            ypix in 16usize..4096,
+        ) {
+            let r = d.resize(xpix, ypix);
+            let (cx, cy) = d.center();
+            let (rx, ry) = r.center();
+            prop_assert!(close(cx, rx, d.width));
+            prop_assert!(close(cy, ry, d.width));
+            prop_assert_eq!((r.xpix, r.ypix), (xpix, ypix));
+            // The new view has to cover everything the old one did.
+            prop_assert!(r.width >= d.width * (1.0 - TOLERANCE));
+            prop_assert!(r.height() >= d.height() * (1.0 - TOLERANCE));
+            // ...and exactly fit it in one direction or the other.
+            prop_assert!(
+                close(r.width, d.width, d.width)
+                    || close(r.height(), d.height(), d.height())
+            );
+        }
+
+        #[test]
+        fn recenter_middle_is_identity(d in any_dims()) {
+            let r = d.recenter(0.5, 0.5);
+            prop_assert!(close(r.x, d.x, d.width));
+            prop_assert!(close(r.y, d.y, d.width));
+            prop_assert_eq!(r.width, d.width);
+        }
+
+        #[test]
+        fn recenter_round_trip(d in any_dims(), xf in 0.0f64..1.0, yf in 0.0f64..1.0) {
+            let r = d.recenter(xf, yf);
+            prop_assert_eq!(r.width, d.width);
+            prop_assert_eq!((r.xpix, r.ypix), (d.xpix, d.ypix));
+            let back = r.recenter(1.0 - xf, 1.0 - yf);
+            prop_assert!(close(back.x, d.x, d.width));
+            prop_assert!(close(back.y, d.y, d.width));
+        }
+
+        #[test]
+        fn shrink_samples_every_nth_pixel(
+            d in any_dims(),
+            factor in 1usize..16,
+            xp in 0usize..16,
+            yp in 0usize..16,
+        ) {
+            let s = d.shrink(factor);
+            prop_assert!(s.xpix * factor >= d.xpix);
+            prop_assert!(s.ypix * factor >= d.ypix);
+            let (sx, sy) = mapping::pixel_to_plane(&s, xp, yp);
+            let (bx, by) = mapping::pixel_to_plane(&d, xp * factor, yp * factor);
+            prop_assert!(close(sx, bx, d.width));
+            prop_assert!(close(sy, by, d.width));
         }
     }
 }
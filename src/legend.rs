@@ -0,0 +1,311 @@
+/*!
+Legends for exported images, for documentation and teaching: a band along
+the bottom with a caption (where the image is, how far it's zoomed in, and
+the formula iterated) over a strip of the color map, with tick marks at
+iteration counts.
+
+The text is drawn in a small built-in bitmap font, so legends can be put
+on images without a display (like from the command line). It only has the
+printable ASCII characters; anything else comes out as a '?'.
+*/
+
+use crate::cx::Cx;
+use crate::image::*;
+
+// Each printable ASCII character, from ' ' to '~', five columns of seven
+// dots each, the lowest bit at the top.
+const GLYPHS: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x5F, 0x00, 0x00],
+    [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7F, 0x14, 0x7F, 0x14],
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12],
+    [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x55, 0x22, 0x50],
+    [0x00, 0x05, 0x03, 0x00, 0x00],
+    [0x00, 0x1C, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1C, 0x00],
+    [0x08, 0x2A, 0x1C, 0x2A, 0x08],
+    [0x08, 0x08, 0x3E, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00],
+    [0x08, 0x08, 0x08, 0x08, 0x08],
+    [0x00, 0x60, 0x60, 0x00, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02],
+    [0x3E, 0x51, 0x49, 0x45, 0x3E],
+    [0x00, 0x42, 0x7F, 0x40, 0x00],
+    [0x42, 0x61, 0x51, 0x49, 0x46],
+    [0x21, 0x41, 0x45, 0x4B, 0x31],
+    [0x18, 0x14, 0x12, 0x7F, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39],
+    [0x3C, 0x4A, 0x49, 0x49, 0x30],
+    [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36],
+    [0x06, 0x49, 0x49, 0x29, 0x1E],
+    [0x00, 0x36, 0x36, 0x00, 0x00],
+    [0x00, 0x56, 0x36, 0x00, 0x00],
+    [0x08, 0x14, 0x22, 0x41, 0x00],
+    [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08],
+    [0x02, 0x01, 0x51, 0x09, 0x06],
+    [0x32, 0x49, 0x79, 0x41, 0x3E],
+    [0x7E, 0x11, 0x11, 0x11, 0x7E],
+    [0x7F, 0x49, 0x49, 0x49, 0x36],
+    [0x3E, 0x41, 0x41, 0x41, 0x22],
+    [0x7F, 0x41, 0x41, 0x22, 0x1C],
+    [0x7F, 0x49, 0x49, 0x49, 0x41],
+    [0x7F, 0x09, 0x09, 0x09, 0x01],
+    [0x3E, 0x41, 0x49, 0x49, 0x7A],
+    [0x7F, 0x08, 0x08, 0x08, 0x7F],
+    [0x00, 0x41, 0x7F, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3F, 0x01],
+    [0x7F, 0x08, 0x14, 0x22, 0x41],
+    [0x7F, 0x40, 0x40, 0x40, 0x40],
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F],
+    [0x7F, 0x04, 0x08, 0x10, 0x7F],
+    [0x3E, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x09, 0x09, 0x09, 0x06],
+    [0x3E, 0x41, 0x51, 0x21, 0x5E],
+    [0x7F, 0x09, 0x19, 0x29, 0x46],
+    [0x46, 0x49, 0x49, 0x49, 0x31],
+    [0x01, 0x01, 0x7F, 0x01, 0x01],
+    [0x3F, 0x40, 0x40, 0x40, 0x3F],
+    [0x1F, 0x20, 0x40, 0x20, 0x1F],
+    [0x3F, 0x40, 0x38, 0x40, 0x3F],
+    [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x07, 0x08, 0x70, 0x08, 0x07],
+    [0x61, 0x51, 0x49, 0x45, 0x43],
+    [0x00, 0x7F, 0x41, 0x41, 0x00],
+    [0x02, 0x04, 0x08, 0x10, 0x20],
+    [0x00, 0x41, 0x41, 0x7F, 0x00],
+    [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40],
+    [0x00, 0x01, 0x02, 0x04, 0x00],
+    [0x20, 0x54, 0x54, 0x54, 0x78],
+    [0x7F, 0x48, 0x44, 0x44, 0x38],
+    [0x38, 0x44, 0x44, 0x44, 0x20],
+    [0x38, 0x44, 0x44, 0x48, 0x7F],
+    [0x38, 0x54, 0x54, 0x54, 0x18],
+    [0x08, 0x7E, 0x09, 0x01, 0x02],
+    [0x0C, 0x52, 0x52, 0x52, 0x3E],
+    [0x7F, 0x08, 0x04, 0x04, 0x78],
+    [0x00, 0x44, 0x7D, 0x40, 0x00],
+    [0x20, 0x40, 0x44, 0x3D, 0x00],
+    [0x7F, 0x10, 0x28, 0x44, 0x00],
+    [0x00, 0x41, 0x7F, 0x40, 0x00],
+    [0x7C, 0x04, 0x18, 0x04, 0x78],
+    [0x7C, 0x08, 0x04, 0x04, 0x78],
+    [0x38, 0x44, 0x44, 0x44, 0x38],
+    [0x7C, 0x14, 0x14, 0x14, 0x08],
+    [0x08, 0x14, 0x14, 0x18, 0x7C],
+    [0x7C, 0x08, 0x04, 0x04, 0x08],
+    [0x48, 0x54, 0x54, 0x54, 0x20],
+    [0x04, 0x3F, 0x44, 0x40, 0x20],
+    [0x3C, 0x40, 0x40, 0x20, 0x7C],
+    [0x1C, 0x20, 0x40, 0x20, 0x1C],
+    [0x3C, 0x40, 0x30, 0x40, 0x3C],
+    [0x44, 0x28, 0x10, 0x28, 0x44],
+    [0x0C, 0x50, 0x50, 0x50, 0x3C],
+    [0x44, 0x64, 0x54, 0x4C, 0x44],
+    [0x00, 0x08, 0x36, 0x41, 0x00],
+    [0x00, 0x00, 0x7F, 0x00, 0x00],
+    [0x00, 0x41, 0x36, 0x08, 0x00],
+    [0x08, 0x04, 0x08, 0x10, 0x08],
+];
+// How many dots each character takes up each way, counting the space
+// after it (or under it).
+const CELL_W: usize = 6;
+const CELL_H: usize = 8;
+// How opaque the band behind the legend is.
+const BAND_OPACITY: f32 = 0.65;
+// About how many tick marks go under the color strip.
+const TICKS: usize = 5;
+
+/** Return the formula iterated by `itype`, like "z^2 + c". */
+pub fn formula(itype: &IterType) -> String {
+    let cx = |z: &Cx| format!("({:.4}{:+.4}i)", z.re, z.im);
+    match itype {
+        IterType::Mandlebrot => "z^2 + c".to_string(),
+        IterType::PseudoMandlebrot { a, b } => format!("{}z^2 + {}c", cx(a), cx(b)),
+        IterType::Polynomial { coefs } => {
+            let terms: Vec<String> = coefs
+                .iter()
+                .enumerate()
+                .rev()
+                .filter(|(_, a)| a.re != 0.0 || a.im != 0.0)
+                .map(|(n, a)| match n {
+                    0 => cx(a),
+                    1 => format!("{}z", cx(a)),
+                    n => format!("{}z^{}", cx(a), n),
+                })
+                .collect();
+            if terms.is_empty() {
+                "0".to_string()
+            } else {
+                terms.join(" + ")
+            }
+        }
+        IterType::Tricorn => "conj(z)^2 + c".to_string(),
+        IterType::Exponential => "c exp(z)".to_string(),
+        IterType::Sine => "c sin(z)".to_string(),
+        IterType::Cosine => "c cos(z)".to_string(),
+    }
+}
+
+/**
+Return the caption for the view `dims` of `itype`: the center, how far
+it's zoomed in (compared to `itype`'s default view), and the formula.
+*/
+pub fn caption(dims: &ImageDims, itype: &IterType) -> String {
+    let (x, y) = dims.center();
+    // Enough decimal places to tell neighboring pixels apart.
+    let pixel = dims.width / (dims.xpix.max(1) as f64);
+    let places = ((-pixel.log10()).ceil().max(0.0) as usize) + 1;
+    let zoom = itype.default_view(dims).width / dims.width;
+    format!(
+        "{:.*} {:+.*}i   zoom {:.3}x   {}",
+        places,
+        x,
+        places,
+        y,
+        zoom,
+        formula(itype)
+    )
+}
+
+/**
+Return round numbers of iterations from 0 up to `limit` to put tick marks
+at, about `n` of them.
+*/
+pub fn ticks(limit: usize, n: usize) -> Vec<usize> {
+    let rough = (limit as f64 / n.max(1) as f64).max(1.0);
+    let power = 10f64.powf(rough.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|m| m * power)
+        .find(|&s| s >= rough)
+        .unwrap_or(10.0 * power) as usize;
+    (0..=limit).step_by(step.max(1)).collect()
+}
+
+// Draw `text` on `img` in `color` with its upper-left corner at (`x`, `y`),
+// each dot of the font `dot` pixels square.
+fn draw_text(img: &mut FImage32, text: &str, x: usize, y: usize, dot: usize, color: RGB) {
+    for (n, c) in text.chars().enumerate() {
+        let glyph = match c {
+            ' '..='~' => &GLYPHS[c as usize - ' ' as usize],
+            _ => &GLYPHS['?' as usize - ' ' as usize],
+        };
+        let left = x + n * CELL_W * dot;
+        for (col, bits) in glyph.iter().enumerate() {
+            for row in 0..7 {
+                if bits & (1 << row) != 0 {
+                    let rect = PixelRect::new(left + col * dot, y + row * dot, dot, dot);
+                    img.fill(rect, color, 1.0);
+                }
+            }
+        }
+    }
+}
+
+/** Return how many pixels wide `text` is, drawn with `dot`-pixel dots. */
+pub fn text_width(text: &str, dot: usize) -> usize {
+    (text.chars().count() * CELL_W).saturating_sub(1) * dot
+}
+
+/**
+Put a legend along the bottom of `img`, an image of the view `dims` of
+`itype` colored with `cmap`: a caption, and a strip of the color map from
+no iterations up to its limit, with tick marks at round numbers of
+iterations. The text is sized to the width of the image; anything that
+doesn't fit is cut off.
+*/
+pub fn add_legend(img: &mut FImage32, dims: &ImageDims, cmap: &ColorMap, itype: &IterType) {
+    let (xpix, ypix) = (img.xpix(), img.ypix());
+    let dot = ((xpix as f64) / 300.0).round().clamp(1.0, 8.0) as usize;
+    let pad = 3 * dot;
+    let strip_h = 6 * dot;
+    let tick_h = 2 * dot;
+    let band_h = 2 * pad + CELL_H * dot + strip_h + tick_h + dot + 7 * dot;
+    let top = ypix.saturating_sub(band_h);
+    let width = xpix.saturating_sub(2 * pad);
+    if width == 0 {
+        return;
+    }
+    img.fill(
+        PixelRect::new(0, top, xpix, band_h),
+        RGB::BLACK,
+        BAND_OPACITY,
+    );
+
+    let fits = width / (CELL_W * dot);
+    let text: String = caption(dims, itype).chars().take(fits).collect();
+    draw_text(img, &text, pad, top + pad, dot, RGB::WHITE);
+
+    let strip_y = top + pad + CELL_H * dot;
+    let mut colors = cmap.sample(width);
+    cmap.finish(&mut colors);
+    for (n, color) in colors.into_iter().enumerate() {
+        img.fill(PixelRect::new(pad + n, strip_y, 1, strip_h), color, 1.0);
+    }
+
+    let limit = cmap.limit().max(1);
+    let tick_y = strip_y + strip_h;
+    let label_y = tick_y + tick_h + dot;
+    // Where the last label ended, so they don't run into each other.
+    let mut clear_from = 0;
+    for count in ticks(limit, TICKS) {
+        let x = pad + (count * (width - 1)) / limit;
+        img.fill(PixelRect::new(x, tick_y, dot, tick_h), RGB::WHITE, 1.0);
+        let label = format!("{}", count);
+        let w = text_width(&label, dot);
+        let left = x
+            .saturating_sub(w / 2)
+            .max(pad)
+            .min(xpix.saturating_sub(w + pad));
+        if left >= clear_from {
+            draw_text(img, &label, left, label_y, dot, RGB::WHITE);
+            clear_from = left + w + CELL_W * dot;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn legends() {
+        assert_eq!(ticks(1000, 5), vec![0, 200, 400, 600, 800, 1000]);
+        assert_eq!(ticks(256, 5), vec![0, 100, 200]);
+        assert_eq!(ticks(3, 5), vec![0, 1, 2, 3]);
+        assert_eq!(formula(&IterType::Mandlebrot), "z^2 + c");
+        let poly = IterType::Polynomial {
+            coefs: vec![
+                Cx::rect(-0.8, 0.156),
+                Cx::rect(0.0, 0.0),
+                Cx::rect(1.0, 0.0),
+            ],
+        };
+        assert_eq!(formula(&poly), "(1.0000+0.0000i)z^2 + (-0.8000+0.1560i)");
+        assert_eq!(text_width("ab", 2), 22);
+
+        let dims = ImageDims {
+            xpix: 200,
+            ypix: 120,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        assert!(caption(&dims, &IterType::Mandlebrot).ends_with("z^2 + c"));
+        let cmap = ColorMap::make(ColorSpec::new(vec![Gradient::default(); 2], RGB::BLACK));
+        let mut img = FImage32::new(dims, RGB::WHITE);
+        add_legend(&mut img, &dims, &cmap, &IterType::Mandlebrot);
+        // The top is untouched, and the band at the bottom is darkened.
+        assert_eq!(img.pixels()[0], RGB::WHITE);
+        let corner = img.pixels()[dims.xpix * dims.ypix - 1];
+        assert!(corner.r() < RGB::WHITE.r() / 2.0);
+    }
+}
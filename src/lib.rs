@@ -1,4 +1,25 @@
+pub mod animate;
+pub mod app;
+pub mod batch;
+pub mod contrast;
+pub mod curves;
 pub mod cx;
+pub mod cycle;
+pub mod export;
+pub mod gallery;
+pub mod headless;
+pub mod history;
+pub mod hook;
 pub mod image;
+pub mod legend;
+pub mod mapping;
+pub mod mesh;
+pub mod morph;
+pub mod prefs;
+pub mod presets;
+pub mod render;
 pub mod rw;
+pub mod script;
 pub mod ui;
+pub mod video;
+pub mod watch;
@@ -1,231 +1,952 @@
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 
-use fltk::dialog;
-
+use jset_desk::animate;
+use jset_desk::app::Globs;
+use jset_desk::batch;
+use jset_desk::cycle;
+use jset_desk::export;
+use jset_desk::gallery;
+use jset_desk::headless;
+use jset_desk::hook;
 use jset_desk::image::*;
+use jset_desk::legend;
+use jset_desk::mesh;
+use jset_desk::prefs;
+use jset_desk::render;
 use jset_desk::rw;
+use jset_desk::script;
 use jset_desk::ui;
 use jset_desk::ui::Msg;
+use jset_desk::video;
+use jset_desk::watch;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const X_CLASS: &str = "JSet-Desktop";
+// Environment variable that can be set to the path of a preferences file
+// to use instead of the usual one (see the `prefs` module).
+const PREFS_VAR: &str = "JSET_PREFS";
+// Environment variable that can be set to scale the whole UI up or down.
+const UI_SCALE_VAR: &str = "JSET_UI_SCALE";
+// Environment variable that can be set to the screen's scale factor (like
+// 2 for a 4K screen), for when the platform doesn't say what it is.
+const SCREEN_SCALE_VAR: &str = "JSET_SCREEN_SCALE";
+// Environment variable that can be set to "toolbar" to put the main
+// window's controls along the top instead of down the side.
+const CONTROLS_VAR: &str = "JSET_CONTROLS";
+// Environment variable that can be set to "docked" to put the color map and
+// iterator panes (and the minimap) inside the main window.
+const PANES_VAR: &str = "JSET_PANES";
+// Environment variable that can be set to the number of seconds a render
+// gets before the rest of it is done with a lower iteration limit.
+const TIME_LIMIT_VAR: &str = "JSET_TIME_LIMIT";
+// Environment variable that can be set to "display-p3" to tag saved images
+// as Display P3 rather than sRGB.
+const COLOR_PROFILE_VAR: &str = "JSET_COLOR_PROFILE";
+// Environment variable that can be set to a command to run on each image
+// after it's saved (see the `hook` module for the details).
+const POST_SAVE_VAR: &str = "JSET_POST_SAVE";
+// Environment variable that can be set to "polar" to write iterator
+// coefficients in polar form, too, for editing parameter files by hand.
+const COEFFICIENTS_VAR: &str = "JSET_COEFFICIENTS";
+// Environment variable that can be set to the ffmpeg program to make
+// videos of animations with, if it isn't just "ffmpeg" on the path.
+const FFMPEG_VAR: &str = "JSET_FFMPEG";
+// Environment variables that can be set to "compact" and/or an opacity
+// (like "compact,0.8") to change how the color map and iterator panes are
+// drawn, so they can float over the image.
+const COLOR_PANE_VAR: &str = "JSET_COLOR_PANE";
+const ITER_PANE_VAR: &str = "JSET_ITER_PANE";
 
-// A container to hold all the global variables.
-struct Globs {
-    iter_pane: ui::iter::IterPane,
-    colr_pane: ui::color::ColorPane,
-    main_pane: ui::img::ImgPane,
+const USAGE: &str = "usage:
+    jset_desk
+        start the application
+    jset_desk --thumbnail <params file> <output.png> [--size <pixels>]
+        render a small preview of an image from saved parameters (or a
+        PNG with parameters embedded in it), like for a file manager
+    jset_desk --export <params file> <output.png> [--width <pixels>]
+              [--height <pixels>] [--oversample <n>] [--no-metadata]
+              [--display-p3] [--post-save <command>] [--lock] [--16-bit]
+              [--quality <1-100>] [--legend]
+        render an image from saved parameters, at any size at all; if only
+        one of width or height is given, the other keeps the aspect ratio;
+        --16-bit writes 16 bits per channel, for smooth gradients; an
+        output name ending in .jpg or .webp saves a (much smaller) JPEG or
+        WebP instead, at the given quality (default 90; for WebP, 100
+        means lossless), though those have to fit in memory, and carry no
+        parameters, so --16-bit, --lock and --no-metadata are PNG-only;
+        --display-p3 tags it with the wide-gamut Display P3 color profile
+        instead of sRGB; --post-save runs a command on the image after
+        it's written (like \"oxipng {file}\"), overriding JSET_POST_SAVE;
+        --lock stamps the embedded parameters so a re-render can be checked
+        for being identical; --legend puts a strip of the colors, with
+        iteration counts, and a caption saying where the image is along the
+        bottom (which means rendering it all at once, too)
+    jset_desk --export-set <params file> <output.png> [--sizes <sizes>]
+              [--no-metadata]
+        render an image once and save it at each of a list of sizes, like
+        \"1080p,4k,800x600\" (default \"1080p,1440p,4k,phone\"; 720p works
+        too), named like output_1080p.png; each takes the middle of the
+        image in its own shape; an output name ending in .jpg or .webp
+        saves JPEGs or WebP images instead (with no parameters, so
+        --no-metadata is PNG-only)
+    jset_desk --mesh <params file> <output.obj or .stl> [--resolution <n>]
+              [--scale <height>]
+        turn the iteration counts into a 3D height field, written as an
+        OBJ or binary STL solid for 3D printing or other renderers, <n>
+        points along its longer side (default 256); the scale is how
+        tall the highest parts stand, as a fraction of the longer side
+        (default 0.1)
+    jset_desk --share <params file>
+    jset_desk --share <share string> <output params file>
+        print the share string (one line of text, starting \"jset:\") of
+        saved parameters, or save the parameters in a share string
+    jset_desk --watch <folder> [--output <folder>] [--interval <seconds>]
+        keep rendering any parameter files that show up in a folder, into
+        <folder>_renders unless told otherwise, logging to render.log
+    jset_desk --batch <params files or folders>... [--output <folder>]
+              [--pattern <pattern>] [--jobs <n>] [--no-metadata]
+        render every parameter file given (and every .toml and .json file
+        in any folder given) into the output folder (default: the current
+        one), naming each image by the pattern (default \"{stem}.png\";
+        {n} is the file's number in the batch); --jobs renders that many
+        files at once
+    jset_desk --animate <params files>... --frames <n> [--easing <easing>]
+              [--output <name.png>] [--morph] [--fps <n>]
+        render an animation moving through each of the parameter files in
+        turn, in <n> frames numbered from the output name (default
+        \"frame.png\", giving frame_001.png and so on); the easing is
+        linear, ease-in, ease-out, or ease-in-out; --morph blends the
+        iterators and colors along the way, too; an output name ending in
+        .mp4, .mkv, .mov, or .webm makes a video (at 30 frames per second
+        unless --fps says otherwise) with ffmpeg, if it's installed, and
+        one ending in .gif or .apng makes an animated GIF or PNG
+    jset_desk --cycle <params file> --frames <n> [--output <name.png>]
+              [--fps <n>]
+        render one full cycle of the colors through an image, in <n>
+        frames numbered from the output name (default \"cycle.png\"), or
+        into a video or animated image like --animate; the frames loop,
+        so the last leads back into the first
+    jset_desk --script <script.rhai> [--start <params file>]
+        run a script that sets up and renders images (see the README),
+        starting from the parameters in a file (or the first example);
+        relative paths in the script are taken from its folder
+";
 
-    cur_dims: ImageDims,
-    cur_iter: IterType,
-    cur_spec: ColorSpec,
-    cur_cmap: ColorMap,
-    cur_imap: IterMap,
-    cur_fimg: FImage32,
+// Handle the command-line arguments, for when the program is being run
+// headless rather than as an application. Returns the exit status.
+fn run_cli(args: &[String]) -> i32 {
+    match args.first().map(|s| s.as_str()) {
+        Some("--thumbnail") => thumbnail_cli(&args[1..]),
+        Some("--export") => export_cli(&args[1..]),
+        Some("--export-set") => export_set_cli(&args[1..]),
+        Some("--mesh") => mesh_cli(&args[1..]),
+        Some("--share") => share_cli(&args[1..]),
+        Some("--watch") => watch_cli(&args[1..]),
+        Some("--batch") => batch_cli(&args[1..]),
+        Some("--animate") => animate_cli(&args[1..]),
+        Some("--cycle") => cycle_cli(&args[1..]),
+        Some("--script") => script_cli(&args[1..]),
+        Some("-h") | Some("--help") => {
+            print!("{}", USAGE);
+            0
+        }
+        Some(arg) => {
+            eprint!("unrecognized argument: {}\n{}", arg, USAGE);
+            2
+        }
+        None => 0,
+    }
+}
 
-    cur_scale: usize,
+// Parse the value following a command-line option that requires a positive
+// number, printing a complaint if there isn't one.
+fn positive_arg<'a, I: Iterator<Item = &'a String>>(opt: &str, args: &mut I) -> Option<usize> {
+    match args.next().map(|s| s.parse::<usize>()) {
+        Some(Ok(n)) if n > 0 => Some(n),
+        _ => {
+            eprint!("{} requires a positive number\n{}", opt, USAGE);
+            None
+        }
+    }
 }
 
-impl Globs {
-    // Given the passed `ImageDims`, decides how much recalculation should
-    // be done, and does only that much, to re-display the current image.
-    pub fn recheck_and_redraw(&mut self, new_dims: ImageDims) {
-        let mut should_redraw = false;
-        let mut should_reiterate = false;
-        let mut should_recolor = false;
+fn thumbnail_cli(args: &[String]) -> i32 {
+    let mut args = args.iter();
+    let mut files: Vec<&String> = Vec::new();
+    let mut size = headless::DEFAULT_THUMBNAIL_SIZE;
+    while let Some(arg) = args.next() {
+        if arg == "--size" || arg == "-s" {
+            size = match positive_arg(arg, &mut args) {
+                Some(n) => n,
+                None => return 2,
+            };
+        } else {
+            files.push(arg);
+        }
+    }
+    if files.len() != 2 {
+        eprint!(
+            "--thumbnail requires an input and an output file\n{}",
+            USAGE
+        );
+        return 2;
+    }
 
-        if new_dims != self.cur_dims {
-            should_redraw = true;
-            self.cur_dims = new_dims;
+    match headless::thumbnail(files[0], files[1], size) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error making thumbnail of {}: {}", files[0], &e);
+            1
         }
+    }
+}
 
-        let new_iter = self.iter_pane.get_itertype();
-        if new_iter != self.cur_iter {
-            should_redraw = true;
-            self.cur_iter = new_iter;
+fn share_cli(args: &[String]) -> i32 {
+    match args {
+        [src] => {
+            let res = rw::load(src).and_then(|(d, s, i)| rw::share_string(&d, &s, &i));
+            match res {
+                Ok(text) => {
+                    println!("{}", text);
+                    0
+                }
+                Err(e) => {
+                    eprintln!("Error reading {}: {}", src, &e);
+                    1
+                }
+            }
+        }
+        [text, dest] => {
+            let res = rw::from_share_string(text).and_then(|(d, s, i)| rw::save(&d, &s, &i, dest));
+            match res {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("Error saving {}: {}", dest, &e);
+                    1
+                }
+            }
         }
+        _ => {
+            eprint!(
+                "--share requires a parameter file, or a share string and an output file\n{}",
+                USAGE
+            );
+            2
+        }
+    }
+}
 
-        let new_spec = self.colr_pane.get_spec();
-        if new_spec != self.cur_spec {
-            let new_cmap = ColorMap::make(new_spec.clone());
-            if new_cmap.len() > self.cur_cmap.len() {
-                should_reiterate = true;
+fn export_cli(args: &[String]) -> i32 {
+    let mut args = args.iter();
+    let mut files: Vec<&String> = Vec::new();
+    let (mut width, mut height, mut oversample) = (None, None, None);
+    let mut quality = None;
+    let mut embed_metadata = true;
+    let mut depth = rw::BitDepth::Eight;
+    let mut with_legend = false;
+    // Options that only mean anything for PNGs.
+    let mut png_only: Vec<&str> = Vec::new();
+    while let Some(arg) = args.next() {
+        let opt = match arg.as_str() {
+            "--width" => &mut width,
+            "--height" => &mut height,
+            "--oversample" => &mut oversample,
+            "--quality" => &mut quality,
+            "--no-metadata" => {
+                embed_metadata = false;
+                png_only.push(arg);
+                continue;
+            }
+            "--display-p3" => {
+                rw::set_color_profile(rw::ColorProfile::DisplayP3);
+                continue;
+            }
+            "--lock" => {
+                rw::set_param_locking(true);
+                png_only.push(arg);
+                continue;
+            }
+            "--16-bit" => {
+                depth = rw::BitDepth::Sixteen;
+                png_only.push(arg);
+                continue;
             }
-            self.cur_spec = new_spec;
-            self.cur_cmap = new_cmap;
-            should_recolor = true;
+            "--legend" => {
+                with_legend = true;
+                continue;
+            }
+            "--post-save" => {
+                match args.next().map(|s| hook::Hook::parse(s)) {
+                    Some(Ok(h)) => hook::set_post_save(Some(h)),
+                    Some(Err(e)) => {
+                        eprint!("--post-save: {}\n{}", &e, USAGE);
+                        return 2;
+                    }
+                    None => {
+                        eprint!("--post-save requires a command\n{}", USAGE);
+                        return 2;
+                    }
+                }
+                continue;
+            }
+            _ => {
+                files.push(arg);
+                continue;
+            }
+        };
+        match positive_arg(arg, &mut args) {
+            Some(n) => *opt = Some(n),
+            None => return 2,
         }
+    }
+    if files.len() != 2 {
+        eprint!("--export requires an input and an output file\n{}", USAGE);
+        return 2;
+    }
 
-        if should_redraw {
-            self.cur_imap = IterMap::new(self.cur_dims, self.cur_iter.clone(), self.cur_cmap.len());
-            should_recolor = true;
-        } else if should_reiterate {
-            self.cur_imap.reiterate(self.cur_cmap.len());
-            should_recolor = true;
+    let (dims, cspec, itype, status) = match rw::load_checked(files[0]) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Error loading {}: {}", files[0], &e);
+            return 1;
         }
+    };
+    if status != rw::LockStatus::Unlocked {
+        eprintln!("{}", status.describe());
+    }
+    let aspect = (dims.xpix as f64) / (dims.ypix as f64);
+    let (xpix, ypix) = match (width, height) {
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) => (w, ((w as f64) / aspect).round().max(1.0) as usize),
+        (None, Some(h)) => (((h as f64) * aspect).round().max(1.0) as usize, h),
+        (None, None) => (dims.xpix, dims.ypix),
+    };
+    let mut dims = dims.resize(xpix, ypix);
+    if let Some(n) = oversample {
+        dims.oversample = n.min(MAX_OVERSAMPLE);
+    }
 
-        if should_recolor {
-            self.cur_fimg = self.cur_imap.color(&self.cur_cmap);
+    let (xpix, ypix) = (dims.xpix, dims.ypix);
+    let quality = quality.map(|q| q.min(100) as u8);
+    let format = match (rw::ImageFormat::of(files[1]).unwrap_or_default(), quality) {
+        (rw::ImageFormat::Jpeg { .. }, Some(quality)) => rw::ImageFormat::Jpeg { quality },
+        (rw::ImageFormat::Webp { .. }, Some(100)) => rw::ImageFormat::Webp { quality: None },
+        (rw::ImageFormat::Webp { .. }, q @ Some(_)) => rw::ImageFormat::Webp { quality: q },
+        (format, _) => format,
+    };
+    if let (false, Some(opt)) = (format == rw::ImageFormat::Png, png_only.first()) {
+        eprint!(
+            "{} only works for PNGs; JPEG and WebP images are 8-bit and carry no parameters\n{}",
+            opt, USAGE
+        );
+        return 2;
+    }
+    let res = if format == rw::ImageFormat::Png && !with_legend {
+        headless::export(files[1], dims, cspec, itype, embed_metadata, depth)
+    } else {
+        // Legends go on the whole image, so it's rendered all at once.
+        let mut img = headless::render(dims, cspec.clone(), itype.clone());
+        if with_legend {
+            let cmap = ColorMap::make(cspec.clone());
+            legend::add_legend(&mut img, &dims, &cmap, &itype);
+        }
+        let params = Some((&dims, &cspec, &itype)).filter(|_| embed_metadata);
+        let best = rw::PngCompression::Best;
+        match (format, depth) {
+            (rw::ImageFormat::Png, rw::BitDepth::Sixteen) => {
+                let (xpix, ypix, data) = if img.is_transparent() {
+                    img.to_rgba16(1)
+                } else {
+                    img.to_rgb16(1)
+                };
+                rw::save16_with_metadata(files[1], xpix, ypix, &data, params, best)
+            }
+            (rw::ImageFormat::Png, _) => {
+                let (xpix, ypix, data) = img.to_bytes(1);
+                rw::save_with_metadata(files[1], xpix, ypix, &data, params, best)
+            }
+            (format, _) => {
+                let (xpix, ypix, data) = img.to_bytes(1);
+                rw::save_as(files[1], xpix, ypix, &data, format)
+            }
         }
+    };
+    if let Err(e) = res {
+        eprintln!("Error exporting {}: {}", files[1], &e);
+        return 1;
+    }
+    match hook::after_save(files[1], xpix, ypix) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error post-processing {}: {}", files[1], &e);
+            1
+        }
+    }
+}
 
-        let (x, y, data) = self.cur_fimg.to_rgb8(self.cur_scale);
+fn export_set_cli(args: &[String]) -> i32 {
+    let mut args = args.iter();
+    let mut files: Vec<&String> = Vec::new();
+    let mut sizes = export::DEFAULT_SIZES.to_string();
+    let mut embed_metadata = true;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sizes" => match args.next() {
+                Some(val) => sizes = val.clone(),
+                None => {
+                    eprint!("--sizes requires a list of sizes\n{}", USAGE);
+                    return 2;
+                }
+            },
+            "--no-metadata" => embed_metadata = false,
+            _ => files.push(arg),
+        }
+    }
+    if files.len() != 2 {
+        eprint!(
+            "--export-set requires an input and an output file\n{}",
+            USAGE
+        );
+        return 2;
+    }
+    let format = rw::ImageFormat::of(files[1]).unwrap_or_default();
+    if format != rw::ImageFormat::Png && !embed_metadata {
+        eprint!(
+            "--no-metadata only works for PNGs; JPEG and WebP images carry no parameters\n{}",
+            USAGE
+        );
+        return 2;
+    }
+    let sizes = match export::parse_sizes(&sizes) {
+        Ok(sizes) => sizes,
+        Err(e) => {
+            eprint!("{}\n{}", &e, USAGE);
+            return 2;
+        }
+    };
+
+    let (dims, cspec, itype) = match rw::load(files[0]) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Error loading {}: {}", files[0], &e);
+            return 1;
+        }
+    };
+    let base = Path::new(files[1]);
+    let res = export::export_set(
+        base,
+        &dims,
+        &cspec,
+        &itype,
+        &sizes,
+        format,
+        embed_metadata,
+        &IterWatcher::new(),
+        |_| true,
+    );
+    match res {
+        Ok(saved) => {
+            for fname in saved.iter() {
+                println!("{}", fname.display());
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("Error exporting {}: {}", files[1], &e);
+            1
+        }
+    }
+}
+
+fn mesh_cli(args: &[String]) -> i32 {
+    let mut args = args.iter();
+    let mut files: Vec<&String> = Vec::new();
+    let mut resolution = mesh::DEFAULT_RESOLUTION;
+    let mut scale = mesh::DEFAULT_SCALE;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--resolution" => match positive_arg(arg, &mut args) {
+                Some(n) => resolution = n,
+                None => return 2,
+            },
+            "--scale" => match args.next().map(|s| s.parse::<f64>()) {
+                Some(Ok(x)) if x.is_finite() && x >= 0.0 => scale = x,
+                _ => {
+                    eprint!("--scale requires a number that isn't negative\n{}", USAGE);
+                    return 2;
+                }
+            },
+            _ => files.push(arg),
+        }
+    }
+    if files.len() != 2 {
+        eprint!("--mesh requires an input and an output file\n{}", USAGE);
+        return 2;
+    }
+
+    let (dims, cspec, itype) = match rw::load(files[0]) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Error loading {}: {}", files[0], &e);
+            return 1;
+        }
+    };
+    match mesh::export(files[1], &dims, &cspec, itype, resolution, scale) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error exporting {}: {}", files[1], &e);
+            1
+        }
+    }
+}
+
+fn watch_cli(args: &[String]) -> i32 {
+    let mut args = args.iter();
+    let (mut inbox, mut outbox) = (None, None);
+    let mut interval = watch::DEFAULT_INTERVAL;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" | "-o" => match args.next() {
+                Some(dir) => outbox = Some(dir),
+                None => {
+                    eprint!("--output requires a folder\n{}", USAGE);
+                    return 2;
+                }
+            },
+            "--interval" => match positive_arg(arg, &mut args) {
+                Some(n) => interval = std::time::Duration::from_secs(n as u64),
+                None => return 2,
+            },
+            _ if inbox.is_none() => inbox = Some(arg),
+            _ => {
+                eprint!("unrecognized argument: {}\n{}", arg, USAGE);
+                return 2;
+            }
+        }
+    }
+    let inbox = match inbox {
+        Some(dir) => dir,
+        None => {
+            eprint!("--watch requires a folder to watch\n{}", USAGE);
+            return 2;
+        }
+    };
 
-        self.main_pane.set_image(x, y, data);
+    match watch::Watcher::new(inbox, outbox) {
+        Ok(mut w) => w.run(interval),
+        Err(e) => {
+            eprintln!("{}", &e);
+            1
+        }
     }
 }
 
+fn batch_cli(args: &[String]) -> i32 {
+    let mut args = args.iter();
+    let mut paths: Vec<&String> = Vec::new();
+    let mut outbox = ".";
+    let mut pattern = batch::DEFAULT_PATTERN;
+    let mut n_parallel = 1;
+    let mut embed_metadata = true;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" | "-o" | "--pattern" => match args.next() {
+                Some(val) if arg == "--pattern" => pattern = val,
+                Some(val) => outbox = val,
+                None => {
+                    eprint!("{} requires a value\n{}", arg, USAGE);
+                    return 2;
+                }
+            },
+            "--jobs" | "-j" => match positive_arg(arg, &mut args) {
+                Some(n) => n_parallel = n,
+                None => return 2,
+            },
+            "--no-metadata" => embed_metadata = false,
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        eprint!("--batch requires files or folders to render\n{}", USAGE);
+        return 2;
+    }
+
+    let mut batch = match batch::inputs(&paths).and_then(|f| batch::Batch::new(f, outbox, pattern))
+    {
+        Ok(b) => b,
+        Err(e) => {
+            eprintln!("{}", &e);
+            return 1;
+        }
+    };
+    batch.set_embed_metadata(embed_metadata);
+    let n_failed = batch.run(n_parallel);
+    if n_failed > 0 {
+        eprintln!("{} of {} files failed", n_failed, batch.jobs().len());
+        1
+    } else {
+        0
+    }
+}
+
+fn animate_cli(args: &[String]) -> i32 {
+    let mut args = args.iter();
+    let mut files: Vec<&String> = Vec::new();
+    let mut frames = None;
+    let mut easing = animate::Easing::Linear;
+    let mut base = "frame.png";
+    let mut morph = false;
+    let mut fps = video::DEFAULT_FPS;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--morph" => morph = true,
+            "--fps" => match positive_arg(arg, &mut args) {
+                Some(n) => fps = n as f64,
+                None => return 2,
+            },
+            "--frames" => match positive_arg(arg, &mut args) {
+                Some(n) => frames = Some(n),
+                None => return 2,
+            },
+            "--easing" => match args.next().map(|s| animate::Easing::from_name(s)) {
+                Some(Some(e)) => easing = e,
+                _ => {
+                    eprint!("--easing requires an easing\n{}", USAGE);
+                    return 2;
+                }
+            },
+            "--output" | "-o" => match args.next() {
+                Some(name) => base = name,
+                None => {
+                    eprint!("--output requires a file name\n{}", USAGE);
+                    return 2;
+                }
+            },
+            _ => files.push(arg),
+        }
+    }
+    let frames = match frames {
+        Some(n) => n,
+        None => {
+            eprint!("--animate requires a number of --frames\n{}", USAGE);
+            return 2;
+        }
+    };
+
+    let mut keyframes: Vec<animate::Keyframe> = Vec::with_capacity(files.len());
+    for f in files.iter() {
+        match rw::load(f) {
+            Ok(k) => keyframes.push(k),
+            Err(e) => {
+                eprintln!("Error loading {}: {}", f, &e);
+                return 1;
+            }
+        }
+    }
+    let mut anim = match animate::Animation::new(keyframes, frames, easing) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{}", &e);
+            return 1;
+        }
+    };
+    anim.set_morph(morph);
+    let (out, note) = video::Output::choose(base);
+    if let Some(note) = note {
+        eprintln!("{}", &note);
+    }
+    let res = anim.render_to(&out, fps, |done| {
+        eprintln!("rendered frame {} of {}", done, frames);
+        true
+    });
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", &e);
+            1
+        }
+    }
+}
+
+// Run a script (see the `script` module), with the arguments after
+// "--script". Returns the exit status.
+fn script_cli(args: &[String]) -> i32 {
+    let mut args = args.iter();
+    let mut file = None;
+    let mut start = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--start" => match args.next() {
+                Some(name) => start = Some(name),
+                None => {
+                    eprint!("--start requires a parameter file\n{}", USAGE);
+                    return 2;
+                }
+            },
+            _ if file.is_none() => file = Some(arg),
+            _ => {
+                eprint!("unexpected argument: {}\n{}", arg, USAGE);
+                return 2;
+            }
+        }
+    }
+    let file = match file {
+        Some(f) => PathBuf::from(f),
+        None => {
+            eprint!("--script requires a script to run\n{}", USAGE);
+            return 2;
+        }
+    };
+
+    let params = match start {
+        Some(start) => rw::load(start).map_err(|e| format!("Error loading {}: {}", start, &e)),
+        None => gallery::load(gallery::names()[0]),
+    };
+    let params = match params {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}", &e);
+            return 1;
+        }
+    };
+    let dir = file.parent().unwrap_or(Path::new("."));
+    let mut runner = script::Runner::new(params, dir);
+    runner.on_render(|path, _| eprintln!("rendered {}", path.display()));
+    match runner.run_file(&file) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", &e);
+            1
+        }
+    }
+}
+
+// Render a cycle of the colors through an image, with the arguments
+// after "--cycle". Returns the exit status.
+fn cycle_cli(args: &[String]) -> i32 {
+    let mut args = args.iter();
+    let mut file = None;
+    let mut frames = None;
+    let mut base = "cycle.png";
+    let mut fps = video::DEFAULT_FPS;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fps" => match positive_arg(arg, &mut args) {
+                Some(n) => fps = n as f64,
+                None => return 2,
+            },
+            "--frames" => match positive_arg(arg, &mut args) {
+                Some(n) => frames = Some(n),
+                None => return 2,
+            },
+            "--output" | "-o" => match args.next() {
+                Some(name) => base = name,
+                None => {
+                    eprint!("--output requires a file name\n{}", USAGE);
+                    return 2;
+                }
+            },
+            _ if file.is_none() => file = Some(arg),
+            _ => {
+                eprint!("unexpected argument: {}\n{}", arg, USAGE);
+                return 2;
+            }
+        }
+    }
+    let (file, frames) = match (file, frames) {
+        (Some(f), Some(n)) => (f, n),
+        _ => {
+            eprint!(
+                "--cycle requires a parameter file and a number of --frames\n{}",
+                USAGE
+            );
+            return 2;
+        }
+    };
+
+    let (dims, cspec, itype) = match rw::load(file) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Error loading {}: {}", file, &e);
+            return 1;
+        }
+    };
+    // The image only gets iterated once; every frame is just recolored.
+    let limit = ColorMap::make(cspec.clone()).limit();
+    let imap = IterMap::with_orbit(dims, itype, limit, cspec.orbit());
+    let (out, note) = video::Output::choose(base);
+    if let Some(note) = note {
+        eprintln!("{}", &note);
+    }
+    let res = cycle::render_to(&imap, &cspec, frames, &out, fps, |done| {
+        eprintln!("rendered frame {} of {}", done, frames);
+        true
+    });
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", &e);
+            1
+        }
+    }
+}
+
+// The setting from the environment variable `var`, or failing that, from
+// the preferences file.
+fn setting<T: ToString>(var: &str, pref: &Option<T>) -> Option<String> {
+    std::env::var(var)
+        .ok()
+        .or_else(|| pref.as_ref().map(|p| p.to_string()))
+}
+
 fn main() {
+    let mut prefs_path = std::env::var_os(PREFS_VAR)
+        .map(PathBuf::from)
+        .or_else(prefs::default_path);
+    let prefs = match prefs_path.as_ref().map(prefs::Prefs::load) {
+        Some(Ok(p)) => p,
+        Some(Err(e)) => {
+            eprintln!(
+                "Ignoring preferences file {}: {}",
+                prefs_path.as_ref().unwrap().display(),
+                &e
+            );
+            // Don't write over it on the way out, either.
+            prefs_path = None;
+            prefs::Prefs::default()
+        }
+        None => prefs::Prefs::default(),
+    };
+
+    if let Some(profile) = setting(COLOR_PROFILE_VAR, &prefs.color_profile)
+        .and_then(|s| rw::ColorProfile::from_name(&s))
+    {
+        rw::set_color_profile(profile);
+    }
+    if let Some(form) = setting(COEFFICIENTS_VAR, &prefs.coefficients) {
+        jset_desk::cx::set_polar_output(form.trim().eq_ignore_ascii_case("polar"));
+    }
+    if let Some(cmd) = setting(POST_SAVE_VAR, &prefs.post_save) {
+        match hook::Hook::parse(&cmd) {
+            Ok(h) => hook::set_post_save(Some(h)),
+            Err(e) => eprintln!("Ignoring {}: {}", POST_SAVE_VAR, &e),
+        }
+    }
+    if let Some(program) = setting(FFMPEG_VAR, &prefs.ffmpeg) {
+        video::set_ffmpeg(Some(program));
+    }
+    if let Some(n) = prefs.threads.filter(|n| *n > 0) {
+        set_iteration_threads(n);
+    }
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(run_cli(&args));
+    }
+
     let version = format!("{} beta", VERSION);
     fltk::window::DoubleWindow::set_default_xclass(X_CLASS);
 
     let (sndr, rcvr) = mpsc::channel::<Msg>();
-    let dims = ImageDims {
+    let mut dims = ImageDims {
         xpix: 900,
         ypix: 600,
         x: -2.0,
         y: 1.0,
         width: 3.0,
+        oversample: 1,
+        adaptive: false,
+        check_period: true,
     };
+    if let (Some(w), Some(h)) = (prefs.width, prefs.height) {
+        if w > 0 && h > 0 {
+            dims = dims.resize(w, h);
+        }
+    }
+    if let Some(n) = prefs.oversample {
+        dims.oversample = n.clamp(1, MAX_OVERSAMPLE);
+        dims.adaptive = dims.oversample > 1;
+    }
+    let white = ColorSpec::new(vec![Gradient::default()], RGB::WHITE);
+    let cspec = match &prefs.palette {
+        Some(p) => rw::load_colors(p).unwrap_or_else(|e| {
+            eprintln!("Unable to load palette {}: {}", p.display(), &e);
+            white
+        }),
+        None => white,
+    };
+    if let Some(dir) = &prefs.last_dir {
+        ui::set_last_dir(dir);
+    }
 
     let a = fltk::app::App::default();
-
-    let mut main_pane = ui::img::ImgPane::new(sndr.clone(), &version, dims);
-    let initial_spec = ColorSpec::new(vec![Gradient::default()], RGB::WHITE);
-    let colr_pane = ui::color::ColorPane::new(initial_spec, sndr.clone());
-    let iter_pane = ui::iter::IterPane::new(IterType::Mandlebrot, sndr.clone());
-
-    let color_spec = colr_pane.get_spec();
-    let color_map = ColorMap::make(color_spec.clone());
-    let iter_type = iter_pane.get_itertype();
-    let iter_map = IterMap::new(dims, iter_type.clone(), color_map.len());
-
-    let fp_image = iter_map.color(&color_map);
-
-    let (xpix, ypix, rgb_data) = fp_image.to_rgb8(1);
-    main_pane.set_image(xpix, ypix, rgb_data);
-
-    let mut globs = Globs {
-        iter_pane,
-        colr_pane,
-        main_pane,
-
-        cur_dims: dims,
-        cur_iter: iter_type,
-        cur_spec: color_spec,
-        cur_cmap: color_map,
-        cur_imap: iter_map,
-        cur_fimg: fp_image,
-
-        cur_scale: 1,
+    let parse_scale = |var: &str, pref: &Option<f64>| {
+        setting(var, pref)
+            .and_then(|s| s.trim().parse::<f64>().ok())
+            .filter(|x| x.is_finite() && *x > 0.0)
     };
+    let detected = ui::layout::take_over_screen_scaling();
+    let screen_scale = parse_scale(SCREEN_SCALE_VAR, &prefs.screen_scale).unwrap_or(detected);
+    let ui_scale = parse_scale(UI_SCALE_VAR, &prefs.ui_scale).unwrap_or(1.0);
+    ui::layout::set_scale(screen_scale * ui_scale);
+    // Without a saved size, start with an image that covers about as much of
+    // the screen as it would on an ordinary one.
+    if prefs.width.is_none() || prefs.height.is_none() {
+        dims = dims.resize(
+            ui::layout::for_screen(dims.xpix, screen_scale),
+            ui::layout::for_screen(dims.ypix, screen_scale),
+        );
+    }
+    if let Some(style) =
+        setting(CONTROLS_VAR, &prefs.controls).and_then(|s| ui::layout::ControlStyle::from_name(&s))
+    {
+        ui::layout::set_control_style(style);
+    }
+    if let Some(panes) =
+        setting(PANES_VAR, &prefs.panes).and_then(|s| ui::layout::PanePlacement::from_name(&s))
+    {
+        ui::layout::set_pane_placement(panes);
+    }
+    let pane_style = |var: &str, pref: &Option<String>| match setting(var, pref) {
+        Some(s) => ui::layout::PaneStyle::parse(&s).unwrap_or_else(|e| {
+            eprintln!("Ignoring {}: {}", var, &e);
+            ui::layout::PaneStyle::NORMAL
+        }),
+        None => ui::layout::PaneStyle::NORMAL,
+    };
+    ui::layout::set_pane_styles(
+        pane_style(COLOR_PANE_VAR, &prefs.color_pane),
+        pane_style(ITER_PANE_VAR, &prefs.iter_pane),
+    );
+    if let Some(secs) = setting(TIME_LIMIT_VAR, &prefs.time_limit)
+        .and_then(|s| s.trim().parse::<f64>().ok())
+        .filter(|s| s.is_finite() && *s > 0.0)
+    {
+        render::set_time_budget(Some(std::time::Duration::from_secs_f64(secs)));
+    }
+
+    let mut globs = Globs::new(sndr, &version, dims, cspec);
+    globs.move_windows(&prefs.windows);
 
     while a.wait() {
         if let Ok(message) = rcvr.try_recv() {
-            #[cfg(debug_assertions)]
-            println!("{:?}", &message);
-            match message {
-                Msg::FocusColorPane => {
-                    globs.colr_pane.raise();
-                }
-                Msg::FocusIterPane => {
-                    globs.iter_pane.raise();
-                }
-                Msg::FocusMainPane => {
-                    globs.main_pane.raise();
-                }
-                Msg::Load => {
-                    //let fname = match ui::pick_a_file(".toml") {
-                    let fname =
-                        match ui::pick_a_file("PNG files (*.png)\tMarkup files (*.toml)", false) {
-                            Some(f) => f,
-                            None => {
-                                continue;
-                            }
-                        };
-                    match rw::load(&fname) {
-                        Err(e) => {
-                            dialog::message_default(&format!("Error loading {}: {}", &fname, &e))
-                        }
-                        Ok((dims, cspec, itype)) => {
-                            globs.colr_pane.respec(cspec);
-                            globs.iter_pane = ui::iter::IterPane::new(itype, sndr.clone());
-                            globs.main_pane.set_input_dimensions(dims.xpix, dims.ypix);
-                            globs.recheck_and_redraw(dims);
-                        }
-                    }
-                }
-                Msg::Nudge(fxpix, fypix) => {
-                    let mut dims = globs.cur_dims;
-                    let xfrac = fxpix / (dims.xpix as f64);
-                    let yfrac = fypix / (dims.ypix as f64);
-                    let (dx, dy) = (xfrac * dims.width, yfrac * dims.height());
-                    dims.x += dx;
-                    dims.y -= dy;
-
-                    globs.recheck_and_redraw(dims);
-                }
-                Msg::Recenter(xfrac, yfrac) => {
-                    let dims = globs.cur_dims.recenter(xfrac, yfrac);
-                    globs.recheck_and_redraw(dims);
-                }
-                Msg::Redraw(owidth, oheight) => {
-                    let dims = globs.cur_dims;
-                    let new_xpix = match owidth {
-                        Some(x) => x,
-                        None => dims.xpix,
-                    };
-                    let new_ypix = match oheight {
-                        Some(y) => y,
-                        None => dims.ypix,
-                    };
-                    let new_dims = dims.resize(new_xpix, new_ypix);
-                    globs.recheck_and_redraw(new_dims);
-                }
-                Msg::SaveImage => {
-                    let fname = match ui::pick_a_file(".png", true) {
-                        Some(fname) => fname,
-                        None => {
-                            continue;
-                        }
-                    };
-                    let (xpix, ypix, data) = globs.main_pane.get_image();
-                    //~ if let Err(e) = rw::save_as_png(fname, xpix, ypix, &data) {
-                    //~ dialog::message_default(&e);
-                    //~ };
-                    if let Err(e) = rw::save_with_metadata(
-                        fname,
-                        xpix,
-                        ypix,
-                        &data,
-                        &globs.cur_dims,
-                        &globs.cur_spec,
-                        &globs.cur_iter,
-                    ) {
-                        dialog::message_default(&e);
-                    };
-                }
-                Msg::SaveValues => {
-                    let fname = match ui::pick_a_file(".toml", true) {
-                        Some(f) => f,
-                        None => {
-                            continue;
-                        }
-                    };
-                    if let Err(estr) =
-                        rw::save(&globs.cur_dims, &globs.cur_spec, &globs.cur_iter, &fname)
-                    {
-                        dialog::message_default(&estr);
-                    }
-                }
-                Msg::Scale(n) => {
-                    globs.cur_scale = n;
-                    globs.recheck_and_redraw(globs.cur_dims);
-                }
-                Msg::Zoom(r) => {
-                    let dims = globs.cur_dims.zoom(r);
-                    globs.recheck_and_redraw(dims);
-                }
-            }
+            globs.handle(message);
+        }
+    }
+
+    if let Some(path) = prefs_path {
+        let (dims, _, _) = globs.params();
+        let prefs = prefs::Prefs {
+            width: Some(dims.xpix),
+            height: Some(dims.ypix),
+            oversample: Some(dims.samples()),
+            last_dir: ui::last_dir(),
+            windows: globs.window_positions(),
+            ..prefs
+        };
+        if let Err(e) = prefs.save(&path) {
+            eprintln!("Unable to save preferences to {}: {}", path.display(), &e);
         }
     }
 }
@@ -0,0 +1,177 @@
+/*!
+Converting between locations in an image and points on the complex plane.
+
+Pixel (_xp_, _yp_) of an image described by an `ImageDims` samples the
+point at its upper-left corner, that is, `xp / xpix` of the way across the
+view and `yp / ypix` of the way down it. Pixel rows count _down_ from the
+top of the image, while the imaginary axis points _up_, so the vertical
+direction gets flipped on the way through.
+
+All the code that needs to know this (iterating, recentering, nudging)
+should go through here so that it all agrees.
+*/
+
+use crate::image::ImageDims;
+
+/**
+Return the point on the plane `x_frac` of the way across the view and
+`y_frac` of the way down it.
+*/
+pub fn frac_to_plane(dims: &ImageDims, x_frac: f64, y_frac: f64) -> (f64, f64) {
+    (
+        dims.x + (x_frac * dims.width),
+        dims.y - (y_frac * dims.height()),
+    )
+}
+
+/**
+Return how far across and down the view the point (`x`, `y`) is, as
+fractions of the width and height of the view. This is the inverse of
+`frac_to_plane()`.
+*/
+pub fn plane_to_frac(dims: &ImageDims, x: f64, y: f64) -> (f64, f64) {
+    ((x - dims.x) / dims.width, (dims.y - y) / dims.height())
+}
+
+/** Return the real coordinate sampled by pixels in column `xp`. */
+pub fn pixel_x(dims: &ImageDims, xp: usize) -> f64 {
+    let x_frac = (xp as f64) / (dims.xpix as f64);
+    dims.x + (x_frac * dims.width)
+}
+
+/** Return the imaginary coordinate sampled by pixels in row `yp`. */
+pub fn pixel_y(dims: &ImageDims, yp: usize) -> f64 {
+    let y_frac = (yp as f64) / (dims.ypix as f64);
+    dims.y - (y_frac * dims.height())
+}
+
+/** Return the point on the plane sampled by pixel (`xp`, `yp`). */
+pub fn pixel_to_plane(dims: &ImageDims, xp: usize, yp: usize) -> (f64, f64) {
+    (pixel_x(dims, xp), pixel_y(dims, yp))
+}
+
+/**
+Return the (fractional) pixel coordinates of the point (`x`, `y`). The
+point sampled by pixel (_xp_, _yp_) comes back as exactly (_xp_, _yp_),
+give or take rounding error.
+*/
+pub fn plane_to_pixel(dims: &ImageDims, x: f64, y: f64) -> (f64, f64) {
+    let (x_frac, y_frac) = plane_to_frac(dims, x, y);
+    (x_frac * (dims.xpix as f64), y_frac * (dims.ypix as f64))
+}
+
+/**
+Return the distance on the plane corresponding to moving `dx` pixels right
+and `dy` pixels down. (The vertical component will have the opposite sign
+from `dy`.)
+*/
+pub fn pixel_offset(dims: &ImageDims, dx: f64, dy: f64) -> (f64, f64) {
+    let x_frac = dx / (dims.xpix as f64);
+    let y_frac = dy / (dims.ypix as f64);
+    (x_frac * dims.width, -(y_frac * dims.height()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::*;
+
+    const EPSILON: f64 = 1.0e-12;
+
+    fn dims() -> ImageDims {
+        ImageDims {
+            xpix: 900,
+            ypix: 600,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        }
+    }
+
+    fn close(a: (f64, f64), b: (f64, f64)) -> bool {
+        (a.0 - b.0).abs() < EPSILON && (a.1 - b.1).abs() < EPSILON
+    }
+
+    #[test]
+    fn corners() {
+        let d = dims();
+        assert!(close(pixel_to_plane(&d, 0, 0), (-2.0, 1.0)));
+        assert!(close(frac_to_plane(&d, 1.0, 1.0), (1.0, -1.0)));
+        assert!(close(frac_to_plane(&d, 0.5, 0.5), d.center()));
+    }
+
+    #[test]
+    fn round_trips() {
+        let d = dims();
+        for &(xp, yp) in [(0, 0), (17, 433), (899, 599), (450, 300)].iter() {
+            let (x, y) = pixel_to_plane(&d, xp, yp);
+            assert!(close(plane_to_pixel(&d, x, y), (xp as f64, yp as f64)));
+        }
+        for &(xf, yf) in [(0.0, 0.0), (0.25, 0.8), (1.0, 1.0)].iter() {
+            let (x, y) = frac_to_plane(&d, xf, yf);
+            assert!(close(plane_to_frac(&d, x, y), (xf, yf)));
+        }
+    }
+
+    #[test]
+    fn offset_matches_pixels() {
+        let d = dims();
+        let (x0, y0) = pixel_to_plane(&d, 100, 200);
+        let (x1, y1) = pixel_to_plane(&d, 130, 190);
+        assert!(close(pixel_offset(&d, 30.0, -10.0), (x1 - x0, y1 - y0)));
+    }
+
+    // Recentering on a spot should put the point that was there at the
+    // center of the new view.
+    #[test]
+    fn recenter_on_pixel() {
+        let d = dims();
+        let (xf, yf) = (123.0 / 900.0, 456.0 / 600.0);
+        let target = frac_to_plane(&d, xf, yf);
+        let nd = d.recenter(xf, yf);
+        assert!(close(nd.center(), target));
+        assert!(close(frac_to_plane(&nd, 0.5, 0.5), target));
+    }
+
+    // Regression test: iterating to a limit in one go and iterating to a
+    // lower limit then reiterating have to sample the same points, or the
+    // image will shift when only the color map changes.
+    #[test]
+    fn iterate_matches_reiterate() {
+        let d = ImageDims {
+            xpix: 61,
+            ypix: 37,
+            ..dims()
+        };
+        let itertypes = vec![
+            IterType::Mandlebrot,
+            IterType::PseudoMandlebrot {
+                a: crate::cx::Cx { re: 0.3, im: -0.2 },
+                b: crate::cx::Cx { re: 0.0, im: 0.0 },
+            },
+            IterType::Polynomial {
+                coefs: vec![
+                    crate::cx::Cx {
+                        re: -0.8,
+                        im: 0.156,
+                    },
+                    crate::cx::Cx { re: 0.0, im: 0.0 },
+                    crate::cx::Cx { re: 1.0, im: 0.0 },
+                ],
+            },
+            IterType::Exponential,
+        ];
+        let spec = ColorSpec::new(vec![Gradient::default(); 4], RGB::BLACK);
+        let cmap = ColorMap::make(spec);
+
+        for it in itertypes.into_iter() {
+            let direct = IterMap::new(d, it.clone(), cmap.len());
+            let mut stepped = IterMap::new(d, it, cmap.len() / 4);
+            stepped.reiterate(cmap.len());
+            assert_eq!(direct.color(&cmap).pixels(), stepped.color(&cmap).pixels());
+        }
+    }
+}
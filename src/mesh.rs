@@ -0,0 +1,293 @@
+/*!
+Turning an image's iteration counts into a 3D height field, written as a
+mesh (Wavefront OBJ or binary STL) for 3D printing or rendering in other
+programs.
+
+The heights are the log of the counts (like the shading in the `image`
+module uses), scaled so the points that never escape make a flat plateau
+at the top. The mesh is a closed solid, with walls around the edge and a
+flat bottom, so it can go straight to a slicer; it's `MODEL_SIZE` units
+across its longer side, sitting on a base `BASE_HEIGHT` units thick.
+*/
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::image::*;
+
+/** How many units across the longer side of the mesh is. */
+pub const MODEL_SIZE: f64 = 100.0;
+/** How thick the solid base under the height field is. */
+pub const BASE_HEIGHT: f64 = 2.0;
+/** The default number of vertices along the longer side. */
+pub const DEFAULT_RESOLUTION: usize = 256;
+/** The most vertices along either side (more than this is just huge). */
+pub const MAX_RESOLUTION: usize = 4096;
+/**
+The default height of the plateau above the base, as a fraction of the
+longer side.
+*/
+pub const DEFAULT_SCALE: f64 = 0.1;
+
+/** The kinds of file a mesh can be written as. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshFormat {
+    Obj,
+    Stl,
+}
+
+impl MeshFormat {
+    /** Choose the format by the extension of `fname`. */
+    pub fn from_path<P: AsRef<Path>>(fname: P) -> Result<MeshFormat, String> {
+        let ext = fname
+            .as_ref()
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match ext.as_deref() {
+            Some("obj") => Ok(MeshFormat::Obj),
+            Some("stl") => Ok(MeshFormat::Stl),
+            _ => Err(format!(
+                "{} should end in .obj or .stl",
+                fname.as_ref().display()
+            )),
+        }
+    }
+}
+
+/**
+A triangle mesh: `triangles` index into `vertices`, with their corners
+counterclockwise as seen from outside.
+*/
+pub struct Mesh {
+    pub vertices: Vec<[f32; 3]>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+/**
+Return the dimensions to iterate for a mesh of `resolution` vertices
+along the longer side of the region `dims` covers (at least two each
+way, and never oversampled, since each sample is a vertex).
+*/
+pub fn mesh_dims(dims: &ImageDims, resolution: usize) -> ImageDims {
+    let res = resolution.clamp(2, MAX_RESOLUTION) as f64;
+    let aspect = dims.xpix as f64 / dims.ypix as f64;
+    let (xpix, ypix) = if aspect >= 1.0 {
+        (res, (res / aspect).round())
+    } else {
+        ((res * aspect).round(), res)
+    };
+    let mut dims = dims.resize(xpix.max(2.0) as usize, ypix.max(2.0) as usize);
+    dims.oversample = 1;
+    dims.adaptive = false;
+    dims
+}
+
+/**
+Return the height of each pixel of `imap`, from 0 to 1, row by row: the
+log of its count over the log of the limit. Points that never escape
+(and rows that failed to iterate) are at 1.
+*/
+pub fn heights(imap: &IterMap) -> Vec<f64> {
+    let dims = imap.dims();
+    let top = (imap.limit() as f64).ln_1p().max(f64::MIN_POSITIVE);
+    let mut h = Vec::with_capacity(dims.xpix * dims.ypix);
+    for y in 0..dims.ypix {
+        for x in 0..dims.xpix {
+            let n = imap.count_at(x, y).unwrap_or(usize::MAX).min(imap.limit());
+            h.push((n as f64).ln_1p() / top);
+        }
+    }
+    h
+}
+
+/**
+Build a closed mesh of the height field `heights` (`xpix` wide, rows
+running down the image, each from 0 to 1), with the tallest points
+`scale` times the longer side above the base.
+*/
+pub fn build(xpix: usize, heights: &[f64], scale: f64) -> Mesh {
+    let ypix = heights.len() / xpix;
+    let step = MODEL_SIZE / ((xpix.max(ypix) - 1).max(1) as f64);
+    let rise = scale * MODEL_SIZE;
+    let n = (xpix * ypix) as u32;
+    let mut vertices: Vec<[f32; 3]> = Vec::with_capacity(2 * n as usize);
+
+    // The top surface, then the bottom right under it. Image rows run
+    // down, but y runs up.
+    for z in [None, Some(0.0)] {
+        for j in 0..ypix {
+            for i in 0..xpix {
+                let h = z.unwrap_or(BASE_HEIGHT + rise * heights[j * xpix + i]);
+                let y = (ypix - 1 - j) as f64 * step;
+                vertices.push([(i as f64 * step) as f32, y as f32, h as f32]);
+            }
+        }
+    }
+
+    let top = |i: usize, j: usize| (j * xpix + i) as u32;
+    let mut triangles: Vec<[u32; 3]> = Vec::new();
+    for j in 0..(ypix - 1) {
+        for i in 0..(xpix - 1) {
+            let (a, b, c, d) = (top(i, j), top(i + 1, j), top(i, j + 1), top(i + 1, j + 1));
+            triangles.push([a, c, b]);
+            triangles.push([b, c, d]);
+            triangles.push([a + n, b + n, c + n]);
+            triangles.push([b + n, d + n, c + n]);
+        }
+    }
+
+    // The walls, going counterclockwise around the edge as seen from
+    // above: along the bottom row, up the right side, back along the top
+    // row, and down the left side.
+    let mut edge: Vec<u32> = Vec::with_capacity(2 * (xpix + ypix));
+    edge.extend((0..xpix).map(|i| top(i, ypix - 1)));
+    edge.extend((0..(ypix - 1)).rev().map(|j| top(xpix - 1, j)));
+    edge.extend((0..(xpix - 1)).rev().map(|i| top(i, 0)));
+    edge.extend((1..(ypix - 1)).map(|j| top(0, j)));
+    for (k, &p) in edge.iter().enumerate() {
+        let q = edge[(k + 1) % edge.len()];
+        triangles.push([p, p + n, q + n]);
+        triangles.push([p, q + n, q]);
+    }
+
+    Mesh {
+        vertices,
+        triangles,
+    }
+}
+
+// The unit normal of triangle `t` of `mesh`.
+fn normal(mesh: &Mesh, t: &[u32; 3]) -> [f32; 3] {
+    let [a, b, c] = t.map(|n| mesh.vertices[n as usize]);
+    let (u, v) = (
+        [b[0] - a[0], b[1] - a[1], b[2] - a[2]],
+        [c[0] - a[0], c[1] - a[1], c[2] - a[2]],
+    );
+    let n = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+    let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+    if len > 0.0 {
+        n.map(|x| x / len)
+    } else {
+        n
+    }
+}
+
+/** Write `mesh` to `w` as a Wavefront OBJ file. */
+pub fn write_obj<W: Write>(mesh: &Mesh, mut w: W) -> std::io::Result<()> {
+    writeln!(w, "# height map from jset_desk")?;
+    for v in mesh.vertices.iter() {
+        writeln!(w, "v {} {} {}", v[0], v[1], v[2])?;
+    }
+    // OBJ counts vertices from 1.
+    for t in mesh.triangles.iter() {
+        writeln!(w, "f {} {} {}", t[0] + 1, t[1] + 1, t[2] + 1)?;
+    }
+    w.flush()
+}
+
+/** Write `mesh` to `w` as a binary STL file. */
+pub fn write_stl<W: Write>(mesh: &Mesh, mut w: W) -> std::io::Result<()> {
+    let mut header = [0u8; 80];
+    let title = b"height map from jset_desk";
+    header[..title.len()].copy_from_slice(title);
+    w.write_all(&header)?;
+    w.write_all(&(mesh.triangles.len() as u32).to_le_bytes())?;
+    for t in mesh.triangles.iter() {
+        let corners = t.map(|n| mesh.vertices[n as usize]);
+        for v in std::iter::once(normal(mesh, t)).chain(corners) {
+            for x in v.iter() {
+                w.write_all(&x.to_le_bytes())?;
+            }
+        }
+        w.write_all(&[0, 0])?;
+    }
+    w.flush()
+}
+
+/**
+Iterate the region `dims` covers with `itype` (as far as `cspec` says to)
+at `resolution` vertices along its longer side, and write the height
+field as a mesh to `dest`, in the format its extension says. `scale` is
+the height of the plateau above the base, as a fraction of the longer
+side.
+*/
+pub fn export<P: AsRef<Path>>(
+    dest: P,
+    dims: &ImageDims,
+    cspec: &ColorSpec,
+    itype: IterType,
+    resolution: usize,
+    scale: f64,
+) -> Result<(), String> {
+    let dest = dest.as_ref();
+    let format = MeshFormat::from_path(dest)?;
+    let dims = mesh_dims(dims, resolution);
+    let imap = IterMap::with_orbit(dims, itype, cspec.limit(), cspec.orbit());
+    let mesh = build(dims.xpix, &heights(&imap), scale);
+
+    let f = match File::create(dest) {
+        Ok(f) => f,
+        Err(e) => {
+            let estr = format!("Error opening {} for writing: {}", dest.display(), &e);
+            return Err(estr);
+        }
+    };
+    let w = BufWriter::new(f);
+    let res = match format {
+        MeshFormat::Obj => write_obj(&mesh, w),
+        MeshFormat::Stl => write_stl(&mesh, w),
+    };
+    res.map_err(|e| format!("Error writing {}: {}", dest.display(), &e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn closed_mesh() {
+        let heights = [0.0, 0.5, 1.0, 0.25, 0.75, 0.0];
+        let mesh = build(3, &heights, 0.1);
+        assert_eq!(mesh.vertices.len(), 12);
+        // Two triangles per cell, top and bottom, and two per edge segment.
+        assert_eq!(mesh.triangles.len(), 2 * 2 * 2 + 2 * 6);
+        // Closed and consistently wound: every edge is used exactly once
+        // each way.
+        let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+        for t in mesh.triangles.iter() {
+            for k in 0..3 {
+                *edges.entry((t[k], t[(k + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in edges.iter() {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1));
+        }
+        // The top faces up and the highest point is where it should be.
+        assert!(normal(&mesh, &mesh.triangles[0])[2] > 0.0);
+        assert!(normal(&mesh, &mesh.triangles[2])[2] < 0.0);
+        assert_eq!(
+            mesh.vertices[2],
+            [100.0, 50.0, (BASE_HEIGHT + 0.1 * MODEL_SIZE) as f32]
+        );
+
+        let mut stl: Vec<u8> = Vec::new();
+        write_stl(&mesh, &mut stl).unwrap();
+        assert_eq!(stl.len(), 84 + 50 * mesh.triangles.len());
+        let mut obj: Vec<u8> = Vec::new();
+        write_obj(&mesh, &mut obj).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        assert_eq!(obj.lines().filter(|l| l.starts_with("v ")).count(), 12);
+        assert!(obj.contains("\nf 1 4 2\n"));
+
+        assert_eq!(MeshFormat::from_path("a.STL"), Ok(MeshFormat::Stl));
+        assert!(MeshFormat::from_path("a.png").is_err());
+    }
+}
@@ -0,0 +1,226 @@
+/*!
+Blending one set of image parameters into another, for animations that
+morph one Julia-type set into another rather than just flying around a
+single one.
+
+Everything that can change smoothly does:
+
+  * The view, the same way `animate` zooms (logarithmically).
+  * The iterator's coefficients, as long as both ends are the same kind of
+    iterator. (The Mandlebrot iterator counts as a pseudo-Mandlebrot one
+    with both coefficients 1, and polynomials of different degrees are
+    padded out with zero coefficients.)
+  * The colors, as long as both color maps have the same number of
+    gradients: each gradient's ends, its length, the default color, and
+    the phase.
+
+Anything else (a Mandlebrot iterator into a polynomial, say, or the way
+colors are mapped to iteration counts) switches over halfway.
+*/
+
+use crate::animate::{interpolate_dims, Keyframe};
+use crate::cx::Cx;
+use crate::image::*;
+
+fn mix(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+fn mix_cx(a: Cx, b: Cx, t: f64) -> Cx {
+    Cx::rect(mix(a.re, b.re, t), mix(a.im, b.im, t))
+}
+
+fn mix_rgb(a: RGB, b: RGB, t: f64) -> RGB {
+    let t = t as f32;
+    RGB::new(
+        a.r() + (b.r() - a.r()) * t,
+        a.g() + (b.g() - a.g()) * t,
+        a.b() + (b.b() - a.b()) * t,
+    )
+}
+
+// Whichever of `a` and `b` `t` is closer to.
+fn nearer<'a, T>(a: &'a T, b: &'a T, t: f64) -> &'a T {
+    if t < 0.5 {
+        a
+    } else {
+        b
+    }
+}
+
+/**
+Return the iterator `t` of the way (from 0 to 1) from `a` to `b`, or
+`None` if there's no way to blend them.
+*/
+pub fn blend_iterators(a: &IterType, b: &IterType, t: f64) -> Option<IterType> {
+    let one = Cx::rect(1.0, 0.0);
+    let as_pseudo = |it: &IterType| match it {
+        IterType::Mandlebrot => Some((one, one)),
+        IterType::PseudoMandlebrot { a, b } => Some((*a, *b)),
+        _ => None,
+    };
+    match (a, b) {
+        (IterType::Mandlebrot, IterType::Mandlebrot) => Some(IterType::Mandlebrot),
+        // The tricorn and the transcendental iterators have nothing to
+        // blend.
+        (IterType::Tricorn, IterType::Tricorn)
+        | (IterType::Exponential, IterType::Exponential)
+        | (IterType::Sine, IterType::Sine)
+        | (IterType::Cosine, IterType::Cosine) => Some(a.clone()),
+        (IterType::Polynomial { coefs: ca }, IterType::Polynomial { coefs: cb }) => {
+            let zero = Cx::rect(0.0, 0.0);
+            let n = ca.len().max(cb.len());
+            let coef = |c: &[Cx], i: usize| c.get(i).copied().unwrap_or(zero);
+            let coefs = (0..n)
+                .map(|i| mix_cx(coef(ca, i), coef(cb, i), t))
+                .collect();
+            Some(IterType::Polynomial { coefs })
+        }
+        _ => {
+            let ((aa, ab), (ba, bb)) = (as_pseudo(a)?, as_pseudo(b)?);
+            Some(IterType::PseudoMandlebrot {
+                a: mix_cx(aa, ba, t),
+                b: mix_cx(ab, bb, t),
+            })
+        }
+    }
+}
+
+/**
+Return the color map `t` of the way (from 0 to 1) from `a` to `b`, or
+`None` if they have different numbers of gradients.
+*/
+pub fn blend_colors(a: &ColorSpec, b: &ColorSpec, t: f64) -> Option<ColorSpec> {
+    let (ga, gb) = (a.clone().gradients(), b.clone().gradients());
+    if ga.len() != gb.len() {
+        return None;
+    }
+    let gradients = ga
+        .iter()
+        .zip(gb.iter())
+        .map(|(x, y)| Gradient {
+            steps: mix(x.steps as f64, y.steps as f64, t).round().max(1.0) as usize,
+            start: mix_rgb(x.start, y.start, t),
+            end: mix_rgb(x.end, y.end, t),
+            space: nearer(x, y, t).space,
+        })
+        .collect();
+    let mut spec = ColorSpec::new(gradients, mix_rgb(a.default(), b.default(), t));
+    let other = nearer(a, b, t);
+    spec.set_repeats(other.repeats());
+    spec.set_overflow(other.overflow());
+    spec.set_transparent(other.transparent());
+    spec.set_iterations(other.iterations());
+    spec.set_transfer(other.transfer());
+    spec.set_adjustment(other.adjustment());
+    spec.set_curves(other.curves().clone());
+    spec.set_shading(other.shading());
+    spec.set_orbit(other.orbit());
+    spec.set_layers(other.layers().to_vec());
+    // The phase goes around in a circle, so take the short way around.
+    let mut d = b.phase() - a.phase();
+    if d > 0.5 {
+        d -= 1.0;
+    } else if d < -0.5 {
+        d += 1.0;
+    }
+    spec.set_phase(a.phase() + d * t);
+    Some(spec)
+}
+
+/**
+Return the image parameters `t` of the way (from 0 to 1) from `a` to `b`,
+the size in pixels (and sampling) of `a`.
+*/
+pub fn blend(a: &Keyframe, b: &Keyframe, t: f64) -> Keyframe {
+    let t = t.clamp(0.0, 1.0);
+    let (da, ca, ia) = a;
+    let (db, cb, ib) = b;
+    let dims = interpolate_dims(da, &db.resize(da.xpix, da.ypix), t);
+    let cspec = blend_colors(ca, cb, t).unwrap_or_else(|| nearer(ca, cb, t).clone());
+    let itype = blend_iterators(ia, ib, t).unwrap_or_else(|| nearer(ia, ib, t).clone());
+    (dims, cspec, itype)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn blending() {
+        let c = |re: f64, im: f64| Cx::rect(re, im);
+        let quad = IterType::Polynomial {
+            coefs: vec![c(-0.75, 0.25), c(0.0, 0.0), c(1.0, 0.0)],
+        };
+        let cubic = IterType::Polynomial {
+            coefs: vec![c(0.25, 0.25), c(0.0, 0.0), c(0.0, 0.0), c(1.0, 0.0)],
+        };
+        assert_eq!(
+            blend_iterators(&quad, &cubic, 0.5),
+            Some(IterType::Polynomial {
+                coefs: vec![c(-0.25, 0.25), c(0.0, 0.0), c(0.5, 0.0), c(0.5, 0.0)]
+            })
+        );
+        let pseudo = IterType::PseudoMandlebrot {
+            a: c(3.0, 0.0),
+            b: c(1.0, 2.0),
+        };
+        assert_eq!(
+            blend_iterators(&IterType::Mandlebrot, &pseudo, 0.5),
+            Some(IterType::PseudoMandlebrot {
+                a: c(2.0, 0.0),
+                b: c(1.0, 1.0)
+            })
+        );
+        assert_eq!(blend_iterators(&quad, &pseudo, 0.5), None);
+
+        let mut red = ColorSpec::new(
+            vec![Gradient {
+                steps: 100,
+                start: RGB::BLACK,
+                end: RGB::new(255.0, 0.0, 0.0),
+                space: Interpolation::Rgb,
+            }],
+            RGB::BLACK,
+        );
+        red.set_phase(0.9);
+        let mut blue = ColorSpec::new(
+            vec![Gradient {
+                steps: 200,
+                start: RGB::WHITE,
+                end: RGB::new(0.0, 0.0, 255.0),
+                space: Interpolation::Lab,
+            }],
+            RGB::WHITE,
+        );
+        blue.set_phase(0.1);
+        let half = blend_colors(&red, &blue, 0.5).unwrap();
+        let g = half.clone().gradients()[0];
+        assert_eq!((g.steps, g.space), (150, Interpolation::Lab));
+        assert_eq!(g.end, RGB::new(127.5, 0.0, 127.5));
+        assert_eq!(half.default(), RGB::new(127.5, 127.5, 127.5));
+        // The short way around from 0.9 to 0.1 is through 0.
+        assert!(half.phase().abs() < 1.0e-9 || (half.phase() - 1.0).abs() < 1.0e-9);
+        let two = red.with_gradients(vec![Gradient::default(); 2]);
+        assert_eq!(blend_colors(&red, &two, 0.5), None);
+
+        let dims = ImageDims {
+            xpix: 30,
+            ypix: 20,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        let a = (dims, red.clone(), quad.clone());
+        let b = (dims.zoom(4.0).resize(60, 40), two, pseudo.clone());
+        let (d, cspec, itype) = blend(&a, &b, 0.75);
+        assert_eq!((d.xpix, d.ypix), (30, 20));
+        assert!((d.width - 1.0607).abs() < 1.0e-4);
+        // What can't be blended switches over halfway.
+        assert_eq!((cspec.len(), itype), (512, pseudo));
+        assert_eq!(blend(&a, &b, 0.0), a);
+    }
+}
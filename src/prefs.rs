@@ -0,0 +1,187 @@
+/*!
+Application preferences, kept in a TOML file in the platform's usual place
+for configuration files, loaded when the application starts and saved when
+it quits.
+
+```toml
+# The size of the image the application starts with, and its antialiasing
+# (samples per pixel in each direction).
+width = 1200
+height = 800
+oversample = 2
+# A color map (or image parameter) file to start with, instead of plain white.
+palette = "/home/me/fractals/fire.toml"
+# Where file choosers start.
+last_dir = "/home/me/fractals"
+# How many threads iterate; defaults to the number of CPU cores.
+threads = 4
+
+# These are the same as the environment variables with the same names in
+# capitals and "JSET_" in front (see the README); the environment
+# variables win when both are set.
+ui_scale = 1.5
+screen_scale = 2.0
+controls = "toolbar"
+panes = "docked"
+color_pane = "compact,0.8"
+iter_pane = "compact"
+time_limit = 30.0
+color_profile = "display-p3"
+post_save = "oxipng {file}"
+coefficients = "polar"
+ffmpeg = "/opt/ffmpeg/bin/ffmpeg"
+
+# Where the windows were (the upper left corners of the main window, the
+# color map and iterator panes, and the minimap).
+[windows]
+main = [0, 0]
+color = [1500, 540]
+iter = [1500, 0]
+minimap = [0, 900]
+```
+
+Everything is optional. Settings the application doesn't recognize are
+ignored (and not written back out).
+*/
+
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+// Preferences files have no business being bigger than this.
+const PREFS_LIMIT: u64 = 1024 * 1024;
+const DIR_NAME: &str = "jset-desk";
+const FILE_NAME: &str = "prefs.toml";
+
+/**
+Where each of the windows was, so they can be put back there. Docked panes
+don't have positions of their own.
+*/
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct WindowPositions {
+    pub main: Option<[i32; 2]>,
+    pub color: Option<[i32; 2]>,
+    pub iter: Option<[i32; 2]>,
+    pub minimap: Option<[i32; 2]>,
+}
+
+/** The application's preferences; `None` means "the default". */
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Prefs {
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub oversample: Option<usize>,
+    pub palette: Option<PathBuf>,
+    pub last_dir: Option<PathBuf>,
+    pub threads: Option<usize>,
+
+    pub ui_scale: Option<f64>,
+    pub screen_scale: Option<f64>,
+    pub controls: Option<String>,
+    pub panes: Option<String>,
+    pub color_pane: Option<String>,
+    pub iter_pane: Option<String>,
+    pub time_limit: Option<f64>,
+    pub color_profile: Option<String>,
+    pub post_save: Option<String>,
+    pub coefficients: Option<String>,
+    pub ffmpeg: Option<String>,
+
+    // This is a table, so it has to come after all the plain values.
+    pub windows: WindowPositions,
+}
+
+impl Prefs {
+    /**
+    Read preferences from `path`. A file that isn't there (yet) just
+    means all the defaults.
+    */
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Prefs, String> {
+        let path = path.as_ref();
+        match std::fs::metadata(path) {
+            Ok(md) if md.len() > PREFS_LIMIT => {
+                return Err(format!("file is larger than {} bytes", PREFS_LIMIT));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Prefs::default());
+            }
+            _ => {}
+        }
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        toml::from_str(&text).map_err(|e| e.to_string())
+    }
+
+    /** Write the preferences to `path`, making its directory if need be. */
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("unable to create {}: {}", dir.display(), &e))?;
+        }
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, text).map_err(|e| e.to_string())
+    }
+}
+
+/**
+Return where the preferences file goes on this platform: in `%APPDATA%` on
+Windows, `~/Library/Application Support` on macOS, and `$XDG_CONFIG_HOME`
+(or `~/.config`) anywhere else. Returns `None` if the environment doesn't
+say where any of those are.
+*/
+pub fn default_path() -> Option<PathBuf> {
+    let var = |name: &str| {
+        std::env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    let base = if cfg!(target_os = "windows") {
+        var("APPDATA")?
+    } else if cfg!(target_os = "macos") {
+        var("HOME")?.join("Library").join("Application Support")
+    } else {
+        match var("XDG_CONFIG_HOME") {
+            Some(dir) => dir,
+            None => var("HOME")?.join(".config"),
+        }
+    };
+    Some(base.join(DIR_NAME).join(FILE_NAME))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let dir = std::env::temp_dir().join(format!("jset_prefs_test_{}", std::process::id()));
+        let path = dir.join("nested").join(FILE_NAME);
+        assert_eq!(Prefs::load(&path), Ok(Prefs::default()));
+
+        let prefs = Prefs {
+            width: Some(1200),
+            height: Some(800),
+            last_dir: Some(PathBuf::from("/tmp")),
+            ui_scale: Some(1.5),
+            panes: Some("docked".to_string()),
+            windows: WindowPositions {
+                main: Some([10, 20]),
+                iter: Some([-1200, 0]),
+                ..WindowPositions::default()
+            },
+            ..Prefs::default()
+        };
+        prefs.save(&path).unwrap();
+        assert_eq!(Prefs::load(&path), Ok(prefs));
+
+        // Unknown settings are fine; settings of the wrong type aren't.
+        std::fs::write(&path, "threads = 2\nsparkles = true\n").unwrap();
+        assert_eq!(Prefs::load(&path).unwrap().threads, Some(2));
+        std::fs::write(&path, "threads = \"lots\"\n").unwrap();
+        assert!(Prefs::load(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,143 @@
+/*!
+A library of named color maps, for starting points.
+
+Each one is just a `ColorSpec`, so once it's loaded it can be tweaked
+like any other.
+*/
+
+use crate::image::{ColorSpec, Gradient, Interpolation, RGB};
+
+/*
+A preset is described as a list of colors, each with the number of steps
+it takes to get to the next one. The last color's count is ignored; it's
+where the final gradient ends.
+*/
+struct Preset {
+    name: &'static str,
+    stops: &'static [([u8; 3], usize)],
+    space: Interpolation,
+    default: [u8; 3],
+}
+
+const PRESETS: &[Preset] = &[
+    Preset {
+        name: "Fire",
+        stops: &[
+            ([0, 0, 0], 64),
+            ([128, 0, 0], 64),
+            ([255, 0, 0], 64),
+            ([255, 160, 0], 64),
+            ([255, 255, 0], 64),
+            ([255, 255, 255], 0),
+        ],
+        space: Interpolation::Rgb,
+        default: [0, 0, 0],
+    },
+    Preset {
+        name: "Ocean",
+        stops: &[
+            ([0, 7, 40], 64),
+            ([0, 60, 120], 64),
+            ([0, 150, 200], 64),
+            ([120, 220, 230], 64),
+            ([255, 255, 255], 0),
+        ],
+        space: Interpolation::Lab,
+        default: [0, 0, 20],
+    },
+    // The default gradient of a certain well-known Windows fractal program.
+    Preset {
+        name: "Ultra Fractal classic",
+        stops: &[
+            ([0, 7, 100], 41),
+            ([32, 107, 203], 67),
+            ([237, 255, 255], 57),
+            ([255, 170, 0], 55),
+            ([0, 2, 0], 36),
+            ([0, 7, 100], 0),
+        ],
+        space: Interpolation::Rgb,
+        default: [0, 0, 0],
+    },
+    // Matplotlib's default colormap, which gets lighter at an even pace.
+    Preset {
+        name: "Viridis",
+        stops: &[
+            ([68, 1, 84], 64),
+            ([59, 82, 139], 64),
+            ([33, 145, 140], 64),
+            ([94, 201, 98], 64),
+            ([253, 231, 37], 0),
+        ],
+        space: Interpolation::Lab,
+        default: [0, 0, 0],
+    },
+    Preset {
+        name: "Rainbow",
+        stops: &[([255, 0, 0], 360), ([255, 0, 0], 0)],
+        space: Interpolation::HsvLong,
+        default: [0, 0, 0],
+    },
+    Preset {
+        name: "Grayscale",
+        stops: &[([0, 0, 0], 256), ([255, 255, 255], 0)],
+        space: Interpolation::Rgb,
+        default: [0, 0, 0],
+    },
+];
+
+fn rgb(c: [u8; 3]) -> RGB {
+    RGB::new(c[0] as f32, c[1] as f32, c[2] as f32)
+}
+
+impl Preset {
+    fn spec(&self) -> ColorSpec {
+        let gradients = self
+            .stops
+            .windows(2)
+            .map(|w| Gradient {
+                steps: w[0].1,
+                start: rgb(w[0].0),
+                end: rgb(w[1].0),
+                space: self.space,
+            })
+            .collect();
+        ColorSpec::new(gradients, rgb(self.default))
+    }
+}
+
+/** Return the names of the presets, in the order they should be offered. */
+pub fn names() -> Vec<&'static str> {
+    PRESETS.iter().map(|p| p.name).collect()
+}
+
+/** Return the preset with the given name (ignoring case), if there is one. */
+pub fn get(name: &str) -> Option<ColorSpec> {
+    PRESETS
+        .iter()
+        .find(|p| p.name.eq_ignore_ascii_case(name))
+        .map(|p| p.spec())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn all_presets() {
+        let names = names();
+        assert!(names.len() >= 4);
+        for (i, name) in names.iter().enumerate() {
+            assert!(!names[..i].contains(name), "{} twice", name);
+            let spec = get(name).unwrap();
+            assert!(spec.len() >= 256, "{} is only {} long", name, spec.len());
+            assert!(spec.gradients().iter().all(|g| g.steps > 0));
+        }
+        assert_eq!(get("viridis"), get("Viridis"));
+        assert_eq!(get("Plaid"), None);
+
+        let fire = get("Fire").unwrap().gradients();
+        assert_eq!(fire.first().unwrap().start, RGB::BLACK);
+        assert_eq!(fire.last().unwrap().end, RGB::WHITE);
+    }
+}
@@ -0,0 +1,496 @@
+/*!
+Iterating images on a background thread, so the UI doesn't freeze up
+during long renders.
+
+A `Render` is started with `Render::start()`, which immediately returns
+while the iteration proceeds on its own thread. The supplied callbacks get
+called (from that thread) as chunks of the image finish and when the whole
+thing is done; after the "done" callback fires, `Render::finish()` will
+hand over the resulting `IterMap`. Bands of the full-resolution image
+can also be collected with `Render::take_bands()` as they're finished.
+
+When iterating a whole new map, a couple of quick low-resolution passes
+are made first, so there's something to look at while the real thing is
+being worked on; these can be collected with `Render::take_preview()`.
+
+Renders can be given a time budget with `set_time_budget()`; whatever's
+left to do when it runs out gets done with a lower iteration limit.
+*/
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::image::*;
+
+// Preview passes are made at 1/8 and then 1/4 resolution before the full
+// one. Together these only add about 8% to the total work.
+const PREVIEW_FACTORS: [usize; 2] = [8, 4];
+// Images with fewer pixels than this are quick enough to not bother with
+// previews.
+const MIN_PREVIEW_PIXELS: usize = 128 * 128;
+// How long renders get, in milliseconds (0 meaning forever). See
+// `set_time_budget()`.
+static TIME_BUDGET_MS: AtomicU64 = AtomicU64::new(0);
+
+/**
+Set how long renders started after this get before the rest of the image
+is done with a lower iteration limit (which makes it approximate; see
+`IterMap::is_approximate()`). `None` means take as long as it takes.
+*/
+pub fn set_time_budget(budget: Option<Duration>) {
+    let ms = budget.map(|d| d.as_millis().max(1) as u64).unwrap_or(0);
+    TIME_BUDGET_MS.store(ms, Ordering::Relaxed);
+}
+
+/** Return how long renders get; see `set_time_budget()`. */
+pub fn time_budget() -> Option<Duration> {
+    match TIME_BUDGET_MS.load(Ordering::Relaxed) {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    }
+}
+
+/// What needs to be done to get an up-to-date `IterMap`.
+pub enum RenderTask {
+    /// Iterate a whole new map from scratch.
+    Iterate {
+        dims: ImageDims,
+        itertype: IterType,
+        limit: usize,
+        orbit: Option<Orbit>,
+    },
+    /// Extend an existing map to a higher iteration limit.
+    Reiterate { imap: IterMap, limit: usize },
+    /// Iterate the maps the layers of `cmap` need of their own (see
+    /// `IterMap::iterate_layers()`).
+    Layers { imap: IterMap, cmap: Box<ColorMap> },
+}
+
+/** How long one pass of a render took. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PassStats {
+    /// The number of points iterated (pixels times samples per pixel).
+    pub samples: usize,
+    pub time: Duration,
+}
+
+/**
+Time `f`, which iterates `samples` points, returning what it returns along
+with how long it took.
+*/
+pub fn timed<T, F: FnOnce() -> T>(samples: usize, f: F) -> (T, PassStats) {
+    let start = Instant::now();
+    let t = f();
+    (
+        t,
+        PassStats {
+            samples,
+            time: start.elapsed(),
+        },
+    )
+}
+
+/** How long the passes of a render took, and how many threads did them. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    /// The preview passes (if there were any) come first.
+    pub passes: Vec<PassStats>,
+    pub threads: usize,
+}
+
+impl RenderStats {
+    /** The total time of all the passes. */
+    pub fn total(&self) -> Duration {
+        self.passes.iter().map(|p| p.time).sum()
+    }
+
+    /** The total number of points iterated in all the passes. */
+    pub fn samples(&self) -> usize {
+        self.passes.iter().map(|p| p.samples).sum()
+    }
+
+    /** The number of points iterated per second, over all the passes. */
+    pub fn samples_per_second(&self) -> f64 {
+        let secs = self.total().as_secs_f64();
+        if secs > 0.0 {
+            (self.samples() as f64) / secs
+        } else {
+            0.0
+        }
+    }
+
+    /**
+    Describe the stats in a line, like
+    "1.25s (0.02s + 0.09s + 1.14s), 2.1M pts/s, 8 threads".
+    */
+    pub fn summary(&self) -> String {
+        let passes: Vec<String> = self
+            .passes
+            .iter()
+            .map(|p| format!("{:.2}s", p.time.as_secs_f64()))
+            .collect();
+        let mut text = format!("{:.2}s", self.total().as_secs_f64());
+        if passes.len() > 1 {
+            text.push_str(&format!(" ({})", passes.join(" + ")));
+        }
+        text.push_str(&format!(
+            ", {:.1}M pts/s, {} threads",
+            self.samples_per_second() / 1.0e6,
+            self.threads
+        ));
+        text
+    }
+}
+
+// The number of points iterated to make an image with dimensions `dims`.
+fn samples_in(dims: &ImageDims) -> usize {
+    let s = dims.samples();
+    dims.xpix * dims.ypix * s * s
+}
+
+/// A handle to an `IterMap` being generated in the background.
+pub struct Render {
+    id: usize,
+    watcher: Arc<IterWatcher>,
+    result: Arc<Mutex<Option<IterMap>>>,
+    preview: Arc<Mutex<Option<(usize, IterMap)>>>,
+    bands: Arc<Mutex<Vec<IterBand>>>,
+    stats: Arc<Mutex<RenderStats>>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl Render {
+    /**
+    Start performing the given `task` on a background thread.
+
+    `id` is just carried along so that the caller can tell different
+    renders apart; it gets passed to all the callbacks. `on_progress` is
+    called with `(id, chunks_done, total_chunks)` as the work proceeds
+    (starting over for each preview pass), `on_preview` is called with `id`
+    each time a new preview is ready, and `on_done` is called with `id`
+    when the task is finished (or has been cancelled).
+    */
+    pub fn start<P, V, D>(
+        id: usize,
+        task: RenderTask,
+        on_progress: P,
+        on_preview: V,
+        on_done: D,
+    ) -> Render
+    where
+        P: Fn(usize, usize, usize) + Send + Sync + 'static,
+        V: Fn(usize) + Send + 'static,
+        D: FnOnce(usize) + Send + 'static,
+    {
+        let result: Arc<Mutex<Option<IterMap>>> = Arc::new(Mutex::new(None));
+        let preview: Arc<Mutex<Option<(usize, IterMap)>>> = Arc::new(Mutex::new(None));
+        let bands: Arc<Mutex<Vec<IterBand>>> = Arc::new(Mutex::new(Vec::new()));
+        let stats = Arc::new(Mutex::new(RenderStats {
+            passes: Vec::new(),
+            threads: iteration_threads(),
+        }));
+
+        // Layers' counts aren't the image's, so their bands aren't either.
+        let full_dims = match &task {
+            RenderTask::Iterate { dims, .. } => Some(*dims),
+            RenderTask::Reiterate { imap, .. } => Some(imap.dims()),
+            RenderTask::Layers { .. } => None,
+        };
+        let mut watcher =
+            IterWatcher::with_progress(move |done, total| on_progress(id, done, total));
+        if let Some(budget) = time_budget() {
+            watcher.set_deadline(Instant::now() + budget);
+        }
+        watcher.set_band_callback({
+            let bands = bands.clone();
+            move |band| {
+                // Bands from the preview passes are of no interest.
+                if Some(band.dims) == full_dims {
+                    bands.lock().unwrap().push(band);
+                }
+            }
+        });
+        let watcher = Arc::new(watcher);
+
+        let handle = thread::spawn({
+            let watcher = watcher.clone();
+            let result = result.clone();
+            let preview = preview.clone();
+            let stats = stats.clone();
+            move || {
+                let note = |p: PassStats| stats.lock().unwrap().passes.push(p);
+                // Panics in the iteration itself are caught chunk by chunk
+                // (see `IterMap::failed_rows()`); this is a last resort so
+                // that `on_done` always gets called.
+                let work = AssertUnwindSafe(|| match task {
+                    RenderTask::Iterate {
+                        dims,
+                        itertype,
+                        limit,
+                        orbit,
+                    } => {
+                        if dims.xpix * dims.ypix >= MIN_PREVIEW_PIXELS {
+                            for factor in PREVIEW_FACTORS.iter() {
+                                let small_dims = dims.shrink(*factor);
+                                let (pimap, pass) = timed(samples_in(&small_dims), || {
+                                    IterMap::new_watched(
+                                        small_dims,
+                                        itertype.clone(),
+                                        limit,
+                                        orbit,
+                                        &watcher,
+                                    )
+                                });
+                                note(pass);
+                                if let Some(pimap) = pimap {
+                                    *preview.lock().unwrap() = Some((*factor, pimap));
+                                    on_preview(id);
+                                }
+                            }
+                        }
+                        let (imap, pass) = timed(samples_in(&dims), || {
+                            IterMap::new_watched(dims, itertype, limit, orbit, &watcher)
+                        });
+                        note(pass);
+                        imap
+                    }
+                    RenderTask::Reiterate { mut imap, limit } => {
+                        let (finished, pass) = timed(samples_in(&imap.dims()), || {
+                            imap.reiterate_watched(limit, &watcher)
+                        });
+                        note(pass);
+                        if finished {
+                            Some(imap)
+                        } else {
+                            None
+                        }
+                    }
+                    RenderTask::Layers { imap, cmap } => {
+                        let (finished, pass) = timed(samples_in(&imap.dims()), || {
+                            imap.iterate_layers(&cmap, &watcher)
+                        });
+                        note(pass);
+                        if finished {
+                            Some(imap)
+                        } else {
+                            None
+                        }
+                    }
+                });
+                let imap = match panic::catch_unwind(work) {
+                    Ok(imap) => imap,
+                    Err(payload) => {
+                        eprintln!("Render {} failed: {}", id, panic_message(payload.as_ref()));
+                        None
+                    }
+                };
+                *result.lock().unwrap() = imap;
+                on_done(id);
+            }
+        });
+
+        Render {
+            id,
+            watcher,
+            result,
+            preview,
+            bands,
+            stats,
+            handle,
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /**
+    Take the most recent low-resolution preview, if there's one that hasn't
+    already been taken. Returns the preview's reduction factor along with
+    the map itself; see `ImageDims::shrink()`.
+    */
+    pub fn take_preview(&self) -> Option<(usize, IterMap)> {
+        self.preview.lock().unwrap().take()
+    }
+
+    /**
+    Take all the bands of the full-resolution image that have been finished
+    since the last call. Progress callbacks are made _after_ the
+    corresponding band becomes available here.
+    */
+    pub fn take_bands(&self) -> Vec<IterBand> {
+        std::mem::take(&mut *self.bands.lock().unwrap())
+    }
+
+    /**
+    Return how long the passes finished so far have taken (all of them,
+    once the "done" callback has fired).
+    */
+    pub fn stats(&self) -> RenderStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /**
+    Ask the render to stop as soon as possible. The "done" callback will
+    still get called, but `finish()` will return `None`.
+    */
+    pub fn cancel(&self) {
+        self.watcher.cancel();
+    }
+
+    /**
+    Wait for the background thread to wrap up and return the resulting
+    `IterMap`, or `None` if the render was cancelled.
+
+    This will block if the render isn't done yet, so it should only be
+    called after the "done" callback has fired.
+    */
+    pub fn finish(self) -> Option<IterMap> {
+        if self.handle.join().is_err() {
+            eprintln!("Render {} thread panicked.", self.id);
+            return None;
+        }
+        let mut result = self.result.lock().unwrap();
+        result.take()
+    }
+}
+
+/**
+Estimates how much longer the pass a render is on will take, from how long
+its chunks have taken so far. Feed it the progress reports a `Render`
+makes; it notices when a new pass starts.
+*/
+#[derive(Clone, Debug)]
+pub struct ProgressClock {
+    pass_start: Instant,
+    last_report: Instant,
+    last_done: usize,
+    last_total: usize,
+}
+
+impl ProgressClock {
+    /** Start timing a render beginning at `now`. */
+    pub fn new(now: Instant) -> ProgressClock {
+        ProgressClock {
+            pass_start: now,
+            last_report: now,
+            last_done: 0,
+            last_total: 0,
+        }
+    }
+
+    /**
+    Note that `done` out of `total` chunks of the current pass were
+    finished at `now`, and return the estimated time left in the pass,
+    if any chunks have been finished to go on.
+    */
+    pub fn update(&mut self, done: usize, total: usize, now: Instant) -> Option<Duration> {
+        // Progress starts over with each pass, which started when the
+        // last one finished.
+        if done < self.last_done || total != self.last_total {
+            self.pass_start = self.last_report;
+        }
+        self.last_report = now;
+        self.last_done = done;
+        self.last_total = total;
+        if done == 0 || done > total {
+            return None;
+        }
+        let per_chunk = now.duration_since(self.pass_start).as_secs_f64() / (done as f64);
+        Some(Duration::from_secs_f64(per_chunk * ((total - done) as f64)))
+    }
+}
+
+/** Say how long `d` is briefly, like "40s", "3m20s", or "2h05m". */
+pub fn short_duration(d: Duration) -> String {
+    let s = d.as_secs_f64().round() as u64;
+    if s < 60 {
+        format!("{}s", s)
+    } else if s < 3600 {
+        format!("{}m{:02}s", s / 60, s % 60)
+    } else {
+        format!("{}h{:02}m", s / 3600, (s % 3600) / 60)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn time_left() {
+        let t0 = Instant::now();
+        let at = |ms: u64| t0 + Duration::from_millis(ms);
+        let ms = |d: Option<Duration>| d.map(|d| (d.as_secs_f64() * 1000.0).round() as u64);
+        let mut clock = ProgressClock::new(t0);
+        assert_eq!(clock.update(0, 10, at(0)), None);
+        assert_eq!(ms(clock.update(2, 10, at(400))), Some(1600));
+        assert_eq!(ms(clock.update(10, 10, at(2000))), Some(0));
+        // A new pass starts the clock over.
+        assert_eq!(ms(clock.update(1, 40, at(3000))), Some(39_000));
+        assert_eq!(ms(clock.update(4, 40, at(3300))), Some(11_700));
+
+        assert_eq!(short_duration(Duration::from_millis(400)), "0s");
+        assert_eq!(short_duration(Duration::from_secs(200)), "3m20s");
+        assert_eq!(short_duration(Duration::from_secs(7500)), "2h05m");
+    }
+
+    #[test]
+    fn render_stats() {
+        let dims = ImageDims {
+            xpix: 300,
+            ypix: 200,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 2,
+            adaptive: false,
+            check_period: true,
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+        let task = RenderTask::Iterate {
+            dims,
+            itertype: IterType::Mandlebrot,
+            limit: 100,
+            orbit: None,
+        };
+        let r = Render::start(
+            7,
+            task,
+            |_, _, _| {},
+            |_| {},
+            move |id| tx.send(id).unwrap(),
+        );
+        assert_eq!(rx.recv().unwrap(), 7);
+        let stats = r.stats();
+        assert!(r.finish().is_some());
+
+        // Two previews, then the real thing.
+        let samples: Vec<usize> = stats.passes.iter().map(|p| p.samples).collect();
+        assert_eq!(samples, vec![38 * 25, 75 * 50, 4 * 300 * 200]);
+        assert_eq!(stats.threads, iteration_threads());
+        assert!(stats.total() > Duration::ZERO);
+
+        let stats = RenderStats {
+            passes: vec![
+                PassStats {
+                    samples: 1_000_000,
+                    time: Duration::from_millis(250),
+                },
+                PassStats {
+                    samples: 4_000_000,
+                    time: Duration::from_millis(750),
+                },
+            ],
+            threads: 4,
+        };
+        assert_eq!(stats.samples_per_second(), 5.0e6);
+        assert_eq!(
+            stats.summary(),
+            "1.00s (0.25s + 0.75s), 5.0M pts/s, 4 threads"
+        );
+    }
+}
@@ -3,57 +3,706 @@ Struct/methods for saving images, and also saving/recalling image
 specifications.
 */
 
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufWriter, Read, Seek, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 //use lodepng::{ColorType, Encoder, FilterStrategy};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
 
+use crate::cx::Cx;
 use crate::image::*;
 
-// Maximum size/amount of a file to be read when attempting to decode a
-// .toml file.
-const READ_LIMIT: usize = 16 * 1024;
+// Maximum size of a parameter file. Big polynomials and long palettes can
+// make for surprisingly large files, but anything past this is surely
+// not legitimate.
+const READ_LIMIT: usize = 4 * 1024 * 1024;
+// Deepest nesting of brackets/braces allowed in parameter text. Real
+// parameter files never get more than a few levels deep, and the TOML
+// parser will blow the stack on hostile input nested a few thousand deep.
+const MAX_NESTING: usize = 32;
+// Largest number of distinct colors that will fit in a PNG palette.
+const MAX_PALETTE_SIZE: usize = 256;
+// How hard the GIF encoder works at picking each frame's colors, from 1
+// (hardest) to 30; 10 is its usual compromise.
+const GIF_SPEED: i32 = 10;
+// The most pixels a WebP image can have in either direction.
+const MAX_WEBP_SIZE: usize = 16383;
+// Whether saved PNGs get tagged as Display P3 instead of sRGB. See
+// `set_color_profile()`.
+static DISPLAY_P3: AtomicBool = AtomicBool::new(false);
+// Whether saved parameters get locked. See `set_param_locking()`.
+static LOCK_PARAMS: AtomicBool = AtomicBool::new(false);
+// The version of the rendering engine recorded in locked parameters.
+const ENGINE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/**
+The color space saved images are tagged with, so that color-managed
+programs (browsers, photo tools) show them the same way everywhere.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorProfile {
+    /// What colors mean on an ordinary monitor; the default.
+    Srgb,
+    /// The wider gamut of recent Macs and phones. The pixels get converted
+    /// to it, so they look the same, but programs that work in it (and
+    /// tools that edit the images) have more room to play with.
+    DisplayP3,
+}
+
+impl ColorProfile {
+    /** Return the profile called `name` ("srgb" or "display-p3"/"p3"). */
+    pub fn from_name(name: &str) -> Option<ColorProfile> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "srgb" => Some(ColorProfile::Srgb),
+            "display-p3" | "displayp3" | "p3" => Some(ColorProfile::DisplayP3),
+            _ => None,
+        }
+    }
+}
+
+/** How many bits each color channel of a saved PNG gets. */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BitDepth {
+    /// The usual, and what the image pane shows.
+    #[default]
+    Eight,
+    /// For smooth gradients without any banding, in files about twice
+    /// the size.
+    Sixteen,
+}
+
+/**
+How hard saved PNGs get squeezed. It only changes the size of the file
+(and how long writing it takes); the pixels are the same either way.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PngCompression {
+    /// Quickest to write, for huge images.
+    Fast,
+    /// In between.
+    Balanced,
+    /// Smallest; the default.
+    #[default]
+    Best,
+}
+
+impl PngCompression {
+    /** All the levels, from fastest to smallest. */
+    pub const ALL: [PngCompression; 3] = [
+        PngCompression::Fast,
+        PngCompression::Balanced,
+        PngCompression::Best,
+    ];
+
+    fn level(&self) -> png::Compression {
+        match self {
+            PngCompression::Fast => png::Compression::Fast,
+            PngCompression::Balanced => png::Compression::Default,
+            PngCompression::Best => png::Compression::Best,
+        }
+    }
+}
+
+/** Set the color space that PNGs saved after this get tagged with. */
+pub fn set_color_profile(profile: ColorProfile) {
+    DISPLAY_P3.store(profile == ColorProfile::DisplayP3, Ordering::Relaxed);
+}
+
+/** Return the color space saved PNGs get tagged with. */
+pub fn color_profile() -> ColorProfile {
+    if DISPLAY_P3.load(Ordering::Relaxed) {
+        ColorProfile::DisplayP3
+    } else {
+        ColorProfile::Srgb
+    }
+}
+
+// Convert to the ICC's s15Fixed16Number format.
+fn s15f16(x: f64) -> [u8; 4] {
+    ((x * 65536.0).round() as i32).to_be_bytes()
+}
+
+/*
+Build a minimal ICC (version 4) display profile for Display P3: its
+primaries (adapted to the D50 white the ICC works in), and the same tone
+curve as sRGB.
+*/
+fn display_p3_icc() -> Vec<u8> {
+    fn xyz(v: [f64; 3]) -> Vec<u8> {
+        let mut t = b"XYZ \0\0\0\0".to_vec();
+        v.iter().for_each(|x| t.extend(s15f16(*x)));
+        t
+    }
+    fn mluc(text: &str) -> Vec<u8> {
+        let utf16: Vec<u8> = text.encode_utf16().flat_map(|c| c.to_be_bytes()).collect();
+        let mut t = b"mluc\0\0\0\0".to_vec();
+        for n in [1u32, 12, 0x656e_5553, utf16.len() as u32, 28].iter() {
+            t.extend(n.to_be_bytes());
+        }
+        t.extend(utf16);
+        t
+    }
+    // The parametric form of the sRGB curve.
+    let mut trc = b"para\0\0\0\0\0\x03\0\0".to_vec();
+    for x in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045].iter() {
+        trc.extend(s15f16(*x));
+    }
+    // Bradford adaptation from D65 to D50.
+    let mut chad = b"sf32\0\0\0\0".to_vec();
+    for x in [
+        1.0478112, 0.0228866, -0.0501270, 0.0295424, 0.9904844, -0.0170491, -0.0092345, 0.0150436,
+        0.7521316,
+    ]
+    .iter()
+    {
+        chad.extend(s15f16(*x));
+    }
+
+    let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", mluc("Display P3")),
+        (b"cprt", mluc("No copyright, use freely")),
+        (b"wtpt", xyz([0.9642, 1.0, 0.8249])),
+        (b"chad", chad),
+        (b"rXYZ", xyz([0.515102, 0.241182, -0.001050])),
+        (b"gXYZ", xyz([0.291965, 0.692236, 0.041882])),
+        (b"bXYZ", xyz([0.157153, 0.066582, 0.784378])),
+        (b"rTRC", trc.clone()),
+        (b"gTRC", trc.clone()),
+        (b"bTRC", trc),
+    ];
+
+    let mut table: Vec<u8> = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data: Vec<u8> = Vec::new();
+    let data_start = 128 + 4 + 12 * tags.len();
+    for (sig, t) in tags.iter() {
+        table.extend(sig.iter());
+        table.extend(((data_start + data.len()) as u32).to_be_bytes());
+        table.extend((t.len() as u32).to_be_bytes());
+        data.extend(t);
+        // Every tag has to start on a four-byte boundary.
+        data.resize((data.len() + 3) & !3, 0);
+    }
+
+    let mut header = vec![0u8; 128];
+    let size = (128 + table.len() + data.len()) as u32;
+    header[0..4].copy_from_slice(&size.to_be_bytes());
+    header[8..12].copy_from_slice(&[4, 0x30, 0, 0]);
+    header[12..16].copy_from_slice(b"mntr");
+    header[16..20].copy_from_slice(b"RGB ");
+    header[20..24].copy_from_slice(b"XYZ ");
+    header[36..40].copy_from_slice(b"acsp");
+    for (i, x) in [0.9642, 1.0, 0.8249].iter().enumerate() {
+        header[(68 + 4 * i)..(72 + 4 * i)].copy_from_slice(&s15f16(*x));
+    }
+
+    header.extend(table);
+    header.extend(data);
+    header
+}
+
+// Linear sRGB to linear Display P3 (both have a D65 white).
+const SRGB_TO_P3: [[f64; 3]; 3] = [
+    [0.8224621, 0.1775380, 0.0],
+    [0.0331941, 0.9668058, 0.0],
+    [0.0170827, 0.0723974, 0.9105199],
+];
+
+// The sRGB tone curve (which Display P3 shares), from and to values from
+// 0 to 1.
+fn srgb_decode(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn srgb_encode(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// The Display P3 values of the sRGB color `rgb` (from 0 to 1).
+fn p3_from_srgb(rgb: [f64; 3]) -> [f64; 3] {
+    let lin = rgb.map(srgb_decode);
+    SRGB_TO_P3.map(|row| srgb_encode(row[0] * lin[0] + row[1] * lin[1] + row[2] * lin[2]))
+}
+
+// Convert RGB (or, if `channels` is 4, RGBA) `data`, with values from 0 to
+// `max`, from sRGB to Display P3, so it looks the same once it's tagged as
+// that. Opacities are left alone.
+fn to_p3<T>(data: &[T], channels: usize, max: f64) -> Vec<T>
+where
+    T: Copy + Into<f64> + TryFrom<u32>,
+{
+    let mut out = data.to_vec();
+    for px in out.chunks_exact_mut(channels) {
+        let rgb = p3_from_srgb([px[0], px[1], px[2]].map(|x| x.into() / max));
+        for (x, v) in px.iter_mut().zip(rgb) {
+            let n = (v * max).round().clamp(0.0, max) as u32;
+            if let Ok(n) = T::try_from(n) {
+                *x = n;
+            }
+        }
+    }
+    out
+}
+
+// Start encoding an `xpix` × `ypix` PNG to `w`, tagged with `profile`.
+fn new_encoder<W: Write>(
+    w: W,
+    xpix: u32,
+    ypix: u32,
+    profile: ColorProfile,
+) -> png::Encoder<'static, W> {
+    match profile {
+        ColorProfile::Srgb => {
+            let mut enc = png::Encoder::new(w, xpix, ypix);
+            enc.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+            // Older programs that don't know the sRGB chunk will still
+            // understand these.
+            let sf = png::ScaledFloat::from_scaled;
+            enc.set_source_gamma(sf(45455));
+            enc.set_source_chromaticities(png::SourceChromaticities {
+                white: (sf(31270), sf(32900)),
+                red: (sf(64000), sf(33000)),
+                green: (sf(30000), sf(60000)),
+                blue: (sf(15000), sf(6000)),
+            });
+            enc
+        }
+        ColorProfile::DisplayP3 => {
+            let mut info = png::Info::with_size(xpix, ypix);
+            info.icc_profile = Some(Cow::Owned(display_p3_icc()));
+            // This only fails on bad animation settings, and there aren't any.
+            png::Encoder::with_info(w, info).expect("still image rejected as animated")
+        }
+    }
+}
+
+/**
+Set whether parameters saved after this (in parameter files or embedded
+in images) are locked: stamped with the version of the program and a
+checksum, so that whoever loads them can tell whether they'll get exactly
+the same image.
+*/
+pub fn set_param_locking(on: bool) {
+    LOCK_PARAMS.store(on, Ordering::Relaxed);
+}
+
+/** Return whether saved parameters are being locked. */
+pub fn param_locking() -> bool {
+    LOCK_PARAMS.load(Ordering::Relaxed)
+}
+
+// A 64-bit FNV-1a hash; nothing fancy is needed to notice that some
+// parameters have changed.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |h, b| {
+        (h ^ (*b as u64)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/**
+The record that makes parameters "locked": the engine that rendered them,
+and a checksum of everything that goes into the image.
+*/
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ParamLock {
+    version: String,
+    checksum: String,
+}
+
+impl ParamLock {
+    fn new(dims: &ImageDims, cspec: &ColorSpec, iter: &IterType) -> ParamLock {
+        ParamLock {
+            version: ENGINE_VERSION.to_string(),
+            checksum: ParamLock::checksum(dims, cspec, iter),
+        }
+    }
+
+    fn checksum(dims: &ImageDims, cspec: &ColorSpec, iter: &IterType) -> String {
+        // Serialization is only impossible for non-finite floats, which
+        // can't be rendered anyway.
+        let mut value = serde_json::to_value((iter, dims, cspec)).unwrap_or_default();
+        rect_coefficients(&mut value);
+        format!("{:016x}", fnv1a(value.to_string().as_bytes()))
+    }
+
+    /** Compare the lock against the parameters it came with and this engine. */
+    pub fn check(&self, dims: &ImageDims, cspec: &ColorSpec, iter: &IterType) -> LockStatus {
+        if self.version != ENGINE_VERSION {
+            LockStatus::OtherVersion(self.version.clone())
+        } else if self.checksum != ParamLock::checksum(dims, cspec, iter) {
+            LockStatus::Modified
+        } else {
+            LockStatus::Identical
+        }
+    }
+}
+
+/*
+Replace any complex numbers written in polar form (see `cx`) with plain
+`[re, im]` pairs, so that the way they happen to be written doesn't
+change the checksum.
+*/
+fn rect_coefficients(value: &mut Value) {
+    match value {
+        Value::Object(map) if map.contains_key("theta_pi") => {
+            if let Ok(z) = serde_json::from_value::<Cx>(value.clone()) {
+                *value = serde_json::json!([z.re, z.im]);
+            }
+        }
+        Value::Object(map) => map.values_mut().for_each(rect_coefficients),
+        Value::Array(a) => a.iter_mut().for_each(rect_coefficients),
+        _ => {}
+    }
+}
+
+/** What re-rendering a set of loaded parameters will produce. */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LockStatus {
+    /// The parameters weren't locked, so there's no telling.
+    Unlocked,
+    /// Exactly the same image as when they were saved.
+    Identical,
+    /// The parameters have been changed since they were locked.
+    Modified,
+    /// The parameters were locked by a different version of the program
+    /// (the one given), which might render them a little differently.
+    OtherVersion(String),
+}
+
+impl LockStatus {
+    /** A description of the status, for people. */
+    pub fn describe(&self) -> String {
+        match self {
+            LockStatus::Unlocked => "These parameters aren't locked.".to_string(),
+            LockStatus::Identical => {
+                "These parameters are locked, and will render exactly as they did when saved."
+                    .to_string()
+            }
+            LockStatus::Modified => {
+                "These parameters have been changed since they were locked, so the image \
+                 won't be the same as when they were saved."
+                    .to_string()
+            }
+            LockStatus::OtherVersion(v) => format!(
+                "These parameters were locked by version {} (this is {}), so the image \
+                 may not be exactly the same.",
+                v, ENGINE_VERSION
+            ),
+        }
+    }
+}
+
+/**
+The version of the format parameters are saved in. Files saved before the
+version was recorded count as version 0; older files are brought up to
+date (see `migrate()`) as they're loaded, and newer ones are refused.
+*/
+pub const PARAMS_VERSION: u64 = 1;
+
+// The changes from each version of the parameter format to the next: the
+// one at index `n` turns version `n` into version `n + 1`.
+const MIGRATIONS: &[fn(&mut Value)] = &[recount_gradients];
+
+// Version 0 to 1: a color spec's `length` is stored alongside its
+// gradients rather than worked out from them, and in unversioned files
+// (many of them edited by hand) the two could disagree, leaving colors
+// missing or the map indexed past its end. The gradients win. (They do
+// for specs of every version now, since `ColorSpec` works its length out
+// when it's read; this is kept so the versions stay in step.)
+fn recount_gradients(params: &mut Value) {
+    fn recount(spec: &mut Value) {
+        let Some(spec) = spec.as_object_mut() else {
+            return;
+        };
+        if let Some(Value::Array(grads)) = spec.get("gradients") {
+            let length: u64 = grads
+                .iter()
+                .filter_map(|g| g.get("steps").and_then(Value::as_u64))
+                .sum();
+            spec.insert("length".to_string(), Value::from(length));
+        }
+        if let Some(Value::Array(layers)) = spec.get_mut("layers") {
+            for layer in layers.iter_mut() {
+                if let Some(spec) = layer.get_mut("spec") {
+                    recount(spec);
+                }
+            }
+        }
+    }
+    if let Some(spec) = params.get_mut("color_spec") {
+        recount(spec);
+    }
+}
+
+/*
+Bring parsed parameters (full ones, or just a color map) of whatever
+version up to the current one, or fail if they're from a newer version of
+the program than this.
+*/
+fn migrate(params: &mut Value) -> Result<(), String> {
+    let version = match params.get("version") {
+        None => 0,
+        Some(v) => match v.as_u64() {
+            Some(n) => n,
+            None => {
+                return Err(format!("{} isn't a parameter format version.", v));
+            }
+        },
+    };
+    if version > PARAMS_VERSION {
+        return Err(format!(
+            "These parameters are in format version {}, but this version of \
+             jset_desk only understands up to {}; it needs updating.",
+            version, PARAMS_VERSION
+        ));
+    }
+    for step in MIGRATIONS[(version as usize)..].iter() {
+        step(params);
+    }
+    if let Some(params) = params.as_object_mut() {
+        params.insert("version".to_string(), Value::from(PARAMS_VERSION));
+    }
+    Ok(())
+}
+
+// Migrate parsed parameters and turn them into whatever they're parameters
+// for.
+fn from_migrated<T: serde::de::DeserializeOwned>(mut params: Value) -> Result<T, String> {
+    migrate(&mut params)?;
+    serde_json::from_value(params).map_err(|e| e.to_string())
+}
 
 /// A container for all the information required to recreate an image.
 #[derive(Deserialize, Serialize)]
 pub struct ImageParameters {
+    #[serde(default)]
+    version: u64,
     iterator: IterType,
     dimensions: ImageDims,
     color_spec: ColorSpec,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lock: Option<ParamLock>,
 }
 
 impl ImageParameters {
-    pub fn toml(dims: &ImageDims, cspec: &ColorSpec, iter: &IterType) -> Result<String, String> {
-        let ips = ImageParameters {
+    fn new(dims: &ImageDims, cspec: &ColorSpec, iter: &IterType) -> ImageParameters {
+        ImageParameters::with_lock(dims, cspec, iter, param_locking())
+    }
+
+    fn with_lock(
+        dims: &ImageDims,
+        cspec: &ColorSpec,
+        iter: &IterType,
+        lock: bool,
+    ) -> ImageParameters {
+        ImageParameters {
+            version: PARAMS_VERSION,
             dimensions: *dims,
             color_spec: cspec.clone(),
             iterator: iter.clone(),
-        };
+            lock: if lock {
+                Some(ParamLock::new(dims, cspec, iter))
+            } else {
+                None
+            },
+        }
+    }
+
+    /** Return what re-rendering these parameters will produce. */
+    pub fn lock_status(&self) -> LockStatus {
+        match &self.lock {
+            Some(lock) => lock.check(&self.dimensions, &self.color_spec, &self.iterator),
+            None => LockStatus::Unlocked,
+        }
+    }
+
+    pub fn toml(dims: &ImageDims, cspec: &ColorSpec, iter: &IterType) -> Result<String, String> {
+        let ips = ImageParameters::new(dims, cspec, iter);
 
         match toml::to_string(&ips) {
             Ok(s) => Ok(s),
             Err(e) => Err(format!("Error serializing data: {}", &e)),
         }
     }
+
+    pub fn json(dims: &ImageDims, cspec: &ColorSpec, iter: &IterType) -> Result<String, String> {
+        let ips = ImageParameters::new(dims, cspec, iter);
+
+        match serde_json::to_string_pretty(&ips) {
+            Ok(s) => Ok(s),
+            Err(e) => Err(format!("Error serializing data: {}", &e)),
+        }
+    }
+}
+
+/**
+A container for just a color map, so it can be saved on its own and then
+applied to any image. It has the same key as a full `ImageParameters`, so
+the color map can be pulled out of either kind of file.
+*/
+#[derive(Deserialize, Serialize)]
+struct ColorParameters {
+    #[serde(default)]
+    version: u64,
+    color_spec: ColorSpec,
+}
+
+/**
+One of the sets of image parameters in a `Session`. Apart from the name,
+it's the same as what's in a parameter file.
+*/
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct NamedParams {
+    pub name: String,
+    pub iterator: IterType,
+    pub dimensions: ImageDims,
+    pub color_spec: ColorSpec,
+}
+
+/**
+A whole exploration, so it can be picked back up later: several named
+sets of image parameters (in the order they were arrived at), which of
+them was being shown, and the views that had been stepped through.
+
+```toml
+current = "step 2"
+view = 1
+
+[[views]]
+xpix = 1200
+# ...the rest of an `ImageDims`
+
+[[params]]
+name = "step 1"
+# ...then `iterator`, `dimensions`, and `color_spec`, as in a parameter file
+```
+*/
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Session {
+    /// The name of the parameter set being shown.
+    pub current: String,
+    /// Where the current view is in `views`.
+    pub view: usize,
+    pub views: Vec<ImageDims>,
+    pub params: Vec<NamedParams>,
+}
+
+impl Session {
+    /** Where the parameter set named by `current` is in `params`. */
+    pub fn current_index(&self) -> Option<usize> {
+        self.params.iter().position(|p| p.name == self.current)
+    }
+
+    // Make sure there's something to show.
+    fn check(&self) -> Result<(), String> {
+        if self.current_index().is_none() {
+            return Err(format!("no parameter set named {:?}", &self.current));
+        }
+        if self.view >= self.views.len() {
+            return Err(format!(
+                "view {} doesn't exist (there are {})",
+                self.view,
+                self.views.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The text formats in which image parameters can be saved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParamFormat {
+    Toml,
+    Json,
+}
+
+impl ParamFormat {
+    /**
+    Determine the format of a parameter file from its extension. Anything
+    not ending in `.json` is considered TOML.
+    */
+    pub fn of<P: AsRef<Path>>(fname: P) -> ParamFormat {
+        match fname.as_ref().extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => ParamFormat::Json,
+            _ => ParamFormat::Toml,
+        }
+    }
+
+    /// Whether `fname` has an extension recognized as a parameter file.
+    pub fn is_param_file<P: AsRef<Path>>(fname: P) -> bool {
+        match fname.as_ref().extension() {
+            Some(ext) => ext.eq_ignore_ascii_case("json") || ext.eq_ignore_ascii_case("toml"),
+            None => false,
+        }
+    }
 }
 
 enum LoadResult {
-    Success(ImageParameters),
+    Success(Box<ImageParameters>),
     GiveUp(String),
     TryOtherType,
 }
 
-/// Save the given image information.
+/**
+Save the given image information. The file will be written as JSON if
+`fname` ends in `.json`, and TOML otherwise.
+*/
 pub fn save<P: AsRef<Path>>(
     dims: &ImageDims,
     cspec: &ColorSpec,
     iter: &IterType,
     fname: &P,
 ) -> Result<(), String> {
-    let toml_string = ImageParameters::toml(dims, cspec, iter)?;
+    let text = match ParamFormat::of(fname) {
+        ParamFormat::Toml => ImageParameters::toml(dims, cspec, iter)?,
+        ParamFormat::Json => ImageParameters::json(dims, cspec, iter)?,
+    };
+    write_text(fname, &text)
+}
+
+/**
+Save just a color map, so it can be loaded into another image with
+`load_colors()`. As with `save()`, the file will be written as JSON if
+`fname` ends in `.json`, and TOML otherwise.
+*/
+pub fn save_colors<P: AsRef<Path>>(cspec: &ColorSpec, fname: &P) -> Result<(), String> {
+    let cps = ColorParameters {
+        version: PARAMS_VERSION,
+        color_spec: cspec.clone(),
+    };
+    let res = match ParamFormat::of(fname) {
+        ParamFormat::Toml => toml::to_string(&cps).map_err(|e| e.to_string()),
+        ParamFormat::Json => serde_json::to_string_pretty(&cps).map_err(|e| e.to_string()),
+    };
+    match res {
+        Ok(text) => write_text(fname, &text),
+        Err(e) => Err(format!("Error serializing data: {}", &e)),
+    }
+}
+
+/** Save a whole `Session`, always as TOML. */
+pub fn save_session<P: AsRef<Path>>(session: &Session, fname: &P) -> Result<(), String> {
+    match toml::to_string(session) {
+        Ok(text) => write_text(fname, &text),
+        Err(e) => Err(format!("Error serializing session: {}", &e)),
+    }
+}
 
+fn write_text<P: AsRef<Path>>(fname: &P, text: &str) -> Result<(), String> {
     let mut f = match File::create(fname) {
         Ok(f) => f,
         Err(e) => {
@@ -62,7 +711,7 @@ pub fn save<P: AsRef<Path>>(
         }
     };
 
-    if let Err(e) = f.write_all(toml_string.as_bytes()) {
+    if let Err(e) = f.write_all(text.as_bytes()) {
         let estr = format!("Error writing to output file: {}", &e);
         return Err(estr);
     }
@@ -75,6 +724,61 @@ pub fn save<P: AsRef<Path>>(
     Ok(())
 }
 
+/*
+The way an image's pixels get written to a PNG file.
+
+Lots of color maps are either all grey or have few enough distinct colors
+to fit in a palette; in those cases the file can be _much_ smaller than
+one with three full bytes per pixel.
+*/
+enum PngLayout {
+    // One byte per pixel; all pixels have R == G == B.
+    Grey(Vec<u8>),
+    // One byte per pixel, indexing into the `palette` of RGB triples.
+    Indexed { palette: Vec<u8>, indices: Vec<u8> },
+    // Plain old three bytes per pixel.
+    Rgb,
+    // Four bytes per pixel, the last being opacity; the only way to
+    // write an image with any transparency in it.
+    Rgba,
+}
+
+impl PngLayout {
+    // Examine the 8-bit RGB (or, if `channels` is 4, RGBA) `data` and
+    // choose the most compact layout that will reproduce it exactly.
+    fn choose(data: &[u8], channels: usize) -> PngLayout {
+        if channels == 4 {
+            return PngLayout::Rgba;
+        }
+        if data.chunks_exact(3).all(|p| p[0] == p[1] && p[1] == p[2]) {
+            let grey: Vec<u8> = data.chunks_exact(3).map(|p| p[0]).collect();
+            return PngLayout::Grey(grey);
+        }
+
+        let mut lookup: HashMap<[u8; 3], u8> = HashMap::new();
+        let mut palette: Vec<u8> = Vec::new();
+        let mut indices: Vec<u8> = Vec::with_capacity(data.len() / 3);
+        for p in data.chunks_exact(3) {
+            let key = [p[0], p[1], p[2]];
+            let idx = match lookup.get(&key) {
+                Some(n) => *n,
+                None => {
+                    if lookup.len() == MAX_PALETTE_SIZE {
+                        return PngLayout::Rgb;
+                    }
+                    let n = lookup.len() as u8;
+                    lookup.insert(key, n);
+                    palette.extend_from_slice(&key);
+                    n
+                }
+            };
+            indices.push(idx);
+        }
+
+        PngLayout::Indexed { palette, indices }
+    }
+}
+
 /// Save the given _image_. Uses maximum zlib compression.
 /*
 pub fn save_as_png<P: AsRef<Path>>(
@@ -105,171 +809,1909 @@ pub fn save_as_png<P: AsRef<Path>>(
                             fname.as_ref().display(), &e);
         Err(estr)
     } else {
-        Ok(())
+        Ok(())
+    }
+}
+*/
+
+/**
+Save the 8-bit RGB (or RGBA, if `data` has four bytes per pixel) image
+`data` as a PNG, with the image parameters `params` embedded as metadata
+(unless that's `None`), squeezed as hard as `compression` says.
+
+If the image is entirely grey, or has no more than 256 distinct colors,
+it will automatically be written as a greyscale or indexed-color PNG,
+respectively, which can be dramatically smaller.
+*/
+pub fn save_with_metadata<P: AsRef<Path>>(
+    fname: P,
+    xpix: usize,
+    ypix: usize,
+    data: &[u8],
+    params: Option<(&ImageDims, &ColorSpec, &IterType)>,
+    compression: PngCompression,
+) -> Result<(), String> {
+    let metadata = params_toml(params)?;
+    write_png(
+        fname.as_ref(),
+        (xpix, ypix),
+        Pixels::Eight(data),
+        metadata,
+        compression,
+        color_profile(),
+    )
+}
+
+/**
+Save the 8-bit RGB (or RGBA) image `data` as a PNG _without_ any embedded image
+parameters, for when you want to share an image without revealing where
+in the plane it came from.
+*/
+pub fn save_without_metadata<P: AsRef<Path>>(
+    fname: P,
+    xpix: usize,
+    ypix: usize,
+    data: &[u8],
+) -> Result<(), String> {
+    save_with_metadata(fname, xpix, ypix, data, None, PngCompression::Best)
+}
+
+/**
+Like `save_with_metadata()`, but for 16-bit RGB (or RGBA) `data`, like
+`FImage32::to_rgb16()` returns. These are always written as full color.
+*/
+pub fn save16_with_metadata<P: AsRef<Path>>(
+    fname: P,
+    xpix: usize,
+    ypix: usize,
+    data: &[u16],
+    params: Option<(&ImageDims, &ColorSpec, &IterType)>,
+    compression: PngCompression,
+) -> Result<(), String> {
+    let metadata = params_toml(params)?;
+    write_png(
+        fname.as_ref(),
+        (xpix, ypix),
+        Pixels::Sixteen(data),
+        metadata,
+        compression,
+        color_profile(),
+    )
+}
+
+/** Like `save_without_metadata()`, but for 16-bit RGB (or RGBA) `data`. */
+pub fn save16_without_metadata<P: AsRef<Path>>(
+    fname: P,
+    xpix: usize,
+    ypix: usize,
+    data: &[u16],
+) -> Result<(), String> {
+    save16_with_metadata(fname, xpix, ypix, data, None, PngCompression::Best)
+}
+
+// Image data to be written, with either size of channel.
+enum Pixels<'a> {
+    Eight(&'a [u8]),
+    Sixteen(&'a [u16]),
+}
+
+// PNGs store 16-bit values most significant byte first.
+fn be_bytes(data: &[u16]) -> Vec<u8> {
+    data.iter().flat_map(|x| x.to_be_bytes()).collect()
+}
+
+// The metadata to embed for `params`, if there are any.
+fn params_toml(
+    params: Option<(&ImageDims, &ColorSpec, &IterType)>,
+) -> Result<Option<String>, String> {
+    match params {
+        Some((dims, cspec, iter)) => ImageParameters::toml(dims, cspec, iter).map(Some),
+        None => Ok(None),
+    }
+}
+
+// Does the actual work of `save_with_metadata()` and
+// `save16_with_metadata()`, converting the sRGB `pixels` to `profile`.
+fn write_png(
+    fname: &Path,
+    (xpix, ypix): (usize, usize),
+    pixels: Pixels,
+    metadata: Option<String>,
+    compression: PngCompression,
+    profile: ColorProfile,
+) -> Result<(), String> {
+    let f = match File::create(fname) {
+        Ok(f) => f,
+        Err(e) => {
+            let estr = format!("Error opening {} for writing: {}", fname.display(), &e);
+            return Err(estr);
+        }
+    };
+    let mut w = BufWriter::new(f);
+
+    let n_pix = xpix * ypix;
+    let channels = |len: usize| if len == 4 * n_pix { 4 } else { 3 };
+    let (converted8, converted16): (Vec<u8>, Vec<u16>);
+    let pixels = match (profile, pixels) {
+        (ColorProfile::Srgb, pixels) => pixels,
+        (ColorProfile::DisplayP3, Pixels::Eight(data)) => {
+            converted8 = to_p3(data, channels(data.len()), 255.0);
+            Pixels::Eight(&converted8)
+        }
+        (ColorProfile::DisplayP3, Pixels::Sixteen(data)) => {
+            converted16 = to_p3(data, channels(data.len()), 65535.0);
+            Pixels::Sixteen(&converted16)
+        }
+    };
+    let (layout, depth) = match pixels {
+        Pixels::Eight(data) => (
+            PngLayout::choose(data, channels(data.len())),
+            png::BitDepth::Eight,
+        ),
+        Pixels::Sixteen(data) if data.len() == 4 * n_pix => {
+            (PngLayout::Rgba, png::BitDepth::Sixteen)
+        }
+        Pixels::Sixteen(_) => (PngLayout::Rgb, png::BitDepth::Sixteen),
+    };
+
+    let mut enc = new_encoder(&mut w, xpix as u32, ypix as u32, profile);
+    match &layout {
+        PngLayout::Grey(_) => enc.set_color(png::ColorType::Grayscale),
+        PngLayout::Indexed { palette, .. } => {
+            enc.set_color(png::ColorType::Indexed);
+            enc.set_palette(palette.clone());
+        }
+        PngLayout::Rgb => enc.set_color(png::ColorType::Rgb),
+        PngLayout::Rgba => enc.set_color(png::ColorType::Rgba),
+    }
+    enc.set_depth(depth);
+    enc.set_filter(png::FilterType::Paeth);
+    enc.set_compression(compression.level());
+    if let Some(metadata) = metadata {
+        if let Err(e) = enc.add_itxt_chunk("jset_desk parameters".to_string(), metadata) {
+            let estr = format!("Error writing metadata: {}", &e);
+            return Err(estr);
+        }
+    }
+    let mut writer = match enc.write_header() {
+        Err(e) => {
+            let estr = format!("Error writing PNG header: {}", &e);
+            return Err(estr);
+        }
+        Ok(x) => x,
+    };
+    let wide: Vec<u8>;
+    let image_data = match (&layout, pixels) {
+        (_, Pixels::Sixteen(data)) => {
+            wide = be_bytes(data);
+            wide.as_slice()
+        }
+        (PngLayout::Grey(grey), _) => grey.as_slice(),
+        (PngLayout::Indexed { indices, .. }, _) => indices.as_slice(),
+        (_, Pixels::Eight(data)) => data,
+    };
+    if let Err(e) = writer.write_image_data(image_data) {
+        let estr = format!("Error writing image data: {}", &e);
+        return Err(estr);
+    }
+
+    Ok(())
+}
+
+/**
+The kinds of file a still image can be saved as. Only PNGs carry the
+image parameters (or 16-bit color); the others are for sharing big,
+noisy images that make enormous PNGs.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Lossless, with the parameters embedded; the default.
+    #[default]
+    Png,
+    /// Lossy, at a `quality` from 1 to 100. Much smaller, but with no
+    /// transparency, and no bigger than 65,535 pixels either way.
+    Jpeg { quality: u8 },
+    /// Lossy at a `quality` from 1 to 100, or lossless if that's `None`.
+    /// Either way it keeps transparency, but can't be bigger than 16,383
+    /// pixels either way.
+    Webp { quality: Option<u8> },
+}
+
+impl ImageFormat {
+    /** The quality lossy formats get unless told otherwise. */
+    pub const DEFAULT_QUALITY: u8 = 90;
+
+    /**
+    Return the format named by `fname`'s extension (`.png`, `.jpg` or
+    `.jpeg`, or `.webp`), with the default quality if it's lossy.
+    */
+    pub fn of<P: AsRef<Path>>(fname: P) -> Option<ImageFormat> {
+        let ext = fname
+            .as_ref()
+            .extension()?
+            .to_string_lossy()
+            .to_ascii_lowercase();
+        let quality = ImageFormat::DEFAULT_QUALITY;
+        match ext.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg { quality }),
+            "webp" => Some(ImageFormat::Webp {
+                quality: Some(quality),
+            }),
+            _ => None,
+        }
+    }
+
+    /** Return the extension (with the dot) files of this format get. */
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => ".png",
+            ImageFormat::Jpeg { .. } => ".jpg",
+            ImageFormat::Webp { .. } => ".webp",
+        }
+    }
+}
+
+/**
+Save the 8-bit RGB (or RGBA) image `data` in the given `format`. PNGs are
+saved with `save_without_metadata()`; JPEGs lose any transparency.
+*/
+pub fn save_as<P: AsRef<Path>>(
+    fname: P,
+    xpix: usize,
+    ypix: usize,
+    data: &[u8],
+    format: ImageFormat,
+) -> Result<(), String> {
+    let fname = fname.as_ref();
+    let alpha = data.len() == 4 * xpix * ypix;
+    let too_big = |most: usize| {
+        format!(
+            "{} x {} is too big for {} (it can only go up to {} pixels each way).",
+            xpix,
+            ypix,
+            format.extension(),
+            most
+        )
+    };
+    let encoded: Vec<u8> = match format {
+        ImageFormat::Png => return save_without_metadata(fname, xpix, ypix, data),
+        ImageFormat::Jpeg { quality } => {
+            if xpix > u16::MAX as usize || ypix > u16::MAX as usize {
+                return Err(too_big(u16::MAX as usize));
+            }
+            let mut bytes: Vec<u8> = Vec::new();
+            let mut enc = jpeg_encoder::Encoder::new(&mut bytes, quality.clamp(1, 100));
+            let (channels, color) = if alpha {
+                (4, jpeg_encoder::ColorType::Rgba)
+            } else {
+                (3, jpeg_encoder::ColorType::Rgb)
+            };
+            let converted: Vec<u8>;
+            let data = match color_profile() {
+                ColorProfile::Srgb => data,
+                ColorProfile::DisplayP3 => {
+                    if let Err(e) = enc.add_icc_profile(&display_p3_icc()) {
+                        return Err(format!("Error writing color profile: {}", &e));
+                    }
+                    converted = to_p3(data, channels, 255.0);
+                    &converted
+                }
+            };
+            if let Err(e) = enc.encode(data, xpix as u16, ypix as u16, color) {
+                return Err(format!("Error encoding JPEG: {}", &e));
+            }
+            bytes
+        }
+        ImageFormat::Webp { quality } => {
+            if xpix > MAX_WEBP_SIZE || ypix > MAX_WEBP_SIZE {
+                return Err(too_big(MAX_WEBP_SIZE));
+            }
+            let enc = if alpha {
+                webp::Encoder::from_rgba(data, xpix as u32, ypix as u32)
+            } else {
+                webp::Encoder::from_rgb(data, xpix as u32, ypix as u32)
+            };
+            match quality {
+                Some(q) => enc.encode(q.clamp(1, 100) as f32).to_vec(),
+                None => enc.encode_lossless().to_vec(),
+            }
+        }
+    };
+
+    match std::fs::write(fname, encoded) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Error writing {}: {}", fname.display(), &e)),
+    }
+}
+
+/**
+Return the name of the file the iteration counts of the image saved as
+`fname` go in (see `save_counts()`): the same name with `.counts` on the
+end.
+*/
+pub fn counts_path<P: AsRef<Path>>(fname: P) -> PathBuf {
+    let mut path = fname.as_ref().as_os_str().to_owned();
+    path.push(".counts");
+    PathBuf::from(path)
+}
+
+/**
+Save the iteration counts `imap` next to the image saved as `fname` (in
+the file `counts_path()` names), so loading the image again can skip
+iterating it.
+*/
+pub fn save_counts<P: AsRef<Path>>(fname: P, imap: &IterMap) -> Result<(), String> {
+    let path = counts_path(fname);
+    let f = match File::create(&path) {
+        Ok(f) => f,
+        Err(e) => {
+            let estr = format!("Error opening {} for writing: {}", path.display(), &e);
+            return Err(estr);
+        }
+    };
+    let mut w = BufWriter::new(f);
+    imap.write_counts(&mut w)?;
+    w.flush()
+        .map_err(|e| format!("Error writing {}: {}", path.display(), &e))
+}
+
+/**
+Load the iteration counts saved next to the image `fname` by
+`save_counts()`, if there are any. It's an error if they aren't of the
+image `dims`, `itertype` and `orbit` describe (which is what loading the
+image itself says they should be).
+*/
+pub fn load_counts<P: AsRef<Path>>(
+    fname: P,
+    dims: ImageDims,
+    itertype: IterType,
+    orbit: Option<Orbit>,
+) -> Result<Option<IterMap>, String> {
+    let path = counts_path(fname);
+    let f = match File::open(&path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Error opening {}: {}", path.display(), &e)),
+    };
+    match IterMap::read_counts(std::io::BufReader::new(f), dims, itertype, orbit) {
+        Ok(imap) => Ok(Some(imap)),
+        Err(e) => Err(format!("{}: {}", path.display(), &e)),
+    }
+}
+
+/**
+A PNG file being written a few rows at a time, for images too big to hold
+in memory all at once.
+
+Unlike `save_with_metadata()`, this can't look at the whole image first
+to decide whether it would be smaller as greyscale or indexed color, so
+it's always written as RGB (or RGBA, if asked for). It also uses the default compression level,
+because the best level is painfully slow on images this size.
+*/
+pub struct PngStream {
+    writer: png::StreamWriter<'static, BufWriter<File>>,
+    row_len: usize,
+    rows_left: usize,
+    channels: usize,
+    profile: ColorProfile,
+}
+
+impl PngStream {
+    /**
+    Start writing an `xpix` × `ypix` PNG to `fname`, with the given image
+    parameters embedded (if any). With `alpha`, the rows written must be
+    RGBA instead of RGB; with a `depth` of 16 bits, they have to be written
+    with `write_rows16()`.
+    */
+    pub fn create<P: AsRef<Path>>(
+        fname: P,
+        xpix: usize,
+        ypix: usize,
+        params: Option<(&ImageDims, &ColorSpec, &IterType)>,
+        alpha: bool,
+        depth: BitDepth,
+    ) -> Result<PngStream, String> {
+        let fname = fname.as_ref();
+        let f = match File::create(fname) {
+            Ok(f) => f,
+            Err(e) => {
+                let estr = format!("Error opening {} for writing: {}", fname.display(), &e);
+                return Err(estr);
+            }
+        };
+
+        let profile = color_profile();
+        let mut enc = new_encoder(BufWriter::new(f), xpix as u32, ypix as u32, profile);
+        let (color, channels) = if alpha {
+            (png::ColorType::Rgba, 4)
+        } else {
+            (png::ColorType::Rgb, 3)
+        };
+        let (depth, width) = match depth {
+            BitDepth::Eight => (png::BitDepth::Eight, 1),
+            BitDepth::Sixteen => (png::BitDepth::Sixteen, 2),
+        };
+        enc.set_color(color);
+        enc.set_depth(depth);
+        enc.set_filter(png::FilterType::Paeth);
+        enc.set_compression(png::Compression::Default);
+        if let Some((dims, cspec, iter)) = params {
+            let metadata = ImageParameters::toml(dims, cspec, iter)?;
+            if let Err(e) = enc.add_itxt_chunk("jset_desk parameters".to_string(), metadata) {
+                let estr = format!("Error writing metadata: {}", &e);
+                return Err(estr);
+            }
+        }
+        let writer = match enc.write_header() {
+            Err(e) => {
+                let estr = format!("Error writing PNG header: {}", &e);
+                return Err(estr);
+            }
+            Ok(x) => x,
+        };
+        let writer = match writer.into_stream_writer() {
+            Err(e) => {
+                let estr = format!("Error writing image data: {}", &e);
+                return Err(estr);
+            }
+            Ok(x) => x,
+        };
+
+        Ok(PngStream {
+            writer,
+            row_len: width * channels * xpix,
+            rows_left: ypix,
+            channels,
+            profile,
+        })
+    }
+
+    /** Write the next few rows of 8-bit RGB (or RGBA) image data. */
+    pub fn write_rows(&mut self, data: &[u8]) -> Result<(), String> {
+        match self.profile {
+            ColorProfile::Srgb => self.write_bytes(data),
+            ColorProfile::DisplayP3 => self.write_bytes(&to_p3(data, self.channels, 255.0)),
+        }
+    }
+
+    /** Write the next few rows of 16-bit RGB (or RGBA) image data. */
+    pub fn write_rows16(&mut self, data: &[u16]) -> Result<(), String> {
+        match self.profile {
+            ColorProfile::Srgb => self.write_bytes(&be_bytes(data)),
+            ColorProfile::DisplayP3 => {
+                self.write_bytes(&be_bytes(&to_p3(data, self.channels, 65535.0)))
+            }
+        }
+    }
+
+    // Write the next few rows, already in the form the file takes.
+    fn write_bytes(&mut self, data: &[u8]) -> Result<(), String> {
+        let n_rows = data.len() / self.row_len;
+        if n_rows * self.row_len != data.len() || n_rows > self.rows_left {
+            return Err("Error writing image data: wrong amount of data".to_string());
+        }
+        if let Err(e) = self.writer.write_all(data) {
+            let estr = format!("Error writing image data: {}", &e);
+            return Err(estr);
+        }
+        self.rows_left -= n_rows;
+        Ok(())
+    }
+
+    /** Finish the file. Every row of the image must have been written. */
+    pub fn finish(self) -> Result<(), String> {
+        if self.rows_left > 0 {
+            let estr = format!("Error writing image data: {} rows missing", self.rows_left);
+            return Err(estr);
+        }
+        match self.writer.finish() {
+            Ok(()) => Ok(()),
+            Err(e) => Err(format!("Error finishing PNG: {}", &e)),
+        }
+    }
+}
+
+/** The kinds of animated image an `AnimationWriter` can write. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnimationFormat {
+    /// An animated GIF: plays just about anywhere, but with only 256 colors
+    /// in each frame.
+    Gif,
+    /// An animated PNG: every color, but bigger, and not everything plays
+    /// them.
+    Apng,
+}
+
+impl AnimationFormat {
+    /** Return the format named by `fname`'s extension (`.gif` or `.apng`). */
+    pub fn of<P: AsRef<Path>>(fname: P) -> Option<AnimationFormat> {
+        let ext = fname
+            .as_ref()
+            .extension()?
+            .to_string_lossy()
+            .to_ascii_lowercase();
+        match ext.as_str() {
+            "gif" => Some(AnimationFormat::Gif),
+            "apng" => Some(AnimationFormat::Apng),
+            _ => None,
+        }
+    }
+}
+
+enum AnimationEncoder {
+    Gif(gif::Encoder<BufWriter<File>>),
+    Apng(png::Writer<BufWriter<File>>),
+}
+
+/**
+An animated GIF or PNG being written a frame at a time, for sharing an
+animation (or a color cycle) without needing another program to put the
+frames together. The animation loops forever.
+
+Each frame of a GIF gets the 256 colors that suit it best, which takes a
+while, and can band a little on smooth gradients.
+*/
+pub struct AnimationWriter {
+    enc: AnimationEncoder,
+    fname: PathBuf,
+    xpix: usize,
+    ypix: usize,
+    // How long each GIF frame shows, in hundredths of a second.
+    gif_delay: u16,
+    frames_left: usize,
+    // The color of the first pixel of the last frame written.
+    last_pixel: Option<[u8; 3]>,
+    // What an animated PNG is tagged with (GIFs can only be sRGB).
+    profile: ColorProfile,
+}
+
+impl AnimationWriter {
+    /**
+    Start writing an animation of `frames` frames, `xpix` × `ypix` pixels,
+    to `fname` in `format`, to play at `fps` frames per second (as near as
+    the format allows).
+    */
+    pub fn create<P: AsRef<Path>>(
+        fname: P,
+        format: AnimationFormat,
+        (xpix, ypix): (usize, usize),
+        frames: usize,
+        fps: f64,
+    ) -> Result<AnimationWriter, String> {
+        let fname = fname.as_ref().to_path_buf();
+        let too_big = |max: usize| xpix > max || ypix > max;
+        if format == AnimationFormat::Gif && too_big(u16::MAX as usize) {
+            return Err(format!(
+                "A GIF can't be more than {} pixels across or down.",
+                u16::MAX
+            ));
+        }
+        if frames == 0 || frames > u32::MAX as usize || too_big(u32::MAX as usize) {
+            return Err(format!(
+                "Can't make an animation of {} frames of {} x {} pixels.",
+                frames, xpix, ypix
+            ));
+        }
+        let f = match File::create(&fname) {
+            Ok(f) => f,
+            Err(e) => {
+                let estr = format!("Error opening {} for writing: {}", fname.display(), &e);
+                return Err(estr);
+            }
+        };
+        let w = BufWriter::new(f);
+        let delay = |units: f64| (units / fps).round().clamp(1.0, u16::MAX as f64) as u16;
+        let profile = match format {
+            AnimationFormat::Gif => ColorProfile::Srgb,
+            AnimationFormat::Apng => color_profile(),
+        };
+
+        let enc = match format {
+            AnimationFormat::Gif => {
+                let mut enc = gif::Encoder::new(w, xpix as u16, ypix as u16, &[])
+                    .map_err(|e| format!("Error writing GIF header: {}", &e))?;
+                enc.set_repeat(gif::Repeat::Infinite)
+                    .map_err(|e| format!("Error writing GIF header: {}", &e))?;
+                AnimationEncoder::Gif(enc)
+            }
+            AnimationFormat::Apng => {
+                let mut enc = new_encoder(w, xpix as u32, ypix as u32, profile);
+                enc.set_color(png::ColorType::Rgb);
+                enc.set_depth(png::BitDepth::Eight);
+                enc.set_filter(png::FilterType::Paeth);
+                enc.set_compression(png::Compression::Default);
+                enc.set_animated(frames as u32, 0)
+                    .and_then(|_| enc.set_frame_delay(delay(1000.0), 1000))
+                    .map_err(|e| format!("Error setting up animation: {}", &e))?;
+                let writer = enc
+                    .write_header()
+                    .map_err(|e| format!("Error writing PNG header: {}", &e))?;
+                AnimationEncoder::Apng(writer)
+            }
+        };
+
+        Ok(AnimationWriter {
+            enc,
+            fname,
+            xpix,
+            ypix,
+            // Browsers slow down anything quicker than 2/100 of a second.
+            gif_delay: delay(100.0).max(2),
+            frames_left: frames,
+            last_pixel: None,
+            profile,
+        })
+    }
+
+    /** Add the next frame, of 8-bit RGB `data`. */
+    pub fn write_frame(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() != 3 * self.xpix * self.ypix {
+            return Err("Error writing animation frame: wrong amount of data".to_string());
+        }
+        if self.frames_left == 0 {
+            return Err("Error writing animation frame: too many frames".to_string());
+        }
+        let converted: Vec<u8>;
+        let data = match self.profile {
+            ColorProfile::Srgb => data,
+            ColorProfile::DisplayP3 => {
+                converted = to_p3(data, 3, 255.0);
+                &converted
+            }
+        };
+        match &mut self.enc {
+            AnimationEncoder::Gif(enc) => {
+                let mut frame =
+                    gif::Frame::from_rgb_speed(self.xpix as u16, self.ypix as u16, data, GIF_SPEED);
+                frame.delay = self.gif_delay;
+                enc.write_frame(&frame)
+                    .map_err(|e| format!("Error writing animation frame: {}", &e))?;
+            }
+            AnimationEncoder::Apng(writer) => {
+                writer
+                    .write_image_data(data)
+                    .map_err(|e| format!("Error writing animation frame: {}", &e))?;
+            }
+        }
+        self.frames_left -= 1;
+        self.last_pixel = Some([data[0], data[1], data[2]]);
+        Ok(())
+    }
+
+    /**
+    Finish the file. An animation stopped before all its frames were
+    written just ends early.
+    */
+    pub fn finish(self) -> Result<(), String> {
+        let estr =
+            |e: &dyn std::fmt::Display| format!("Error finishing {}: {}", self.fname.display(), e);
+        match self.enc {
+            AnimationEncoder::Gif(enc) => {
+                let mut w = enc.into_inner().map_err(|e| estr(&e))?;
+                w.flush().map_err(|e| estr(&e))
+            }
+            AnimationEncoder::Apng(mut writer) => {
+                // An animated PNG says up front how many frames it has, so
+                // any missing ones get filled in with invisible ones: one
+                // pixel, the same as what's already there, shown for no
+                // time at all.
+                if self.frames_left > 0 {
+                    let pixel = self
+                        .last_pixel
+                        .ok_or_else(|| estr(&"no frames were written"))?;
+                    writer
+                        .set_frame_dimension(1, 1)
+                        .and_then(|_| writer.set_frame_delay(0, 1))
+                        .map_err(|e| estr(&e))?;
+                    for _ in 0..self.frames_left {
+                        writer.write_image_data(&pixel).map_err(|e| estr(&e))?;
+                    }
+                }
+                writer.finish().map_err(|e| estr(&e))
+            }
+        }
+    }
+}
+
+/**
+Read the PNG file `src` and write a copy of its image to `dest` with all
+the metadata (embedded parameters, text chunks with author info, etc.)
+removed. `src` and `dest` may be the same file.
+
+Only the first frame of an animated PNG will be kept.
+*/
+pub fn strip_metadata<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dest: Q) -> Result<(), String> {
+    let (src, dest) = (src.as_ref(), dest.as_ref());
+
+    let f = match File::open(src) {
+        Ok(f) => f,
+        Err(e) => {
+            let estr = format!("Error opening file {}: {}", src.display(), &e);
+            return Err(estr);
+        }
+    };
+    let mut rdr = match png::Decoder::new(f).read_info() {
+        Ok(r) => r,
+        Err(e) => {
+            let estr = format!("Error reading {}: {}", src.display(), &e);
+            return Err(estr);
+        }
+    };
+
+    // The color space information isn't metadata to be stripped; without
+    // it, the colors might not look the same.
+    let out_info = {
+        let nfo = rdr.info();
+        let mut out = png::Info::with_size(nfo.width, nfo.height);
+        out.color_type = nfo.color_type;
+        out.bit_depth = nfo.bit_depth;
+        out.palette = nfo.palette.as_ref().map(|p| Cow::Owned(p.to_vec()));
+        out.trns = nfo.trns.as_ref().map(|t| Cow::Owned(t.to_vec()));
+        out.srgb = nfo.srgb;
+        out.icc_profile = nfo.icc_profile.as_ref().map(|p| Cow::Owned(p.to_vec()));
+        out.source_gamma = nfo.source_gamma;
+        out.source_chromaticities = nfo.source_chromaticities;
+        out
+    };
+    let mut buff: Vec<u8> = vec![0; rdr.output_buffer_size()];
+    let frame = match rdr.next_frame(&mut buff) {
+        Ok(frame) => frame,
+        Err(e) => {
+            let estr = format!("Error decoding image data from {}: {}", src.display(), &e);
+            return Err(estr);
+        }
+    };
+    buff.truncate(frame.buffer_size());
+    drop(rdr);
+
+    let f = match File::create(dest) {
+        Ok(f) => f,
+        Err(e) => {
+            let estr = format!("Error opening {} for writing: {}", dest.display(), &e);
+            return Err(estr);
+        }
+    };
+    let mut w = BufWriter::new(f);
+
+    let mut enc = match png::Encoder::with_info(&mut w, out_info) {
+        Ok(enc) => enc,
+        Err(e) => {
+            let estr = format!("Error writing PNG header: {}", &e);
+            return Err(estr);
+        }
+    };
+    enc.set_filter(png::FilterType::Paeth);
+    enc.set_compression(png::Compression::Best);
+    let mut writer = match enc.write_header() {
+        Err(e) => {
+            let estr = format!("Error writing PNG header: {}", &e);
+            return Err(estr);
+        }
+        Ok(x) => x,
+    };
+    if let Err(e) = writer.write_image_data(&buff) {
+        let estr = format!("Error writing image data: {}", &e);
+        return Err(estr);
+    }
+
+    Ok(())
+}
+
+/*
+Return whether `text` has brackets or braces nested more than `MAX_NESTING`
+deep. This doesn't bother distinguishing brackets inside of strings, so it
+may occasionally be overly cautious, but it will never miss anything.
+*/
+fn nested_too_deep(text: &str) -> bool {
+    let mut depth: usize = 0;
+    for b in text.bytes() {
+        match b {
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > MAX_NESTING {
+                    return true;
+                }
+            }
+            b']' | b'}' => {
+                depth = depth.saturating_sub(1);
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+// Parse TOML text, refusing anything that could make the parser recurse
+// itself to death.
+fn parse_toml(text: &str) -> Result<Value, String> {
+    if nested_too_deep(text) {
+        return Err("Parameters are nested too deeply to be legitimate.".to_string());
+    }
+    toml::from_str(text).map_err(|e| e.to_string())
+}
+
+// Deserialize parameters (of any version) from TOML text.
+fn params_from_toml(text: &str) -> Result<ImageParameters, String> {
+    parse_toml(text).and_then(from_migrated)
+}
+
+fn try_load_text<R: Read>(f: &mut R, format: ParamFormat) -> LoadResult {
+    let mut buff: Vec<u8> = Vec::new();
+
+    // One extra byte, to tell whether there's more than `READ_LIMIT`.
+    let str_len = match f.take(READ_LIMIT as u64 + 1).read_to_end(&mut buff) {
+        Ok(n) => n,
+        Err(e) => {
+            return LoadResult::GiveUp(e.to_string());
+        }
+    };
+
+    if str_len > READ_LIMIT {
+        // Parsing a truncated file might "succeed" with the wrong values,
+        // so if it looks like text, don't even try. (A multibyte character
+        // may have gotten cut off at the end; that doesn't count against
+        // it.)
+        return match std::str::from_utf8(&buff[..str_len]) {
+            Err(e) if e.error_len().is_some() => LoadResult::TryOtherType,
+            _ => LoadResult::GiveUp(format!(
+                "File too large: parameter files are limited to {} MiB.",
+                READ_LIMIT / (1024 * 1024)
+            )),
+        };
+    }
+
+    let text = match std::str::from_utf8(&buff[..str_len]) {
+        Ok(s) => s,
+        Err(_) => {
+            return LoadResult::TryOtherType;
+        }
+    };
+
+    let ips: ImageParameters = match format {
+        // Text that isn't TOML might still be an image, but TOML that
+        // isn't parameters is just wrong.
+        ParamFormat::Toml => match parse_toml(text).map(from_migrated) {
+            Ok(Ok(x)) => x,
+            Ok(Err(e)) => {
+                let estr = format!("Error decoding parameters: {}", &e);
+                return LoadResult::GiveUp(estr);
+            }
+            Err(_) => {
+                return LoadResult::TryOtherType;
+            }
+        },
+        ParamFormat::Json => match serde_json::from_str(text)
+            .map_err(|e| e.to_string())
+            .and_then(from_migrated)
+        {
+            Ok(x) => x,
+            Err(e) => {
+                let estr = format!("Error decoding JSON parameters: {}", &e);
+                return LoadResult::GiveUp(estr);
+            }
+        },
+    };
+
+    LoadResult::Success(Box::new(ips))
+}
+
+fn try_load_png<R: Read>(f: &mut R) -> LoadResult {
+    let dec = png::Decoder::new(f);
+    let rdr = match dec.read_info() {
+        Ok(r) => r,
+        Err(e) => {
+            return LoadResult::GiveUp(e.to_string());
+        }
+    };
+
+    let mut meta_text: Option<String> = None;
+
+    for chunk in rdr.info().utf8_text.iter() {
+        if &chunk.keyword == "jset_desk parameters" {
+            match chunk.get_text() {
+                Ok(s) => {
+                    meta_text = Some(s);
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("Error decoding metadata text chunk: {}", &e);
+                }
+            }
+        }
+    }
+
+    let meta_text = match meta_text {
+        Some(s) => s,
+        None => {
+            return LoadResult::GiveUp(
+                "File contains no recognizable metadata parameters.".to_string(),
+            );
+        }
+    };
+
+    let ips: ImageParameters = match params_from_toml(&meta_text) {
+        Ok(x) => x,
+        Err(e) => {
+            let estr = format!("Error decoding metadata chunk: {}", &e);
+            return LoadResult::GiveUp(estr);
+        }
+    };
+
+    LoadResult::Success(Box::new(ips))
+}
+
+pub fn load<P: AsRef<Path>>(fname: P) -> Result<(ImageDims, ColorSpec, IterType), String> {
+    let (dims, cspec, itype, _) = load_checked(fname)?;
+    Ok((dims, cspec, itype))
+}
+
+/**
+Load image parameters like `load()`, along with what re-rendering them
+will produce, if they were locked.
+*/
+pub fn load_checked<P: AsRef<Path>>(
+    fname: P,
+) -> Result<(ImageDims, ColorSpec, IterType, LockStatus), String> {
+    let fname = fname.as_ref();
+    let mut f = match File::open(fname) {
+        Ok(f) => f,
+        Err(e) => {
+            let estr = format!("Error opening file {}: {}", fname.display(), &e);
+            return Err(estr);
+        }
+    };
+
+    let ips = load_params_from(&mut f, ParamFormat::of(fname))?;
+    let status = ips.lock_status();
+    Ok((ips.dimensions, ips.color_spec, ips.iterator, status))
+}
+
+/**
+Load image parameters from the contents of a file already in memory.
+`format` is the format to expect if the data is text; image data will be
+checked for embedded parameters regardless.
+*/
+pub fn load_bytes(
+    data: &[u8],
+    format: ParamFormat,
+) -> Result<(ImageDims, ColorSpec, IterType), String> {
+    load_from(&mut std::io::Cursor::new(data), format)
+}
+
+fn load_from<R: Read + Seek>(
+    f: &mut R,
+    format: ParamFormat,
+) -> Result<(ImageDims, ColorSpec, IterType), String> {
+    let ips = load_params_from(f, format)?;
+    Ok((ips.dimensions, ips.color_spec, ips.iterator))
+}
+
+fn load_params_from<R: Read + Seek>(
+    f: &mut R,
+    format: ParamFormat,
+) -> Result<ImageParameters, String> {
+    match try_load_text(f, format) {
+        LoadResult::Success(ips) => {
+            return Ok(*ips);
+        }
+        LoadResult::GiveUp(e) => {
+            return Err(e);
+        }
+        LoadResult::TryOtherType => { /* continue trying other type! */ }
+    }
+
+    if let Err(e) = f.seek(std::io::SeekFrom::Start(0)) {
+        return Err(e.to_string());
+    }
+
+    match try_load_png(f) {
+        LoadResult::Success(ips) => Ok(*ips),
+        LoadResult::GiveUp(e) => Err(e),
+        LoadResult::TryOtherType => Err("Could not load from PNG for some reason.".to_string()),
+    }
+}
+
+// Decode the `%XX` escapes in the path portion of a `file://` URI.
+fn decode_uri_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut n: usize = 0;
+    while n < bytes.len() {
+        if bytes[n] == b'%' && n + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[n + 1..n + 3]).unwrap_or("");
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                decoded.push(b);
+                n += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[n]);
+        n += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Turn a line of pasted text that might be a `file://` URI into something
+// that might be a path.
+fn pasted_path(line: &str) -> String {
+    let path = match line.strip_prefix("file://") {
+        None => {
+            return line.to_string();
+        }
+        Some(p) => p,
+    };
+    let path = path.strip_prefix("localhost").unwrap_or(path);
+    let path = decode_uri_path(path);
+    // Windows URIs look like file:///C:/Users/...
+    let pb = path.as_bytes();
+    if pb.len() > 2 && pb[0] == b'/' && pb[2] == b':' {
+        path[1..].to_string()
+    } else {
+        path
+    }
+}
+
+/**
+Return the path of the file dropped onto a window, given the text that
+arrives with the drop: a list of paths or `file://` URIs, one per line, of
+which only the first is considered. Returns `None` if there's no file
+there.
+*/
+pub fn dropped_file(text: &str) -> Option<String> {
+    let first_line = text.trim().lines().next()?.trim();
+    let path = pasted_path(first_line);
+    if Path::new(&path).is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+// Share strings start with this, so they can be picked out of any other
+// text (like a URL they've been tacked onto the end of).
+const SHARE_PREFIX: &str = "jset:";
+
+/**
+Encode a whole set of image parameters as one line of text, to paste
+into a chat or the end of a URL: `jset:` followed by the parameters as
+compressed JSON, in URL-safe base64. `from_share_string()` (and pasting)
+turns it back into parameters.
+*/
+pub fn share_string(
+    dims: &ImageDims,
+    cspec: &ColorSpec,
+    iter: &IterType,
+) -> Result<String, String> {
+    let ips = ImageParameters::with_lock(dims, cspec, iter, false);
+    let json = match serde_json::to_vec(&ips) {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(format!("Error serializing data: {}", &e));
+        }
+    };
+    let mut w = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+    let packed = match w.write_all(&json).and_then(|_| w.finish()) {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(format!("Error compressing data: {}", &e));
+        }
+    };
+    Ok(format!(
+        "{}{}",
+        SHARE_PREFIX,
+        URL_SAFE_NO_PAD.encode(packed)
+    ))
+}
+
+/**
+Decode the parameters in a share string made by `share_string()`. Any
+text around it (like the URL it's on the end of) is ignored.
+*/
+pub fn from_share_string(text: &str) -> Result<(ImageDims, ColorSpec, IterType), String> {
+    let start = match text.find(SHARE_PREFIX) {
+        Some(n) => n + SHARE_PREFIX.len(),
+        None => {
+            return Err(format!("A share string starts with {:?}.", SHARE_PREFIX));
+        }
+    };
+    let code: String = text[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .collect();
+    let packed = match URL_SAFE_NO_PAD.decode(&code) {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(format!("The share string is damaged: {}", &e));
+        }
+    };
+    // One extra byte, to tell whether there's more than `READ_LIMIT`.
+    let mut json: Vec<u8> = Vec::new();
+    let inflated = flate2::read::DeflateDecoder::new(&packed[..])
+        .take(READ_LIMIT as u64 + 1)
+        .read_to_end(&mut json);
+    match inflated {
+        Err(e) => Err(format!("The share string is damaged: {}", &e)),
+        Ok(n) if n > READ_LIMIT => Err("The share string unpacks to too much.".to_string()),
+        Ok(_) => {
+            let ips: ImageParameters = serde_json::from_slice(&json)
+                .map_err(|e| e.to_string())
+                .and_then(from_migrated)
+                .map_err(|e| format!("Error decoding shared parameters: {}", &e))?;
+            Ok((ips.dimensions, ips.color_spec, ips.iterator))
+        }
+    }
+}
+
+/**
+Load image parameters from text pasted from the clipboard.
+
+The text can either be the parameters themselves (as TOML or JSON, or a
+share string from `share_string()`), or the path to (or `file://` URI
+of) any file `load()` can read, which is what most file managers put on
+the clipboard when a file is copied. An image copied as _pixels_ doesn't
+carry its PNG text chunks along with it, so there's nothing to be
+recovered from that.
+*/
+pub fn load_pasted(text: &str) -> Result<(ImageDims, ColorSpec, IterType), String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(
+            "The clipboard doesn't contain any text. A copied image is only \
+             pixels, without the parameters, so copy the image file instead."
+                .to_string(),
+        );
+    }
+
+    // If several files were copied, only the first one is considered.
+    let first_line = text.lines().next().unwrap_or("").trim();
+    let path = pasted_path(first_line);
+    if Path::new(&path).is_file() {
+        return load(&path);
+    }
+    // A share string is one line of text, maybe the end of a URL.
+    if text.contains(SHARE_PREFIX) && !text.contains('\n') {
+        return from_share_string(text);
+    }
+
+    let ips: ImageParameters = if text.starts_with('{') {
+        match serde_json::from_str(text)
+            .map_err(|e| e.to_string())
+            .and_then(from_migrated)
+        {
+            Ok(x) => x,
+            Err(e) => {
+                let estr = format!("Error decoding pasted JSON parameters: {}", &e);
+                return Err(estr);
+            }
+        }
+    } else {
+        match params_from_toml(text) {
+            Ok(x) => x,
+            Err(e) => {
+                let estr = format!(
+                    "Pasted text is neither a file nor recognizable parameters: {}",
+                    &e
+                );
+                return Err(estr);
+            }
+        }
+    };
+
+    Ok((ips.dimensions, ips.color_spec, ips.iterator))
+}
+
+/** Load a `Session` saved by `save_session()`. */
+pub fn load_session<P: AsRef<Path>>(fname: P) -> Result<Session, String> {
+    let text = read_text(fname.as_ref())?;
+    if nested_too_deep(&text) {
+        return Err("Session is nested too deeply to be legitimate.".to_string());
+    }
+    let session: Session = match toml::from_str(&text) {
+        Ok(s) => s,
+        Err(e) => {
+            return Err(format!("Error decoding session: {}", &e));
+        }
+    };
+    session.check()?;
+    Ok(session)
+}
+
+// Read the text of a file, refusing anything larger than `READ_LIMIT`.
+fn read_text(fname: &Path) -> Result<String, String> {
+    let f = match File::open(fname) {
+        Ok(f) => f,
+        Err(e) => {
+            let estr = format!("Error opening file {}: {}", fname.display(), &e);
+            return Err(estr);
+        }
+    };
+    let mut buff: Vec<u8> = Vec::new();
+    if let Err(e) = f.take(READ_LIMIT as u64 + 1).read_to_end(&mut buff) {
+        let estr = format!("Error reading {}: {}", fname.display(), &e);
+        return Err(estr);
+    }
+    if buff.len() > READ_LIMIT {
+        return Err(format!(
+            "File too large: files like this are limited to {} MiB.",
+            READ_LIMIT / (1024 * 1024)
+        ));
+    }
+    // Palette files from older programs are often in some legacy encoding,
+    // but anything that matters is ASCII anyway.
+    Ok(String::from_utf8_lossy(&buff).into_owned())
+}
+
+/**
+Read the colors from the text of a GIMP palette (`.gpl`) file. (Krita,
+Inkscape, and Aseprite also read and write these.)
+*/
+pub fn parse_gpl(text: &str) -> Result<Vec<RGB>, String> {
+    let mut lines = text.lines().enumerate();
+    match lines.next() {
+        Some((_, line)) if line.trim_start_matches('\u{feff}').trim() == "GIMP Palette" => {}
+        _ => {
+            return Err("Not a GIMP palette file.".to_string());
+        }
+    }
+
+    let mut colors: Vec<RGB> = Vec::new();
+    for (n, line) in lines {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:")
+        {
+            continue;
+        }
+        let vals: Vec<u8> = line
+            .split_whitespace()
+            .take(3)
+            .filter_map(|v| v.parse::<u8>().ok())
+            .collect();
+        if vals.len() != 3 {
+            return Err(format!(
+                "Line {}: expected a color, found {:?}",
+                n + 1,
+                line
+            ));
+        }
+        colors.push(RGB::new(vals[0] as f32, vals[1] as f32, vals[2] as f32));
+    }
+
+    if colors.is_empty() {
+        return Err("The palette has no colors in it.".to_string());
+    }
+    Ok(colors)
+}
+
+/** Read the colors from a GIMP palette (`.gpl`) file. */
+pub fn load_gpl<P: AsRef<Path>>(fname: P) -> Result<Vec<RGB>, String> {
+    parse_gpl(&read_text(fname.as_ref())?)
+}
+
+/**
+The formats of gradient files from other programs that can be loaded as
+color maps.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GradientFormat {
+    /// Ultra Fractal's `.ugr` gradient collections.
+    Ugr,
+    /// GMT color palette tables (`.cpt`), like the ones on cpt-city.
+    Cpt,
+}
+
+impl GradientFormat {
+    /** Determine the format of a gradient file from its extension, if it is one. */
+    pub fn of<P: AsRef<Path>>(fname: P) -> Option<GradientFormat> {
+        match fname.as_ref().extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("ugr") => Some(GradientFormat::Ugr),
+            Some(ext) if ext.eq_ignore_ascii_case("cpt") => Some(GradientFormat::Cpt),
+            _ => None,
+        }
+    }
+}
+
+// The number of positions along an Ultra Fractal gradient.
+const UGR_POSITIONS: usize = 400;
+// The total number of steps a .cpt file's gradients are spread over.
+const CPT_STEPS: f64 = 256.0;
+
+/**
+Read the first gradient from the text of an Ultra Fractal (`.ugr`) file.
+
+Ultra Fractal gradients wrap around, so the last color blends back into
+the first.
+*/
+pub fn parse_ugr(text: &str) -> Result<ColorSpec, String> {
+    let mut stops: Vec<(usize, RGB)> = Vec::new();
+    let mut in_gradient = false;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if !in_gradient {
+            in_gradient = line == "gradient:";
+            continue;
+        }
+        if line.ends_with(':') || line.starts_with('}') {
+            break;
+        }
+
+        let mut index: Option<usize> = None;
+        let mut color: Option<u32> = None;
+        for tok in line.split_whitespace() {
+            if let Some(v) = tok.strip_prefix("index=") {
+                index = v.parse::<usize>().ok();
+            } else if let Some(v) = tok.strip_prefix("color=") {
+                color = v.parse::<u32>().ok();
+            }
+        }
+        match (index, color) {
+            (Some(i), Some(c)) if i < UGR_POSITIONS => {
+                // Colors are stored as 0x00BBGGRR.
+                let [r, g, b, _] = c.to_le_bytes();
+                stops.push((i, RGB::new(r as f32, g as f32, b as f32)));
+            }
+            (None, None) => {}
+            _ => {
+                return Err(format!(
+                    "Line {}: expected an index and a color, found {:?}",
+                    n + 1,
+                    line
+                ));
+            }
+        }
+    }
+
+    if stops.is_empty() {
+        return Err("No Ultra Fractal gradient found.".to_string());
+    }
+    stops.sort_by_key(|(i, _)| *i);
+    stops.dedup_by_key(|(i, _)| *i);
+
+    let first = stops[0];
+    let gradients = stops
+        .iter()
+        .zip(stops.iter().skip(1).chain(std::iter::once(&first)))
+        .map(|((i, start), (j, end))| Gradient {
+            steps: (j + UGR_POSITIONS - i - 1) % UGR_POSITIONS + 1,
+            start: *start,
+            end: *end,
+            space: Interpolation::Rgb,
+        })
+        .collect();
+    Ok(ColorSpec::new(gradients, RGB::BLACK))
+}
+
+/**
+Read the text of a GMT color palette table (`.cpt`) file, the format of
+most of the gradients on cpt-city.
+
+The gradients are stretched (or squished) to add up to about 256 steps,
+in proportion to how much of the table's range each one covers. The
+"foreground" color (for values past the end of the table) becomes the
+default color.
+*/
+pub fn parse_cpt(text: &str) -> Result<ColorSpec, String> {
+    let mut hsv = false;
+    let mut default: Option<RGB> = None;
+    let mut segments: Vec<(f64, RGB, f64, RGB)> = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix('#') {
+            let comment = comment.to_ascii_uppercase();
+            if comment.contains("COLOR_MODEL") {
+                hsv = comment.contains("HSV");
+            }
+            continue;
+        }
+        let toks: Vec<&str> = line
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        let bad_line = || format!("Line {}: can't make sense of {:?}", n + 1, line);
+        match toks.first() {
+            None => {}
+            Some(&"F") => {
+                default = Some(cpt_color(&toks[1..], hsv).ok_or_else(bad_line)?.0);
+            }
+            Some(&"B") | Some(&"N") => {}
+            Some(_) => {
+                let z0 = toks[0].parse::<f64>().map_err(|_| bad_line())?;
+                let (c0, rest) = cpt_color(&toks[1..], hsv).ok_or_else(bad_line)?;
+                let z1 = rest
+                    .first()
+                    .and_then(|t| t.parse::<f64>().ok())
+                    .ok_or_else(bad_line)?;
+                let (c1, _) = cpt_color(&rest[1..], hsv).ok_or_else(bad_line)?;
+                if !(z0.is_finite() && z1.is_finite()) || z1 < z0 {
+                    return Err(bad_line());
+                }
+                segments.push((z0, c0, z1, c1));
+            }
+        }
+    }
+
+    if segments.is_empty() {
+        return Err("No color table found.".to_string());
+    }
+    let lo = segments.iter().map(|s| s.0).fold(f64::INFINITY, f64::min);
+    let hi = segments
+        .iter()
+        .map(|s| s.2)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let range = if hi > lo { hi - lo } else { 1.0 };
+    let space = if hsv {
+        Interpolation::Hsv
+    } else {
+        Interpolation::Rgb
+    };
+
+    let gradients = segments
+        .iter()
+        .map(|(z0, start, z1, end)| Gradient {
+            steps: ((z1 - z0) * CPT_STEPS / range).round().max(1.0) as usize,
+            start: *start,
+            end: *end,
+            space,
+        })
+        .collect();
+    Ok(ColorSpec::new(gradients, default.unwrap_or(RGB::BLACK)))
+}
+
+/*
+Read a color from the start of some .cpt tokens, either as three values
+or as one "r/g/b" (or "r-g-b") token; returns it along with the remaining
+tokens. In HSV mode, the values are hue in degrees, then saturation and
+value from 0 to 1.
+*/
+fn cpt_color<'a, 'b>(toks: &'a [&'b str], hsv: bool) -> Option<(RGB, &'a [&'b str])> {
+    let first = toks.first()?;
+    let (vals, rest): (Vec<&str>, &[&str]) = if first.contains('/') {
+        (first.split('/').collect(), &toks[1..])
+    } else if first.matches('-').count() == 2 && !first.starts_with('-') {
+        (first.split('-').collect(), &toks[1..])
+    } else if toks.len() >= 3 {
+        (toks[..3].to_vec(), &toks[3..])
+    } else {
+        return None;
+    };
+    if vals.len() != 3 {
+        return None;
     }
+    let mut v = [0.0f32; 3];
+    for (x, s) in v.iter_mut().zip(vals.iter()) {
+        *x = s.parse::<f32>().ok().filter(|x| x.is_finite())?;
+    }
+    let color = if hsv {
+        RGB::from_hsv(v[0], v[1], v[2])
+    } else {
+        RGB::new(v[0], v[1], v[2])
+    };
+    Some((color, rest))
 }
-*/
 
-pub fn save_with_metadata<P: AsRef<Path>>(
-    fname: P,
-    xpix: usize,
-    ypix: usize,
-    data: &[u8],
-    dims: &ImageDims,
-    cspec: &ColorSpec,
-    iter: &IterType,
-) -> Result<(), String> {
+/**
+Load just the color map from a file: one saved with `save_colors()`, a
+full parameter file (or PNG with parameters embedded), or a gradient file
+from another program.
+*/
+pub fn load_colors<P: AsRef<Path>>(fname: P) -> Result<ColorSpec, String> {
     let fname = fname.as_ref();
-    let metadata = ImageParameters::toml(dims, cspec, iter)?;
-    let f = match File::create(fname) {
-        Ok(f) => f,
-        Err(e) => {
-            let estr = format!("Error opening {} for writing: {}", fname.display(), &e);
-            return Err(estr);
+    if GradientFormat::of(fname).is_some() {
+        return load_gradient(fname);
+    }
+    if !ParamFormat::is_param_file(fname) {
+        return load(fname).map(|(_, cspec, _)| cspec);
+    }
+
+    let text = read_text(fname)?;
+    let res: Result<Value, String> = match ParamFormat::of(fname) {
+        ParamFormat::Toml if nested_too_deep(&text) => {
+            Err("Parameters are nested too deeply to be legitimate.".to_string())
         }
+        ParamFormat::Toml => toml::from_str(&text).map_err(|e| e.to_string()),
+        ParamFormat::Json => serde_json::from_str(&text).map_err(|e| e.to_string()),
     };
-    let mut w = BufWriter::new(f);
+    match res.and_then(from_migrated::<ColorParameters>) {
+        Ok(cps) => Ok(cps.color_spec),
+        Err(e) => Err(format!("Error decoding color map: {}", &e)),
+    }
+}
 
-    let mut enc = png::Encoder::new(&mut w, xpix as u32, ypix as u32);
-    enc.set_color(png::ColorType::Rgb);
-    enc.set_depth(png::BitDepth::Eight);
-    enc.set_filter(png::FilterType::Paeth);
-    enc.set_compression(png::Compression::Best);
-    if let Err(e) = enc.add_itxt_chunk("jset_desk parameters".to_string(), metadata) {
-        let estr = format!("Error writing metadata: {}", &e);
-        return Err(estr);
+/** Load a gradient file from another program as a color map. */
+pub fn load_gradient<P: AsRef<Path>>(fname: P) -> Result<ColorSpec, String> {
+    let fname = fname.as_ref();
+    let text = read_text(fname)?;
+    match GradientFormat::of(fname) {
+        Some(GradientFormat::Ugr) => parse_ugr(&text),
+        Some(GradientFormat::Cpt) => parse_cpt(&text),
+        None => Err(format!(
+            "{} isn't a known gradient file type.",
+            fname.display()
+        )),
     }
-    let mut writer = match enc.write_header() {
-        Err(e) => {
-            let estr = format!("Error writing PNG header: {}", &e);
-            return Err(estr);
+}
+
+/** The formats of other fractal programs' location files that can be loaded. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LocationFormat {
+    /// Kalles Fraktaler's `.kfr` files.
+    Kfr,
+    /// Fractint's `.par` parameter files.
+    Par,
+}
+
+impl LocationFormat {
+    /** Determine the format of a location file from its extension, if it is one. */
+    pub fn of<P: AsRef<Path>>(fname: P) -> Option<LocationFormat> {
+        match fname.as_ref().extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("kfr") => Some(LocationFormat::Kfr),
+            Some(ext) if ext.eq_ignore_ascii_case("par") => Some(LocationFormat::Par),
+            _ => None,
         }
-        Ok(x) => x,
-    };
-    if let Err(e) = writer.write_image_data(data) {
-        let estr = format!("Error writing image data: {}", &e);
-        return Err(estr);
     }
+}
 
-    Ok(())
+/**
+A spot in the plane to look at, and what to iterate there, as read from
+another fractal program's location file. There's nothing about colors or
+the size of the image in these, so those are left as they are.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub center: (f64, f64),
+    /// The view has to be at least this wide and this high.
+    pub width: f64,
+    pub height: f64,
+    pub itertype: IterType,
+    /// The iteration limit, if the file says.
+    pub iterations: Option<usize>,
+}
+
+impl Location {
+    /** Return the view of this location with the pixel dimensions of `dims`. */
+    pub fn view(&self, dims: &ImageDims) -> ImageDims {
+        let (x, y) = self.center;
+        dims.framing(x, y, self.width, self.height)
+    }
 }
 
-fn try_to_fill<R: Read>(r: &mut R, buff: &mut [u8]) -> Result<usize, std::io::Error> {
-    let mut total_read: usize = 0;
+// Past this much magnification, 64-bit floats run out of precision, and
+// there's nothing to see but big square pixels.
+const MAX_LOCATION_ZOOM: f64 = 1.0e13;
 
-    loop {
-        match r.read(&mut buff[total_read..])? {
-            0 => {
-                return Ok(total_read);
-            }
-            n => {
-                total_read += n;
-            }
-        }
+// Make sure a view `size` across can be drawn at all.
+fn check_location_size(size: f64) -> Result<f64, String> {
+    if !(size.is_finite() && size > 0.0) {
+        Err(format!("{} isn't a size of view", size))
+    } else if size < 4.0 / MAX_LOCATION_ZOOM {
+        Err("That's zoomed in too deep to draw without arbitrary precision.".to_string())
+    } else {
+        Ok(size)
     }
 }
 
-fn try_load_toml(f: &mut File) -> LoadResult {
-    let mut buff: Vec<u8> = vec![0; READ_LIMIT];
+/**
+Read the location from the text of a Kalles Fraktaler (`.kfr`) file.
 
-    let str_len = match try_to_fill(f, &mut buff) {
-        Ok(n) => n,
-        Err(e) => {
-            return LoadResult::GiveUp(e.to_string());
+Only the plain Mandlebrot set (fractal type 0, power 2) can be drawn, and
+any rotation is ignored. Zoom 1 takes in 2 units either side of the center.
+*/
+pub fn parse_kfr(text: &str) -> Result<Location, String> {
+    let fields: HashMap<&str, &str> = text
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim(), v.trim()))
+        .collect();
+    let number = |key: &str| -> Result<Option<f64>, String> {
+        match fields.get(key) {
+            None => Ok(None),
+            Some(v) => match v.parse::<f64>() {
+                Ok(x) => Ok(Some(x)),
+                Err(_) => Err(format!("{}: can't read {:?} as a number", key, v)),
+            },
         }
     };
 
-    let toml_str = match std::str::from_utf8(&buff[..str_len]) {
-        Ok(s) => s,
-        Err(_) => {
-            return LoadResult::TryOtherType;
+    let (re, im) = match (number("Re")?, number("Im")?) {
+        (Some(re), Some(im)) => (re, im),
+        _ => {
+            return Err("No Re: and Im: (the center of the view) found.".to_string());
         }
     };
+    let fractal = number("FractalType")?.unwrap_or(0.0);
+    let power = number("Power")?.unwrap_or(2.0);
+    if fractal != 0.0 || power != 2.0 {
+        return Err(format!(
+            "Only the Mandlebrot set can be drawn, not fractal type {} to the power {}.",
+            fractal, power
+        ));
+    }
+    let size = check_location_size(4.0 / number("Zoom")?.unwrap_or(1.0))?;
 
-    let ips: ImageParameters = match toml::from_str(toml_str) {
-        Ok(x) => x,
-        Err(_) => {
-            return LoadResult::TryOtherType;
+    Ok(Location {
+        center: (re, im),
+        width: size,
+        height: size,
+        itertype: IterType::Mandlebrot,
+        iterations: number("Iterations")?.map(|n| n as usize),
+    })
+}
+
+/**
+Read the location from the first entry in the text of a Fractint (`.par`)
+parameter file.
+
+The view can be given either by `corners=` or `center-mag=` (magnification
+1 is 2 units high, at Fractint's usual 4:3 shape); `mandel`, `julia` and
+`lambda` (and their `fp` versions) are the types that can be drawn.
+*/
+pub fn parse_par(text: &str) -> Result<Location, String> {
+    // Comments run from a ';' to the end of the line, and a '\' at the end
+    // of a line continues it on the next.
+    let text: String = text
+        .lines()
+        .map(|line| line.split(';').next().unwrap_or_default())
+        .map(|line| match line.trim_end().strip_suffix('\\') {
+            Some(line) => line.to_string(),
+            None => format!("{} ", line),
+        })
+        .collect();
+    let body = match (text.find('{'), text.find('}')) {
+        (Some(start), Some(end)) if start < end => &text[(start + 1)..end],
+        _ => {
+            return Err("No { ... } parameter entry found.".to_string());
+        }
+    };
+    let fields: HashMap<String, &str> = body
+        .split_whitespace()
+        .filter_map(|tok| tok.split_once('='))
+        .map(|(k, v)| (k.to_ascii_lowercase(), v))
+        .collect();
+    let numbers = |key: &str| -> Result<Vec<f64>, String> {
+        match fields.get(key) {
+            None => Ok(Vec::new()),
+            Some(v) => v
+                .split('/')
+                .map(|x| {
+                    x.parse::<f64>()
+                        .map_err(|_| format!("{}: can't read {:?} as a number", key, x))
+                })
+                .collect(),
         }
     };
 
-    LoadResult::Success(ips)
-}
-
-fn try_load_png(f: &mut File) -> LoadResult {
-    let dec = png::Decoder::new(f);
-    let rdr = match dec.read_info() {
-        Ok(r) => r,
-        Err(e) => {
-            return LoadResult::GiveUp(e.to_string());
+    let params = numbers("params")?;
+    let c = Cx {
+        re: params.first().copied().unwrap_or(0.0),
+        im: params.get(1).copied().unwrap_or(0.0),
+    };
+    let kind = fields.get("type").map(|t| t.to_ascii_lowercase());
+    // Along with the iterator, where Fractint centers the view of each type
+    // unless told otherwise.
+    let (itertype, usual_x) = match kind.as_deref().map(|t| t.trim_end_matches("fp")) {
+        None | Some("mandel") => (IterType::Mandlebrot, -0.5),
+        Some("julia") => (
+            IterType::Polynomial {
+                coefs: vec![c, Cx { re: 0.0, im: 0.0 }, Cx { re: 1.0, im: 0.0 }],
+            },
+            0.0,
+        ),
+        // z -> λz(1 - z)
+        Some("lambda") => (
+            IterType::Polynomial {
+                coefs: vec![Cx { re: 0.0, im: 0.0 }, c, -c],
+            },
+            0.5,
+        ),
+        Some(t) => {
+            return Err(format!("Fractint's {:?} type can't be drawn.", t));
         }
     };
 
-    let mut meta_text: Option<String> = None;
+    let corners = numbers("corners")?;
+    let center_mag = numbers("center-mag")?;
+    let (center, width, height) = if corners.len() >= 4 {
+        let (x0, x1, y0, y1) = (corners[0], corners[1], corners[2], corners[3]);
+        let center = ((x0 + x1) / 2.0, (y0 + y1) / 2.0);
+        (center, (x1 - x0).abs(), (y1 - y0).abs())
+    } else if center_mag.len() >= 3 {
+        let height = 2.0 / center_mag[2];
+        let stretch = center_mag.get(3).copied().unwrap_or(1.0);
+        let width = height * 4.0 / 3.0 / stretch;
+        ((center_mag[0], center_mag[1]), width, height)
+    } else {
+        ((usual_x, 0.0), 4.0, 3.0)
+    };
 
-    for chunk in rdr.info().utf8_text.iter() {
-        if &chunk.keyword == "jset_desk parameters" {
-            match chunk.get_text() {
-                Ok(s) => {
-                    meta_text = Some(s);
-                    break;
-                }
-                Err(e) => {
-                    eprintln!("Error decoding metadata text chunk: {}", &e);
-                }
+    let iterations = match fields.get("maxiter") {
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) => Some(n),
+            Err(_) => {
+                return Err(format!("maxiter: can't read {:?} as a number", n));
             }
-        }
+        },
+        None => None,
+    };
+
+    Ok(Location {
+        center,
+        width: check_location_size(width)?,
+        height: check_location_size(height)?,
+        itertype,
+        iterations,
+    })
+}
+
+/**
+Load a location from another fractal program's file, in whichever format
+the file's extension says it's in.
+*/
+pub fn load_location<P: AsRef<Path>>(fname: P) -> Result<Location, String> {
+    let fname = fname.as_ref();
+    let text = read_text(fname)?;
+    match LocationFormat::of(fname) {
+        Some(LocationFormat::Kfr) => parse_kfr(&text),
+        Some(LocationFormat::Par) => parse_par(&text),
+        None => Err(format!(
+            "{} isn't a known location file type.",
+            fname.display()
+        )),
     }
+}
 
-    let meta_text = match meta_text {
-        Some(s) => s,
-        None => {
-            return LoadResult::GiveUp(
-                "File contains no recognizable metadata parameters.".to_string(),
-            );
-        }
-    };
+// How far (in 8-bit RGB units) a pixel of a palette strip can be from the
+// straight line through its gradient and still count as part of it.
+const STRIP_TOLERANCE: f32 = 3.0;
+// Strips longer than this get sampled down when they're imported.
+const MAX_STRIP_LENGTH: usize = 4096;
 
-    let ips: ImageParameters = match toml::from_str(&meta_text) {
-        Ok(x) => x,
-        Err(e) => {
-            let estr = format!("Error decoding metadata chunk: {}", &e);
-            return LoadResult::GiveUp(estr);
+/**
+Save one run through the color map as a PNG strip one pixel high, with a
+pixel for each step, for image editors and other fractal programs to
+pick up.
+*/
+pub fn save_strip<P: AsRef<Path>>(cmap: &ColorMap, fname: P) -> Result<(), String> {
+    if cmap.is_empty() {
+        return Err("The color map is empty.".to_string());
+    }
+    let data: Vec<u8> = cmap.colors().iter().flat_map(|c| c.to_rgb8()).collect();
+    // The strip is read back as colors, so it stays sRGB.
+    write_png(
+        fname.as_ref(),
+        (cmap.len(), 1),
+        Pixels::Eight(&data),
+        None,
+        PngCompression::Best,
+        ColorProfile::Srgb,
+    )
+}
+
+// Whether `px` could all be steps of one RGB gradient: each pixel is on
+// the line from the first to the last, and the color the line reaches a
+// step past the last pixel (which is the gradient's end color) is a color.
+fn is_gradient(px: &[RGB]) -> bool {
+    if px.len() < 2 {
+        return true;
+    }
+    let (first, last) = (px[0], px[px.len() - 1]);
+    let n = (px.len() - 1) as f32;
+    let channels = |c: RGB| [c.r(), c.g(), c.b()];
+    let (c0, c1) = (channels(first), channels(last));
+    let fits = px.iter().enumerate().all(|(i, c)| {
+        let t = (i as f32) / n;
+        channels(*c)
+            .iter()
+            .enumerate()
+            .all(|(k, x)| (c0[k] + (c1[k] - c0[k]) * t - x).abs() <= STRIP_TOLERANCE)
+    });
+    let t = (n + 1.0) / n;
+    fits && (0..3).all(|k| {
+        let end = c0[k] + (c1[k] - c0[k]) * t;
+        (-STRIP_TOLERANCE..=(255.0 + STRIP_TOLERANCE)).contains(&end)
+    })
+}
+
+/**
+Split the pixels of a palette strip up into gradients, each as long as
+it can be while still matching its pixels, so an imported strip comes
+out as a few gradients rather than a step per pixel.
+*/
+pub fn strip_gradients(pixels: &[RGB]) -> Vec<Gradient> {
+    let mut gradients: Vec<Gradient> = Vec::new();
+    let mut a = 0;
+    while a < pixels.len() {
+        let mut b = a + 1;
+        while b < pixels.len() && is_gradient(&pixels[a..=b]) {
+            b += 1;
         }
-    };
 
-    LoadResult::Success(ips)
+        let (start, last) = (pixels[a], pixels[b - 1]);
+        let end = if b - a < 2 {
+            start
+        } else {
+            let t = ((b - a) as f32) / ((b - a - 1) as f32);
+            RGB::new(
+                start.r() + (last.r() - start.r()) * t,
+                start.g() + (last.g() - start.g()) * t,
+                start.b() + (last.b() - start.b()) * t,
+            )
+        };
+        gradients.push(Gradient {
+            steps: b - a,
+            start,
+            end,
+            space: Interpolation::Rgb,
+        });
+        a = b;
+    }
+    gradients
 }
 
-pub fn load<P: AsRef<Path>>(fname: P) -> Result<(ImageDims, ColorSpec, IterType), String> {
+/**
+Read the colors from a palette strip: a PNG (from this program or any
+other) that's a single row of colors. If it's more than one pixel high,
+the middle row is read; if it's taller than it is wide, the middle column.
+*/
+pub fn load_strip<P: AsRef<Path>>(fname: P) -> Result<Vec<RGB>, String> {
     let fname = fname.as_ref();
-    let mut f = match File::open(fname) {
+    let f = match File::open(fname) {
         Ok(f) => f,
         Err(e) => {
             let estr = format!("Error opening file {}: {}", fname.display(), &e);
             return Err(estr);
         }
     };
-
-    match try_load_toml(&mut f) {
-        LoadResult::Success(ips) => {
-            return Ok((ips.dimensions, ips.color_spec, ips.iterator));
+    let mut dec = png::Decoder::new(f);
+    dec.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut rdr = match dec.read_info() {
+        Ok(r) => r,
+        Err(e) => {
+            let estr = format!("Error reading {}: {}", fname.display(), &e);
+            return Err(estr);
         }
-        LoadResult::GiveUp(e) => {
-            return Err(e);
+    };
+    let mut buff: Vec<u8> = vec![0; rdr.output_buffer_size()];
+    let frame = match rdr.next_frame(&mut buff) {
+        Ok(frame) => frame,
+        Err(e) => {
+            let estr = format!("Error decoding image data from {}: {}", fname.display(), &e);
+            return Err(estr);
         }
-        LoadResult::TryOtherType => { /* continue trying other type! */ }
-    }
-
-    if let Err(e) = f.seek(std::io::SeekFrom::Start(0)) {
-        return Err(e.to_string());
-    }
+    };
 
-    match try_load_png(&mut f) {
-        LoadResult::Success(ips) => Ok((ips.dimensions, ips.color_spec, ips.iterator)),
-        LoadResult::GiveUp(e) => Err(e),
-        LoadResult::TryOtherType => Err("Could not load from PNG for some reason.".to_string()),
+    let (w, h) = (frame.width as usize, frame.height as usize);
+    let samples = frame.color_type.samples();
+    let pixel = |x: usize, y: usize| {
+        let n = y * frame.line_size + x * samples;
+        match frame.color_type {
+            png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => {
+                let v = buff[n] as f32;
+                RGB::new(v, v, v)
+            }
+            _ => RGB::new(buff[n] as f32, buff[n + 1] as f32, buff[n + 2] as f32),
+        }
+    };
+    let len = w.max(h);
+    let n = len.min(MAX_STRIP_LENGTH);
+    let colors: Vec<RGB> = (0..n)
+        .map(|i| {
+            let i = i * len / n;
+            if w >= h {
+                pixel(i, h / 2)
+            } else {
+                pixel(w / 2, i)
+            }
+        })
+        .collect();
+    if colors.is_empty() {
+        return Err(format!("{} has no pixels in it.", fname.display()));
     }
+    Ok(colors)
 }
 
 //~ pub fn load_from_metadata<P: AsRef<Path>>(fname: P)
@@ -355,3 +2797,733 @@ pub fn load<P: AsRef<Path>>(fname: P) -> Result<(ImageDims, ColorSpec, IterType)
 
 //~ Ok((ips.dimensions, ips.color_spec, ips.iterator))
 //~ }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn params() -> (ImageDims, ColorSpec, IterType) {
+        let dims = ImageDims {
+            xpix: 64,
+            ypix: 48,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        let spec = ColorSpec::new(vec![Gradient::default()], RGB::BLACK);
+        (dims, spec, IterType::Mandlebrot)
+    }
+
+    #[test]
+    fn round_trip_bytes() {
+        let (dims, spec, iter) = params();
+        let toml_text = ImageParameters::toml(&dims, &spec, &iter).unwrap();
+        let json_text = ImageParameters::json(&dims, &spec, &iter).unwrap();
+        let (d, _, i) = load_bytes(toml_text.as_bytes(), ParamFormat::Toml).unwrap();
+        assert_eq!((d, i), (dims, iter.clone()));
+        let (d, _, i) = load_bytes(json_text.as_bytes(), ParamFormat::Json).unwrap();
+        assert_eq!((d, i), (dims, iter));
+    }
+
+    #[test]
+    fn format_versions() {
+        // As the first versions wrote them: no version, sampling, or
+        // anything but gradients in the color spec, and (edited by hand)
+        // a length that doesn't match the gradients.
+        let first = "[iterator]\ntype = \"Polynomial\"\n\
+                     coefs = [[-0.8, 0.156], [0.0, 0.0], [1.0, 0.0]]\n\n\
+                     [dimensions]\nxpix = 600\nypix = 400\nx = -1.5\ny = 1.0\nwidth = 3.0\n\n\
+                     [color_spec]\nlength = 100\ndefault = [0.0, 0.0, 0.0]\n\n\
+                     [[color_spec.gradients]]\nsteps = 256\n\
+                     start = [0.0, 0.0, 0.0]\nend = [255.0, 255.0, 255.0]\n";
+        let (dims, spec, iter) = load_bytes(first.as_bytes(), ParamFormat::Toml).unwrap();
+        assert_eq!((dims.xpix, dims.oversample, dims.adaptive), (600, 1, false));
+        assert_eq!(spec.len(), 256);
+        assert_eq!(spec, ColorSpec::new(vec![Gradient::default()], RGB::BLACK));
+        assert!(matches!(iter, IterType::Polynomial { coefs } if coefs.len() == 3));
+
+        // Later, but still unversioned: polar coefficients and layers,
+        // which have lengths of their own.
+        let later = r#"{
+            "iterator": {"type": "Polynomial", "coefs": [{"r": 1, "theta_pi": 0.5}, [0, 0], [1, 0]]},
+            "dimensions": {"xpix": 60, "ypix": 40, "x": -2, "y": 1, "width": 3,
+                           "oversample": 3, "adaptive": true},
+            "color_spec": {"length": 10, "default": [0, 0, 0], "repeats": 2,
+                           "gradients": [{"steps": 40, "start": [0, 0, 0], "end": [9, 9, 9]}],
+                           "layers": [{"opacity": 0.5, "spec": {"length": 0, "default": [1, 1, 1],
+                               "gradients": [{"steps": 7, "start": [0, 0, 0], "end": [1, 1, 1]}]}}]}
+        }"#;
+        let (dims, spec, _) = load_pasted(later).unwrap();
+        assert_eq!((dims.oversample, dims.adaptive), (3, true));
+        assert_eq!((spec.len(), spec.palette_limit()), (40, 80));
+        assert_eq!(spec.layers()[0].spec.len(), 7);
+
+        // The current version writes its version, and reads it back.
+        let (dims, spec, iter) = params();
+        let text = ImageParameters::toml(&dims, &spec, &iter).unwrap();
+        assert!(text.starts_with(&format!("version = {}\n", PARAMS_VERSION)));
+        assert_eq!(
+            load_bytes(text.as_bytes(), ParamFormat::Toml).unwrap(),
+            (dims, spec.clone(), iter)
+        );
+        let mut colors = serde_json::json!({"color_spec": spec});
+        colors["color_spec"]["length"] = Value::from(1);
+        let cps: ColorParameters = from_migrated(colors).unwrap();
+        assert_eq!(cps.color_spec, spec);
+
+        // Current files can still disagree about the length (edited by
+        // hand, or pasted with a typo), and the gradients still win, so
+        // the map can be made whatever the phase and transfer.
+        for (length, extra) in [
+            (300, "phase = 0.5"),
+            (100, "transfer = { type = \"Sqrt\" }"),
+        ] {
+            let text = format!(
+                "version = {}\n{}\n[color_spec]\nlength = {}\ndefault = [0.0, 0.0, 0.0]\n{}\n\n\
+                 [[color_spec.gradients]]\nsteps = 256\n\
+                 start = [0.0, 0.0, 0.0]\nend = [255.0, 255.0, 255.0]\n",
+                PARAMS_VERSION,
+                &first[..first.find("[color_spec]").unwrap()],
+                length,
+                extra
+            );
+            let (_, spec, _) = load_bytes(text.as_bytes(), ParamFormat::Toml).unwrap();
+            assert_eq!(spec.len(), 256);
+            assert_eq!(ColorMap::make(spec).len(), 256);
+        }
+
+        // Files from the future are refused, not misread.
+        let future = text.replacen(
+            &format!("version = {}", PARAMS_VERSION),
+            &format!("version = {}", PARAMS_VERSION + 1),
+            1,
+        );
+        let e = load_pasted(&future).unwrap_err();
+        assert!(e.contains("needs updating"), "{}", e);
+        let e = load_bytes(future.as_bytes(), ParamFormat::Toml).unwrap_err();
+        assert!(e.contains("needs updating"), "{}", e);
+        assert!(load_pasted("version = \"one\"\n").is_err());
+    }
+
+    #[test]
+    fn share_strings() {
+        let (dims, spec, _) = params();
+        let iter = IterType::Polynomial {
+            coefs: vec![
+                Cx {
+                    re: -0.8,
+                    im: 0.156,
+                },
+                Cx { re: 0.0, im: 0.0 },
+                Cx { re: 1.0, im: 0.0 },
+            ],
+        };
+        let text = share_string(&dims, &spec, &iter).unwrap();
+        assert!(text.starts_with("jset:"));
+        assert!(!text.contains(char::is_whitespace));
+        // Shorter than the plain parameters, at least.
+        assert!(text.len() < ImageParameters::json(&dims, &spec, &iter).unwrap().len());
+        let back = (dims, spec, iter);
+        assert_eq!(from_share_string(&text).unwrap(), back);
+        let url = format!("https://example.com/view#{}&from=chat", text);
+        assert_eq!(from_share_string(&url).unwrap(), back);
+        assert_eq!(load_pasted(&format!("  {}\n", text)).unwrap(), back);
+
+        assert!(from_share_string("no prefix").is_err());
+        assert!(from_share_string(&text[..(text.len() / 2)]).is_err());
+        let junk = format!("jset:{}", URL_SAFE_NO_PAD.encode(b"not deflated"));
+        assert!(from_share_string(&junk).is_err());
+    }
+
+    // These used to overflow the stack inside the TOML parser.
+    #[test]
+    fn deeply_nested() {
+        let text = format!("iterator = {}", "[".repeat(100_000));
+        assert!(load_bytes(text.as_bytes(), ParamFormat::Toml).is_err());
+        assert!(load_bytes(text.as_bytes(), ParamFormat::Json).is_err());
+        assert!(load_pasted(&text).is_err());
+    }
+
+    #[test]
+    fn sessions() {
+        let (dims, spec, iter) = params();
+        let named = |name: &str, dims: ImageDims| NamedParams {
+            name: name.to_string(),
+            iterator: iter.clone(),
+            dimensions: dims,
+            color_spec: spec.clone(),
+        };
+        let zoomed = dims.zoom(2.0);
+        let session = Session {
+            current: "zoomed".to_string(),
+            view: 1,
+            views: vec![dims, zoomed],
+            params: vec![named("whole", dims), named("zoomed", zoomed)],
+        };
+        assert_eq!(session.current_index(), Some(1));
+
+        let dir = std::env::temp_dir().join(format!("jset_session_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("session.toml");
+        save_session(&session, &path).unwrap();
+        assert_eq!(load_session(&path), Ok(session.clone()));
+
+        // A session has to have something to show.
+        let mut broken = session.clone();
+        broken.current = "missing".to_string();
+        save_session(&broken, &path).unwrap();
+        assert!(load_session(&path).is_err());
+        let mut broken = session;
+        broken.view = 2;
+        save_session(&broken, &path).unwrap();
+        assert!(load_session(&path).is_err());
+        // And it isn't an ordinary parameter file.
+        save(&dims, &spec, &iter, &path).unwrap();
+        assert!(load_session(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dropped_files() {
+        let dir = std::env::temp_dir().join(format!("jset_drop_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("my params.toml");
+        std::fs::write(&path, "").unwrap();
+        let plain = path.display().to_string();
+
+        assert_eq!(dropped_file(&format!("{}\n", &plain)), Some(plain.clone()));
+        let uri = format!(
+            "file://{}\r\nfile:///elsewhere.toml",
+            plain.replace(' ', "%20")
+        );
+        assert_eq!(dropped_file(&uri), Some(plain));
+        assert_eq!(dropped_file("some text"), None);
+        assert_eq!(dropped_file(""), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Files a bit bigger than the old 16 KiB limit used to fall through to
+    // the PNG loader and fail confusingly.
+    #[test]
+    fn large_text() {
+        let (dims, spec, iter) = params();
+        let mut text = ImageParameters::toml(&dims, &spec, &iter).unwrap();
+        while text.len() <= 64 * 1024 {
+            text.push_str("# padding padding padding padding\n");
+        }
+        assert!(load_bytes(text.as_bytes(), ParamFormat::Toml).is_ok());
+    }
+
+    #[test]
+    fn oversized_text() {
+        let (dims, spec, iter) = params();
+        let mut text = ImageParameters::toml(&dims, &spec, &iter).unwrap();
+        while text.len() <= READ_LIMIT {
+            text.push_str("# padding padding padding padding\n");
+        }
+        let e = load_bytes(text.as_bytes(), ParamFormat::Toml).unwrap_err();
+        assert!(e.contains("too large"));
+    }
+
+    #[test]
+    fn truncated_png() {
+        let signature = [0x89u8, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+        for n in 0..signature.len() {
+            assert!(load_bytes(&signature[..n], ParamFormat::Toml).is_err());
+        }
+        let mut junk = signature.to_vec();
+        junk.extend_from_slice(&[0xff; 64]);
+        assert!(load_bytes(&junk, ParamFormat::Toml).is_err());
+    }
+
+    #[test]
+    fn gimp_palettes() {
+        let text = "GIMP Palette\nName: Test\nColumns: 4\n# a comment\n\
+                    255   0   0\tRed\n  0 128 255 Untitled\n\n 12 34 56\n";
+        let colors = parse_gpl(text).unwrap();
+        assert_eq!(
+            colors,
+            vec![
+                RGB::new(255.0, 0.0, 0.0),
+                RGB::new(0.0, 128.0, 255.0),
+                RGB::new(12.0, 34.0, 56.0),
+            ]
+        );
+
+        assert!(parse_gpl("JASC-PAL\n0100\n").is_err());
+        assert!(parse_gpl("GIMP Palette\nName: Empty\n").is_err());
+        let e = parse_gpl("GIMP Palette\n1 2 3\n4 five 6\n").unwrap_err();
+        assert!(e.starts_with("Line 3"), "{}", e);
+        assert!(parse_gpl("GIMP Palette\n1 2 300\n").is_err());
+    }
+
+    #[test]
+    fn gradient_files() {
+        let ugr = "Blues {\ngradient:\n  title=\"Blues and more\" smooth=no\n\
+                   \x20 index=100 color=16711680\n  index=0 color=255\n\
+                   \x20 index=300 color=65280\nopacity:\n  smooth=no index=0 opacity=255\n}\n\
+                   Second {\ngradient:\n  index=0 color=0\n}\n";
+        let spec = parse_ugr(ugr).unwrap();
+        let (red, green, blue) = (
+            RGB::new(255.0, 0.0, 0.0),
+            RGB::new(0.0, 255.0, 0.0),
+            RGB::new(0.0, 0.0, 255.0),
+        );
+        let ends: Vec<(usize, RGB, RGB)> = spec
+            .clone()
+            .gradients()
+            .iter()
+            .map(|g| (g.steps, g.start, g.end))
+            .collect();
+        assert_eq!(
+            ends,
+            vec![(100, red, blue), (200, blue, green), (100, green, red)]
+        );
+        assert_eq!(spec.len(), UGR_POSITIONS);
+        assert!(parse_ugr("Nothing {\n}\n").is_err());
+        assert!(parse_ugr("X {\ngradient:\n  index=3\n}\n").is_err());
+
+        let cpt = "# a comment\n# COLOR_MODEL = RGB\n\
+                   0 255 0 0 1 0 255 0\n1 0/255/0 3 0-0-255 ; label\n\
+                   B 0 0 0\nF 255 255 255\nN 128 128 128\n";
+        let spec = parse_cpt(cpt).unwrap();
+        let ends: Vec<(usize, RGB, RGB)> = spec
+            .clone()
+            .gradients()
+            .iter()
+            .map(|g| (g.steps, g.start, g.end))
+            .collect();
+        assert_eq!(ends, vec![(85, red, green), (171, green, blue)]);
+        assert_eq!(spec.default(), RGB::WHITE);
+
+        let spec = parse_cpt("# COLOR_MODEL = +HSV\n-1 0 1 1 1 120 1 1\n").unwrap();
+        let g = spec.clone().gradients()[0];
+        assert_eq!((g.start, g.end, g.space), (red, green, Interpolation::Hsv));
+        assert_eq!(spec.default(), RGB::BLACK);
+        assert!(parse_cpt("# nothing\n").is_err());
+        assert!(parse_cpt("0 1 2 3 1 red\n").is_err());
+
+        assert_eq!(GradientFormat::of("a/b.UGR"), Some(GradientFormat::Ugr));
+        assert_eq!(GradientFormat::of("c.cpt"), Some(GradientFormat::Cpt));
+        assert_eq!(GradientFormat::of("d.toml"), None);
+    }
+
+    #[test]
+    fn location_files() {
+        let kfr = "Re: -0.743643887037151\r\nIm: 0.13182590420533\r\n\
+                   Zoom: 4E2\r\nIterations: 2500\r\nIterDiv: 1.000000\r\n\
+                   SmoothMethod: 0\r\nPower: 2\r\nFractalType: 0\r\n";
+        let loc = parse_kfr(kfr).unwrap();
+        assert_eq!(loc.center, (-0.743643887037151, 0.13182590420533));
+        assert_eq!((loc.width, loc.height), (0.01, 0.01));
+        assert_eq!(loc.itertype, IterType::Mandlebrot);
+        assert_eq!(loc.iterations, Some(2500));
+        let dims = ImageDims {
+            xpix: 300,
+            ypix: 200,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        let view = loc.view(&dims);
+        assert_eq!((view.xpix, view.ypix), (300, 200));
+        assert!((view.height() - 0.01).abs() < 1.0e-12);
+        assert!(parse_kfr("Re: 0\nIm: 0\nFractalType: 1\n").is_err());
+        assert!(parse_kfr("Re: 0\nIm: 0\nZoom: 1E30\n").is_err());
+        assert!(parse_kfr("Zoom: 1\n").is_err());
+
+        let par = "; from a collection\nFirst { ; a Julia set\n  reset=2004 type=julia\n\
+                   \x20 center-mag=0/0/0.5 params=-0.8/0.156 float=y \\\n  maxiter=500\n  }\n\
+                   Second {\n  type=mandel\n  }\n";
+        let loc = parse_par(par).unwrap();
+        assert_eq!(loc.center, (0.0, 0.0));
+        assert_eq!(loc.height, 4.0);
+        assert_eq!(
+            loc.itertype,
+            IterType::Polynomial {
+                coefs: vec![
+                    Cx {
+                        re: -0.8,
+                        im: 0.156
+                    },
+                    Cx { re: 0.0, im: 0.0 },
+                    Cx { re: 1.0, im: 0.0 }
+                ]
+            }
+        );
+        assert_eq!(loc.iterations, Some(500));
+        let loc = parse_par("Z {\n type=mandelfp corners=-1/1/-0.5/0.25\n}\n").unwrap();
+        assert_eq!(loc.center, (0.0, -0.125));
+        assert_eq!((loc.width, loc.height), (2.0, 0.75));
+        assert_eq!((loc.itertype, loc.iterations), (IterType::Mandlebrot, None));
+        assert!(parse_par("Z {\n type=barnsleyj1\n}\n").is_err());
+        assert!(parse_par("type=mandel\n").is_err());
+
+        assert_eq!(LocationFormat::of("a/b.KFR"), Some(LocationFormat::Kfr));
+        assert_eq!(LocationFormat::of("c.par"), Some(LocationFormat::Par));
+        assert_eq!(LocationFormat::of("d.png"), None);
+    }
+
+    #[test]
+    fn palette_strips() {
+        let grad = |steps: usize, start: RGB, end: RGB| Gradient {
+            steps,
+            start,
+            end,
+            space: Interpolation::Rgb,
+        };
+        let (red, blue) = (RGB::new(255.0, 0.0, 0.0), RGB::new(0.0, 0.0, 255.0));
+        let spec = ColorSpec::new(
+            vec![
+                grad(100, RGB::BLACK, red),
+                grad(1, RGB::WHITE, RGB::WHITE),
+                grad(50, blue, RGB::WHITE),
+                grad(20, red, red),
+            ],
+            RGB::BLACK,
+        );
+        let cmap = spec.to_map();
+        let fname = std::env::temp_dir().join("jset_strip_test.png");
+        save_strip(&cmap, &fname).unwrap();
+        let pixels = load_strip(&fname).unwrap();
+        assert_eq!(pixels.len(), cmap.len());
+
+        let gradients = strip_gradients(&pixels);
+        assert!(gradients.len() <= 6, "{:?}", gradients);
+        let imported = ColorSpec::new(gradients, RGB::BLACK).to_map();
+        assert_eq!(imported.len(), cmap.len());
+        for (a, b) in cmap.colors().iter().zip(imported.colors().iter()) {
+            let d = (a.r() - b.r()).abs() + (a.g() - b.g()).abs() + (a.b() - b.b()).abs();
+            assert!(d <= 3.0 * STRIP_TOLERANCE, "{:?} vs {:?}", a, b);
+        }
+
+        // A strip standing on end, from somewhere else.
+        let data: Vec<u8> = (0..3 * 2 * 40).map(|n| (n / 6) as u8).collect();
+        save_without_metadata(&fname, 2, 40, &data).unwrap();
+        let pixels = load_strip(&fname).unwrap();
+        assert_eq!(pixels.len(), 40);
+        assert_eq!(pixels[39], RGB::new(39.0, 39.0, 39.0));
+        assert_eq!(strip_gradients(&pixels).len(), 1);
+        assert!(strip_gradients(&[]).is_empty());
+    }
+
+    #[test]
+    fn locked_params() {
+        let dims = ImageDims {
+            xpix: 30,
+            ypix: 20,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        let cspec = ColorSpec::new(vec![Gradient::default()], RGB::BLACK);
+        let iter = IterType::Mandlebrot;
+
+        let fname = std::env::temp_dir().join("jset_locked_test.toml");
+        let unlocked = ImageParameters::with_lock(&dims, &cspec, &iter, false);
+        std::fs::write(&fname, toml::to_string(&unlocked).unwrap()).unwrap();
+        assert_eq!(load_checked(&fname).unwrap().3, LockStatus::Unlocked);
+
+        let locked = ImageParameters::with_lock(&dims, &cspec, &iter, true);
+        let text = toml::to_string(&locked).unwrap();
+        assert!(text.contains("[lock]"));
+        std::fs::write(&fname, &text).unwrap();
+        let (ldims, lspec, _, status) = load_checked(&fname).unwrap();
+        assert_eq!(
+            (ldims, lspec, status),
+            (dims, cspec.clone(), LockStatus::Identical)
+        );
+
+        let modified = text.replace("xpix = 30", "xpix = 31");
+        assert_ne!(modified, text);
+        std::fs::write(&fname, &modified).unwrap();
+        assert_eq!(load_checked(&fname).unwrap().3, LockStatus::Modified);
+
+        let old = text.replace(
+            &format!("version = \"{}\"", ENGINE_VERSION),
+            "version = \"0.0.1\"",
+        );
+        assert_ne!(old, text);
+        std::fs::write(&fname, &old).unwrap();
+        let status = load_checked(&fname).unwrap().3;
+        assert_eq!(status, LockStatus::OtherVersion("0.0.1".to_string()));
+        assert!(status.describe().contains("0.0.1"));
+
+        // Periodicity checking goes with the parameters (and only says
+        // anything when it's off).
+        assert!(!text.contains("check_period"));
+        let other = text.replace("xpix = 30", "xpix = 30\ncheck_period = false");
+        assert_ne!(other, text);
+        std::fs::write(&fname, &other).unwrap();
+        let (ldims, _, _, status) = load_checked(&fname).unwrap();
+        assert!(!ldims.check_period);
+        assert_eq!(status, LockStatus::Modified);
+        std::fs::remove_file(&fname).unwrap();
+    }
+
+    #[test]
+    fn color_files() {
+        let dir = std::env::temp_dir();
+        let mut cspec = ColorSpec::new(
+            vec![
+                Gradient::default(),
+                Gradient {
+                    steps: 10,
+                    start: RGB::WHITE,
+                    end: RGB::new(255.0, 0.0, 0.0),
+                    space: Interpolation::Lch,
+                },
+            ],
+            RGB::new(0.0, 0.0, 64.0),
+        );
+        cspec.set_transfer(Transfer::Power { exponent: 0.25 });
+        cspec.set_repeats(3);
+        cspec.set_phase(0.5);
+        for ext in ["toml", "json"].iter() {
+            let fname = dir.join(format!("jset_colors_test.{}", ext));
+            save_colors(&cspec, &fname).unwrap();
+            assert_eq!(load_colors(&fname).unwrap(), cspec);
+            // It's not a whole set of image parameters.
+            assert!(load(&fname).is_err());
+            std::fs::remove_file(&fname).unwrap();
+        }
+
+        // The colors can come out of a full parameter file, too.
+        let dims = ImageDims {
+            xpix: 30,
+            ypix: 20,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        let fname = dir.join("jset_colors_test_full.toml");
+        save(&dims, &cspec, &IterType::Mandlebrot, &fname).unwrap();
+        assert_eq!(load_colors(&fname).unwrap(), cspec);
+        std::fs::remove_file(&fname).unwrap();
+    }
+
+    #[test]
+    fn color_profiles() {
+        let icc = display_p3_icc();
+        assert_eq!(
+            u32::from_be_bytes([icc[0], icc[1], icc[2], icc[3]]) as usize,
+            icc.len()
+        );
+        assert_eq!(&icc[36..40], b"acsp");
+        let n_tags = u32::from_be_bytes([icc[128], icc[129], icc[130], icc[131]]) as usize;
+        for entry in icc[132..(132 + 12 * n_tags)].chunks(12) {
+            let word =
+                |i: usize| u32::from_be_bytes([entry[i], entry[i + 1], entry[i + 2], entry[i + 3]]);
+            let (offset, size) = (word(4) as usize, word(8) as usize);
+            assert_eq!(offset % 4, 0);
+            assert!(offset + size <= icc.len());
+        }
+
+        let dir = std::env::temp_dir();
+        let (srgb, p3) = (dir.join("jset_srgb_test.png"), dir.join("jset_p3_test.png"));
+        let data: Vec<u8> = (0..(3 * 40 * 30)).map(|n| (n % 251) as u8).collect();
+        let info = |fname: &Path| {
+            let rdr = png::Decoder::new(File::open(fname).unwrap())
+                .read_info()
+                .unwrap();
+            let nfo = rdr.info();
+            (
+                nfo.srgb.is_some(),
+                nfo.icc_profile.as_ref().map(|p| p.to_vec()),
+            )
+        };
+
+        let write = |fname: &Path, profile| {
+            let pixels = Pixels::Eight(&data);
+            write_png(fname, (40, 30), pixels, None, PngCompression::Fast, profile).unwrap()
+        };
+        write(&srgb, ColorProfile::Srgb);
+        write(&p3, ColorProfile::DisplayP3);
+        assert_eq!(info(&srgb), (true, None));
+        assert_eq!(info(&p3), (false, Some(icc.clone())));
+        // The pixels are converted, so they look the same: sRGB's red is
+        // inside P3's, and greys stay grey.
+        let mut rdr = png::Decoder::new(File::open(&p3).unwrap());
+        rdr.set_transformations(png::Transformations::EXPAND);
+        let mut rdr = rdr.read_info().unwrap();
+        let mut pixels = vec![0u8; rdr.output_buffer_size()];
+        rdr.next_frame(&mut pixels).unwrap();
+        assert_eq!(pixels, to_p3(&data, 3, 255.0));
+        assert_ne!(pixels, data);
+        assert_eq!(to_p3(&[255u8, 0, 0, 128], 4, 255.0), vec![234, 51, 35, 128]);
+        assert_eq!(
+            to_p3(&[0u16, 0, 0, 9000, 9000, 9000], 3, 65535.0)[3..],
+            [9000; 3]
+        );
+
+        // Stripping metadata shouldn't strip the color space.
+        strip_metadata(&p3, &p3).unwrap();
+        assert_eq!(info(&p3), (false, Some(icc)));
+        std::fs::remove_file(&srgb).unwrap();
+        std::fs::remove_file(&p3).unwrap();
+        assert_eq!(
+            ColorProfile::from_name(" Display-P3"),
+            Some(ColorProfile::DisplayP3)
+        );
+    }
+
+    #[test]
+    fn deep_color() {
+        let fname = std::env::temp_dir().join("jset_deep_color_test.png");
+        let decode = |fname: &Path| {
+            let mut rdr = png::Decoder::new(File::open(fname).unwrap())
+                .read_info()
+                .unwrap();
+            let mut buff = vec![0u8; rdr.output_buffer_size()];
+            let frame = rdr.next_frame(&mut buff).unwrap();
+            (frame.color_type, frame.bit_depth, buff)
+        };
+
+        let data: Vec<u16> = (0..(3 * 8 * 5)).map(|n| (n * 511) as u16).collect();
+        save16_without_metadata(&fname, 8, 5, &data).unwrap();
+        let (color, depth, buff) = decode(&fname);
+        assert_eq!(
+            (color, depth),
+            (png::ColorType::Rgb, png::BitDepth::Sixteen)
+        );
+        assert_eq!(buff, be_bytes(&data));
+
+        let dims = ImageDims {
+            xpix: 30,
+            ypix: 20,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 2,
+            adaptive: false,
+            check_period: true,
+        };
+        let mut img = FImage32::new(dims, RGB::new(1.5, 128.0, 255.0));
+        assert_eq!(img.to_rgb16(1).2[..3], [386, 32896, 65535]);
+        let spec = ColorSpec::new(vec![Gradient::default()], RGB::BLACK);
+        let mut clear = spec.clone();
+        clear.set_transparent(true);
+        let imap = IterMap::new(dims, IterType::Mandlebrot, spec.limit());
+        img = imap.color(&ColorMap::make(clear));
+        assert!(img.is_transparent());
+        let (w, h, data) = img.to_rgba16(1);
+        let params = Some((&dims, &spec, &IterType::Mandlebrot));
+        save16_with_metadata(&fname, w, h, &data, params, PngCompression::Best).unwrap();
+        let (color, depth, _) = decode(&fname);
+        assert_eq!(
+            (color, depth),
+            (png::ColorType::Rgba, png::BitDepth::Sixteen)
+        );
+        assert_eq!(load(&fname).unwrap().1, spec);
+
+        // Eight-bit RGBA gets written as it is.
+        let (w, h, data) = img.to_rgba8(1);
+        save_without_metadata(&fname, w, h, &data).unwrap();
+        let (color, depth, buff) = decode(&fname);
+        assert_eq!((color, depth), (png::ColorType::Rgba, png::BitDepth::Eight));
+        assert_eq!(buff, data);
+        std::fs::remove_file(&fname).unwrap();
+    }
+
+    #[test]
+    fn other_formats() {
+        assert_eq!(
+            ImageFormat::of("a.JPEG"),
+            Some(ImageFormat::Jpeg { quality: 90 })
+        );
+        assert_eq!(ImageFormat::of("a.png"), Some(ImageFormat::Png));
+        assert_eq!(ImageFormat::of("a.tiff"), None);
+        assert_eq!(ImageFormat::Webp { quality: None }.extension(), ".webp");
+
+        let dir = std::env::temp_dir();
+        let rgb: Vec<u8> = (0..(3 * 16 * 12)).map(|n| (n % 256) as u8).collect();
+        let rgba: Vec<u8> = (0..(4 * 16 * 12)).map(|n| (n % 256) as u8).collect();
+        for (name, data, format) in [
+            ("jpg", &rgb, ImageFormat::Jpeg { quality: 50 }),
+            ("jpg", &rgba, ImageFormat::Jpeg { quality: 50 }),
+            ("webp", &rgb, ImageFormat::Webp { quality: Some(50) }),
+            ("webp", &rgba, ImageFormat::Webp { quality: None }),
+        ] {
+            let fname = dir.join(format!("jset_other_formats_test.{}", name));
+            save_as(&fname, 16, 12, data, format).unwrap();
+            let bytes = std::fs::read(&fname).unwrap();
+            match format {
+                ImageFormat::Jpeg { .. } => assert_eq!(bytes[..2], [0xFF, 0xD8]),
+                _ => assert!(bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP"),
+            }
+            std::fs::remove_file(&fname).unwrap();
+        }
+        let big = vec![0u8; 3 * 20000];
+        assert!(save_as(
+            dir.join("jset_too_big.webp"),
+            20000,
+            1,
+            &big,
+            ImageFormat::Webp { quality: None }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn animations() {
+        assert_eq!(AnimationFormat::of("a.GIF"), Some(AnimationFormat::Gif));
+        assert_eq!(AnimationFormat::of("a.apng"), Some(AnimationFormat::Apng));
+        assert_eq!(AnimationFormat::of("a.png"), None);
+
+        let dir = std::env::temp_dir();
+        let (gif_file, apng_file) = (
+            dir.join("jset_anim_test.gif"),
+            dir.join("jset_anim_test.apng"),
+        );
+        let frame = |n: u8| -> Vec<u8> { (0..(3 * 20 * 10)).map(|k| (k as u8) ^ n).collect() };
+        for (fname, format) in [
+            (&gif_file, AnimationFormat::Gif),
+            (&apng_file, AnimationFormat::Apng),
+        ] {
+            // Stopped after two frames of three.
+            let mut w = AnimationWriter::create(fname, format, (20, 10), 3, 30.0).unwrap();
+            w.write_frame(&frame(0)).unwrap();
+            assert!(w.write_frame(&[0, 0, 0]).is_err());
+            w.write_frame(&frame(1)).unwrap();
+            w.finish().unwrap();
+        }
+
+        let mut gif_opts = gif::DecodeOptions::new();
+        gif_opts.set_color_output(gif::ColorOutput::RGBA);
+        let mut dec = gif_opts.read_info(File::open(&gif_file).unwrap()).unwrap();
+        let mut delays = Vec::new();
+        while let Some(f) = dec.read_next_frame().unwrap() {
+            delays.push(f.delay);
+        }
+        assert_eq!(delays, vec![3, 3]);
+
+        let mut rdr = png::Decoder::new(File::open(&apng_file).unwrap())
+            .read_info()
+            .unwrap();
+        assert_eq!(rdr.info().animation_control.unwrap().num_frames, 3);
+        let mut buf = vec![0; rdr.output_buffer_size()];
+        rdr.next_frame(&mut buf).unwrap();
+        assert_eq!(&buf[..600], &frame(0)[..]);
+        for _ in 0..2 {
+            rdr.next_frame(&mut buf).unwrap();
+        }
+        assert_eq!(rdr.info().frame_control.unwrap().width, 1);
+
+        std::fs::remove_file(&gif_file).unwrap();
+        std::fs::remove_file(&apng_file).unwrap();
+    }
+}
@@ -0,0 +1,452 @@
+/*!
+Scripting sweeps through parameter space with [Rhai](https://rhai.rs), for
+rendering grids of images (like a polynomial's coefficient varied over a
+lattice) without setting each one up by hand.
+
+A script starts with a set of image parameters (whatever the application
+is showing, or one loaded from a file), changes them with these functions,
+and renders them whenever it likes:
+
+  * `load(path)`: replace everything with the parameters in a file
+  * `set_dims(x, y, width)`: the upper left corner of the view on the
+    complex plane, and its width (like in parameter files)
+  * `set_center(re, im)`: move the view, keeping its width
+  * `zoom(factor)`: zoom in (or out, with a factor less than 1)
+  * `set_size(width, height)`: the image's size in pixels
+  * `set_oversample(n)`: samples per pixel in each direction, up to 4
+    (counting those, an image can't be more than 16384 samples across
+    or down)
+  * `set_iterator(kind)`, `set_iterator(kind, coefficients)`: the kind is
+    "mandlebrot", "pseudo-mandlebrot" (whose two coefficients are `a` and
+    `b`), "polynomial" (whose coefficients start with the constant term),
+    "tricorn", or one of "exp", "sin", and "cos" (for c·exp(z) and so
+    on); each coefficient is a number or an `[re, im]` pair
+  * `set_coefficient(n, re, im)`: change just one coefficient
+  * `set_palette(path)`: the colors from a parameter or gradient file
+  * `set_phase(phase)`: the color map's phase (from 0 to 1)
+  * `render_to(path)`: render the image as a PNG, with its parameters
+    embedded (and run the post-save command, if there is one)
+
+Relative paths are taken from the folder the script is in. For example:
+
+```text
+for i in 0..5 {
+    for j in 0..5 {
+        set_coefficient(0, -0.8 + 0.1 * i, 0.1 * j);
+        render_to(`grid/julia_${i}_${j}.png`);
+    }
+}
+```
+*/
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use rhai::{Array, Dynamic, Engine, EvalAltResult};
+
+use crate::cx::Cx;
+use crate::export;
+use crate::headless;
+use crate::hook;
+use crate::image::*;
+use crate::rw;
+
+/** Everything about an image a script can change. */
+pub type Params = (ImageDims, ColorSpec, IterType);
+
+type FnResult<T> = Result<T, Box<EvalAltResult>>;
+// Told of each image a script renders.
+type RenderHook = Box<dyn FnMut(&Path, usize)>;
+
+// What a script is working on.
+struct State {
+    params: Params,
+    dir: PathBuf,
+    rendered: usize,
+    on_render: RenderHook,
+}
+
+impl State {
+    fn path(&self, path: &str) -> PathBuf {
+        self.dir.join(path)
+    }
+}
+
+// A number, whether the script wrote it as an integer or not.
+fn float(d: &Dynamic) -> FnResult<f64> {
+    if let Ok(x) = d.as_float() {
+        return Ok(x);
+    }
+    match d.as_int() {
+        Ok(n) => Ok(n as f64),
+        Err(_) => Err(format!("expected a number, not {}", d.type_name()).into()),
+    }
+}
+
+// A whole number of at least 1.
+fn count(d: &Dynamic, what: &str) -> FnResult<usize> {
+    match d.as_int() {
+        Ok(n) if n >= 1 => Ok(n as usize),
+        _ => Err(format!("{} must be a whole number of at least 1", what).into()),
+    }
+}
+
+// A coefficient: a real number, or an `[re, im]` pair.
+fn coefficient(d: &Dynamic) -> FnResult<Cx> {
+    if let Some(pair) = d.read_lock::<Array>() {
+        return match pair.as_slice() {
+            [re, im] => Ok(Cx::rect(float(re)?, float(im)?)),
+            _ => Err("a coefficient must be a number or an [re, im] pair".into()),
+        };
+    }
+    Ok(Cx::rect(float(d)?, 0.0))
+}
+
+// The iterator of kind `kind` (ignoring case, spaces, dashes, and
+// underscores) with the coefficients `coefs`.
+fn iterator(kind: &str, coefs: Vec<Cx>) -> FnResult<IterType> {
+    let kind: String = kind
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '_'))
+        .collect::<String>()
+        .to_ascii_lowercase();
+    match (kind.as_str(), coefs.as_slice()) {
+        ("mandlebrot", []) => Ok(IterType::Mandlebrot),
+        ("pseudomandlebrot", [a, b]) => Ok(IterType::PseudoMandlebrot { a: *a, b: *b }),
+        ("polynomial", cs) if !cs.is_empty() => Ok(IterType::Polynomial { coefs: cs.to_vec() }),
+        ("tricorn" | "mandelbar", []) => Ok(IterType::Tricorn),
+        ("exp" | "exponential", []) => Ok(IterType::Exponential),
+        ("sin" | "sine", []) => Ok(IterType::Sine),
+        ("cos" | "cosine", []) => Ok(IterType::Cosine),
+        (
+            "mandlebrot" | "tricorn" | "mandelbar" | "exp" | "exponential" | "sin" | "sine" | "cos"
+            | "cosine",
+            _,
+        ) => Err(format!("the {} iterator has no coefficients", kind).into()),
+        ("pseudomandlebrot", _) => {
+            Err("the pseudo-Mandlebrot iterator takes two coefficients, a and b".into())
+        }
+        ("polynomial", _) => Err("a polynomial needs at least one coefficient".into()),
+        _ => Err(format!(
+            "{:?} isn't mandlebrot, pseudo-mandlebrot, polynomial, tricorn, exp, sin, or cos",
+            kind
+        )
+        .into()),
+    }
+}
+
+/** Runs scripts, keeping the image parameters they leave behind. */
+pub struct Runner {
+    engine: Engine,
+    state: Rc<RefCell<State>>,
+}
+
+impl Runner {
+    /**
+    Get ready to run scripts starting from the image parameters `start`,
+    taking relative paths from the folder `dir`.
+    */
+    pub fn new<P: AsRef<Path>>(start: Params, dir: P) -> Runner {
+        let state = Rc::new(RefCell::new(State {
+            params: start,
+            dir: dir.as_ref().to_path_buf(),
+            rendered: 0,
+            on_render: Box::new(|_, _| {}),
+        }));
+        let mut engine = Engine::new();
+
+        let st = state.clone();
+        engine.register_fn("load", move |path: &str| -> FnResult<()> {
+            let mut st = st.borrow_mut();
+            st.params = rw::load(st.path(path))?;
+            Ok(())
+        });
+        let st = state.clone();
+        engine.register_fn(
+            "set_dims",
+            move |x: Dynamic, y: Dynamic, width: Dynamic| -> FnResult<()> {
+                let (x, y, width) = (float(&x)?, float(&y)?, float(&width)?);
+                if !(width > 0.0 && width.is_finite() && x.is_finite() && y.is_finite()) {
+                    return Err("the width must be positive (and everything finite)".into());
+                }
+                let dims = &mut st.borrow_mut().params.0;
+                dims.x = x;
+                dims.y = y;
+                dims.width = width;
+                Ok(())
+            },
+        );
+        let st = state.clone();
+        engine.register_fn(
+            "set_center",
+            move |re: Dynamic, im: Dynamic| -> FnResult<()> {
+                let (re, im) = (float(&re)?, float(&im)?);
+                let dims = &mut st.borrow_mut().params.0;
+                *dims = dims.framing(re, im, dims.width, 0.0);
+                Ok(())
+            },
+        );
+        let st = state.clone();
+        engine.register_fn("zoom", move |factor: Dynamic| -> FnResult<()> {
+            let factor = float(&factor)?;
+            if !(factor > 0.0 && factor.is_finite()) {
+                return Err("the zoom factor must be positive".into());
+            }
+            let dims = &mut st.borrow_mut().params.0;
+            *dims = dims.zoom(factor);
+            Ok(())
+        });
+        let st = state.clone();
+        engine.register_fn(
+            "set_size",
+            move |xpix: Dynamic, ypix: Dynamic| -> FnResult<()> {
+                let (xpix, ypix) = (count(&xpix, "the width")?, count(&ypix, "the height")?);
+                let dims = &mut st.borrow_mut().params.0;
+                let resized = dims.resize(xpix, ypix);
+                export::check_size(&resized)?;
+                *dims = resized;
+                Ok(())
+            },
+        );
+        let st = state.clone();
+        engine.register_fn("set_oversample", move |n: Dynamic| -> FnResult<()> {
+            let n = count(&n, "the oversampling")?;
+            if n > MAX_OVERSAMPLE {
+                return Err(
+                    format!("the oversampling can't be more than {}", MAX_OVERSAMPLE).into(),
+                );
+            }
+            let dims = &mut st.borrow_mut().params.0;
+            let oversampled = ImageDims {
+                oversample: n,
+                ..*dims
+            };
+            export::check_size(&oversampled)?;
+            *dims = oversampled;
+            Ok(())
+        });
+        let st = state.clone();
+        engine.register_fn("set_iterator", move |kind: &str| -> FnResult<()> {
+            st.borrow_mut().params.2 = iterator(kind, Vec::new())?;
+            Ok(())
+        });
+        let st = state.clone();
+        engine.register_fn(
+            "set_iterator",
+            move |kind: &str, coefs: Array| -> FnResult<()> {
+                let coefs = coefs
+                    .iter()
+                    .map(coefficient)
+                    .collect::<FnResult<Vec<Cx>>>()?;
+                st.borrow_mut().params.2 = iterator(kind, coefs)?;
+                Ok(())
+            },
+        );
+        let st = state.clone();
+        engine.register_fn(
+            "set_coefficient",
+            move |n: Dynamic, re: Dynamic, im: Dynamic| -> FnResult<()> {
+                let c = Cx::rect(float(&re)?, float(&im)?);
+                let n = n
+                    .as_int()
+                    .map_err(|_| "the coefficient number must be a whole number")?;
+                let itype = &mut st.borrow_mut().params.2;
+                let slot = match (itype, n) {
+                    (IterType::PseudoMandlebrot { a, .. }, 0) => a,
+                    (IterType::PseudoMandlebrot { b, .. }, 1) => b,
+                    (IterType::Polynomial { coefs }, n) if n >= 0 && (n as usize) < coefs.len() => {
+                        &mut coefs[n as usize]
+                    }
+                    _ => {
+                        return Err(format!("the iterator has no coefficient {}", n).into());
+                    }
+                };
+                *slot = c;
+                Ok(())
+            },
+        );
+        let st = state.clone();
+        engine.register_fn("set_palette", move |path: &str| -> FnResult<()> {
+            let mut st = st.borrow_mut();
+            let path = st.path(path);
+            let phase = st.params.1.phase();
+            let mut cspec = if rw::GradientFormat::of(&path).is_some() {
+                rw::load_gradient(&path)?
+            } else {
+                rw::load_colors(&path)?
+            };
+            // The phase is usually being swept separately.
+            cspec.set_phase(phase);
+            st.params.1 = cspec;
+            Ok(())
+        });
+        let st = state.clone();
+        engine.register_fn("set_phase", move |phase: Dynamic| -> FnResult<()> {
+            let phase = float(&phase)?;
+            st.borrow_mut().params.1.set_phase(phase);
+            Ok(())
+        });
+        let st = state.clone();
+        engine.register_fn("render_to", move |path: &str| -> FnResult<()> {
+            let mut st = st.borrow_mut();
+            let path = st.path(path);
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)
+                    .map_err(|e| format!("Error creating folder {}: {}", dir.display(), &e))?;
+            }
+            let (dims, cspec, itype) = st.params.clone();
+            headless::export(&path, dims, cspec, itype, true, rw::BitDepth::Eight)?;
+            hook::after_save(&path, dims.xpix, dims.ypix)?;
+            st.rendered += 1;
+            let n = st.rendered;
+            (st.on_render)(&path, n);
+            Ok(())
+        });
+
+        Runner { engine, state }
+    }
+
+    /**
+    Call `f` with the name of each image a script renders, and how many
+    it's rendered so far.
+    */
+    pub fn on_render<F: FnMut(&Path, usize) + 'static>(&mut self, f: F) {
+        self.state.borrow_mut().on_render = Box::new(f);
+    }
+
+    /** Send whatever scripts `print()` to `f` (rather than to stdout). */
+    pub fn on_print<F: Fn(&str) + 'static>(&mut self, f: F) {
+        self.engine.on_print(f);
+    }
+
+    /**
+    Stop any script that's running as soon as `stop` gets set (which
+    can't interrupt an image in the middle of being rendered).
+    */
+    pub fn stop_when(&mut self, stop: Arc<AtomicBool>) {
+        self.engine.on_progress(move |_| {
+            if stop.load(Ordering::Relaxed) {
+                Some(Dynamic::UNIT)
+            } else {
+                None
+            }
+        });
+    }
+
+    /**
+    Run the script `source`. Being stopped (see `stop_when()`) isn't an
+    error.
+    */
+    pub fn run(&mut self, source: &str) -> Result<(), String> {
+        match self.engine.run(source) {
+            Ok(()) => Ok(()),
+            Err(e) if matches!(*e, EvalAltResult::ErrorTerminated(..)) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /** Read the script file `fname` and run it. */
+    pub fn run_file<P: AsRef<Path>>(&mut self, fname: P) -> Result<(), String> {
+        let fname = fname.as_ref();
+        let source = fs::read_to_string(fname)
+            .map_err(|e| format!("Error reading {}: {}", fname.display(), &e))?;
+        self.run(&source)
+            .map_err(|e| format!("Error in {}: {}", fname.display(), &e))
+    }
+
+    /** The image parameters as the scripts run so far have left them. */
+    pub fn params(&self) -> Params {
+        self.state.borrow().params.clone()
+    }
+
+    /** How many images the scripts run so far have rendered. */
+    pub fn rendered(&self) -> usize {
+        self.state.borrow().rendered
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scripts() {
+        let dims = ImageDims {
+            xpix: 30,
+            ypix: 20,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        let cspec = ColorSpec::new(vec![Gradient::default()], RGB::BLACK);
+        let dir = std::env::temp_dir().join(format!("jset_script_test_{}", std::process::id()));
+        let mut runner = Runner::new((dims, cspec.clone(), IterType::Mandlebrot), &dir);
+        let seen: Rc<RefCell<Vec<PathBuf>>> = Rc::new(RefCell::new(Vec::new()));
+        runner.on_render({
+            let seen = seen.clone();
+            move |p, _| seen.borrow_mut().push(p.to_path_buf())
+        });
+
+        runner
+            .run(
+                r#"
+                set_iterator("polynomial", [[-0.5, 0], 0, 1]);
+                set_center(0, 0);
+                for i in 0..2 {
+                    set_coefficient(0, -0.5 - 0.25 * i, 0.1);
+                    render_to(`grid/julia_${i}.png`);
+                }
+                set_size(60, 20);
+                set_phase(0.25);
+                "#,
+            )
+            .unwrap();
+        assert_eq!(runner.rendered(), 2);
+        assert_eq!(
+            *seen.borrow(),
+            vec![dir.join("grid/julia_0.png"), dir.join("grid/julia_1.png")]
+        );
+        let (d, _, i) = rw::load(dir.join("grid/julia_1.png")).unwrap();
+        let c = |re: f64, im: f64| Cx::rect(re, im);
+        assert_eq!(
+            i,
+            IterType::Polynomial {
+                coefs: vec![c(-0.75, 0.1), c(0.0, 0.0), c(1.0, 0.0)]
+            }
+        );
+        assert_eq!(d.center(), (0.0, 0.0));
+        let (d, c, _) = runner.params();
+        assert_eq!((d.xpix, d.ypix, c.phase()), (60, 20, 0.25));
+
+        // Mistakes are reported, with where they were.
+        let e = runner.run("set_coefficient(5, 0, 0);").unwrap_err();
+        assert!(e.contains("no coefficient 5"), "{}", e);
+        assert!(runner.run("set_iterator(\"spiral\");").is_err());
+        assert!(runner.run("set_iterator(\"sin\", [1]);").is_err());
+        runner.run("set_iterator(\"Cosine\");").unwrap();
+        assert_eq!(runner.params().2, IterType::Cosine);
+        assert!(runner.run("zoom(-1);").is_err());
+        assert!(runner.run("set_oversample(5);").is_err());
+        assert!(runner.run("set_size(20000, 10);").is_err());
+        runner
+            .run("set_size(4096, 10); set_oversample(4);")
+            .unwrap();
+        assert!(runner.run("set_size(4097, 10);").is_err());
+        assert!(runner.run("set_oversample(1); set_size(4097, 10);").is_ok());
+        assert_eq!(runner.params().0.xpix, 4097);
+
+        // Stopping a runaway script isn't an error.
+        let stop = Arc::new(AtomicBool::new(true));
+        runner.stop_when(stop);
+        runner.run("loop { zoom(1.0); }").unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -5,167 +5,318 @@ some functions to facilitate choosing colors.
 
 use std::cell::{Cell, RefCell};
 use std::rc::Rc;
-use std::sync::mpsc;
+use std::sync::{mpsc, Mutex};
 
 use fltk::{
     app::add_timeout3,
-    button::Button,
-    enums::{Event, Shortcut},
+    button::{Button, CheckButton},
+    enums::{ColorDepth, Event, Shortcut},
     frame::Frame,
-    input::IntInput,
+    image::RgbImage,
+    input::{FloatInput, IntInput},
+    menu::Choice,
     prelude::*,
     valuator::{HorNiceSlider, ValueInput},
     window::DoubleWindow,
 };
 
 use super::*;
+use crate::contrast;
+use crate::curves::Curves;
+use crate::cx::Cx;
 use crate::image::*;
+use crate::presets;
+use crate::rw;
 
-// The following constants all express dimensions of elements of the color
-// picker window created by `pick_color()`.
-const PICKER_LABEL_WIDTH: i32 = 24;
-const PICKER_SLIDER_WIDTH: i32 = 256;
-const PICKER_INPUT_WIDTH: i32 = 48;
-const PICKER_ROW_HEIGHT: i32 = 32;
-const PICKER_OUTPUT_WIDTH: i32 = 4 * PICKER_ROW_HEIGHT;
+// How many of the colors most recently picked the picker offers again.
+const RECENT_COLORS: usize = 12;
 
-const PICKER_ROW_WIDTH: i32 = PICKER_LABEL_WIDTH + PICKER_SLIDER_WIDTH + PICKER_INPUT_WIDTH;
-const PICKER_WINDOW_WIDTH: i32 = PICKER_ROW_WIDTH + PICKER_OUTPUT_WIDTH;
-const PICKER_WINDOW_HEIGHT: i32 = PICKER_ROW_HEIGHT * 4;
-const PICKER_BUTTON_WIDTH: i32 = PICKER_ROW_WIDTH / 2;
+// The colors most recently picked, most recent first.
+static RECENT: Mutex<Vec<RGB>> = Mutex::new(Vec::new());
+
+// Put `c` at the front of the recently picked colors.
+fn remember_color(c: RGB) {
+    let mut recent = RECENT.lock().unwrap();
+    recent.retain(|x| x.to_rgb8() != c.to_rgb8());
+    recent.insert(0, c);
+    recent.truncate(RECENT_COLORS);
+}
+
+fn recent_colors() -> Vec<RGB> {
+    RECENT.lock().unwrap().clone()
+}
+
+// What the color picker is currently set to. The hue, saturation and
+// value are kept separately rather than always worked out from the RGB
+// values, so that (for example) turning the saturation of a color all the
+// way down and back up again doesn't lose its hue.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PickerState {
+    rgb: RGB,
+    hsv: (f32, f32, f32),
+}
+
+impl PickerState {
+    fn new(rgb: RGB) -> PickerState {
+        PickerState {
+            rgb,
+            hsv: rgb.to_hsv(),
+        }
+    }
+}
+
+// One of the color picker's rows of widgets: a component of either the RGB
+// or the HSV version of the color.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Channel {
+    Red,
+    Green,
+    Blue,
+    Hue,
+    Saturation,
+    Value,
+}
+
+impl Channel {
+    const ALL: [Channel; 6] = [
+        Channel::Red,
+        Channel::Green,
+        Channel::Blue,
+        Channel::Hue,
+        Channel::Saturation,
+        Channel::Value,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            Channel::Red => "R",
+            Channel::Green => "G",
+            Channel::Blue => "B",
+            Channel::Hue => "H",
+            Channel::Saturation => "S",
+            Channel::Value => "V",
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Channel::Red => "red",
+            Channel::Green => "green",
+            Channel::Blue => "blue",
+            Channel::Hue => "hue",
+            Channel::Saturation => "saturation",
+            Channel::Value => "value",
+        }
+    }
+
+    // The largest value this row's widgets go up to. Hue is in degrees,
+    // and saturation and value in percent.
+    fn max(&self) -> f64 {
+        match self {
+            Channel::Hue => 359.0,
+            Channel::Saturation | Channel::Value => 100.0,
+            _ => 255.0,
+        }
+    }
+
+    fn get(&self, state: &PickerState) -> f64 {
+        let (h, s, v) = state.hsv;
+        let x = match self {
+            Channel::Red => state.rgb.r(),
+            Channel::Green => state.rgb.g(),
+            Channel::Blue => state.rgb.b(),
+            Channel::Hue => h,
+            Channel::Saturation => 100.0 * s,
+            Channel::Value => 100.0 * v,
+        };
+        (x as f64).round()
+    }
+
+    // Return `state` with this channel set to `x`, and the other version
+    // of the color changed to match.
+    fn set(&self, state: &PickerState, x: f64) -> PickerState {
+        let x = x.clamp(0.0, self.max()) as f32;
+        let (mut rgb, (mut h, mut s, mut v)) = (state.rgb, state.hsv);
+        match self {
+            Channel::Red => rgb.set_r(x),
+            Channel::Green => rgb.set_g(x),
+            Channel::Blue => rgb.set_b(x),
+            Channel::Hue => h = x,
+            Channel::Saturation => s = x / 100.0,
+            Channel::Value => v = x / 100.0,
+        }
+        match self {
+            Channel::Red | Channel::Green | Channel::Blue => {
+                let (new_h, new_s, new_v) = rgb.to_hsv();
+                // Grays don't have a hue of their own, and black doesn't
+                // have a saturation either, so they keep the ones they had.
+                if new_s > 0.0 {
+                    h = new_h;
+                    s = new_s;
+                } else if new_v > 0.0 {
+                    s = 0.0;
+                }
+                v = new_v;
+            }
+            _ => {
+                rgb = RGB::from_hsv(h, s, v);
+            }
+        }
+        PickerState {
+            rgb,
+            hsv: (h, s, v),
+        }
+    }
+}
+
+// The widgets in one row of the color picker.
+struct PickerRow {
+    channel: Channel,
+    slider: HorNiceSlider,
+    vinput: ValueInput,
+}
+
+impl PickerRow {
+    // Show the value `state` has for this row's channel.
+    fn show(&mut self, state: &PickerState) {
+        let x = self.channel.get(state);
+        let value = format!("{}", x);
+        self.slider.set_value(x);
+        self.vinput.set_value(x);
+        describe_value(&mut self.slider, self.channel.name(), &value);
+        describe_value(&mut self.vinput, self.channel.name(), &value);
+    }
+}
+
+// Make `new_state` the picker's state, and show it on all its rows and
+// its preview swatch.
+fn show_picker_state(
+    new_state: PickerState,
+    state: &Cell<PickerState>,
+    rows: &RefCell<Vec<PickerRow>>,
+    prev: &DoubleWindow,
+) {
+    state.set(new_state);
+    for row in rows.borrow_mut().iter_mut() {
+        row.show(&new_state);
+    }
+    let mut prev = prev.clone();
+    prev.set_color(rgb_to_fltk(new_state.rgb));
+    prev.redraw();
+}
 
 // This function only exists to save typing in the implementation of
-// `pick_color()`. There are three nearly-identical rows of widgets in the
-// color picker window; this abstracts creating them.
+// `pick_color()`. There are six nearly-identical rows of widgets in the
+// color picker window; this abstracts creating them. Changing any row
+// updates all of the `rows` (which this one should be added to) to match.
 fn make_picker_row(
     ypos: i32,
-    label: &'static str,
-    initial_value: f64,
-    mut prev: DoubleWindow,
-    rvalue: Rc<Cell<RGB>>,
-) -> (Frame, HorNiceSlider, ValueInput) {
-    let lab = Frame::default()
-        .with_label(label)
+    channel: Channel,
+    prev: DoubleWindow,
+    state: Rc<Cell<PickerState>>,
+    rows: Rc<RefCell<Vec<PickerRow>>>,
+) -> PickerRow {
+    let l = layout::current();
+    let _ = Frame::default()
+        .with_label(channel.label())
         .with_pos(0, ypos)
-        .with_size(PICKER_LABEL_WIDTH, PICKER_ROW_HEIGHT);
+        .with_size(l.picker_label_width, l.picker_row_height);
     let mut slider = HorNiceSlider::default()
-        .with_pos(PICKER_LABEL_WIDTH, ypos)
-        .with_size(PICKER_SLIDER_WIDTH, PICKER_ROW_HEIGHT);
-    slider.set_value(initial_value);
+        .with_pos(l.picker_label_width, ypos)
+        .with_size(l.picker_slider_width, l.picker_row_height);
     let mut vinput = ValueInput::new(
-        PICKER_LABEL_WIDTH + PICKER_SLIDER_WIDTH,
+        l.picker_label_width + l.picker_slider_width,
         ypos,
-        PICKER_INPUT_WIDTH,
-        PICKER_ROW_HEIGHT,
+        l.picker_input_width,
+        l.picker_row_height,
         None,
     );
-    vinput.set_value(initial_value);
 
-    slider.set_range(0.0, 255.0);
-    vinput.set_bounds(0.0, 255.0);
+    slider.set_range(0.0, channel.max());
+    vinput.set_bounds(0.0, channel.max());
     slider.set_step(1.0, 1);
 
-    slider.set_callback({
-        let rvalue = rvalue.clone();
-        let mut vinput = vinput.clone();
-        let mut prev = prev.clone();
-        move |s| {
-            let x = s.value();
-            vinput.set_value(x);
-            let mut rv = rvalue.get();
-            match label {
-                "R" => {
-                    rv.set_r(x as f32);
-                }
-                "G" => {
-                    rv.set_g(x as f32);
-                }
-                "B" => {
-                    rv.set_b(x as f32);
-                }
-                s => {
-                    panic!("ui::make_picker_row(): bad picker row label: {}", s);
-                }
-            }
-            rvalue.set(rv);
-            let c = rgb_to_fltk(rv);
-            prev.set_color(c);
-            prev.redraw();
-        }
+    // Both widgets do the same thing when they change.
+    let changed = Rc::new(move |x: f64| {
+        let new_state = channel.set(&state.get(), x);
+        show_picker_state(new_state, &state, &rows, &prev);
     });
-
-    vinput.set_callback({
-        let mut slider = slider.clone();
-        move |v| {
-            let x = v.value();
-            slider.set_value(x);
-            let mut rv = rvalue.get();
-            match label {
-                "R" => {
-                    rv.set_r(x as f32);
-                }
-                "G" => {
-                    rv.set_g(x as f32);
-                }
-                "B" => {
-                    rv.set_b(x as f32);
-                }
-                s => {
-                    panic!("ui::make_picker_row(): bad picker row label: {}", s);
-                }
-            }
-            rvalue.set(rv);
-            let c = rgb_to_fltk(rv);
-            prev.set_color(c);
-            prev.redraw();
-        }
+    slider.set_callback({
+        let changed = changed.clone();
+        move |s| changed(s.value())
     });
+    vinput.set_callback(move |v| changed(v.value()));
 
-    (lab, slider, vinput)
+    PickerRow {
+        channel,
+        slider,
+        vinput,
+    }
 }
 
 /**
 Pops up a modal window for selecting a color.
 */
 pub fn pick_color(start: RGB) -> Option<RGB> {
-    let rvalue: Rc<Cell<RGB>> = Rc::new(Cell::new(start));
+    let l = layout::current();
+    let state = Rc::new(Cell::new(PickerState::new(start)));
+    let rows: Rc<RefCell<Vec<PickerRow>>> = Rc::new(RefCell::new(Vec::new()));
 
     let mut w = DoubleWindow::default()
         .with_label("Specify a Color")
-        .with_size(PICKER_WINDOW_WIDTH, PICKER_WINDOW_HEIGHT);
+        .with_size(l.picker_window_width(), l.picker_window_height());
 
     let mut prev = DoubleWindow::default()
-        .with_size(PICKER_OUTPUT_WIDTH, PICKER_WINDOW_HEIGHT)
-        .with_pos(PICKER_ROW_WIDTH, 0);
+        .with_size(l.picker_output_width(), l.picker_body_height())
+        .with_pos(l.picker_row_width(), 0);
     prev.end();
     prev.set_color(rgb_to_fltk(start));
 
-    let (_, _, _) = make_picker_row(0, "R", start.r() as f64, prev.clone(), rvalue.clone());
-    let (_, _, _) = make_picker_row(
-        PICKER_ROW_HEIGHT,
-        "G",
-        start.g() as f64,
-        prev.clone(),
-        rvalue.clone(),
-    );
-    let (_, _, _) = make_picker_row(
-        2 * PICKER_ROW_HEIGHT,
-        "B",
-        start.b() as f64,
-        prev.clone(),
-        rvalue.clone(),
-    );
+    for (n, channel) in Channel::ALL.iter().enumerate() {
+        let mut row = make_picker_row(
+            (n as i32) * l.picker_row_height,
+            *channel,
+            prev.clone(),
+            state.clone(),
+            rows.clone(),
+        );
+        row.show(&state.get());
+        rows.borrow_mut().push(row);
+    }
+    let button_ypos = (Channel::ALL.len() as i32) * l.picker_row_height;
 
     let mut ok = Button::default()
         .with_label("Set @returnarrow")
-        .with_size(PICKER_BUTTON_WIDTH, PICKER_ROW_HEIGHT)
-        .with_pos(0, 3 * PICKER_ROW_HEIGHT);
+        .with_size(l.picker_button_width(), l.picker_row_height)
+        .with_pos(0, button_ypos);
     ok.set_shortcut(Shortcut::from_key(Key::Enter));
     let mut no = Button::default()
         .with_label("Cancel (Esc)")
-        .with_size(PICKER_BUTTON_WIDTH, PICKER_ROW_HEIGHT)
-        .with_pos(PICKER_BUTTON_WIDTH, 3 * PICKER_ROW_HEIGHT);
+        .with_size(l.picker_button_width(), l.picker_row_height)
+        .with_pos(l.picker_button_width(), button_ypos);
     no.set_shortcut(Shortcut::from_key(Key::Escape));
+    let mut sample = Button::default()
+        .with_label("From Image")
+        .with_size(l.picker_button_width(), l.picker_row_height)
+        .with_pos(2 * l.picker_button_width(), button_ypos);
+    sample.set_tooltip("pick a color by clicking on the image");
+
+    // Clicking one of the recent colors along the bottom picks it again.
+    let swatch_width = l.picker_window_width() / (RECENT_COLORS as i32);
+    for (n, c) in recent_colors().into_iter().enumerate() {
+        let mut swatch = Button::default()
+            .with_size(swatch_width, l.picker_row_height)
+            .with_pos((n as i32) * swatch_width, l.picker_body_height());
+        swatch.set_color(rgb_to_fltk(c));
+        let [r, g, b] = c.to_rgb8();
+        swatch.set_tooltip(&format!("recent color: {}, {}, {}", r, g, b));
+        swatch.set_callback({
+            let state = state.clone();
+            let rows = rows.clone();
+            let prev = prev.clone();
+            move |_| show_picker_state(PickerState::new(c), &state, &rows, &prev)
+        });
+    }
 
     w.end();
     w.make_modal(true);
@@ -173,10 +324,31 @@ pub fn pick_color(start: RGB) -> Option<RGB> {
 
     let (tx, rx) = mpsc::channel::<Option<RGB>>();
 
+    // The picker steps out of the way until the image has been clicked.
+    sample.set_callback({
+        let state = state.clone();
+        let w = w.clone();
+        move |_| {
+            let mut w = w.clone();
+            w.hide();
+            let state = state.clone();
+            let rows = rows.clone();
+            let prev = prev.clone();
+            super::wait_for_sample(move |c| {
+                if let Some(c) = c {
+                    show_picker_state(PickerState::new(c), &state, &rows, &prev);
+                }
+                w.show();
+            });
+        }
+    });
+
     ok.set_callback({
         let tx = tx.clone();
         move |_| {
-            tx.send(Some(rvalue.get())).unwrap();
+            let c = state.get().rgb;
+            remember_color(c);
+            tx.send(Some(c)).unwrap();
         }
     });
     no.set_callback({
@@ -197,88 +369,153 @@ pub fn pick_color(start: RGB) -> Option<RGB> {
     None
 }
 
-// The following constants all specify dimensions of the `GradientChooser`
-// widget wrapper's UI elements.
-const GRADIENT_BUTTON_WIDTH: i32 = 32;
-const GRADIENT_ROW_HEIGHT: i32 = 32;
-const GRADIENT_STEPS_WIDTH: i32 = 64;
-const GRADIENT_ROW_WIDTH: i32 = (2 * GRADIENT_BUTTON_WIDTH) + GRADIENT_STEPS_WIDTH;
-
 // Wraps some UI elements for specifying a `Gradient`.
 struct GradientChooser {
     win: DoubleWindow,
     start_color: Rc<Cell<RGB>>,
     end_color: Rc<Cell<RGB>>,
     steps_n: Rc<Cell<usize>>,
+    space: Rc<Cell<Interpolation>>,
 }
 
 impl GradientChooser {
     // Create a new `GradientChooser` that initially displays parameters
-    // for the supplied `Gradient`.
-    fn new(g: Gradient, drag_color: Rc<Cell<Option<RGB>>>) -> GradientChooser {
-        let w = DoubleWindow::default().with_size(GRADIENT_ROW_WIDTH, GRADIENT_ROW_HEIGHT);
+    // for the supplied `Gradient`. `changed` is called whenever any of
+    // them is changed.
+    fn new(
+        g: Gradient,
+        drag_color: Rc<Cell<Option<RGB>>>,
+        changed: Rc<dyn Fn()>,
+    ) -> GradientChooser {
+        let l = layout::current();
+        let w = DoubleWindow::default().with_size(l.gradient_row_width(), l.gradient_row_height);
         let mut sbutt = Button::default()
-            .with_size(GRADIENT_BUTTON_WIDTH, GRADIENT_ROW_HEIGHT)
+            .with_size(l.gradient_button_width, l.gradient_row_height)
             .with_pos(0, 0);
         sbutt.set_tooltip("set start color");
         sbutt.set_color(rgb_to_fltk(g.start));
+        let mut swapbutt = Button::default()
+            .with_label("@<->")
+            .with_size(l.gradient_swap_width, l.gradient_row_height)
+            .with_pos(l.gradient_button_width + l.gradient_steps_width, 0);
+        swapbutt.set_tooltip("swap start and end colors");
         let mut ebutt = Button::default()
-            .with_size(GRADIENT_BUTTON_WIDTH, GRADIENT_ROW_HEIGHT)
-            .with_pos(GRADIENT_BUTTON_WIDTH + GRADIENT_STEPS_WIDTH, 0);
+            .with_size(l.gradient_button_width, l.gradient_row_height)
+            .with_pos(
+                l.gradient_button_width + l.gradient_steps_width + l.gradient_swap_width,
+                0,
+            );
         ebutt.set_tooltip("set end color");
         ebutt.set_color(rgb_to_fltk(g.end));
         let mut stepsi = IntInput::default()
-            .with_size(GRADIENT_STEPS_WIDTH, GRADIENT_ROW_HEIGHT)
-            .with_pos(GRADIENT_BUTTON_WIDTH, 0);
-        stepsi.set_tooltip("number of steps");
+            .with_size(l.gradient_steps_width, l.gradient_row_height)
+            .with_pos(l.gradient_button_width, 0);
         stepsi.set_value(&format!("{}", g.steps));
+        describe_value(&mut stepsi, "number of steps", &format!("{}", g.steps));
+        let mut spacec = Choice::default()
+            .with_size(l.gradient_space_width, l.gradient_row_height)
+            .with_pos(l.gradient_row_width() - l.gradient_space_width, 0);
+        spacec.add_choice("RGB|HSV|HSV long|Lab|LCH");
+        spacec.set_tooltip(
+            "blend through RGB, through hues the short or long way around, \
+             or evenly to the eye (Lab, or LCH through hues)",
+        );
+        spacec.set_value(match g.space {
+            Interpolation::Rgb => 0,
+            Interpolation::Hsv => 1,
+            Interpolation::HsvLong => 2,
+            Interpolation::Lab => 3,
+            Interpolation::Lch => 4,
+        });
         w.end();
 
         let sc_cell = Rc::new(Cell::new(g.start));
         let ec_cell = Rc::new(Cell::new(g.end));
         let sn_cell = Rc::new(Cell::new(g.steps));
+        let space_cell = Rc::new(Cell::new(g.space));
 
         sbutt.set_callback({
             let sc_cell = sc_cell.clone();
+            let changed = changed.clone();
             move |b| {
                 if let Some(c) = pick_color(sc_cell.get()) {
                     b.set_color(rgb_to_fltk(c));
                     b.redraw();
                     sc_cell.set(c);
+                    changed();
                 }
             }
         });
         ebutt.set_callback({
             let ec_cell = ec_cell.clone();
+            let changed = changed.clone();
             move |b| {
                 if let Some(c) = pick_color(ec_cell.get()) {
                     b.set_color(rgb_to_fltk(c));
                     b.redraw();
                     ec_cell.set(c);
+                    changed();
                 }
             }
         });
 
+        swapbutt.set_callback({
+            let sc_cell = sc_cell.clone();
+            let ec_cell = ec_cell.clone();
+            let mut sbutt = sbutt.clone();
+            let mut ebutt = ebutt.clone();
+            let changed = changed.clone();
+            move |_| {
+                sc_cell.swap(&ec_cell);
+                sbutt.set_color(rgb_to_fltk(sc_cell.get()));
+                ebutt.set_color(rgb_to_fltk(ec_cell.get()));
+                sbutt.redraw();
+                ebutt.redraw();
+                changed();
+            }
+        });
+
         stepsi.set_callback({
             let sn_cell = sn_cell.clone();
+            let changed = changed.clone();
             move |i| {
                 if let Ok(n) = i.value().parse::<usize>() {
                     sn_cell.set(n);
+                    changed();
                 } else {
                     i.set_value(&format!("{}", sn_cell.get()));
                 }
+                let value = i.value();
+                describe_value(i, "number of steps", &value);
+            }
+        });
+
+        spacec.set_callback({
+            let space_cell = space_cell.clone();
+            let changed = changed.clone();
+            move |c| {
+                space_cell.set(match c.value() {
+                    1 => Interpolation::Hsv,
+                    2 => Interpolation::HsvLong,
+                    3 => Interpolation::Lab,
+                    4 => Interpolation::Lch,
+                    _ => Interpolation::Rgb,
+                });
+                changed();
             }
         });
 
         sbutt.handle({
             let sc_cell = sc_cell.clone();
             let drag_color = drag_color.clone();
+            let changed = changed.clone();
             move |b, evt| match evt {
                 Event::Enter => {
                     if let Some(c) = drag_color.get() {
                         b.set_color(rgb_to_fltk(c));
                         b.redraw();
                         sc_cell.set(c);
+                        changed();
                         true
                     } else {
                         false
@@ -306,6 +543,7 @@ impl GradientChooser {
                         b.set_color(rgb_to_fltk(c));
                         b.redraw();
                         ec_cell.set(c);
+                        changed();
                         true
                     } else {
                         false
@@ -330,6 +568,7 @@ impl GradientChooser {
             start_color: sc_cell,
             end_color: ec_cell,
             steps_n: sn_cell,
+            space: space_cell,
         }
     }
 
@@ -354,12 +593,146 @@ impl GradientChooser {
             start: self.start_color.get(),
             end: self.end_color.get(),
             steps: self.steps_n.get(),
+            space: self.space.get(),
         }
     }
 }
 
-// The calculated width of the `ColorPane`'s window.
-const COLOR_PANE_WIDTH: i32 = (4 * GRADIENT_BUTTON_WIDTH) + GRADIENT_STEPS_WIDTH;
+// The number of steps between colors when a palette is imported "blended".
+const PALETTE_BLEND_STEPS: usize = 16;
+
+// The most problems the contrast check lists at once.
+const MAX_REPORTED_PROBLEMS: usize = 12;
+
+// The exponent offered when the "power" mapping is first chosen; less
+// than 1 spreads out the high iteration counts, like the other mappings.
+const DEFAULT_EXPONENT: f64 = 0.5;
+
+// What the value inputs at the bottom of the pane are called when they
+// describe themselves.
+const EXPONENT_NAME: &str = "exponent for the \"power\" mapping";
+const REPEAT_NAME: &str = "times to cycle through the colors before the default color";
+const PHASE_NAME: &str = "rotate the colors along the image";
+const GAMMA_NAME: &str = "gamma (more than 1 lightens the midtones)";
+const BRIGHTNESS_NAME: &str = "brightness (from -1 to 1)";
+const CONTRAST_NAME: &str = "contrast (1 leaves it alone)";
+const AZIMUTH_NAME: &str =
+    "direction the light comes from (degrees counterclockwise from the right)";
+const ELEVATION_NAME: &str = "height of the light above the image (degrees, 5 to 90)";
+const DEPTH_NAME: &str = "how steep the relief is";
+const STRENGTH_NAME: &str = "how much of the shading shows (0 to 1)";
+const ITERATIONS_NAME: &str =
+    "iterations before a point counts as inside (0 to stop at the end of the palette)";
+const TRAP_RE_NAME: &str = "real part of the orbit trap's center";
+const TRAP_IM_NAME: &str = "imaginary part of the orbit trap's center";
+const TRAP_SIZE_NAME: &str = "circle trap radius, line trap angle (times pi), or stripes per turn";
+const TRAP_SCALE_NAME: &str = "distance from the orbit trap that gets the last color";
+
+// What the orbit inputs hold when there isn't a trap to fill them in from:
+// center, size, scale. Stripes get their own size.
+const DEFAULT_TRAP: (Cx, f64, f64) = (Cx { re: 0.0, im: 0.0 }, 0.5, 1.0);
+const DEFAULT_DENSITY: f64 = 5.0;
+
+// The entries in the orbit `Choice`, in order.
+const ORBIT_CHOICES: &str =
+    "escape time|point trap|line trap|circle trap|stripe average|triangle average";
+const STRIPE_CHOICE: i32 = 4;
+
+// Which of the four orbit inputs (center re, center im, size, scale) mean
+// anything for the orbit `Choice` entry `choice`.
+fn orbit_inputs_used(choice: i32) -> [bool; 4] {
+    match choice {
+        1 => [true, true, false, true],
+        2 | 3 => [true, true, true, true],
+        STRIPE_CHOICE => [false, false, true, false],
+        _ => [false; 4],
+    }
+}
+
+// Build the orbit coloring chosen by `choice` (the index of the orbit
+// `Choice`) from the values in its four inputs, putting what was actually
+// used back in any of them that didn't make sense.
+fn read_orbit(choice: i32, inputs: &mut [FloatInput; 4]) -> Option<Orbit> {
+    let names = [TRAP_RE_NAME, TRAP_IM_NAME, TRAP_SIZE_NAME, TRAP_SCALE_NAME];
+    let (c, size, scale) = DEFAULT_TRAP;
+    let defaults = [c.re, c.im, size, scale];
+    let mut vals = [0.0; 4];
+    for (n, i) in inputs.iter_mut().enumerate() {
+        vals[n] = match i.value().trim().parse::<f64>() {
+            Ok(x) if x.is_finite() => x,
+            _ => defaults[n],
+        };
+        // Neither a radius nor a scale can be negative.
+        if n >= 2 {
+            vals[n] = vals[n].abs();
+        }
+        i.set_value(&format!("{}", vals[n]));
+        describe_value(i, names[n], &format!("{}", vals[n]));
+    }
+    let [re, im, size, scale] = vals;
+    let center = Cx { re, im };
+    let trap = match choice {
+        1 => Trap::Point { center, scale },
+        2 => Trap::Line {
+            center,
+            angle_pi: size,
+            scale,
+        },
+        3 => Trap::Circle {
+            center,
+            radius: size,
+            scale,
+        },
+        STRIPE_CHOICE => return Some(Orbit::Stat(OrbitStat::Stripe { density: size })),
+        5 => return Some(Orbit::Stat(OrbitStat::Triangle)),
+        _ => return None,
+    };
+    Some(Orbit::Trap(trap))
+}
+
+// Build the shading from the values in its four inputs (azimuth,
+// elevation, depth, strength), putting what was actually used back in
+// them.
+fn read_shading(inputs: &mut [FloatInput; 4]) -> Shading {
+    let names = [AZIMUTH_NAME, ELEVATION_NAME, DEPTH_NAME, STRENGTH_NAME];
+    let d = Shading::default();
+    let defaults = [d.azimuth, d.elevation, d.depth, d.strength];
+    let mut vals = [0.0; 4];
+    for (n, i) in inputs.iter().enumerate() {
+        vals[n] = i.value().trim().parse::<f64>().unwrap_or(defaults[n]);
+    }
+    let [azimuth, elevation, depth, strength] = vals;
+    let shading = Shading {
+        azimuth,
+        elevation,
+        depth,
+        strength,
+    }
+    .clamped();
+    let vals = [
+        shading.azimuth,
+        shading.elevation,
+        shading.depth,
+        shading.strength,
+    ];
+    for (n, i) in inputs.iter_mut().enumerate() {
+        i.set_value(&format!("{}", vals[n]));
+        describe_value(i, names[n], &format!("{}", vals[n]));
+    }
+    shading
+}
+
+// Read the exponent for the "power" mapping, putting what was actually
+// used back in the input if what was there didn't make sense.
+fn parse_exponent(input: &mut FloatInput) -> f64 {
+    let p = match input.value().trim().parse::<f64>() {
+        Ok(p) if p.is_finite() => p.clamp(Transfer::MIN_EXPONENT, Transfer::MAX_EXPONENT),
+        _ => DEFAULT_EXPONENT,
+    };
+    input.set_value(&format!("{}", p));
+    describe_value(input, EXPONENT_NAME, &format!("{}", p));
+    p
+}
 
 // The `ColorPaneGuts` holds the `ColorPane`'s window and other UI
 // elements. It also must hold a reference to itself, which is a little
@@ -370,38 +743,72 @@ struct ColorPaneGuts {
     choosers: Vec<GradientChooser>,
     win: DoubleWindow,
     default_color: RGB,
+    overflow: Overflow,
+    transparent: bool,
+    iterations: usize,
+    transfer: Transfer,
+    repeats: usize,
+    phase: f64,
+    adjust: Adjustment,
+    curves: Curves,
+    shading: Option<Shading>,
+    orbit: Option<Orbit>,
+    layers: Vec<Layer>,
     drag_color: Rc<Cell<Option<RGB>>>,
+    // The strip across the top that shows the whole color map.
+    preview: Option<Frame>,
+    // Whether hovering over a gradient should highlight its pixels on
+    // the image.
+    highlight: Rc<Cell<bool>>,
+    pipe: mpsc::Sender<Msg>,
     me: Option<Rc<RefCell<ColorPaneGuts>>>,
 }
 
 impl ColorPaneGuts {
-    fn new(
-        new_gradients: Vec<Gradient>,
-        default_color: RGB,
-        pipe: mpsc::Sender<Msg>,
-    ) -> Rc<RefCell<ColorPaneGuts>> {
+    fn new(spec: &ColorSpec, pipe: mpsc::Sender<Msg>) -> Rc<RefCell<ColorPaneGuts>> {
+        let l = layout::current();
         let (scrn_w, scrn_h) = fltk::app::screen_size();
         let (scrn_w, scrn_h) = (scrn_w as i32, scrn_h as i32);
-        let mut w = DoubleWindow::default().with_pos(scrn_w - COLOR_PANE_WIDTH, scrn_h / 2);
+        let (x, y) =
+            dock_begin(Dock::ColorMap).unwrap_or((scrn_w - l.color_pane_width(), scrn_h / 2));
+        let mut w = DoubleWindow::default().with_pos(x, y);
         w.set_border(false);
-        w.end();
+        // It has no title bar to show this in, but the OS (and anything
+        // reading windows out to the user) still has a name for it.
+        w.set_label("Color Map");
+        end_pane(&mut w);
 
-        setup_subwindow_behavior(&mut w, pipe);
+        setup_subwindow_behavior(&mut w, pipe.clone());
 
         let drag_color: Rc<Cell<Option<RGB>>> = Rc::new(Cell::new(None));
 
         let pg = Rc::new(RefCell::new(ColorPaneGuts {
-            choosers: new_gradients
-                .iter()
-                .map(|g| GradientChooser::new(*g, drag_color.clone()))
-                .collect(),
+            choosers: Vec::new(),
             win: w.clone(),
-            default_color,
+            default_color: spec.default(),
+            overflow: spec.overflow(),
+            transparent: spec.transparent(),
+            iterations: spec.iterations(),
+            transfer: spec.transfer(),
+            repeats: spec.repeats(),
+            phase: spec.phase(),
+            adjust: spec.adjustment(),
+            curves: spec.curves().clone(),
+            shading: spec.shading(),
+            orbit: spec.orbit(),
+            layers: spec.layers().to_vec(),
             drag_color,
+            preview: None,
+            highlight: Rc::new(Cell::new(false)),
+            pipe,
             me: None,
         }));
 
         pg.borrow_mut().me = Some(pg.clone());
+        for g in spec.clone().gradients().into_iter() {
+            let gc = pg.borrow().chooser(g);
+            pg.borrow_mut().choosers.push(gc);
+        }
 
         pg
     }
@@ -409,34 +816,96 @@ impl ColorPaneGuts {
     // Every time a gradient chooser is added or removed, the window
     // needs to be resized/redrawn.
     fn redraw(&mut self) {
+        let l = layout::current();
         for ch in self.choosers.iter() {
             self.win.remove(ch.get_win());
         }
         self.win.clear();
-        let height = (3 + self.choosers.len() as i32) * GRADIENT_ROW_HEIGHT;
-        self.win.set_size(COLOR_PANE_WIDTH, height);
+        // Compact panes leave out the title row and the labels.
+        let compact = l.color_pane.compact;
+        let top = if compact { 0 } else { 1 };
+        let height = (top + 14 + self.choosers.len() as i32) * l.gradient_row_height;
+        self.win.set_size(l.color_pane_width(), height);
         self.win.begin();
 
-        let _ = Frame::default()
-            .with_label("Color Map")
-            .with_pos(0, 0)
-            .with_size(COLOR_PANE_WIDTH, GRADIENT_ROW_HEIGHT);
-
+        let label = |text: &str, (x, y): (i32, i32), (w, h): (i32, i32)| {
+            if !compact {
+                let _ = Frame::default()
+                    .with_label(text)
+                    .with_pos(x, y)
+                    .with_size(w, h);
+            }
+        };
+        label(
+            "Color Map",
+            (0, 0),
+            (l.color_pane_width(), l.gradient_row_height),
+        );
+        let mut preview = Frame::default()
+            .with_pos(0, top * l.gradient_row_height)
+            .with_size(l.color_pane_width(), l.gradient_row_height);
+        preview.set_tooltip("the whole color map, from the first iteration to the last");
+        self.preview = Some(preview);
+
+        let n_choosers = self.choosers.len();
         for (n, ch) in self.choosers.iter_mut().enumerate() {
-            let ypos = (1 + n as i32) * GRADIENT_ROW_HEIGHT;
+            let ypos = (top + 1 + n as i32) * l.gradient_row_height;
             let mut insert_butt = Button::default()
                 .with_label("@+")
-                .with_size(GRADIENT_BUTTON_WIDTH, GRADIENT_ROW_HEIGHT)
+                .with_size(l.gradient_button_width, l.gradient_row_height)
                 .with_pos(0, ypos);
             insert_butt.set_tooltip("insert gradient before this one");
             self.win.add(ch.get_win());
-            ch.set_pos(GRADIENT_BUTTON_WIDTH, ypos);
+            ch.set_pos(l.gradient_button_width, ypos);
             //ch.show();
             let mut remove_butt = Button::default()
                 .with_label("x")
-                .with_size(GRADIENT_BUTTON_WIDTH, GRADIENT_ROW_HEIGHT)
-                .with_pos(GRADIENT_BUTTON_WIDTH + GRADIENT_ROW_WIDTH, ypos);
+                .with_size(l.gradient_button_width, l.gradient_row_height)
+                .with_pos(l.gradient_button_width + l.gradient_row_width(), ypos);
             remove_butt.set_tooltip("remove this gradient");
+            let mut dup_butt = Button::default()
+                .with_label("dup")
+                .with_size(l.gradient_button_width, l.gradient_row_height)
+                .with_pos((2 * l.gradient_button_width) + l.gradient_row_width(), ypos);
+            dup_butt.set_tooltip("insert a copy of this gradient after it");
+            let move_x = (3 * l.gradient_button_width) + l.gradient_row_width();
+            let mut up_butt = Button::default()
+                .with_label("@8>")
+                .with_size(l.gradient_move_width, l.gradient_row_height / 2)
+                .with_pos(move_x, ypos);
+            up_butt.set_tooltip("move this gradient up");
+            let mut down_butt = Button::default()
+                .with_label("@2>")
+                .with_size(l.gradient_move_width, l.gradient_row_height / 2)
+                .with_pos(move_x, ypos + (l.gradient_row_height / 2));
+            down_butt.set_tooltip("move this gradient down");
+            if n == 0 {
+                up_butt.deactivate();
+            }
+            if n + 1 == n_choosers {
+                down_butt.deactivate();
+            }
+
+            // Passing the event on (by returning `false`) lets the
+            // buttons inside still see it.
+            ch.win.handle({
+                let highlight = self.highlight.clone();
+                let pipe = self.pipe.clone();
+                move |_, evt| {
+                    if highlight.get() {
+                        match evt {
+                            Event::Enter => {
+                                pipe.send(Msg::HighlightGradient(Some(n))).unwrap();
+                            }
+                            Event::Leave => {
+                                pipe.send(Msg::HighlightGradient(None)).unwrap();
+                            }
+                            _ => {}
+                        }
+                    }
+                    false
+                }
+            });
 
             insert_butt.set_callback({
                 let me = self.me.as_ref().unwrap().clone();
@@ -451,38 +920,354 @@ impl ColorPaneGuts {
                     me.borrow_mut().remove(n);
                 }
             });
+
+            dup_butt.set_callback({
+                let me = self.me.as_ref().unwrap().clone();
+                move |_| {
+                    me.borrow_mut().duplicate(n);
+                }
+            });
+
+            up_butt.set_callback({
+                let me = self.me.as_ref().unwrap().clone();
+                move |_| {
+                    me.borrow_mut().swap(n - 1, n);
+                }
+            });
+
+            down_butt.set_callback({
+                let me = self.me.as_ref().unwrap().clone();
+                move |_| {
+                    me.borrow_mut().swap(n, n + 1);
+                }
+            });
         }
 
-        let tail_w_ypos = (1 + self.choosers.len() as i32) * GRADIENT_ROW_HEIGHT;
-        let tail_label_w = (2 * GRADIENT_BUTTON_WIDTH) + GRADIENT_STEPS_WIDTH;
+        let tail_w_ypos = (top + 1 + self.choosers.len() as i32) * l.gradient_row_height;
+        let tail_label_w = l.color_pane_width() - (2 * l.gradient_button_width);
         //~ let tail_w = DoubleWindow::default()
-        //~ .with_size(COLOR_PANE_WIDTH, 2*GRADIENT_ROW_HEIGHT)
+        //~ .with_size(l.color_pane_width(), 2*l.gradient_row_height)
         //~ .with_pos(0, tail_w_ypos);
         let mut append_butt = Button::default()
             .with_label("@+")
             .with_pos(0, tail_w_ypos)
-            .with_size(2 * GRADIENT_BUTTON_WIDTH, GRADIENT_ROW_HEIGHT);
-        let _ = Frame::default()
-            .with_label("append gradient")
-            .with_pos(2 * GRADIENT_BUTTON_WIDTH, tail_w_ypos)
-            .with_size(tail_label_w, GRADIENT_ROW_HEIGHT);
-        let _ = Frame::default()
-            .with_label("default color")
-            .with_pos(0, tail_w_ypos + GRADIENT_ROW_HEIGHT)
-            .with_size(tail_label_w, GRADIENT_ROW_HEIGHT);
+            .with_size(2 * l.gradient_button_width, l.gradient_row_height);
+        append_butt.set_tooltip("append a gradient");
+        label(
+            "append gradient",
+            (2 * l.gradient_button_width, tail_w_ypos),
+            (
+                tail_label_w - (2 * l.gradient_button_width),
+                l.gradient_row_height,
+            ),
+        );
+        let mut reverse_butt = Button::default()
+            .with_label("reverse")
+            .with_pos(tail_label_w, tail_w_ypos)
+            .with_size(2 * l.gradient_button_width, l.gradient_row_height);
+        reverse_butt.set_tooltip("run the whole color map backward");
+        let clear_w = 3 * l.gradient_button_width;
+        label(
+            "interior color",
+            (0, tail_w_ypos + l.gradient_row_height),
+            (tail_label_w - clear_w, l.gradient_row_height),
+        );
+        let mut transparent_check = CheckButton::default()
+            .with_label("transparent")
+            .with_pos(tail_label_w - clear_w, tail_w_ypos + l.gradient_row_height)
+            .with_size(clear_w, l.gradient_row_height);
+        transparent_check.set_checked(self.transparent);
+        transparent_check.set_tooltip("leave points that never escape transparent in saved images");
         let mut default_select = Button::default()
-            .with_pos(tail_label_w, tail_w_ypos + GRADIENT_ROW_HEIGHT)
-            .with_size(2 * GRADIENT_BUTTON_WIDTH, GRADIENT_ROW_HEIGHT);
+            .with_pos(tail_label_w, tail_w_ypos + l.gradient_row_height)
+            .with_size(2 * l.gradient_button_width, l.gradient_row_height);
         default_select.set_color(rgb_to_fltk(self.default_color));
-        default_select.set_tooltip("set default color");
+        default_select.set_tooltip("set the color of points that never escape");
+        let overflow_ypos = tail_w_ypos + (2 * l.gradient_row_height);
+        let mut overflow_choice = Choice::default().with_pos(0, overflow_ypos).with_size(
+            l.color_pane_width() - (4 * l.gradient_button_width),
+            l.gradient_row_height,
+        );
+        overflow_choice
+            .add_choice("past the palette: interior color|last color|wrap around|own color");
+        overflow_choice
+            .set_tooltip("how points that escape after the palette runs out are colored");
+        let mut iterations_input = IntInput::default()
+            .with_pos(
+                l.color_pane_width() - (4 * l.gradient_button_width),
+                overflow_ypos,
+            )
+            .with_size(2 * l.gradient_button_width, l.gradient_row_height);
+        iterations_input.set_value(&format!("{}", self.iterations));
+        describe_value(
+            &mut iterations_input,
+            ITERATIONS_NAME,
+            &format!("{}", self.iterations),
+        );
+        let mut overflow_select = Button::default()
+            .with_pos(tail_label_w, overflow_ypos)
+            .with_size(2 * l.gradient_button_width, l.gradient_row_height);
+        overflow_select.set_tooltip("set the color of points past the palette");
+        let (choice, color) = match self.overflow {
+            Overflow::Interior => (0, None),
+            Overflow::Clamp => (1, None),
+            Overflow::Wrap => (2, None),
+            Overflow::Color { color } => (3, Some(color)),
+        };
+        overflow_choice.set_value(choice);
+        // The button holds onto its color even when it's not being used.
+        let overflow_color = Rc::new(Cell::new(color.unwrap_or(self.default_color)));
+        overflow_select.set_color(rgb_to_fltk(overflow_color.get()));
+        if color.is_none() {
+            overflow_select.deactivate();
+        }
+        let transfer_ypos = tail_w_ypos + (3 * l.gradient_row_height);
+        let mut transfer_choice = Choice::default()
+            .with_pos(0, transfer_ypos)
+            .with_size(tail_label_w, l.gradient_row_height);
+        transfer_choice.add_choice("linear|logarithmic|square root|power");
+        transfer_choice.set_tooltip("how iteration counts are spread across the color map");
+        let mut exponent_input = FloatInput::default()
+            .with_pos(tail_label_w, transfer_ypos)
+            .with_size(2 * l.gradient_button_width, l.gradient_row_height);
+
+        let (choice, exponent) = match self.transfer {
+            Transfer::Linear => (0, None),
+            Transfer::Log => (1, None),
+            Transfer::Sqrt => (2, None),
+            Transfer::Power { exponent } => (3, Some(exponent)),
+        };
+        transfer_choice.set_value(choice);
+        exponent_input.set_value(&format!("{}", exponent.unwrap_or(DEFAULT_EXPONENT)));
+        let value = exponent_input.value();
+        describe_value(&mut exponent_input, EXPONENT_NAME, &value);
+        if exponent.is_none() {
+            exponent_input.deactivate();
+        }
+        let repeat_ypos = tail_w_ypos + (4 * l.gradient_row_height);
+        label(
+            "repeat palette",
+            (0, repeat_ypos),
+            (tail_label_w, l.gradient_row_height),
+        );
+        let mut repeat_input = IntInput::default()
+            .with_pos(tail_label_w, repeat_ypos)
+            .with_size(2 * l.gradient_button_width, l.gradient_row_height);
+        repeat_input.set_value(&format!("{}", self.repeats));
+        describe_value(&mut repeat_input, REPEAT_NAME, &format!("{}", self.repeats));
+        let phase_ypos = repeat_ypos + l.gradient_row_height;
+        label(
+            "phase",
+            (0, phase_ypos),
+            (2 * l.gradient_button_width, l.gradient_row_height),
+        );
+        let mut phase_slider = HorNiceSlider::default()
+            .with_pos(2 * l.gradient_button_width, phase_ypos)
+            .with_size(
+                l.color_pane_width() - (2 * l.gradient_button_width),
+                l.gradient_row_height,
+            );
+        phase_slider.set_range(0.0, 1.0);
+        phase_slider.set_value(self.phase);
+        describe_value(&mut phase_slider, PHASE_NAME, &format!("{:.3}", self.phase));
+        let tone_ypos = phase_ypos + l.gradient_row_height;
+        label(
+            "tone",
+            (0, tone_ypos),
+            (2 * l.gradient_button_width, l.gradient_row_height),
+        );
+        let tone_w = (l.color_pane_width() - (4 * l.gradient_button_width)) / 3;
+        let tone_input = |n: i32, name: &str, value: f64| {
+            let mut i = FloatInput::default()
+                .with_pos(2 * l.gradient_button_width + n * tone_w, tone_ypos)
+                .with_size(tone_w, l.gradient_row_height);
+            i.set_value(&format!("{}", value));
+            describe_value(&mut i, name, &format!("{}", value));
+            i
+        };
+        let mut gamma_input = tone_input(0, GAMMA_NAME, self.adjust.gamma);
+        let mut brightness_input = tone_input(1, BRIGHTNESS_NAME, self.adjust.brightness);
+        let mut contrast_input = tone_input(2, CONTRAST_NAME, self.adjust.contrast);
+        let mut curves_butt = Button::default()
+            .with_label("curves")
+            .with_pos(
+                l.color_pane_width() - (2 * l.gradient_button_width),
+                tone_ypos,
+            )
+            .with_size(2 * l.gradient_button_width, l.gradient_row_height);
+        curves_butt.set_tooltip("edit the red, green, and blue tone curves");
+        let shade_ypos = tone_ypos + l.gradient_row_height;
+        let mut shade_check = CheckButton::default()
+            .with_label("shade")
+            .with_pos(0, shade_ypos)
+            .with_size(2 * l.gradient_button_width, l.gradient_row_height);
+        shade_check.set_checked(self.shading.is_some());
+        shade_check.set_tooltip("light the image as if the iteration counts were heights");
+        let shade = self.shading.unwrap_or_default();
+        let shade_w = (l.color_pane_width() - (2 * l.gradient_button_width)) / 4;
+        let shade_input = |n: i32, name: &str, value: f64| {
+            let mut i = FloatInput::default()
+                .with_pos(2 * l.gradient_button_width + n * shade_w, shade_ypos)
+                .with_size(shade_w, l.gradient_row_height);
+            i.set_value(&format!("{}", value));
+            describe_value(&mut i, name, &format!("{}", value));
+            if self.shading.is_none() {
+                i.deactivate();
+            }
+            i
+        };
+        let mut shade_inputs = [
+            shade_input(0, AZIMUTH_NAME, shade.azimuth),
+            shade_input(1, ELEVATION_NAME, shade.elevation),
+            shade_input(2, DEPTH_NAME, shade.depth),
+            shade_input(3, STRENGTH_NAME, shade.strength),
+        ];
+        let trap_ypos = shade_ypos + l.gradient_row_height;
+        label(
+            "color by",
+            (0, trap_ypos),
+            (2 * l.gradient_button_width, l.gradient_row_height),
+        );
+        let mut trap_choice = Choice::default()
+            .with_pos(2 * l.gradient_button_width, trap_ypos)
+            .with_size(
+                l.color_pane_width() - (4 * l.gradient_button_width),
+                l.gradient_row_height,
+            );
+        trap_choice.add_choice(ORBIT_CHOICES);
+        trap_choice.set_tooltip(
+            "color by escape time, how close orbits come to a shape, or an average over them",
+        );
+        let (c, s) = (DEFAULT_TRAP.0, DEFAULT_TRAP.2);
+        let (choice, (center, size, scale)) = match self.orbit {
+            None => (0, DEFAULT_TRAP),
+            Some(Orbit::Trap(Trap::Point { center, scale })) => {
+                (1, (center, DEFAULT_TRAP.1, scale))
+            }
+            Some(Orbit::Trap(Trap::Line {
+                center,
+                angle_pi,
+                scale,
+            })) => (2, (center, angle_pi, scale)),
+            Some(Orbit::Trap(Trap::Circle {
+                center,
+                radius,
+                scale,
+            })) => (3, (center, radius, scale)),
+            Some(Orbit::Stat(OrbitStat::Stripe { density })) => (STRIPE_CHOICE, (c, density, s)),
+            Some(Orbit::Stat(OrbitStat::Triangle)) => (5, DEFAULT_TRAP),
+        };
+        trap_choice.set_value(choice);
+        let mut layers_butt = Button::default()
+            .with_label(&format!("layers ({})", self.layers.len()))
+            .with_pos(
+                l.color_pane_width() - (2 * l.gradient_button_width),
+                trap_ypos,
+            )
+            .with_size(2 * l.gradient_button_width, l.gradient_row_height);
+        layers_butt.set_tooltip("blend more colorings of the image over this one");
+        let trap_w = l.color_pane_width() / 4;
+        let trap_input = |n: i32, name: &str, value: f64| {
+            let mut i = FloatInput::default()
+                .with_pos(n * trap_w, trap_ypos + l.gradient_row_height)
+                .with_size(trap_w, l.gradient_row_height);
+            i.set_value(&format!("{}", value));
+            describe_value(&mut i, name, &format!("{}", value));
+            if !orbit_inputs_used(choice)[n as usize] {
+                i.deactivate();
+            }
+            i
+        };
+        let mut trap_inputs = [
+            trap_input(0, TRAP_RE_NAME, center.re),
+            trap_input(1, TRAP_IM_NAME, center.im),
+            trap_input(2, TRAP_SIZE_NAME, size),
+            trap_input(3, TRAP_SCALE_NAME, scale),
+        ];
+        let preset_ypos = trap_ypos + (2 * l.gradient_row_height);
+        label(
+            "preset",
+            (0, preset_ypos),
+            (2 * l.gradient_button_width, l.gradient_row_height),
+        );
+        let mut preset_choice = Choice::default()
+            .with_pos(2 * l.gradient_button_width, preset_ypos)
+            .with_size(
+                l.color_pane_width() - (4 * l.gradient_button_width),
+                l.gradient_row_height,
+            );
+        for name in presets::names().iter() {
+            preset_choice.add_choice(name);
+        }
+        preset_choice.set_tooltip("replace the color map with one of the built-in ones");
+        let mut import_butt = Button::default()
+            .with_label("import")
+            .with_pos(
+                l.color_pane_width() - (2 * l.gradient_button_width),
+                preset_ypos,
+            )
+            .with_size(2 * l.gradient_button_width, l.gradient_row_height);
+        import_butt.set_tooltip(
+            "replace the color map with the colors from a GIMP palette or a PNG palette strip",
+        );
+        let file_ypos = preset_ypos + l.gradient_row_height;
+        label(
+            "color map file",
+            (0, file_ypos),
+            (
+                l.color_pane_width() - (4 * l.gradient_button_width),
+                l.gradient_row_height,
+            ),
+        );
+        let mut save_butt = Button::default()
+            .with_label("save")
+            .with_pos(
+                l.color_pane_width() - (4 * l.gradient_button_width),
+                file_ypos,
+            )
+            .with_size(2 * l.gradient_button_width, l.gradient_row_height);
+        save_butt.set_tooltip(
+            "save just the color map, to use with other images (or as a PNG palette strip)",
+        );
+        let mut load_butt = Button::default()
+            .with_label("load")
+            .with_pos(
+                l.color_pane_width() - (2 * l.gradient_button_width),
+                file_ypos,
+            )
+            .with_size(2 * l.gradient_button_width, l.gradient_row_height);
+        load_butt.set_tooltip(
+            "replace the color map with one from a file, leaving the rest of the image alone",
+        );
+        let mut highlight_check = CheckButton::default()
+            .with_label("highlight on image")
+            .with_pos(0, file_ypos + l.gradient_row_height)
+            .with_size(
+                l.color_pane_width() - (2 * l.gradient_button_width),
+                l.gradient_row_height,
+            );
+        highlight_check.set_checked(self.highlight.get());
+        highlight_check.set_tooltip("show which pixels a gradient colors when hovering over it");
+        let mut check_butt = Button::default()
+            .with_label("check")
+            .with_pos(
+                l.color_pane_width() - (2 * l.gradient_button_width),
+                file_ypos + l.gradient_row_height,
+            )
+            .with_size(2 * l.gradient_button_width, l.gradient_row_height);
+        check_butt.set_tooltip("look for gradients too flat to see and harsh jumps between them");
         //~ tail_w.end();
 
-        self.win.end();
+        end_pane(&mut self.win);
         self.win.show();
+        if self.win.parent().is_none() {
+            self.win.set_opacity(l.color_pane.opacity);
+        }
 
         for ch in self.choosers.iter_mut() {
             ch.show();
         }
+        self.show_preview();
 
         append_butt.set_callback({
             let me = self.me.as_ref().unwrap().clone();
@@ -497,8 +1282,389 @@ impl ColorPaneGuts {
                 let old_c = me.borrow().default_color;
                 if let Some(c) = pick_color(old_c) {
                     me.borrow_mut().default_color = c;
+                    me.borrow_mut().show_preview();
+                    b.set_color(rgb_to_fltk(c));
+                    b.redraw();
+                }
+            }
+        });
+        transparent_check.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            let pipe = self.pipe.clone();
+            move |c| {
+                me.borrow_mut().transparent = c.is_checked();
+                pipe.send(Msg::Recolor).unwrap();
+            }
+        });
+        overflow_choice.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            let pipe = self.pipe.clone();
+            let overflow_color = overflow_color.clone();
+            let mut overflow_select = overflow_select.clone();
+            move |c| {
+                let o = match c.value() {
+                    1 => Overflow::Clamp,
+                    2 => Overflow::Wrap,
+                    3 => Overflow::Color {
+                        color: overflow_color.get(),
+                    },
+                    _ => Overflow::Interior,
+                };
+                if c.value() == 3 {
+                    overflow_select.activate();
+                } else {
+                    overflow_select.deactivate();
+                }
+                me.borrow_mut().overflow = o;
+                me.borrow_mut().show_preview();
+                pipe.send(Msg::Recolor).unwrap();
+            }
+        });
+        overflow_select.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            let pipe = self.pipe.clone();
+            move |b| {
+                if let Some(c) = pick_color(overflow_color.get()) {
+                    overflow_color.set(c);
+                    me.borrow_mut().overflow = Overflow::Color { color: c };
+                    me.borrow_mut().show_preview();
                     b.set_color(rgb_to_fltk(c));
                     b.redraw();
+                    pipe.send(Msg::Recolor).unwrap();
+                }
+            }
+        });
+        iterations_input.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            let pipe = self.pipe.clone();
+            move |i| {
+                let n = match i.value().trim().parse::<usize>() {
+                    Ok(n) => n,
+                    Err(_) => me.borrow().iterations,
+                };
+                i.set_value(&format!("{}", n));
+                describe_value(i, ITERATIONS_NAME, &format!("{}", n));
+                me.borrow_mut().iterations = n;
+                me.borrow_mut().show_preview();
+                pipe.send(Msg::Recolor).unwrap();
+            }
+        });
+        transfer_choice.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            let mut exponent_input = exponent_input.clone();
+            move |c| {
+                let t = match c.value() {
+                    1 => Transfer::Log,
+                    2 => Transfer::Sqrt,
+                    3 => Transfer::Power {
+                        exponent: parse_exponent(&mut exponent_input),
+                    },
+                    _ => Transfer::Linear,
+                };
+                if c.value() == 3 {
+                    exponent_input.activate();
+                } else {
+                    exponent_input.deactivate();
+                }
+                me.borrow_mut().transfer = t;
+                me.borrow_mut().show_preview();
+            }
+        });
+        exponent_input.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            let transfer_choice = transfer_choice.clone();
+            move |i| {
+                if transfer_choice.value() == 3 {
+                    me.borrow_mut().transfer = Transfer::Power {
+                        exponent: parse_exponent(i),
+                    };
+                    me.borrow_mut().show_preview();
+                }
+            }
+        });
+        repeat_input.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            move |i| {
+                let n = match i.value().trim().parse::<usize>() {
+                    Ok(n) => n.clamp(1, MAX_REPEATS),
+                    Err(_) => me.borrow().repeats,
+                };
+                i.set_value(&format!("{}", n));
+                describe_value(i, REPEAT_NAME, &format!("{}", n));
+                me.borrow_mut().repeats = n;
+                me.borrow_mut().show_preview();
+            }
+        });
+        phase_slider.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            let pipe = self.pipe.clone();
+            move |s| {
+                me.borrow_mut().phase = s.value();
+                me.borrow_mut().show_preview();
+                describe_value(s, PHASE_NAME, &format!("{:.3}", s.value()));
+                pipe.send(Msg::Recolor).unwrap();
+            }
+        });
+        // The three tone inputs all work the same way: whatever's typed in
+        // gets forced into range (and shown that way), and the image gets
+        // recolored.
+        let tone_callback = |name: &'static str, field: fn(&mut Adjustment) -> &mut f64| {
+            let me = self.me.as_ref().unwrap().clone();
+            let pipe = self.pipe.clone();
+            move |i: &mut FloatInput| {
+                let mut adjust = me.borrow().adjust;
+                if let Ok(x) = i.value().trim().parse::<f64>() {
+                    *field(&mut adjust) = x;
+                }
+                let mut adjust = adjust.clamped();
+                let value = *field(&mut adjust);
+                i.set_value(&format!("{}", value));
+                describe_value(i, name, &format!("{}", value));
+                me.borrow_mut().adjust = adjust;
+                me.borrow_mut().show_preview();
+                pipe.send(Msg::Recolor).unwrap();
+            }
+        };
+        gamma_input.set_callback(tone_callback(GAMMA_NAME, |a| &mut a.gamma));
+        brightness_input.set_callback(tone_callback(BRIGHTNESS_NAME, |a| &mut a.brightness));
+        contrast_input.set_callback(tone_callback(CONTRAST_NAME, |a| &mut a.contrast));
+        // Like the orbit coloring, the shading gets rebuilt from all its
+        // inputs whenever any of them (or the check box) changes.
+        let shade_callback = {
+            let me = self.me.as_ref().unwrap().clone();
+            let pipe = self.pipe.clone();
+            let check = shade_check.clone();
+            let inputs = shade_inputs.clone();
+            move || {
+                let mut inputs = inputs.clone();
+                for i in inputs.iter_mut() {
+                    if check.is_checked() {
+                        i.activate();
+                    } else {
+                        i.deactivate();
+                    }
+                }
+                let shading = read_shading(&mut inputs);
+                me.borrow_mut().shading = check.is_checked().then_some(shading);
+                pipe.send(Msg::Recolor).unwrap();
+            }
+        };
+        shade_check.set_callback({
+            let f = shade_callback.clone();
+            move |_| f()
+        });
+        for i in shade_inputs.iter_mut() {
+            let f = shade_callback.clone();
+            i.set_callback(move |_| f());
+        }
+        // The orbit choice and its inputs all just rebuild the orbit
+        // coloring from what they say; the inputs that don't mean anything
+        // for the chosen one get grayed out. Stripes per turn and trap
+        // sizes are different enough that switching between them starts
+        // the size over.
+        let trap_callback = {
+            let me = self.me.as_ref().unwrap().clone();
+            let pipe = self.pipe.clone();
+            let choice = trap_choice.clone();
+            let inputs = trap_inputs.clone();
+            move || {
+                let mut inputs = inputs.clone();
+                let choice = choice.value();
+                for (i, used) in inputs.iter_mut().zip(orbit_inputs_used(choice)) {
+                    if used {
+                        i.activate();
+                    } else {
+                        i.deactivate();
+                    }
+                }
+                let was_stripes = matches!(
+                    me.borrow().orbit,
+                    Some(Orbit::Stat(OrbitStat::Stripe { .. }))
+                );
+                if was_stripes != (choice == STRIPE_CHOICE) && (1..=STRIPE_CHOICE).contains(&choice)
+                {
+                    let size = if was_stripes {
+                        DEFAULT_TRAP.1
+                    } else {
+                        DEFAULT_DENSITY
+                    };
+                    inputs[2].set_value(&format!("{}", size));
+                }
+                me.borrow_mut().orbit = read_orbit(choice, &mut inputs);
+                pipe.send(Msg::Recolor).unwrap();
+            }
+        };
+        trap_choice.set_callback({
+            let f = trap_callback.clone();
+            move |_| f()
+        });
+        for i in trap_inputs.iter_mut() {
+            let f = trap_callback.clone();
+            i.set_callback(move |_| f());
+        }
+        layers_butt.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            let pipe = self.pipe.clone();
+            move |b| {
+                let old = me.borrow().layers.clone();
+                if let Some(layers) = super::layers::edit_layers(&old) {
+                    b.set_label(&format!("layers ({})", layers.len()));
+                    me.borrow_mut().layers = layers;
+                    pipe.send(Msg::Recolor).unwrap();
+                }
+            }
+        });
+        curves_butt.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            let pipe = self.pipe.clone();
+            move |_| {
+                let old = me.borrow().curves.clone();
+                if let Some(c) = super::curves::edit_curves(&old) {
+                    me.borrow_mut().curves = c;
+                    me.borrow_mut().show_preview();
+                    pipe.send(Msg::Recolor).unwrap();
+                }
+            }
+        });
+        preset_choice.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            move |c| {
+                if let Some(spec) = c.choice().and_then(|name| presets::get(&name)) {
+                    me.borrow_mut().respec(spec);
+                }
+            }
+        });
+        import_butt.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            move |_| {
+                let fname = match choose_file("Import a palette:", "Palettes\t*.{gpl,GPL,png,PNG}")
+                {
+                    Some(f) => f,
+                    None => {
+                        return;
+                    }
+                };
+                // PNGs are palette strips, with a color for every step.
+                if fname.to_ascii_lowercase().ends_with(".png") {
+                    match rw::load_strip(&fname) {
+                        Ok(pixels) => {
+                            let default = me.borrow().default_color;
+                            me.borrow_mut()
+                                .respec(ColorSpec::new(rw::strip_gradients(&pixels), default));
+                        }
+                        Err(e) => {
+                            dialog::alert_default(&format!("Error importing {}: {}", &fname, &e));
+                        }
+                    }
+                    return;
+                }
+                let colors = match rw::load_gpl(&fname) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        dialog::alert_default(&format!("Error importing {}: {}", &fname, &e));
+                        return;
+                    }
+                };
+                let steps = match dialog::choice2_default(
+                    "Import the palette's colors as:",
+                    "One step each",
+                    "Blended",
+                    "",
+                ) {
+                    Some(0) => 1,
+                    Some(1) => PALETTE_BLEND_STEPS,
+                    _ => {
+                        return;
+                    }
+                };
+                let default = me.borrow().default_color;
+                me.borrow_mut()
+                    .respec(ColorSpec::from_colors(&colors, steps, default));
+            }
+        });
+        reverse_butt.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            move |_| {
+                let spec = me.borrow().spec().reversed();
+                me.borrow_mut().respec(spec);
+            }
+        });
+        save_butt.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            move |_| {
+                let mut fname = match pick_a_file(".toml", FileMode::SaveAny) {
+                    Some(f) => f,
+                    None => {
+                        return;
+                    }
+                };
+                let spec = me.borrow().spec();
+                // Naming it .png saves a palette strip instead.
+                let saved = if fname.to_ascii_lowercase().ends_with(".png") {
+                    rw::save_strip(&spec.to_map(), &fname)
+                } else {
+                    if !rw::ParamFormat::is_param_file(&fname) {
+                        fname.push_str(".toml");
+                    }
+                    rw::save_colors(&spec, &fname)
+                };
+                if let Err(e) = saved {
+                    dialog::alert_default(&format!("Error saving {}: {}", &fname, &e));
+                }
+            }
+        });
+        load_butt.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            move |_| {
+                let fname =
+                    match choose_file("Load a color map:", "Color maps\t*.{toml,json,png,ugr,cpt}")
+                    {
+                        Some(f) => f,
+                        None => {
+                            return;
+                        }
+                    };
+                match rw::load_colors(&fname) {
+                    Ok(spec) => me.borrow_mut().respec(spec),
+                    Err(e) => {
+                        dialog::alert_default(&format!("Error loading {}: {}", &fname, &e));
+                    }
+                }
+            }
+        });
+        check_butt.set_callback({
+            let me = self.me.as_ref().unwrap().clone();
+            move |_| {
+                let spec = me.borrow().spec();
+                let problems =
+                    contrast::analyze(&spec, contrast::JUST_NOTICEABLE, contrast::DEFAULT_HARSH);
+                if problems.is_empty() {
+                    dialog::message_default("No problems found.");
+                    return;
+                }
+
+                let mut report = String::new();
+                for p in problems.iter().take(MAX_REPORTED_PROBLEMS) {
+                    report.push_str(&format!("{}\n    {}\n", p.describe(), p.suggestion()));
+                }
+                if problems.len() > MAX_REPORTED_PROBLEMS {
+                    report.push_str(&format!(
+                        "...and {} more.\n",
+                        problems.len() - MAX_REPORTED_PROBLEMS
+                    ));
+                }
+                if let Some(1) = dialog::choice2_default(&report, "Leave it", "Fix them all", "") {
+                    me.borrow_mut().respec(contrast::fix_all(&spec, &problems));
+                }
+            }
+        });
+        highlight_check.set_callback({
+            let highlight = self.highlight.clone();
+            let pipe = self.pipe.clone();
+            move |b| {
+                highlight.set(b.is_checked());
+                if !b.is_checked() {
+                    pipe.send(Msg::HighlightGradient(None)).unwrap();
                 }
             }
         });
@@ -510,6 +1676,7 @@ impl ColorPaneGuts {
                     if let Some(c) = drag_color.get() {
                         b.set_color(rgb_to_fltk(c));
                         me.borrow_mut().default_color = c;
+                        me.borrow_mut().show_preview();
                         b.redraw();
                         true
                     } else {
@@ -531,6 +1698,49 @@ impl ColorPaneGuts {
         });
     }
 
+    // Make a `GradientChooser` for `g` that updates the preview strip
+    // whenever it's changed.
+    fn chooser(&self, g: Gradient) -> GradientChooser {
+        let me = self.me.as_ref().unwrap().clone();
+        let changed = move || {
+            if let Ok(mut me) = me.try_borrow_mut() {
+                me.show_preview();
+            }
+        };
+        GradientChooser::new(g, self.drag_color.clone(), Rc::new(changed))
+    }
+
+    // Show the whole color map as it currently stands in the preview
+    // strip: every color from the first iteration to the limit (however
+    // many times the palette repeats), then a block of the default color.
+    fn show_preview(&mut self) {
+        let l = layout::current();
+        let (w, h) = match self.preview.as_ref() {
+            Some(p) => (p.w(), p.h()),
+            None => {
+                return;
+            }
+        };
+        let default_w = l.gradient_button_width.min(w);
+        let mut row: Vec<u8> = Vec::with_capacity(3 * w as usize);
+        let cmap = self.spec().to_map();
+        let mut colors = cmap.sample((w - default_w) as usize);
+        colors.extend(std::iter::repeat_n(self.default_color, default_w as usize));
+        cmap.finish(&mut colors);
+        for c in colors.into_iter() {
+            row.extend_from_slice(&c.to_rgb8());
+        }
+
+        let data = row.repeat(h as usize);
+        if let (Some(p), Ok(img)) = (
+            self.preview.as_mut(),
+            RgbImage::new(&data, w, h, ColorDepth::Rgb8),
+        ) {
+            p.set_image(Some(img));
+            p.redraw();
+        }
+    }
+
     // Insert a new `GradientChooser` at position `n`. If `n` is larger
     // than the current `Vec` of `GradientChooser`s, it will just be
     // appended. This behavor is relied upon.
@@ -559,13 +1769,75 @@ impl ColorPaneGuts {
             start: new_start,
             end: new_end,
             steps: 256,
+            space: Interpolation::Rgb,
         };
-        let gc = GradientChooser::new(g, self.drag_color.clone());
+        let gc = self.chooser(g);
         self.choosers.insert(n, gc);
 
         self.redraw();
     }
 
+    // Insert a copy of the `GradientChooser` at position `n` right after
+    // it, if it exists.
+    fn duplicate(&mut self, n: usize) {
+        if let Some(g) = self.choosers.get(n).map(|ch| ch.get_gradient()) {
+            let gc = self.chooser(g);
+            self.choosers.insert(n + 1, gc);
+            self.redraw();
+        }
+    }
+
+    // Swap the `GradientChooser`s at positions `a` and `b`, if they both
+    // exist.
+    fn swap(&mut self, a: usize, b: usize) {
+        if a < self.choosers.len() && b < self.choosers.len() {
+            self.choosers.swap(a, b);
+            self.redraw();
+        }
+    }
+
+    // Replace everything with the given `ColorSpec`.
+    fn respec(&mut self, new_spec: ColorSpec) {
+        self.default_color = new_spec.default();
+        self.overflow = new_spec.overflow();
+        self.transparent = new_spec.transparent();
+        self.iterations = new_spec.iterations();
+        self.transfer = new_spec.transfer();
+        self.repeats = new_spec.repeats();
+        self.phase = new_spec.phase();
+        self.adjust = new_spec.adjustment();
+        self.curves = new_spec.curves().clone();
+        self.shading = new_spec.shading();
+        self.orbit = new_spec.orbit();
+        self.layers = new_spec.layers().to_vec();
+        self.clear();
+        for grad in new_spec.gradients().into_iter() {
+            let gc = self.chooser(grad);
+            self.choosers.push(gc);
+        }
+        self.redraw();
+    }
+
+    // The spec for what's currently shown.
+    fn spec(&self) -> ColorSpec {
+        let mut spec = ColorSpec::new(
+            self.choosers.iter().map(|ch| ch.get_gradient()).collect(),
+            self.default_color,
+        );
+        spec.set_overflow(self.overflow);
+        spec.set_transparent(self.transparent);
+        spec.set_iterations(self.iterations);
+        spec.set_transfer(self.transfer);
+        spec.set_repeats(self.repeats);
+        spec.set_phase(self.phase);
+        spec.set_adjustment(self.adjust);
+        spec.set_curves(self.curves.clone());
+        spec.set_shading(self.shading);
+        spec.set_orbit(self.orbit);
+        spec.set_layers(self.layers.clone());
+        spec
+    }
+
     // Append a `GradientChooser` to the end.
     fn append(&mut self) {
         self.insert(self.choosers.len());
@@ -610,31 +1882,39 @@ pub struct ColorPane {
 impl ColorPane {
     /** Instantiate a new `ColorPane` with the provided specification. */
     pub fn new(spec: ColorSpec, pipe: mpsc::Sender<Msg>) -> ColorPane {
-        let def = spec.default();
-        let cpg = ColorPaneGuts::new(spec.gradients(), def, pipe);
+        let cpg = ColorPaneGuts::new(&spec, pipe);
         cpg.borrow_mut().redraw();
         ColorPane { guts: cpg }
     }
 
     /** Get the `ColorSpec` currently specified by the `ColorPane`. */
     pub fn get_spec(&self) -> ColorSpec {
-        let g = self.guts.borrow();
-        ColorSpec::new(
-            g.choosers.iter().map(|ch| ch.get_gradient()).collect(),
-            g.default_color,
-        )
+        self.guts.borrow().spec()
     }
 
     pub fn respec(&mut self, new_spec: ColorSpec) {
-        let new_default = new_spec.default();
-        let mut g = self.guts.borrow_mut();
-        g.default_color = new_default;
-        g.clear();
-        for grad in new_spec.gradients().into_iter() {
-            let gc = GradientChooser::new(grad, g.drag_color.clone());
-            g.choosers.push(gc);
+        self.guts.borrow_mut().respec(new_spec);
+    }
+
+    /** Where the window is on the screen (`None` if it's docked). */
+    pub fn position(&self) -> Option<(i32, i32)> {
+        let w = &self.guts.borrow().win;
+        match w.parent() {
+            Some(_) => None,
+            None => Some((w.x(), w.y())),
+        }
+    }
+
+    /**
+    Move the window to (`x`, `y`), or as near as it can go and still be on
+    a screen. Docked panes stay put.
+    */
+    pub fn move_to(&mut self, x: i32, y: i32) {
+        let w = &mut self.guts.borrow_mut().win;
+        if w.parent().is_none() {
+            let (x, y) = fit_on_screen(x, y, w.w(), w.h());
+            w.set_pos(x, y);
         }
-        g.redraw();
     }
 
     /**
@@ -647,10 +1927,14 @@ impl ColorPane {
     `hide_before_raise` feature.
     */
     pub fn raise(&mut self) {
+        if show_docked(Dock::ColorMap) {
+            return;
+        }
         let w = &mut self.guts.borrow_mut().win;
         #[cfg(feature = "hide_before_raise")]
         w.hide();
         w.show();
+        w.set_opacity(layout::current().color_pane.opacity);
     }
 }
 
@@ -659,6 +1943,46 @@ mod test {
     use super::*;
 
     #[test]
+    fn picker_channels() {
+        let red = PickerState::new(RGB::new(255.0, 0.0, 0.0));
+        let green = Channel::Hue.set(&red, 120.0);
+        assert_eq!(green.rgb, RGB::new(0.0, 255.0, 0.0));
+        assert_eq!(Channel::Green.get(&green), 255.0);
+        assert_eq!(Channel::Red.set(&red, 300.0).rgb, red.rgb);
+
+        // Colors without a hue (or saturation) keep the ones they had.
+        let white = Channel::Saturation.set(&green, 0.0);
+        assert_eq!(white.rgb, RGB::WHITE);
+        assert_eq!(Channel::Hue.get(&white), 120.0);
+        assert_eq!(Channel::Saturation.set(&white, 100.0).rgb, green.rgb);
+        let black = Channel::Green.set(&green, 0.0);
+        assert_eq!(black.hsv, (120.0, 1.0, 0.0));
+        assert_eq!(Channel::Value.set(&black, 100.0), green);
+        let gray = Channel::Red.set(&Channel::Blue.set(&black, 20.0), 20.0);
+        let gray = Channel::Green.set(&gray, 20.0);
+        assert_eq!((Channel::Hue.get(&gray), gray.hsv.1), (300.0, 0.0));
+    }
+
+    #[test]
+    fn remembered_colors() {
+        let red = RGB::new(255.0, 0.0, 0.0);
+        for n in 0..(2 * RECENT_COLORS) {
+            remember_color(RGB::new(n as f32, 0.0, 0.0));
+        }
+        remember_color(red);
+        remember_color(RGB::BLACK);
+        remember_color(red);
+        let recent = recent_colors();
+        assert_eq!(recent.len(), RECENT_COLORS);
+        assert_eq!(recent[..2], [red, RGB::BLACK]);
+        assert_eq!(
+            recent[2],
+            RGB::new((2 * RECENT_COLORS - 1) as f32, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    #[ignore = "interactive; opens a window and waits for it to be closed"]
     fn pick_a_color() {
         let a = fltk::app::App::default();
         let c = pick_color(RGB::BLACK);
@@ -667,14 +1991,16 @@ mod test {
     }
 
     #[test]
+    #[ignore = "interactive; opens a window and waits for it to be closed"]
     fn query_gradient() {
         let a = fltk::app::App::default();
         let g = Gradient {
             start: RGB::BLACK,
             end: RGB::WHITE,
             steps: 256,
+            space: Interpolation::Rgb,
         };
-        let mut gc = GradientChooser::new(g);
+        let mut gc = GradientChooser::new(g, Rc::new(Cell::new(None)), Rc::new(|| {}));
 
         let mut w = DoubleWindow::default().with_size(256, 128);
         w.add(gc.get_win());
@@ -683,26 +2009,26 @@ mod test {
         w.end();
         w.show();
 
-        setup_subwindow_behavior(&mut w);
+        let (tx, _rx) = mpsc::channel::<Msg>();
+        setup_subwindow_behavior(&mut w, tx);
 
-        const K: Key = Key::from_char(' ');
+        const _K: Key = Key::from_char(' ');
 
         while a.wait() {
-            match fltk::app::event() {
-                Event::KeyDown => {
-                    let k = fltk::app::event_key();
-                    println!("{:?}", &k);
-                }
-                _ => {}
+            if fltk::app::event() == Event::KeyDown {
+                let k = fltk::app::event_key();
+                println!("{:?}", &k);
             }
         }
     }
 
     #[test]
+    #[ignore = "interactive; opens a window and waits for it to be closed"]
     fn color_pane() {
         let a = fltk::app::App::default();
-        let gv = vec![Gradient::default()];
-        let p = ColorPane::new(gv, RGB::WHITE);
+        let spec = ColorSpec::new(vec![Gradient::default()], RGB::WHITE);
+        let (tx, _rx) = mpsc::channel::<Msg>();
+        let p = ColorPane::new(spec, tx);
 
         let mut w = DoubleWindow::default().with_size(100, 100);
         let mut b = Button::default()
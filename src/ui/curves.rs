@@ -0,0 +1,393 @@
+/*!
+A popup window for editing the tone curves applied to the finished image
+(see the `curves` module).
+
+The curves are drawn on a graph, input level across and output level up.
+Clicking on the graph adds a control point to the curve of the channel
+being edited, dragging a point moves it, and right-clicking one removes
+it.
+*/
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc;
+
+use fltk::{
+    app::MouseButton,
+    button::Button,
+    enums::{ColorDepth, Event, Key, Shortcut},
+    frame::Frame,
+    image::RgbImage,
+    menu::Choice,
+    prelude::*,
+    window::DoubleWindow,
+};
+
+use super::layout;
+use crate::curves::{Curve, Curves, MAX_POINTS, MIN_GAP};
+
+// How close (in pixels) a click has to be to a control point to grab it.
+const GRAB_RADIUS: i32 = 6;
+// Half the width of the square drawn for a control point.
+const POINT_SIZE: i32 = 3;
+const BACKGROUND: [u8; 3] = [24, 24, 24];
+const GRID: [u8; 3] = [64, 64, 64];
+// The colors each channel's curve is drawn in; the curves of the other
+// channels are drawn dimmer than the one being edited.
+const CHANNEL_COLORS: [[u8; 3]; 3] = [[255, 72, 72], [72, 255, 72], [104, 104, 255]];
+
+// The points of all three curves, and what's being done to them.
+struct EditorState {
+    points: [Vec<[f64; 2]>; 3],
+    channel: usize,
+    // The index of the point being dragged, if one is.
+    grabbed: Option<usize>,
+}
+
+impl EditorState {
+    fn new(curves: &Curves) -> EditorState {
+        EditorState {
+            points: [
+                curves.red.points().to_vec(),
+                curves.green.points().to_vec(),
+                curves.blue.points().to_vec(),
+            ],
+            channel: 0,
+            grabbed: None,
+        }
+    }
+
+    fn curves(&self) -> Curves {
+        Curves {
+            red: Curve::new(self.points[0].clone()),
+            green: Curve::new(self.points[1].clone()),
+            blue: Curve::new(self.points[2].clone()),
+        }
+    }
+
+    // The index of the point of the current curve within `GRAB_RADIUS`
+    // pixels of (`x`, `y`) on a graph `size` pixels square, if there is one.
+    fn point_near(&self, x: i32, y: i32, size: i32) -> Option<usize> {
+        self.points[self.channel]
+            .iter()
+            .map(|p| to_pixel(*p, size))
+            .position(|(px, py)| (px - x).abs() <= GRAB_RADIUS && (py - y).abs() <= GRAB_RADIUS)
+    }
+
+    // Move the grabbed point as near to `level` as it can go: the end
+    // points only go up and down, and the others have to stay between
+    // their neighbors.
+    fn drag_to(&mut self, level: [f64; 2]) {
+        let points = &mut self.points[self.channel];
+        let n = match self.grabbed {
+            Some(n) if n < points.len() => n,
+            _ => {
+                return;
+            }
+        };
+        let x = if n == 0 || n == points.len() - 1 {
+            points[n][0]
+        } else {
+            let (lo, hi) = (points[n - 1][0] + MIN_GAP, points[n + 1][0] - MIN_GAP);
+            // Neighbors closer together than that (from a loaded file, say)
+            // leave it nowhere to go sideways.
+            if lo <= hi {
+                level[0].clamp(lo, hi)
+            } else {
+                points[n][0]
+            }
+        };
+        points[n] = [x, level[1].clamp(0.0, 1.0)];
+    }
+
+    // Add a point to the current curve at `level` (and grab it), unless
+    // it already has as many as it can.
+    fn add(&mut self, level: [f64; 2]) {
+        let points = &mut self.points[self.channel];
+        if points.len() >= MAX_POINTS {
+            return;
+        }
+        let n = points.partition_point(|p| p[0] < level[0]);
+        let fits = n > 0
+            && n < points.len()
+            && level[0] - points[n - 1][0] >= MIN_GAP
+            && points[n][0] - level[0] >= MIN_GAP;
+        if fits {
+            points.insert(n, [level[0], level[1].clamp(0.0, 1.0)]);
+            self.grabbed = Some(n);
+        }
+    }
+
+    // Remove point `n` of the current curve, unless it's one of the ends.
+    fn remove(&mut self, n: usize) {
+        let points = &mut self.points[self.channel];
+        if n > 0 && n + 1 < points.len() {
+            points.remove(n);
+        }
+    }
+}
+
+// Where the level `p` is on a graph `size` pixels square.
+fn to_pixel(p: [f64; 2], size: i32) -> (i32, i32) {
+    let last = (size - 1) as f64;
+    (
+        (p[0] * last).round() as i32,
+        ((1.0 - p[1]) * last).round() as i32,
+    )
+}
+
+// The level at pixel (`x`, `y`) of a graph `size` pixels square.
+fn to_level(x: i32, y: i32, size: i32) -> [f64; 2] {
+    let last = (size - 1) as f64;
+    [
+        ((x as f64) / last).clamp(0.0, 1.0),
+        (1.0 - (y as f64) / last).clamp(0.0, 1.0),
+    ]
+}
+
+// Draw the graph of the curves, as RGB data `size` pixels square.
+fn render_graph(state: &EditorState, size: i32) -> Vec<u8> {
+    let s = size.max(2) as usize;
+    let mut data: Vec<u8> = BACKGROUND.repeat(s * s);
+    let mut plot = |x: i32, y: i32, c: [u8; 3]| {
+        if (0..size).contains(&x) && (0..size).contains(&y) {
+            let n = 3 * ((y as usize) * s + (x as usize));
+            data[n..(n + 3)].copy_from_slice(&c);
+        }
+    };
+
+    for q in 1..4 {
+        let at = q * (size - 1) / 4;
+        for i in 0..size {
+            plot(at, i, GRID);
+            plot(i, at, GRID);
+        }
+    }
+    for i in 0..size {
+        plot(i, size - 1 - i, GRID);
+    }
+
+    // The curve being edited goes on top.
+    let order = (1..4).map(|k| (state.channel + k) % 3);
+    for ch in order {
+        let color = if ch == state.channel {
+            CHANNEL_COLORS[ch]
+        } else {
+            CHANNEL_COLORS[ch].map(|v| v / 2)
+        };
+        let ys: Vec<i32> = Curve::new(state.points[ch].clone())
+            .sample(s)
+            .into_iter()
+            .map(|y| to_pixel([0.0, y], size).1)
+            .collect();
+        // Fill in between neighboring columns, so steep parts don't
+        // break up into dots.
+        for x in 0..s {
+            let (y0, y1) = (ys[x], ys[x.saturating_sub(1)]);
+            for y in y0.min(y1)..=y0.max(y1) {
+                plot(x as i32, y, color);
+            }
+        }
+    }
+
+    for p in state.points[state.channel].iter() {
+        let (px, py) = to_pixel(*p, size);
+        for dy in -POINT_SIZE..=POINT_SIZE {
+            for dx in -POINT_SIZE..=POINT_SIZE {
+                plot(px + dx, py + dy, [255, 255, 255]);
+            }
+        }
+    }
+
+    data
+}
+
+// Show the current state of the curves in `graph`.
+fn show_graph(graph: &mut Frame, state: &EditorState) {
+    let size = layout::current().curve_graph_size;
+    let data = render_graph(state, size);
+    if let Ok(img) = RgbImage::new(&data, size, size, ColorDepth::Rgb8) {
+        graph.set_image(Some(img));
+        graph.redraw();
+    }
+}
+
+/**
+Pops up a modal window for editing the tone curves, starting with `start`.
+Returns the edited curves, or `None` if the editing is cancelled.
+*/
+pub fn edit_curves(start: &Curves) -> Option<Curves> {
+    let l = layout::current();
+    let size = l.curve_graph_size;
+    let row_h = l.picker_row_height;
+    let button_w = size / 4;
+    let state = Rc::new(RefCell::new(EditorState::new(start)));
+
+    let mut w = DoubleWindow::default()
+        .with_label("Tone Curves")
+        .with_size(size, size + row_h);
+    let mut graph = Frame::default().with_pos(0, 0).with_size(size, size);
+    graph.set_tooltip(
+        "click to add a point, drag a point to move it, or right-click a point to remove it",
+    );
+    let mut channel = Choice::default()
+        .with_pos(0, size)
+        .with_size(button_w, row_h);
+    channel.add_choice("red|green|blue");
+    channel.set_value(0);
+    channel.set_tooltip("the channel whose curve is being edited");
+    let mut reset = Button::default()
+        .with_label("reset")
+        .with_pos(button_w, size)
+        .with_size(button_w, row_h);
+    reset.set_tooltip("straighten out this channel's curve");
+    let mut ok = Button::default()
+        .with_label("Set @returnarrow")
+        .with_pos(2 * button_w, size)
+        .with_size(button_w, row_h);
+    ok.set_shortcut(Shortcut::from_key(Key::Enter));
+    let mut no = Button::default()
+        .with_label("Cancel")
+        .with_pos(3 * button_w, size)
+        .with_size(size - 3 * button_w, row_h);
+    no.set_shortcut(Shortcut::from_key(Key::Escape));
+
+    w.end();
+    w.make_modal(true);
+    w.show();
+    show_graph(&mut graph, &state.borrow());
+
+    graph.handle({
+        let state = state.clone();
+        move |g, evt| {
+            let (x, y) = fltk::app::event_coords();
+            let (x, y) = (x - g.x(), y - g.y());
+            let mut st = state.borrow_mut();
+            match evt {
+                Event::Push => {
+                    let near = st.point_near(x, y, size);
+                    if fltk::app::event_mouse_button() == MouseButton::Right {
+                        if let Some(n) = near {
+                            st.remove(n);
+                        }
+                    } else if near.is_some() {
+                        st.grabbed = near;
+                    } else {
+                        st.add(to_level(x, y, size));
+                    }
+                }
+                Event::Drag => {
+                    st.drag_to(to_level(x, y, size));
+                }
+                Event::Released => {
+                    st.grabbed = None;
+                }
+                _ => {
+                    return false;
+                }
+            }
+            show_graph(g, &st);
+            true
+        }
+    });
+    channel.set_callback({
+        let state = state.clone();
+        let mut graph = graph.clone();
+        move |c| {
+            let mut st = state.borrow_mut();
+            st.channel = c.value().clamp(0, 2) as usize;
+            show_graph(&mut graph, &st);
+        }
+    });
+    reset.set_callback({
+        let state = state.clone();
+        move |_| {
+            let mut st = state.borrow_mut();
+            let ch = st.channel;
+            st.points[ch] = Curve::identity().points().to_vec();
+            show_graph(&mut graph, &st);
+        }
+    });
+
+    let (tx, rx) = mpsc::channel::<Option<Curves>>();
+    ok.set_callback({
+        let tx = tx.clone();
+        move |_| {
+            tx.send(Some(state.borrow().curves())).unwrap();
+        }
+    });
+    no.set_callback(move |_| {
+        tx.send(None).unwrap();
+    });
+
+    while match rx.try_recv() {
+        Err(_) => true,
+        Ok(c) => {
+            DoubleWindow::delete(w);
+            return c;
+        }
+    } {
+        fltk::app::wait();
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn editing_points() {
+        let mut st = EditorState::new(&Curves::default());
+        st.channel = 1;
+        st.add([0.5, 0.8]);
+        assert_eq!(st.grabbed, Some(1));
+        // Too close to another point.
+        st.add([0.505, 0.1]);
+        assert_eq!(st.points[1].len(), 3);
+
+        // Points in the middle stay between their neighbors; the ends
+        // only move up and down.
+        st.drag_to([1.5, -1.0]);
+        assert_eq!(st.points[1][1], [1.0 - MIN_GAP, 0.0]);
+        st.grabbed = Some(0);
+        st.drag_to([0.3, 0.2]);
+        assert_eq!(st.points[1][0], [0.0, 0.2]);
+        // Squeezed in between two points it can't keep its distance from,
+        // it can still go up and down.
+        let squeezed = vec![
+            [0.0, 0.0],
+            [0.5, 0.5],
+            [0.5 + 0.75 * MIN_GAP, 0.6],
+            [0.5 + 1.5 * MIN_GAP, 0.7],
+            [1.0, 1.0],
+        ];
+        st.points[2] = squeezed.clone();
+        st.channel = 2;
+        st.grabbed = Some(2);
+        st.drag_to([0.9, 0.9]);
+        assert_eq!(st.points[2][2], [squeezed[2][0], 0.9]);
+        st.points[2] = Curves::default().blue.points().to_vec();
+        st.channel = 1;
+
+        let size = 101;
+        assert_eq!(to_level(50, 50, size), [0.5, 0.5]);
+        assert_eq!(st.point_near(2, 80, size), Some(0));
+        assert_eq!(st.point_near(50, 50, size), None);
+
+        st.remove(0);
+        st.remove(1);
+        assert_eq!(st.points[1].len(), 2);
+        let curves = st.curves();
+        assert!(curves.red.is_identity() && curves.blue.is_identity());
+        assert_eq!(curves.green.points(), &[[0.0, 0.2], [1.0, 1.0]]);
+        assert_eq!(render_graph(&st, size).len(), 3 * 101 * 101);
+    }
+
+    #[test]
+    #[ignore = "interactive; opens a window and waits for it to be closed"]
+    fn curve_editor() {
+        let _a = fltk::app::App::default();
+        println!("{:?}", edit_curves(&Curves::default()));
+    }
+}
@@ -5,29 +5,197 @@ displays the image and controls navigation and zooming.
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::mpsc;
+use std::time::Instant;
 
 use fltk::{
-    button::{Button, RadioRoundButton},
-    enums::{Color, ColorDepth, Key},
+    app::MouseButton,
+    button::{Button, CheckButton, RadioRoundButton},
+    draw,
+    enums::{Align, Color, ColorDepth, Key, Shortcut},
     frame::Frame,
-    group::{Pack, PackType, Scroll, ScrollType},
+    group::{Group, Pack, PackType, Scroll, ScrollType, Tabs},
     image::RgbImage,
     input::IntInput,
+    menu::{Choice, MenuBar, MenuButton, MenuFlag},
+    misc::Progress,
     valuator::ValueInput,
     window::DoubleWindow,
 };
 
+use super::layout::{ControlStyle, Layout, PanePlacement};
 use super::*;
+use crate::render::{short_duration, ProgressClock};
 
-const COL_WIDTH: i32 = 72;
-const ROW_HEIGHT: i32 = 24;
-const COL_HEIGHT: i32 = ROW_HEIGHT * 22;
-const HALF_BUTTON: i32 = COL_WIDTH / 2;
+// The number of rows tall the column of controls is.
+const CONTROL_ROWS: i32 = 33;
 const N_SCALERS: usize = 5;
 const MIN_DIMENSION: usize = 16;
 
 const DEFAULT_ZOOM: f64 = 2.0;
 const DEFAULT_NUDGE: f64 = 10.0;
+// How bright pixels outside a highlight overlay are, relative to normal.
+const OVERLAY_DIM: f32 = 0.25;
+// How many times farther the view moves when an arrow key is pressed
+// with Ctrl held down.
+const BIG_NUDGE: f64 = 10.0;
+// How far (in pixels) the mouse has to be dragged across the image for it
+// to count as a drag rather than a click. (To select a region to zoom in
+// on, it has to go this far both across and down.)
+const MIN_BAND: i32 = 4;
+
+// The least room (in rows) left for docked panes under the minimap.
+const DOCK_ROWS: i32 = 16;
+
+// The menu items that get switched on and off.
+const BACK_ITEM: &str = "View/Back";
+const FORWARD_ITEM: &str = "View/Forward";
+
+// The menu items that just send a message: where they go in the menu bar,
+// their shortcuts, and any flags (like a divider after them). The rest
+// (examples, zooming, scales, pasting, quitting) need more than that.
+fn menu_items() -> Vec<(&'static str, Shortcut, MenuFlag, Msg)> {
+    let none = MenuFlag::Normal;
+    vec![
+        (
+            "File/Load Values...",
+            Shortcut::Command | 'o',
+            none,
+            Msg::Load,
+        ),
+        (
+            "File/Save Image...",
+            Shortcut::Command | 's',
+            none,
+            Msg::SaveImage,
+        ),
+        (
+            "File/Save Image Set...",
+            Shortcut::None,
+            none,
+            Msg::ExportSet,
+        ),
+        (
+            "File/Save Values...",
+            Shortcut::Command | Shortcut::Shift | 's',
+            none,
+            Msg::SaveValues,
+        ),
+        (
+            "File/Load Session...",
+            Shortcut::None,
+            none,
+            Msg::LoadSession,
+        ),
+        (
+            "File/Save Session...",
+            Shortcut::None,
+            MenuFlag::MenuDivider,
+            Msg::SaveSession,
+        ),
+        (
+            "File/Copy Share String",
+            Shortcut::None,
+            none,
+            Msg::CopyShareString,
+        ),
+        (
+            "File/Load Share String...",
+            Shortcut::None,
+            MenuFlag::MenuDivider,
+            Msg::LoadShareString,
+        ),
+        (
+            "File/Strip Metadata...",
+            Shortcut::None,
+            none,
+            Msg::StripMetadata,
+        ),
+        (
+            "File/Run Script...",
+            Shortcut::None,
+            MenuFlag::MenuDivider,
+            Msg::RunScript,
+        ),
+        (
+            "File/Animation/Add Keyframe",
+            Shortcut::Command | 'k',
+            none,
+            Msg::AddKeyframe,
+        ),
+        (
+            "File/Animation/Clear Keyframes",
+            Shortcut::None,
+            MenuFlag::MenuDivider,
+            Msg::ClearKeyframes,
+        ),
+        (
+            "File/Animation/Render Frames...",
+            Shortcut::None,
+            none,
+            Msg::RenderAnimation,
+        ),
+        (
+            "File/Animation/Morph Between Files...",
+            Shortcut::None,
+            none,
+            Msg::Morph,
+        ),
+        (
+            "File/Animation/Color Cycle Frames...",
+            Shortcut::None,
+            MenuFlag::MenuDivider,
+            Msg::ExportCycle,
+        ),
+        (
+            "File/Animation/Stop Rendering",
+            Shortcut::None,
+            none,
+            Msg::Cancel,
+        ),
+        ("Edit/Undo", Shortcut::Command | 'z', none, Msg::Undo),
+        ("Edit/Redo", Shortcut::Command | 'y', none, Msg::Redo),
+        (
+            "View/Color Map Pane",
+            Shortcut::None,
+            none,
+            Msg::FocusColorPane,
+        ),
+        (
+            "View/Iterator Pane",
+            Shortcut::None,
+            MenuFlag::MenuDivider,
+            Msg::FocusIterPane,
+        ),
+        (
+            BACK_ITEM,
+            Shortcut::Alt | Key::Left,
+            MenuFlag::Inactive,
+            Msg::ViewBack,
+        ),
+        (
+            FORWARD_ITEM,
+            Shortcut::Alt | Key::Right,
+            MenuFlag::Inactive | MenuFlag::MenuDivider,
+            Msg::ViewForward,
+        ),
+        (
+            "View/Cycle Colors",
+            Shortcut::None,
+            MenuFlag::Toggle | MenuFlag::MenuDivider,
+            Msg::CycleColors,
+        ),
+    ]
+}
+
+// The menu item that switches saved images to 16 bits per channel.
+const DEEP_COLOR_ITEM: &str = "File/Save 16-Bit Color";
+// The menu item that saves the iteration counts along with images.
+const COUNTS_ITEM: &str = "File/Save Iteration Counts";
+
+// Where the menu item for scale `n`:1 goes.
+fn scale_item(n: usize) -> String {
+    format!("View/Scale/{}:1", n)
+}
 
 /**
 The `ImgPane` is the main window of the application. It displays the actual
@@ -35,10 +203,374 @@ image and features the controlls for navigation/zooming.
 */
 pub struct ImgPane {
     win: DoubleWindow,
+    menu: MenuBar,
     im_frame: Frame,
     xpix_input: IntInput,
     ypix_input: IntInput,
+    oversample_choice: Choice,
+    adaptive_check: CheckButton,
+    metadata_check: CheckButton,
+    lock_check: CheckButton,
+    cancel_butt: Button,
+    progress_bar: Progress,
+    // Times the render in progress, for guessing how long it has left.
+    clock: ProgressClock,
+    back_butt: Button,
+    forward_butt: Button,
+    // The line under the image saying what's under the mouse.
+    status_frame: Frame,
+    stats_frame: Frame,
     image_data: Vec<u8>,
+    // The image with a highlight overlay applied, when one is showing.
+    overlay_data: Vec<u8>,
+    // Text most recently pasted into the window, waiting to be collected
+    // by `take_pasted()`.
+    pasted: Rc<RefCell<Option<String>>>,
+    // Likewise whatever was last dropped onto it (`take_dropped()`).
+    dropped: Rc<RefCell<Option<String>>>,
+}
+
+// All the widgets that control the image, however they're arranged.
+// The functions that build them just place them; `ImgPane::new()` sets
+// them up and hooks up their callbacks.
+struct Controls {
+    width_input: IntInput,
+    height_input: IntInput,
+    zoom_input: ValueInput,
+    zoom_in: Button,
+    zoom_out: Button,
+    back_butt: Button,
+    forward_butt: Button,
+    nudge_input: ValueInput,
+    nudge_up_butt: Button,
+    nudge_right_butt: Button,
+    nudge_left_butt: Button,
+    nudge_down_butt: Button,
+    scalers: Vec<RadioRoundButton>,
+    oversample_choice: Choice,
+    adaptive_check: CheckButton,
+    cancel_butt: Button,
+    progress_bar: Progress,
+    save_butt: Button,
+    metadata_check: CheckButton,
+    lock_check: CheckButton,
+    remember_butt: Button,
+    load_butt: Button,
+    strip_butt: Button,
+    paste_butt: Button,
+    examples_menu: MenuButton,
+}
+
+// Build the controls in a column down the left side of the window,
+// starting `top` pixels down.
+fn column_controls(l: &Layout, top: i32) -> Controls {
+    let ctrl = Pack::default()
+        .with_size(l.col_width, l.row_height * CONTROL_ROWS)
+        .with_pos(0, top);
+
+    let _ = Frame::default()
+        .with_label("Width")
+        .with_size(l.col_width, l.row_height);
+    let width_input = IntInput::default().with_size(l.col_width, l.row_height);
+    let _ = Frame::default()
+        .with_label("Height")
+        .with_size(l.col_width, l.row_height);
+    let height_input = IntInput::default().with_size(l.col_width, l.row_height);
+
+    let _ = Frame::default()
+        .with_label("Zoom")
+        .with_size(l.col_width, l.row_height);
+    let zoom_input = ValueInput::default().with_size(l.col_width, l.row_height);
+    let zoom_butt_pack = Pack::default()
+        .with_type(PackType::Horizontal)
+        .with_size(l.col_width, l.row_height);
+    let zoom_in = Button::default()
+        .with_label("@+")
+        .with_size(l.half_button(), l.row_height);
+    let zoom_out = Button::default()
+        .with_label("@line")
+        .with_size(l.half_button(), l.row_height);
+    zoom_butt_pack.end();
+    let view_butt_pack = Pack::default()
+        .with_type(PackType::Horizontal)
+        .with_size(l.col_width, l.row_height);
+    let back_butt = Button::default()
+        .with_label("@<<")
+        .with_size(l.half_button(), l.row_height);
+    let forward_butt = Button::default()
+        .with_label("@>>")
+        .with_size(l.half_button(), l.row_height);
+    view_butt_pack.end();
+
+    let _ = Frame::default()
+        .with_label("Nudge")
+        .with_size(l.col_width, l.row_height);
+    let nudge_input = ValueInput::default().with_size(l.col_width, l.row_height);
+    let nudge_top_pack = Pack::default()
+        .with_type(PackType::Horizontal)
+        .with_size(l.col_width, l.row_height);
+    let nudge_up_butt = Button::default()
+        .with_size(l.half_button(), l.row_height)
+        .with_label("@#00090->");
+    let nudge_right_butt = Button::default()
+        .with_size(l.half_button(), l.row_height)
+        .with_label("@->");
+    nudge_top_pack.end();
+    let nudge_bottom_pack = Pack::default()
+        .with_type(PackType::Horizontal)
+        .with_size(l.col_width, l.row_height);
+    let nudge_left_butt = Button::default()
+        .with_size(l.half_button(), l.row_height)
+        .with_label("@<-");
+    let nudge_down_butt = Button::default()
+        .with_size(l.half_button(), l.row_height)
+        .with_label("@#00090<-");
+    nudge_bottom_pack.end();
+
+    let mut scalers: Vec<RadioRoundButton> = Vec::new();
+
+    let _ = Frame::default()
+        .with_label("Scale")
+        .with_size(l.col_width, l.row_height);
+    let scale_pack = Pack::default().with_size(l.col_width, 5 * l.row_height);
+    for n in 0..N_SCALERS {
+        let mut sb = RadioRoundButton::default().with_size(l.col_width, l.row_height);
+        sb.set_label(&format!("{}:1", n + 1));
+        scalers.push(sb);
+    }
+    scalers[0].toggle(true);
+    scale_pack.end();
+
+    let _ = Frame::default()
+        .with_label("Antialias")
+        .with_size(l.col_width, l.row_height);
+    let oversample_choice = Choice::default().with_size(l.col_width, l.row_height);
+    let adaptive_check = CheckButton::default()
+        .with_label("adaptive")
+        .with_size(l.col_width, l.row_height);
+
+    let cancel_butt = Button::default()
+        .with_label("cancel")
+        .with_size(l.col_width, l.row_height);
+    let progress_bar = Progress::default().with_size(l.col_width, l.row_height);
+
+    let save_butt = Button::default()
+        .with_label("save\nimage")
+        .with_size(l.col_width, 2 * l.row_height);
+    let metadata_check = CheckButton::default()
+        .with_label("metadata")
+        .with_size(l.col_width, l.row_height);
+    let remember_butt = Button::default()
+        .with_label("save\nvalues")
+        .with_size(l.col_width, 2 * l.row_height);
+    let lock_check = CheckButton::default()
+        .with_label("lock")
+        .with_size(l.col_width, l.row_height);
+    let load_butt = Button::default()
+        .with_label("load")
+        .with_size(l.col_width, l.row_height);
+    let strip_butt = Button::default()
+        .with_label("strip")
+        .with_size(l.col_width, l.row_height);
+    let paste_butt = Button::default()
+        .with_label("paste")
+        .with_size(l.col_width, l.row_height);
+    let examples_menu = MenuButton::default()
+        .with_label("examples")
+        .with_size(l.col_width, l.row_height);
+
+    ctrl.end();
+
+    Controls {
+        width_input,
+        height_input,
+        zoom_input,
+        zoom_in,
+        zoom_out,
+        back_butt,
+        forward_butt,
+        nudge_input,
+        nudge_up_butt,
+        nudge_right_butt,
+        nudge_left_butt,
+        nudge_down_butt,
+        scalers,
+        oversample_choice,
+        adaptive_check,
+        cancel_butt,
+        progress_bar,
+        save_butt,
+        metadata_check,
+        lock_check,
+        remember_butt,
+        load_butt,
+        strip_butt,
+        paste_butt,
+        examples_menu,
+    }
+}
+
+// Hands out positions for toolbar widgets from left to right, starting a
+// new row when the next one won't fit in `width`.
+struct Flow {
+    x: i32,
+    y: i32,
+    width: i32,
+    row_height: i32,
+}
+
+impl Flow {
+    // Reserve room for something `w` pixels wide.
+    fn next(&mut self, w: i32) -> (i32, i32) {
+        if self.x > 0 && self.x + w > self.width {
+            self.x = 0;
+            self.y += self.row_height;
+        }
+        let pos = (self.x, self.y);
+        self.x += w;
+        pos
+    }
+
+    fn height(&self) -> i32 {
+        self.y + self.row_height
+    }
+}
+
+// Build the controls as a toolbar across a window `width` pixels wide,
+// starting `top` pixels down, using icons where there are sensible ones.
+// Related controls are kept together on the same row. Returns the controls
+// and where the bottom of the toolbar is.
+fn toolbar_controls(l: &Layout, top: i32, width: i32) -> (Controls, i32) {
+    let (hb, cw, rh) = (l.half_button(), l.col_width, l.row_height);
+    let mut flow = Flow {
+        x: 0,
+        y: top,
+        width: width.max(cw),
+        row_height: rh,
+    };
+    // This keeps the controls from stretching when the window does.
+    let mut bar = Group::default().with_pos(0, top).with_size(flow.width, rh);
+    // Icons need tooltips to say what they do; ones that are just the
+    // same on both layouts get set up in `ImgPane::new()`.
+    let button = |(x, y): (i32, i32), w: i32, label: &str, tip: Option<&str>| {
+        let mut b = Button::default()
+            .with_label(label)
+            .with_pos(x, y)
+            .with_size(w, rh);
+        if let Some(tip) = tip {
+            b.set_tooltip(tip);
+        }
+        b
+    };
+
+    let (x, y) = flow.next(2 * (hb + cw));
+    let _ = Frame::default()
+        .with_label("W")
+        .with_pos(x, y)
+        .with_size(hb, rh);
+    let width_input = IntInput::default().with_pos(x + hb, y).with_size(cw, rh);
+    let _ = Frame::default()
+        .with_label("H")
+        .with_pos(x + hb + cw, y)
+        .with_size(hb, rh);
+    let height_input = IntInput::default()
+        .with_pos(x + 2 * hb + cw, y)
+        .with_size(cw, rh);
+
+    let (x, y) = flow.next(cw + 4 * hb);
+    let zoom_input = ValueInput::default().with_pos(x, y).with_size(cw, rh);
+    let zoom_in = button((x + cw, y), hb, "@+", Some("zoom in"));
+    let zoom_out = button((x + cw + hb, y), hb, "@line", Some("zoom out"));
+    let back_butt = button((x + cw + 2 * hb, y), hb, "@<<", None);
+    let forward_butt = button((x + cw + 3 * hb, y), hb, "@>>", None);
+
+    let (x, y) = flow.next(cw + 4 * hb);
+    let nudge_input = ValueInput::default().with_pos(x, y).with_size(cw, rh);
+    let nudge_left_butt = button((x + cw, y), hb, "@<-", Some("nudge left"));
+    let nudge_up_butt = button((x + cw + hb, y), hb, "@#00090->", Some("nudge up"));
+    let nudge_down_butt = button((x + cw + 2 * hb, y), hb, "@#00090<-", Some("nudge down"));
+    let nudge_right_butt = button((x + cw + 3 * hb, y), hb, "@->", Some("nudge right"));
+
+    let scaler_w = 2 * cw / 3;
+    let (x, y) = flow.next(N_SCALERS as i32 * scaler_w);
+    let scale_pack = Pack::default()
+        .with_type(PackType::Horizontal)
+        .with_pos(x, y)
+        .with_size(N_SCALERS as i32 * scaler_w, rh);
+    let mut scalers: Vec<RadioRoundButton> = Vec::new();
+    for n in 0..N_SCALERS {
+        let mut sb = RadioRoundButton::default().with_size(scaler_w, rh);
+        sb.set_label(&format!("{}", n + 1));
+        sb.set_tooltip(&format!("scale {}:1", n + 1));
+        scalers.push(sb);
+    }
+    scalers[0].toggle(true);
+    scale_pack.end();
+
+    let (x, y) = flow.next(2 * cw);
+    let oversample_choice = Choice::default().with_pos(x, y).with_size(cw, rh);
+    let adaptive_check = CheckButton::default()
+        .with_label("adaptive")
+        .with_pos(x + cw, y)
+        .with_size(cw, rh);
+
+    let (x, y) = flow.next(hb + cw);
+    let cancel_butt = button((x, y), hb, "@square", None);
+    let progress_bar = Progress::default().with_pos(x + hb, y).with_size(cw, rh);
+
+    let (x, y) = flow.next(3 * hb + cw);
+    let save_butt = button((x, y), hb, "@filesave", Some("save image"));
+    let metadata_check = CheckButton::default()
+        .with_label("metadata")
+        .with_pos(x + hb, y)
+        .with_size(cw, rh);
+    let remember_butt = button((x + hb + cw, y), hb, "@filesaveas", Some("save values"));
+    let load_butt = button((x + 2 * hb + cw, y), hb, "@fileopen", Some("load"));
+
+    let (x, y) = flow.next(4 * cw);
+    let lock_check = CheckButton::default()
+        .with_label("lock")
+        .with_pos(x, y)
+        .with_size(cw, rh);
+    let strip_butt = button((x + cw, y), cw, "strip", None);
+    let paste_butt = button((x + 2 * cw, y), cw, "paste", None);
+    let examples_menu = MenuButton::default()
+        .with_label("examples")
+        .with_pos(x + 3 * cw, y)
+        .with_size(cw, rh);
+
+    bar.end();
+    bar.set_size(flow.width, flow.height() - top);
+    bar.make_resizable(false);
+
+    let ctrl = Controls {
+        width_input,
+        height_input,
+        zoom_input,
+        zoom_in,
+        zoom_out,
+        back_butt,
+        forward_butt,
+        nudge_input,
+        nudge_up_butt,
+        nudge_right_butt,
+        nudge_left_butt,
+        nudge_down_butt,
+        scalers,
+        oversample_choice,
+        adaptive_check,
+        cancel_butt,
+        progress_bar,
+        save_butt,
+        metadata_check,
+        lock_check,
+        remember_butt,
+        load_butt,
+        strip_butt,
+        paste_butt,
+        examples_menu,
+    };
+    (ctrl, flow.height())
 }
 
 impl ImgPane {
@@ -49,112 +581,168 @@ impl ImgPane {
     are to be sent.
     */
     pub fn new(pipe: mpsc::Sender<Msg>, version: &str, dims: crate::image::ImageDims) -> ImgPane {
+        let l = layout::current();
         let image_xpix = dims.xpix as i32;
         let image_ypix = dims.ypix as i32;
-        let mut w = DoubleWindow::default()
-            .with_size(image_xpix + COL_WIDTH, image_ypix)
-            .with_pos(0, 0);
+        let mut w = DoubleWindow::default().with_pos(0, 0);
         w.set_label(&format!("JSet-Desktop {}", version));
         w.set_border(true);
         w.make_resizable(true);
 
-        let ctrl = Pack::default()
-            .with_size(COL_WIDTH, COL_HEIGHT)
-            .with_pos(0, 0);
+        // The menu bar goes across the top, and the image goes to the
+        // right of or below the controls, with any docked panes to the
+        // right of it.
+        let dock_w = match l.panes {
+            PanePlacement::Floating => 0,
+            PanePlacement::Docked => l.dock_width(),
+        };
+        let menu_width = dock_w
+            + match l.controls {
+                ControlStyle::Column => l.col_width + image_xpix,
+                ControlStyle::Toolbar => image_xpix,
+            };
+        let mut menu = MenuBar::default()
+            .with_pos(0, 0)
+            .with_size(menu_width, l.row_height);
+        let top = l.row_height;
+        let (ctrl, (image_x, image_y)) = match l.controls {
+            ControlStyle::Column => (column_controls(&l, top), (l.col_width, top)),
+            ControlStyle::Toolbar => {
+                let (ctrl, bar_bottom) = toolbar_controls(&l, top, image_xpix);
+                (ctrl, (0, bar_bottom))
+            }
+        };
+        // The status line goes under the image.
+        let win_h = match l.panes {
+            PanePlacement::Floating => image_y + image_ypix + l.row_height,
+            // Leave the docked panes a decent amount of room, even under
+            // a small image.
+            PanePlacement::Docked => (image_y + image_ypix + l.row_height)
+                .max(top + l.minimap_height() + DOCK_ROWS * l.row_height),
+        };
+        w.set_size(image_x + image_xpix + dock_w, win_h);
+        let Controls {
+            mut width_input,
+            mut height_input,
+            mut zoom_input,
+            mut zoom_in,
+            mut zoom_out,
+            mut back_butt,
+            mut forward_butt,
+            mut nudge_input,
+            mut nudge_up_butt,
+            mut nudge_right_butt,
+            mut nudge_left_butt,
+            mut nudge_down_butt,
+            scalers,
+            mut oversample_choice,
+            mut adaptive_check,
+            mut cancel_butt,
+            mut progress_bar,
+            mut save_butt,
+            mut metadata_check,
+            mut lock_check,
+            mut remember_butt,
+            mut load_butt,
+            mut strip_butt,
+            mut paste_butt,
+            mut examples_menu,
+        } = ctrl;
 
-        let _ = Frame::default()
-            .with_label("Width")
-            .with_size(COL_WIDTH, ROW_HEIGHT);
-        let mut width_input = IntInput::default().with_size(COL_WIDTH, ROW_HEIGHT);
         width_input.set_tooltip("set image width in pixels");
         width_input.set_value(&format!("{}", dims.xpix));
-        let _ = Frame::default()
-            .with_label("Height")
-            .with_size(COL_WIDTH, ROW_HEIGHT);
-        let mut height_input = IntInput::default().with_size(COL_WIDTH, ROW_HEIGHT);
         height_input.set_tooltip("set image height in pixels");
         height_input.set_value(&format!("{}", dims.ypix));
-
-        let _ = Frame::default()
-            .with_label("Zoom")
-            .with_size(COL_WIDTH, ROW_HEIGHT);
-        let mut zoom_input = ValueInput::default().with_size(COL_WIDTH, ROW_HEIGHT);
+        oversample_choice.add_choice("none|2x2|3x3|4x4");
+        oversample_choice.set_value(dims.samples() as i32 - 1);
+        oversample_choice.set_tooltip("samples per pixel; more is smoother, but slower");
+        adaptive_check.set_checked(dims.adaptive);
+        adaptive_check.set_tooltip("only oversample pixels on edges; much faster");
+        cancel_butt.set_tooltip("stop the render in progress");
+        cancel_butt.deactivate();
+        progress_bar.set_minimum(0.0);
+        progress_bar.set_maximum(1.0);
+        progress_bar.set_value(0.0);
+        progress_bar.set_selection_color(Color::from_rgb(96, 160, 255));
+        back_butt.set_tooltip("go back to the previous view (Alt-Left)");
+        back_butt.set_shortcut(Shortcut::Alt | Key::Left);
+        back_butt.deactivate();
+        forward_butt.set_tooltip("go forward to the next view (Alt-Right)");
+        forward_butt.set_shortcut(Shortcut::Alt | Key::Right);
+        forward_butt.deactivate();
+        metadata_check.set_tooltip("embed image parameters in saved images");
+        metadata_check.set_checked(true);
+        lock_check.set_tooltip(
+            "stamp saved parameters so whoever loads them can tell if they'll get the same image",
+        );
+        strip_butt.set_tooltip("strip metadata from a PNG file");
+        paste_butt.set_tooltip("load parameters from a file or text on the clipboard");
+        examples_menu.set_tooltip("load one of the built-in examples");
+        for name in crate::gallery::names() {
+            examples_menu.add_choice(name);
+        }
         zoom_input.set_tooltip("set_zoom_ratio");
         zoom_input.set_minimum(1.0);
         zoom_input.set_value(DEFAULT_ZOOM);
-        let zoom_butt_pack = Pack::default()
-            .with_type(PackType::Horizontal)
-            .with_size(COL_WIDTH, ROW_HEIGHT);
-        let mut zoom_in = Button::default()
-            .with_label("@+")
-            .with_size(HALF_BUTTON, ROW_HEIGHT);
-        let mut zoom_out = Button::default()
-            .with_label("@line")
-            .with_size(HALF_BUTTON, ROW_HEIGHT);
-        zoom_butt_pack.end();
-
-        let _ = Frame::default()
-            .with_label("Nudge")
-            .with_size(COL_WIDTH, ROW_HEIGHT);
-        let mut nudge_input = ValueInput::default().with_size(COL_WIDTH, ROW_HEIGHT);
+        nudge_input.set_tooltip("nudge distance in pixels");
         nudge_input.set_minimum(0.0);
         nudge_input.set_value(DEFAULT_NUDGE);
         nudge_input.set_step(1.0, 10);
-        let nudge_top_pack = Pack::default()
-            .with_type(PackType::Horizontal)
-            .with_size(COL_WIDTH, ROW_HEIGHT);
-        let mut nudge_up_butt = Button::default()
-            .with_size(HALF_BUTTON, ROW_HEIGHT)
-            .with_label("@#00090->");
-        let mut nudge_right_butt = Button::default()
-            .with_size(HALF_BUTTON, ROW_HEIGHT)
-            .with_label("@->");
-        nudge_top_pack.end();
-        let nudge_bottom_pack = Pack::default()
-            .with_type(PackType::Horizontal)
-            .with_size(COL_WIDTH, ROW_HEIGHT);
-        let mut nudge_left_butt = Button::default()
-            .with_size(HALF_BUTTON, ROW_HEIGHT)
-            .with_label("@<-");
-        let mut nudge_down_butt = Button::default()
-            .with_size(HALF_BUTTON, ROW_HEIGHT)
-            .with_label("@#00090<-");
-        nudge_bottom_pack.end();
-
-        let mut scalers: Vec<RadioRoundButton> = Vec::new();
-
-        let _ = Frame::default()
-            .with_label("Scale")
-            .with_size(COL_WIDTH, ROW_HEIGHT);
-        let scale_pack = Pack::default().with_size(COL_WIDTH, 5 * ROW_HEIGHT);
-        for n in 0..N_SCALERS {
-            let mut sb = RadioRoundButton::default().with_size(COL_WIDTH, ROW_HEIGHT);
-            sb.set_label(&format!("{}:1", n + 1));
-            scalers.push(sb);
-        }
-        scalers[0].toggle(true);
-        scale_pack.end();
-
-        let mut save_butt = Button::default()
-            .with_label("save\nimage")
-            .with_size(COL_WIDTH, 2 * ROW_HEIGHT);
-        let mut remember_butt = Button::default()
-            .with_label("save\nvalues")
-            .with_size(COL_WIDTH, 2 * ROW_HEIGHT);
-        let _ = Frame::default().with_size(COL_WIDTH, ROW_HEIGHT); // spacer
-        let mut load_butt = Button::default()
-            .with_label("load")
-            .with_size(COL_WIDTH, ROW_HEIGHT);
-
-        ctrl.end();
 
         let scroll_region = Scroll::default()
-            .with_pos(COL_WIDTH, 0)
+            .with_pos(image_x, image_y)
             .with_size(image_xpix, image_ypix)
             .with_type(ScrollType::Both);
-        let mut image_frame = Frame::default().with_pos(COL_WIDTH, 0);
+        let mut image_frame = Frame::default().with_pos(image_x, image_y);
         image_frame.set_color(Color::Black);
         scroll_region.end();
+        // The status line shares its row with the timing of the last render.
+        let status_w = 3 * image_xpix / 5;
+        let mut status_frame = Frame::default()
+            .with_pos(image_x, image_y + image_ypix)
+            .with_size(status_w, l.row_height);
+        status_frame.set_align(Align::Left | Align::Inside | Align::Clip);
+        let mut stats_frame = Frame::default()
+            .with_pos(image_x + status_w, image_y + image_ypix)
+            .with_size(image_xpix - status_w, l.row_height);
+        stats_frame.set_align(Align::Right | Align::Inside | Align::Clip);
+        stats_frame.set_tooltip(
+            "last render: total time (time per pass), points iterated per second, threads used",
+        );
+
+        // Docked panes go down the right side: the minimap on top, and the
+        // other two in tabs under it.
+        if l.panes == PanePlacement::Docked {
+            let side_x = image_x + image_xpix;
+            let mut overview = Scroll::default()
+                .with_pos(side_x + (dock_w - l.minimap_width) / 2, top)
+                .with_size(l.minimap_width, l.minimap_height());
+            overview.set_type(ScrollType::None);
+            overview.end();
+            let tabs_y = top + l.minimap_height();
+            let tabs = Tabs::default()
+                .with_pos(side_x, tabs_y)
+                .with_size(dock_w, win_h - tabs_y);
+            let place = |label: &str| {
+                let s = Scroll::default()
+                    .with_label(label)
+                    .with_pos(side_x, tabs_y + l.row_height)
+                    .with_size(dock_w, win_h - tabs_y - l.row_height);
+                s.end();
+                s
+            };
+            let color_place = place("Color Map");
+            let iter_place = place("Iterator");
+            tabs.end();
+            super::set_docks(
+                tabs,
+                vec![
+                    (Dock::Overview, overview),
+                    (Dock::ColorMap, color_place),
+                    (Dock::Iterator, iter_place),
+                ],
+            );
+        }
 
         w.resizable(&scroll_region);
 
@@ -163,10 +751,25 @@ impl ImgPane {
 
         let ip = ImgPane {
             win: w.clone(),
+            menu: menu.clone(),
             im_frame: image_frame.clone(),
             xpix_input: width_input.clone(),
             ypix_input: height_input.clone(),
+            oversample_choice: oversample_choice.clone(),
+            adaptive_check: adaptive_check.clone(),
+            metadata_check: metadata_check.clone(),
+            lock_check: lock_check.clone(),
+            cancel_butt: cancel_butt.clone(),
+            progress_bar: progress_bar.clone(),
+            clock: ProgressClock::new(Instant::now()),
+            back_butt: back_butt.clone(),
+            forward_butt: forward_butt.clone(),
+            status_frame,
+            stats_frame,
             image_data: Vec::new(),
+            overlay_data: Vec::new(),
+            pasted: Rc::new(RefCell::new(None)),
+            dropped: Rc::new(RefCell::new(None)),
         };
 
         let scalers = Rc::new(RefCell::new(scalers));
@@ -214,60 +817,97 @@ impl ImgPane {
             let pipe = pipe.clone();
             let width_input = width_input.clone();
             let height_input = height_input.clone();
+            let pasted = ip.pasted.clone();
+            let dropped = ip.dropped.clone();
+            let get_nudge_distance = get_nudge_distance.clone();
+            let get_zoom_factor = get_zoom_factor.clone();
+            // Dropped files arrive as a paste right after the drop.
+            let mut dropping = false;
             move |_, evt| {
                 match evt {
-                    Event::KeyDown => match fltk::app::event_key() {
-                        Key::Enter => {
-                            let xpix = match width_input.value().parse::<usize>() {
-                                Err(e) => {
-                                    eprintln!("Unable to parse image height: {}", &e);
-                                    None
-                                }
-                                Ok(n) => {
-                                    if n < MIN_DIMENSION {
-                                        eprintln!("{} pixels is just too small.", &n);
+                    Event::DndEnter | Event::DndDrag | Event::DndLeave => true,
+                    Event::DndRelease => {
+                        dropping = true;
+                        true
+                    }
+                    Event::Paste if dropping => {
+                        dropping = false;
+                        *dropped.borrow_mut() = Some(fltk::app::event_text());
+                        pipe.send(Msg::LoadDropped).unwrap();
+                        true
+                    }
+                    Event::Paste => {
+                        *pasted.borrow_mut() = Some(fltk::app::event_text());
+                        pipe.send(Msg::PasteParams).unwrap();
+                        true
+                    }
+                    Event::KeyDown => {
+                        if let Some(msg) = super::undo_key() {
+                            pipe.send(msg).unwrap();
+                            return true;
+                        }
+                        if let Some(msg) = nav_msg(
+                            fltk::app::event_key(),
+                            fltk::app::event_state(),
+                            get_nudge_distance(),
+                            get_zoom_factor(),
+                        ) {
+                            pipe.send(msg).unwrap();
+                            return true;
+                        }
+                        match fltk::app::event_key() {
+                            Key::Enter => {
+                                let xpix = match width_input.value().parse::<usize>() {
+                                    Err(e) => {
+                                        eprintln!("Unable to parse image height: {}", &e);
                                         None
-                                    } else {
-                                        Some(n)
                                     }
-                                }
-                            };
-                            let ypix = match height_input.value().parse::<usize>() {
-                                Err(e) => {
-                                    eprintln!("Unable to parse image width: {}", &e);
-                                    None
-                                }
-                                Ok(n) => {
-                                    if n < MIN_DIMENSION {
-                                        eprintln!("{} pixels is just too small.", &n);
+                                    Ok(n) => {
+                                        if n < MIN_DIMENSION {
+                                            eprintln!("{} pixels is just too small.", &n);
+                                            None
+                                        } else {
+                                            Some(n)
+                                        }
+                                    }
+                                };
+                                let ypix = match height_input.value().parse::<usize>() {
+                                    Err(e) => {
+                                        eprintln!("Unable to parse image width: {}", &e);
                                         None
-                                    } else {
-                                        Some(n)
                                     }
-                                }
-                            };
-                            pipe.send(Msg::Redraw(xpix, ypix)).unwrap();
-                            true
-                        }
-                        Key::Escape => {
-                            // Pretend like we've handled it so the app
-                            // won't quit.
-                            true
-                        }
-                        A_KEY => {
-                            pipe.send(Msg::FocusIterPane).unwrap();
-                            true
-                        }
-                        Z_KEY => {
-                            pipe.send(Msg::FocusColorPane).unwrap();
-                            true
-                        }
-                        _k => {
-                            #[cfg(debug_assertions)]
-                            println!("{:?}", _k.to_char());
-                            false
+                                    Ok(n) => {
+                                        if n < MIN_DIMENSION {
+                                            eprintln!("{} pixels is just too small.", &n);
+                                            None
+                                        } else {
+                                            Some(n)
+                                        }
+                                    }
+                                };
+                                pipe.send(Msg::Redraw(xpix, ypix)).unwrap();
+                                true
+                            }
+                            Key::Escape => {
+                                // Pretend like we've handled it so the app
+                                // won't quit.
+                                true
+                            }
+                            A_KEY => {
+                                pipe.send(Msg::FocusIterPane).unwrap();
+                                true
+                            }
+                            Z_KEY => {
+                                pipe.send(Msg::FocusColorPane).unwrap();
+                                true
+                            }
+                            _k => {
+                                #[cfg(debug_assertions)]
+                                println!("{:?}", _k.to_char());
+                                false
+                            }
                         }
-                    },
+                    }
                     _ => false,
                 }
             }
@@ -278,16 +918,110 @@ impl ImgPane {
             fltk::app::quit();
         });
 
+        // The rubber band being dragged out on the image, if there is one:
+        // where the drag started and where the mouse is now, relative to
+        // the image.
+        let band: Rc<RefCell<Option<[i32; 4]>>> = Rc::new(RefCell::new(None));
+        image_frame.draw({
+            let band = band.clone();
+            move |f| {
+                if let Some([x0, y0, x1, y1]) = *band.borrow() {
+                    let (x, y) = (f.x() + x0.min(x1), f.y() + y0.min(y1));
+                    let (w, h) = ((x1 - x0).abs() + 1, (y1 - y0).abs() + 1);
+                    // Light on dark, so it shows up on any image.
+                    draw::draw_rect_with_color(x, y, w, h, Color::Black);
+                    if w > 2 && h > 2 {
+                        draw::draw_rect_with_color(x + 1, y + 1, w - 2, h - 2, Color::White);
+                    }
+                }
+            }
+        });
+        // Dragging with the right or middle button pans the view instead.
+        // While it does, the image slides along with the mouse; this is
+        // where the mouse started (in window coordinates), and where the
+        // image was.
+        let mut pan: Option<[i32; 4]> = None;
         image_frame.handle({
             let pipe = pipe.clone();
+            let get_scale = get_scale.clone();
             move |f, evt| {
-                if evt != Event::Released {
-                    return false;
+                let (ex, ey) = fltk::app::event_coords();
+                let (mut px, mut py) = (ex - f.x(), ey - f.y());
+                match evt {
+                    Event::Push => {
+                        if fltk::app::event_mouse_button() == MouseButton::Left {
+                            *band.borrow_mut() = Some([px, py, px, py]);
+                        } else {
+                            pan = Some([ex, ey, f.x(), f.y()]);
+                        }
+                        return true;
+                    }
+                    Event::Drag => {
+                        if let Some([x0, y0, fx, fy]) = pan {
+                            f.set_pos(fx + ex - x0, fy + ey - y0);
+                            if let Some(mut p) = f.parent() {
+                                p.redraw();
+                            }
+                        } else if let Some(b) = band.borrow_mut().as_mut() {
+                            b[2] = px;
+                            b[3] = py;
+                        }
+                        f.redraw();
+                        return true;
+                    }
+                    Event::Released => {}
+                    // Taking this is what gets the `Move`s sent here.
+                    Event::Enter => {
+                        return true;
+                    }
+                    Event::Move => {
+                        if f.w() > 0 && f.h() > 0 {
+                            let x_frac = (px as f64) / (f.w() as f64);
+                            let y_frac = (py as f64) / (f.h() as f64);
+                            pipe.send(Msg::PointerMoved(Some((x_frac, y_frac))))
+                                .unwrap();
+                        }
+                        return true;
+                    }
+                    Event::Leave => {
+                        pipe.send(Msg::PointerMoved(None)).unwrap();
+                        return true;
+                    }
+                    _ => {
+                        return false;
+                    }
+                }
+
+                if let Some([x0, y0, fx, fy]) = pan.take() {
+                    f.set_pos(fx, fy);
+                    let (dx, dy) = (ex - x0, ey - y0);
+                    if dx.abs() >= MIN_BAND || dy.abs() >= MIN_BAND {
+                        // The view moves the opposite way the image got
+                        // dragged, by that many pixels of the full-size image.
+                        let scale = get_scale() as f64;
+                        pipe.send(Msg::Nudge(-scale * dx as f64, -scale * dy as f64))
+                            .unwrap();
+                        return true;
+                    }
+                    // Too small to be a drag; it's a click.
+                    (px, py) = (ex - fx, ey - fy);
+                }
+
+                let dragged = band.borrow_mut().take();
+                f.redraw();
+                if let Some(deliver) = super::take_sample_request() {
+                    deliver(pixel_at(f, px, py));
+                    return true;
+                }
+
+                if let Some(b) = dragged {
+                    if let Some((x0, y0, x1, y1)) = band_fracs(b, f.w(), f.h()) {
+                        pipe.send(Msg::ZoomRect(x0, y0, x1, y1)).unwrap();
+                        return true;
+                    }
                 }
 
                 let (fxpix, fypix) = (f.w() as f64, f.h() as f64);
-                let (px, py) = fltk::app::event_coords();
-                let (px, py) = (px - f.x(), py - f.y());
                 let x_frac = (px as f64) / fxpix;
                 let y_frac = (py as f64) / fypix;
 
@@ -305,13 +1039,27 @@ impl ImgPane {
             }
         });
         zoom_out.set_callback({
+            let get_zoom = get_zoom_factor.clone();
             let pipe = pipe.clone();
             move |_| {
-                let zf = 1.0 / get_zoom_factor();
+                let zf = 1.0 / get_zoom();
                 pipe.send(Msg::Zoom(zf)).unwrap();
             }
         });
 
+        back_butt.set_callback({
+            let pipe = pipe.clone();
+            move |_| {
+                pipe.send(Msg::ViewBack).unwrap();
+            }
+        });
+        forward_butt.set_callback({
+            let pipe = pipe.clone();
+            move |_| {
+                pipe.send(Msg::ViewForward).unwrap();
+            }
+        });
+
         nudge_up_butt.set_callback({
             let dist = get_nudge_distance.clone();
             let pipe = pipe.clone();
@@ -346,8 +1094,12 @@ impl ImgPane {
 
         let send_scale = {
             let pipe = pipe.clone();
+            let menu = menu.clone();
             move |_: &mut RadioRoundButton| {
                 let s = get_scale();
+                if let Some(mut item) = menu.find_item(&scale_item(s)) {
+                    item.set();
+                }
                 pipe.send(Msg::Scale(s)).unwrap();
             }
         };
@@ -357,6 +1109,31 @@ impl ImgPane {
             b.set_callback(cb);
         }
 
+        let send_oversample = {
+            let pipe = pipe.clone();
+            let oversample_choice = oversample_choice.clone();
+            let adaptive_check = adaptive_check.clone();
+            move || {
+                let n = oversample_choice.value();
+                if n >= 0 {
+                    let msg = Msg::Oversample(n as usize + 1, adaptive_check.is_checked());
+                    pipe.send(msg).unwrap();
+                }
+            }
+        };
+        oversample_choice.set_callback({
+            let send_oversample = send_oversample.clone();
+            move |_| send_oversample()
+        });
+        adaptive_check.set_callback(move |_| send_oversample());
+
+        cancel_butt.set_callback({
+            let pipe = pipe.clone();
+            move |_| {
+                pipe.send(Msg::Cancel).unwrap();
+            }
+        });
+
         save_butt.set_callback({
             let pipe = pipe.clone();
             move |_| {
@@ -370,38 +1147,198 @@ impl ImgPane {
             }
         });
         load_butt.set_callback({
+            let pipe = pipe.clone();
             move |_| {
                 pipe.send(Msg::Load).unwrap();
             }
         });
+        strip_butt.set_callback({
+            let pipe = pipe.clone();
+            move |_| {
+                pipe.send(Msg::StripMetadata).unwrap();
+            }
+        });
+        examples_menu.set_callback({
+            let pipe = pipe.clone();
+            move |m| {
+                if m.value() >= 0 {
+                    pipe.send(Msg::LoadExample(m.value() as usize)).unwrap();
+                }
+            }
+        });
+        // The pasted text arrives as an `Event::Paste` sent to the window.
+        paste_butt.set_callback({
+            let w = w.clone();
+            move |_| {
+                fltk::app::paste_text(&w);
+            }
+        });
+
+        // Most of the menu items do the same things as buttons; they're
+        // added in the order they appear.
+        menu.add(
+            "File/Copy Image",
+            Shortcut::Command | 'c',
+            MenuFlag::Normal,
+            {
+                let pipe = pipe.clone();
+                move |_| pipe.send(Msg::CopyImage).unwrap()
+            },
+        );
+        menu.add(
+            "File/Paste Values",
+            Shortcut::Command | 'v',
+            MenuFlag::MenuDivider,
+            {
+                let w = w.clone();
+                move |_| fltk::app::paste_text(&w)
+            },
+        );
+        for (path, shortcut, flag, msg) in menu_items() {
+            if path == BACK_ITEM {
+                // The zoom items go just before the view history ones.
+                for (label, zoom_in) in [("View/Zoom In", true), ("View/Zoom Out", false)] {
+                    let pipe = pipe.clone();
+                    let get_zoom = get_zoom_factor.clone();
+                    menu.add(label, Shortcut::None, MenuFlag::Normal, move |_| {
+                        let zf = get_zoom();
+                        let zf = if zoom_in { zf } else { 1.0 / zf };
+                        pipe.send(Msg::Zoom(zf)).unwrap();
+                    });
+                }
+            }
+            let pipe = pipe.clone();
+            menu.add(path, shortcut, flag, move |_| pipe.send(msg).unwrap());
+        }
+        menu.add(DEEP_COLOR_ITEM, Shortcut::None, MenuFlag::Toggle, |_| {});
+        menu.add(
+            COUNTS_ITEM,
+            Shortcut::None,
+            MenuFlag::Toggle | MenuFlag::MenuDivider,
+            |_| {},
+        );
+        menu.add(
+            "File/Quit",
+            Shortcut::Command | 'q',
+            MenuFlag::Normal,
+            |_| fltk::app::quit(),
+        );
+        for n in 1..=N_SCALERS {
+            let scalers = scalers.clone();
+            let pipe = pipe.clone();
+            menu.add(&scale_item(n), Shortcut::None, MenuFlag::Radio, move |_| {
+                // Keep the radio buttons showing the same thing.
+                for (k, b) in scalers.borrow_mut().iter_mut().enumerate() {
+                    b.toggle(k + 1 == n);
+                }
+                pipe.send(Msg::Scale(n)).unwrap();
+            });
+        }
+        if let Some(mut item) = menu.find_item(&scale_item(1)) {
+            item.set();
+        }
+        // Help goes last, after all the menus the items above make.
+        for (n, name) in crate::gallery::names().into_iter().enumerate() {
+            let pipe = pipe.clone();
+            menu.add(
+                &format!("Help/Examples/{}", name),
+                Shortcut::None,
+                MenuFlag::Normal,
+                move |_| pipe.send(Msg::LoadExample(n)).unwrap(),
+            );
+        }
 
         ip
     }
 
     /**
-    Set the image to be displayed.
+    Set the image to be displayed, either 8-bit RGB or RGBA data.
 
     Won't do anything if the dimensions passed don't match the length of
     the data supplied.
     */
     pub fn set_image(&mut self, xpix: usize, ypix: usize, data: Vec<u8>) {
         let npix = xpix * ypix;
-        if npix * 3 != data.len() {
+        if npix * 3 != data.len() && npix * 4 != data.len() {
             eprintln!("Image dimensions don't match data dimenison.");
             return;
         }
 
         self.image_data = data;
-        let (w, h) = (xpix as i32, ypix as i32);
-        let frame_img =
-            unsafe { RgbImage::from_data(&self.image_data, w, h, ColorDepth::Rgb8).unwrap() };
+        self.overlay_data.clear();
+        self.im_frame.set_size(xpix as i32, ypix as i32);
+        self.show_data(false);
+    }
+
+    // Bytes per pixel in the image data: 4 if it has transparency, else 3.
+    fn channels(&self) -> usize {
+        let npix = (self.im_frame.w() * self.im_frame.h()).max(1) as usize;
+        if self.image_data.len() == 4 * npix {
+            4
+        } else {
+            3
+        }
+    }
+
+    // Point the image frame at either the plain image data or the overlay.
+    fn show_data(&mut self, overlay: bool) {
+        let (w, h) = (self.im_frame.w(), self.im_frame.h());
+        let data = if overlay {
+            &self.overlay_data
+        } else {
+            &self.image_data
+        };
+        let depth = if self.channels() == 4 {
+            ColorDepth::Rgba8
+        } else {
+            ColorDepth::Rgb8
+        };
+        let frame_img = unsafe { RgbImage::from_data(data, w, h, depth).unwrap() };
 
-        self.im_frame.set_size(w, h);
         self.im_frame.set_image(Some(frame_img));
         self.win.redraw();
         fltk::app::sleep(0.01);
     }
 
+    /**
+    Highlight part of the displayed image.
+
+    The `mask` is 8-bit RGB data the same size as the displayed image; the
+    brighter a mask pixel is, the less the corresponding image pixel gets
+    dimmed. Passing `None` (or a mask of the wrong size) removes any
+    highlight. The plain image is what gets saved either way.
+    */
+    pub fn set_overlay(&mut self, mask: Option<Vec<u8>>) {
+        let channels = self.channels();
+        let mask = match mask {
+            Some(m) if m.len() / 3 * channels == self.image_data.len() => m,
+            _ => {
+                if !self.overlay_data.is_empty() {
+                    self.overlay_data.clear();
+                    self.show_data(false);
+                }
+                return;
+            }
+        };
+
+        // Only the colors get dimmed; any opacity stays as it is.
+        self.overlay_data = self
+            .image_data
+            .chunks_exact(channels)
+            .zip(mask.chunks_exact(3))
+            .flat_map(|(px, m)| {
+                px.iter().enumerate().map(move |(i, &b)| match m.get(i) {
+                    Some(&m) => {
+                        let frac = OVERLAY_DIM + (1.0 - OVERLAY_DIM) * (m as f32 / 255.0);
+                        (b as f32 * frac) as u8
+                    }
+                    None => b,
+                })
+            })
+            .collect();
+        self.show_data(true);
+    }
+
     /**
     "Focus" the window.
 
@@ -416,27 +1353,244 @@ impl ImgPane {
         self.win.show();
     }
 
+    /// Where the window is on the screen.
+    pub fn position(&self) -> (i32, i32) {
+        (self.win.x(), self.win.y())
+    }
+
+    /// Move the window to (`x`, `y`), or as near as it can go and still
+    /// be on a screen.
+    pub fn move_to(&mut self, x: i32, y: i32) {
+        let (x, y) = super::fit_on_screen(x, y, self.win.w(), self.win.h());
+        self.win.set_pos(x, y);
+    }
+
     /// When an image is loaded, these inputs need their values set properly.
     pub fn set_input_dimensions(&mut self, x: usize, y: usize) {
         self.xpix_input.set_value(&format!("{}", x));
         self.ypix_input.set_value(&format!("{}", y));
     }
 
+    /// Likewise the anti-aliasing controls.
+    pub fn set_oversample(&mut self, samples: usize, adaptive: bool) {
+        self.oversample_choice.set_value(samples as i32 - 1);
+        self.adaptive_check.set_checked(adaptive);
+    }
+
+    /**
+    Show whether a render is in progress. While it is, the cancel button
+    is active; when it's done, any progress indication is cleared.
+    */
+    pub fn set_rendering(&mut self, rendering: bool) {
+        if rendering {
+            self.cancel_butt.activate();
+            self.clock = ProgressClock::new(Instant::now());
+        } else {
+            self.cancel_butt.deactivate();
+            self.progress_bar.set_label("");
+            self.progress_bar.set_tooltip("");
+        }
+        self.progress_bar.set_value(0.0);
+        self.win.redraw();
+    }
+
+    /// Show (or stop showing) that the image was finished with a lower
+    /// iteration limit in places, because the render ran out of time.
+    pub fn set_approximate(&mut self, approx: bool) {
+        if approx {
+            self.progress_bar.set_label("approx.");
+            self.progress_bar
+                .set_tooltip("the render ran out of time; hit return to refine it");
+        } else {
+            self.progress_bar.set_label("");
+            self.progress_bar.set_tooltip("");
+        }
+        self.progress_bar.redraw();
+    }
+
+    /// Show whether there are views to go back and forward to.
+    pub fn set_view_history(&mut self, back: bool, forward: bool) {
+        for (b, on) in [
+            (&mut self.back_butt, back),
+            (&mut self.forward_butt, forward),
+        ] {
+            if on {
+                b.activate();
+            } else {
+                b.deactivate();
+            }
+        }
+        for (path, on) in [(BACK_ITEM, back), (FORWARD_ITEM, forward)] {
+            if let Some(mut item) = self.menu.find_item(path) {
+                if on {
+                    item.activate();
+                } else {
+                    item.deactivate();
+                }
+            }
+        }
+    }
+
+    /// Show `text` in the status line under the image.
+    pub fn set_status(&mut self, text: &str) {
+        self.status_frame.set_label(text);
+        self.status_frame.redraw();
+    }
+
+    /// Show `text` (a summary of how long the last render took) beside
+    /// the status line.
+    pub fn set_stats(&mut self, text: &str) {
+        self.stats_frame.set_label(text);
+        self.stats_frame.redraw();
+    }
+
+    /**
+    Show that `done` out of `total` chunks of the render (or of the preview
+    pass it's on) are finished, and about how long the rest will take.
+    */
+    pub fn set_progress(&mut self, done: usize, total: usize) {
+        let left = self.clock.update(done, total, Instant::now());
+        self.progress_bar.set_maximum(total.max(1) as f64);
+        self.progress_bar.set_value(done as f64);
+        let label = match left {
+            Some(t) => format!("{}/{} {}", done, total, short_duration(t)),
+            None => format!("{}/{}", done, total),
+        };
+        self.progress_bar.set_label(&label);
+        self.progress_bar
+            .set_tooltip("chunks done, out of the total, and about how long the rest will take");
+        self.progress_bar.redraw();
+    }
+
+    /// Collect any text pasted into the window since the last call.
+    pub fn take_pasted(&mut self) -> Option<String> {
+        self.pasted.borrow_mut().take()
+    }
+
+    /// Collect whatever was last dropped onto the window (the text of it,
+    /// which for files is their paths).
+    pub fn take_dropped(&mut self) -> Option<String> {
+        self.dropped.borrow_mut().take()
+    }
+
+    /// Whether the user wants image parameters embedded in saved images.
+    pub fn embed_metadata(&self) -> bool {
+        self.metadata_check.is_checked()
+    }
+
+    /// Whether the user wants images saved with 16 bits per channel.
+    pub fn deep_color(&self) -> bool {
+        self.menu
+            .find_item(DEEP_COLOR_ITEM)
+            .is_some_and(|item| item.value())
+    }
+
+    /// Whether the user wants the iteration counts saved along with
+    /// images (see `rw::save_counts()`).
+    pub fn save_counts(&self) -> bool {
+        self.menu
+            .find_item(COUNTS_ITEM)
+            .is_some_and(|item| item.value())
+    }
+
+    /// Whether the user wants saved parameters locked (see
+    /// `rw::set_param_locking()`).
+    pub fn lock_params(&self) -> bool {
+        self.lock_check.is_checked()
+    }
+
     /**
     Get the data of the image displayed.
 
     This is just used to save the data (I think).
     */
     pub fn get_image(&self) -> (usize, usize, Vec<u8>) {
-        let immij = self.im_frame.image().unwrap();
-        (immij.w() as usize, immij.h() as usize, immij.to_rgb_data())
+        (
+            self.im_frame.w() as usize,
+            self.im_frame.h() as usize,
+            self.image_data.clone(),
+        )
+    }
+}
+
+// The color of the pixel at (`x`, `y`) of the image shown in `f`.
+fn pixel_at(f: &Frame, x: i32, y: i32) -> Option<RGB> {
+    let img = f.image()?;
+    let w = img.data_w();
+    if x < 0 || y < 0 || x >= w {
+        return None;
+    }
+    let data = img.to_rgb_data();
+    let n = img.depth() as usize * (y as usize * w as usize + x as usize);
+    let px = data.get(n..(n + 3))?;
+    Some(RGB::new(px[0] as f32, px[1] as f32, px[2] as f32))
+}
+
+// What pressing `key` with the modifier keys in `state` held down does to
+// the view, if anything: the arrow keys nudge it `nudge` pixels, and + and
+// - zoom in and out by a factor of `zoom`. Holding down Ctrl makes the
+// steps bigger: `BIG_NUDGE` times as far, or zooming twice over. Alt goes
+// with the arrows to step back and forth between views, so that's left
+// alone.
+fn nav_msg(key: Key, state: EventState, nudge: f64, zoom: f64) -> Option<Msg> {
+    if state.contains(EventState::Alt) {
+        return None;
     }
+    let (nudge, zoom) = if state.contains(EventState::Ctrl) {
+        (nudge * BIG_NUDGE, zoom * zoom)
+    } else {
+        (nudge, zoom)
+    };
+    let plus = Key::from_char('+');
+    let equals = Key::from_char('=');
+    let minus = Key::from_char('-');
+    match key {
+        Key::Left => Some(Msg::Nudge(-nudge, 0.0)),
+        Key::Right => Some(Msg::Nudge(nudge, 0.0)),
+        Key::Up => Some(Msg::Nudge(0.0, -nudge)),
+        Key::Down => Some(Msg::Nudge(0.0, nudge)),
+        k if k == plus || k == equals => Some(Msg::Zoom(zoom)),
+        k if k == minus => Some(Msg::Zoom(1.0 / zoom)),
+        _ => None,
+    }
+}
+
+// The corners of the rubber band dragged from (`x0`, `y0`) to (`x1`, `y1`)
+// on an image `w` by `h` pixels, as fractions of the way across and down
+// it (upper left, then lower right), or `None` if it's too small to be
+// anything but a click.
+fn band_fracs([x0, y0, x1, y1]: [i32; 4], w: i32, h: i32) -> Option<(f64, f64, f64, f64)> {
+    if (x1 - x0).abs() < MIN_BAND || (y1 - y0).abs() < MIN_BAND || w < 1 || h < 1 {
+        return None;
+    }
+    let across = |x: i32| (x.clamp(0, w) as f64) / (w as f64);
+    let down = |y: i32| (y.clamp(0, h) as f64) / (h as f64);
+    Some((
+        across(x0.min(x1)),
+        down(y0.min(y1)),
+        across(x0.max(x1)),
+        down(y0.max(y1)),
+    ))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn menu_paths() {
+        let items = menu_items();
+        let paths: Vec<&str> = items.iter().map(|i| i.0).collect();
+        // The items that get found by path later have to be there.
+        assert!(paths.contains(&BACK_ITEM) && paths.contains(&FORWARD_ITEM));
+        for (n, p) in paths.iter().enumerate() {
+            assert!(!paths[..n].contains(p), "{} is in the menu twice", p);
+            let top = p.split('/').next().unwrap();
+            assert!(["File", "Edit", "View"].contains(&top), "{}", p);
+        }
+        assert_eq!(scale_item(3), "View/Scale/3:1");
+    }
+
     fn generate_image_data() -> (usize, usize, Vec<u8>) {
         let xpix: u16 = 256 * 2;
         let ypix: u16 = 256;
@@ -460,22 +1614,76 @@ mod test {
     }
 
     #[test]
+    fn toolbar_flow() {
+        let mut flow = Flow {
+            x: 0,
+            y: 0,
+            width: 100,
+            row_height: 10,
+        };
+        assert_eq!(flow.next(60), (0, 0));
+        assert_eq!(flow.next(40), (60, 0));
+        assert_eq!(flow.next(1), (0, 10));
+        // Something too wide for any row still gets a row to itself.
+        assert_eq!(flow.next(150), (0, 20));
+        assert_eq!(flow.next(10), (0, 30));
+        assert_eq!(flow.height(), 40);
+    }
+
+    #[test]
+    fn navigation_keys() {
+        let plain = EventState::None;
+        let nav = |key: Key, state: EventState| format!("{:?}", nav_msg(key, state, 5.0, 2.0));
+        assert_eq!(nav(Key::Left, plain), "Some(Nudge(-5.0, 0.0))");
+        assert_eq!(nav(Key::Down, EventState::Ctrl), "Some(Nudge(0.0, 50.0))");
+        assert_eq!(nav(Key::from_char('='), plain), "Some(Zoom(2.0))");
+        assert_eq!(
+            nav(Key::from_char('-'), EventState::Ctrl | EventState::Shift),
+            "Some(Zoom(0.25))"
+        );
+        assert_eq!(nav(Key::Right, EventState::Alt), "None");
+        assert_eq!(nav(Key::from_char('q'), plain), "None");
+    }
+
+    #[test]
+    fn rubber_bands() {
+        assert_eq!(band_fracs([10, 10, 12, 40], 100, 50), None);
+        assert_eq!(
+            band_fracs([50, 40, 25, 10], 100, 50),
+            Some((0.25, 0.2, 0.5, 0.8))
+        );
+        // Dragging off the edge stops at the edge.
+        assert_eq!(
+            band_fracs([-20, 0, 50, 60], 100, 50),
+            Some((0.0, 0.0, 0.5, 1.0))
+        );
+    }
+
+    #[test]
+    #[ignore = "interactive; opens a window and waits for it to be closed"]
     fn image_pane() {
         let a = fltk::app::App::default();
         let (w, h, data) = generate_image_data();
         let (tx, rx) = mpsc::channel::<Msg>();
 
-        let mut p = ImgPane::new(tx, "internal test");
+        let dims = crate::image::ImageDims {
+            xpix: w,
+            ypix: h,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        let mut p = ImgPane::new(tx, "internal test", dims);
         p.set_image(w, h, data);
         fltk::app::sleep(0.01);
 
         //~ a.run().unwrap();
         while a.wait() {
-            match rx.try_recv() {
-                Ok(m) => {
-                    println!("{:?}", &m);
-                }
-                Err(e) => {}
+            if let Ok(m) = rx.try_recv() {
+                println!("{:?}", &m);
             }
         }
     }
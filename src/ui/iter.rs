@@ -9,7 +9,7 @@ use std::f64::consts::PI;
 use std::rc::Rc;
 
 use fltk::{
-    button::Button,
+    button::{Button, CheckButton},
     enums::Font,
     frame::Frame,
     group::{Pack, PackType},
@@ -26,14 +26,6 @@ use crate::image::*;
 // Labels that are mathematical variable symbols get typeset in this.
 const MATH_FONT: Font = Font::HelveticaItalic;
 
-// The following constants express dimensions of elements of the
-// `CoefSpecifier`
-const COEF_ROW_HEIGHT: i32 = 32;
-const COEF_DEGREE_WIDTH: i32 = 48;
-const COEF_VAR_WIDTH: i32 = 16;
-const COEF_INPUT_WIDTH: i32 = 72;
-const COEF_ROW_WIDTH: i32 = COEF_DEGREE_WIDTH + (4 * COEF_VAR_WIDTH) + (2 * COEF_INPUT_WIDTH);
-
 /*
 A wrapped collection of UI elements for specifying a complex coefficient in
 polar form.
@@ -48,36 +40,60 @@ impl CoefSpecifier {
     // Construct a new `CoefSpecifier` with the given term label and initial
     // values of `r` and `t`heta.
     pub fn new(term: &str, r: f64, t: f64) -> CoefSpecifier {
-        let mut rw = Pack::default().with_size(COEF_ROW_WIDTH, COEF_ROW_HEIGHT);
+        let l = layout::current();
+        let mut rw = Pack::default().with_size(l.coef_row_width(), l.coef_row_height);
         rw.set_type(PackType::Horizontal);
         rw.end();
 
-        let mut deg_lab = Frame::default().with_size(COEF_DEGREE_WIDTH, COEF_ROW_HEIGHT);
+        let mut deg_lab = Frame::default().with_size(l.coef_degree_width, l.coef_row_height);
         deg_lab.set_label_font(MATH_FONT);
         deg_lab.set_label(term);
 
         let mut rlab = Frame::default()
             .with_label("r:")
-            .with_size(COEF_VAR_WIDTH, COEF_ROW_HEIGHT);
+            .with_size(l.coef_var_width, l.coef_row_height);
         rlab.set_label_font(MATH_FONT);
 
-        let mut r_input = ValueInput::default().with_size(COEF_INPUT_WIDTH, COEF_ROW_HEIGHT);
-        r_input.set_tooltip(&format!("modulus of {} coefficient", term));
+        let mut r_input = ValueInput::default().with_size(l.coef_input_width, l.coef_row_height);
         r_input.set_value(r);
+        describe_value(
+            &mut r_input,
+            &format!("modulus of {} coefficient", term),
+            &format!("{}", r),
+        );
 
-        let spacer = Frame::default().with_size(COEF_VAR_WIDTH, COEF_ROW_HEIGHT);
+        let spacer = Frame::default().with_size(l.coef_var_width, l.coef_row_height);
 
         let tlab = Frame::default()
             .with_label("𝜃:")
-            .with_size(COEF_VAR_WIDTH, COEF_ROW_HEIGHT);
+            .with_size(l.coef_var_width, l.coef_row_height);
 
-        let mut t_input = ValueInput::default().with_size(COEF_INPUT_WIDTH, COEF_ROW_HEIGHT);
-        t_input.set_tooltip(&format!("phase of {} coefficient", term));
+        let mut t_input = ValueInput::default().with_size(l.coef_input_width, l.coef_row_height);
         t_input.set_value(t);
+        describe_value(
+            &mut t_input,
+            &format!("phase of {} coefficient", term),
+            &format!("{} pi", t),
+        );
 
         let pilab = Frame::default()
             .with_label("𝜋")
-            .with_size(COEF_VAR_WIDTH, COEF_ROW_HEIGHT);
+            .with_size(l.coef_var_width, l.coef_row_height);
+
+        r_input.set_callback({
+            let name = format!("modulus of {} coefficient", term);
+            move |i| {
+                let value = format!("{}", i.value());
+                describe_value(i, &name, &value);
+            }
+        });
+        t_input.set_callback({
+            let name = format!("phase of {} coefficient", term);
+            move |i| {
+                let value = format!("{} pi", i.value());
+                describe_value(i, &name, &value);
+            }
+        });
 
         rw.add(&deg_lab);
         rw.add(&rlab);
@@ -121,10 +137,8 @@ impl CoefSpecifier {
     }
 }
 
-// Specifying the sizes of the UI elements of the `IterPane`'s window.
-const COEF_BUTTON_WIDTH: i32 = 32;
-const INITIAL_ITER_PANE_HEIGHT: i32 = COEF_ROW_HEIGHT * 12;
-const ITER_SELECTOR_WIDTH: i32 = 192;
+// The initial height of the `IterPane`'s window, in rows.
+const INITIAL_ITER_PANE_ROWS: i32 = 13;
 
 static DEFAULT_COEFS: [[f64; 2]; 3] = [[0.7, 0.63], [0.0, 0.0], [1.0, 0.0]];
 
@@ -135,6 +149,7 @@ This struct holds and manages the UI elements for specifying an image's
 pub struct IterPane {
     win: DoubleWindow,
     selector: Choice,
+    period_check: CheckButton,
     pm_a: CoefSpecifier,
     pm_b: CoefSpecifier,
     coefs: Rc<RefCell<Vec<CoefSpecifier>>>,
@@ -146,34 +161,77 @@ impl IterPane {
     selected.
     */
     pub fn new(initial_state: IterType, pipe: std::sync::mpsc::Sender<Msg>) -> IterPane {
+        let l = layout::current();
         let scrn_w = fltk::app::screen_size().0 as i32;
+        let (x, y) = dock_begin(Dock::Iterator).unwrap_or((scrn_w - l.coef_row_width(), 0));
         let mut w = DoubleWindow::default()
-            .with_size(COEF_ROW_WIDTH, INITIAL_ITER_PANE_HEIGHT)
-            .with_pos(scrn_w - COEF_ROW_WIDTH, 0);
+            .with_size(
+                l.coef_row_width(),
+                l.coef_row_height * INITIAL_ITER_PANE_ROWS,
+            )
+            .with_pos(x, y);
         w.set_border(false);
+        w.set_label("Iterator Options");
+
+        // Compact panes leave out the labels.
+        let compact = l.iter_pane.compact;
+        let label = |text: &str, (x, y): (i32, i32), (w, h): (i32, i32)| {
+            if !compact {
+                let _ = Frame::default()
+                    .with_label(text)
+                    .with_pos(x, y)
+                    .with_size(w, h);
+            }
+        };
 
-        let _lab = Frame::default()
-            .with_label("Iterator Options")
-            .with_size(COEF_ROW_WIDTH, COEF_ROW_HEIGHT)
-            .with_pos(0, 0);
+        label(
+            "Iterator Options",
+            (0, 0),
+            (l.coef_row_width(), l.coef_row_height),
+        );
 
         let mut sel = Choice::default()
-            .with_label("Iterator")
-            .with_size(ITER_SELECTOR_WIDTH, COEF_ROW_HEIGHT)
-            .with_pos(COEF_ROW_WIDTH - ITER_SELECTOR_WIDTH, COEF_ROW_HEIGHT);
-        sel.add_choice("Mandlebrot|Pseudo-Mandlebrot|Polynomial");
+            .with_label(if compact { "" } else { "Iterator" })
+            .with_size(l.iter_selector_width, l.coef_row_height)
+            .with_pos(
+                l.coef_row_width() - l.iter_selector_width,
+                l.coef_row_height,
+            );
+        sel.add_choice(
+            "Mandlebrot|Pseudo-Mandlebrot|Polynomial|Tricorn|c·exp(z)|c·sin(z)|c·cos(z)",
+        );
+        sel.set_tooltip("the kind of iterator");
         match initial_state {
             IterType::Mandlebrot => sel.set_value(0),
             IterType::PseudoMandlebrot { a: _, b: _ } => sel.set_value(1),
             IterType::Polynomial { coefs: _ } => sel.set_value(2),
+            IterType::Tricorn => sel.set_value(3),
+            IterType::Exponential => sel.set_value(4),
+            IterType::Sine => sel.set_value(5),
+            IterType::Cosine => sel.set_value(6),
         };
 
+        let mut period_check = CheckButton::default()
+            .with_label("check for periodic orbits")
+            .with_size(l.coef_row_width(), l.coef_row_height)
+            .with_pos(0, 2 * l.coef_row_height);
+        period_check.set_checked(true);
+        period_check.set_tooltip(
+            "give up early on points stuck in a cycle; much faster, but may change a few pixels",
+        );
+        period_check.set_callback({
+            let pipe = pipe.clone();
+            move |b| {
+                pipe.send(Msg::CheckPeriod(b.is_checked())).unwrap();
+            }
+        });
+
         let mut pw = DoubleWindow::default()
-            .with_size(COEF_ROW_WIDTH, 3 * COEF_ROW_HEIGHT)
-            .with_pos(0, 2 * COEF_ROW_HEIGHT);
+            .with_size(l.coef_row_width(), 3 * l.coef_row_height)
+            .with_pos(0, 3 * l.coef_row_height);
         let mut pw_label = Frame::default()
             .with_pos(0, 0)
-            .with_size(COEF_ROW_WIDTH, COEF_ROW_HEIGHT)
+            .with_size(l.coef_row_width(), l.coef_row_height)
             .with_label("az^2 + bc");
         pw_label.set_label_font(MATH_FONT);
         let mut a: CoefSpecifier;
@@ -192,55 +250,63 @@ impl IterPane {
                 b = CoefSpecifier::new("b", 1.0, 0.0);
             }
         }
-        a.get_mut_row().set_pos(0, COEF_ROW_HEIGHT);
-        b.get_mut_row().set_pos(0, COEF_ROW_HEIGHT * 2);
+        a.get_mut_row().set_pos(0, l.coef_row_height);
+        b.get_mut_row().set_pos(0, l.coef_row_height * 2);
         pw.end();
         pw.deactivate();
 
         let mut cs: Vec<CoefSpecifier> = Vec::new();
 
         let mut pyw = DoubleWindow::default()
-            .with_size(COEF_ROW_WIDTH, 7 * COEF_ROW_HEIGHT)
-            .with_pos(0, 5 * COEF_ROW_HEIGHT);
-        let _ = Frame::default()
-            .with_size(COEF_ROW_WIDTH, COEF_ROW_HEIGHT)
-            .with_label("Polynomial Coefficients")
-            .with_pos(0, 0);
-        let _ = Frame::default()
-            .with_pos(0, COEF_ROW_HEIGHT)
-            .with_size(COEF_ROW_WIDTH - COEF_BUTTON_WIDTH, COEF_ROW_HEIGHT)
-            .with_label("decrease degree");
-        let _ = Frame::default()
-            .with_pos(COEF_BUTTON_WIDTH, 2 * COEF_ROW_HEIGHT)
-            .with_size(COEF_ROW_WIDTH - COEF_BUTTON_WIDTH, COEF_ROW_HEIGHT)
-            .with_label("increase degree");
+            .with_size(l.coef_row_width(), 7 * l.coef_row_height)
+            .with_pos(0, 6 * l.coef_row_height);
+        label(
+            "Polynomial Coefficients",
+            (0, 0),
+            (l.coef_row_width(), l.coef_row_height),
+        );
+        label(
+            "decrease degree",
+            (0, l.coef_row_height),
+            (l.coef_row_width() - l.coef_button_width, l.coef_row_height),
+        );
+        label(
+            "increase degree",
+            (l.coef_button_width, 2 * l.coef_row_height),
+            (l.coef_row_width() - l.coef_button_width, l.coef_row_height),
+        );
 
         let mut coef_add = Button::default()
             .with_label("@+")
-            .with_size(COEF_BUTTON_WIDTH, COEF_ROW_HEIGHT)
-            .with_pos(0, 2 * COEF_ROW_HEIGHT);
+            .with_size(l.coef_button_width, l.coef_row_height)
+            .with_pos(0, 2 * l.coef_row_height);
         coef_add.set_tooltip("add a z^3 coefficient");
         let mut coef_del = Button::default()
             .with_label("@line")
-            .with_pos(COEF_ROW_WIDTH - COEF_BUTTON_WIDTH, COEF_ROW_HEIGHT)
-            .with_size(COEF_BUTTON_WIDTH, COEF_ROW_HEIGHT);
+            .with_pos(l.coef_row_width() - l.coef_button_width, l.coef_row_height)
+            .with_size(l.coef_button_width, l.coef_row_height);
         coef_del.set_tooltip("remove the z^2 coefficient");
 
         match initial_state {
             IterType::Polynomial { coefs: ref v } => {
-                w.set_size(COEF_ROW_WIDTH, (v.len() as i32 + 9) * COEF_ROW_HEIGHT);
-                pyw.set_size(COEF_ROW_WIDTH, (v.len() as i32 + 4) * COEF_ROW_HEIGHT);
+                w.set_size(
+                    l.coef_row_width(),
+                    (v.len() as i32 + 10) * l.coef_row_height,
+                );
+                pyw.set_size(l.coef_row_width(), (v.len() as i32 + 4) * l.coef_row_height);
                 for (n, z) in v.iter().enumerate() {
                     let mut c =
                         CoefSpecifier::new(&CoefSpecifier::term_label(n), z.r(), z.theta() / PI);
-                    c.get_mut_row().set_pos(0, (n as i32 + 3) * COEF_ROW_HEIGHT);
+                    c.get_mut_row()
+                        .set_pos(0, (n as i32 + 3) * l.coef_row_height);
                     cs.push(c);
                 }
             }
             _ => {
                 for (n, coef) in DEFAULT_COEFS.iter().enumerate() {
                     let mut c = CoefSpecifier::new(&CoefSpecifier::term_label(n), coef[0], coef[1]);
-                    c.get_mut_row().set_pos(0, (n as i32 + 3) * COEF_ROW_HEIGHT);
+                    c.get_mut_row()
+                        .set_pos(0, (n as i32 + 3) * l.coef_row_height);
                     cs.push(c);
                 }
             }
@@ -248,18 +314,24 @@ impl IterPane {
         pyw.end();
         pyw.deactivate();
 
-        w.end();
+        end_pane(&mut w);
         w.show();
+        if w.parent().is_none() {
+            w.set_opacity(l.iter_pane.opacity);
+        }
 
-        setup_subwindow_behavior(&mut w, pipe);
+        setup_subwindow_behavior(&mut w, pipe.clone());
 
         let cs = Rc::new(RefCell::new(cs));
 
-        sel.set_callback({
+        // Only the parameters of the selected iterator type are editable.
+        let mut show_params = {
             let mut pw = pw.clone();
             let mut pyw = pyw.clone();
-            move |s| match s.value() {
-                0 => {
+            move |n: i32| match n {
+                // The tricorn and the transcendental iterators have no
+                // parameters.
+                0 | 3..=6 => {
                     pw.deactivate();
                     pyw.deactivate();
                 }
@@ -275,8 +347,20 @@ impl IterPane {
                     eprintln!("IterPane::selector callback illegal value: {}", n);
                 }
             }
+        };
+        show_params(sel.value());
+
+        sel.set_callback({
+            let mut last = sel.value();
+            move |s| {
+                let n = s.value();
+                show_params(n);
+                if n != last {
+                    last = n;
+                    pipe.send(Msg::IterTypeChanged).unwrap();
+                }
+            }
         });
-        sel.do_callback();
 
         coef_del.set_callback({
             let mut win = w.clone();
@@ -288,9 +372,9 @@ impl IterPane {
                     let old_spec = cs.borrow_mut().pop().unwrap();
                     pyw.remove(old_spec.get_row());
                     let (w, h) = (pyw.w(), pyw.h());
-                    pyw.set_size(w, h - COEF_ROW_HEIGHT);
+                    pyw.set_size(w, h - l.coef_row_height);
                     let h = win.h();
-                    win.set_size(w, h - COEF_ROW_HEIGHT);
+                    win.set_size(w, h - l.coef_row_height);
                     Pack::delete(old_spec.row);
                 }
 
@@ -318,11 +402,11 @@ impl IterPane {
             let cs = cs.clone();
             move |b| {
                 let (w, h) = (win.w(), win.h());
-                win.set_size(w, h + COEF_ROW_HEIGHT);
+                win.set_size(w, h + l.coef_row_height);
                 let h = pyw.h();
-                pyw.set_size(w, h + COEF_ROW_HEIGHT);
+                pyw.set_size(w, h + l.coef_row_height);
                 let n = cs.borrow().len();
-                let y_pos = (3 + n as i32) * COEF_ROW_HEIGHT;
+                let y_pos = (3 + n as i32) * l.coef_row_height;
                 let mut new_coef = CoefSpecifier::new(&CoefSpecifier::term_label(n), 0.0, 0.0);
                 pyw.add(new_coef.get_row());
                 new_coef.get_mut_row().set_pos(0, y_pos);
@@ -342,6 +426,7 @@ impl IterPane {
         IterPane {
             win: w,
             selector: sel,
+            period_check,
             pm_a: a,
             pm_b: b,
             coefs: cs,
@@ -358,9 +443,40 @@ impl IterPane {
     `hide_before_raise` feature.
     */
     pub fn raise(&mut self) {
+        if show_docked(Dock::Iterator) {
+            return;
+        }
         #[cfg(feature = "hide_before_raise")]
         self.win.hide();
         self.win.show();
+        self.win.set_opacity(layout::current().iter_pane.opacity);
+    }
+
+    /** Where the window is on the screen (`None` if it's docked). */
+    pub fn position(&self) -> Option<(i32, i32)> {
+        match self.win.parent() {
+            Some(_) => None,
+            None => Some((self.win.x(), self.win.y())),
+        }
+    }
+
+    /**
+    Move the window to (`x`, `y`), or as near as it can go and still be on
+    a screen. Docked panes stay put.
+    */
+    pub fn move_to(&mut self, x: i32, y: i32) {
+        if self.win.parent().is_none() {
+            let (x, y) = fit_on_screen(x, y, self.win.w(), self.win.h());
+            self.win.set_pos(x, y);
+        }
+    }
+
+    /**
+    Show whether iteration checks for periodic orbits (see
+    `image::ImageDims`).
+    */
+    pub fn set_periodicity_checking(&mut self, on: bool) {
+        self.period_check.set_checked(on);
     }
 
     /**Return the `image::IterType` currently specified by the `IterPane`.*/
@@ -374,6 +490,10 @@ impl IterPane {
             2 => IterType::Polynomial {
                 coefs: self.coefs.borrow().iter().map(|c| c.get_value()).collect(),
             },
+            3 => IterType::Tricorn,
+            4 => IterType::Exponential,
+            5 => IterType::Sine,
+            6 => IterType::Cosine,
             n => {
                 eprintln!("IterPane::get_itertype(): illegal selector value: {}", &n);
                 IterType::Mandlebrot
@@ -397,9 +517,11 @@ mod test {
     use super::*;
 
     #[test]
+    #[ignore = "interactive; opens a window and waits for it to be closed"]
     fn iter_pane() {
         let a = fltk::app::App::default();
-        let p = IterPane::new();
+        let (tx, _rx) = std::sync::mpsc::channel::<Msg>();
+        let p = IterPane::new(IterType::Mandlebrot, tx);
 
         let mut w = DoubleWindow::default().with_size(100, 100);
         let mut b = Button::default()
@@ -0,0 +1,250 @@
+/*!
+A popup window for editing the stack of coloring layers blended over the
+color map (see `image::Layer`).
+
+Each layer gets a row, bottom one first, with a blend mode, an opacity
+slider, and a button to take it out. New layers are read from color map
+files, the same kind the color pane loads.
+*/
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc;
+
+use fltk::{
+    button::Button,
+    dialog,
+    enums::{Align, Key, Shortcut},
+    frame::Frame,
+    menu::Choice,
+    prelude::*,
+    valuator::HorNiceSlider,
+    window::DoubleWindow,
+};
+
+use super::{choose_file, describe_value, layout};
+use crate::image::{Blend, Layer, Orbit, OrbitStat, Trap};
+use crate::rw;
+
+// How opaque a newly added layer starts out.
+const NEW_OPACITY: f32 = 0.5;
+
+// What the editor's buttons ask for.
+enum Action {
+    Add,
+    Remove(usize),
+    Done(Option<Vec<Layer>>),
+}
+
+// A short description of what a layer colors by, and with how many colors.
+fn describe(layer: &Layer) -> String {
+    let by = match layer.spec.orbit() {
+        None => "escape time",
+        Some(Orbit::Trap(Trap::Point { .. })) => "point trap",
+        Some(Orbit::Trap(Trap::Line { .. })) => "line trap",
+        Some(Orbit::Trap(Trap::Circle { .. })) => "circle trap",
+        Some(Orbit::Stat(OrbitStat::Stripe { .. })) => "stripe average",
+        Some(Orbit::Stat(OrbitStat::Triangle)) => "triangle average",
+    };
+    format!("{}, {} colors", by, layer.spec.len())
+}
+
+fn blend_name(b: Blend) -> &'static str {
+    match b {
+        Blend::Normal => "normal",
+        Blend::Multiply => "multiply",
+        Blend::Screen => "screen",
+        Blend::Overlay => "overlay",
+    }
+}
+
+// Build the editor window for the layers in `layers`, hooking its buttons
+// up to send their `Action`s down `tx`.
+fn build(layers: &Rc<RefCell<Vec<Layer>>>, tx: &mpsc::Sender<Action>) -> DoubleWindow {
+    let l = layout::current();
+    let row_h = l.picker_row_height;
+    let width = 2 * l.curve_graph_size;
+    let col_w = width / 8;
+    let n = layers.borrow().len() as i32;
+
+    let mut w = DoubleWindow::default()
+        .with_label("Layers")
+        .with_size(width, (n.max(1) + 1) * row_h);
+    if n == 0 {
+        let _ = Frame::default()
+            .with_label("no layers; the color map is all there is")
+            .with_pos(0, 0)
+            .with_size(width, row_h);
+    }
+    for (i, layer) in layers.borrow().iter().enumerate() {
+        let ypos = i as i32 * row_h;
+        let mut label = Frame::default()
+            .with_label(&format!("{}: {}", i + 1, describe(layer)))
+            .with_pos(0, ypos)
+            .with_size(3 * col_w, row_h);
+        label.set_align(Align::Left | Align::Inside);
+        let mut blend = Choice::default()
+            .with_pos(3 * col_w, ypos)
+            .with_size(2 * col_w, row_h);
+        for b in Blend::ALL.iter() {
+            blend.add_choice(blend_name(*b));
+        }
+        blend.set_value(
+            Blend::ALL
+                .iter()
+                .position(|b| *b == layer.blend)
+                .unwrap_or(0) as i32,
+        );
+        blend.set_tooltip("how the layer combines with what's under it");
+        blend.set_callback({
+            let layers = layers.clone();
+            move |c| {
+                let b = Blend::ALL[c.value().clamp(0, 3) as usize];
+                layers.borrow_mut()[i].blend = b;
+            }
+        });
+        let mut opacity = HorNiceSlider::default()
+            .with_pos(5 * col_w, ypos)
+            .with_size(2 * col_w, row_h);
+        opacity.set_range(0.0, 1.0);
+        opacity.set_value(layer.opacity as f64);
+        describe_value(&mut opacity, "opacity", &format!("{:.2}", layer.opacity));
+        opacity.set_callback({
+            let layers = layers.clone();
+            move |s| {
+                layers.borrow_mut()[i].opacity = s.value() as f32;
+                describe_value(s, "opacity", &format!("{:.2}", s.value()));
+            }
+        });
+        let mut remove = Button::default()
+            .with_label("x")
+            .with_pos(7 * col_w, ypos)
+            .with_size(width - 7 * col_w, row_h);
+        remove.set_tooltip("take this layer out");
+        remove.set_callback({
+            let tx = tx.clone();
+            move |_| tx.send(Action::Remove(i)).unwrap()
+        });
+    }
+
+    let ypos = n.max(1) * row_h;
+    let mut add = Button::default()
+        .with_label("add...")
+        .with_pos(0, ypos)
+        .with_size(2 * col_w, row_h);
+    add.set_tooltip("add a layer colored by the color map in a file");
+    add.set_callback({
+        let tx = tx.clone();
+        move |_| tx.send(Action::Add).unwrap()
+    });
+    let mut ok = Button::default()
+        .with_label("Set @returnarrow")
+        .with_pos(4 * col_w, ypos)
+        .with_size(2 * col_w, row_h);
+    ok.set_shortcut(Shortcut::from_key(Key::Enter));
+    ok.set_callback({
+        let tx = tx.clone();
+        let layers = layers.clone();
+        move |_| {
+            tx.send(Action::Done(Some(layers.borrow().clone())))
+                .unwrap()
+        }
+    });
+    let mut no = Button::default()
+        .with_label("Cancel")
+        .with_pos(6 * col_w, ypos)
+        .with_size(width - 6 * col_w, row_h);
+    no.set_shortcut(Shortcut::from_key(Key::Escape));
+    no.set_callback({
+        let tx = tx.clone();
+        move |_| tx.send(Action::Done(None)).unwrap()
+    });
+
+    w.end();
+    w.make_modal(true);
+    w.show();
+    w
+}
+
+/**
+Pops up a modal window for editing the layers, starting with `start`.
+Returns the edited layers, or `None` if the editing is cancelled.
+*/
+pub fn edit_layers(start: &[Layer]) -> Option<Vec<Layer>> {
+    let layers = Rc::new(RefCell::new(start.to_vec()));
+    let (tx, rx) = mpsc::channel::<Action>();
+    let mut w = build(&layers, &tx);
+
+    loop {
+        match rx.try_recv() {
+            Err(_) => {
+                fltk::app::wait();
+            }
+            Ok(Action::Done(result)) => {
+                DoubleWindow::delete(w);
+                return result;
+            }
+            Ok(act) => {
+                match act {
+                    Action::Remove(n) => {
+                        layers.borrow_mut().remove(n);
+                    }
+                    _ => {
+                        let fname = match choose_file(
+                            "Add a layer from:",
+                            "Color maps\t*.{toml,json,png,ugr,cpt}",
+                        ) {
+                            Some(f) => f,
+                            None => continue,
+                        };
+                        match rw::load_colors(&fname) {
+                            Ok(spec) => layers.borrow_mut().push(Layer {
+                                blend: Blend::Normal,
+                                opacity: NEW_OPACITY,
+                                spec,
+                            }),
+                            Err(e) => {
+                                dialog::alert_default(&format!("Error loading {}: {}", &fname, &e));
+                                continue;
+                            }
+                        }
+                    }
+                }
+                let pos = (w.x(), w.y());
+                DoubleWindow::delete(w);
+                w = build(&layers, &tx);
+                w.set_pos(pos.0, pos.1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::{ColorSpec, Gradient, RGB};
+
+    #[test]
+    fn describing() {
+        let mut spec = ColorSpec::new(vec![Gradient::default(); 2], RGB::BLACK);
+        let n = spec.len();
+        let mut layer = Layer {
+            blend: Blend::Screen,
+            opacity: 1.0,
+            spec: spec.clone(),
+        };
+        assert_eq!(describe(&layer), format!("escape time, {} colors", n));
+        spec.set_orbit(Some(Orbit::Stat(OrbitStat::Stripe { density: 3.0 })));
+        layer.spec = spec;
+        assert_eq!(describe(&layer), format!("stripe average, {} colors", n));
+        let names: Vec<&str> = Blend::ALL.iter().map(|b| blend_name(*b)).collect();
+        assert_eq!(names, vec!["normal", "multiply", "screen", "overlay"]);
+    }
+
+    #[test]
+    #[ignore = "interactive; opens a window and waits for it to be closed"]
+    fn layer_editor() {
+        let _a = fltk::app::App::default();
+        println!("{:?}", edit_layers(&[]));
+    }
+}
@@ -0,0 +1,472 @@
+/*!
+The sizes of all the bits of the user interface.
+
+Every pixel dimension used to lay out the three windows lives in a
+`Layout`, so that they can all be scaled together by a single factor:
+up for HiDPI screens or bigger, easier-to-read widgets, or down for a
+compact layout on a small screen. The windows read the current layout
+with `current()` when they're built, so `set_scale()` should be called
+before any of them are.
+*/
+
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+
+/** Where the main window's controls go. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlStyle {
+    /// A column down the left side of the image.
+    Column,
+    /// Rows of mostly icons along the top, for wide images on short screens.
+    Toolbar,
+}
+
+impl ControlStyle {
+    /** Parse a style name ("column" or "toolbar", in any case). */
+    pub fn from_name(name: &str) -> Option<ControlStyle> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "column" => Some(ControlStyle::Column),
+            "toolbar" => Some(ControlStyle::Toolbar),
+            _ => None,
+        }
+    }
+}
+
+/** Where the color map and iterator panes (and the minimap) go. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanePlacement {
+    /// Each in its own borderless window, that can be dragged around.
+    Floating,
+    /// In tabs down the right side of the main window.
+    Docked,
+}
+
+impl PanePlacement {
+    /** Parse a placement name ("floating" or "docked", in any case). */
+    pub fn from_name(name: &str) -> Option<PanePlacement> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "floating" => Some(PanePlacement::Floating),
+            "docked" => Some(PanePlacement::Docked),
+            _ => None,
+        }
+    }
+}
+
+/** The least opaque a side pane can be made. */
+pub const MIN_OPACITY: f64 = 0.2;
+// How much smaller the rows of a compact pane are.
+const COMPACT_SCALE: f64 = 0.75;
+
+/** How one of the side panes (color map or iterator) is drawn. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PaneStyle {
+    /// Smaller rows, and no labels (tooltips still say what things do).
+    pub compact: bool,
+    /// From `MIN_OPACITY` (mostly see-through) to 1 (opaque).
+    pub opacity: f64,
+}
+
+impl PaneStyle {
+    pub const NORMAL: PaneStyle = PaneStyle {
+        compact: false,
+        opacity: 1.0,
+    };
+
+    /**
+    Parse a pane style: "compact", an opacity (like "0.8"), or both,
+    separated by a comma or spaces. The opacity is forced into the range
+    `MIN_OPACITY` to 1.
+    */
+    pub fn parse(spec: &str) -> Result<PaneStyle, String> {
+        let mut style = PaneStyle::NORMAL;
+        for tok in spec.split(|c: char| c == ',' || c.is_whitespace()) {
+            let tok = tok.trim();
+            if tok.is_empty() {
+                continue;
+            } else if tok.eq_ignore_ascii_case("compact") {
+                style.compact = true;
+            } else if tok.eq_ignore_ascii_case("normal") {
+                style.compact = false;
+            } else {
+                match tok.parse::<f64>() {
+                    Ok(x) if x.is_finite() => {
+                        style.opacity = x.clamp(MIN_OPACITY, 1.0);
+                    }
+                    _ => {
+                        return Err(format!("Unrecognized pane style: {}", tok));
+                    }
+                }
+            }
+        }
+        Ok(style)
+    }
+}
+
+/** The smallest scale factor `set_scale()` will accept. */
+pub const MIN_SCALE: f64 = 0.5;
+/** The largest scale factor `set_scale()` will accept. */
+pub const MAX_SCALE: f64 = 4.0;
+
+/**
+Sizes (in pixels) of UI elements. The derived sizes (like the width of a
+whole row, which is the sum of the widths of its parts) are methods, so
+they can't disagree with the sizes they're derived from.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    /// The factor by which these sizes have been scaled from the defaults.
+    pub scale: f64,
+    /// Size of the text in labels and inputs.
+    pub font_size: i32,
+    /// Where the main window's controls go.
+    pub controls: ControlStyle,
+    /// Where the side panes go.
+    pub panes: PanePlacement,
+    /// How the color map pane is drawn.
+    pub color_pane: PaneStyle,
+    /// How the iterator pane is drawn.
+    pub iter_pane: PaneStyle,
+
+    // The main window's column of controls.
+    pub col_width: i32,
+    pub row_height: i32,
+
+    // The color picker popup.
+    pub picker_label_width: i32,
+    pub picker_slider_width: i32,
+    pub picker_input_width: i32,
+    pub picker_row_height: i32,
+
+    // The curves editor popup.
+    pub curve_graph_size: i32,
+
+    // The minimap window.
+    pub minimap_width: i32,
+
+    // The scroll bars of docked panes.
+    pub scrollbar_width: i32,
+
+    // The rows of the color pane.
+    pub gradient_button_width: i32,
+    pub gradient_row_height: i32,
+    pub gradient_steps_width: i32,
+    pub gradient_swap_width: i32,
+    pub gradient_space_width: i32,
+    pub gradient_move_width: i32,
+
+    // The iterator pane.
+    pub coef_row_height: i32,
+    pub coef_degree_width: i32,
+    pub coef_var_width: i32,
+    pub coef_input_width: i32,
+    pub coef_button_width: i32,
+    pub iter_selector_width: i32,
+}
+
+// The unscaled sizes.
+const BASE: Layout = Layout {
+    scale: 1.0,
+    font_size: 14,
+    controls: ControlStyle::Column,
+    panes: PanePlacement::Floating,
+    color_pane: PaneStyle::NORMAL,
+    iter_pane: PaneStyle::NORMAL,
+
+    col_width: 72,
+    row_height: 24,
+
+    picker_label_width: 24,
+    picker_slider_width: 256,
+    picker_input_width: 48,
+    picker_row_height: 32,
+
+    curve_graph_size: 320,
+
+    minimap_width: 192,
+
+    scrollbar_width: 16,
+
+    gradient_button_width: 32,
+    gradient_row_height: 32,
+    gradient_steps_width: 64,
+    gradient_swap_width: 24,
+    gradient_space_width: 56,
+    gradient_move_width: 20,
+
+    coef_row_height: 32,
+    coef_degree_width: 48,
+    coef_var_width: 16,
+    coef_input_width: 72,
+    coef_button_width: 32,
+    iter_selector_width: 192,
+};
+
+impl Layout {
+    /**
+    Return the default layout with everything scaled by `scale`, which
+    will be forced into the range `MIN_SCALE` to `MAX_SCALE`.
+    */
+    pub fn scaled(scale: f64) -> Layout {
+        let scale = if scale.is_finite() {
+            scale.clamp(MIN_SCALE, MAX_SCALE)
+        } else {
+            1.0
+        };
+        let px = |n: i32| ((n as f64) * scale).round() as i32;
+
+        Layout {
+            scale,
+            font_size: px(BASE.font_size),
+            controls: BASE.controls,
+            panes: BASE.panes,
+            color_pane: BASE.color_pane,
+            iter_pane: BASE.iter_pane,
+
+            col_width: px(BASE.col_width),
+            row_height: px(BASE.row_height),
+
+            picker_label_width: px(BASE.picker_label_width),
+            picker_slider_width: px(BASE.picker_slider_width),
+            picker_input_width: px(BASE.picker_input_width),
+            picker_row_height: px(BASE.picker_row_height),
+
+            curve_graph_size: px(BASE.curve_graph_size),
+
+            minimap_width: px(BASE.minimap_width),
+
+            scrollbar_width: px(BASE.scrollbar_width),
+
+            gradient_button_width: px(BASE.gradient_button_width),
+            gradient_row_height: px(BASE.gradient_row_height),
+            gradient_steps_width: px(BASE.gradient_steps_width),
+            gradient_swap_width: px(BASE.gradient_swap_width),
+            gradient_space_width: px(BASE.gradient_space_width),
+            gradient_move_width: px(BASE.gradient_move_width),
+
+            coef_row_height: px(BASE.coef_row_height),
+            coef_degree_width: px(BASE.coef_degree_width),
+            coef_var_width: px(BASE.coef_var_width),
+            coef_input_width: px(BASE.coef_input_width),
+            coef_button_width: px(BASE.coef_button_width),
+            iter_selector_width: px(BASE.iter_selector_width),
+        }
+    }
+
+    /** Width of half a button in the main window's column of controls. */
+    pub fn half_button(&self) -> i32 {
+        self.col_width / 2
+    }
+
+    /** Width of the swatch of the color being picked. */
+    pub fn picker_output_width(&self) -> i32 {
+        4 * self.picker_row_height
+    }
+    /** Width of a row of color picker sliders. */
+    pub fn picker_row_width(&self) -> i32 {
+        self.picker_label_width + self.picker_slider_width + self.picker_input_width
+    }
+    pub fn picker_window_width(&self) -> i32 {
+        self.picker_row_width() + self.picker_output_width()
+    }
+    /** Height of the color picker above its row of recent colors. */
+    pub fn picker_body_height(&self) -> i32 {
+        self.picker_row_height * 7
+    }
+    pub fn picker_window_height(&self) -> i32 {
+        self.picker_body_height() + self.picker_row_height
+    }
+    pub fn picker_button_width(&self) -> i32 {
+        self.picker_row_width() / 3
+    }
+
+    /** Height of the minimap, which is 3:2 like the default view. */
+    pub fn minimap_height(&self) -> i32 {
+        2 * self.minimap_width / 3
+    }
+
+    /**
+    Return this layout with the side panes drawn in the given styles;
+    the rows of compact panes are shrunk.
+    */
+    pub fn restyled(&self, color_pane: PaneStyle, iter_pane: PaneStyle) -> Layout {
+        let mut l = Layout::scaled(self.scale);
+        l.controls = self.controls;
+        l.panes = self.panes;
+        l.color_pane = color_pane;
+        l.iter_pane = iter_pane;
+        let px = |n: &mut i32| *n = ((*n as f64) * COMPACT_SCALE).round() as i32;
+        if color_pane.compact {
+            px(&mut l.gradient_button_width);
+            px(&mut l.gradient_row_height);
+            px(&mut l.gradient_steps_width);
+            px(&mut l.gradient_swap_width);
+            px(&mut l.gradient_space_width);
+            px(&mut l.gradient_move_width);
+        }
+        if iter_pane.compact {
+            px(&mut l.coef_row_height);
+            px(&mut l.coef_degree_width);
+            px(&mut l.coef_var_width);
+            px(&mut l.coef_input_width);
+            px(&mut l.coef_button_width);
+            px(&mut l.iter_selector_width);
+        }
+        l
+    }
+
+    /** Width of the widgets for specifying a single gradient. */
+    pub fn gradient_row_width(&self) -> i32 {
+        (2 * self.gradient_button_width)
+            + self.gradient_steps_width
+            + self.gradient_swap_width
+            + self.gradient_space_width
+    }
+    pub fn color_pane_width(&self) -> i32 {
+        (3 * self.gradient_button_width) + self.gradient_row_width() + self.gradient_move_width
+    }
+
+    /** Width of a row for specifying one complex coefficient. */
+    pub fn coef_row_width(&self) -> i32 {
+        self.coef_degree_width + (4 * self.coef_var_width) + (2 * self.coef_input_width)
+    }
+
+    /**
+    Width of the area down the side of the main window that docked panes
+    go in: wide enough for the widest of them, and a scroll bar.
+    */
+    pub fn dock_width(&self) -> i32 {
+        self.color_pane_width()
+            .max(self.coef_row_width())
+            .max(self.minimap_width)
+            + self.scrollbar_width
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Layout {
+        BASE
+    }
+}
+
+lazy_static! {
+    static ref CURRENT: RwLock<Layout> = RwLock::new(BASE);
+}
+
+/**
+Scale the whole UI by `scale` (1.0 being the default size). This also
+sets the default font size, so it has to be called after the
+`fltk::app::App` has been created, but before any windows are.
+*/
+pub fn set_scale(scale: f64) {
+    let cur = current();
+    let mut l = Layout::scaled(scale);
+    l.controls = cur.controls;
+    l.panes = cur.panes;
+    let l = l.restyled(cur.color_pane, cur.iter_pane);
+    fltk::app::set_font_size(l.font_size);
+    *CURRENT.write().unwrap() = l;
+}
+
+/**
+Turn off FLTK's own scaling for HiDPI screens, and return the factor it
+would have scaled everything by (the main screen's), so that it can be
+folded into the one given to `set_scale()` instead. FLTK scales images
+along with everything else, which blurs them; this way the controls come
+out the same size, but images are shown one pixel to a screen pixel. Call
+it after the `fltk::app::App` has been created, before any windows are.
+*/
+pub fn take_over_screen_scaling() -> f64 {
+    let factor = fltk::app::screen_scale(0) as f64;
+    for n in 0..fltk::app::screen_count() {
+        fltk::app::set_screen_scale(n, 1.0);
+    }
+    if factor.is_finite() && factor > 0.0 {
+        factor
+    } else {
+        1.0
+    }
+}
+
+/**
+Return how many screen pixels `pixels` (meant for an ordinary screen)
+should be on a screen that scales things by `screen_scale`, so that an
+image of that size takes up the same room as it would there.
+*/
+pub fn for_screen(pixels: usize, screen_scale: f64) -> usize {
+    let screen_scale = if screen_scale.is_finite() {
+        screen_scale.clamp(MIN_SCALE, MAX_SCALE)
+    } else {
+        1.0
+    };
+    ((pixels as f64) * screen_scale).round().max(1.0) as usize
+}
+
+/** Choose where the main window's controls go; call before it's built. */
+pub fn set_control_style(style: ControlStyle) {
+    CURRENT.write().unwrap().controls = style;
+}
+
+/** Choose where the side panes go; call before any windows are built. */
+pub fn set_pane_placement(panes: PanePlacement) {
+    CURRENT.write().unwrap().panes = panes;
+}
+
+/** Choose how the side panes are drawn; call before they're built. */
+pub fn set_pane_styles(color_pane: PaneStyle, iter_pane: PaneStyle) {
+    let l = current().restyled(color_pane, iter_pane);
+    *CURRENT.write().unwrap() = l;
+}
+
+/** Return the current layout. */
+pub fn current() -> Layout {
+    *CURRENT.read().unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scaling() {
+        assert_eq!(Layout::scaled(1.0), Layout::default());
+
+        let big = Layout::scaled(2.0);
+        assert_eq!(big.col_width, 2 * BASE.col_width);
+        assert_eq!(big.coef_row_width(), 2 * BASE.coef_row_width());
+
+        assert_eq!(Layout::scaled(100.0).scale, MAX_SCALE);
+        assert_eq!(Layout::scaled(0.0).scale, MIN_SCALE);
+        assert_eq!(Layout::scaled(f64::NAN).scale, 1.0);
+        assert_eq!(for_screen(900, 2.0), 1800);
+        assert_eq!(for_screen(900, 1.25), 1125);
+        assert_eq!(for_screen(900, f64::INFINITY), 900);
+
+        assert_eq!(
+            ControlStyle::from_name(" Toolbar"),
+            Some(ControlStyle::Toolbar)
+        );
+        assert_eq!(
+            ControlStyle::from_name("column"),
+            Some(ControlStyle::Column)
+        );
+        assert_eq!(ControlStyle::from_name("ribbon"), None);
+        assert_eq!(
+            PanePlacement::from_name("Docked "),
+            Some(PanePlacement::Docked)
+        );
+        assert_eq!(PanePlacement::from_name("tabs"), None);
+
+        let style = PaneStyle::parse(" compact, 0.7").unwrap();
+        assert_eq!((style.compact, style.opacity), (true, 0.7));
+        assert_eq!(PaneStyle::parse("0").unwrap().opacity, MIN_OPACITY);
+        assert_eq!(PaneStyle::parse("").unwrap(), PaneStyle::NORMAL);
+        assert!(PaneStyle::parse("compact see-through").is_err());
+
+        let l = big.restyled(style, PaneStyle::NORMAL);
+        assert_eq!(l.gradient_row_height, 48);
+        assert_eq!((l.coef_row_height, l.col_width), (64, big.col_width));
+        assert_eq!(l.restyled(PaneStyle::NORMAL, PaneStyle::NORMAL), big);
+        assert_eq!(big.dock_width(), 2 * BASE.dock_width());
+    }
+}
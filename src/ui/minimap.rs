@@ -0,0 +1,213 @@
+/*!
+A small window showing the current iterator's default view (the whole set,
+more or less) with a rectangle marking the part of it the main window is
+showing, so it's easy to tell where a deep zoom is. Clicking on it moves
+the main view there, at the same zoom.
+*/
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::mpsc;
+
+use fltk::{
+    draw,
+    enums::{Color, ColorDepth, Event},
+    frame::Frame,
+    image::RgbImage,
+    prelude::*,
+    window::DoubleWindow,
+};
+
+use super::{dock_begin, end_pane, fit_on_screen, layout, setup_subwindow_behavior, Dock, Msg};
+use crate::image::ImageDims;
+use crate::mapping;
+
+// The smallest the rectangle marking the main view gets drawn (in pixels
+// each way), so that it doesn't disappear on deep zooms.
+const MIN_MARK: i32 = 5;
+
+/** The minimap window. */
+pub struct MiniMap {
+    win: DoubleWindow,
+    frame: Frame,
+    // The view of the plane the minimap shows.
+    overview: Rc<Cell<ImageDims>>,
+    // Where the main view is on the minimap (x, y, width, height).
+    mark: Rc<Cell<(i32, i32, i32, i32)>>,
+    image_data: Vec<u8>,
+}
+
+impl MiniMap {
+    /**
+    Open the minimap in the lower left corner of the screen (or in its
+    place in the main window, if it's docked). When it's clicked, it sends
+    a `Msg::JumpTo` down `pipe`.
+    */
+    pub fn new(pipe: mpsc::Sender<Msg>) -> MiniMap {
+        let l = layout::current();
+        let (w, h) = (l.minimap_width, l.minimap_height());
+        let scrn_h = fltk::app::screen_size().1 as i32;
+        let (x, y) = dock_begin(Dock::Overview).unwrap_or((0, scrn_h - h));
+        let mut win = DoubleWindow::default().with_size(w, h).with_pos(x, y);
+        win.set_border(false);
+        win.set_label("Overview");
+        let mut frame = Frame::default().with_pos(0, 0).with_size(w, h);
+        frame.set_color(Color::Black);
+        frame.set_tooltip("click to move the view here");
+        end_pane(&mut win);
+        setup_subwindow_behavior(&mut win, pipe.clone());
+        win.show();
+
+        let overview = Rc::new(Cell::new(ImageDims {
+            xpix: w as usize,
+            ypix: h as usize,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        }));
+        let mark = Rc::new(Cell::new((0, 0, 0, 0)));
+
+        frame.draw({
+            let mark = mark.clone();
+            move |f| {
+                let (x, y, w, h) = mark.get();
+                if w > 0 && h > 0 {
+                    draw::draw_rect_with_color(f.x() + x, f.y() + y, w, h, Color::Black);
+                    if w > 2 && h > 2 {
+                        let (x, y) = (f.x() + x + 1, f.y() + y + 1);
+                        draw::draw_rect_with_color(x, y, w - 2, h - 2, Color::White);
+                    }
+                }
+            }
+        });
+        frame.handle({
+            let overview = overview.clone();
+            move |f, evt| match evt {
+                Event::Push => true,
+                Event::Released => {
+                    let (ex, ey) = fltk::app::event_coords();
+                    if f.w() > 0 && f.h() > 0 {
+                        let x_frac = ((ex - f.x()) as f64) / (f.w() as f64);
+                        let y_frac = ((ey - f.y()) as f64) / (f.h() as f64);
+                        let (x, y) = mapping::frac_to_plane(&overview.get(), x_frac, y_frac);
+                        pipe.send(Msg::JumpTo(x, y)).unwrap();
+                    }
+                    true
+                }
+                _ => false,
+            }
+        });
+
+        MiniMap {
+            win,
+            frame,
+            overview,
+            mark,
+            image_data: Vec::new(),
+        }
+    }
+
+    /**
+    Return the size of the overview image the minimap wants (it'll be
+    shown at that size).
+    */
+    pub fn image_size(&self) -> (usize, usize) {
+        (
+            self.frame.w().max(1) as usize,
+            self.frame.h().max(1) as usize,
+        )
+    }
+
+    /**
+    Show the image of the overview `dims` (`xpix` by `ypix` pixels of
+    8-bit RGB `data`).
+    */
+    pub fn set_image(&mut self, dims: ImageDims, xpix: usize, ypix: usize, data: Vec<u8>) {
+        if xpix * ypix * 3 != data.len() {
+            eprintln!("Minimap image dimensions don't match data dimension.");
+            return;
+        }
+        self.overview.set(dims);
+        self.image_data = data;
+        let img = unsafe {
+            RgbImage::from_data(&self.image_data, xpix as i32, ypix as i32, ColorDepth::Rgb8)
+        };
+        if let Ok(img) = img {
+            self.frame.set_image(Some(img));
+        }
+        self.win.redraw();
+    }
+
+    /** Where the window is on the screen (`None` if it's docked). */
+    pub fn position(&self) -> Option<(i32, i32)> {
+        match self.win.parent() {
+            Some(_) => None,
+            None => Some((self.win.x(), self.win.y())),
+        }
+    }
+
+    /**
+    Move the window to (`x`, `y`), or as near as it can go and still be on
+    a screen. A docked minimap stays put.
+    */
+    pub fn move_to(&mut self, x: i32, y: i32) {
+        if self.win.parent().is_none() {
+            let (x, y) = fit_on_screen(x, y, self.win.w(), self.win.h());
+            self.win.set_pos(x, y);
+        }
+    }
+
+    /** Mark where the view `view` is on the minimap. */
+    pub fn show_view(&mut self, view: &ImageDims) {
+        self.mark.set(view_mark(&self.overview.get(), view));
+        self.frame.redraw();
+    }
+}
+
+// Where the view `view` is on an image of the view `overview`, as the
+// (x, y, width, height) of a rectangle at least `MIN_MARK` pixels across.
+// It may be partly (or wholly) off the image.
+fn view_mark(overview: &ImageDims, view: &ImageDims) -> (i32, i32, i32, i32) {
+    let (x0, y0) = mapping::plane_to_frac(overview, view.x, view.y);
+    let (x1, y1) = mapping::plane_to_frac(overview, view.x + view.width, view.y - view.height());
+    let (w, h) = (overview.xpix as f64, overview.ypix as f64);
+    let (x0, y0, x1, y1) = (x0 * w, y0 * h, x1 * w, y1 * h);
+    // Big enough to see, but still centered on the view.
+    let side = |a: f64, b: f64| -> (i32, i32) {
+        let len = (b - a).round().max(MIN_MARK as f64);
+        let start = ((a + b - len) / 2.0).round();
+        let clip = |x: f64| x.clamp(-1.0e6, 1.0e6) as i32;
+        (clip(start), clip(len))
+    };
+    let (x, w) = side(x0, x1);
+    let (y, h) = side(y0, y1);
+    (x, y, w, h)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn view_marks() {
+        let overview = ImageDims {
+            xpix: 300,
+            ypix: 200,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        assert_eq!(view_mark(&overview, &overview), (0, 0, 300, 200));
+        let zoomed = overview.zoom(2.0);
+        assert_eq!(view_mark(&overview, &zoomed), (75, 50, 150, 100));
+        // Deep zooms still get a mark you can see, in the right place.
+        let deep = overview.recenter(0.25, 0.75).zoom(1.0e9);
+        assert_eq!(view_mark(&overview, &deep), (73, 148, MIN_MARK, MIN_MARK));
+    }
+}
@@ -3,18 +3,34 @@ Various user interface elements and functionality.
 
 This module is further split up into submodules that govern the behavior
 of each of the application's three windows.
+
+The color map and iterator panes (and the minimap) can also be docked
+inside the main window instead of floating in windows of their own (see
+`layout::PanePlacement`). They're still `DoubleWindow`s either way; docked
+ones are just subwindows of the main window, put in the places it sets
+aside for them with `set_docks()`.
 */
 
 use fltk::{
     dialog,
-    enums::{Color, Event, Key},
+    enums::{Color, Event, EventState, Key},
+    group::{Group, Scroll, Tabs},
     prelude::*,
     window::DoubleWindow,
 };
 
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
 use crate::image::RGB;
 
+// How much of a window (in pixels each way) has to be on a screen for it to
+// be put back where it was last time.
+const MIN_ON_SCREEN: i32 = 32;
+
 const A_KEY: Key = Key::from_char('a');
+const Y_KEY: Key = Key::from_char('y');
 const Z_KEY: Key = Key::from_char('z');
 
 /**
@@ -25,33 +41,142 @@ other windows are emitted by both subwindows.
 */
 #[derive(Clone, Copy, Debug)]
 pub enum Msg {
+    /// Add the current image parameters to the end of the list of keyframes
+    /// for an animation.
+    AddKeyframe,
+    /// A background animation render has finished the given number of
+    /// frames out of the given total.
+    AnimationProgress(usize, usize),
+    /// The background animation render has finished (or been stopped, or
+    /// failed).
+    AnimationDone,
+    /// Stop the render currently in progress (and any animation being
+    /// rendered).
+    Cancel,
+    /// The user turns checking for periodic orbits on or off; the value
+    /// emitted is whether it's on.
+    CheckPeriod(bool),
+    /// Forget all the keyframes added so far.
+    ClearKeyframes,
+    /// Put the image, as it's shown, on the system clipboard.
+    CopyImage,
+    /// Put the current image parameters on the clipboard as a share
+    /// string (see `rw::share_string()`).
+    CopyShareString,
+    /// Start (or stop) cycling the colors through the image.
+    CycleColors,
+    /// Time for the colors being cycled to move on to the next frame.
+    CycleStep,
+    /// Render the morph between the two sets of parameters being morphed
+    /// between as an animation.
+    ExportMorph,
+    /// Render one full cycle of the colors through the image as an
+    /// animation.
+    ExportCycle,
+    /// Save the image at several sizes at once (see the `export` module).
+    ExportSet,
     FocusColorPane,
     FocusIterPane,
     FocusMainPane,
+    /// The mouse is over the row of the color pane for the gradient with
+    /// the given index (or `None` if it has left them all); the pixels
+    /// that gradient colors should be highlighted on the image.
+    HighlightGradient(Option<usize>),
+    /// The user selects a different type of iterator in the iterator pane.
+    IterTypeChanged,
+    /// The user clicks on the minimap. The values emitted are the point on
+    /// the complex plane to move the center of the view to.
+    JumpTo(f64, f64),
+    /// Choose two parameter files to morph between.
+    Morph,
+    /// The user moves the morph slider; the value emitted is how far (from
+    /// 0 to 1) to go from the first set of parameters to the second.
+    MorphTo(f64),
     /// Load image parameters previously saved to a TOML file.
     Load,
+    /// Something has been dropped onto the main window; if it's a file,
+    /// load it like `Load` would.
+    LoadDropped,
+    /// The user picks the built-in example with the given index (in the
+    /// order of `gallery::names()`).
+    LoadExample(usize),
+    /// Pick up a whole exploration saved with `SaveSession`.
+    LoadSession,
+    /// Ask for a share string, and load the parameters in it.
+    LoadShareString,
     /// The user pushes one of the "Nudge" buttons. The values emitted are
     /// horzontal and vertical distance in pixels to nudge the image. This
     /// will get translated to a distance on the complex plane, which is
     /// why floats are okay.
     Nudge(f64, f64),
+    /// The user changes the anti-aliasing settings; the values emitted are
+    /// the number of samples per pixel in each direction, and whether to
+    /// only take them all on edges.
+    Oversample(usize, bool),
+    /// The mouse moves over the image; the values emitted are its
+    /// horizontal/vertical location as fractions of the width/height of
+    /// the image (or `None` if it has left the image).
+    PointerMoved(Option<(f64, f64)>),
+    /// Text has been pasted into the main window; it should be checked
+    /// for image parameters.
+    PasteParams,
     /// The user clicks on the image in order to recenter it. The values
     /// emitted are the horizontal/vertical locations of the click as
     /// fractions of the width/height of the image.
     Recenter(f64, f64),
+    /// Something in the color map pane has changed that should be shown on
+    /// the image right away (like the phase slider being dragged).
+    Recolor,
+    /// The background render with the given id has finished (or been
+    /// cancelled).
+    RenderDone(usize),
+    /// The background render with the given id has a low-resolution
+    /// preview ready.
+    RenderPreview(usize),
+    /// The background render with the given id has completed the given
+    /// number of chunks out of the given total.
+    RenderProgress(usize, usize, usize),
+    /// Render an animation through the keyframes added so far.
+    RenderAnimation,
     /// The user just hits the return key. Values emited are values from
     /// the "Width" and "Height" inputs, if valid.
     Redraw(Option<usize>, Option<usize>),
+    /// Go forward to the image parameters last undone.
+    Redo,
+    /// Choose a script to run (see the `script` module).
+    RunScript,
     /// Save current image.
     SaveImage,
+    /// Save the parameters and views gone through so far (the undo and
+    /// view histories) to a session file.
+    SaveSession,
     /// Save current image generation parameters to a TOML file.
     SaveValues,
     /// The user clicks one of the scale radio butons; the value emitted
     /// is the scale ratio selected.
     Scale(usize),
+    /// The script running in the background has finished (or been
+    /// stopped, or failed).
+    ScriptDone,
+    /// A script running in the background has rendered the given number
+    /// of images so far.
+    ScriptProgress(usize),
+    /// Remove any embedded metadata from a PNG file chosen by the user.
+    StripMetadata,
+    /// Go back to the image parameters from before the last change.
+    Undo,
+    /// The user steps back to the view of the image before the last one
+    /// (the previous `ImageDims`).
+    ViewBack,
+    /// The user steps forward again to the view they stepped back from.
+    ViewForward,
     /// The user zooms in/out. The value emitted is the value in the "Zoom"
     /// input (if a zoom in) or its reciprocal (if a zoom out).
     Zoom(f64),
+    /// The user drags out a rectangle on the image to zoom in on. The
+    /// values emitted are the left, top, right, and bottom edges of the
+    /// rectangle, as fractions of the width/height of the image.
+    ZoomRect(f64, f64, f64, f64),
 }
 
 /** Convert an `RGB` struct to an `fltk::enums::Color` value. */
@@ -60,13 +185,185 @@ pub fn rgb_to_fltk(c: RGB) -> Color {
     Color::from_rgb(v[0], v[1], v[2])
 }
 
+/**
+Keep the text describing a value widget in step with its current value.
+
+FLTK doesn't talk to the platforms' accessibility APIs, so the only text
+a screen reader can get at is what widgets and windows show, tooltips
+included. This sets `w`'s tooltip to its `name` followed by `value` (like
+"red: 128"), and should be called whenever the value changes, so that
+what gets read out is the value it has now.
+*/
+pub fn describe_value<W: WidgetExt>(w: &mut W, name: &str, value: &str) {
+    w.set_tooltip(&format!("{}: {}", name, value));
+}
+
+// What to do with a color sampled from the image.
+type Sampler = Box<dyn FnOnce(Option<RGB>)>;
+
+/** The panes that can be docked in the main window. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dock {
+    ColorMap,
+    Iterator,
+    Overview,
+}
+
+// The places in the main window set aside for docked panes, and the tabs
+// that some of them are in.
+struct Docks {
+    tabs: Tabs,
+    places: Vec<(Dock, Scroll)>,
+}
+
+thread_local! {
+    // What to do with the next color sampled from the image, if the
+    // user has asked to sample one.
+    static EYEDROPPER: RefCell<Option<Sampler>> = RefCell::new(None);
+    static DOCKS: RefCell<Option<Docks>> = const { RefCell::new(None) };
+}
+
+/**
+Set aside places for panes to dock in: each `Scroll` in `places` gets the
+pane it's paired with, and the ones that are tabs of `tabs` get brought to
+the front by `show_docked()`. This has to be called before the panes are
+built (and if it never is, they float).
+*/
+pub fn set_docks(tabs: Tabs, places: Vec<(Dock, Scroll)>) {
+    DOCKS.with(|d| *d.borrow_mut() = Some(Docks { tabs, places }));
+}
+
+/**
+If there's a place for `dock`, start adding widgets to it, so that the
+next window built goes there, and return where that window should go
+(relative to the main window). Otherwise return `None`; the window will
+be a window of its own.
+
+The pane should finish its window with `end_pane()`.
+*/
+pub fn dock_begin(dock: Dock) -> Option<(i32, i32)> {
+    DOCKS.with(|d| {
+        let d = d.borrow();
+        let (_, g) = d.as_ref()?.places.iter().find(|(k, _)| *k == dock)?;
+        g.begin();
+        Some((g.x(), g.y()))
+    })
+}
+
+/**
+Finish adding widgets to a pane's window. If it's docked, this also makes
+sure that windows built later (like popups) don't end up inside the dock.
+*/
+pub fn end_pane(w: &mut DoubleWindow) {
+    w.end();
+    if let Some(mut dock) = w.parent() {
+        Group::set_current(None::<&Group>);
+        // In case the pane changed size, and the scroll bars need to.
+        dock.redraw();
+    }
+}
+
+/**
+If `dock` is docked, bring its tab (if it's in one) to the front and
+return `true`; otherwise return `false` (and the pane should raise its
+window).
+*/
+pub fn show_docked(dock: Dock) -> bool {
+    DOCKS.with(|d| match d.borrow_mut().as_mut() {
+        Some(d) => match d.places.iter().find(|(k, _)| *k == dock) {
+            Some((_, g)) => {
+                // Places that aren't tabs are always showing anyway.
+                let _ = d.tabs.set_value(g);
+                true
+            }
+            None => false,
+        },
+        None => false,
+    })
+}
+
+/**
+Turn the next click on the image into an eyedropper: instead of recentering
+the image, the color of the pixel clicked gets passed to `f` (or `None`, if
+there's no image there to sample). Asking again before a click replaces
+the earlier request, which gets `None`.
+*/
+pub fn wait_for_sample<F: FnOnce(Option<RGB>) + 'static>(f: F) {
+    let old = EYEDROPPER.with(|e| e.borrow_mut().replace(Box::new(f)));
+    if let Some(old) = old {
+        old(None);
+    }
+}
+
+/** Take the pending request for a color sampled from the image, if any. */
+pub fn take_sample_request() -> Option<impl FnOnce(Option<RGB>)> {
+    EYEDROPPER.with(|e| e.borrow_mut().take())
+}
+
+// The undo or redo message for pressing `key` with the modifier keys
+// in `state` held down, if it's one of those.
+fn undo_msg(key: Key, state: EventState) -> Option<Msg> {
+    let ctrl = state.contains(EventState::Ctrl) || state.contains(EventState::Command);
+    let shift = state.contains(EventState::Shift);
+    match key {
+        Z_KEY if ctrl && shift => Some(Msg::Redo),
+        Z_KEY if ctrl => Some(Msg::Undo),
+        Y_KEY if ctrl => Some(Msg::Redo),
+        _ => None,
+    }
+}
+
+/**
+If the key just pressed is `Ctrl-Z` (undo) or `Ctrl-Y` or `Ctrl-Shift-Z`
+(redo), return the message for it. Windows should check this before
+treating the Z key as a plain "focus the color pane".
+*/
+pub fn undo_key() -> Option<Msg> {
+    undo_msg(fltk::app::event_key(), fltk::app::event_state())
+}
+
+/**
+Return where a window `w` by `h` pixels should go to be as near to (`x`,
+`y`) as it can while still being on a screen. Screens get unplugged and
+change resolution between runs, and a window nobody can see is no use.
+*/
+pub fn fit_on_screen(x: i32, y: i32, w: i32, h: i32) -> (i32, i32) {
+    let screens: Vec<(i32, i32, i32, i32)> = (0..fltk::app::screen_count())
+        .map(fltk::app::screen_xywh)
+        .collect();
+    fit_on(x, y, w, h, &screens)
+}
+
+// Where a window `w` by `h` pixels should go, to be as near as it can to
+// (`x`, `y`) and still have its top edge and a decent piece of it on one
+// of the `screens` (x, y, width, height); if it's not, it gets moved onto
+// the first one.
+fn fit_on(x: i32, y: i32, w: i32, h: i32, screens: &[(i32, i32, i32, i32)]) -> (i32, i32) {
+    let showing = |&(sx, sy, sw, sh): &(i32, i32, i32, i32)| {
+        let across = (x + w).min(sx + sw) - x.max(sx);
+        let down = (y + h).min(sy + sh) - y;
+        y >= sy && across >= MIN_ON_SCREEN.min(w) && down >= MIN_ON_SCREEN.min(h)
+    };
+    if screens.iter().any(showing) {
+        return (x, y);
+    }
+    match screens.first() {
+        Some(&(sx, sy, sw, sh)) => (
+            x.clamp(sx, (sx + sw - w).max(sx)),
+            y.clamp(sy, (sy + sh - h).max(sy)),
+        ),
+        None => (x, y),
+    }
+}
+
 /**
 Makes some changes to the way an `fltk::window::DoubleWindow` behaves in
 order to conform more closely to desired UI behavior.
 
 It removes the "borders", but then sets the window so it can still be
-dragged around by clicking on inactive parts. It also prevents the window
-from closing when the user hits `esc` when the window is focused.
+dragged around by clicking on inactive parts (unless it's docked inside
+another window). It also prevents the window from closing when the user
+hits `esc` when the window is focused.
 */
 pub fn setup_subwindow_behavior(w: &mut DoubleWindow, pipe: std::sync::mpsc::Sender<Msg>) {
     w.handle({
@@ -82,6 +379,9 @@ pub fn setup_subwindow_behavior(w: &mut DoubleWindow, pipe: std::sync::mpsc::Sen
                     true
                 }
                 Event::Drag => {
+                    if w.parent().is_some() {
+                        return true;
+                    }
                     let dx = fltk::app::event_x() - x;
                     let dy = fltk::app::event_y() - y;
                     wx += dx;
@@ -90,6 +390,10 @@ pub fn setup_subwindow_behavior(w: &mut DoubleWindow, pipe: std::sync::mpsc::Sen
                     true
                 }
                 Event::KeyDown => {
+                    if let Some(msg) = undo_key() {
+                        pipe.send(msg).unwrap();
+                        return true;
+                    }
                     match fltk::app::event_key() {
                         Key::Escape => {
                             // Pretend like we handled it to prevent
@@ -117,31 +421,203 @@ pub fn setup_subwindow_behavior(w: &mut DoubleWindow, pipe: std::sync::mpsc::Sen
     });
 }
 
-/**
-Pops up an `fltk` file chooser dialog to specify a file name and ensures
-it ends with the supplied `extension`.
-*/
-pub fn pick_a_file(extension: &str, force_extension: bool) -> Option<String> {
-    let lc_ext = extension.to_ascii_lowercase();
-    let filter = format!("*{}\t*{}", &lc_ext, &extension.to_ascii_uppercase());
+// The directory of the file most recently chosen, where file choosers start.
+static LAST_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/** Return the directory file choosers start in (the last one used). */
+pub fn last_dir() -> Option<PathBuf> {
+    LAST_DIR.lock().unwrap().clone()
+}
 
-    let mut fname = match dialog::file_chooser("Name your image file:", &filter, ".", true) {
-        None => {
-            return None;
+/** Start file choosers in `dir` (like one saved in the preferences). */
+pub fn set_last_dir<P: AsRef<Path>>(dir: P) {
+    *LAST_DIR.lock().unwrap() = Some(dir.as_ref().to_path_buf());
+}
+
+// The name (without its extension) of the file most recently chosen, to
+// suggest when saving something; or this, before there is one.
+static LAST_STEM: Mutex<Option<String>> = Mutex::new(None);
+const DEFAULT_STEM: &str = "jset";
+
+// Remember where `fname` is, and what it's called, for the next chooser.
+fn remember(fname: &Path) {
+    if let Some(dir) = fname.parent() {
+        if !dir.as_os_str().is_empty() {
+            set_last_dir(dir);
         }
-        Some(f) => f,
+    }
+    if let Some(stem) = fname.file_stem() {
+        *LAST_STEM.lock().unwrap() = Some(stem.to_string_lossy().into_owned());
+    }
+}
+
+/** What a file is being picked for, by `pick_a_file()`. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileMode {
+    /// A file to open.
+    Open,
+    /// A file to save, which gets the extension put on the end if it
+    /// doesn't already have it.
+    Save,
+    /// A file to save, which can be called anything (the extension is just
+    /// the one suggested).
+    SaveAny,
+}
+
+/*
+Return the native file chooser filter for files with `extension` (like
+".png"), in either case, unless `what` is a whole filter already: lines
+of a description and a pattern, separated by a tab.
+*/
+fn filter_for(what: &str) -> String {
+    if what.contains('\t') {
+        return what.to_string();
+    }
+    let ext = what.trim_start_matches('.');
+    format!(
+        "{} files\t*.{{{},{}}}",
+        ext.to_ascii_uppercase(),
+        ext.to_ascii_lowercase(),
+        ext.to_ascii_uppercase()
+    )
+}
+
+// Show the platform's own file chooser, starting in the directory last
+// used; if `preset` is given, it's for saving, starting with that name.
+fn show_chooser(title: &str, filter: &str, preset: Option<&str>) -> Option<PathBuf> {
+    let kind = match preset {
+        Some(_) => dialog::NativeFileChooserType::BrowseSaveFile,
+        None => dialog::NativeFileChooserType::BrowseFile,
     };
+    let mut nfc = dialog::NativeFileChooser::new(kind);
+    nfc.set_title(title);
+    nfc.set_filter(filter);
+    // A directory that's gone since just means starting somewhere else.
+    if let Some(dir) = last_dir() {
+        let _ = nfc.set_directory(&dir);
+    }
+    if let Some(name) = preset {
+        nfc.set_preset_file(name);
+        nfc.set_option(
+            dialog::NativeFileChooserOptions::SaveAsConfirm
+                | dialog::NativeFileChooserOptions::NewFolder,
+        );
+    }
+    nfc.show();
+    let fname = nfc.filename();
+    if fname.as_os_str().is_empty() {
+        return None;
+    }
+    remember(&fname);
+    Some(fname)
+}
 
-    if fname.to_ascii_lowercase().ends_with(&lc_ext) {
-        return Some(fname);
+/**
+Pops up the platform's file chooser with the given `title`, for a file to
+open, showing files that match `filter` (lines of a description and a
+pattern, separated by a tab, like `"Palettes\t*.{gpl,png}"`), starting in
+the directory of the file chosen last.
+*/
+pub fn choose_file(title: &str, filter: &str) -> Option<String> {
+    let filter = format!("{}\nAll files\t*", filter);
+    show_chooser(title, &filter, None).map(|p| p.to_string_lossy().into_owned())
+}
+
+/**
+Pops up the platform's file chooser for a file with the given `extension`
+(or, for opening, any of the kinds of file in a filter like `choose_file()`
+takes), for `mode`. Saving starts with the name of the file chosen last,
+with the extension, and asks before replacing a file that's already there.
+*/
+pub fn pick_a_file(extension: &str, mode: FileMode) -> Option<String> {
+    let filter = filter_for(extension);
+    if mode == FileMode::Open {
+        return choose_file("Open", &filter);
     }
 
-    if force_extension {
+    let stem = LAST_STEM.lock().unwrap().clone();
+    let preset = format!("{}{}", stem.as_deref().unwrap_or(DEFAULT_STEM), extension);
+    let fname = show_chooser("Save As", &filter, Some(&preset))?;
+    let mut fname = fname.to_string_lossy().into_owned();
+    if mode == FileMode::Save
+        && !fname
+            .to_ascii_lowercase()
+            .ends_with(&extension.to_ascii_lowercase())
+    {
         fname.push_str(extension);
+        // The chooser only asked about replacing the name without it.
+        if Path::new(&fname).exists() {
+            let question = format!("{} already exists. Replace it?", &fname);
+            if dialog::choice2_default(&question, "Cancel", "Replace", "") != Some(1) {
+                return None;
+            }
+        }
     }
     Some(fname)
 }
 
 pub mod color;
+pub mod curves;
 pub mod img;
 pub mod iter;
+pub mod layers;
+pub mod layout;
+pub mod minimap;
+pub mod morph;
+pub mod save;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn sample_requests() {
+        let got: Rc<RefCell<Vec<Option<RGB>>>> = Rc::new(RefCell::new(Vec::new()));
+        assert!(take_sample_request().is_none());
+        for _ in 0..2 {
+            let got = got.clone();
+            wait_for_sample(move |c| got.borrow_mut().push(c));
+        }
+        // Asking twice cancels the first request.
+        assert_eq!(*got.borrow(), vec![None]);
+        take_sample_request().unwrap()(Some(RGB::WHITE));
+        assert_eq!(*got.borrow(), vec![None, Some(RGB::WHITE)]);
+        assert!(take_sample_request().is_none());
+    }
+
+    #[test]
+    fn fitting_on_screen() {
+        let screens = [(0, 0, 1920, 1080), (1920, 0, 1280, 1024)];
+        assert_eq!(fit_on(100, 50, 400, 300, &screens), (100, 50));
+        // Mostly on the second screen is fine.
+        assert_eq!(fit_on(3000, 900, 400, 300, &screens), (3000, 900));
+        // A screen that's gone, or just the bottom poking onto one.
+        assert_eq!(fit_on(4000, 100, 400, 300, &screens), (1520, 100));
+        assert_eq!(fit_on(200, -290, 400, 300, &screens), (200, 0));
+        assert_eq!(fit_on(5, 5, 400, 300, &[]), (5, 5));
+    }
+
+    #[test]
+    fn file_filters() {
+        assert_eq!(filter_for(".png"), "PNG files\t*.{png,PNG}");
+        let filter = "Gradients\t*.{ugr,cpt}";
+        assert_eq!(filter_for(filter), filter);
+        remember(Path::new("some/dir/spiral.toml"));
+        assert_eq!(LAST_STEM.lock().unwrap().as_deref(), Some("spiral"));
+        assert_eq!(last_dir(), Some(PathBuf::from("some/dir")));
+    }
+
+    #[test]
+    fn undo_keys() {
+        let ctrl = EventState::Ctrl;
+        assert!(matches!(undo_msg(Z_KEY, ctrl), Some(Msg::Undo)));
+        assert!(matches!(undo_msg(Y_KEY, ctrl), Some(Msg::Redo)));
+        assert!(matches!(
+            undo_msg(Z_KEY, ctrl | EventState::Shift),
+            Some(Msg::Redo)
+        ));
+        assert!(undo_msg(Z_KEY, EventState::None).is_none());
+        assert!(undo_msg(A_KEY, ctrl).is_none());
+    }
+}
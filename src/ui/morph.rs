@@ -0,0 +1,93 @@
+/*!
+A small window for morphing between two sets of image parameters (see the
+`morph` module): a slider to pick how far from one to the other, and a
+button to export the whole morph as frames.
+*/
+
+use std::sync::mpsc;
+
+use fltk::{
+    button::Button,
+    enums::{Align, CallbackTrigger},
+    frame::Frame,
+    prelude::*,
+    valuator::HorNiceSlider,
+    window::DoubleWindow,
+};
+
+use super::{describe_value, layout, Msg};
+
+const SLIDER_NAME: &str = "morph";
+
+/** The morph window. */
+pub struct MorphPane {
+    win: DoubleWindow,
+}
+
+impl MorphPane {
+    /**
+    Open the window, labelled with the names of the two ends, `from` and
+    `to`. Letting go of the slider sends a `Msg::MorphTo` down `pipe` (not
+    while it's being dragged, so the image isn't re-rendered over and
+    over), and the export button sends a `Msg::ExportMorph`.
+    */
+    pub fn new(from: &str, to: &str, pipe: mpsc::Sender<Msg>) -> MorphPane {
+        let l = layout::current();
+        let (w, row_h) = (l.curve_graph_size, l.row_height);
+        let half_w = w / 2;
+
+        let mut win = DoubleWindow::default()
+            .with_label("Morph")
+            .with_size(w, 3 * row_h);
+        Frame::default()
+            .with_label(from)
+            .with_pos(0, 0)
+            .with_size(half_w, row_h)
+            .set_align(Align::Left | Align::Inside | Align::Clip);
+        Frame::default()
+            .with_label(to)
+            .with_pos(half_w, 0)
+            .with_size(w - half_w, row_h)
+            .set_align(Align::Right | Align::Inside | Align::Clip);
+        let mut slider = HorNiceSlider::default()
+            .with_pos(0, row_h)
+            .with_size(w, row_h);
+        slider.set_range(0.0, 1.0);
+        slider.set_value(0.0);
+        slider.set_trigger(CallbackTrigger::Release);
+        describe_value(&mut slider, SLIDER_NAME, "0.000");
+        let mut export_butt = Button::default()
+            .with_label("export frames...")
+            .with_pos(0, 2 * row_h)
+            .with_size(half_w, row_h);
+        export_butt.set_tooltip("render the whole morph to numbered PNGs");
+        let mut close_butt = Button::default()
+            .with_label("close")
+            .with_pos(half_w, 2 * row_h)
+            .with_size(w - half_w, row_h);
+        win.end();
+        win.show();
+
+        slider.set_callback({
+            let pipe = pipe.clone();
+            move |s| {
+                describe_value(s, SLIDER_NAME, &format!("{:.3}", s.value()));
+                pipe.send(Msg::MorphTo(s.value())).unwrap();
+            }
+        });
+        export_butt.set_callback(move |_| {
+            pipe.send(Msg::ExportMorph).unwrap();
+        });
+        close_butt.set_callback({
+            let mut win = win.clone();
+            move |_| win.hide()
+        });
+
+        MorphPane { win }
+    }
+
+    /** Close the window for good. */
+    pub fn close(self) {
+        DoubleWindow::delete(self.win);
+    }
+}
@@ -0,0 +1,308 @@
+/*!
+A popup window for choosing how to save the image: what kind of file (see
+`rw::ImageFormat`), how big, with how much oversampling, whether PNGs
+carry the parameters, how hard to compress it, and whether it gets a
+legend (see the `legend` module).
+*/
+
+use std::sync::mpsc;
+
+use fltk::{
+    button::{Button, CheckButton},
+    enums::{Key, Shortcut},
+    input::IntInput,
+    menu::Choice,
+    prelude::*,
+    valuator::HorNiceSlider,
+    window::DoubleWindow,
+};
+
+use super::{describe_value, layout};
+use crate::export::MAX_SIZE;
+use crate::image::{ImageDims, MAX_OVERSAMPLE};
+use crate::rw::{ImageFormat, PngCompression};
+
+const QUALITY_NAME: &str = "quality (lower is smaller)";
+
+// The entries in the PNG compression `Choice`, in the order of
+// `PngCompression::ALL`.
+const COMPRESSION_CHOICES: &str = "compress: fast|compress: balanced|compress: smallest";
+
+/** Everything about how an image gets saved. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExportOptions {
+    pub format: ImageFormat,
+    /// The size of the saved image, in pixels.
+    pub xpix: usize,
+    pub ypix: usize,
+    /// The number of samples per pixel each way.
+    pub oversample: usize,
+    /// Whether the image gets the parameters embedded (never, unless it's
+    /// a PNG; JPEG and WebP images can't carry them).
+    pub metadata: bool,
+    /// How hard PNGs get compressed.
+    pub compression: PngCompression,
+    /// Whether the image gets a legend and caption along the bottom.
+    pub legend: bool,
+}
+
+impl ExportOptions {
+    /** Return the view `view` at the size and oversampling these say. */
+    pub fn dims(&self, view: &ImageDims) -> ImageDims {
+        let mut dims = view.resize(self.xpix, self.ypix);
+        dims.oversample = self.oversample;
+        dims
+    }
+}
+
+// The length of the other side of an image `side` pixels along one, with
+// that side `ratio` times as long as the other (but no more than
+// `MAX_SIZE`).
+fn other_side(side: usize, ratio: f64) -> usize {
+    ((side as f64) / ratio).round().clamp(1.0, MAX_SIZE as f64) as usize
+}
+
+fn set_active<W: WidgetExt>(w: &mut W, active: bool) {
+    if active {
+        w.activate();
+    } else {
+        w.deactivate();
+    }
+}
+
+// The value of `input`, if it's a sensible number of pixels, cut down to
+// `MAX_SIZE` if it's more than that.
+fn pixels_in(input: &IntInput) -> Option<usize> {
+    input
+        .value()
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .filter(|&n| n > 0)
+        .map(|n| n.min(MAX_SIZE))
+}
+
+// The entries in the format `Choice`, in order.
+const FORMAT_CHOICES: &str = "PNG (keeps the parameters)|JPEG (smallest)|WebP|WebP (lossless)";
+
+// Which entry of the format `Choice` is `format`, and its quality, if it
+// has one.
+fn choice_of(format: ImageFormat) -> (i32, Option<u8>) {
+    match format {
+        ImageFormat::Png => (0, None),
+        ImageFormat::Jpeg { quality } => (1, Some(quality)),
+        ImageFormat::Webp { quality: Some(q) } => (2, Some(q)),
+        ImageFormat::Webp { quality: None } => (3, None),
+    }
+}
+
+// The format entry `choice` of the format `Choice` stands for, at `quality`
+// if it's lossy.
+fn format_of(choice: i32, quality: u8) -> ImageFormat {
+    match choice {
+        1 => ImageFormat::Jpeg { quality },
+        2 => ImageFormat::Webp {
+            quality: Some(quality),
+        },
+        3 => ImageFormat::Webp { quality: None },
+        _ => ImageFormat::Png,
+    }
+}
+
+/**
+Pops up a modal window for choosing how to save an image, starting with
+`start`. Returns the options chosen, or `None` if the saving is cancelled.
+*/
+pub fn choose_export(start: ExportOptions) -> Option<ExportOptions> {
+    let l = layout::current();
+    let (w, row_h) = (l.curve_graph_size, l.row_height);
+    let half_w = w / 2;
+    let (choice, quality) = choice_of(start.format);
+    let aspect = start.xpix.max(1) as f64 / start.ypix.max(1) as f64;
+
+    let mut win = DoubleWindow::default()
+        .with_label("Save Image As")
+        .with_size(w, 7 * row_h);
+    let mut format_choice = Choice::default().with_pos(0, 0).with_size(w, row_h);
+    format_choice.add_choice(FORMAT_CHOICES);
+    format_choice.set_value(choice);
+    format_choice.set_tooltip(
+        "JPEG and WebP images don't carry the parameters; only PNGs can be loaded again",
+    );
+    let mut slider = HorNiceSlider::default()
+        .with_pos(0, row_h)
+        .with_size(w, row_h);
+    slider.set_range(1.0, 100.0);
+    slider.set_step(1.0, 1);
+    let q = quality.unwrap_or(ImageFormat::DEFAULT_QUALITY);
+    slider.set_value(q as f64);
+    describe_value(&mut slider, QUALITY_NAME, &format!("{}", q));
+    let mut compression_choice = Choice::default().with_pos(0, 2 * row_h).with_size(w, row_h);
+    compression_choice.add_choice(COMPRESSION_CHOICES);
+    let level = PngCompression::ALL
+        .iter()
+        .position(|&c| c == start.compression);
+    compression_choice.set_value(level.unwrap_or(0) as i32);
+    compression_choice.set_tooltip("smaller PNGs take longer to write, but look the same");
+    let mut width_input = IntInput::default()
+        .with_pos(0, 3 * row_h)
+        .with_size(half_w, row_h);
+    width_input.set_value(&format!("{}", start.xpix));
+    width_input.set_tooltip(&format!(
+        "width of the saved image, in pixels (up to {})",
+        MAX_SIZE
+    ));
+    let mut height_input = IntInput::default()
+        .with_pos(half_w, 3 * row_h)
+        .with_size(w - half_w, row_h);
+    height_input.set_value(&format!("{}", start.ypix));
+    height_input.set_tooltip(&format!(
+        "height of the saved image, in pixels (up to {})",
+        MAX_SIZE
+    ));
+    let mut oversample_choice = Choice::default()
+        .with_pos(0, 4 * row_h)
+        .with_size(half_w, row_h);
+    for n in 1..=MAX_OVERSAMPLE {
+        oversample_choice.add_choice(&format!("{} x {} samples", n, n));
+    }
+    oversample_choice.set_value(start.oversample.clamp(1, MAX_OVERSAMPLE) as i32 - 1);
+    oversample_choice.set_tooltip("samples per pixel each way (more is smoother, but slower)");
+    let mut metadata_check = CheckButton::default()
+        .with_label("parameters (PNG)")
+        .with_pos(half_w, 4 * row_h)
+        .with_size(w - half_w, row_h);
+    metadata_check.set_checked(start.metadata);
+    metadata_check.set_tooltip("embed the image parameters, so the PNG can be loaded again");
+    let mut legend_check = CheckButton::default()
+        .with_label("legend and caption")
+        .with_pos(0, 5 * row_h)
+        .with_size(w, row_h);
+    legend_check.set_checked(start.legend);
+    legend_check.set_tooltip(
+        "put the colors, with iteration counts, and where the image is along the bottom",
+    );
+    let mut ok = Button::default()
+        .with_label("Save... @returnarrow")
+        .with_pos(0, 6 * row_h)
+        .with_size(half_w, row_h);
+    ok.set_shortcut(Shortcut::from_key(Key::Enter));
+    let mut no = Button::default()
+        .with_label("Cancel")
+        .with_pos(half_w, 6 * row_h)
+        .with_size(w - half_w, row_h);
+    no.set_shortcut(Shortcut::from_key(Key::Escape));
+    win.end();
+    win.make_modal(true);
+    win.show();
+
+    // Only the controls that make a difference to the format chosen are
+    // active.
+    let mut activate_for = {
+        let mut slider = slider.clone();
+        let mut compression_choice = compression_choice.clone();
+        let mut metadata_check = metadata_check.clone();
+        move |choice: i32| {
+            let format = format_of(choice, 1);
+            let png = format == ImageFormat::Png;
+            set_active(&mut slider, choice_of(format).1.is_some());
+            set_active(&mut compression_choice, png);
+            set_active(&mut metadata_check, png);
+        }
+    };
+    activate_for(choice);
+    format_choice.set_callback(move |c| activate_for(c.value()));
+    slider.set_callback(|s| describe_value(s, QUALITY_NAME, &format!("{}", s.value())));
+    // Changing one side changes the other to keep the shape of the image.
+    width_input.set_callback({
+        let mut height_input = height_input.clone();
+        move |i| {
+            if let Some(n) = pixels_in(i) {
+                height_input.set_value(&format!("{}", other_side(n, aspect)));
+            }
+        }
+    });
+    height_input.set_callback({
+        let mut width_input = width_input.clone();
+        move |i| {
+            if let Some(n) = pixels_in(i) {
+                width_input.set_value(&format!("{}", other_side(n, 1.0 / aspect)));
+            }
+        }
+    });
+
+    let (tx, rx) = mpsc::channel::<bool>();
+    ok.set_callback({
+        let tx = tx.clone();
+        move |_| tx.send(true).unwrap()
+    });
+    no.set_callback(move |_| tx.send(false).unwrap());
+
+    let chosen = loop {
+        match rx.try_recv() {
+            Ok(chosen) => break chosen,
+            Err(_) if !win.shown() => break false,
+            Err(_) => {
+                fltk::app::wait();
+            }
+        }
+    };
+    let format = format_of(format_choice.value(), slider.value().round() as u8);
+    let options = ExportOptions {
+        format,
+        xpix: pixels_in(&width_input).unwrap_or(start.xpix),
+        ypix: pixels_in(&height_input).unwrap_or(start.ypix),
+        oversample: oversample_choice.value().max(0) as usize + 1,
+        metadata: metadata_check.is_checked() && format == ImageFormat::Png,
+        compression: PngCompression::ALL
+            .get(compression_choice.value().max(0) as usize)
+            .copied()
+            .unwrap_or_default(),
+        legend: legend_check.is_checked(),
+    };
+    DoubleWindow::delete(win);
+    chosen.then_some(options)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn format_choices() {
+        for format in [
+            ImageFormat::Png,
+            ImageFormat::Jpeg { quality: 75 },
+            ImageFormat::Webp { quality: Some(40) },
+            ImageFormat::Webp { quality: None },
+        ] {
+            let (choice, quality) = choice_of(format);
+            assert_eq!(format_of(choice, quality.unwrap_or(1)), format);
+        }
+        assert_eq!(FORMAT_CHOICES.split('|').count(), 4);
+        assert_eq!(
+            COMPRESSION_CHOICES.split('|').count(),
+            PngCompression::ALL.len()
+        );
+        assert_eq!(other_side(1920, 16.0 / 9.0), 1080);
+        assert_eq!(other_side(1080, 9.0 / 16.0), 1920);
+        assert_eq!(other_side(1, 10.0), 1);
+        assert_eq!(other_side(MAX_SIZE, 0.5), MAX_SIZE);
+    }
+
+    #[test]
+    #[ignore = "interactive; opens a window and waits for it to be closed"]
+    fn export_chooser() {
+        let _a = fltk::app::App::default();
+        let start = ExportOptions {
+            format: ImageFormat::Png,
+            xpix: 800,
+            ypix: 600,
+            oversample: 2,
+            metadata: true,
+            compression: PngCompression::Best,
+            legend: false,
+        };
+        println!("{:?}", choose_export(start));
+    }
+}
@@ -0,0 +1,344 @@
+/*!
+Turning animations straight into videos, by piping their frames (as raw
+RGB) to an `ffmpeg` process, rather than leaving a pile of PNGs to stitch
+together afterwards.
+
+The kind of video comes from the file's extension: `.mp4`, `.mkv`, and
+`.mov` get H.264, and `.webm` gets VP9. If `ffmpeg` can't be run, the
+frames are saved as numbered PNGs instead, the same as if a `.png` name
+had been given.
+
+Animated GIFs and PNGs (`.gif` and `.apng`) don't need `ffmpeg`; they're
+written by `rw::AnimationWriter`.
+*/
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::Mutex;
+
+use crate::rw::{AnimationFormat, AnimationWriter};
+
+/** The frame rate of videos, unless asked for otherwise. */
+pub const DEFAULT_FPS: f64 = 30.0;
+
+// The program run if no other has been set.
+const DEFAULT_FFMPEG: &str = "ffmpeg";
+// The most of ffmpeg's complaints to pass along when it fails.
+const MAX_STDERR_LINES: usize = 8;
+// The extensions of the kinds of video that can be made.
+const VIDEO_EXTENSIONS: [&str; 4] = ["mp4", "mkv", "mov", "webm"];
+
+static FFMPEG: Mutex<Option<String>> = Mutex::new(None);
+
+/** Run `program` as `ffmpeg` (or just `ffmpeg` if `None`). */
+pub fn set_ffmpeg(program: Option<String>) {
+    *FFMPEG.lock().unwrap() = program;
+}
+
+/** The program run as `ffmpeg`. */
+pub fn ffmpeg() -> String {
+    FFMPEG
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| DEFAULT_FFMPEG.to_string())
+}
+
+/** Return whether `ffmpeg` can be run. */
+pub fn ffmpeg_available() -> bool {
+    Command::new(ffmpeg())
+        .arg("-version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+/** Return whether `fname` has the extension of a kind of video. */
+pub fn is_video<P: AsRef<Path>>(fname: P) -> bool {
+    match fname.as_ref().extension() {
+        Some(ext) => {
+            let ext = ext.to_string_lossy().to_ascii_lowercase();
+            VIDEO_EXTENSIONS.contains(&ext.as_str())
+        }
+        None => false,
+    }
+}
+
+/**
+The arguments to give `ffmpeg` to make a video at `dest` from raw RGB
+frames `xpix` by `ypix` pixels, at `fps` frames per second, read from its
+standard input.
+*/
+pub fn ffmpeg_args(dest: &Path, xpix: usize, ypix: usize, fps: f64) -> Vec<String> {
+    let mut args: Vec<String> = [
+        "-hide_banner",
+        "-loglevel",
+        "error",
+        "-y",
+        "-f",
+        "rawvideo",
+        "-pix_fmt",
+        "rgb24",
+        "-s",
+        &format!("{}x{}", xpix, ypix),
+        "-framerate",
+        &fps.to_string(),
+        "-i",
+        "-",
+        // Most players only take 4:2:0 video, which has to be an even
+        // number of pixels each way.
+        "-vf",
+        "pad=ceil(iw/2)*2:ceil(ih/2)*2",
+        "-pix_fmt",
+        "yuv420p",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    let webm = dest
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("webm"))
+        .unwrap_or(false);
+    let codec: &[&str] = if webm {
+        &["-c:v", "libvpx-vp9", "-crf", "30", "-b:v", "0"]
+    } else {
+        &["-c:v", "libx264", "-crf", "18", "-movflags", "+faststart"]
+    };
+    args.extend(codec.iter().map(|s| s.to_string()));
+    args.push(dest.to_string_lossy().into_owned());
+    args
+}
+
+/** Where the frames of an animation go. */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Output {
+    /// Numbered PNGs, named after this one (see `animate::frame_name()`).
+    Frames(PathBuf),
+    /// A video.
+    Video(PathBuf),
+    /// An animated GIF or PNG.
+    Animated(PathBuf, AnimationFormat),
+}
+
+impl Output {
+    /**
+    Decide where an animation saved as `dest` goes: into a video if
+    that's what `dest` is named as, and `ffmpeg` can be run, into an
+    animated GIF or PNG if it's named as one of those, or otherwise
+    numbered PNGs. If a video was asked for but can't be made, the PNGs
+    are named after it (with a `.png` extension), and the message that
+    comes with them says so.
+    */
+    pub fn choose<P: AsRef<Path>>(dest: P) -> (Output, Option<String>) {
+        let dest = dest.as_ref();
+        if let Some(format) = AnimationFormat::of(dest) {
+            return (Output::Animated(dest.to_path_buf(), format), None);
+        }
+        if !is_video(dest) {
+            return (Output::Frames(dest.to_path_buf()), None);
+        }
+        if ffmpeg_available() {
+            return (Output::Video(dest.to_path_buf()), None);
+        }
+        let base = dest.with_extension("png");
+        let note = format!(
+            "{} couldn't be run to make a video, so the frames will be saved as PNGs named after {}.",
+            &ffmpeg(),
+            base.display()
+        );
+        (Output::Frames(base), Some(note))
+    }
+}
+
+/** An `ffmpeg` process being fed frames of a video. */
+pub struct VideoWriter {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    frame_len: usize,
+    dest: PathBuf,
+}
+
+impl VideoWriter {
+    /**
+    Start making a video at `dest` of frames `xpix` by `ypix` pixels, at
+    `fps` frames per second.
+    */
+    pub fn create<P: AsRef<Path>>(
+        dest: P,
+        xpix: usize,
+        ypix: usize,
+        fps: f64,
+    ) -> Result<VideoWriter, String> {
+        let dest = dest.as_ref().to_path_buf();
+        let program = ffmpeg();
+        let mut child = Command::new(&program)
+            .args(ffmpeg_args(&dest, xpix, ypix, fps))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Error running {}: {}", &program, &e))?;
+        let stdin = child.stdin.take();
+        Ok(VideoWriter {
+            child,
+            stdin,
+            frame_len: xpix * ypix * 3,
+            dest,
+        })
+    }
+
+    /** Add a frame of 8-bit RGB `data` to the video. */
+    pub fn write_frame(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() != self.frame_len {
+            return Err(format!(
+                "A frame of {} bytes doesn't fit a video with {} bytes per frame.",
+                data.len(),
+                self.frame_len
+            ));
+        }
+        let res = match self.stdin.as_mut() {
+            Some(stdin) => stdin.write_all(data),
+            None => return Err(self.failure()),
+        };
+        // If ffmpeg has quit, what it said about it is more use than
+        // the broken pipe.
+        res.map_err(|_| self.failure())
+    }
+
+    /** Finish the video, waiting for `ffmpeg` to write the end of it. */
+    pub fn finish(mut self) -> Result<(), String> {
+        self.stdin = None;
+        match self.child.wait() {
+            Ok(status) if status.success() => Ok(()),
+            _ => Err(self.failure()),
+        }
+    }
+
+    // Stop feeding ffmpeg, wait for it to quit, and describe what went
+    // wrong.
+    fn failure(&mut self) -> String {
+        self.stdin = None;
+        let status = self.child.wait();
+        let mut stderr = String::new();
+        if let Some(mut err) = self.child.stderr.take() {
+            let _ = err.read_to_string(&mut stderr);
+        }
+        let mut estr = match status {
+            Ok(status) => format!(
+                "Error making {}: {} failed ({})",
+                self.dest.display(),
+                &ffmpeg(),
+                &status
+            ),
+            Err(e) => format!("Error making {}: {}", self.dest.display(), &e),
+        };
+        let lines: Vec<&str> = stderr.lines().collect();
+        for line in lines[lines.len().saturating_sub(MAX_STDERR_LINES)..].iter() {
+            estr.push('\n');
+            estr.push_str(line);
+        }
+        estr
+    }
+}
+
+impl Drop for VideoWriter {
+    fn drop(&mut self) {
+        // Let ffmpeg finish off whatever it's got, rather than leaving it
+        // waiting for more.
+        self.stdin = None;
+        let _ = self.child.wait();
+    }
+}
+
+/**
+Make a video or animated image, `out`, of `count` frames, `xpix` by `ypix`
+pixels, at `fps` frames per second, where `frame(n)` returns the 8-bit RGB
+data of frame `n` (counting from 0). After each frame, `progress` is
+called with the number done so far; if it returns `false`, the animation
+ends there.
+
+Numbered PNGs are written band by band instead (see `headless::export()`),
+so they aren't made here.
+*/
+pub fn encode<G, F>(
+    out: &Output,
+    (xpix, ypix): (usize, usize),
+    fps: f64,
+    count: usize,
+    mut frame: G,
+    mut progress: F,
+) -> Result<(), String>
+where
+    G: FnMut(usize) -> Vec<u8>,
+    F: FnMut(usize) -> bool,
+{
+    match out {
+        Output::Frames(base) => Err(format!(
+            "Frames named after {} aren't encoded as one file.",
+            base.display()
+        )),
+        Output::Video(dest) => {
+            let mut video = VideoWriter::create(dest, xpix, ypix, fps)?;
+            for n in 0..count {
+                video.write_frame(&frame(n))?;
+                if !progress(n + 1) {
+                    break;
+                }
+            }
+            video.finish()
+        }
+        Output::Animated(dest, format) => {
+            let mut anim = AnimationWriter::create(dest, *format, (xpix, ypix), count, fps)?;
+            for n in 0..count {
+                anim.write_frame(&frame(n))?;
+                if !progress(n + 1) {
+                    break;
+                }
+            }
+            anim.finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn videos() {
+        assert!(is_video("zoom.MP4") && is_video("out/zoom.webm"));
+        assert!(!is_video("zoom.png") && !is_video("zoom"));
+
+        let args = ffmpeg_args(Path::new("zoom.webm"), 301, 200, 24.0);
+        let pos = |a: &str| args.iter().position(|x| x == a).unwrap();
+        assert_eq!(args[pos("-s") + 1], "301x200");
+        assert_eq!(args[pos("-framerate") + 1], "24");
+        assert_eq!(args[pos("-c:v") + 1], "libvpx-vp9");
+        assert_eq!(args.last().unwrap(), "zoom.webm");
+        let args = ffmpeg_args(Path::new("zoom.mp4"), 300, 200, 30.0);
+        assert!(args.contains(&"libx264".to_string()));
+
+        // Without ffmpeg, videos turn into numbered PNGs.
+        set_ffmpeg(Some("jset_no_such_ffmpeg".to_string()));
+        assert!(!ffmpeg_available());
+        assert_eq!(
+            Output::choose("zoom.png"),
+            (Output::Frames(PathBuf::from("zoom.png")), None)
+        );
+        assert_eq!(
+            Output::choose("zoom.gif"),
+            (
+                Output::Animated(PathBuf::from("zoom.gif"), AnimationFormat::Gif),
+                None
+            )
+        );
+        let (out, note) = Output::choose("out/zoom.mp4");
+        assert_eq!(out, Output::Frames(PathBuf::from("out/zoom.png")));
+        assert!(note.unwrap().contains("jset_no_such_ffmpeg"));
+        assert!(VideoWriter::create("zoom.mp4", 30, 20, 30.0).is_err());
+        set_ffmpeg(None);
+    }
+}
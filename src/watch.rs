@@ -0,0 +1,289 @@
+/*!
+Watching a folder for parameter files and rendering each one that shows
+up, for turning a spare machine into a render box.
+
+Any parameter file (`.toml`, `.json`, or a `.png` with parameters
+embedded) dropped into the watched folder gets rendered to a PNG of the
+same name in the output folder, at the size and oversampling it was saved
+with. A file is rendered again if it's changed after its image was made.
+Everything that happens is written to `render.log` in the output folder.
+
+There's no portable way to be told when a folder changes without pulling
+in another dependency, so the folder is just checked every so often.
+*/
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::headless;
+use crate::hook;
+use crate::rw;
+
+/** How often the folder is checked if no interval is specified. */
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+/** The name of the log file written in the output folder. */
+pub const LOG_NAME: &str = "render.log";
+// Files modified more recently than this might still be being copied in,
+// so they're left for the next check.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/** Renders the parameter files that appear in a folder. */
+pub struct Watcher {
+    inbox: PathBuf,
+    outbox: PathBuf,
+    // Files that failed to render, and when they'd been modified, so they
+    // aren't tried again until they change.
+    failed: HashMap<PathBuf, SystemTime>,
+}
+
+impl Watcher {
+    /**
+    Start watching `inbox`, rendering into `outbox` (which is created if
+    it doesn't exist). Without an `outbox`, images go in a sibling folder
+    named after `inbox` with "_renders" on the end.
+    */
+    pub fn new<P: AsRef<Path>>(inbox: P, outbox: Option<P>) -> Result<Watcher, String> {
+        let inbox = inbox.as_ref().to_path_buf();
+        if !inbox.is_dir() {
+            return Err(format!("{} isn't a folder.", inbox.display()));
+        }
+        let outbox = match outbox {
+            Some(p) => p.as_ref().to_path_buf(),
+            None => {
+                let full = inbox.canonicalize().unwrap_or_else(|_| inbox.clone());
+                let name = match full.file_name() {
+                    Some(name) => name.to_string_lossy().into_owned(),
+                    None => "jset".to_string(),
+                };
+                let parent = full.parent().unwrap_or(&full);
+                parent.join(format!("{}_renders", &name))
+            }
+        };
+        if let Err(e) = fs::create_dir_all(&outbox) {
+            let estr = format!("Error creating folder {}: {}", outbox.display(), &e);
+            return Err(estr);
+        }
+
+        Ok(Watcher {
+            inbox,
+            outbox,
+            failed: HashMap::new(),
+        })
+    }
+
+    /** The folder images are rendered into. */
+    pub fn outbox(&self) -> &Path {
+        &self.outbox
+    }
+
+    /** Where the image rendered from `src` goes. */
+    pub fn output_for(&self, src: &Path) -> PathBuf {
+        let stem = src.file_stem().unwrap_or_default();
+        self.outbox.join(stem).with_extension("png")
+    }
+
+    /**
+    Return the files in the watched folder that need rendering, oldest
+    first: those that haven't been rendered yet (or have changed since),
+    and haven't already failed.
+    */
+    pub fn pending(&self) -> Vec<PathBuf> {
+        let entries = match fs::read_dir(&self.inbox) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.log(&format!("Error reading {}: {}", self.inbox.display(), &e));
+                return Vec::new();
+            }
+        };
+        let settled = SystemTime::now() - SETTLE_TIME;
+
+        let mut files: Vec<(SystemTime, PathBuf)> = Vec::new();
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            let is_png = path
+                .extension()
+                .map(|ext| ext.eq_ignore_ascii_case("png"))
+                .unwrap_or(false);
+            if !(is_png || rw::ParamFormat::is_param_file(&path)) {
+                continue;
+            }
+            let modified = match path.metadata().and_then(|m| m.modified()) {
+                Ok(t) => t,
+                Err(_) => {
+                    continue;
+                }
+            };
+            if modified > settled || self.failed.get(&path) == Some(&modified) {
+                continue;
+            }
+            let rendered = self
+                .output_for(&path)
+                .metadata()
+                .and_then(|m| m.modified())
+                .map(|t| t >= modified)
+                .unwrap_or(false);
+            if !rendered {
+                files.push((modified, path));
+            }
+        }
+        files.sort();
+        files.into_iter().map(|(_, p)| p).collect()
+    }
+
+    /**
+    Render one parameter file into the output folder (running the post-save
+    hook, if there is one), returning the name of the image.
+    */
+    pub fn render(&self, src: &Path) -> Result<PathBuf, String> {
+        let (dims, cspec, itype) = rw::load(src)?;
+        let dest = self.output_for(src);
+        headless::export(&dest, dims, cspec, itype, true, rw::BitDepth::Eight)?;
+        hook::after_save(&dest, dims.xpix, dims.ypix)?;
+        Ok(dest)
+    }
+
+    /** Render everything that's pending, returning how many succeeded. */
+    pub fn poll(&mut self) -> usize {
+        let mut n_done = 0;
+        for src in self.pending() {
+            let start = Instant::now();
+            match self.render(&src) {
+                Ok(dest) => {
+                    self.log(&format!(
+                        "rendered {} to {} in {:.1} s",
+                        src.display(),
+                        dest.display(),
+                        start.elapsed().as_secs_f64()
+                    ));
+                    self.failed.remove(&src);
+                    n_done += 1;
+                }
+                Err(e) => {
+                    self.log(&format!("failed to render {}: {}", src.display(), &e));
+                    if let Ok(t) = src.metadata().and_then(|m| m.modified()) {
+                        self.failed.insert(src, t);
+                    }
+                }
+            }
+        }
+        n_done
+    }
+
+    /** Check the folder every `interval`, forever. */
+    pub fn run(&mut self, interval: Duration) -> ! {
+        self.log(&format!(
+            "watching {}, rendering to {}",
+            self.inbox.display(),
+            self.outbox.display()
+        ));
+        loop {
+            self.poll();
+            std::thread::sleep(interval);
+        }
+    }
+
+    // Add a timestamped line to the log (and to stderr, for whoever's
+    // watching).
+    fn log(&self, msg: &str) {
+        let line = format!("{} {}", timestamp(SystemTime::now()), msg);
+        eprintln!("{}", &line);
+        let res = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.outbox.join(LOG_NAME))
+            .and_then(|mut f| writeln!(f, "{}", &line));
+        if let Err(e) = res {
+            eprintln!("Error writing to {}: {}", LOG_NAME, &e);
+        }
+    }
+}
+
+/*
+Format a time as "YYYY-MM-DD HH:MM:SS" UTC. (This is Howard Hinnant's
+days-to-civil-date algorithm.)
+*/
+fn timestamp(t: SystemTime) -> String {
+    let secs = t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, secs) = (secs / SECONDS_PER_DAY, secs % SECONDS_PER_DAY);
+
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::*;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01 00:00:00");
+        let t = UNIX_EPOCH + Duration::from_secs(951_827_696);
+        assert_eq!(timestamp(t), "2000-02-29 12:34:56");
+    }
+
+    #[test]
+    fn watched_folder() {
+        let dir = std::env::temp_dir().join("jset_watch_test");
+        let _ = fs::remove_dir_all(&dir);
+        let (inbox, outbox) = (dir.join("in"), dir.join("out"));
+        fs::create_dir_all(&inbox).unwrap();
+
+        let dims = ImageDims {
+            xpix: 40,
+            ypix: 30,
+            x: -2.0,
+            y: 1.0,
+            width: 3.0,
+            oversample: 1,
+            adaptive: false,
+            check_period: true,
+        };
+        let cspec = ColorSpec::new(vec![Gradient::default()], RGB::BLACK);
+        rw::save(&dims, &cspec, &IterType::Mandlebrot, &inbox.join("a.toml")).unwrap();
+        fs::write(inbox.join("b.json"), "{ not really }").unwrap();
+        fs::write(inbox.join("notes.txt"), "not a parameter file").unwrap();
+        // Pretend they've been there a while.
+        std::thread::sleep(SETTLE_TIME + Duration::from_millis(100));
+
+        let mut w = Watcher::new(&inbox, Some(&outbox)).unwrap();
+        assert_eq!(
+            w.pending(),
+            vec![inbox.join("a.toml"), inbox.join("b.json")]
+        );
+        assert_eq!(w.poll(), 1);
+        let (ldims, lspec, _) = rw::load(outbox.join("a.png")).unwrap();
+        assert_eq!((ldims, lspec), (dims, cspec));
+        // The bad file isn't retried until it changes.
+        assert!(w.pending().is_empty());
+        assert_eq!(w.poll(), 0);
+
+        let log = fs::read_to_string(outbox.join(LOG_NAME)).unwrap();
+        assert_eq!(log.lines().count(), 2);
+        assert!(log.contains("rendered") && log.contains("failed"));
+
+        let w = Watcher::new(&inbox, None).unwrap();
+        assert_eq!(w.outbox(), dir.canonicalize().unwrap().join("in_renders"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}